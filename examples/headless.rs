@@ -0,0 +1,59 @@
+//! Plays pieces with random moves and no window, the way a bot runner would embed the game.
+//! Run it with `cargo run --example headless --no-default-features`.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tetris::prelude::*;
+
+const ACTIONS: [Action; 6] = [
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::RotateCw,
+    Action::SoftDrop,
+    Action::Hold,
+    Action::HardDrop,
+];
+
+/// What a run of random play came to.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub pieces: u32,
+    pub lines: u32,
+    pub games: u32,
+}
+
+/// Plays `pieces` pieces of random actions from `seed`, starting a new game whenever one
+/// tops out.
+pub fn play(seed: u64, pieces: u32) -> Summary {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut game = Game::with_seed(seed);
+    game.start_game_with_seed(seed);
+    let mut summary = Summary {
+        games: 1,
+        ..Summary::default()
+    };
+    while summary.pieces < pieces {
+        game.apply(ACTIONS[rng.gen_range(0..ACTIONS.len())]);
+        // One simulation step after each action, as the game loop would run
+        game.tick(FIXED_DT);
+        for event in game.drain_events() {
+            if let GameEvent::PieceLocked { lines_cleared, .. } = event {
+                summary.pieces += 1;
+                summary.lines += lines_cleared;
+            }
+        }
+        if game.state == GameState::GameOver {
+            game.start_game_with_seed(rng.gen());
+            summary.games += 1;
+        }
+    }
+    summary
+}
+
+fn main() {
+    let summary = play(rand::random(), 100);
+    println!(
+        "{} pieces over {} games, {} lines cleared",
+        summary.pieces, summary.games, summary.lines
+    );
+}
@@ -1,8 +1,29 @@
-use tetris::multiplayer::MultiplayerServer;
+use tetris::admin::run_console;
+use tetris::multiplayer::{MultiplayerServer, ServerPaths};
+use tracing_subscriber::EnvFilter;
+
+/// Parses `--data-dir <dir>` from the command line: where the results log, replays and
+/// ratings go, by default the working directory.
+fn parse_data_dir() -> ServerPaths {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--data-dir" {
+            return args.next().map(ServerPaths::in_dir).unwrap_or_default();
+        }
+    }
+    ServerPaths::default()
+}
 
 #[tokio::main]
 async fn main() {
-    let server = MultiplayerServer::new();
+    // RUST_LOG takes precedence; --verbose adds per-message logging
+    let verbose = std::env::args().any(|arg| arg == "--verbose");
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(if verbose { "debug" } else { "info" }));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let server = MultiplayerServer::with_paths(parse_data_dir());
     println!("Starting Tetris multiplayer server on ws://localhost:8080");
-    server.start("127.0.0.1:8080").await;
-} 
+    println!("Admin commands: list, kick <player_id>, say <text>");
+    tokio::join!(server.start("127.0.0.1:8080"), run_console(&server));
+}
@@ -1,8 +1,49 @@
 use raylib::prelude::*;
 use std::time::{Duration, Instant};
 
-mod tetris;
-use tetris::*;
+use ::tetris::*;
+use ::tetris::browser::{BrowserChoice, ServerBrowser};
+use ::tetris::capture::{capture_path, export_board_ascii, SCREENSHOTS_DIR};
+use ::tetris::config::{
+    load_config, save_assist, save_connected_pieces, save_drought_counter, save_keys,
+    save_randomizer, save_reduce_motion, save_rumble, save_translucent_pause, save_ui_scale,
+    Config, ConfigWatcher, KeyBindings, CONFIG_PATH,
+};
+use ::tetris::daily::{
+    daily_seed, sync_daily, DailyEntry, DailySubmission, DailySync, HighScores, HIGH_SCORES_PATH,
+};
+use ::tetris::date::UtcDateTime;
+use ::tetris::editor::{BoardEditor, EditorField, SETUPS_DIR};
+use ::tetris::haptics::Haptics;
+use ::tetris::i18n::{set_locale, tr, tr_args, Strings, LOCALES_DIR};
+use ::tetris::menu::{Menu, MenuItem};
+use ::tetris::multiplayer::{
+    fetch_replay, list_replays, list_rooms, RemotePiece, RoomInfo, RoomRules,
+    MAX_GARBAGE_DELAY_PIECES, MAX_PERFECT_CLEAR_ATTACK,
+};
+use ::tetris::replay::{Playback, Replay};
+use ::tetris::overlay::{StatsServer, StatsSnapshot};
+use ::tetris::settings::{step_ui_scale, Rebind, SettingsItem, SettingsMenu};
+use ::tetris::splits::BestSplits;
+#[cfg(feature = "discord")]
+use ::tetris::multiplayer::Throttled;
+#[cfg(feature = "discord")]
+use ::tetris::presence::{presence, DiscordPresence, Presence, PRESENCE_INTERVAL};
+use ::tetris::stats::{append_record, log_to_csv, GameRecord, GAME_LOG_FILE};
+use ::tetris::verify::{ClaimedRecord, RecordRejection};
+use futures_util::FutureExt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tokio::task::JoinHandle;
+
+const SERVER_ADDR: &str = "ws://localhost:8080";
+
+// How long a first R press on the pause screen waits for the confirming one
+const RESTART_CONFIRM_WINDOW: Duration = Duration::from_secs(2);
+// How long a sprint split's comparison with the best stays next to the clock
+const SPLIT_DELTA_DURATION: Duration = Duration::from_secs(3);
+// The gamepad read for gamepad bindings
+const GAMEPAD: i32 = 0;
 
 struct SoundEffects<'a> {
     move_sound: Sound<'a>,
@@ -10,7 +51,10 @@ struct SoundEffects<'a> {
     hard_drop_sound: Sound<'a>,
     line_clear_sound: Sound<'a>,
     game_over_sound: Sound<'a>,
+    buzz_sound: Sound<'a>,
     last_line_clear: Instant,
+    // Scales every effect's own volume
+    volume: f32,
 }
 
 impl<'a> SoundEffects<'a> {
@@ -31,48 +75,865 @@ impl<'a> SoundEffects<'a> {
             game_over_sound: rl
                 .new_sound("assets/sounds/game_over.wav")
                 .expect("Failed to load game over sound"),
+            // The move sound, pitched down, makes a soft buzz
+            buzz_sound: rl
+                .new_sound("assets/sounds/move.wav")
+                .expect("Failed to load move sound"),
             last_line_clear: Instant::now(),
+            volume: 1.0,
         }
     }
 
     fn play_move(&mut self) {
-        self.move_sound.set_volume(0.5);
+        self.move_sound.set_volume(0.5 * self.volume);
         self.move_sound.play();
     }
 
     fn play_rotate(&mut self) {
-        self.rotate_sound.set_volume(0.2);
+        self.rotate_sound.set_volume(0.2 * self.volume);
         self.rotate_sound.play();
     }
 
     fn play_hard_drop(&mut self) {
-        self.hard_drop_sound.set_volume(0.5);
+        self.hard_drop_sound.set_volume(0.5 * self.volume);
         self.hard_drop_sound.play();
     }
 
     fn try_play_line_clear(&mut self) {
         if self.last_line_clear.elapsed() >= Duration::from_millis(200) {
-            self.line_clear_sound.set_volume(1.0);
+            self.line_clear_sound.set_volume(1.0 * self.volume);
             self.line_clear_sound.play();
             self.last_line_clear = Instant::now();
         }
     }
 
     fn play_game_over(&mut self) {
-        self.game_over_sound.set_volume(0.3);
+        self.game_over_sound.set_volume(0.3 * self.volume);
         self.game_over_sound.play();
     }
+
+    fn play_warning(&mut self) {
+        self.buzz_sound.set_volume(0.6 * self.volume);
+        self.buzz_sound.set_pitch(0.8);
+        self.buzz_sound.play();
+    }
+
+    fn play_buzz(&mut self) {
+        self.buzz_sound.set_volume(0.3 * self.volume);
+        self.buzz_sound.set_pitch(0.4);
+        self.buzz_sound.play();
+    }
+}
+
+/// Puts `config` into effect: input timing, volumes, the theme and the UI scale.
+fn apply_config(
+    config: &Config,
+    input: &mut InputMap,
+    sound_effects: &mut SoundEffects<'_>,
+    music: &mut Music<'_>,
+) {
+    input.set_timing(config.das, config.arr);
+    sound_effects.volume = config.sfx_volume;
+    music.set_volume(config.music_volume);
+    set_theme(config.theme);
+    set_ui_scale(config.ui_scale);
+}
+
+/// Classic mode keeps the flat squares of the original, everything else rounds them off.
+fn block_style(mode: GameMode, config: &Config) -> BlockStyle {
+    if mode == GameMode::Classic {
+        BlockStyle::Square
+    } else if config.connected_pieces {
+        BlockStyle::Connected
+    } else {
+        BlockStyle::Rounded
+    }
+}
+
+/// Sets the gamepad's motor speeds. raylib 5.0 has no vibration call (it arrives in 5.5),
+/// so until the bindings catch up this does nothing and pads stay still.
+fn rumble_gamepad(_rl: &RaylibHandle, _left: f32, _right: f32) {}
+
+/// Player 1's bindings: their own keys, or the left of the keyboard in local versus.
+fn player_bindings(config: &Config, versus: bool) -> Vec<(Input, Action)> {
+    if versus {
+        KeyBindings::player_one().bindings()
+    } else {
+        config.keys.bindings()
+    }
+}
+
+struct CpuOpponent {
+    game: Game,
+    ai: AiPlayer,
+    won: bool,
+}
+
+/// Player 2 in local versus, with their own key repeat state.
+struct LocalRival {
+    game: Game,
+    input: InputMap,
+    won: bool,
+}
+
+/// Who a marathon is played against offline, instead of online players.
+#[derive(Clone, Copy)]
+enum Opponent {
+    Cpu(Difficulty),
+    Local,
+}
+
+/// Applies one player's input to `game`: shifts and rotation with their key repeat, soft
+/// drop, hard drop and hold. `allowed` can hold actions back. Returns the hard drop's lock.
+fn handle_input(
+    rl: &RaylibHandle,
+    game: &mut Game,
+    input: &mut InputMap,
+    allowed: impl Fn(Action) -> bool,
+    sound_effects: &mut SoundEffects<'_>,
+) -> Option<LockResult> {
+    let actions = input.update(|input| match input {
+        Input::Key(key) => rl.is_key_down(key),
+        Input::Gamepad(button) => rl.is_gamepad_button_down(GAMEPAD, button),
+    });
+
+    game.timer.soft_drop = input.is_held(Action::SoftDrop) && allowed(Action::SoftDrop);
+
+    // Keys held now act on the next piece as it spawns
+    game.hold_requested = input.is_held(Action::Hold) && allowed(Action::Hold);
+    game.rotation_requested = input.is_held(Action::RotateCw) && allowed(Action::RotateCw);
+
+    let mut lock = None;
+    for action in actions.into_iter().filter(|&action| allowed(action)) {
+        match action {
+            Action::HardDrop if game.is_controllable() => {
+                sound_effects.play_hard_drop();
+                lock = Some(game.hard_drop());
+            }
+            // Buffered until the next piece can move
+            Action::HardDrop => {
+                game.apply(action);
+            }
+            _ => {
+                // Only a new press counts for finesse, not the repeats after it
+                let acted = if input.is_repeat(action) {
+                    game.apply(action)
+                } else {
+                    game.press(action)
+                };
+                match action {
+                    Action::RotateCw if acted => sound_effects.play_rotate(),
+                    _ if acted => sound_effects.play_move(),
+                    _ => {}
+                }
+            }
+        }
+    }
+    lock
+}
+
+/// Trades garbage between two games played on this machine, using the multiplayer attack
+/// table and landing by the same rule, and ends the match as soon as either side tops out.
+/// Returns whether `other`, warned about as `other_name`, won once the match is over.
+fn exchange_garbage(game: &mut Game, other: &mut Game, other_name: &str) -> Option<bool> {
+    other.queue_garbage(tr("player_one").to_string(), game.take_outgoing_garbage());
+    game.queue_garbage(other_name.to_string(), other.take_outgoing_garbage());
+
+    let lost = game.state == GameState::GameOver;
+    let other_lost = other.state == GameState::GameOver;
+    if other_lost && !lost {
+        game.state = GameState::GameOver;
+        Some(false)
+    } else if lost && !other_lost {
+        other.state = GameState::GameOver;
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// A run at the daily challenge, with the leaderboard fetched from the server.
+struct DailyRun {
+    date: String,
+    official: bool,
+    leaderboard: Option<Vec<DailyEntry>>,
+    // Whether the server put the official run on the leaderboard, once it has said
+    verdict: Option<Result<(), RecordRejection>>,
+    sync: Option<JoinHandle<Result<DailySync, String>>>,
+}
+
+impl DailyRun {
+    /// Starts today's challenge. Only the first attempt of the day is official.
+    fn start(game: &mut Game, high_scores: &mut HighScores) -> Self {
+        let date = UtcDateTime::now().date();
+        let official = high_scores.start_daily(&date);
+        if let Err(e) = high_scores.save(HIGH_SCORES_PATH) {
+            eprintln!("Failed to save high scores: {}", e);
+        }
+        game.start_sprint(daily_seed(&date));
+        let sync = tokio::spawn(sync_daily(SERVER_ADDR, date.clone(), None));
+        Self {
+            date,
+            official,
+            leaderboard: None,
+            verdict: None,
+            sync: Some(sync),
+        }
+    }
+
+    /// Records a finished run, submitting it with its inputs if it was the official attempt.
+    fn finish(&mut self, game: &Game, high_scores: &mut HighScores) {
+        let mut submission = None;
+        // An assisted run still gets to see the leaderboard, just not go on it
+        if self.official && !game.is_assisted() {
+            high_scores.finish_daily(&self.date, game.play_time);
+            if let Err(e) = high_scores.save(HIGH_SCORES_PATH) {
+                eprintln!("Failed to save high scores: {}", e);
+            }
+            submission = game.input_log().map(|log| DailySubmission {
+                name: game.player_name.clone(),
+                claimed: ClaimedRecord::of(game),
+                replay_bytes: log.to_bytes(),
+            });
+        }
+        let sync = sync_daily(SERVER_ADDR, self.date.clone(), submission);
+        self.sync = Some(tokio::spawn(sync));
+    }
+
+    /// Picks up the leaderboard once the server has answered.
+    fn poll(&mut self) {
+        let Some(sync) = &mut self.sync else {
+            return;
+        };
+        if !sync.is_finished() {
+            return;
+        }
+        match sync.now_or_never() {
+            Some(Ok(Ok(sync))) => {
+                self.leaderboard = Some(sync.entries);
+                self.verdict = sync.verdict;
+            }
+            Some(Ok(Err(e))) => eprintln!("Failed to fetch the daily leaderboard: {}", e),
+            Some(Err(e)) => eprintln!("Failed to fetch the daily leaderboard: {}", e),
+            None => return,
+        }
+        self.sync = None;
+    }
+}
+
+/// Parses `--vs-cpu [easy|normal|hard]` or `--versus` from the command line.
+fn parse_opponent() -> Option<Opponent> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--vs-cpu" => {
+                let difficulty = args
+                    .next()
+                    .and_then(|name| Difficulty::from_name(&name))
+                    .unwrap_or(Difficulty::Normal);
+                return Some(Opponent::Cpu(difficulty));
+            }
+            "--versus" => return Some(Opponent::Local),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses `--classic [start-level]` from the command line.
+fn parse_classic() -> Option<u32> {
+    let mut args = std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        if arg == "--classic" {
+            let level = args
+                .next_if(|next| !next.starts_with("--"))
+                .and_then(|level| level.parse().ok())
+                .unwrap_or(0);
+            return Some(level.min(CLASSIC_MAX_START_LEVEL));
+        }
+    }
+    None
+}
+
+/// Parses `--level <start-level>` from the command line, for marathon.
+fn parse_level() -> Option<u32> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--level" {
+            let level = args.next().and_then(|level| level.parse().ok()).unwrap_or(1);
+            return Some(level.clamp(1, MAX_START_LEVEL));
+        }
+    }
+    None
+}
+
+/// Parses `--name <name>` from the command line.
+fn parse_name() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--name" {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// Parses `--lang <code>`, which takes precedence over the config file.
+fn parse_lang() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--lang" {
+            return args.next();
+        }
+    }
+    None
+}
+
+struct RoomArgs {
+    name: String,
+    create: bool,
+    password: Option<String>,
+    // Only used for a room we create
+    rules: RoomRules,
+}
+
+/// Parses `--room <name>` or `--create-room <name>`, and `--room-password <password>`,
+/// with `--lock-delay <ms>`, `--garbage-messiness <0-1>` and `--no-hold` for the rules of
+/// a new room.
+fn parse_room() -> Option<RoomArgs> {
+    let mut room = None;
+    let mut password = None;
+    let mut rules = RoomRules::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--room" | "--create-room" => {
+                let create = arg == "--create-room";
+                room = args.next().map(|name| (name, create));
+            }
+            "--room-password" => password = args.next(),
+            "--lock-delay" => {
+                let millis = args.next().and_then(|ms| ms.parse().ok());
+                rules.lock_delay_ms = millis.unwrap_or(rules.lock_delay_ms);
+            }
+            "--garbage-messiness" => {
+                let messiness = args.next().and_then(|value| value.parse::<f32>().ok());
+                let messiness = messiness.map(|value| value.clamp(0.0, 1.0));
+                rules.garbage.messiness = messiness.unwrap_or(rules.garbage.messiness);
+            }
+            "--no-hold" => rules.hold = false,
+            "--perfect-clear-attack" => {
+                let lines = args.next().and_then(|lines| lines.parse::<u32>().ok());
+                let lines = lines.map(|lines| lines.min(MAX_PERFECT_CLEAR_ATTACK));
+                rules.perfect_clear_attack = lines.unwrap_or(rules.perfect_clear_attack);
+            }
+            "--garbage-delay" => {
+                let pieces = args.next().and_then(|pieces| pieces.parse::<u32>().ok());
+                let pieces = pieces.map(|pieces| pieces.min(MAX_GARBAGE_DELAY_PIECES));
+                rules.garbage_delay_pieces = pieces.unwrap_or(rules.garbage_delay_pieces);
+            }
+            _ => {}
+        }
+    }
+    room.map(|(name, create)| RoomArgs {
+        name,
+        create,
+        password,
+        rules,
+    })
+}
+
+/// Parses `--entry-delay <ms>` from the command line.
+fn parse_entry_delay() -> Duration {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--entry-delay" {
+            let millis = args.next().and_then(|ms| ms.parse().ok()).unwrap_or(0);
+            return Duration::from_millis(millis).min(MAX_ENTRY_DELAY);
+        }
+    }
+    Duration::ZERO
+}
+
+/// Toast text for files saved to `path` with the given extensions, or why they weren't.
+fn saved_message(saved: std::io::Result<std::path::PathBuf>, extensions: &str) -> String {
+    match saved {
+        Ok(path) => {
+            let path = format!("{}{}", path.display(), extensions);
+            tr_args("saved_to", &[("path", &path)])
+        }
+        Err(e) => tr_args("save_failed", &[("error", &e)]),
+    }
+}
+
+/// Parses `--export-stats <dir>` from the command line, giving the log file to append to.
+fn parse_export_stats() -> Option<PathBuf> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--export-stats" {
+            return args.next().map(|dir| PathBuf::from(dir).join(GAME_LOG_FILE));
+        }
+    }
+    None
+}
+
+/// `--export-csv <log> [out]`: converts a stats log to CSV, written to `out` or printed.
+/// Returns whether the command was given.
+fn run_export_csv() -> bool {
+    if !std::env::args().any(|arg| arg == "--export-csv") {
+        return false;
+    }
+    let mut args = std::env::args().skip_while(|arg| arg != "--export-csv").skip(1);
+    let Some(log_path) = args.next() else {
+        eprintln!("--export-csv needs the path of a stats log");
+        return true;
+    };
+    let csv = match std::fs::read_to_string(&log_path) {
+        Ok(log) => log_to_csv(&log).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    let written = csv.and_then(|csv| match args.next() {
+        Some(out) => std::fs::write(out, csv).map_err(|e| e.to_string()),
+        None => {
+            print!("{}", csv);
+            Ok(())
+        }
+    });
+    if let Err(e) = written {
+        eprintln!("Failed to convert {}: {}", log_path, e);
+    }
+    true
+}
+
+/// `--list-rooms`: prints the server's rooms, marking the ones that need a password and the
+/// ones with a match on.
+/// Returns whether the command was given.
+async fn run_list_rooms() -> bool {
+    if !std::env::args().any(|arg| arg == "--list-rooms") {
+        return false;
+    }
+    match list_rooms(SERVER_ADDR).await {
+        Ok(rooms) => {
+            for room in rooms {
+                let limit = room.limit.map_or(String::new(), |limit| format!("/{}", limit));
+                let lock = if room.locked { " (locked)" } else { "" };
+                let playing = if room.in_match { ", in a match" } else { "" };
+                println!("{}: {}{} players{}{}", room.name, room.players, limit, lock, playing);
+            }
+        }
+        Err(e) => eprintln!("Failed to list rooms: {}", e),
+    }
+    true
+}
+
+/// `--list-replays`: prints the matches the server has recorded, newest first.
+/// Returns whether the command was given.
+async fn run_list_replays() -> bool {
+    if !std::env::args().any(|arg| arg == "--list-replays") {
+        return false;
+    }
+    match list_replays(SERVER_ADDR).await {
+        Ok(replays) => {
+            for replay in replays {
+                let players: Vec<&str> = replay
+                    .players
+                    .iter()
+                    .map(|player| player.name.as_deref().unwrap_or(&player.player_id))
+                    .collect();
+                println!("{}: {}", replay.id, players.join(" vs "));
+            }
+        }
+        Err(e) => eprintln!("Failed to list replays: {}", e),
+    }
+    true
+}
+
+// How far Right skips ahead in a replay
+const REPLAY_SKIP: Duration = Duration::from_secs(5);
+
+/// `--replay <id>`: downloads a recorded match from the server and plays it back in its
+/// own window. Returns whether the command was given.
+async fn run_replay() -> bool {
+    if !std::env::args().any(|arg| arg == "--replay") {
+        return false;
+    }
+    let mut args = std::env::args().skip_while(|arg| arg != "--replay").skip(1);
+    let Some(id) = args.next() else {
+        eprintln!("--replay needs the id of a replay, as --list-replays prints");
+        return true;
+    };
+    let replay = match fetch_replay(SERVER_ADDR, &id).await {
+        Ok(Some(text)) => Replay::parse(&text),
+        Ok(None) => Err("no such replay".to_string()),
+        Err(e) => Err(e),
+    };
+    let replay = match replay {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("Failed to load replay {}: {}", id, e);
+            return true;
+        }
+    };
+    if let Some(lang) = parse_lang() {
+        match Strings::load(LOCALES_DIR, &lang) {
+            Ok(strings) => {
+                set_locale(strings);
+            }
+            Err(e) => eprintln!("Failed to load language {:?}: {}", lang, e),
+        }
+    }
+
+    let (mut rl, thread) = raylib::init()
+        .size(WINDOW_WIDTH, WINDOW_HEIGHT)
+        .title(&format!("Tetris — replay {}", id))
+        .vsync()
+        .build();
+    rl.set_target_fps(FPS);
+    let mut playback = Playback::new(replay);
+    let mut paused = false;
+    while !rl.window_should_close() {
+        if rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
+            paused = !paused;
+        }
+        let mut step = Duration::from_secs_f32(rl.get_frame_time());
+        if paused {
+            step = Duration::ZERO;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+            step += REPLAY_SKIP;
+        }
+        playback.advance(step);
+
+        let mut d = rl.begin_drawing(&thread);
+        d.clear_background(theme().background);
+        draw_replay(&mut d, &playback, paused);
+    }
+    true
+}
+
+/// Parses `--stats-port <port>` from the command line.
+fn parse_stats_port() -> Option<u16> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--stats-port" {
+            return args.next().and_then(|port| port.parse().ok());
+        }
+    }
+    None
+}
+
+/// Parses `--cheese [lines]` from the command line.
+fn parse_cheese() -> Option<u32> {
+    let mut args = std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        if arg == "--cheese" {
+            let target = args
+                .next_if(|next| !next.starts_with("--"))
+                .and_then(|lines| lines.parse().ok())
+                .unwrap_or(CheeseConfig::default().target_lines);
+            return Some(target);
+        }
+    }
+    None
+}
+
+/// Parses `--practice [board-file]` and `--queue <pieces>` from the command line.
+fn parse_practice() -> Result<Option<PracticeSetup>, Box<dyn std::error::Error>> {
+    let mut setup = None;
+    let mut queue = None;
+    let mut args = std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--practice" => {
+                let mut practice = PracticeSetup::default();
+                if let Some(path) = args.next_if(|next| !next.starts_with("--")) {
+                    practice.load_board(&path)?;
+                }
+                setup = Some(practice);
+            }
+            "--queue" => {
+                let pieces = args.next().ok_or("--queue needs a piece sequence")?;
+                queue = Some(parse_queue(&pieces)?);
+            }
+            _ => {}
+        }
+    }
+
+    if let (Some(setup), Some(queue)) = (&mut setup, queue) {
+        setup.queue = queue;
+    }
+    Ok(setup)
+}
+
+/// Game modes, picked from the menu or given on the command line.
+enum ModeChoice {
+    Marathon(u32),
+    Classic(u32),
+    Cascade,
+    Sprint,
+    Daily,
+    CheeseRace(u32),
+    Missions,
+    Practice(Box<PracticeSetup>),
+    Tutorial,
+}
+
+impl ModeChoice {
+    /// The mode started by the menu's selection, with `None` for quitting, settings and the
+    /// board editor, which don't start a game. In the mode select it's the selected mode with
+    /// its option; both versus items and online play marathon from the marathon start level.
+    fn from_menu(menu: &Menu) -> Option<Self> {
+        if menu.is_choosing_mode() {
+            let mode = menu.selected_mode().mode;
+            let value = menu.option(mode).unwrap_or_default();
+            return Some(match mode {
+                GameMode::Marathon => ModeChoice::Marathon(value),
+                GameMode::Classic => ModeChoice::Classic(value),
+                GameMode::CheeseRace => ModeChoice::CheeseRace(value),
+                GameMode::Sprint => ModeChoice::Sprint,
+                GameMode::Cascade => ModeChoice::Cascade,
+                _ => ModeChoice::Practice(Box::default()),
+            });
+        }
+        let mode = match menu.selected() {
+            MenuItem::VersusCpu | MenuItem::VersusLocal | MenuItem::Online => {
+                ModeChoice::Marathon(menu.option(GameMode::Marathon).unwrap_or(1))
+            }
+            MenuItem::Daily => ModeChoice::Daily,
+            MenuItem::Missions => ModeChoice::Missions,
+            MenuItem::Tutorial => ModeChoice::Tutorial,
+            MenuItem::SinglePlayer
+            | MenuItem::Settings
+            | MenuItem::BoardEditor
+            | MenuItem::Quit => return None,
+        };
+        Some(mode)
+    }
+}
+
+/// Picks the mode given on the command line, if any.
+fn parse_mode() -> Result<Option<ModeChoice>, Box<dyn std::error::Error>> {
+    let practice = parse_practice()?;
+    let mode = if std::env::args().any(|arg| arg == "--missions") {
+        ModeChoice::Missions
+    } else if std::env::args().any(|arg| arg == "--daily") {
+        ModeChoice::Daily
+    } else if std::env::args().any(|arg| arg == "--tutorial") {
+        ModeChoice::Tutorial
+    } else if let Some(setup) = practice {
+        ModeChoice::Practice(Box::new(setup))
+    } else if let Some(target_lines) = parse_cheese() {
+        ModeChoice::CheeseRace(target_lines)
+    } else if let Some(start_level) = parse_classic() {
+        ModeChoice::Classic(start_level)
+    } else if std::env::args().any(|arg| arg == "--cascade") {
+        ModeChoice::Cascade
+    } else if std::env::args().any(|arg| arg == "--sprint") {
+        ModeChoice::Sprint
+    } else if parse_room().is_some()
+        || std::env::args().any(|arg| arg == "--quick-match")
+        || parse_level().is_some()
+    {
+        ModeChoice::Marathon(parse_level().unwrap_or(1))
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(mode))
+}
+
+/// Everything that belongs to one game, from picking a mode to going back to the menu.
+struct Session {
+    game: Game,
+    cpu: Option<CpuOpponent>,
+    rival: Option<LocalRival>,
+    missions: Option<MissionRun>,
+    daily_run: Option<DailyRun>,
+    tutorial: Option<Tutorial>,
+}
+
+impl Session {
+    /// Stands in while the menu is showing.
+    fn idle() -> Self {
+        Self {
+            game: Game::default(),
+            cpu: None,
+            rival: None,
+            missions: None,
+            daily_run: None,
+            tutorial: None,
+        }
+    }
+}
+
+/// Sets up and starts a game in `mode`, connecting to the server for online marathon and
+/// going into `room` once there.
+async fn start_session(
+    mode: ModeChoice,
+    opponent: Option<Opponent>,
+    room: Option<RoomArgs>,
+    randomizer: RandomizerKind,
+    high_scores: &mut HighScores,
+) -> Result<Session, String> {
+    let mut session = Session::idle();
+    let game = &mut session.game;
+    game.config.initial_actions = std::env::args().any(|arg| arg == "--initial-actions");
+    game.config.entry_delay = parse_entry_delay();
+    game.config.instant_gravity = std::env::args().any(|arg| arg == "--20g");
+    if std::env::args().any(|arg| arg == "--double-hold") {
+        game.config.hold_slots = MAX_HOLD_SLOTS;
+    }
+    if std::env::args().any(|arg| arg == "--variable-goal") {
+        game.config.leveling = Leveling::Variable;
+    }
+    game.player_name = parse_name();
+    // The daily is the same pieces for everyone, so it keeps the classic randomizer
+    if !matches!(mode, ModeChoice::Daily) {
+        game.config.randomizer = randomizer;
+    }
+
+    // Connect to multiplayer server (versus modes and the single player modes are offline)
+    if matches!(mode, ModeChoice::Marathon(_)) && opponent.is_none() {
+        if let Err(e) = game.connect_multiplayer(SERVER_ADDR).await {
+            eprintln!("Failed to connect to multiplayer server: {}", e);
+        } else {
+            // Online players all get classic pieces and one hold, whatever they picked
+            game.config.randomizer = RandomizerKind::Classic;
+            game.config.hold_slots = 1;
+            if let Some(room) = room {
+                if room.create {
+                    game.create_room(&room.name, room.password);
+                    game.set_room_rules(room.rules);
+                } else {
+                    game.join_room(&room.name, room.password);
+                }
+            } else if std::env::args().any(|arg| arg == "--quick-match") {
+                game.queue_for_match();
+            }
+        }
+    }
+
+    match mode {
+        ModeChoice::Missions => {
+            let missions = load_missions(MISSIONS_PATH)
+                .map_err(|e| format!("Failed to load missions: {}", e))?;
+            let run = MissionRun::new(missions);
+            game.start_mission(run.mission().setup.clone());
+            session.missions = Some(run);
+        }
+        ModeChoice::Daily => session.daily_run = Some(DailyRun::start(game, high_scores)),
+        ModeChoice::Practice(setup) => game.start_practice(*setup),
+        ModeChoice::Tutorial => {
+            // `--free-input` lets every key through, not just the ones a step teaches
+            let lock_input = !std::env::args().any(|arg| arg == "--free-input");
+            let tutorial = Tutorial::new(TUTORIAL_STEPS, lock_input);
+            game.start_tutorial(tutorial.setup());
+            session.tutorial = Some(tutorial);
+        }
+        ModeChoice::CheeseRace(target_lines) => game.start_cheese_race(target_lines),
+        ModeChoice::Classic(start_level) => game.start_classic(start_level),
+        ModeChoice::Cascade => {
+            game.mode = GameMode::Cascade;
+            game.start_game();
+        }
+        ModeChoice::Sprint => game.start_sprint(rand::random()),
+        ModeChoice::Marathon(start_level) => game.start_marathon(start_level),
+    }
+
+    match opponent {
+        Some(Opponent::Cpu(difficulty)) => {
+            let mut cpu = CpuOpponent {
+                game: Game::default(),
+                ai: AiPlayer::new(difficulty),
+                won: false,
+            };
+            cpu.game.config.randomizer = session.game.config.randomizer;
+            cpu.game.start_game();
+            session.cpu = Some(cpu);
+        }
+        Some(Opponent::Local) => {
+            // Both players play the same rules from the same level
+            let mut rival = LocalRival {
+                game: Game::default(),
+                input: InputMap::new(KeyBindings::player_two().bindings()),
+                won: false,
+            };
+            rival.game.config = session.game.config.clone();
+            rival.game.start_marathon(session.game.score.level);
+            session.rival = Some(rival);
+        }
+        None => {}
+    }
+    Ok(session)
 }
 
 #[tokio::main]
 async fn main() {
+    let command = run_export_csv()
+        || run_list_rooms().await
+        || run_list_replays().await
+        || run_replay().await;
+    if command {
+        return;
+    }
+    let launch = match parse_mode() {
+        Ok(launch) => launch,
+        Err(e) => {
+            eprintln!("Failed to set up practice mode: {}", e);
+            return;
+        }
+    };
+    let opponent = parse_opponent();
+    let mut high_scores = HighScores::load(HIGH_SCORES_PATH).unwrap_or_else(|e| {
+        eprintln!("Failed to load high scores: {}", e);
+        HighScores::default()
+    });
+
+    // A mode on the command line skips the menu
+    let mut menu = Menu::default();
+    // Open over the menu, from its Settings item
+    let mut settings: Option<SettingsMenu> = None;
+    // Likewise the board editor, and the setup it left to be played
+    let mut editor: Option<BoardEditor> = None;
+    let mut edited: Option<PracticeSetup> = None;
+    // And the server browser, with its request for the room list and the room it picked
+    let mut browser: Option<ServerBrowser> = None;
+    let mut room_fetch: Option<JoinHandle<Result<Vec<RoomInfo>, String>>> = None;
+    let mut browsed: Option<RoomArgs> = None;
+    let mut in_menu = launch.is_none() && opponent.is_none();
+    let session = if in_menu {
+        Session::idle()
+    } else {
+        let mode = launch.unwrap_or(ModeChoice::Marathon(1));
+        // A broken config is reported once the window is up
+        let randomizer = load_config(CONFIG_PATH).map(|config| config.randomizer);
+        let randomizer = randomizer.unwrap_or_default();
+        match start_session(mode, opponent, parse_room(), randomizer, &mut high_scores).await {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    };
+    let Session {
+        mut game,
+        mut cpu,
+        mut rival,
+        mut missions,
+        mut daily_run,
+        mut tutorial,
+    } = session;
+
     let (mut rl, thread) = raylib::init()
         .size(WINDOW_WIDTH, WINDOW_HEIGHT)
         .title("Tetris")
+        .resizable()
         .vsync()
         .build();
 
     rl.set_target_fps(FPS);
+    // Escape pauses, and leaves for the menu from the pause screen, instead of closing the window
+    rl.set_exit_key(None);
 
     // Initialize audio device
     let audio_device = RaylibAudio::init_audio_device().expect("Failed to initialize audio device");
@@ -84,215 +945,1344 @@ async fn main() {
     let mut music = audio_device
         .new_music("assets/background.mp3")
         .expect("Failed to load background music");
-    music.set_volume(0.2);
     music.play_stream();
 
-    let mut game = Game::default();
+    let mut chain_popup: Option<(u32, Instant)> = None;
+    let mut attack_popup: Option<(u32, Instant)> = None;
+    let mut perfect_clear_flash: Option<Instant> = None;
+    // Newest first, until each attack lands or its crossed-out look fades
+    let mut attack_warnings: Vec<AttackWarning> = Vec::new();
+    let mut toast: Option<(String, Instant)> = None;
+    let mut pause_denied: Option<Instant> = None;
+    let mut restart_armed: Option<Instant> = None;
+    let mut restart_hold = HoldGesture::new(RESTART_HOLD);
+    let mut idle = IdleTimer::default();
+    // The placement heatmap, shown over a finished game's results
+    let mut show_heatmap = false;
+    // Practice mode's input counts for each spot the piece can land
+    let mut show_placement_hints = false;
+    let mut placement_hints = PlacementHintCache::default();
+    // Sprint splits taken so far and how the last compared to the best, then the best as
+    // it stood before the run finished and whether the run beat it
+    let mut splits_seen = 0;
+    let mut split_delta: Option<(i64, Instant)> = None;
+    let mut sprint_best_before: Option<BestSplits> = None;
+    let mut sprint_new_best = false;
+    // Whether the game just over set the best score for its mode
+    let mut new_high_score = false;
+    // `--coach` flashes the board and buzzes when a placement takes too many inputs
+    let coach = std::env::args().any(|arg| arg == "--coach");
+    let mut finesse_flash: Option<Instant> = None;
+    // `--debug` adds slow motion and frame stepping
+    let debug = std::env::args().any(|arg| arg == "--debug");
+    let mut opponent_order = OpponentOrder::default();
+    let mut opponent_page: usize = 0;
+    let mut window_title_text = String::new();
+    let mut window_title_updated = Instant::now();
 
-    // Connect to multiplayer server
-    if let Err(e) = game.connect_multiplayer("ws://localhost:8080").await {
-        eprintln!("Failed to connect to multiplayer server: {}", e);
-    }
+    // Live stats for stream overlays, if asked for
+    let export_stats = parse_export_stats();
+    let stats_server = match parse_stats_port() {
+        Some(port) => match StatsServer::start(port).await {
+            Ok(server) => Some(server),
+            Err(e) => {
+                eprintln!("Failed to start the stats server on port {}: {}", port, e);
+                None
+            }
+        },
+        None => None,
+    };
 
-    game.start_game();
+    // Settings from config.toml, reloaded whenever the file changes
+    let mut config = load_config(CONFIG_PATH).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        toast = Some((e.to_string(), Instant::now()));
+        Config::default()
+    });
+    let mut input = InputMap::new(player_bindings(&config, rival.is_some()));
+    let mut haptics = Haptics::new(config.rumble);
+    apply_config(&config, &mut input, &mut sound_effects, &mut music);
+    if let Some(rival) = &mut rival {
+        rival.input.set_timing(config.das, config.arr);
+    }
+    // Each frame is laid out at its natural size here, then scaled to fit the window
+    let mut scene_width = WINDOW_WIDTH;
+    let mut scene = rl
+        .load_render_texture(&thread, scene_width as u32, WINDOW_HEIGHT as u32)
+        .expect("Failed to create the scene texture");
+    let mut board_sprites =
+        BoardSprites::new(&mut rl, &thread).expect("Failed to create the board textures");
+    let mut config_watcher = ConfigWatcher::new(CONFIG_PATH);
+    // Missing text falls back to English, so a partial translation still works
+    if let Some(lang) = parse_lang().or_else(|| config.language.clone()) {
+        match Strings::load(LOCALES_DIR, &lang) {
+            Ok(strings) => {
+                set_locale(strings);
+            }
+            Err(e) => eprintln!("Failed to load language {:?}: {}", lang, e),
+        }
+    }
 
-    let mut left_key = KeyState::new(false);
-    let mut right_key = KeyState::new(false);
-    let mut down_key = KeyState::new(false);
-    let mut rotate_key = KeyState::new(true);
+    // Rich presence, when built with the `discord` feature and given an application id
+    #[cfg(feature = "discord")]
+    let discord = config.discord_app_id.clone().map(DiscordPresence::start);
+    #[cfg(feature = "discord")]
+    let mut presence_sender = Throttled::new(PRESENCE_INTERVAL);
 
     while !rl.window_should_close() {
         // Update music stream
         music.update_stream();
 
-        // Handle input
-        if game.state == GameState::Playing {
-            let mut moved = false;
+        // A bad edit keeps the settings already in use
+        match config_watcher.poll_at(Instant::now()) {
+            Some(Ok(reloaded)) => {
+                config = reloaded;
+                haptics.enabled = config.rumble;
+                apply_config(&config, &mut input, &mut sound_effects, &mut music);
+                input.set_bindings(player_bindings(&config, rival.is_some()));
+                if let Some(rival) = &mut rival {
+                    rival.input.set_timing(config.das, config.arr);
+                }
+                toast = Some((tr("config_reloaded").to_string(), Instant::now()));
+            }
+            Some(Err(e)) => toast = Some((e.to_string(), Instant::now())),
+            None => {}
+        }
+
+        #[cfg(feature = "discord")]
+        if let Some(discord) = &discord {
+            let current = if in_menu {
+                Presence::in_menu()
+            } else {
+                presence(&game, SystemTime::now())
+            };
+            if let Some(current) = presence_sender.poll(current) {
+                discord.update(current);
+            }
+        }
+
+        // Local versus and bigger UI scales widen the scene, and the window along with it
+        set_versus_layout(rival.is_some() && !in_menu);
+        if layout().window_width != scene_width {
+            scene_width = layout().window_width;
+            scene = rl
+                .load_render_texture(&thread, scene_width as u32, WINDOW_HEIGHT as u32)
+                .expect("Failed to create the scene texture");
+            rl.set_window_size(scene_width, WINDOW_HEIGHT);
+        }
+        board_sprites.refresh(&mut rl, &thread);
 
-            if left_key.update(rl.is_key_down(KeyboardKey::KEY_LEFT)) {
-                moved = game.move_current_block(-1, 0);
-                if moved {
-                    sound_effects.play_move();
+        if let Some(screen) = &mut settings {
+            let mut saved = None;
+            let mut back = false;
+            if screen.rebind.is_some() {
+                // raylib empties its queue every frame, so the Enter that started this is gone
+                while let Some(key) = rl.get_key_pressed() {
+                    if let Some(keys) = screen.key_pressed(key, &config.keys) {
+                        config.keys = keys;
+                        saved = Some(save_keys(CONFIG_PATH, &config.keys));
+                    }
+                }
+            } else {
+                if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+                    screen.move_cursor(-1);
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+                    screen.move_cursor(1);
+                }
+                let steps = rl.is_key_pressed(KeyboardKey::KEY_RIGHT) as i32
+                    - rl.is_key_pressed(KeyboardKey::KEY_LEFT) as i32;
+                let scale = step_ui_scale(config.ui_scale, steps);
+                if screen.selected() == SettingsItem::UiScale && scale != config.ui_scale {
+                    config.ui_scale = scale;
+                    set_ui_scale(scale);
+                    saved = Some(save_ui_scale(CONFIG_PATH, scale));
+                }
+                let toggled = steps != 0 || rl.is_key_pressed(KeyboardKey::KEY_ENTER);
+                if screen.selected() == SettingsItem::Rumble && toggled {
+                    config.rumble = !config.rumble;
+                    haptics.enabled = config.rumble;
+                    saved = Some(save_rumble(CONFIG_PATH, config.rumble));
+                }
+                if screen.selected() == SettingsItem::Randomizer && steps != 0 {
+                    config.randomizer = config.randomizer.cycled(steps);
+                    saved = Some(save_randomizer(CONFIG_PATH, config.randomizer));
+                }
+                if screen.selected() == SettingsItem::ConnectedPieces && toggled {
+                    config.connected_pieces = !config.connected_pieces;
+                    saved = Some(save_connected_pieces(CONFIG_PATH, config.connected_pieces));
+                }
+                if screen.selected() == SettingsItem::TranslucentPause && toggled {
+                    config.translucent_pause = !config.translucent_pause;
+                    saved = Some(save_translucent_pause(CONFIG_PATH, config.translucent_pause));
                 }
+                if screen.selected() == SettingsItem::ReduceMotion && toggled {
+                    config.reduce_motion = !config.reduce_motion;
+                    saved = Some(save_reduce_motion(CONFIG_PATH, config.reduce_motion));
+                }
+                if screen.selected() == SettingsItem::DroughtCounter && toggled {
+                    config.drought_counter = !config.drought_counter;
+                    saved = Some(save_drought_counter(CONFIG_PATH, config.drought_counter));
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+                    match screen.selected() {
+                        SettingsItem::Bind(action) => {
+                            screen.rebind = Some(Rebind::Listening(action));
+                        }
+                        SettingsItem::ResetKeys => {
+                            config.keys = KeyBindings::default();
+                            saved = Some(save_keys(CONFIG_PATH, &config.keys));
+                        }
+                        SettingsItem::Back => back = true,
+                        SettingsItem::UiScale
+                        | SettingsItem::Rumble
+                        | SettingsItem::Randomizer
+                        | SettingsItem::ConnectedPieces
+                        | SettingsItem::TranslucentPause
+                        | SettingsItem::ReduceMotion
+                        | SettingsItem::DroughtCounter => {}
+                    }
+                }
+                back |= rl.is_key_pressed(KeyboardKey::KEY_ESCAPE);
             }
-            if right_key.update(rl.is_key_down(KeyboardKey::KEY_RIGHT)) && !moved {
-                moved = game.move_current_block(1, 0);
-                if moved {
-                    sound_effects.play_move();
+            if let Some(result) = saved {
+                if let Err(e) = result {
+                    toast = Some((tr_args("save_failed", &[("error", &e)]), Instant::now()));
                 }
+                // Our own write isn't an edit to reload
+                config_watcher = ConfigWatcher::new(CONFIG_PATH);
             }
-            if rotate_key.update(rl.is_key_down(KeyboardKey::KEY_UP)) {
-                if game.rotate_current_block() {
-                    sound_effects.play_rotate();
+
+            let mut frame = rl.begin_drawing(&thread);
+            let mut d = frame.begin_texture_mode(&thread, &mut scene);
+            d.clear_background(theme().background);
+            draw_settings(&mut d, screen, &config);
+            if let Some((text, shown_at)) = &toast {
+                if shown_at.elapsed() < TOAST_DURATION {
+                    draw_toast(&mut d, text);
                 }
             }
+            drop(d);
+            draw_scene(&mut frame, &scene);
+            if back {
+                settings = None;
+            }
+            continue;
+        }
 
-            game.timer.soft_drop = down_key.update(rl.is_key_down(KeyboardKey::KEY_DOWN));
+        if let Some(screen) = &mut editor {
+            let mut back = rl.is_key_pressed(KeyboardKey::KEY_ESCAPE);
+            if rl.is_key_pressed(KeyboardKey::KEY_TAB) {
+                screen.next_field();
+            }
+            if screen.field == EditorField::Board {
+                let dx = rl.is_key_pressed(KeyboardKey::KEY_RIGHT) as isize
+                    - rl.is_key_pressed(KeyboardKey::KEY_LEFT) as isize;
+                let dy = rl.is_key_pressed(KeyboardKey::KEY_DOWN) as isize
+                    - rl.is_key_pressed(KeyboardKey::KEY_UP) as isize;
+                screen.move_cursor(dx, dy);
+                if rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
+                    screen.toggle();
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_C) {
+                    screen.cycle_brush();
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_F) {
+                    screen.fill_row();
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_X) {
+                    screen.clear_row();
+                }
+            } else {
+                while let Some(ch) = rl.get_char_pressed() {
+                    screen.type_char(ch);
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+                    screen.backspace();
+                }
+            }
+            // Saved and played straight away, from the menu next frame
+            if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+                match screen.setup() {
+                    Ok(setup) => {
+                        toast = Some((saved_message(screen.save(SETUPS_DIR), ""), Instant::now()));
+                        edited = Some(setup);
+                        back = true;
+                    }
+                    Err(e) => screen.error = Some(e),
+                }
+            }
 
-            if rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
-                if game.hard_drop() {
-                    sound_effects.play_hard_drop();
-                } else {
-                    sound_effects.play_hard_drop();
+            let mut frame = rl.begin_drawing(&thread);
+            let mut d = frame.begin_texture_mode(&thread, &mut scene);
+            d.clear_background(theme().background);
+            let style = block_style(GameMode::Practice, &config);
+            draw_board_editor(&mut d, screen, style, &board_sprites);
+            drop(d);
+            draw_scene(&mut frame, &scene);
+            if back {
+                editor = None;
+            }
+            continue;
+        }
+
+        if let Some(screen) = &mut browser {
+            let now = Instant::now();
+            if room_fetch.is_none() && screen.wants_refresh(now) {
+                room_fetch = Some(tokio::spawn(list_rooms(SERVER_ADDR)));
+                screen.requested(now);
+            }
+            if room_fetch.as_ref().is_some_and(JoinHandle::is_finished) {
+                if let Some(rooms) = room_fetch.take().and_then(FutureExt::now_or_never) {
+                    screen.received(rooms.unwrap_or_else(|e| Err(e.to_string())));
+                }
+            }
+
+            let mut choice = None;
+            if let Some((_, input)) = &mut screen.prompt {
+                while let Some(ch) = rl.get_char_pressed() {
+                    input.type_char(ch);
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+                    input.backspace();
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+                    choice = screen.submit_prompt();
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) {
+                    screen.prompt = None;
+                }
+            } else {
+                if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+                    screen.move_cursor(-1);
                 }
+                if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+                    screen.move_cursor(1);
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_R) {
+                    screen.refresh();
+                }
+                // The mouse picks the row it's over once it moves, and a click acts on it
+                let window = (rl.get_screen_width() as f32, rl.get_screen_height() as f32);
+                let size = (scene_width as f32, WINDOW_HEIGHT as f32);
+                let mouse = scene_point(rl.get_mouse_position(), window, size);
+                let hovered = browser_row_at(screen, mouse.y);
+                if let Some(row) = hovered.filter(|_| rl.get_mouse_delta() != Vector2::zero()) {
+                    screen.select(row);
+                }
+                let clicked = hovered.is_some()
+                    && rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT);
+                if rl.is_key_pressed(KeyboardKey::KEY_ENTER) || clicked {
+                    choice = screen.confirm();
+                }
+                if rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) {
+                    choice = Some(BrowserChoice::Back);
+                }
+            }
+
+            let mut frame = rl.begin_drawing(&thread);
+            let mut d = frame.begin_texture_mode(&thread, &mut scene);
+            d.clear_background(theme().background);
+            draw_server_browser(&mut d, screen);
+            drop(d);
+            draw_scene(&mut frame, &scene);
+            let closed = choice.is_some();
+            // Played from the menu next frame, in the room picked
+            let room = match choice {
+                Some(BrowserChoice::Join { room, password }) => Some((room, false, password)),
+                Some(BrowserChoice::Create { name }) => Some((name, true, None)),
+                Some(BrowserChoice::Back) | None => None,
+            };
+            browsed = room.map(|(name, create, password)| RoomArgs {
+                name,
+                create,
+                password,
+                rules: RoomRules::default(),
+            });
+            if closed {
+                browser = None;
+                room_fetch = None;
             }
-            if (rl.is_key_pressed(KeyboardKey::KEY_LEFT_SHIFT)
-                || rl.is_key_pressed(KeyboardKey::KEY_C))
-                && !game.has_held
+            continue;
+        }
+
+        if in_menu {
+            if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+                menu.move_cursor(-1);
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+                menu.move_cursor(1);
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
+                menu.adjust(-1);
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+                menu.adjust(1);
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) {
+                menu.close_mode_select();
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_ENTER)
+                && !menu.is_choosing_mode()
+                && menu.selected() == MenuItem::SinglePlayer
             {
-                if let Some(held_block) = game.hold_block {
-                    let mut temp = held_block;
-                    temp.reset();
-                    game.hold_block = Some(game.current_block);
-                    game.current_block = temp;
-                } else {
-                    game.hold_block = Some(game.current_block);
-                    game.current_block = game.next_block;
-                    game.next_block = Block::new(BlockKind::random());
+                menu.open_mode_select();
+            } else if rl.is_key_pressed(KeyboardKey::KEY_ENTER)
+                && menu.selected() == MenuItem::Settings
+            {
+                settings = Some(SettingsMenu::default());
+            } else if rl.is_key_pressed(KeyboardKey::KEY_ENTER)
+                && menu.selected() == MenuItem::BoardEditor
+            {
+                editor = Some(BoardEditor::default());
+            } else if rl.is_key_pressed(KeyboardKey::KEY_ENTER)
+                && menu.selected() == MenuItem::Online
+            {
+                browser = Some(ServerBrowser::default());
+            } else if rl.is_key_pressed(KeyboardKey::KEY_ENTER)
+                || edited.is_some()
+                || browsed.is_some()
+            {
+                let mode = match edited.take() {
+                    Some(setup) => ModeChoice::Practice(Box::new(setup)),
+                    None => match ModeChoice::from_menu(&menu) {
+                        Some(mode) => mode,
+                        None => break,
+                    },
+                };
+                let opponent = match menu.selected() {
+                    MenuItem::VersusCpu => Some(Opponent::Cpu(Difficulty::Normal)),
+                    MenuItem::VersusLocal => Some(Opponent::Local),
+                    _ => None,
+                };
+                let room = browsed.take().or_else(parse_room);
+                let randomizer = config.randomizer;
+                match start_session(mode, opponent, room, randomizer, &mut high_scores).await {
+                    Ok(session) => {
+                        Session {
+                            game,
+                            cpu,
+                            rival,
+                            missions,
+                            daily_run,
+                            tutorial,
+                        } = session;
+                        input.set_bindings(player_bindings(&config, rival.is_some()));
+                        if let Some(rival) = &mut rival {
+                            rival.input.set_timing(config.das, config.arr);
+                        }
+                        in_menu = false;
+                        chain_popup = None;
+                        attack_popup = None;
+                        perfect_clear_flash = None;
+                        pause_denied = None;
+                        restart_armed = None;
+                        opponent_page = 0;
+                    }
+                    Err(e) => toast = Some((e, Instant::now())),
                 }
-                game.has_held = true;
-                sound_effects.play_move();
             }
+
+            let mut frame = rl.begin_drawing(&thread);
+            let mut d = frame.begin_texture_mode(&thread, &mut scene);
+            d.clear_background(theme().background);
+            if menu.is_choosing_mode() {
+                draw_mode_select(&mut d, &menu, &high_scores);
+            } else {
+                draw_menu(&mut d, &menu);
+            }
+            if let Some((text, shown_at)) = &toast {
+                if shown_at.elapsed() < TOAST_DURATION {
+                    draw_toast(&mut d, text);
+                }
+            }
+            drop(d);
+            draw_scene(&mut frame, &scene);
+            continue;
+        }
+
+        let prev_state = game.state;
+        let mut lock_results = Vec::new();
+
+        // Handle input
+        if game.state == GameState::Playing {
+            // The tutorial can hold back actions its current step doesn't teach
+            let allowed = |action| match &tutorial {
+                Some(tutorial) => tutorial.allows(action),
+                None => true,
+            };
+            let lock = handle_input(&rl, &mut game, &mut input, allowed, &mut sound_effects);
+            lock_results.extend(lock);
+        }
+        let mut rival_locks = Vec::new();
+        let rival_playing = rival.as_mut().filter(|rival| rival.game.state == GameState::Playing);
+        if let Some(rival) = rival_playing {
+            rival_locks.extend(handle_input(
+                &rl,
+                &mut rival.game,
+                &mut rival.input,
+                |_| true,
+                &mut sound_effects,
+            ));
+        }
+
+        // A game left alone pauses itself, or online plays on with us shown as away. Only
+        // time spent in a running game counts
+        let now = Instant::now();
+        let touched = input.any_held()
+            || rival.as_ref().is_some_and(|rival| rival.input.any_held())
+            || rl.get_key_pressed().is_some()
+            || rl.get_gamepad_button_pressed().is_some();
+        idle.update_at(!touched && game.state == GameState::Playing, now);
+        game.afk = config.idle_pause.is_some_and(|limit| idle.is_idle(limit, now));
+        if game.afk && rival.is_none() && game.multiplayer.is_none() && game.can_pause() {
+            game.idle_pause();
+            if let Some(cpu) = &mut cpu {
+                cpu.game.toggle_pause();
+            }
+            music.pause_stream();
         }
 
-        if rl.is_key_pressed(KeyboardKey::KEY_P) {
+        // The finished tutorial and the results screen have nothing to pause, so they
+        // leave straight away
+        let can_leave = game.state == GameState::Paused
+            || game.state == GameState::Finished && tutorial.is_some()
+            || game.state == GameState::GameOver && cpu.is_none() && rival.is_none();
+        if can_leave
+            && (rl.is_key_pressed(KeyboardKey::KEY_Q) || rl.is_key_pressed(KeyboardKey::KEY_ESCAPE))
+        {
+            // Give up the game, leaving any multiplayer room, and go back to the menu
+            game.leave_multiplayer().await;
+            in_menu = true;
+            music.resume_stream();
+            continue;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_P)
+            || (rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) && game.state == GameState::Playing)
+        {
+            if game.state == GameState::Playing && !game.can_pause() {
+                pause_denied = Some(Instant::now());
+            }
             game.toggle_pause();
+            if let Some(cpu) = &mut cpu {
+                cpu.game.toggle_pause();
+            }
+            if let Some(rival) = &mut rival {
+                rival.game.toggle_pause();
+            }
             if game.state == GameState::Paused {
                 music.pause_stream();
             } else {
                 music.resume_stream();
             }
         }
-        if rl.is_key_pressed(KeyboardKey::KEY_R) && game.state == GameState::GameOver {
-            game.start_game();
-            music.resume_stream();
+        if debug {
+            let speeds = [
+                (KeyboardKey::KEY_ONE, 0.25),
+                (KeyboardKey::KEY_TWO, 0.5),
+                (KeyboardKey::KEY_THREE, 1.0),
+            ];
+            for (key, scale) in speeds {
+                if rl.is_key_pressed(key) && !game.set_speed(scale) {
+                    toast = Some((tr("no_slow_motion").to_string(), Instant::now()));
+                }
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_F) && !game.toggle_frozen() {
+                toast = Some((tr("no_frame_stepping").to_string(), Instant::now()));
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_PERIOD) {
+                game.step_frame();
+            }
+            // The CPU and player 2 run on the same clock
+            if let Some(cpu) = &mut cpu {
+                cpu.game.speed = game.speed;
+            }
+            if let Some(rival) = &mut rival {
+                rival.game.speed = game.speed;
+            }
+        }
+        let restart_pressed = config.keys.restart.iter().any(|&key| rl.is_key_pressed(key));
+        let restart_down = config.keys.restart.iter().any(|&key| rl.is_key_down(key));
+        // Holding the key restarts mid-game, so a stray tap can't. Not in multiplayer,
+        // where leaving a match goes through the pause screen, nor in the modes where a
+        // tap already resets
+        let can_hold_restart = game.state == GameState::Playing
+            && game.multiplayer.is_none()
+            && !game.is_reconnecting()
+            && missions.is_none()
+            && tutorial.is_none()
+            && game.mode != GameMode::Practice;
+        let held_restart = restart_hold.update_at(restart_down && can_hold_restart, Instant::now());
+        if let Some(run) = &mut missions {
+            if restart_pressed {
+                // Retry the current mission, or the whole list once it's done
+                if run.is_finished() {
+                    *run = MissionRun::new(run.missions.clone());
+                } else {
+                    run.retry();
+                }
+                game.start_mission(run.mission().setup.clone());
+                music.resume_stream();
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_N) && !run.is_finished() {
+                let status = match run.progress.status {
+                    MissionStatus::Failed => MissionStatus::Failed,
+                    _ => MissionStatus::Skipped,
+                };
+                run.advance(status);
+                if run.is_finished() {
+                    game.state = GameState::Finished;
+                } else {
+                    game.start_mission(run.mission().setup.clone());
+                }
+                music.resume_stream();
+            }
+        } else if let Some(tutorial) = &mut tutorial {
+            if restart_pressed {
+                // Restart the current step, or the whole tutorial once it's done
+                if tutorial.is_finished() {
+                    *tutorial = Tutorial::new(TUTORIAL_STEPS, tutorial.lock_input);
+                }
+                game.start_tutorial(tutorial.setup());
+                music.resume_stream();
+            }
+        } else if game.mode == GameMode::Practice {
+            if restart_pressed {
+                game.reset_practice();
+                music.resume_stream();
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_Z) && game.undo() {
+                music.resume_stream();
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_G) {
+                show_placement_hints = !show_placement_hints;
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_F5) {
+                if let Some(saved) = game.save_state() {
+                    let text =
+                        tr_args("state_saved", &[("count", &saved), ("slots", &SAVE_STATE_SLOTS)]);
+                    toast = Some((text, Instant::now()));
+                }
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_F8) {
+                let text = match game.load_state() {
+                    Some(age) => {
+                        music.resume_stream();
+                        let count = game.save_state_count();
+                        tr_args("state_loaded", &[("age", &age), ("count", &count)])
+                    }
+                    None => tr("no_saved_states").to_string(),
+                };
+                toast = Some((text, Instant::now()));
+            }
+        } else if restart_pressed || held_restart {
+            // Restarting from the pause screen takes a second press within the window
+            let confirmed = restart_armed
+                .take()
+                .is_some_and(|armed| armed.elapsed() < RESTART_CONFIRM_WINDOW);
+            let restart = match game.state {
+                GameState::GameOver | GameState::Finished => true,
+                GameState::Paused if confirmed => {
+                    game.forfeit();
+                    true
+                }
+                GameState::Paused => {
+                    restart_armed = Some(Instant::now());
+                    false
+                }
+                GameState::Playing => held_restart,
+            };
+            if restart {
+                match &mut daily_run {
+                    // Replays are unofficial, unless the day has rolled over
+                    Some(run) => *run = DailyRun::start(&mut game, &mut high_scores),
+                    None if config.restart_same_seed => game.start_game_with_seed(game.seed),
+                    None => game.start_game(),
+                }
+                if let Some(cpu) = &mut cpu {
+                    cpu.game.start_game();
+                }
+                if let Some(rival) = &mut rival {
+                    rival.game.start_game();
+                }
+                music.resume_stream();
+            }
         }
 
-        let prev_state = game.state;
+        let ended = matches!(game.state, GameState::GameOver | GameState::Finished);
+        if !ended {
+            show_heatmap = false;
+        } else if rl.is_key_pressed(KeyboardKey::KEY_H) && !game.heatmap.is_empty() {
+            show_heatmap = !show_heatmap;
+        }
+
+        lock_results.extend(game.update());
+
+        let events = game.drain_events();
+        haptics.on_events(&events, Instant::now());
+        let (left, right) = haptics.motors(Instant::now());
+        rumble_gamepad(&rl, left, right);
+        let finesse_fault = events
+            .iter()
+            .any(|event| matches!(event, GameEvent::FinesseFault { .. }));
+        let match_finished = events.contains(&GameEvent::MatchFinished);
+        if let (true, Some(result), Some(own_id)) =
+            (match_finished, &game.match_result, &game.player_id)
+        {
+            high_scores.record_match(result, own_id);
+            if let Err(e) = high_scores.save(HIGH_SCORES_PATH) {
+                eprintln!("Failed to save high scores: {}", e);
+            }
+        }
+        for event in &events {
+            match event {
+                GameEvent::AttackIncoming {
+                    id,
+                    from,
+                    lines,
+                    kind,
+                } => {
+                    let name = game.other_players.get(from).and_then(|info| info.name.as_deref());
+                    let warning = AttackWarning {
+                        id: *id,
+                        name: scoreboard_name(from, name),
+                        color: player_accent(&game, from),
+                        lines: *lines,
+                        kind: *kind,
+                        cancelled_at: None,
+                    };
+                    attack_warnings.insert(0, warning);
+                    sound_effects.play_warning();
+                }
+                GameEvent::AttackCancelled { id, remaining, .. } => {
+                    if let Some(warning) = attack_warnings.iter_mut().find(|w| w.id == *id) {
+                        // A cancelled attack keeps its size, crossed out
+                        match remaining {
+                            0 => warning.cancelled_at = Some(Instant::now()),
+                            _ => warning.lines = *remaining,
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        attack_warnings.retain(|warning| match warning.cancelled_at {
+            Some(at) => at.elapsed() < CANCELLED_ATTACK_DURATION,
+            None => game.incoming_attacks().any(|attack| attack.id == warning.id),
+        });
+        if coach && finesse_fault {
+            finesse_flash = Some(Instant::now());
+            sound_effects.play_buzz();
+        }
+        if let Some(run) = &mut missions {
+            for event in &events {
+                match run.progress.on_event(event) {
+                    MissionStatus::Complete => {
+                        run.advance(MissionStatus::Complete);
+                        if run.is_finished() {
+                            game.state = GameState::Finished;
+                        } else {
+                            game.start_mission(run.mission().setup.clone());
+                        }
+                        break;
+                    }
+                    MissionStatus::Failed => {
+                        game.state = GameState::GameOver;
+                        break;
+                    }
+                    MissionStatus::Active | MissionStatus::Skipped => {}
+                }
+            }
+        }
+        if let Some(tutorial) = &mut tutorial {
+            let now = Instant::now();
+            let retry = events
+                .iter()
+                .any(|event| tutorial.on_event(event, now) == StepProgress::Retry);
+            let advanced = tutorial.update(now);
+            if advanced && tutorial.is_finished() {
+                game.state = GameState::Finished;
+            } else if advanced || retry {
+                game.start_tutorial(tutorial.setup());
+            }
+        }
 
-        // Check if lines were cleared and play sound
-        if game.lines_just_cleared {
+        // Play the line clear sound for hard drops and gravity locks alike
+        if lock_results.iter().any(|result| result.lines_cleared > 0) {
             sound_effects.try_play_line_clear();
-            game.lines_just_cleared = false;
+        }
+        if let Some(chain) = lock_results.iter().map(|result| result.chain).max() {
+            if chain >= 2 {
+                chain_popup = Some((chain, Instant::now()));
+            }
+        }
+        // Attacks only mean something with someone to send them to
+        let against = game.multiplayer.is_some() || cpu.is_some() || rival.is_some();
+        if let Some(attack) = lock_results.iter().map(|result| result.attack).max() {
+            if attack > 0 && against {
+                attack_popup = Some((attack, Instant::now()));
+            }
+        }
+        if lock_results.iter().any(|result| result.perfect_clear) {
+            perfect_clear_flash = Some(Instant::now());
         }
 
-        game.update();
+        if let Some(cpu) = &mut cpu {
+            cpu.ai.update(&mut cpu.game);
+            cpu.game.update();
+            if let Some(won) = exchange_garbage(&mut game, &mut cpu.game, tr("cpu")) {
+                cpu.won = won;
+            }
+        }
+        if let Some(rival) = &mut rival {
+            rival_locks.extend(rival.game.update());
+            if rival_locks.iter().any(|result| result.lines_cleared > 0) {
+                sound_effects.try_play_line_clear();
+            }
+            if let Some(won) = exchange_garbage(&mut game, &mut rival.game, tr("player_two")) {
+                rival.won = won;
+            }
+        }
 
         // Play game over sound if state changed to GameOver
         if prev_state != GameState::GameOver && game.state == GameState::GameOver {
             sound_effects.play_game_over();
             music.pause_stream();
+            // Only solo games played for points count towards the best scores
+            let solo = cpu.is_none() && rival.is_none() && daily_run.is_none();
+            new_high_score = solo
+                && game.plays_for_records()
+                && high_scores.record_score(game.mode, game.score.points);
+            if new_high_score {
+                if let Err(e) = high_scores.save(HIGH_SCORES_PATH) {
+                    eprintln!("Failed to save high scores: {}", e);
+                }
+            }
+        }
+        if game.mode == GameMode::Sprint {
+            let times = game.splits.times();
+            // Fewer splits than before means a new run
+            if times.len() < splits_seen {
+                split_delta = None;
+            }
+            if let Some(&time) = times.last().filter(|_| times.len() > splits_seen) {
+                let best = high_scores.sprint_best.as_ref();
+                let delta = best.and_then(|best| best.delta(times.len() - 1, time));
+                split_delta = delta.map(|delta| (delta, Instant::now()));
+            }
+            splits_seen = times.len();
+            if prev_state != GameState::Finished && game.state == GameState::Finished {
+                sprint_best_before = high_scores.sprint_best.clone();
+                sprint_new_best = !game.is_assisted() && high_scores.finish_sprint(&game.splits);
+                if let Err(e) = high_scores.save(HIGH_SCORES_PATH) {
+                    eprintln!("Failed to save high scores: {}", e);
+                }
+            }
+        }
+        // Record each finished game, writing off the render thread
+        let ended = matches!(game.state, GameState::GameOver | GameState::Finished);
+        if ended && matches!(prev_state, GameState::Playing | GameState::Paused) {
+            if let Some(path) = &export_stats {
+                let record = GameRecord::from_game(&game, UtcDateTime::now());
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = append_record(&path, &record) {
+                        eprintln!("Failed to write game stats to {}: {}", path.display(), e);
+                    }
+                });
+            }
+        }
+        if let Some(run) = &mut daily_run {
+            if prev_state != GameState::Finished && game.state == GameState::Finished {
+                run.finish(&game, &mut high_scores);
+            }
+            run.poll();
+        }
+        // Pauses end once the resume countdown runs out, or a multiplayer pause runs over
+        if prev_state == GameState::Paused && game.state == GameState::Playing {
+            music.resume_stream();
+        }
+
+        // Page through opponent boards, and switch between score and attack order
+        let opponent_pages = opponent_page_count(game.other_boards.len());
+        if rl.is_key_pressed(KeyboardKey::KEY_PAGE_DOWN) {
+            opponent_page += 1;
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_PAGE_UP) {
+            opponent_page = opponent_page.saturating_sub(1);
+        }
+        opponent_page = opponent_page.min(opponent_pages - 1);
+        if rl.is_key_pressed(KeyboardKey::KEY_TAB) {
+            opponent_order = opponent_order.toggled();
+        }
+
+        // Watch other players' boards one at a time, then come back to our own
+        if rl.is_key_pressed(KeyboardKey::KEY_V) {
+            game.spectate_next();
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+            game.stop_spectating();
+        }
+
+        // F9 saves the board for sharing or practice mode, F12 the whole frame once drawn
+        if rl.is_key_pressed(KeyboardKey::KEY_F9) {
+            let board = game.display_board();
+            let saved = capture_path(SCREENSHOTS_DIR, "board", &["txt", "png"], SystemTime::now())
+                .and_then(|path| {
+                    export_board_ascii(board, path.with_extension("txt"))?;
+                    let style = block_style(game.mode, &config);
+                    board_image(&mut rl, &thread, board, style, &board_sprites)
+                        .map_err(std::io::Error::other)?
+                        .export_image(&path.with_extension("png").to_string_lossy());
+                    Ok(path)
+                });
+            toast = Some((saved_message(saved, ".txt and .png"), Instant::now()));
+        }
+        let screenshot_requested = rl.is_key_pressed(KeyboardKey::KEY_F12);
+
+        // F1 turns assist mode on or off for good, taking the game under way off the records
+        if rl.is_key_pressed(KeyboardKey::KEY_F1) {
+            config.assist = !config.assist;
+            let text = match save_assist(CONFIG_PATH, config.assist) {
+                Err(e) => e.to_string(),
+                Ok(()) if config.assist => tr("assist_toggled_on").to_string(),
+                Ok(()) => tr("assist_toggled_off").to_string(),
+            };
+            toast = Some((text, Instant::now()));
+        }
+        if game.assist() != config.assist {
+            game.set_assist(config.assist);
+        }
+
+        // Join or leave the 1v1 matchmaking queue
+        if rl.is_key_pressed(KeyboardKey::KEY_M) && game.multiplayer.is_some() {
+            if game.queued_at.is_some() {
+                game.leave_queue();
+            } else {
+                game.queue_for_match();
+            }
+        }
+
+        if let Some(stats_server) = &stats_server {
+            stats_server.publish(StatsSnapshot::from_game(&game));
+        }
+
+        // Refresh the window title at most once a second, and only when it changed
+        if window_title_updated.elapsed() >= WINDOW_TITLE_INTERVAL {
+            let title = window_title(&game);
+            if title != window_title_text {
+                rl.set_window_title(&thread, &title);
+                window_title_text = title;
+            }
+            window_title_updated = Instant::now();
         }
 
         // Render
-        let mut d = rl.begin_drawing(&thread);
-        d.clear_background(BACKGROUND_COLOR);
+        let block_style = block_style(game.mode, &config);
+        let net_stats = if debug { game.net_stats() } else { None };
+        let mut frame = rl.begin_drawing(&thread);
+        let mut d = frame.begin_texture_mode(&thread, &mut scene);
+        d.clear_background(theme().background);
+        let layout = layout();
+        let board_x = layout.board_x;
 
         // Get screen shake offset
-        let (shake_x, shake_y) = game.screen_shake.get_offset();
-
-        // Apply shake offset to board and all game elements
-        draw_board(
-            &mut d,
-            &game.board,
-            BOARD_OFFSET_X + shake_x,
-            BOARD_OFFSET_Y + shake_y,
-        );
+        let (shake_x, shake_y) = if config.reduce_motion {
+            (0, 0)
+        } else {
+            game.screen_shake.get_offset()
+        };
+        let suggestion = game.suggestion();
+        let collapse = game.collapse().filter(|_| !config.reduce_motion);
 
-        if game.state == GameState::Playing {
-            draw_ghost_block(
+        let spectated = game.spectated_board();
+        if let Some((player_id, remote)) = spectated {
+            draw_spectated_board(&mut d, &game, player_id, remote, block_style, &board_sprites);
+        } else {
+            // Apply shake offset to board and all game elements
+            draw_game_board(
                 &mut d,
-                &game.current_block,
-                &game.board,
-                BOARD_OFFSET_X + shake_x,
+                &game,
+                board_x + shake_x,
                 BOARD_OFFSET_Y + shake_y,
+                block_style,
+                &board_sprites,
+                collapse,
             );
-            draw_block(
+            draw_clearing_rows(
                 &mut d,
-                &game.current_block,
-                BOARD_OFFSET_X + shake_x,
+                &game.clearing_rows(),
+                board_x + shake_x,
                 BOARD_OFFSET_Y + shake_y,
             );
+            draw_garbage_meter(&mut d, &game, board_x, BOARD_OFFSET_Y);
         }
 
-        // Draw scoreboard
-        draw_scoreboard(
-            &mut d,
-            game.score.points,
-            game.score.lines,
-            game.score.level,
-            &game.other_players,
-            game.player_id.as_deref(),
-        );
+        if game.is_controllable() && spectated.is_none() {
+            // Classic mode has no ghost piece, and the ghost marks where rows end up so it
+            // waits for them to get there
+            if game.mode != GameMode::Classic && collapse.is_none() {
+                draw_ghost_block(
+                    &mut d,
+                    &game.ghost_block(),
+                    board_x + shake_x,
+                    BOARD_OFFSET_Y + shake_y,
+                    block_style,
+                );
+            }
+            if let Some(suggestion) = &suggestion {
+                draw_suggestion(&mut d, suggestion, board_x + shake_x, BOARD_OFFSET_Y + shake_y);
+            }
+            if show_placement_hints && game.mode == GameMode::Practice {
+                let hints = placement_hints.hints(&game.board, &game.current_block);
+                draw_placement_hints(&mut d, hints, board_x + shake_x, BOARD_OFFSET_Y + shake_y);
+            }
+            draw_falling_block(
+                &mut d,
+                &game,
+                board_x + shake_x,
+                BOARD_OFFSET_Y + shake_y,
+                block_style,
+            );
+        }
 
-        d.draw_text(
-            "Next:",
-            BOARD_OFFSET_X + (BOARD_WIDTH as i32 * CELL_SIZE) + 30 + shake_x,
-            BOARD_OFFSET_Y + shake_y,
-            20,
-            Color::WHITE,
-        );
-        draw_preview_block(
-            &mut d,
-            game.next_block.kind,
-            BOARD_OFFSET_X + (BOARD_WIDTH as i32 * CELL_SIZE) + 30 + shake_x,
-            BOARD_OFFSET_Y + 30 + shake_y,
-        );
+        // Draw the CPU board in versus mode, otherwise the multiplayer scoreboard
+        if let Some(cpu) = &cpu {
+            draw_ui_text(
+                &mut d,
+                &tr_args(
+                    "cpu_score",
+                    &[("difficulty", &cpu.ai.difficulty.name()), ("score", &cpu.game.score.points)],
+                ),
+                layout.panel_x,
+                layout.scoreboard_y,
+                20,
+                Color::WHITE,
+            );
+            let cpu_piece = (cpu.game.state == GameState::Playing)
+                .then_some(RemotePiece::still(cpu.game.current_block));
+            // As big as fits above the stats block
+            let cpu_y = layout.scoreboard_y + layout.line_height(20);
+            let cpu_cell = (layout.stats_y - 10 - cpu_y) / BOARD_HEIGHT as i32;
+            let cpu_cell = cpu_cell.min(MINI_CELL_SIZE);
+            let (board, x) = (&cpu.game.board, layout.panel_x);
+            draw_mini_board(&mut d, board, x, cpu_y, cpu_cell, cpu_piece, theme().grid);
+            draw_player_stats(&mut d, &game);
+        } else if let Some(rival) = &rival {
+            let score = tr_args("score_value", &[("score", &game.score.points)]);
+            let y = layout.scoreboard_y;
+            draw_ui_text(&mut d, tr("player_one"), layout.panel_x, y, 20, Color::YELLOW);
+            let y = y + layout.line_height(20);
+            draw_ui_text(&mut d, &score, layout.panel_x, y, 20, Color::WHITE);
+            draw_player_stats(&mut d, &game);
+            let reduce_motion = config.reduce_motion;
+            draw_versus_board(&mut d, &rival.game, block_style, &board_sprites, reduce_motion);
+        } else {
+            draw_scoreboard(&mut d, &game);
 
-        d.draw_text(
-            "Hold:",
-            20 + shake_x,
-            BOARD_OFFSET_Y + 100 + shake_y,
+            draw_opponent_boards(&mut d, &game, opponent_order, opponent_page);
+        }
+
+        draw_ui_text(
+            &mut d,
+            tr("next"),
+            layout.panel_x + shake_x,
+            BOARD_OFFSET_Y + shake_y,
             20,
             Color::WHITE,
         );
-        if let Some(held_block) = &game.hold_block {
+        if config.drought_counter && spectated.is_none() {
+            draw_drought_counter(
+                &mut d,
+                game.session_stats.drought(),
+                layout.panel_x + shake_x,
+                BOARD_OFFSET_Y + shake_y,
+            );
+        }
+        // While spectating, the preview shows their next piece instead of ours
+        let next_kind = match spectated {
+            Some((_, remote)) => remote.next,
+            None => Some(game.next_block.kind),
+        };
+        if let Some(next_kind) = next_kind {
             draw_preview_block(
                 &mut d,
-                held_block.kind,
+                next_kind,
+                layout.panel_x + shake_x,
+                layout.next_y + shake_y,
+                block_style,
+            );
+        }
+
+        // Classic mode has no hold
+        if game.mode != GameMode::Classic && spectated.is_none() {
+            draw_ui_text(
+                &mut d,
+                tr("hold"),
+                20 + shake_x,
+                layout.hold_y + shake_y,
+                20,
+                Color::WHITE,
+            );
+            draw_hold_queue(
+                &mut d,
+                &game.hold,
+                game.config.hold_slots,
                 20 + shake_x,
-                BOARD_OFFSET_Y + 130 + shake_y,
+                layout.hold_y + layout.line_height(20) + shake_y,
+                block_style,
             );
         }
+        if game.assist() && spectated.is_none() {
+            let hold_better = suggestion.is_some_and(|suggestion| suggestion.hold_better);
+            draw_assist_label(&mut d, hold_better, 20, layout.hud_y - 40);
+        }
+
+        match game.mode {
+            GameMode::Practice => draw_practice_hud(&mut d, game.undo_count()),
+            GameMode::CheeseRace => {
+                draw_cheese_hud(&mut d, game.play_time, game.cheese_remaining())
+            }
+            GameMode::Mission => {
+                if let Some(run) = &missions {
+                    if !run.is_finished() {
+                        draw_mission_hud(&mut d, run);
+                        if let Some(row) = run.progress.marked_row() {
+                            draw_row_marker(
+                                &mut d,
+                                row,
+                                board_x + shake_x,
+                                BOARD_OFFSET_Y + shake_y,
+                            );
+                        }
+                    }
+                }
+            }
+            GameMode::Sprint => {
+                let title = match &daily_run {
+                    Some(run) => tr_args("daily_title", &[("date", &run.date)]),
+                    None => tr("sprint").to_string(),
+                };
+                let delta = split_delta
+                    .filter(|(_, shown_at)| shown_at.elapsed() < SPLIT_DELTA_DURATION)
+                    .map(|(delta, _)| delta);
+                draw_sprint_hud(&mut d, &title, game.play_time, game.score.lines, delta);
+            }
+            GameMode::Tutorial => {
+                if let Some(tutorial) = &tutorial {
+                    draw_tutorial_hud(&mut d, tutorial, Instant::now());
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(flashed_at) = finesse_flash {
+            if flashed_at.elapsed() < FINESSE_FLASH_DURATION {
+                draw_finesse_flash(
+                    &mut d,
+                    flashed_at.elapsed(),
+                    board_x + shake_x,
+                    BOARD_OFFSET_Y + shake_y,
+                );
+            }
+        }
+
+        if debug {
+            draw_game_speed(&mut d, &game.speed);
+            let (calls, unbatched) = take_board_draw_calls();
+            draw_board_draw_calls(&mut d, calls, unbatched);
+            if game.rejected_boards > 0 {
+                draw_rejected_boards(&mut d, game.rejected_boards);
+            }
+            if let Some(stats) = &net_stats {
+                draw_net_stats(&mut d, stats);
+            }
+            let randomizer = game.randomizer_debug();
+            draw_randomizer_debug(&mut d, game.randomizer_kind(), &randomizer, &game.session_stats);
+        }
+        if let Some(status) = game.connection_status() {
+            draw_connection_status(&mut d, status);
+        }
+
+        if let Some((chain, shown_at)) = chain_popup {
+            if shown_at.elapsed() < CHAIN_POPUP_DURATION {
+                draw_chain_popup(&mut d, chain);
+            }
+        }
+        if let Some(at) = perfect_clear_flash.filter(|_| spectated.is_none()) {
+            let (x, y) = (board_x + shake_x, BOARD_OFFSET_Y + shake_y);
+            draw_perfect_clear_flash(&mut d, x, y, CELL_SIZE, at.elapsed());
+        }
+        if let Some((attack, shown_at)) = attack_popup {
+            if shown_at.elapsed() < CHAIN_POPUP_DURATION {
+                draw_attack_popup(&mut d, attack);
+            }
+        }
+        if let Some(progress) = restart_hold.progress(Instant::now()) {
+            draw_restart_hold(&mut d, progress);
+        }
+        if spectated.is_none() {
+            draw_attack_warnings(&mut d, &attack_warnings, board_x, BOARD_OFFSET_Y);
+        }
 
+        if game.connection_lost {
+            draw_connection_lost(&mut d, game.is_reconnecting());
+        } else if let Some(code) = game.room_error {
+            draw_room_error(&mut d, code);
+        }
+        if let Some(queued_at) = game.queued_at {
+            draw_queue_status(&mut d, game.queue_position, queued_at.elapsed());
+        }
+        if let Some((text, received_at)) = &game.server_message {
+            if received_at.elapsed() < SERVER_MESSAGE_DURATION {
+                draw_server_message(&mut d, text);
+            }
+        }
+        if let Some((text, shown_at)) = &toast {
+            if shown_at.elapsed() < TOAST_DURATION {
+                draw_toast(&mut d, text);
+            }
+        }
+        if let Some(denied_at) = pause_denied {
+            if denied_at.elapsed() < PAUSE_DENIED_DURATION {
+                draw_pause_denied(&mut d);
+            }
+        }
+
+        // Solo and online games played for points end on a full results screen
+        let results_shown = game.state == GameState::GameOver
+            && game.match_result.is_none()
+            && cpu.is_none()
+            && rival.is_none()
+            && !matches!(game.mode, GameMode::Practice | GameMode::Mission);
         match game.state {
+            GameState::GameOver | GameState::Finished if show_heatmap => {
+                let dim = Color::new(0, 0, 0, 128);
+                d.draw_rectangle(0, 0, layout.window_width, WINDOW_HEIGHT, dim);
+                draw_heatmap(&mut d, &game.heatmap, board_x, BOARD_OFFSET_Y);
+            }
+            GameState::Finished => {
+                let dim = Color::new(0, 0, 0, 128);
+                d.draw_rectangle(0, 0, layout.window_width, WINDOW_HEIGHT, dim);
+                if let Some(run) = &missions {
+                    draw_mission_results(&mut d, run);
+                } else if tutorial.is_some() {
+                    draw_tutorial_complete(&mut d);
+                } else if let Some(run) = &daily_run {
+                    draw_daily_results(
+                        &mut d,
+                        &run.date,
+                        game.play_time,
+                        run.official,
+                        run.verdict,
+                        run.leaderboard.as_deref(),
+                    );
+                } else {
+                    draw_results(&mut d, game.play_time, game.pieces_placed);
+                }
+                if game.mode == GameMode::Sprint {
+                    let best = sprint_best_before.as_ref();
+                    draw_split_table(&mut d, &game.splits, best, sprint_new_best);
+                }
+            }
             GameState::Paused | GameState::GameOver => {
                 // Draw semi-transparent black overlay
-                d.draw_rectangle(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT, Color::new(0, 0, 0, 128));
+                let dim = Color::new(0, 0, 0, 128);
+                d.draw_rectangle(0, 0, layout.window_width, WINDOW_HEIGHT, dim);
 
                 if game.state == GameState::Paused {
-                    d.draw_text(
-                        "PAUSED",
-                        WINDOW_WIDTH / 2 - 50,
-                        WINDOW_HEIGHT / 2,
-                        30,
-                        Color::WHITE,
-                    );
-                    d.draw_text(
-                        "Press P to resume",
-                        WINDOW_WIDTH / 2 - 80,
-                        WINDOW_HEIGHT / 2 + 40,
-                        20,
-                        Color::WHITE,
-                    );
+                    let time_left = game.pause_time_left().map(|time_left| {
+                        tr_args(
+                            "pause_hidden",
+                            &[
+                                ("seconds", &(time_left.as_secs() + 1)),
+                                ("pauses", &game.pauses_left().unwrap_or(0)),
+                            ],
+                        )
+                    });
+                    // The board is hidden so a pause can't be used to plan, unless the player
+                    // would rather see it and the game can't set a record
+                    let for_records = game.plays_for_records() && cpu.is_none() && rival.is_none();
+                    let translucent = config.translucent_pause && !for_records;
+                    let countdown = game.resume_countdown();
+                    if countdown.is_none() && (time_left.is_some() || !translucent) {
+                        let hold_slots = match game.mode {
+                            GameMode::Classic => 0,
+                            _ => game.config.hold_slots,
+                        };
+                        hide_playfield(&mut d, board_x, hold_slots);
+                    }
+                    let actions = match game.mode {
+                        GameMode::Practice => tr("pause_keys_practice"),
+                        GameMode::Mission => tr("pause_keys_mission"),
+                        GameMode::Tutorial => tr("pause_keys_tutorial"),
+                        _ => tr("pause_keys"),
+                    };
+                    let confirm_restart = restart_armed
+                        .is_some_and(|armed| armed.elapsed() < RESTART_CONFIRM_WINDOW);
+                    match countdown {
+                        Some(seconds) => draw_resume_countdown(&mut d, seconds),
+                        None => {
+                            let time_left = time_left.as_deref();
+                            let idle = game.is_idle_paused();
+                            draw_pause_overlay(&mut d, actions, time_left, confirm_restart, idle);
+                        }
+                    }
+                } else if let Some(result) = &game.match_result {
+                    let own_id = game.player_id.as_deref();
+                    let rivals = &high_scores.rivals;
+                    let rules = &game.room_rules;
+                    draw_match_results(&mut d, result, own_id, &game.session, rivals, rules);
+                } else if results_shown {
+                    draw_game_results(&mut d, &game, tr("game_over"), new_high_score);
                 } else {
-                    d.draw_text(
-                        "GAME OVER",
-                        WINDOW_WIDTH / 2 - 70,
-                        WINDOW_HEIGHT / 2,
-                        30,
-                        Color::WHITE,
-                    );
-                    d.draw_text(
-                        "Press R to restart",
-                        WINDOW_WIDTH / 2 - 80,
-                        WINDOW_HEIGHT / 2 + 40,
-                        20,
-                        Color::WHITE,
-                    );
+                    let title = match (&cpu, &rival) {
+                        (Some(cpu), _) if cpu.won => tr("cpu_wins"),
+                        (Some(_), _) => tr("you_win"),
+                        (_, Some(rival)) if rival.won => tr("player_two_wins"),
+                        (_, Some(_)) => tr("player_one_wins"),
+                        _ if game.mode == GameMode::Mission => tr("mission_failed"),
+                        _ => tr("game_over"),
+                    };
+                    let hint = match game.mode {
+                        GameMode::Practice => tr("game_over_keys_practice"),
+                        GameMode::Mission => tr("game_over_keys_mission"),
+                        _ => tr("restart_hint"),
+                    };
+                    draw_game_over(&mut d, title, hint);
                 }
             }
             _ => {}
         }
+        let ended = matches!(game.state, GameState::GameOver | GameState::Finished);
+        if ended && !show_heatmap && !results_shown && !game.heatmap.is_empty() {
+            draw_heatmap_hint(&mut d, board_x, BOARD_OFFSET_Y);
+        }
+
+        drop(d);
+        draw_scene(&mut frame, &scene);
+
+        // The scene at its own size, whatever shape the window has been dragged to
+        if screenshot_requested {
+            let saved = capture_path(SCREENSHOTS_DIR, "screenshot", &["png"], SystemTime::now())
+                .and_then(|path| {
+                    let mut image = scene.load_image().map_err(std::io::Error::other)?;
+                    image.flip_vertical();
+                    image.export_image(&path.with_extension("png").to_string_lossy());
+                    Ok(path)
+                });
+            toast = Some((saved_message(saved, ".png"), Instant::now()));
+        }
+    }
+
+    // Closing the window mid-match forfeits and lets the other players know rather than
+    // just dropping out
+    game.leave_multiplayer().await;
+    if let Some(stats_server) = stats_server {
+        stats_server.shutdown().await;
+    }
+    #[cfg(feature = "discord")]
+    if let Some(discord) = discord {
+        discord.shutdown();
     }
 }
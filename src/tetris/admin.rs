@@ -0,0 +1,182 @@
+use std::sync::atomic::Ordering;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use super::multiplayer::MultiplayerServer;
+
+/// A command typed into the server's admin console.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+    List,
+    Kick { player_id: String },
+    Say { text: String },
+}
+
+impl AdminCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command {
+            "list" => Ok(AdminCommand::List),
+            "kick" if rest.is_empty() => Err("usage: kick <player_id>".to_string()),
+            "kick" => Ok(AdminCommand::Kick {
+                player_id: rest.to_string(),
+            }),
+            "say" if rest.is_empty() => Err("usage: say <text>".to_string()),
+            "say" => Ok(AdminCommand::Say {
+                text: rest.to_string(),
+            }),
+            _ => Err(format!("unknown command '{}' (try list, kick or say)", command)),
+        }
+    }
+}
+
+/// Reads admin commands from stdin until it closes, printing the replies to stdout.
+pub async fn run_console(server: &MultiplayerServer) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match AdminCommand::parse(&line) {
+            Ok(command) => println!("{}", run_command(server, &command)),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+pub fn run_command(server: &MultiplayerServer, command: &AdminCommand) -> String {
+    match command {
+        AdminCommand::List => {
+            let stats = &server.stats;
+            let mut out = format!(
+                "joins {}, leaves {}, relayed {}, rejected {}",
+                stats.joins.load(Ordering::Relaxed),
+                stats.leaves.load(Ordering::Relaxed),
+                stats.relayed.load(Ordering::Relaxed),
+                stats.rejected.load(Ordering::Relaxed),
+            );
+            let rooms = server.room_summaries();
+            if rooms.is_empty() {
+                out.push_str("\nno players connected");
+            }
+            for room in rooms {
+                let lock = if room.locked { " (locked)" } else { "" };
+                out.push_str(&format!("\n{}{}: {} players", room.name, lock, room.players.len()));
+                for player in room.players {
+                    out.push_str(&format!(
+                        "\n  {}  {}  {} pts",
+                        player.player_id,
+                        player.name.as_deref().unwrap_or("-"),
+                        player.score
+                    ));
+                }
+            }
+            out
+        }
+        AdminCommand::Kick { player_id } => {
+            if server.kick(player_id) {
+                format!("kicked {}", player_id)
+            } else {
+                format!("no player {}", player_id)
+            }
+        }
+        AdminCommand::Say { text } => {
+            server.say(text);
+            "sent".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::multiplayer::{GameMessage, MultiplayerClient, ServerPaths};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_commands_and_explains_mistakes() {
+        assert_eq!(AdminCommand::parse("  list "), Ok(AdminCommand::List));
+        assert_eq!(
+            AdminCommand::parse("kick  abc-123"),
+            Ok(AdminCommand::Kick {
+                player_id: "abc-123".to_string()
+            })
+        );
+        assert_eq!(
+            AdminCommand::parse("say back in 5 minutes"),
+            Ok(AdminCommand::Say {
+                text: "back in 5 minutes".to_string()
+            })
+        );
+        assert_eq!(AdminCommand::parse("kick"), Err("usage: kick <player_id>".to_string()));
+        assert_eq!(AdminCommand::parse("say   "), Err("usage: say <text>".to_string()));
+        assert!(AdminCommand::parse("ban someone").unwrap_err().contains("unknown command"));
+    }
+
+    #[test]
+    fn commands_on_an_empty_server() {
+        let server = MultiplayerServer::with_paths(ServerPaths::scratch("admin"));
+        let list = run_command(&server, &AdminCommand::List);
+        assert!(list.ends_with("no players connected"), "{}", list);
+        let kick = AdminCommand::Kick {
+            player_id: "nobody".to_string(),
+        };
+        assert_eq!(run_command(&server, &kick), "no player nobody");
+    }
+
+    /// Waits for the first message `wanted` picks out, giving up after a few seconds.
+    async fn wait_for<T>(
+        client: &mut MultiplayerClient,
+        wanted: impl Fn(GameMessage) -> Option<T>,
+    ) -> Option<T> {
+        for _ in 0..300 {
+            while let Some(msg) = client.try_receive() {
+                if let Some(found) = wanted(msg) {
+                    return Some(found);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn kick_closes_the_socket_and_tells_the_room() {
+        let server = Arc::new(MultiplayerServer::with_paths(ServerPaths::scratch("server-18140")));
+        let listener = server.clone();
+        tokio::spawn(async move { listener.start("127.0.0.1:18140").await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let addr = "ws://127.0.0.1:18140";
+        let mut kicked = MultiplayerClient::connect(addr).await.unwrap();
+        let mut watcher = MultiplayerClient::connect(addr).await.unwrap();
+        let own_id = |msg| match msg {
+            GameMessage::Join { player_id, .. } => Some(player_id),
+            _ => None,
+        };
+        let kicked_id = wait_for(&mut kicked, own_id).await.unwrap();
+        wait_for(&mut watcher, own_id).await.unwrap();
+
+        let kick = AdminCommand::Kick {
+            player_id: kicked_id.clone(),
+        };
+        assert_eq!(run_command(&server, &kick), format!("kicked {}", kicked_id));
+        let left = wait_for(&mut watcher, |msg| match msg {
+            GameMessage::PlayerLeft { player_id } => Some(player_id),
+            _ => None,
+        });
+        assert_eq!(left.await, Some(kicked_id));
+
+        let say = AdminCommand::Say {
+            text: "hello".to_string(),
+        };
+        assert_eq!(run_command(&server, &say), "sent");
+        let text = wait_for(&mut watcher, |msg| match msg {
+            GameMessage::ServerMessage { text } => Some(text),
+            _ => None,
+        });
+        assert_eq!(text.await.as_deref(), Some("hello"));
+    }
+}
@@ -0,0 +1,307 @@
+use rand::Rng;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::finesse::occupancy_hash;
+use super::{Action, Block, BlockKind, Board, Game, GameState};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Weights {
+    pub aggregate_height: f32,
+    pub lines_cleared: f32,
+    pub holes: f32,
+    pub bumpiness: f32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            aggregate_height: -0.51,
+            lines_cleared: 0.76,
+            holes: -0.36,
+            bumpiness: -0.18,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn pieces_per_second(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.8,
+            Difficulty::Normal => 1.5,
+            Difficulty::Hard => 3.0,
+        }
+    }
+
+    /// Chance that the bot settles for a random placement instead of the best one.
+    pub fn error_chance(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.25,
+            Difficulty::Normal => 0.08,
+            Difficulty::Hard => 0.0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub use_hold: bool,
+    pub kind: BlockKind,
+    pub rotation: u8,
+    pub x: i32,
+    pub score: f32,
+}
+
+impl Placement {
+    /// Actions that take a freshly spawned piece to this placement and drop it.
+    pub fn actions(&self, spawn: &Block) -> Vec<Action> {
+        let mut actions = Vec::new();
+        if self.use_hold {
+            actions.push(Action::Hold);
+        }
+        for _ in 0..self.rotation {
+            actions.push(Action::RotateCw);
+        }
+        let dx = self.x - spawn.x;
+        let step = if dx < 0 { Action::MoveLeft } else { Action::MoveRight };
+        for _ in 0..dx.abs() {
+            actions.push(step);
+        }
+        actions.push(Action::HardDrop);
+        actions
+    }
+
+    /// Where the piece comes to rest on `board`.
+    pub fn landed(&self, board: &Board) -> Block {
+        let block = Block {
+            rotation: self.rotation,
+            x: self.x,
+            ..Block::new(self.kind)
+        };
+        Block {
+            y: board.drop_row(&block),
+            ..block
+        }
+    }
+}
+
+pub fn evaluate(board: &Board, lines_cleared: u32, weights: &Weights) -> f32 {
+    let aggregate_height: usize = board.column_heights().iter().sum();
+    weights.aggregate_height * aggregate_height as f32
+        + weights.lines_cleared * lines_cleared as f32
+        + weights.holes * board.hole_count() as f32
+        + weights.bumpiness * board.bumpiness() as f32
+}
+
+/// Every placement of `kind` reachable by rotating at spawn, sliding sideways and dropping.
+pub fn enumerate_placements(
+    board: &Board,
+    kind: BlockKind,
+    use_hold: bool,
+    weights: &Weights,
+) -> Vec<Placement> {
+    let mut placements = Vec::new();
+    let spawn = Block::new(kind);
+
+    for rotation in 0..4 {
+        let mut rotated = spawn;
+        rotated.rotation = rotation;
+        if !board.is_valid_position(&rotated) {
+            continue;
+        }
+
+        for direction in [-1, 1] {
+            let mut block = rotated;
+            if direction == 1 {
+                block.x += 1;
+            }
+            while board.is_valid_position(&block) {
+                let dropped = Block {
+                    y: board.drop_row(&block),
+                    ..block
+                };
+
+                let mut simulated = board.clone();
+                if simulated.place_block(&dropped) {
+                    let lines_cleared = simulated.clear_lines().count;
+                    placements.push(Placement {
+                        use_hold,
+                        kind,
+                        rotation,
+                        x: block.x,
+                        score: evaluate(&simulated, lines_cleared, weights),
+                    });
+                }
+                block.x += direction;
+            }
+        }
+    }
+    placements
+}
+
+/// Picks the highest scoring placement for the current piece, optionally considering hold.
+pub fn best_placement(game: &Game, weights: &Weights) -> Option<Placement> {
+    candidate_placements(game, weights)
+        .into_iter()
+        .max_by(|a, b| a.score.total_cmp(&b.score))
+}
+
+fn candidate_placements(game: &Game, weights: &Weights) -> Vec<Placement> {
+    let mut placements =
+        enumerate_placements(&game.board, game.current_block.kind, false, weights);
+    if !game.has_held {
+        let hold_slots = game.config.hold_slots;
+        let hold_kind = game.hold.next_out(hold_slots).unwrap_or(&game.next_block).kind;
+        placements.extend(enumerate_placements(&game.board, hold_kind, true, weights));
+    }
+    placements
+}
+
+/// How much more the best placement after holding has to score before assist mode
+/// suggests holding.
+pub const HOLD_HINT_MARGIN: f32 = 1.0;
+
+/// Assist mode's advice: where the current piece is best landed, and whether holding
+/// first would do noticeably better.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Suggestion {
+    pub landed: Block,
+    pub hold_better: bool,
+}
+
+/// The suggestion for the last board and pieces asked about, searched again only when one
+/// of them changes.
+#[derive(Debug, Default)]
+pub struct SuggestionCache {
+    key: Option<(BlockKind, Option<BlockKind>, u64)>,
+    suggestion: Option<Suggestion>,
+}
+
+impl SuggestionCache {
+    pub fn suggestion(&mut self, game: &Game, weights: &Weights) -> Option<Suggestion> {
+        let key = Some((game.current_block.kind, hold_kind(game), occupancy_hash(&game.board)));
+        if self.key != key {
+            self.suggestion = suggest(game, weights);
+            self.key = key;
+        }
+        self.suggestion
+    }
+}
+
+/// What the evaluator would do with the current piece, and whether it would rather hold.
+pub fn suggest(game: &Game, weights: &Weights) -> Option<Suggestion> {
+    let best = |kind, use_hold| {
+        enumerate_placements(&game.board, kind, use_hold, weights)
+            .into_iter()
+            .max_by(|a: &Placement, b| a.score.total_cmp(&b.score))
+    };
+    let own = best(game.current_block.kind, false)?;
+    let held = hold_kind(game).and_then(|kind| best(kind, true));
+    Some(Suggestion {
+        landed: own.landed(&game.board),
+        hold_better: held.is_some_and(|held| held.score - own.score > HOLD_HINT_MARGIN),
+    })
+}
+
+// The piece a hold would bring in, if the current piece can be held
+fn hold_kind(game: &Game) -> Option<BlockKind> {
+    if !game.can_hold() {
+        return None;
+    }
+    let hold_slots = game.config.hold_slots;
+    Some(game.hold.next_out(hold_slots).unwrap_or(&game.next_block).kind)
+}
+
+pub struct AiPlayer {
+    pub difficulty: Difficulty,
+    pub weights: Weights,
+    plan: VecDeque<Action>,
+    planned_piece: u32,
+    action_interval: Duration,
+    last_action: Instant,
+}
+
+impl AiPlayer {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self {
+            difficulty,
+            weights: Weights::default(),
+            plan: VecDeque::new(),
+            planned_piece: 0,
+            action_interval: Duration::ZERO,
+            last_action: Instant::now(),
+        }
+    }
+
+    fn plan_next_piece(&mut self, game: &Game) {
+        let mut placements = candidate_placements(game, &self.weights);
+        if placements.is_empty() {
+            self.plan.push_back(Action::HardDrop);
+        } else {
+            let mut rng = rand::thread_rng();
+            let placement = if rng.gen_bool(self.difficulty.error_chance()) {
+                placements.swap_remove(rng.gen_range(0..placements.len()))
+            } else {
+                placements
+                    .into_iter()
+                    .max_by(|a, b| a.score.total_cmp(&b.score))
+                    .unwrap()
+            };
+            self.plan.extend(placement.actions(&game.current_block));
+        }
+
+        // Spread the actions for one piece evenly over the time budget for that piece
+        let piece_time = Duration::from_secs_f32(1.0 / self.difficulty.pieces_per_second());
+        self.action_interval = piece_time / self.plan.len() as u32;
+    }
+
+    pub fn update(&mut self, game: &mut Game) {
+        if game.state != GameState::Playing {
+            self.plan.clear();
+            return;
+        }
+
+        if !game.is_controllable() || self.last_action.elapsed() < self.action_interval {
+            return;
+        }
+
+        // Gravity can lock the piece before the plan finishes; start over for the new one
+        if self.planned_piece != game.pieces_placed {
+            self.plan.clear();
+        }
+
+        if self.plan.is_empty() {
+            self.plan_next_piece(game);
+            self.planned_piece = game.pieces_placed;
+        }
+
+        if let Some(action) = self.plan.pop_front() {
+            game.apply(action);
+            self.last_action = Instant::now();
+        }
+    }
+}
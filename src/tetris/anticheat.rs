@@ -0,0 +1,315 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::multiplayer::MAX_PERFECT_CLEAR_ATTACK;
+use super::{
+    with_badges, BOARD_HEIGHT, BOARD_WIDTH, CLASSIC_MAX_START_LEVEL, MAX_KO_BADGE_BONUS,
+    MAX_START_LEVEL,
+};
+
+// Faster than anyone places pieces by hand, so every check allows at least this
+pub const MAX_PPS: f32 = 10.0;
+// Points one piece can score for each level above zero: a classic tetris is 1200 a level,
+// with as much again for a cascade's chain bonuses
+pub const MAX_POINTS_PER_LEVEL: i64 = 2400;
+// Garbage a player can send in a minute, well above the best players' attack rates
+pub const MAX_ATTACK_PER_MINUTE: u32 = 600;
+// Reports are judged over this much of a player's recent play
+pub const CHECK_WINDOW: Duration = Duration::from_secs(20);
+// A clear is checked against the fullest board seen this recently, since the snapshot
+// just before it may come late or not at all
+pub const BOARD_WINDOW: Duration = Duration::from_secs(1);
+// Cells a locking piece adds to the board, which its own clear may use
+const CELLS_PER_PIECE: usize = 4;
+// Players who break the checks this many times are disconnected
+pub const MAX_VIOLATIONS: u32 = 3;
+
+/// Pieces a player could have locked in `elapsed`, counting the one that just locked.
+fn max_pieces(elapsed: Duration) -> f32 {
+    MAX_PPS * elapsed.as_secs_f32() + 1.0
+}
+
+/// The most points a player with `lines` cleared can score in `elapsed`. Their level is
+/// taken at its highest: the top start level plus one for every ten lines.
+pub fn max_score_gain(lines: u32, elapsed: Duration) -> i64 {
+    let level = MAX_START_LEVEL.max(CLASSIC_MAX_START_LEVEL) + lines / 10;
+    let per_piece = MAX_POINTS_PER_LEVEL * (i64::from(level) + 1);
+    (max_pieces(elapsed) as f64 * per_piece as f64).ceil() as i64
+}
+
+/// The most lines a player can clear in `elapsed`. A cascade chain can clear the whole
+/// board with one piece.
+pub fn max_lines_gain(elapsed: Duration) -> u32 {
+    (max_pieces(elapsed) * BOARD_HEIGHT as f32).ceil() as u32
+}
+
+/// The most garbage a player can send in `elapsed`, with room for one perfect clear at the
+/// biggest a room allows, boosted by every KO badge.
+pub fn max_attack(elapsed: Duration) -> u32 {
+    let burst = with_badges(MAX_PERFECT_CLEAR_ATTACK, MAX_KO_BADGE_BONUS);
+    let steady = MAX_ATTACK_PER_MINUTE as f32 * elapsed.as_secs_f32() / 60.0;
+    burst + steady.ceil() as u32
+}
+
+/// Whether clearing `count` lines fits a board with `filled` cells: every cleared cell was
+/// on it, or in the piece that cleared them.
+pub fn clear_fits_board(count: u32, filled: usize) -> bool {
+    count as usize * BOARD_WIDTH <= filled + CELLS_PER_PIECE
+}
+
+/// Whether `time` is a possible time for a sprint of `lines` lines, which takes at least a
+/// piece for every four cells cleared.
+pub fn plausible_sprint_time(lines: u32, time: Duration) -> bool {
+    let pieces = (lines as usize * BOARD_WIDTH).div_ceil(CELLS_PER_PIECE);
+    time.as_secs_f32() * MAX_PPS >= pieces as f32
+}
+
+/// Filled cells in a board snapshot from the network.
+pub fn filled_cells(cells: &[Vec<Option<i32>>]) -> usize {
+    cells.iter().flatten().filter(|cell| cell.is_some()).count()
+}
+
+/// A report that can't be true, with what gave it away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    Score { gained: i64, allowed: i64, over: Duration },
+    Lines { gained: u32, allowed: u32, over: Duration },
+    Attack { sent: u32, allowed: u32, over: Duration },
+    /// A clear of more lines than the board had cells for.
+    Clear { count: u32, filled: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Score {
+                gained,
+                allowed,
+                over,
+            } => write!(f, "scored {} in {:?}, at most {}", gained, over, allowed),
+            Violation::Lines {
+                gained,
+                allowed,
+                over,
+            } => write!(f, "cleared {} lines in {:?}, at most {}", gained, over, allowed),
+            Violation::Attack {
+                sent,
+                allowed,
+                over,
+            } => write!(f, "sent {} lines in {:?}, at most {}", sent, over, allowed),
+            Violation::Clear { count, filled } => {
+                write!(f, "cleared {} lines from a board of {} cells", count, filled)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ScoreReport {
+    at: Instant,
+    score: i32,
+    lines: u32,
+}
+
+/// One player's recent reports, checked as they come in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plausibility {
+    scores: VecDeque<ScoreReport>,
+    attacks: VecDeque<(Instant, u32)>,
+    boards: VecDeque<(Instant, usize)>,
+    violations: u32,
+}
+
+impl Plausibility {
+    /// Times the player has broken the checks.
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+
+    /// Checks a reported score and line count against each of the player's recent ones. A
+    /// score that goes down is a new game, which starts the window over.
+    pub fn score(&mut self, now: Instant, score: i32, lines: u32) -> Option<Violation> {
+        if self.scores.back().is_some_and(|last| score < last.score || lines < last.lines) {
+            self.scores.clear();
+        }
+        forget_before(&mut self.scores, now, |report| report.at);
+        let violation = self.scores.iter().rev().find_map(|earlier| {
+            let over = now.saturating_duration_since(earlier.at);
+            let gained = i64::from(score) - i64::from(earlier.score);
+            let allowed = max_score_gain(lines, over);
+            if gained > allowed {
+                return Some(Violation::Score {
+                    gained,
+                    allowed,
+                    over,
+                });
+            }
+            let gained = lines - earlier.lines;
+            let allowed = max_lines_gain(over);
+            (gained > allowed).then_some(Violation::Lines {
+                gained,
+                allowed,
+                over,
+            })
+        });
+        // Judged once: later reports are measured from this one
+        if violation.is_some() {
+            self.scores.clear();
+        }
+        self.scores.push_back(ScoreReport { at: now, score, lines });
+        self.flag(violation)
+    }
+
+    /// Checks an attack of `lines`, with what the player has sent recently, against how
+    /// much they could have sent since each earlier attack.
+    pub fn attack(&mut self, now: Instant, lines: u32) -> Option<Violation> {
+        self.attacks.push_back((now, lines));
+        forget_before(&mut self.attacks, now, |&(at, _)| at);
+        let mut sent = 0;
+        let violation = self.attacks.iter().rev().find_map(|&(at, lines)| {
+            sent += lines;
+            let over = now.saturating_duration_since(at);
+            let allowed = max_attack(over);
+            (sent > allowed).then_some(Violation::Attack {
+                sent,
+                allowed,
+                over,
+            })
+        });
+        if violation.is_some() {
+            self.attacks.clear();
+        }
+        self.flag(violation)
+    }
+
+    /// Notes a board snapshot with `filled` cells.
+    pub fn board(&mut self, now: Instant, filled: usize) {
+        self.boards.push_back((now, filled));
+        self.forget_boards(now);
+    }
+
+    /// Checks a clear of `count` lines against the boards seen just before it, if any were.
+    pub fn clear(&mut self, now: Instant, count: u32) -> Option<Violation> {
+        self.forget_boards(now);
+        let filled = self.boards.iter().map(|&(_, filled)| filled).max()?;
+        let violation = (!clear_fits_board(count, filled)).then_some(Violation::Clear {
+            count,
+            filled,
+        });
+        self.flag(violation)
+    }
+
+    fn forget_boards(&mut self, now: Instant) {
+        while self
+            .boards
+            .front()
+            .is_some_and(|&(at, _)| now.saturating_duration_since(at) > BOARD_WINDOW)
+        {
+            self.boards.pop_front();
+        }
+    }
+
+    fn flag(&mut self, violation: Option<Violation>) -> Option<Violation> {
+        if violation.is_some() {
+            self.violations += 1;
+        }
+        violation
+    }
+}
+
+// Drops reports from before the window
+fn forget_before<T>(reports: &mut VecDeque<T>, now: Instant, at: impl Fn(&T) -> Instant) {
+    while reports
+        .front()
+        .is_some_and(|report| now.saturating_duration_since(at(report)) > CHECK_WINDOW)
+    {
+        reports.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three pieces a second for two minutes from level 1: a tetris every ten pieces, worth
+    // 800 a level, sending 4 plus a back-to-back, and a combo of singles in between
+    fn fast_marathon(checks: &mut Plausibility) -> Vec<Violation> {
+        let start = Instant::now();
+        let mut violations = Vec::new();
+        let (mut score, mut lines) = (0, 0);
+        for piece in 1..=360u32 {
+            let now = start + Duration::from_millis(u64::from(piece) * 333);
+            let (cleared, sent) = match piece % 10 {
+                0 => (4, 5),
+                5..=8 => (1, piece % 10 - 4),
+                _ => (0, 0),
+            };
+            score += 800 * (1 + lines / 10) as i32 * cleared as i32 / 4;
+            lines += cleared;
+            violations.extend(checks.score(now, score, lines));
+            if sent > 0 {
+                violations.extend(checks.attack(now, sent));
+            }
+        }
+        violations
+    }
+
+    #[test]
+    fn three_pieces_a_second_is_not_flagged() {
+        let mut checks = Plausibility::default();
+        assert_eq!(fast_marathon(&mut checks), Vec::new());
+        assert_eq!(checks.violations(), 0);
+
+        // A tetris off a full board, with the snapshot from just before it
+        let now = Instant::now();
+        checks.board(now, 36 + 40);
+        assert_eq!(checks.clear(now + Duration::from_millis(150), 4), None);
+    }
+
+    #[test]
+    fn impossible_scores_and_attacks_are_flagged() {
+        let start = Instant::now();
+        let mut checks = Plausibility::default();
+        assert_eq!(checks.score(start, 0, 0), None);
+        let second = start + Duration::from_secs(1);
+        assert!(matches!(checks.score(second, 1_000_000, 4), Some(Violation::Score { .. })));
+        // Measured from the flagged report, honest play carries on unflagged
+        assert_eq!(checks.score(second + Duration::from_secs(1), 1_000_800, 8), None);
+        // A new game starts over
+        assert_eq!(checks.score(second + Duration::from_secs(2), 0, 0), None);
+
+        let flagged = (0..100u64)
+            .filter_map(|tick| checks.attack(start + Duration::from_millis(tick * 100), 20))
+            .count();
+        assert!(flagged >= 1);
+        assert!(checks.violations() >= 2);
+    }
+
+    #[test]
+    fn a_long_wait_does_not_hide_a_jump() {
+        let start = Instant::now();
+        let mut checks = Plausibility::default();
+        checks.score(start, 1000, 10);
+        let later = start + CHECK_WINDOW * 2;
+        assert_eq!(checks.score(later, 1000, 10), None);
+        assert!(checks.score(later + Duration::from_millis(10), 900_000, 12).is_some());
+    }
+
+    #[test]
+    fn clears_need_a_board_that_could_hold_them() {
+        let now = Instant::now();
+        let mut checks = Plausibility::default();
+        assert_eq!(checks.clear(now, 4), None, "no boards, nothing to go on");
+        checks.board(now, 6);
+        assert_eq!(checks.clear(now, 4), Some(Violation::Clear { count: 4, filled: 6 }));
+        assert_eq!(checks.clear(now + BOARD_WINDOW * 2, 4), None, "the board has gone stale");
+        assert_eq!(checks.violations(), 1);
+    }
+
+    #[test]
+    fn sprint_times_need_enough_pieces() {
+        assert!(plausible_sprint_time(40, Duration::from_secs(15)));
+        assert!(!plausible_sprint_time(40, Duration::from_secs(2)));
+    }
+}
@@ -1,6 +1,14 @@
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy)]
+use super::BOARD_WIDTH;
+
+// Pieces enter play this far above the top of the board
+const SPAWN_Y: i32 = -2;
+
+/// Serialized as its single letter, e.g. `"T"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "char", try_from = "char")]
 pub enum BlockKind {
     I,
     J,
@@ -12,9 +20,22 @@ pub enum BlockKind {
 }
 
 impl BlockKind {
+    pub const ALL: [BlockKind; 7] = [
+        BlockKind::I,
+        BlockKind::J,
+        BlockKind::L,
+        BlockKind::O,
+        BlockKind::S,
+        BlockKind::T,
+        BlockKind::Z,
+    ];
+
     pub fn random() -> Self {
-        let mut rng = rand::thread_rng();
-        match rng.gen_range(0..1) {
+        Self::random_with(&mut rand::thread_rng())
+    }
+
+    pub fn random_with(rng: &mut impl Rng) -> Self {
+        match rng.gen_range(0..7) {
             0 => BlockKind::I,
             1 => BlockKind::J,
             2 => BlockKind::L,
@@ -25,6 +46,64 @@ impl BlockKind {
         }
     }
 
+    /// Cell offsets in the spawn orientation, flat side down as in the guideline.
+    /// This is the single source of truth for piece shapes.
+    pub const fn base_cells(&self) -> [(i32, i32); 4] {
+        match self {
+            BlockKind::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+            BlockKind::J => [(-1, -1), (-1, 0), (0, 0), (1, 0)],
+            BlockKind::L => [(1, -1), (-1, 0), (0, 0), (1, 0)],
+            BlockKind::O => [(0, -1), (1, -1), (0, 0), (1, 0)],
+            BlockKind::S => [(0, -1), (1, -1), (-1, 0), (0, 0)],
+            BlockKind::T => [(0, -1), (-1, 0), (0, 0), (1, 0)],
+            BlockKind::Z => [(-1, -1), (0, -1), (0, 0), (1, 0)],
+        }
+    }
+
+    /// Cell offsets after `rotation` clockwise quarter turns around the piece origin.
+    pub fn cells(&self, rotation: u8) -> [(i32, i32); 4] {
+        ROTATIONS[*self as usize][(rotation % 4) as usize]
+    }
+
+    pub fn min_x(&self) -> i32 {
+        self.base_cells().iter().map(|&(x, _)| x).min().unwrap()
+    }
+
+    pub fn min_y(&self) -> i32 {
+        self.base_cells().iter().map(|&(_, y)| y).min().unwrap()
+    }
+
+    /// Width in cells of the spawn orientation.
+    pub fn width(&self) -> i32 {
+        self.base_cells().iter().map(|&(x, _)| x).max().unwrap() - self.min_x() + 1
+    }
+
+    /// Height in cells of the spawn orientation.
+    pub fn height(&self) -> i32 {
+        self.base_cells().iter().map(|&(_, y)| y).max().unwrap() - self.min_y() + 1
+    }
+
+    /// Inverse of `color`, for palette indices coming off the network.
+    pub fn from_color(color: u8) -> Option<Self> {
+        Self::ALL.get(color as usize).copied()
+    }
+
+    pub fn letter(&self) -> char {
+        match self {
+            BlockKind::I => 'I',
+            BlockKind::J => 'J',
+            BlockKind::L => 'L',
+            BlockKind::O => 'O',
+            BlockKind::S => 'S',
+            BlockKind::T => 'T',
+            BlockKind::Z => 'Z',
+        }
+    }
+
+    pub fn from_letter(letter: char) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.letter() == letter)
+    }
+
     pub fn color(&self) -> u8 {
         match self {
             BlockKind::I => 0,
@@ -38,7 +117,52 @@ impl BlockKind {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+// Every kind's cells in each rotation, indexed by kind then rotation. Collision checks
+// look pieces up here thousands of times a frame, so the rotations are worked out once.
+const ROTATIONS: [[[(i32, i32); 4]; 4]; 7] = rotation_table();
+
+const fn rotation_table() -> [[[(i32, i32); 4]; 4]; 7] {
+    let mut table = [[[(0, 0); 4]; 4]; 7];
+    let mut kind = 0;
+    while kind < BlockKind::ALL.len() {
+        let base = BlockKind::ALL[kind].base_cells();
+        let mut rotation = 0;
+        while rotation < 4 {
+            let mut cell = 0;
+            while cell < 4 {
+                let (x, y) = base[cell];
+                table[kind][rotation][cell] = match rotation {
+                    0 => (x, y),
+                    1 => (-y, x),
+                    2 => (-x, -y),
+                    _ => (y, -x),
+                };
+                cell += 1;
+            }
+            rotation += 1;
+        }
+        kind += 1;
+    }
+    table
+}
+
+impl From<BlockKind> for char {
+    fn from(kind: BlockKind) -> Self {
+        kind.letter()
+    }
+}
+
+impl TryFrom<char> for BlockKind {
+    type Error = String;
+
+    fn try_from(letter: char) -> Result<Self, Self::Error> {
+        BlockKind::from_letter(letter).ok_or_else(|| format!("unknown piece letter '{}'", letter))
+    }
+}
+
+/// Serialized compactly as `[kind, x, y, rotation]`, e.g. `["T",4,-2,0]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "WireBlock", from = "WireBlock")]
 pub struct Block {
     pub kind: BlockKind,
     pub x: i32,
@@ -46,12 +170,42 @@ pub struct Block {
     pub rotation: u8,
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct WireBlock(BlockKind, i32, i32, u8);
+
+impl From<WireBlock> for Block {
+    fn from(WireBlock(kind, x, y, rotation): WireBlock) -> Self {
+        Self {
+            kind,
+            x,
+            y,
+            rotation: rotation % 4,
+        }
+    }
+}
+
+impl From<Block> for WireBlock {
+    fn from(block: Block) -> Self {
+        WireBlock(block.kind, block.x, block.y, block.rotation)
+    }
+}
+
 impl Block {
+    /// A piece of `kind` entering play on the standard board.
     pub fn new(kind: BlockKind) -> Self {
+        Self::spawn(kind, BOARD_WIDTH)
+    }
+
+    /// A piece of `kind` entering play on a board `board_width` cells wide: flat side down,
+    /// just above the board, with its bounding box centered and rounded left. On a standard
+    /// board that puts I, J, L, S, T and Z in columns 3 to 5 (6 for I) and O in 4 and 5, as
+    /// in the guideline.
+    pub fn spawn(kind: BlockKind, board_width: usize) -> Self {
+        let left = (board_width as i32 - kind.width()) / 2;
         Self {
             kind,
-            x: 4,
-            y: -2,
+            x: left - kind.min_x(),
+            y: SPAWN_Y,
             rotation: 0,
         }
     }
@@ -61,33 +215,137 @@ impl Block {
     }
 
     pub fn blocks(&self) -> [(i32, i32); 4] {
-        let base_positions = match self.kind {
-            BlockKind::I => [(0, 0), (-1, 0), (1, 0), (2, 0)],
-            BlockKind::J => [(0, 0), (-1, 0), (1, 0), (-1, -1)],
-            BlockKind::L => [(0, 0), (-1, 0), (1, 0), (1, -1)],
-            BlockKind::O => [(0, -1), (1, -1), (0, 0), (1, 0)],
-            BlockKind::S => [(0, -1), (-1, -1), (0, 0), (1, 0)],
-            BlockKind::T => [(0, -1), (-1, 0), (1, 0), (0, 0)],
-            BlockKind::Z => [(0, -1), (1, -1), (0, 0), (-1, 0)],
-        };
-
-        let mut rotated = [(0, 0); 4];
-        for (i, &(x, y)) in base_positions.iter().enumerate() {
-            let (rx, ry) = match self.rotation {
-                0 => (x, y),
-                1 => (-y, x),
-                2 => (-x, -y),
-                3 => (y, -x),
-                _ => unreachable!(),
-            };
-            rotated[i] = (rx + self.x, ry + self.y);
+        self.kind
+            .cells(self.rotation)
+            .map(|(x, y)| (x + self.x, y + self.y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The spawn orientation drawn row by row, '#' for filled cells.
+    fn spawn_shape(kind: BlockKind) -> Vec<String> {
+        let cells = kind.base_cells();
+        (0..kind.height())
+            .map(|row| {
+                (0..kind.width())
+                    .map(|col| {
+                        let cell = (kind.min_x() + col, kind.min_y() + row);
+                        if cells.contains(&cell) {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spawn_orientations_match_the_guideline() {
+        assert_eq!(spawn_shape(BlockKind::I), ["####"]);
+        assert_eq!(spawn_shape(BlockKind::J), ["#..", "###"]);
+        assert_eq!(spawn_shape(BlockKind::L), ["..#", "###"]);
+        assert_eq!(spawn_shape(BlockKind::O), ["##", "##"]);
+        assert_eq!(spawn_shape(BlockKind::S), [".##", "##."]);
+        assert_eq!(spawn_shape(BlockKind::T), [".#.", "###"]);
+        assert_eq!(spawn_shape(BlockKind::Z), ["##.", ".##"]);
+    }
+
+    // The columns a piece spawns in on a board `width` wide, and its rows
+    fn spawn_cells(kind: BlockKind, width: usize) -> (Vec<i32>, Vec<i32>) {
+        let cells = Block::spawn(kind, width).blocks();
+        let mut columns: Vec<i32> = cells.iter().map(|&(x, _)| x).collect();
+        let mut rows: Vec<i32> = cells.iter().map(|&(_, y)| y).collect();
+        for values in [&mut columns, &mut rows] {
+            values.sort();
+            values.dedup();
+        }
+        (columns, rows)
+    }
+
+    #[test]
+    fn pieces_spawn_centered_on_any_board_width() {
+        let expected = [
+            (BlockKind::I, [3, 4, 5, 6], [2, 3, 4, 5]),
+            (BlockKind::J, [3, 4, 5, 0], [2, 3, 4, 0]),
+            (BlockKind::L, [3, 4, 5, 0], [2, 3, 4, 0]),
+            (BlockKind::O, [4, 5, 0, 0], [3, 4, 0, 0]),
+            (BlockKind::S, [3, 4, 5, 0], [2, 3, 4, 0]),
+            (BlockKind::T, [3, 4, 5, 0], [2, 3, 4, 0]),
+            (BlockKind::Z, [3, 4, 5, 0], [2, 3, 4, 0]),
+        ];
+        for (kind, ten, eight) in expected {
+            let width = kind.width() as usize;
+            assert_eq!(spawn_cells(kind, 10).0, ten[..width], "{:?}", kind);
+            assert_eq!(spawn_cells(kind, 8).0, eight[..width], "{:?}", kind);
+            // Flat side down, with the bottom row just above the board
+            let rows = if kind == BlockKind::I { vec![-2] } else { vec![-3, -2] };
+            assert_eq!(spawn_cells(kind, 10).1, rows, "{:?}", kind);
+            assert_eq!(Block::spawn(kind, 10), Block::new(kind));
+        }
+    }
+
+    #[test]
+    fn every_rotation_fits_in_a_four_by_four_box() {
+        for kind in BlockKind::ALL {
+            for rotation in 0..4 {
+                let cells = kind.cells(rotation);
+                let xs = cells.map(|(x, _)| x);
+                let ys = cells.map(|(_, y)| y);
+                let span = |values: [i32; 4]| {
+                    values.iter().max().unwrap() - values.iter().min().unwrap() + 1
+                };
+                assert!(span(xs) <= 4 && span(ys) <= 4, "{:?} rotation {}", kind, rotation);
+
+                let mut unique = cells.to_vec();
+                unique.sort();
+                unique.dedup();
+                assert_eq!(unique.len(), 4, "{:?} rotation {}", kind, rotation);
+            }
+            assert_eq!(kind.cells(4), kind.cells(0));
         }
-        rotated
     }
 
-    pub fn reset(&mut self) {
-        self.x = 4;
-        self.y = -2;
-        self.rotation = 0;
+    #[test]
+    fn rotation_table_matches_rotating_the_spawn_cells() {
+        for kind in BlockKind::ALL {
+            let mut cells = kind.base_cells();
+            for rotation in 0..8 {
+                assert_eq!(kind.cells(rotation), cells, "{:?} rotation {}", kind, rotation);
+                // A clockwise quarter turn, with y pointing down
+                cells = cells.map(|(x, y)| (-y, x));
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_round_trip_in_the_compact_form() {
+        for kind in BlockKind::ALL {
+            let letter = serde_json::to_string(&kind).unwrap();
+            assert_eq!(letter, format!("\"{}\"", kind.letter()));
+            assert_eq!(serde_json::from_str::<BlockKind>(&letter).unwrap(), kind);
+
+            for rotation in 0..4 {
+                let block = Block {
+                    kind,
+                    x: 4,
+                    y: -2,
+                    rotation,
+                };
+                let json = serde_json::to_string(&block).unwrap();
+                assert_eq!(serde_json::from_str::<Block>(&json).unwrap(), block);
+            }
+        }
+
+        let t = Block::new(BlockKind::T);
+        assert_eq!(serde_json::to_string(&t).unwrap(), r#"["T",4,-2,0]"#);
+        assert!(serde_json::from_str::<BlockKind>(r#""X""#).is_err());
+        // Rotations are read modulo a full turn
+        let block: Block = serde_json::from_str(r#"["I",0,0,5]"#).unwrap();
+        assert_eq!(block.rotation, 1);
     }
 }
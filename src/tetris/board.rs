@@ -2,84 +2,378 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
-use super::Block;
+use super::{Block, BlockKind};
 
 pub const BOARD_WIDTH: usize = 10;
 pub const BOARD_HEIGHT: usize = 20;
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+// Occupancy mask of a completely filled row, one bit per column
+const FULL_ROW: u16 = (1 << BOARD_WIDTH) - 1;
+
+// Palette index used for garbage on the wire, just past the seven piece colors
+pub const GARBAGE_WIRE_INDEX: i32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellContent {
+    /// A cell of a placed piece. `id` tells apart pieces placed on this board, and is 0 for
+    /// cells that came from the network or a text layout.
+    Piece { kind: BlockKind, id: u32 },
+    Garbage,
+}
+
+impl CellContent {
+    pub fn to_wire(&self) -> i32 {
+        match self {
+            CellContent::Piece { kind, .. } => kind.color() as i32,
+            CellContent::Garbage => GARBAGE_WIRE_INDEX,
+        }
+    }
+
+    /// Unknown indices map to garbage so a bad value can never index past the palette.
+    pub fn from_wire(value: i32) -> Self {
+        u8::try_from(value)
+            .ok()
+            .and_then(BlockKind::from_color)
+            .map_or(CellContent::Garbage, |kind| CellContent::Piece { kind, id: 0 })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "WireCell", into = "WireCell")]
 pub enum Cell {
+    Empty,
+    Filled(CellContent),
+}
+
+// Serialized form of a cell, keeping the original small-integer palette encoding
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum WireCell {
     Empty,
     Filled(i32),
 }
 
+impl From<WireCell> for Cell {
+    fn from(value: WireCell) -> Self {
+        match value {
+            WireCell::Empty => Cell::Empty,
+            WireCell::Filled(value) => Cell::Filled(CellContent::from_wire(value)),
+        }
+    }
+}
+
+impl From<Cell> for WireCell {
+    fn from(value: Cell) -> Self {
+        match value {
+            Cell::Empty => WireCell::Empty,
+            Cell::Filled(content) => WireCell::Filled(content.to_wire()),
+        }
+    }
+}
+
 impl Cell {
     pub fn to_option(&self) -> Option<i32> {
         match self {
             Cell::Empty => None,
-            Cell::Filled(value) => Some(*value),
+            Cell::Filled(content) => Some(content.to_wire()),
         }
     }
 
     pub fn from_option(opt: Option<i32>) -> Self {
         match opt {
             None => Cell::Empty,
-            Some(value) => Cell::Filled(value),
+            Some(value) => Cell::Filled(CellContent::from_wire(value)),
         }
     }
 }
 
+/// How garbage holes are laid out within a single attack.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GarbageConfig {
+    /// Chance per line that the hole moves: 0.0 keeps one column per attack, 1.0 is fully random.
+    pub messiness: f32,
+    /// Whether a moved hole may land back in the same column.
+    pub hole_repeat: bool,
+}
+
+impl Default for GarbageConfig {
+    fn default() -> Self {
+        Self {
+            messiness: 0.0,
+            hole_repeat: false,
+        }
+    }
+}
+
+/// How many rows each row of the board fell when lines were removed, indexed by where it
+/// ended up. The empty rows that come in at the top count as not having moved.
+pub type RowDrops = [usize; BOARD_HEIGHT];
+
+/// Rows completed by a placement, top to bottom, in board coordinates before removal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClearResult {
+    pub rows: Vec<usize>,
+    pub count: u32,
+    /// How many of the cleared rows held garbage.
+    pub garbage: u32,
+    /// How far the rows above fell into the gaps. Cascades empty rows in place, so
+    /// theirs are all 0.
+    pub drops: RowDrops,
+}
+
+/// Why a board layout could not be parsed by `Board::from_ascii`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    TooManyRows { found: usize },
+    /// Lines and columns count from 1, as in the text that was parsed.
+    WrongRowWidth { line: usize, found: usize },
+    UnknownCell { line: usize, col: usize, found: char },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::TooManyRows { found } => {
+                write!(f, "expected at most {} rows, found {}", BOARD_HEIGHT, found)
+            }
+            ParseError::WrongRowWidth { line, found } => {
+                write!(f, "line {}: expected {} cells, found {}", line, BOARD_WIDTH, found)
+            }
+            ParseError::UnknownCell { line, col, found } => {
+                write!(f, "line {}, column {}: unknown cell '{}'", line, col, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Why a board from the network was turned away by `Board::update_from_network`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkBoardError {
+    WrongRowCount { found: usize },
+    /// Rows count from 0 at the top.
+    WrongRowWidth { row: usize, found: usize },
+}
+
+impl fmt::Display for NetworkBoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkBoardError::WrongRowCount { found } => {
+                write!(f, "expected {} rows, found {}", BOARD_HEIGHT, found)
+            }
+            NetworkBoardError::WrongRowWidth { row, found } => {
+                write!(f, "row {}: expected {} cells, found {}", row, BOARD_WIDTH, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NetworkBoardError {}
+
+/// One clear in a cascade, with the board as it rested afterwards.
+#[derive(Clone)]
+pub struct CascadeStep {
+    pub cleared: ClearResult,
+    pub board: Board,
+}
+
+/// The playfield. Colors are kept per cell for rendering, while `rows` mirrors them as one
+/// occupancy bitmask per row so collision and line checks stay cheap for simulation.
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "BoardCells", into = "BoardCells")]
 pub struct Board {
     cells: [[Cell; BOARD_WIDTH]; BOARD_HEIGHT],
+    rows: [u16; BOARD_HEIGHT],
+    next_piece_id: u32,
+}
+
+// Serialized form of the board; the occupancy masks are rebuilt on deserialization
+#[derive(Clone, Serialize, Deserialize)]
+struct BoardCells {
+    cells: [[Cell; BOARD_WIDTH]; BOARD_HEIGHT],
+}
+
+impl From<BoardCells> for Board {
+    fn from(value: BoardCells) -> Self {
+        let mut board = Board::new();
+        for (y, row) in value.cells.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                board.set_cell(y, x, *cell);
+            }
+        }
+        board
+    }
+}
+
+impl From<Board> for BoardCells {
+    fn from(value: Board) -> Self {
+        Self { cells: value.cells }
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Board {
     pub fn new() -> Self {
         Self {
             cells: [[Cell::Empty; BOARD_WIDTH]; BOARD_HEIGHT],
+            rows: [0; BOARD_HEIGHT],
+            next_piece_id: 1,
         }
     }
 
     pub fn get_cells_for_network(&self) -> Vec<Vec<Option<i32>>> {
-        let mut result = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
-        for y in 0..BOARD_HEIGHT {
-            for x in 0..BOARD_WIDTH {
-                result[y][x] = self.cells[y][x].to_option();
+        self.cells
+            .iter()
+            .map(|row| row.iter().map(Cell::to_option).collect())
+            .collect()
+    }
+
+    /// Takes on a board sent by another player. Grids of the wrong size are turned away,
+    /// leaving the board as it was, and colors outside the palette read as garbage.
+    pub fn update_from_network(
+        &mut self,
+        cells: Vec<Vec<Option<i32>>>,
+    ) -> Result<(), NetworkBoardError> {
+        if cells.len() != BOARD_HEIGHT {
+            return Err(NetworkBoardError::WrongRowCount { found: cells.len() });
+        }
+        if let Some((row, found)) = cells
+            .iter()
+            .map(Vec::len)
+            .enumerate()
+            .find(|&(_, width)| width != BOARD_WIDTH)
+        {
+            return Err(NetworkBoardError::WrongRowWidth { row, found });
+        }
+        for (y, row) in cells.into_iter().enumerate() {
+            for (x, cell) in row.into_iter().enumerate() {
+                self.set_cell(y, x, Cell::from_option(cell));
             }
         }
-        result
+        Ok(())
     }
 
-    pub fn update_from_network(&mut self, cells: Vec<Vec<Option<i32>>>) {
-        for y in 0..BOARD_HEIGHT {
-            for x in 0..BOARD_WIDTH {
-                if let Some(cell) = cells.get(y).and_then(|row| row.get(x)) {
-                    self.cells[y][x] = Cell::from_option(*cell);
-                }
+    /// Parses a layout of '.' (empty), piece letters and 'G' for garbage, as written by the
+    /// alternate Display form. '#' is also accepted for a filled cell, but as it doesn't say
+    /// which piece filled it, it reads as garbage. Blank lines and surrounding whitespace are
+    /// ignored, and layouts with fewer than `BOARD_HEIGHT` rows sit on the floor with empty
+    /// rows above.
+    pub fn from_ascii(text: &str) -> Result<Board, ParseError> {
+        // Rows keep their line numbers, so errors point into the original text
+        let lines: Vec<(usize, &str)> = text
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .collect();
+        if lines.len() > BOARD_HEIGHT {
+            return Err(ParseError::TooManyRows { found: lines.len() });
+        }
+
+        let mut board = Board::new();
+        let top = BOARD_HEIGHT - lines.len();
+        for (row, &(index, line)) in lines.iter().enumerate() {
+            let width = line.chars().count();
+            if width != BOARD_WIDTH {
+                return Err(ParseError::WrongRowWidth {
+                    line: index + 1,
+                    found: width,
+                });
+            }
+            for (col, ch) in line.chars().enumerate() {
+                let cell = match ch {
+                    '.' => Cell::Empty,
+                    '#' | 'G' => Cell::Filled(CellContent::Garbage),
+                    _ => match BlockKind::from_letter(ch) {
+                        Some(kind) => Cell::Filled(CellContent::Piece { kind, id: 0 }),
+                        None => {
+                            return Err(ParseError::UnknownCell {
+                                line: index + 1,
+                                col: col + 1,
+                                found: ch,
+                            })
+                        }
+                    },
+                };
+                board.set_cell(top + row, col, cell);
             }
         }
+        Ok(board)
     }
 
-    pub fn add_garbage_lines(&mut self, count: i32) {
-        for _ in 0..count {
-            // Shift all rows up
-            for y in (1..BOARD_HEIGHT).rev() {
-                for x in 0..BOARD_WIDTH {
-                    self.cells[y][x] = self.cells[y - 1][x];
-                }
+    /// Sets the cell at row `y`, column `x`, which must be on the board.
+    pub fn set_cell(&mut self, y: usize, x: usize, cell: Cell) {
+        self.cells[y][x] = cell;
+        match cell {
+            Cell::Empty => self.rows[y] &= !(1 << x),
+            Cell::Filled(_) => self.rows[y] |= 1 << x,
+        }
+    }
+
+    fn is_occupied(&self, y: usize, x: usize) -> bool {
+        self.rows[y] & (1 << x) != 0
+    }
+
+    /// Occupancy mask of a row, bit `x` set when column `x` is filled.
+    pub fn row_mask(&self, row: usize) -> u16 {
+        self.rows[row]
+    }
+
+    /// Hole columns of the bottom `count` rows, top to bottom, so the first line of an
+    /// attack comes first.
+    #[cfg(test)]
+    pub(crate) fn garbage_holes(&self, count: usize) -> Vec<usize> {
+        (BOARD_HEIGHT - count..BOARD_HEIGHT)
+            .map(|y| (!self.row_mask(y) & FULL_ROW).trailing_zeros() as usize)
+            .collect()
+    }
+
+    /// Pushes one attack of `count` garbage lines in from the bottom, holed at `hole`
+    /// unless messiness moves it. Returns true when filled cells were pushed off the top of
+    /// the board, which tops the player out.
+    pub fn add_garbage_lines(
+        &mut self,
+        count: i32,
+        hole: usize,
+        config: &GarbageConfig,
+        rng: &mut impl Rng,
+    ) -> bool {
+        let mut topped_out = false;
+        let mut hole = hole.min(BOARD_WIDTH - 1);
+        for line in 0..count {
+            if line > 0 && rng.gen::<f32>() < config.messiness {
+                hole = if config.hole_repeat {
+                    rng.gen_range(0..BOARD_WIDTH)
+                } else {
+                    // Pick among the other columns so the hole always moves
+                    (hole + rng.gen_range(1..BOARD_WIDTH)) % BOARD_WIDTH
+                };
             }
 
-            // Add garbage line at bottom with one random hole
-            let hole = rand::thread_rng().gen_range(0..BOARD_WIDTH);
+            // Shift all rows up, losing the top row
+            topped_out |= self.rows[0] != 0;
+            for y in 0..BOARD_HEIGHT - 1 {
+                self.cells[y] = self.cells[y + 1];
+                self.rows[y] = self.rows[y + 1];
+            }
+
+            // Add garbage line at bottom with the hole
+            let bottom = BOARD_HEIGHT - 1;
             for x in 0..BOARD_WIDTH {
-                self.cells[0][x] = if x == hole {
+                let cell = if x == hole {
                     Cell::Empty
                 } else {
-                    Cell::Filled(8)
-                }; // 8 represents garbage block
+                    Cell::Filled(CellContent::Garbage)
+                };
+                self.set_cell(bottom, x, cell);
             }
         }
+        topped_out
     }
 
     pub fn get_cell(&self, row: usize, col: usize) -> Option<Cell> {
@@ -111,68 +405,702 @@ impl Board {
             }
 
             // Check collision with existing blocks
-            matches!(self.cells[y][x], Cell::Empty)
+            !self.is_occupied(y, x)
         })
     }
 
+    /// The lowest y `block` can fall to from where it is, keeping its column and rotation.
+    /// A block that doesn't fit where it is stays at its own y.
+    pub fn drop_row(&self, block: &Block) -> i32 {
+        let mut dropped = *block;
+        if !self.is_valid_position(block) {
+            return dropped.y;
+        }
+        loop {
+            dropped.y += 1;
+            if !self.is_valid_position(&dropped) {
+                return dropped.y - 1;
+            }
+        }
+    }
+
     pub fn place_block(&mut self, block: &Block) -> bool {
         if !self.is_valid_position(block) {
             return false;
         }
 
         // Place the block
+        let content = CellContent::Piece {
+            kind: block.kind,
+            id: self.next_piece_id,
+        };
+        self.next_piece_id += 1;
         for (x, y) in block.blocks() {
             if y < 0 {
                 return false;
             }
-            self.cells[y as usize][x as usize] = Cell::Filled(block.kind.color() as i32);
+            self.set_cell(y as usize, x as usize, Cell::Filled(content));
         }
         true
     }
 
-    pub fn clear_lines(&mut self) -> u32 {
-        let mut lines_cleared = 0;
-        let mut y = 0;
-        while y < BOARD_HEIGHT {
-            if self.is_line_complete(y) {
-                self.remove_line(y);
-                lines_cleared += 1;
-            } else {
-                y += 1;
+    pub fn clear_lines(&mut self) -> ClearResult {
+        let rows = self.find_complete_lines();
+        let garbage = rows.iter().filter(|&&y| self.is_garbage_row(y)).count() as u32;
+        let drops = self.remove_lines(&rows);
+        ClearResult {
+            count: rows.len() as u32,
+            rows,
+            garbage,
+            drops,
+        }
+    }
+
+    pub fn find_complete_lines(&self) -> Vec<usize> {
+        (0..BOARD_HEIGHT).filter(|&y| self.is_line_complete(y)).collect()
+    }
+
+    /// Removes the given rows and collapses everything above them down, returning how far
+    /// each row fell.
+    pub fn remove_lines(&mut self, rows: &[usize]) -> RowDrops {
+        let mut drops = [0; BOARD_HEIGHT];
+        let mut target = BOARD_HEIGHT;
+        for y in (0..BOARD_HEIGHT).rev() {
+            if rows.contains(&y) {
+                continue;
             }
+            target -= 1;
+            self.cells[target] = self.cells[y];
+            self.rows[target] = self.rows[y];
+            drops[target] = target - y;
+        }
+        // Clear the rows freed up at the top
+        for y in 0..target {
+            self.cells[y] = [Cell::Empty; BOARD_WIDTH];
+            self.rows[y] = 0;
         }
-        lines_cleared
+        drops
     }
 
-    fn is_line_complete(&self, y: usize) -> bool {
-        (0..BOARD_WIDTH).all(|x| matches!(self.cells[y][x], Cell::Filled(_)))
+    /// Cascade gravity: complete rows are emptied in place, then connected groups of cells
+    /// fall until they rest, repeating while that completes more rows. Returns one step per
+    /// clear, so the first step is the ordinary line clear and any further ones are chains.
+    pub fn apply_cascade_gravity(&mut self) -> Vec<CascadeStep> {
+        let mut steps = Vec::new();
+        loop {
+            let rows = self.find_complete_lines();
+            if rows.is_empty() {
+                return steps;
+            }
+
+            let garbage = rows.iter().filter(|&&y| self.is_garbage_row(y)).count() as u32;
+            for &y in &rows {
+                for x in 0..BOARD_WIDTH {
+                    self.set_cell(y, x, Cell::Empty);
+                }
+            }
+            self.settle_groups();
+
+            steps.push(CascadeStep {
+                cleared: ClearResult {
+                    count: rows.len() as u32,
+                    rows,
+                    garbage,
+                    drops: [0; BOARD_HEIGHT],
+                },
+                board: self.clone(),
+            });
+        }
+    }
+
+    /// Drops every connected group as far as it will go, lowest groups first, until
+    /// nothing moves.
+    fn settle_groups(&mut self) {
+        loop {
+            let mut groups = self.connected_groups();
+            groups.sort_by_key(|group| std::cmp::Reverse(group.iter().map(|&(_, y)| y).max()));
+
+            let mut moved = false;
+            for group in groups {
+                let fits = |board: &Board, distance: usize| {
+                    group.iter().all(|&(x, y)| {
+                        let target = y + distance;
+                        target < BOARD_HEIGHT
+                            && (!board.is_occupied(target, x) || group.contains(&(x, target)))
+                    })
+                };
+                let mut distance = 0;
+                while fits(self, distance + 1) {
+                    distance += 1;
+                }
+                if distance == 0 {
+                    continue;
+                }
+
+                let contents: Vec<Cell> = group.iter().map(|&(x, y)| self.cells[y][x]).collect();
+                for &(x, y) in &group {
+                    self.set_cell(y, x, Cell::Empty);
+                }
+                for (&(x, y), &cell) in group.iter().zip(&contents) {
+                    self.set_cell(y + distance, x, cell);
+                }
+                moved = true;
+            }
+
+            if !moved {
+                break;
+            }
+        }
     }
 
-    fn remove_line(&mut self, y: usize) {
-        // Move all lines above down
-        for row in (1..=y).rev() {
+    /// Orthogonally connected cells with the same content: the remains of one piece, or a
+    /// connected patch of garbage.
+    fn connected_groups(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut seen = [[false; BOARD_WIDTH]; BOARD_HEIGHT];
+        let mut groups = Vec::new();
+        for y in 0..BOARD_HEIGHT {
             for x in 0..BOARD_WIDTH {
-                self.cells[row][x] = self.cells[row - 1][x];
+                let Cell::Filled(content) = self.cells[y][x] else {
+                    continue;
+                };
+                if seen[y][x] {
+                    continue;
+                }
+
+                let mut group = Vec::new();
+                let mut stack = vec![(x, y)];
+                seen[y][x] = true;
+                while let Some((cx, cy)) = stack.pop() {
+                    group.push((cx, cy));
+                    let neighbours = [
+                        (cx.wrapping_sub(1), cy),
+                        (cx + 1, cy),
+                        (cx, cy.wrapping_sub(1)),
+                        (cx, cy + 1),
+                    ];
+                    for (nx, ny) in neighbours {
+                        if nx < BOARD_WIDTH
+                            && ny < BOARD_HEIGHT
+                            && !seen[ny][nx]
+                            && self.cells[ny][nx] == Cell::Filled(content)
+                        {
+                            seen[ny][nx] = true;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    /// Height of each column, measured from the floor up to its highest filled cell.
+    pub fn column_heights(&self) -> [usize; BOARD_WIDTH] {
+        let mut heights = [0; BOARD_WIDTH];
+        for (x, height) in heights.iter_mut().enumerate() {
+            if let Some(y) = (0..BOARD_HEIGHT).find(|&y| self.is_occupied(y, x)) {
+                *height = BOARD_HEIGHT - y;
             }
         }
-        // Clear top line
-        for x in 0..BOARD_WIDTH {
-            self.cells[0][x] = Cell::Empty;
+        heights
+    }
+
+    /// Whether every cell is empty, as after a perfect clear.
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|&row| row == 0)
+    }
+
+    pub fn max_height(&self) -> usize {
+        self.column_heights().into_iter().max().unwrap_or(0)
+    }
+
+    /// Empty cells that have at least one filled cell above them in the same column.
+    pub fn hole_count(&self) -> usize {
+        // Any column covered by a filled cell above carries down into every row below it
+        let mut covered = 0u16;
+        let mut holes = 0;
+        for &row in &self.rows {
+            holes += (covered & !row).count_ones() as usize;
+            covered |= row;
         }
+        holes
+    }
+
+    /// Sum of the height differences between neighbouring columns.
+    pub fn bumpiness(&self) -> u32 {
+        self.column_heights()
+            .windows(2)
+            .map(|pair| pair[0].abs_diff(pair[1]) as u32)
+            .sum()
+    }
+
+    /// Rows that still contain at least one garbage cell.
+    pub fn garbage_rows(&self) -> usize {
+        (0..BOARD_HEIGHT).filter(|&y| self.is_garbage_row(y)).count()
+    }
+
+    fn is_garbage_row(&self, y: usize) -> bool {
+        self.cells[y].contains(&Cell::Filled(CellContent::Garbage))
+    }
+
+    fn is_line_complete(&self, y: usize) -> bool {
+        self.rows[y] == FULL_ROW
     }
 }
 
+/// Draws filled cells as '#', for a quick look at the shape of the stack. Only the
+/// alternate form (`{:#}`) can be read back by `Board::from_ascii`: it writes each cell's
+/// piece letter, or 'G' for garbage, so boards round-trip exactly.
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for row in 0..BOARD_HEIGHT {
             for col in 0..BOARD_WIDTH {
-                match self.cells[row][col] {
-                    Cell::Empty => write!(f, " ")?,
-                    Cell::Filled(_) => write!(f, "#")?,
-                }
+                let ch = match (self.cells[row][col], f.alternate()) {
+                    (Cell::Empty, false) => ' ',
+                    (Cell::Filled(_), false) => '#',
+                    (Cell::Empty, true) => '.',
+                    (Cell::Filled(CellContent::Piece { kind, .. }), true) => kind.letter(),
+                    (Cell::Filled(CellContent::Garbage), true) => 'G',
+                };
+                write!(f, "{}", ch)?;
             }
             writeln!(f)?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::time::Instant;
+
+    fn board(layout: &str) -> Board {
+        Board::from_ascii(layout).unwrap()
+    }
+
+    /// Occupancy of a row as read from the colored cells alone, ignoring the masks.
+    fn cells_mask(board: &Board, y: usize) -> u16 {
+        (0..BOARD_WIDTH)
+            .filter(|&x| board.get_cell(y, x) != Some(Cell::Empty))
+            .fold(0, |mask, x| mask | 1 << x)
+    }
+
+    /// Collision check against the colored cells alone, the way it worked before masks.
+    fn cells_valid(board: &Board, block: &Block) -> bool {
+        block.blocks().iter().all(|&(x, y)| {
+            (0..BOARD_WIDTH as i32).contains(&x)
+                && y < BOARD_HEIGHT as i32
+                && (y < 0 || board.get_cell(y as usize, x as usize) == Some(Cell::Empty))
+        })
+    }
+
+    fn random_block(rng: &mut StdRng) -> Block {
+        Block {
+            kind: BlockKind::random_with(rng),
+            x: rng.gen_range(-1..=BOARD_WIDTH as i32),
+            y: rng.gen_range(-2..BOARD_HEIGHT as i32),
+            rotation: rng.gen_range(0..4),
+        }
+    }
+
+    /// The bottom `count` rows in the alternate Display form.
+    fn bottom_rows(board: &Board, count: usize) -> Vec<String> {
+        let text = format!("{:#}", board);
+        let rows: Vec<String> = text.lines().map(str::to_string).collect();
+        rows[rows.len() - count..].to_vec()
+    }
+
+    #[test]
+    fn ascii_layout_round_trips() {
+        let layout = "\
+            ..T.......
+            .TTT..OO..
+            IIIIGGOOJJ";
+        let parsed = board(layout);
+        assert_eq!(bottom_rows(&parsed, 3), ["..T.......", ".TTT..OO..", "IIIIGGOOJJ"]);
+        assert_eq!(
+            parsed.get_cell(19, 4),
+            Some(Cell::Filled(CellContent::Garbage))
+        );
+
+        let reparsed = board(&format!("{:#}", parsed));
+        assert_eq!(format!("{:#}", reparsed), format!("{:#}", parsed));
+    }
+
+    #[test]
+    fn hash_reads_as_garbage() {
+        let parsed = board("#.........");
+        assert_eq!(parsed.get_cell(19, 0), Some(Cell::Filled(CellContent::Garbage)));
+        assert_eq!(bottom_rows(&parsed, 1), ["G........."]);
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_original_line() {
+        let text = "\n..........\n\n.........X\n";
+        assert_eq!(
+            Board::from_ascii(text).err(),
+            Some(ParseError::UnknownCell {
+                line: 4,
+                col: 10,
+                found: 'X'
+            })
+        );
+        let text = "..........\n\n\n.....\n";
+        assert_eq!(
+            Board::from_ascii(text).err(),
+            Some(ParseError::WrongRowWidth { line: 4, found: 5 })
+        );
+        let text = "..........\n".repeat(BOARD_HEIGHT + 1);
+        assert_eq!(
+            Board::from_ascii(&text).err(),
+            Some(ParseError::TooManyRows {
+                found: BOARD_HEIGHT + 1
+            })
+        );
+    }
+
+    #[test]
+    fn clear_lines_reports_rows_and_garbage() {
+        let mut board = board(
+            "
+            .....T....
+            IIIIIIIIII
+            GGGG.GGGGG
+            GGGGGGGGGG",
+        );
+        let cleared = board.clear_lines();
+        // Everything above both cleared rows fell two, and the row between them one
+        let mut drops = [2; BOARD_HEIGHT];
+        drops[..2].fill(0);
+        drops[19] = 1;
+        assert_eq!(
+            cleared,
+            ClearResult {
+                rows: vec![17, 19],
+                count: 2,
+                garbage: 1,
+                drops,
+            }
+        );
+        assert_eq!(bottom_rows(&board, 3), ["..........", ".....T....", "GGGG.GGGGG"]);
+        assert_eq!(board.row_mask(19), 0b11_1110_1111);
+    }
+
+    #[test]
+    fn stack_metrics_on_hand_built_boards() {
+        let empty = Board::new();
+        assert_eq!(empty.column_heights(), [0; BOARD_WIDTH]);
+        assert_eq!(empty.max_height(), 0);
+        assert_eq!(empty.hole_count(), 0);
+        assert_eq!(empty.bumpiness(), 0);
+
+        // Column 1 has two stacked holes under its top cell; column 5 is solid
+        let stack = board(
+            "
+            .G........
+            .....G....
+            .G...G....
+            .....G....
+            .G...G...G
+            GG...G...G",
+        );
+        assert_eq!(stack.column_heights(), [1, 6, 0, 0, 0, 5, 0, 0, 0, 2]);
+        assert_eq!(stack.max_height(), 6);
+        assert_eq!(stack.hole_count(), 2);
+        assert_eq!(stack.bumpiness(), 5 + 6 + 5 + 5 + 2);
+
+        let full_column = board(&"G.........\n".repeat(BOARD_HEIGHT));
+        assert_eq!(full_column.column_heights()[0], BOARD_HEIGHT);
+        assert_eq!(full_column.max_height(), BOARD_HEIGHT);
+        assert_eq!(full_column.hole_count(), 0);
+        assert_eq!(full_column.bumpiness(), BOARD_HEIGHT as u32);
+    }
+
+    #[test]
+    fn masks_agree_with_cells_after_random_operations() {
+        let mut rng = StdRng::seed_from_u64(1106);
+        let garbage = GarbageConfig::default();
+        for _ in 0..50 {
+            let mut board = Board::new();
+            for _ in 0..200 {
+                match rng.gen_range(0..10) {
+                    0 => {
+                        let hole = rng.gen_range(0..BOARD_WIDTH);
+                        board.add_garbage_lines(rng.gen_range(1..4), hole, &garbage, &mut rng);
+                    }
+                    1 => {
+                        board.clear_lines();
+                    }
+                    2 => {
+                        board.apply_cascade_gravity();
+                    }
+                    3 => {
+                        let json = serde_json::to_string(&board).unwrap();
+                        board = serde_json::from_str(&json).unwrap();
+                    }
+                    _ => {
+                        let block = random_block(&mut rng);
+                        assert_eq!(board.is_valid_position(&block), cells_valid(&board, &block));
+                        board.place_block(&block);
+                    }
+                }
+
+                for y in 0..BOARD_HEIGHT {
+                    assert_eq!(board.row_mask(y), cells_mask(&board, y), "row {}", y);
+                }
+                let complete: Vec<usize> =
+                    (0..BOARD_HEIGHT).filter(|&y| cells_mask(&board, y) == FULL_ROW).collect();
+                assert_eq!(board.find_complete_lines(), complete);
+            }
+        }
+    }
+
+    /// Rough timing of collision checks with and without the masks. Run it with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_collision_checks() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut board = Board::new();
+        board.add_garbage_lines(8, 3, &GarbageConfig::default(), &mut rng);
+        let blocks: Vec<Block> = (0..1000).map(|_| random_block(&mut rng)).collect();
+
+        let time = |check: &dyn Fn(&Block) -> bool| {
+            let start = Instant::now();
+            let mut valid = 0;
+            for _ in 0..1000 {
+                for block in &blocks {
+                    valid += check(std::hint::black_box(block)) as u32;
+                }
+            }
+            (start.elapsed(), valid)
+        };
+        let (masks, masks_count) = time(&|block| board.is_valid_position(block));
+        let (cells, cells_count) = time(&|block| cells_valid(&board, block));
+        assert_eq!(masks_count, cells_count);
+        println!("1M collision checks: {:?} with masks, {:?} with cells", masks, cells);
+    }
+
+    /// Rough timing of collision checks using the rotation table against rotating the
+    /// spawn cells on every check, as pieces used to. Run it with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_rotation_table() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut board = Board::new();
+        board.add_garbage_lines(8, 3, &GarbageConfig::default(), &mut rng);
+        let blocks: Vec<Block> = (0..1000).map(|_| random_block(&mut rng)).collect();
+        let rotated_valid = |block: &Block| {
+            let cells = block.kind.base_cells().map(|(x, y)| match block.rotation % 4 {
+                0 => (x, y),
+                1 => (-y, x),
+                2 => (-x, -y),
+                _ => (y, -x),
+            });
+            cells.iter().all(|&(x, y)| {
+                let (x, y) = (x + block.x, y + block.y);
+                (0..BOARD_WIDTH as i32).contains(&x)
+                    && y < BOARD_HEIGHT as i32
+                    && (y < 0 || !board.is_occupied(y as usize, x as usize))
+            })
+        };
+
+        let time = |check: &dyn Fn(&Block) -> bool| {
+            let start = Instant::now();
+            let mut valid = 0;
+            for _ in 0..1000 {
+                for block in &blocks {
+                    valid += check(std::hint::black_box(block)) as u32;
+                }
+            }
+            (start.elapsed(), valid)
+        };
+        let (table, table_count) = time(&|block| board.is_valid_position(block));
+        let (rotated, rotated_count) = time(&rotated_valid);
+        assert_eq!(table_count, rotated_count);
+        println!("1M collision checks: {:?} with the table, {:?} rotating", table, rotated);
+    }
+
+    #[test]
+    fn remove_lines_collapses_the_rows_above_in_order() {
+        let mut board = board(
+            "
+            ..J.......
+            IIIIIIIII.
+            .O........
+            LLLLLLLLL.
+            .S........",
+        );
+        assert!(board.find_complete_lines().is_empty());
+
+        let drops = board.remove_lines(&[16, 18]);
+        assert_eq!(&drops[16..], [2, 2, 1, 0]);
+        assert_eq!(
+            bottom_rows(&board, 5),
+            ["..........", "..........", "..J.......", ".O........", ".S........"]
+        );
+        assert_eq!(board.row_mask(15), 0);
+        assert_eq!(board.row_mask(17), 0b100);
+    }
+
+    #[test]
+    fn clear_lines_reports_split_rows() {
+        let mut board = board(
+            "
+            IIIIIIIIII
+            .JJJJ.JJJJ
+            IIIIIIIIII
+            .OOOO.OOOO",
+        );
+        let cleared = board.clear_lines();
+        assert_eq!(cleared.rows, [16, 18]);
+        assert_eq!(cleared.count, 2);
+        assert_eq!(bottom_rows(&board, 2), [".JJJJ.JJJJ", ".OOOO.OOOO"]);
+        assert!(board.find_complete_lines().is_empty());
+    }
+
+    #[test]
+    fn garbage_messiness_moves_holes_with_a_fixed_seed() {
+        let holes = |messiness: f32, hole_repeat: bool| {
+            let mut rng = StdRng::seed_from_u64(1109);
+            let mut board = Board::new();
+            let config = GarbageConfig {
+                messiness,
+                hole_repeat,
+            };
+            board.add_garbage_lines(8, 3, &config, &mut rng);
+            board.garbage_holes(8)
+        };
+        assert_eq!(holes(0.0, false), [3; 8]);
+        assert_eq!(holes(0.5, false), [3, 5, 1, 9, 9, 9, 9, 9]);
+        // Fully messy garbage moves the hole every line, unless it may land where it was
+        assert_eq!(holes(1.0, false), [3, 5, 1, 9, 6, 7, 8, 2]);
+        assert_eq!(holes(1.0, true), [3, 1, 8, 1, 2, 4, 1, 5]);
+    }
+
+    #[test]
+    fn cells_keep_the_small_integer_wire_format() {
+        for kind in BlockKind::ALL {
+            let content = CellContent::Piece { kind, id: 0 };
+            assert_eq!(content.to_wire(), kind.color() as i32);
+            assert_eq!(CellContent::from_wire(content.to_wire()), content);
+        }
+        assert_eq!(CellContent::Garbage.to_wire(), GARBAGE_WIRE_INDEX);
+        // Out of range values can't index past the palette
+        assert_eq!(CellContent::from_wire(99), CellContent::Garbage);
+        assert_eq!(CellContent::from_wire(-1), CellContent::Garbage);
+
+        let t = Cell::Filled(CellContent::Piece {
+            kind: BlockKind::T,
+            id: 7,
+        });
+        assert_eq!(serde_json::to_string(&t).unwrap(), r#"{"Filled":5}"#);
+        assert_eq!(serde_json::to_string(&Cell::Empty).unwrap(), r#""Empty""#);
+        let old = r#"["Empty",{"Filled":3},{"Filled":8}]"#;
+        let old: Vec<Cell> = serde_json::from_str(old).unwrap();
+        assert_eq!(
+            old,
+            [
+                Cell::Empty,
+                Cell::Filled(CellContent::Piece {
+                    kind: BlockKind::O,
+                    id: 0
+                }),
+                Cell::Filled(CellContent::Garbage),
+            ]
+        );
+    }
+
+    #[test]
+    fn board_round_trips_through_serde_and_the_network_form() {
+        let original = board(
+            "
+            ..T.......
+            .TTT..OO..
+            IIIIGGOOJJ",
+        );
+        let json = serde_json::to_string(&original).unwrap();
+        let decoded: Board = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{:#}", decoded), format!("{:#}", original));
+        assert_eq!(decoded.row_mask(19), FULL_ROW);
+
+        let mut received = Board::new();
+        received.update_from_network(original.get_cells_for_network()).unwrap();
+        assert_eq!(format!("{:#}", received), format!("{:#}", original));
+    }
+
+    #[test]
+    fn network_boards_of_the_wrong_size_are_turned_away() {
+        let original = board("IIIIGGOOJJ");
+        let mut received = original.clone();
+        let row = vec![Some(1); BOARD_WIDTH];
+
+        let short = vec![row.clone(); BOARD_HEIGHT - 1];
+        let found = BOARD_HEIGHT - 1;
+        assert_eq!(
+            received.update_from_network(short),
+            Err(NetworkBoardError::WrongRowCount { found })
+        );
+        let tall = vec![row.clone(); 10_000];
+        assert!(received.update_from_network(tall).is_err());
+        let mut wide = vec![row.clone(); BOARD_HEIGHT];
+        wide[3].push(Some(1));
+        assert_eq!(
+            received.update_from_network(wide),
+            Err(NetworkBoardError::WrongRowWidth { row: 3, found: BOARD_WIDTH + 1 })
+        );
+        // Nothing of a bad update gets through
+        assert_eq!(format!("{:#}", received), format!("{:#}", original));
+
+        // Colors off the palette, negative or huge, come through as garbage
+        let mut cells = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+        cells[19][0] = Some(-1);
+        cells[19][1] = Some(i32::MAX);
+        cells[19][2] = Some(GARBAGE_WIRE_INDEX + 1);
+        received.update_from_network(cells).unwrap();
+        assert_eq!(format!("{:#}", received).lines().last(), Some("GGG......."));
+    }
+
+    #[test]
+    fn drop_row_finds_the_landing_spot() {
+        let t = |x, y, rotation| Block {
+            kind: BlockKind::T,
+            x,
+            y,
+            rotation,
+        };
+        let uneven = board(
+            "
+            .....G.G..
+            ....G..G..
+            G...GG.GG.",
+        );
+        // Flat side down: stopped by the tallest column under it
+        assert_eq!(uneven.drop_row(&t(1, -2, 0)), 18);
+        assert_eq!(uneven.drop_row(&t(4, -2, 0)), 16);
+        // Pointing down into the gap between two columns
+        assert_eq!(uneven.drop_row(&t(6, -2, 2)), 16);
+        assert_eq!(uneven.drop_row(&t(2, -2, 2)), 18);
+
+        // Resting on a ledge, with empty cells underneath it elsewhere
+        let overhang = board(
+            "
+            ..GGGG....
+            ..G.......
+            ..G.......",
+        );
+        assert_eq!(overhang.drop_row(&t(4, -2, 0)), 16);
+        assert_eq!(overhang.drop_row(&t(7, -2, 0)), 19);
+
+        // Partly above the board, falling all the way or going nowhere
+        let tall = board(&"G.........\n".repeat(BOARD_HEIGHT - 1));
+        assert_eq!(tall.drop_row(&t(2, -1, 0)), BOARD_HEIGHT as i32 - 1);
+        assert_eq!(tall.drop_row(&t(0, -1, 1)), -1);
+        assert_eq!(tall.drop_row(&t(0, 5, 0)), 5, "a block that doesn't fit stays put");
+    }
+}
@@ -0,0 +1,287 @@
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use super::menu::visible_items;
+use super::multiplayer::RoomInfo;
+use super::text_input::TextInput;
+
+// How often the room list refreshes itself while the browser is open
+pub const BROWSER_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+// Longest room name and password that can be typed in
+pub const MAX_ROOM_NAME: usize = 24;
+pub const MAX_ROOM_PASSWORD: usize = 64;
+
+/// How the last request for the room list went.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoomListing {
+    /// Nothing has come back yet.
+    Loading,
+    Loaded,
+    Failed(String),
+}
+
+/// A line of the browser: one of the rooms, or an action after them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrowserRow {
+    Room(usize),
+    CreateRoom,
+    Back,
+}
+
+/// What the text prompt is asking for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Prompt {
+    RoomName,
+    Password { room: String },
+}
+
+/// What picking a row or finishing a prompt asks for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BrowserChoice {
+    Join {
+        room: String,
+        password: Option<String>,
+    },
+    Create {
+        name: String,
+    },
+    Back,
+}
+
+/// The server browser, opened from the main menu: the rooms on the server with a cursor
+/// over them, then creating a room and going back.
+#[derive(Debug)]
+pub struct ServerBrowser {
+    rooms: Vec<RoomInfo>,
+    pub listing: RoomListing,
+    selected: usize,
+    // When the list was last asked for; None asks again straight away
+    requested_at: Option<Instant>,
+    /// While set, typing goes to the prompt instead of moving the cursor.
+    pub prompt: Option<(Prompt, TextInput)>,
+}
+
+impl Default for ServerBrowser {
+    fn default() -> Self {
+        Self {
+            rooms: Vec::new(),
+            listing: RoomListing::Loading,
+            selected: 0,
+            requested_at: None,
+            prompt: None,
+        }
+    }
+}
+
+impl ServerBrowser {
+    pub fn rooms(&self) -> &[RoomInfo] {
+        &self.rooms
+    }
+
+    /// The rooms, then creating one, then going back.
+    pub fn rows(&self) -> Vec<BrowserRow> {
+        let rooms = (0..self.rooms.len()).map(BrowserRow::Room);
+        rooms.chain([BrowserRow::CreateRoom, BrowserRow::Back]).collect()
+    }
+
+    pub fn selected(&self) -> BrowserRow {
+        self.rows()[self.selected]
+    }
+
+    /// Moves the cursor by `step` rows, wrapping around at either end.
+    pub fn move_cursor(&mut self, step: isize) {
+        let len = self.rows().len() as isize;
+        self.selected = (self.selected as isize + step).rem_euclid(len) as usize;
+    }
+
+    /// Puts the cursor on the row at `index`, such as the one under the mouse.
+    pub fn select(&mut self, index: usize) {
+        if index < self.rows().len() {
+            self.selected = index;
+        }
+    }
+
+    /// Indices of the rows to show when only `fit` fit on screen.
+    pub fn visible_rows(&self, fit: usize) -> Range<usize> {
+        visible_items(self.rows().len(), self.selected, fit)
+    }
+
+    /// Whether it's time to ask for the list again, at `now`.
+    pub fn wants_refresh(&self, now: Instant) -> bool {
+        self.requested_at
+            .is_none_or(|at| now.saturating_duration_since(at) >= BROWSER_REFRESH_INTERVAL)
+    }
+
+    /// Asks for the list again as soon as possible.
+    pub fn refresh(&mut self) {
+        self.requested_at = None;
+    }
+
+    /// Notes that the list was asked for at `now`.
+    pub fn requested(&mut self, now: Instant) {
+        self.requested_at = Some(now);
+    }
+
+    /// Takes in an answer to a request for the list. The first list starts the cursor at
+    /// the top, and later ones keep it on the room it was on, if that's still open.
+    pub fn received(&mut self, rooms: Result<Vec<RoomInfo>, String>) {
+        let first = self.listing == RoomListing::Loading;
+        let current = match self.selected() {
+            BrowserRow::Room(index) => Some(self.rooms[index].name.clone()),
+            _ => None,
+        };
+        let action = self.selected.saturating_sub(self.rooms.len());
+        match rooms {
+            Ok(rooms) => {
+                self.rooms = rooms;
+                self.listing = RoomListing::Loaded;
+            }
+            Err(e) => {
+                self.rooms.clear();
+                self.listing = RoomListing::Failed(e);
+            }
+        }
+        let room = current.and_then(|name| self.rooms.iter().position(|room| room.name == name));
+        self.selected = room.unwrap_or(self.rooms.len() + action).min(self.rows().len() - 1);
+        if first {
+            self.selected = 0;
+        }
+    }
+
+    /// Acts on the selected row. Locked rooms and new rooms prompt for a password or a
+    /// name first, and full rooms can't be joined.
+    pub fn confirm(&mut self) -> Option<BrowserChoice> {
+        match self.selected() {
+            BrowserRow::Room(index) => {
+                let room = &self.rooms[index];
+                if room.limit.is_some_and(|limit| room.players >= limit) {
+                    None
+                } else if room.locked {
+                    let prompt = Prompt::Password {
+                        room: room.name.clone(),
+                    };
+                    self.prompt = Some((prompt, TextInput::new(MAX_ROOM_PASSWORD)));
+                    None
+                } else {
+                    Some(BrowserChoice::Join {
+                        room: room.name.clone(),
+                        password: None,
+                    })
+                }
+            }
+            BrowserRow::CreateRoom => {
+                self.prompt = Some((Prompt::RoomName, TextInput::new(MAX_ROOM_NAME)));
+                None
+            }
+            BrowserRow::Back => Some(BrowserChoice::Back),
+        }
+    }
+
+    /// Finishes the prompt with what was typed. A blank room name keeps it open.
+    pub fn submit_prompt(&mut self) -> Option<BrowserChoice> {
+        let (prompt, input) = self.prompt.take()?;
+        let text = input.text().trim().to_string();
+        match prompt {
+            Prompt::RoomName if text.is_empty() => {
+                self.prompt = Some((prompt, input));
+                None
+            }
+            Prompt::RoomName => Some(BrowserChoice::Create { name: text }),
+            Prompt::Password { room } => Some(BrowserChoice::Join {
+                room,
+                password: Some(input.text().to_string()),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::multiplayer::{RoomRules, MAX_ROOM_PLAYERS};
+
+    fn room(name: &str, players: usize, locked: bool) -> RoomInfo {
+        RoomInfo {
+            name: name.to_string(),
+            players,
+            limit: Some(MAX_ROOM_PLAYERS),
+            locked,
+            in_match: false,
+            rules: RoomRules::default(),
+        }
+    }
+
+    #[test]
+    fn cursor_follows_its_room_across_refreshes() {
+        let mut browser = ServerBrowser::default();
+        assert_eq!(browser.rows(), [BrowserRow::CreateRoom, BrowserRow::Back]);
+        browser.received(Ok(vec![room("den", 1, false), room("loft", 2, false)]));
+        browser.move_cursor(1);
+        assert_eq!(browser.selected(), BrowserRow::Room(1));
+
+        // A room opened ahead of it moves it down a row
+        let rooms = vec![room("attic", 1, false), room("den", 1, false), room("loft", 3, false)];
+        browser.received(Ok(rooms));
+        assert_eq!(browser.selected(), BrowserRow::Room(2));
+        // Once it closes the cursor lands on the first action
+        browser.received(Ok(vec![room("attic", 1, false)]));
+        assert_eq!(browser.selected(), BrowserRow::CreateRoom);
+        browser.move_cursor(1);
+
+        let error = "no answer from the server".to_string();
+        browser.received(Err(error.clone()));
+        assert_eq!(browser.listing, RoomListing::Failed(error));
+        assert_eq!(browser.selected(), BrowserRow::Back);
+    }
+
+    #[test]
+    fn rooms_are_joined_created_or_refused() {
+        let mut browser = ServerBrowser::default();
+        browser.received(Ok(vec![
+            room("den", 1, false),
+            room("vault", 1, true),
+            room("hall", MAX_ROOM_PLAYERS, false),
+        ]));
+        let den = BrowserChoice::Join {
+            room: "den".to_string(),
+            password: None,
+        };
+        assert_eq!(browser.confirm(), Some(den));
+
+        browser.move_cursor(1);
+        assert_eq!(browser.confirm(), None);
+        let (_, input) = browser.prompt.as_mut().unwrap();
+        "hunter2".chars().for_each(|ch| input.type_char(ch));
+        let vault = BrowserChoice::Join {
+            room: "vault".to_string(),
+            password: Some("hunter2".to_string()),
+        };
+        assert_eq!(browser.submit_prompt(), Some(vault));
+
+        browser.move_cursor(1);
+        assert_eq!(browser.confirm(), None, "the hall is full");
+        assert!(browser.prompt.is_none());
+
+        browser.move_cursor(1);
+        assert_eq!(browser.confirm(), None);
+        assert_eq!(browser.submit_prompt(), None, "a room needs a name");
+        let (_, input) = browser.prompt.as_mut().unwrap();
+        " nook ".chars().for_each(|ch| input.type_char(ch));
+        let nook = BrowserChoice::Create {
+            name: "nook".to_string(),
+        };
+        assert_eq!(browser.submit_prompt(), Some(nook));
+    }
+
+    #[test]
+    fn the_list_refreshes_every_few_seconds() {
+        let mut browser = ServerBrowser::default();
+        let now = Instant::now();
+        assert!(browser.wants_refresh(now));
+        browser.requested(now);
+        assert!(!browser.wants_refresh(now + BROWSER_REFRESH_INTERVAL / 2));
+        assert!(browser.wants_refresh(now + BROWSER_REFRESH_INTERVAL));
+        browser.refresh();
+        assert!(browser.wants_refresh(now));
+    }
+}
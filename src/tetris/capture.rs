@@ -0,0 +1,101 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::date::UtcDateTime;
+use super::Board;
+
+pub const SCREENSHOTS_DIR: &str = "screenshots";
+
+/// UTC time as `2024-06-01_12-30-05`, safe to use in a file name.
+pub fn timestamp(time: SystemTime) -> String {
+    let time = UtcDateTime::from_system_time(time);
+    format!(
+        "{}_{:02}-{:02}-{:02}",
+        time.date(),
+        time.hour,
+        time.minute,
+        time.second
+    )
+}
+
+/// Picks a timestamped path in `dir`, without an extension, that is free for each of
+/// `extensions`. The directory is created if needed, and `-2`, `-3`, ... is added to the
+/// name when several captures land in the same second.
+pub fn capture_path(
+    dir: impl AsRef<Path>,
+    prefix: &str,
+    extensions: &[&str],
+    time: SystemTime,
+) -> io::Result<PathBuf> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let stem = format!("{}-{}", prefix, timestamp(time));
+    let mut path = dir.join(&stem);
+    let mut copy = 1;
+    while extensions.iter().any(|extension| path.with_extension(extension).exists()) {
+        copy += 1;
+        path = dir.join(format!("{}-{}", stem, copy));
+    }
+    Ok(path)
+}
+
+/// Writes a board in the text layout practice mode loads, see `Board::from_ascii`.
+pub fn export_board_ascii(board: &Board, path: impl AsRef<Path>) -> io::Result<()> {
+    fs::write(path, format!("{:#}", board))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tetris-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn timestamps_are_file_name_safe() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_717_245_005);
+        assert_eq!(timestamp(time), "2024-06-01_12-30-05");
+    }
+
+    #[test]
+    fn capture_path_creates_the_dir_and_skips_taken_names() {
+        let dir = scratch_dir("capture").join("nested");
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_717_245_005);
+        let first = capture_path(&dir, "shot", &["png", "txt"], time).unwrap();
+        assert!(dir.is_dir());
+        assert_eq!(first, dir.join("shot-2024-06-01_12-30-05"));
+
+        // Any one extension being taken moves every extension on to the next name
+        fs::write(first.with_extension("txt"), "").unwrap();
+        let second = capture_path(&dir, "shot", &["png", "txt"], time).unwrap();
+        assert_eq!(second, dir.join("shot-2024-06-01_12-30-05-2"));
+        fs::write(second.with_extension("png"), "").unwrap();
+        let third = capture_path(&dir, "shot", &["png", "txt"], time).unwrap();
+        assert_eq!(third, dir.join("shot-2024-06-01_12-30-05-3"));
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn exported_boards_load_back() {
+        let board = Board::from_ascii(
+            "
+            ..T.......
+            .TTT..OO..
+            GGGG.GGGGG",
+        )
+        .unwrap();
+        let dir = scratch_dir("export");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("board.txt");
+        export_board_ascii(&board, &path).unwrap();
+        let loaded = Board::from_ascii(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.get_cells_for_network(), board.get_cells_for_network());
+    }
+}
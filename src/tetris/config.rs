@@ -0,0 +1,803 @@
+use raylib::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::{
+    Action, BlockKind, Input, RandomizerKind, Theme, KEY_REPEAT_DELAY, KEY_REPEAT_RATE,
+    MAX_UI_SCALE, MIN_UI_SCALE,
+};
+
+pub const CONFIG_PATH: &str = "config.toml";
+// How often the config file is checked for changes
+pub const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Anything slower than this isn't a repeat any more
+const MAX_REPEAT_TIMING: Duration = Duration::from_secs(1);
+// A single-player game pauses itself after this long without input, unless set otherwise
+pub const DEFAULT_IDLE_PAUSE: Duration = Duration::from_secs(30);
+
+/// Key names as written in the config file.
+pub const KEY_NAMES: &[(&str, KeyboardKey)] = &[
+    ("A", KeyboardKey::KEY_A),
+    ("B", KeyboardKey::KEY_B),
+    ("C", KeyboardKey::KEY_C),
+    ("D", KeyboardKey::KEY_D),
+    ("E", KeyboardKey::KEY_E),
+    ("F", KeyboardKey::KEY_F),
+    ("G", KeyboardKey::KEY_G),
+    ("H", KeyboardKey::KEY_H),
+    ("I", KeyboardKey::KEY_I),
+    ("J", KeyboardKey::KEY_J),
+    ("K", KeyboardKey::KEY_K),
+    ("L", KeyboardKey::KEY_L),
+    ("M", KeyboardKey::KEY_M),
+    ("N", KeyboardKey::KEY_N),
+    ("O", KeyboardKey::KEY_O),
+    ("P", KeyboardKey::KEY_P),
+    ("Q", KeyboardKey::KEY_Q),
+    ("R", KeyboardKey::KEY_R),
+    ("S", KeyboardKey::KEY_S),
+    ("T", KeyboardKey::KEY_T),
+    ("U", KeyboardKey::KEY_U),
+    ("V", KeyboardKey::KEY_V),
+    ("W", KeyboardKey::KEY_W),
+    ("X", KeyboardKey::KEY_X),
+    ("Y", KeyboardKey::KEY_Y),
+    ("Z", KeyboardKey::KEY_Z),
+    ("0", KeyboardKey::KEY_ZERO),
+    ("1", KeyboardKey::KEY_ONE),
+    ("2", KeyboardKey::KEY_TWO),
+    ("3", KeyboardKey::KEY_THREE),
+    ("4", KeyboardKey::KEY_FOUR),
+    ("5", KeyboardKey::KEY_FIVE),
+    ("6", KeyboardKey::KEY_SIX),
+    ("7", KeyboardKey::KEY_SEVEN),
+    ("8", KeyboardKey::KEY_EIGHT),
+    ("9", KeyboardKey::KEY_NINE),
+    ("Left", KeyboardKey::KEY_LEFT),
+    ("Right", KeyboardKey::KEY_RIGHT),
+    ("Up", KeyboardKey::KEY_UP),
+    ("Down", KeyboardKey::KEY_DOWN),
+    ("Space", KeyboardKey::KEY_SPACE),
+    ("Enter", KeyboardKey::KEY_ENTER),
+    ("Tab", KeyboardKey::KEY_TAB),
+    ("LeftShift", KeyboardKey::KEY_LEFT_SHIFT),
+    ("RightShift", KeyboardKey::KEY_RIGHT_SHIFT),
+    ("LeftControl", KeyboardKey::KEY_LEFT_CONTROL),
+    ("RightControl", KeyboardKey::KEY_RIGHT_CONTROL),
+    ("LeftAlt", KeyboardKey::KEY_LEFT_ALT),
+    ("RightAlt", KeyboardKey::KEY_RIGHT_ALT),
+    ("Comma", KeyboardKey::KEY_COMMA),
+    ("Period", KeyboardKey::KEY_PERIOD),
+    ("Slash", KeyboardKey::KEY_SLASH),
+    ("Semicolon", KeyboardKey::KEY_SEMICOLON),
+    ("Kp0", KeyboardKey::KEY_KP_0),
+    ("Kp1", KeyboardKey::KEY_KP_1),
+    ("Kp2", KeyboardKey::KEY_KP_2),
+    ("Kp3", KeyboardKey::KEY_KP_3),
+    ("Kp4", KeyboardKey::KEY_KP_4),
+    ("Kp5", KeyboardKey::KEY_KP_5),
+    ("Kp6", KeyboardKey::KEY_KP_6),
+    ("Kp7", KeyboardKey::KEY_KP_7),
+    ("Kp8", KeyboardKey::KEY_KP_8),
+    ("Kp9", KeyboardKey::KEY_KP_9),
+];
+
+/// The key called `name` in the config file, ignoring case.
+pub fn key_from_name(name: &str) -> Option<KeyboardKey> {
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+}
+
+/// The name `key` is written as in the config file, if it has one.
+pub fn key_name(key: KeyboardKey) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|&&(_, named)| named == key).map(|&(name, _)| name)
+}
+
+/// Keys for each in-game action; any of an action's keys triggers it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    pub move_left: Vec<KeyboardKey>,
+    pub move_right: Vec<KeyboardKey>,
+    pub soft_drop: Vec<KeyboardKey>,
+    pub rotate: Vec<KeyboardKey>,
+    pub hard_drop: Vec<KeyboardKey>,
+    pub hold: Vec<KeyboardKey>,
+    /// Held to restart mid-game; pressed to restart once the game is over.
+    pub restart: Vec<KeyboardKey>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            move_left: vec![KeyboardKey::KEY_LEFT],
+            move_right: vec![KeyboardKey::KEY_RIGHT],
+            soft_drop: vec![KeyboardKey::KEY_DOWN],
+            rotate: vec![KeyboardKey::KEY_UP],
+            hard_drop: vec![KeyboardKey::KEY_SPACE],
+            hold: vec![KeyboardKey::KEY_LEFT_SHIFT, KeyboardKey::KEY_C],
+            restart: vec![KeyboardKey::KEY_R],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// The actions that can be bound, in settings screen order.
+    pub const ACTIONS: [Action; 6] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::RotateCw,
+        Action::HardDrop,
+        Action::Hold,
+    ];
+
+    /// Player 1's keys in local versus, on the left of the keyboard.
+    pub fn player_one() -> Self {
+        Self {
+            move_left: vec![KeyboardKey::KEY_A],
+            move_right: vec![KeyboardKey::KEY_D],
+            soft_drop: vec![KeyboardKey::KEY_S],
+            rotate: vec![KeyboardKey::KEY_W],
+            hard_drop: vec![KeyboardKey::KEY_SPACE],
+            hold: vec![KeyboardKey::KEY_LEFT_SHIFT],
+            restart: vec![KeyboardKey::KEY_R],
+        }
+    }
+
+    /// Player 2's keys in local versus: the arrows, with Enter and right shift.
+    pub fn player_two() -> Self {
+        Self {
+            hard_drop: vec![KeyboardKey::KEY_ENTER],
+            hold: vec![KeyboardKey::KEY_RIGHT_SHIFT],
+            ..Self::default()
+        }
+    }
+
+    /// The action's name under `[keys]` in the config file.
+    pub fn config_name(action: Action) -> &'static str {
+        match action {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::SoftDrop | Action::SonicDrop => "soft_drop",
+            Action::RotateCw => "rotate",
+            Action::HardDrop => "hard_drop",
+            Action::Hold => "hold",
+        }
+    }
+
+    /// The keys bound to `action`. Sonic drop shares soft drop's.
+    pub fn keys(&self, action: Action) -> &[KeyboardKey] {
+        match action {
+            Action::MoveLeft => &self.move_left,
+            Action::MoveRight => &self.move_right,
+            Action::SoftDrop | Action::SonicDrop => &self.soft_drop,
+            Action::RotateCw => &self.rotate,
+            Action::HardDrop => &self.hard_drop,
+            Action::Hold => &self.hold,
+        }
+    }
+
+    fn keys_mut(&mut self, action: Action) -> &mut Vec<KeyboardKey> {
+        match action {
+            Action::MoveLeft => &mut self.move_left,
+            Action::MoveRight => &mut self.move_right,
+            Action::SoftDrop | Action::SonicDrop => &mut self.soft_drop,
+            Action::RotateCw => &mut self.rotate,
+            Action::HardDrop => &mut self.hard_drop,
+            Action::Hold => &mut self.hold,
+        }
+    }
+
+    /// Every key with the action it's bound to, for an `InputMap`.
+    pub fn bindings(&self) -> Vec<(Input, Action)> {
+        Self::ACTIONS
+            .into_iter()
+            .flat_map(|action| self.keys(action).iter().map(move |&key| (Input::Key(key), action)))
+            .collect()
+    }
+
+    /// The action `key` is bound to, if any.
+    pub fn action_for(&self, key: KeyboardKey) -> Option<Action> {
+        Self::ACTIONS.into_iter().find(|&action| self.keys(action).contains(&key))
+    }
+
+    /// Makes `key` the only key for `action`.
+    pub fn bind(&mut self, action: Action, key: KeyboardKey) {
+        *self.keys_mut(action) = vec![key];
+    }
+
+    /// Binds `key` to `action` when it already belongs to another action, which gets
+    /// `action`'s old keys in its place.
+    pub fn swap(&mut self, action: Action, key: KeyboardKey) {
+        let old = std::mem::replace(self.keys_mut(action), vec![key]);
+        let Some(other) = Self::ACTIONS
+            .into_iter()
+            .find(|&other| other != action && self.keys(other).contains(&key))
+        else {
+            return;
+        };
+        let keys = self.keys_mut(other);
+        if let Some(at) = keys.iter().position(|&bound| bound == key) {
+            keys.splice(at..=at, old);
+        }
+    }
+}
+
+/// Player settings from `config.toml`, with defaults for anything left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// How long a direction is held before it repeats (DAS).
+    pub das: Duration,
+    /// Time between repeats once it does (ARR); at most one per frame.
+    pub arr: Duration,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub keys: KeyBindings,
+    pub theme: Theme,
+    /// Discord application to show rich presence under; none turns it off.
+    pub discord_app_id: Option<String>,
+    /// Language code of the UI text, such as "es"; English when unset.
+    pub language: Option<String>,
+    /// How much bigger to draw text and the panels beside the board.
+    pub ui_scale: f32,
+    /// Whether the gamepad rumbles on hard drops, big clears and incoming garbage.
+    pub rumble: bool,
+    /// How single-player games pick their pieces; multiplayer and the daily stay classic.
+    pub randomizer: RandomizerKind,
+    /// Draws each piece as one joined shape rather than separate cells, outside classic.
+    pub connected_pieces: bool,
+    /// Restarts deal the same pieces as the game they replace, for drilling an opening.
+    pub restart_same_seed: bool,
+    /// Leaves the board showing through the pause screen, except in games that could set
+    /// a record, where it's always hidden.
+    pub translucent_pause: bool,
+    /// Leaves out screen shake and rows sliding down after a clear.
+    pub reduce_motion: bool,
+    /// Shows how many pieces it's been since the last I piece, beside the next piece.
+    pub drought_counter: bool,
+    /// How long without input before a single-player game pauses itself, and an online
+    /// player shows as away. None never does.
+    pub idle_pause: Option<Duration>,
+    /// Outlines where the bot would put each piece. Games played with it don't set records.
+    pub assist: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            das: KEY_REPEAT_DELAY,
+            arr: KEY_REPEAT_RATE,
+            music_volume: 0.2,
+            sfx_volume: 1.0,
+            keys: KeyBindings::default(),
+            theme: Theme::default(),
+            discord_app_id: None,
+            language: None,
+            ui_scale: MIN_UI_SCALE,
+            rumble: true,
+            randomizer: RandomizerKind::default(),
+            connected_pieces: false,
+            restart_same_seed: false,
+            translucent_pause: false,
+            reduce_motion: false,
+            drought_counter: false,
+            idle_pause: Some(DEFAULT_IDLE_PAUSE),
+            assist: false,
+        }
+    }
+}
+
+// The config as written in config.toml; every field is optional
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigDef {
+    das_ms: Option<u64>,
+    arr_ms: Option<u64>,
+    music_volume: Option<f32>,
+    sfx_volume: Option<f32>,
+    discord_app_id: Option<String>,
+    language: Option<String>,
+    ui_scale: Option<f32>,
+    rumble: Option<bool>,
+    randomizer: Option<RandomizerKind>,
+    connected_pieces: Option<bool>,
+    restart_same_seed: Option<bool>,
+    translucent_pause: Option<bool>,
+    reduce_motion: Option<bool>,
+    drought_counter: Option<bool>,
+    idle_pause_secs: Option<u64>,
+    assist: Option<bool>,
+    keys: KeysDef,
+    theme: ThemeDef,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct KeysDef {
+    move_left: Option<Vec<String>>,
+    move_right: Option<Vec<String>>,
+    soft_drop: Option<Vec<String>>,
+    rotate: Option<Vec<String>>,
+    hard_drop: Option<Vec<String>>,
+    hold: Option<Vec<String>>,
+    restart: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeDef {
+    background: Option<String>,
+    grid: Option<String>,
+    garbage: Option<String>,
+    pieces: HashMap<BlockKind, String>,
+}
+
+impl TryFrom<ConfigDef> for Config {
+    type Error = String;
+
+    fn try_from(def: ConfigDef) -> Result<Self, Self::Error> {
+        let mut config = Config::default();
+        if let Some(ms) = def.das_ms {
+            config.das = repeat_timing("das_ms", ms)?;
+        }
+        if let Some(ms) = def.arr_ms {
+            config.arr = repeat_timing("arr_ms", ms)?;
+        }
+        if let Some(volume) = def.music_volume {
+            config.music_volume = volume_level("music_volume", volume)?;
+        }
+        if let Some(volume) = def.sfx_volume {
+            config.sfx_volume = volume_level("sfx_volume", volume)?;
+        }
+
+        if let Some(app_id) = def.discord_app_id {
+            if app_id.is_empty() || !app_id.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!("discord_app_id must be a number, got {:?}", app_id));
+            }
+            config.discord_app_id = Some(app_id);
+        }
+
+        config.language = def.language;
+
+        if let Some(scale) = def.ui_scale {
+            if !(MIN_UI_SCALE..=MAX_UI_SCALE).contains(&scale) {
+                return Err(format!(
+                    "ui_scale must be between {} and {}, got {}",
+                    MIN_UI_SCALE, MAX_UI_SCALE, scale
+                ));
+            }
+            config.ui_scale = scale;
+        }
+        if let Some(rumble) = def.rumble {
+            config.rumble = rumble;
+        }
+        if let Some(randomizer) = def.randomizer {
+            config.randomizer = randomizer;
+        }
+        if let Some(connected) = def.connected_pieces {
+            config.connected_pieces = connected;
+        }
+        if let Some(same_seed) = def.restart_same_seed {
+            config.restart_same_seed = same_seed;
+        }
+        if let Some(translucent) = def.translucent_pause {
+            config.translucent_pause = translucent;
+        }
+        if let Some(reduce) = def.reduce_motion {
+            config.reduce_motion = reduce;
+        }
+        if let Some(drought) = def.drought_counter {
+            config.drought_counter = drought;
+        }
+        // 0 turns it off
+        if let Some(secs) = def.idle_pause_secs {
+            config.idle_pause = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if let Some(assist) = def.assist {
+            config.assist = assist;
+        }
+
+        let keys = def.keys;
+        let bindings = [
+            ("move_left", keys.move_left, &mut config.keys.move_left),
+            ("move_right", keys.move_right, &mut config.keys.move_right),
+            ("soft_drop", keys.soft_drop, &mut config.keys.soft_drop),
+            ("rotate", keys.rotate, &mut config.keys.rotate),
+            ("hard_drop", keys.hard_drop, &mut config.keys.hard_drop),
+            ("hold", keys.hold, &mut config.keys.hold),
+            ("restart", keys.restart, &mut config.keys.restart),
+        ];
+        for (action, names, binding) in bindings {
+            if let Some(names) = names {
+                *binding = key_list(action, &names)?;
+            }
+        }
+
+        let theme = def.theme;
+        let colors = [
+            ("background", theme.background, &mut config.theme.background),
+            ("grid", theme.grid, &mut config.theme.grid),
+            ("garbage", theme.garbage, &mut config.theme.garbage),
+        ];
+        for (name, hex, color) in colors {
+            if let Some(hex) = hex {
+                *color = parse_color(&hex).map_err(|e| format!("theme.{}: {}", name, e))?;
+            }
+        }
+        for (kind, hex) in theme.pieces {
+            config.theme.pieces[kind.color() as usize] = parse_color(&hex)
+                .map_err(|e| format!("theme.pieces.{}: {}", kind.letter(), e))?;
+        }
+        Ok(config)
+    }
+}
+
+fn repeat_timing(name: &str, ms: u64) -> Result<Duration, String> {
+    let timing = Duration::from_millis(ms);
+    if timing > MAX_REPEAT_TIMING {
+        let max = MAX_REPEAT_TIMING.as_millis();
+        return Err(format!("{} must be at most {} ms, got {}", name, max, ms));
+    }
+    Ok(timing)
+}
+
+fn volume_level(name: &str, volume: f32) -> Result<f32, String> {
+    if !(0.0..=1.0).contains(&volume) {
+        return Err(format!("{} must be between 0 and 1, got {}", name, volume));
+    }
+    Ok(volume)
+}
+
+fn key_list(action: &str, names: &[String]) -> Result<Vec<KeyboardKey>, String> {
+    if names.is_empty() {
+        return Err(format!("keys.{} needs at least one key", action));
+    }
+    names
+        .iter()
+        .map(|name| {
+            key_from_name(name).ok_or_else(|| format!("keys.{}: unknown key {:?}", action, name))
+        })
+        .collect()
+}
+
+/// Parses a `#RRGGBB` or `#RRGGBBAA` color.
+pub fn parse_color(hex: &str) -> Result<Color, String> {
+    let digits = hex
+        .strip_prefix('#')
+        .filter(|digits| matches!(digits.len(), 6 | 8) && digits.is_ascii())
+        .ok_or_else(|| format!("expected #RRGGBB or #RRGGBBAA, got {:?}", hex))?;
+    let channel = |i: usize| {
+        u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("bad hex color {:?}", hex))
+    };
+    let alpha = if digits.len() == 8 { channel(6)? } else { 255 };
+    Ok(Color::new(channel(0)?, channel(2)?, channel(4)?, alpha))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+    /// The file couldn't be parsed to write a setting back into it.
+    Edit(toml_edit::TomlError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "could not parse config: {}", e),
+            ConfigError::Invalid(message) => write!(f, "invalid config: {}", message),
+            ConfigError::Edit(e) => write!(f, "could not update config: {}", e),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+pub fn parse_config(text: &str) -> Result<Config, ConfigError> {
+    let def: ConfigDef = toml::from_str(text).map_err(ConfigError::Parse)?;
+    Config::try_from(def).map_err(ConfigError::Invalid)
+}
+
+/// Loads the config at `path`, or the defaults if there's no file there.
+pub fn load_config(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse_config(&text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(ConfigError::Io(e)),
+    }
+}
+
+/// Writes every key binding into the config file at `path`.
+pub fn save_keys(path: impl AsRef<Path>, keys: &KeyBindings) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| {
+        let table = doc["keys"].or_insert(toml_edit::table());
+        for action in KeyBindings::ACTIONS {
+            let names: toml_edit::Array =
+                keys.keys(action).iter().filter_map(|&key| key_name(key)).collect();
+            table[KeyBindings::config_name(action)] = toml_edit::value(names);
+        }
+    })
+}
+
+/// Writes the UI scale into the config file at `path`.
+pub fn save_ui_scale(path: impl AsRef<Path>, scale: f32) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| doc["ui_scale"] = toml_edit::value(scale as f64))
+}
+
+/// Writes whether the gamepad rumbles into the config file at `path`.
+pub fn save_rumble(path: impl AsRef<Path>, rumble: bool) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| doc["rumble"] = toml_edit::value(rumble))
+}
+
+/// Writes which randomizer single-player games use into the config file at `path`.
+pub fn save_randomizer(path: impl AsRef<Path>, kind: RandomizerKind) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| doc["randomizer"] = toml_edit::value(kind.name()))
+}
+
+/// Writes whether pieces are drawn connected into the config file at `path`.
+pub fn save_connected_pieces(path: impl AsRef<Path>, connected: bool) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| doc["connected_pieces"] = toml_edit::value(connected))
+}
+
+/// Writes whether the board shows through the pause screen into the config file at `path`.
+pub fn save_translucent_pause(
+    path: impl AsRef<Path>,
+    translucent: bool,
+) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| {
+        doc["translucent_pause"] = toml_edit::value(translucent)
+    })
+}
+
+/// Writes whether motion is reduced into the config file at `path`.
+pub fn save_reduce_motion(path: impl AsRef<Path>, reduce: bool) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| doc["reduce_motion"] = toml_edit::value(reduce))
+}
+
+/// Writes whether the drought counter is shown into the config file at `path`.
+pub fn save_drought_counter(path: impl AsRef<Path>, shown: bool) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| doc["drought_counter"] = toml_edit::value(shown))
+}
+
+/// Writes whether assist mode is on into the config file at `path`.
+pub fn save_assist(path: impl AsRef<Path>, assist: bool) -> Result<(), ConfigError> {
+    update_config(path.as_ref(), |doc| doc["assist"] = toml_edit::value(assist))
+}
+
+// Changes settings in place, so the rest of the file, comments included, stays as written
+fn update_config(
+    path: &Path,
+    edit: impl FnOnce(&mut toml_edit::DocumentMut),
+) -> Result<(), ConfigError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(ConfigError::Io(e)),
+    };
+    let mut doc: toml_edit::DocumentMut = text.parse().map_err(ConfigError::Edit)?;
+    edit(&mut doc);
+    fs::write(path, doc.to_string()).map_err(ConfigError::Io)
+}
+
+/// Notices changes to the config file by checking its modification time now and then.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl ConfigWatcher {
+    /// Watches `path`, taking its current contents as already loaded.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = modified_time(&path);
+        Self {
+            path,
+            modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Reloads the config if the file changed since the last check, at most once per
+    /// `CONFIG_POLL_INTERVAL`. A deleted file goes back to the defaults.
+    pub fn poll_at(&mut self, now: Instant) -> Option<Result<Config, ConfigError>> {
+        if now.saturating_duration_since(self.last_check) < CONFIG_POLL_INTERVAL {
+            return None;
+        }
+        self.last_check = now;
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(load_config(&self.path))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_settings_keep_their_defaults() {
+        let config = parse_config(
+            r##"
+            das_ms = 100
+            sfx_volume = 0.5
+
+            restart_same_seed = true
+            idle_pause_secs = 0
+
+            [keys]
+            hard_drop = ["W", "space"]
+            restart = ["T"]
+
+            [theme.pieces]
+            T = "#FF00FF"
+            "##,
+        )
+        .unwrap();
+        let defaults = Config::default();
+        assert_eq!(config.das, Duration::from_millis(100));
+        assert_eq!(config.arr, defaults.arr);
+        assert_eq!(config.sfx_volume, 0.5);
+        assert_eq!(config.music_volume, defaults.music_volume);
+        assert_eq!(config.keys.hard_drop, [KeyboardKey::KEY_W, KeyboardKey::KEY_SPACE]);
+        assert_eq!(config.keys.hold, defaults.keys.hold);
+        assert_eq!(config.keys.restart, [KeyboardKey::KEY_T]);
+        assert!(config.restart_same_seed);
+        assert_eq!(config.idle_pause, None);
+        assert_eq!(defaults.idle_pause, Some(DEFAULT_IDLE_PAUSE));
+        assert_eq!(config.theme.piece(BlockKind::T), Color::new(255, 0, 255, 255));
+        assert_eq!(config.theme.piece(BlockKind::S), defaults.theme.piece(BlockKind::S));
+        assert_eq!(config.theme.background, defaults.theme.background);
+
+        assert_eq!(parse_config("").unwrap(), defaults);
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        let invalid = |text: &str| match parse_config(text) {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("{:?} for {:?}", other, text),
+        };
+        assert!(invalid("das_ms = 5000").contains("das_ms"));
+        assert!(invalid("music_volume = 1.5").contains("music_volume"));
+        assert!(invalid("ui_scale = 3.0").contains("ui_scale"));
+        assert!(invalid("discord_app_id = \"tetris\"").contains("discord_app_id"));
+        assert!(invalid("[keys]\nrotate = []").contains("keys.rotate"));
+        assert!(invalid("[keys]\nhold = [\"Hyper\"]").contains("Hyper"));
+        assert!(invalid("[theme]\ngrid = \"blue\"").contains("theme.grid"));
+        // Typos and unknown pieces don't parse at all
+        assert!(matches!(parse_config("dass_ms = 1"), Err(ConfigError::Parse(_))));
+        assert!(matches!(
+            parse_config("[theme.pieces]\nX = \"#000000\""),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn colors_parse_with_and_without_alpha() {
+        assert_eq!(parse_color("#2E3440"), Ok(Color::new(46, 52, 64, 255)));
+        assert_eq!(parse_color("#ffffff80"), Ok(Color::new(255, 255, 255, 128)));
+        assert!(parse_color("2E3440").is_err());
+        assert!(parse_color("#2E344").is_err());
+        assert!(parse_color("#GG0000").is_err());
+    }
+
+    #[test]
+    fn watcher_reloads_a_changed_file() {
+        let path =
+            std::env::temp_dir().join(format!("tetris-config-test-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let start = Instant::now();
+        let mut watcher = ConfigWatcher::new(&path);
+        assert!(watcher.poll_at(start + CONFIG_POLL_INTERVAL).is_none());
+
+        fs::write(&path, "arr_ms = 0").unwrap();
+        // Not checked again until the interval has passed
+        assert!(watcher.poll_at(start + CONFIG_POLL_INTERVAL).is_none());
+        let config = watcher.poll_at(start + CONFIG_POLL_INTERVAL * 2).unwrap().unwrap();
+        assert_eq!(config.arr, Duration::ZERO);
+        assert!(watcher.poll_at(start + CONFIG_POLL_INTERVAL * 3).is_none());
+
+        fs::remove_file(&path).unwrap();
+        let config = watcher.poll_at(start + CONFIG_POLL_INTERVAL * 4).unwrap().unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn saved_settings_keep_the_rest_of_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("tetris-config-save-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+        save_ui_scale(&path, 1.5).unwrap();
+        assert_eq!(load_config(&path).unwrap().ui_scale, 1.5);
+
+        let edited = "# my settings\nsfx_volume = 0.5\nui_scale = 1.25\n\n[keys]\nhold = [\"C\"]\n";
+        fs::write(&path, edited).unwrap();
+        save_ui_scale(&path, 2.0).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# my settings\nsfx_volume = 0.5\nui_scale = 2.0\n"), "{}", text);
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.ui_scale, 2.0);
+        assert_eq!(config.keys.hold, [KeyboardKey::KEY_C]);
+        assert!(config.rumble);
+        save_rumble(&path, false).unwrap();
+        assert!(!load_config(&path).unwrap().rumble);
+        save_randomizer(&path, RandomizerKind::History4).unwrap();
+        assert_eq!(load_config(&path).unwrap().randomizer, RandomizerKind::History4);
+        save_connected_pieces(&path, true).unwrap();
+        assert!(load_config(&path).unwrap().connected_pieces);
+        save_translucent_pause(&path, true).unwrap();
+        assert!(load_config(&path).unwrap().translucent_pause);
+        save_reduce_motion(&path, true).unwrap();
+        assert!(load_config(&path).unwrap().reduce_motion);
+        save_drought_counter(&path, true).unwrap();
+        assert!(load_config(&path).unwrap().drought_counter);
+        save_assist(&path, true).unwrap();
+        assert!(load_config(&path).unwrap().assist);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        for &(name, key) in KEY_NAMES {
+            assert_eq!(key_from_name(key_name(key).unwrap()), Some(key), "{}", name);
+        }
+        assert_eq!(key_name(KeyboardKey::KEY_LEFT_SHIFT), Some("LeftShift"));
+        assert_eq!(key_name(KeyboardKey::KEY_NULL), None);
+    }
+
+    #[test]
+    fn rebinding_swaps_conflicting_keys() {
+        let mut keys = KeyBindings::default();
+        assert_eq!(keys.action_for(KeyboardKey::KEY_C), Some(Action::Hold));
+        assert_eq!(keys.action_for(KeyboardKey::KEY_Q), None);
+
+        keys.bind(Action::RotateCw, KeyboardKey::KEY_Q);
+        assert_eq!(keys.rotate, [KeyboardKey::KEY_Q]);
+
+        keys.swap(Action::HardDrop, KeyboardKey::KEY_C);
+        assert_eq!(keys.hard_drop, [KeyboardKey::KEY_C]);
+        assert_eq!(keys.hold, [KeyboardKey::KEY_LEFT_SHIFT, KeyboardKey::KEY_SPACE]);
+    }
+
+    #[test]
+    fn saved_keys_load_back() {
+        let path =
+            std::env::temp_dir().join(format!("tetris-config-keys-{}.toml", std::process::id()));
+        fs::write(&path, "sfx_volume = 0.5
+").unwrap();
+        let mut keys = KeyBindings::default();
+        keys.bind(Action::Hold, KeyboardKey::KEY_KP_0);
+        save_keys(&path, &keys).unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.keys, keys);
+        assert_eq!(config.sfx_volume, 0.5);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn versus_players_share_no_keys() {
+        let (one, two) = (KeyBindings::player_one(), KeyBindings::player_two());
+        for action in KeyBindings::ACTIONS {
+            for &key in one.keys(action) {
+                assert_eq!(two.action_for(key), None, "{:?} is bound for both", key);
+            }
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use super::multiplayer::{query, GameMessage};
+use super::results::{head_to_head, HeadToHead, MatchResult};
+use super::splits::{update_best, BestSplits, Splits};
+use super::verify::{ClaimedRecord, RecordRejection};
+use super::GameMode;
+
+pub const HIGH_SCORES_PATH: &str = "highscores.json";
+// How many of the day's best times the server sends back
+pub const DAILY_TOP: usize = 10;
+
+/// The day's seed, the same for everyone: a 64-bit FNV-1a hash of the date, e.g. "2024-06-01".
+pub fn daily_seed(date: &str) -> u64 {
+    date.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Our record for one day's challenge. Only the first attempt counts.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct DailyAttempt {
+    pub attempts: u32,
+    /// Time of the first attempt, if it was finished.
+    pub time_ms: Option<u64>,
+}
+
+/// Local high scores, kept in a JSON file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HighScores {
+    /// Daily challenge attempts by date.
+    #[serde(default)]
+    pub daily: BTreeMap<String, DailyAttempt>,
+    /// Lifetime records against opponents, by name.
+    #[serde(default)]
+    pub rivals: BTreeMap<String, HeadToHead>,
+    /// Splits of the fastest 40-line sprint.
+    #[serde(default)]
+    pub sprint_best: Option<BestSplits>,
+    /// The best score in each mode played for points.
+    #[serde(default)]
+    pub best_scores: BTreeMap<GameMode, u32>,
+}
+
+impl HighScores {
+    /// Loads the high score file, starting empty if there isn't one yet.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Counts an attempt at the day's challenge. Returns whether it is the official one.
+    pub fn start_daily(&mut self, date: &str) -> bool {
+        let attempt = self.daily.entry(date.to_string()).or_default();
+        attempt.attempts += 1;
+        attempt.attempts == 1
+    }
+
+    /// Records the time of the day's official attempt.
+    pub fn finish_daily(&mut self, date: &str, time: Duration) {
+        if let Some(attempt) = self.daily.get_mut(date) {
+            attempt.time_ms.get_or_insert(time.as_millis() as u64);
+        }
+    }
+
+    /// Keeps a game's score if it's the best yet in its mode. Returns whether it was.
+    pub fn record_score(&mut self, mode: GameMode, points: u32) -> bool {
+        let best = self.best_scores.entry(mode).or_default();
+        if points <= *best {
+            return false;
+        }
+        *best = points;
+        true
+    }
+
+    /// Keeps a sprint's splits if it's the fastest yet. Returns whether it was.
+    pub fn finish_sprint(&mut self, run: &Splits) -> bool {
+        update_best(&mut self.sprint_best, run)
+    }
+
+    /// Adds a finished match to the records against every named opponent in it.
+    pub fn record_match(&mut self, result: &MatchResult, own_id: &str) {
+        for (opponent, won) in head_to_head(result, own_id) {
+            if let Some(name) = &opponent.name {
+                self.rivals.entry(name.clone()).or_default().add(won);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DailyEntry {
+    pub name: String,
+    pub time_ms: u64,
+}
+
+/// Daily challenge times the server has been sent, best per name. Kept in memory only.
+#[derive(Default)]
+pub struct DailyBoard {
+    times: HashMap<String, HashMap<String, u64>>,
+}
+
+impl DailyBoard {
+    pub fn submit(&mut self, date: &str, name: &str, time_ms: u64) {
+        let best = self
+            .times
+            .entry(date.to_string())
+            .or_default()
+            .entry(name.to_string())
+            .or_insert(time_ms);
+        *best = (*best).min(time_ms);
+    }
+
+    /// The day's `count` fastest times, ties in name order.
+    pub fn top(&self, date: &str, count: usize) -> Vec<DailyEntry> {
+        let mut entries: Vec<DailyEntry> = self
+            .times
+            .get(date)
+            .into_iter()
+            .flatten()
+            .map(|(name, time_ms)| DailyEntry {
+                name: name.clone(),
+                time_ms: *time_ms,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.time_ms.cmp(&b.time_ms).then_with(|| a.name.cmp(&b.name)));
+        entries.truncate(count);
+        entries
+    }
+}
+
+/// An official daily run to send, with the input log the server checks it by.
+#[derive(Debug, Clone)]
+pub struct DailySubmission {
+    pub name: Option<String>,
+    pub claimed: ClaimedRecord,
+    pub replay_bytes: Vec<u8>,
+}
+
+/// The server's answer: the day's top times and, if a run was sent, whether it went on them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailySync {
+    pub entries: Vec<DailyEntry>,
+    pub verdict: Option<Result<(), RecordRejection>>,
+}
+
+/// Sends an official result, if given, and fetches the day's top times. This goes over a
+/// query connection, so daily runs never show up in the lobby or its matches.
+pub async fn sync_daily(
+    server_addr: &str,
+    date: String,
+    submission: Option<DailySubmission>,
+) -> Result<DailySync, String> {
+    let mut requests = Vec::new();
+    if let Some(submission) = submission {
+        requests.push(GameMessage::SubmitRecord {
+            mode: GameMode::Sprint,
+            date: date.clone(),
+            name: submission.name,
+            claimed: submission.claimed,
+            replay_bytes: submission.replay_bytes,
+        });
+    }
+    requests.push(GameMessage::DailyTop { date: date.clone() });
+    // The verdict comes first, as the server answers in order
+    let mut verdict = None;
+    let entries = query(server_addr, requests, |msg| match msg {
+        GameMessage::RecordAccepted => {
+            verdict = Some(Ok(()));
+            None
+        }
+        GameMessage::RecordRejected { reason } => {
+            verdict = Some(Err(reason));
+            None
+        }
+        GameMessage::DailyLeaderboard { date: day, entries } if day == date => Some(entries),
+        _ => None,
+    })
+    .await?;
+    Ok(DailySync { entries, verdict })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::multiplayer::{list_rooms, MultiplayerServer, ServerPaths, DEFAULT_ROOM};
+    use crate::tetris::{best_placement, Game, GameState, Weights};
+
+    // The day's sprint played through by the bot, a frame after each of its inputs
+    fn daily_sprint(date: &str) -> Game {
+        let mut game = Game::with_seed(0);
+        game.start_sprint(daily_seed(date));
+        while game.state == GameState::Playing {
+            if !game.is_controllable() {
+                game.tick(Duration::from_millis(50));
+                continue;
+            }
+            let placement = best_placement(&game, &Weights::default()).unwrap();
+            for action in placement.actions(&game.current_block) {
+                game.apply(action);
+                game.tick(Duration::from_millis(50));
+            }
+        }
+        game
+    }
+
+    #[test]
+    fn seed_depends_only_on_the_date() {
+        assert_eq!(daily_seed("2024-06-01"), daily_seed("2024-06-01"));
+        assert_ne!(daily_seed("2024-06-01"), daily_seed("2024-06-02"));
+    }
+
+    #[test]
+    fn only_the_first_attempt_of_the_day_is_official() {
+        let mut scores = HighScores::default();
+        assert!(scores.start_daily("2024-06-01"));
+        scores.finish_daily("2024-06-01", Duration::from_millis(61_000));
+        assert!(!scores.start_daily("2024-06-01"));
+        scores.finish_daily("2024-06-01", Duration::from_millis(50_000));
+        assert_eq!(scores.daily["2024-06-01"].time_ms, Some(61_000));
+        assert_eq!(scores.daily["2024-06-01"].attempts, 2);
+        assert!(scores.start_daily("2024-06-02"));
+    }
+
+    #[test]
+    fn best_scores_are_kept_per_mode() {
+        let mut scores = HighScores::default();
+        assert!(!scores.record_score(GameMode::Marathon, 0));
+        assert!(scores.record_score(GameMode::Marathon, 1200));
+        assert!(!scores.record_score(GameMode::Marathon, 1200));
+        assert!(scores.record_score(GameMode::Classic, 400));
+        assert!(scores.record_score(GameMode::Marathon, 1500));
+
+        let json = serde_json::to_string(&scores).unwrap();
+        assert!(json.contains(r#""best_scores":{"marathon":1500,"classic":400}"#), "{}", json);
+        let loaded: HighScores = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.best_scores, scores.best_scores);
+    }
+
+    #[test]
+    fn board_keeps_each_players_best_time() {
+        let mut board = DailyBoard::default();
+        board.submit("2024-06-01", "bo", 70_000);
+        board.submit("2024-06-01", "al", 65_000);
+        board.submit("2024-06-01", "bo", 60_000);
+        board.submit("2024-06-01", "al", 80_000);
+        board.submit("2024-06-02", "cy", 10_000);
+        let top: Vec<(String, u64)> = board
+            .top("2024-06-01", DAILY_TOP)
+            .into_iter()
+            .map(|entry| (entry.name, entry.time_ms))
+            .collect();
+        assert_eq!(top, [("bo".to_string(), 60_000), ("al".to_string(), 65_000)]);
+        assert_eq!(board.top("2024-06-01", 1).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sync_submits_without_joining_the_lobby() {
+        let server = MultiplayerServer::with_paths(ServerPaths::scratch("server-18149"));
+        tokio::spawn(async move { server.start("127.0.0.1:18149").await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let addr = "ws://127.0.0.1:18149";
+
+        let game = daily_sprint("2024-06-01");
+        let claimed = ClaimedRecord::of(&game);
+        let submission = DailySubmission {
+            name: Some("al".to_string()),
+            claimed,
+            replay_bytes: game.input_log().unwrap().to_bytes(),
+        };
+        let sync = sync_daily(addr, "2024-06-01".to_string(), Some(submission.clone()));
+        assert_eq!(sync.await.unwrap().verdict, Some(Ok(())));
+
+        // The same run claiming to be faster goes nowhere
+        let tampered = DailySubmission {
+            name: Some("bo".to_string()),
+            claimed: ClaimedRecord {
+                time_ms: claimed.time_ms - 1000,
+                ..claimed
+            },
+            ..submission
+        };
+        let sync = sync_daily(addr, "2024-06-01".to_string(), Some(tampered)).await.unwrap();
+        assert_eq!(sync.verdict, Some(Err(RecordRejection::Mismatch)));
+
+        let sync = sync_daily(addr, "2024-06-01".to_string(), None).await.unwrap();
+        assert_eq!(sync.verdict, None);
+        assert_eq!(sync.entries.len(), 1);
+        assert_eq!(sync.entries[0].name, "al");
+        assert_eq!(sync.entries[0].time_ms, claimed.time_ms);
+
+        let rooms = list_rooms(addr).await.unwrap();
+        let lobby = rooms.iter().find(|room| room.name == DEFAULT_ROOM).unwrap();
+        assert_eq!(lobby.players, 0);
+    }
+}
@@ -0,0 +1,129 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::multiplayer::{GameMessage, MAX_FRAME_BYTES};
+
+// Largest datagram sent or accepted, safely under a typical path MTU. A deflated board
+// takes a fraction of it
+pub const MAX_DATAGRAM_BYTES: usize = 1200;
+// How often a client pokes the server's UDP port until it hears the side channel works,
+// and how many times before it settles for the WebSocket alone
+pub const UDP_PROBE_INTERVAL: Duration = Duration::from_millis(250);
+pub const UDP_PROBES: u32 = 8;
+// An unchanged board goes out again this often over UDP, in case the last one was lost
+pub const UDP_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One message on the unreliable side channel, which only carries updates that a newer
+/// one makes obsolete.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Datagram {
+    /// The key the sending client registered over its WebSocket; 0 from the server.
+    pub key: u64,
+    /// Counts up with each datagram about a player, so late ones can be dropped.
+    pub seq: u64,
+    /// Left out of probes, which only tell the server where a client's datagrams come
+    /// from.
+    pub msg: Option<GameMessage>,
+}
+
+/// Deflates a datagram, or returns None if it would still be too big to send.
+pub fn encode_datagram(datagram: &Datagram) -> Option<Vec<u8>> {
+    let json = serde_json::to_vec(datagram).ok()?;
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&json).ok()?;
+    let bytes = encoder.finish().ok()?;
+    (bytes.len() <= MAX_DATAGRAM_BYTES).then_some(bytes)
+}
+
+pub fn decode_datagram(bytes: &[u8]) -> Option<Datagram> {
+    let mut json = Vec::new();
+    DeflateDecoder::new(bytes)
+        .take(MAX_FRAME_BYTES as u64)
+        .read_to_end(&mut json)
+        .ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Where a client's sequence numbers start. Taken from the clock so a client that
+/// reconnects carries on above the numbers its last connection used.
+pub fn first_sequence() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |time| time.as_micros() as u64)
+}
+
+/// Drops datagrams that arrive after a newer one about the same player. Lost ones don't
+/// hold anything up: the next to arrive is simply newer.
+#[derive(Default)]
+pub struct SequenceFilter {
+    latest: HashMap<String, u64>,
+}
+
+impl SequenceFilter {
+    /// Whether a datagram about `player_id` numbered `seq` is newer than any seen so far.
+    pub fn accept(&mut self, player_id: &str, seq: u64) -> bool {
+        match self.latest.get_mut(player_id) {
+            Some(latest) if *latest >= seq => false,
+            Some(latest) => {
+                *latest = seq;
+                true
+            }
+            None => {
+                self.latest.insert(player_id.to_string(), seq);
+                true
+            }
+        }
+    }
+
+    pub fn forget(&mut self, player_id: &str) {
+        self.latest.remove(player_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::{BlockKind, BOARD_HEIGHT, BOARD_WIDTH};
+
+    #[test]
+    fn full_boards_fit_in_a_datagram() {
+        let cells = (0..BOARD_HEIGHT)
+            .map(|row| (0..BOARD_WIDTH).map(|col| Some(((row + col) % 7) as i32)).collect())
+            .collect();
+        let datagram = Datagram {
+            key: 42,
+            seq: first_sequence(),
+            msg: Some(GameMessage::BoardState {
+                player_id: uuid::Uuid::new_v4().to_string(),
+                cells,
+                current: None,
+                next: Some(BlockKind::T),
+            }),
+        };
+        let bytes = encode_datagram(&datagram).expect("board too big for a datagram");
+        assert!(decode_datagram(&bytes) == Some(datagram));
+        assert!(decode_datagram(&bytes[..bytes.len() / 2]).is_none());
+        assert!(decode_datagram(b"not a datagram").is_none());
+    }
+
+    #[test]
+    fn late_and_repeated_datagrams_are_dropped() {
+        let mut filter = SequenceFilter::default();
+        assert!(filter.accept("alice", 5));
+        // 6 and 7 were lost on the way
+        assert!(filter.accept("alice", 8));
+        assert!(!filter.accept("alice", 7));
+        assert!(!filter.accept("alice", 8));
+        // Each player counts on their own
+        assert!(filter.accept("bob", 1));
+        assert!(filter.accept("alice", 9));
+
+        filter.forget("alice");
+        assert!(filter.accept("alice", 1));
+    }
+}
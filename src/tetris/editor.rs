@@ -0,0 +1,269 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::capture::export_board_ascii;
+use super::practice::{parse_queue, PracticeSetup};
+use super::{BlockKind, Board, Cell, CellContent, BOARD_HEIGHT, BOARD_WIDTH};
+
+// Where the editor saves setups, and the longest name one can be given
+pub const SETUPS_DIR: &str = "setups";
+pub const MAX_SETUP_NAME: usize = 24;
+
+/// What the editor paints cells with: one of the piece colors, or garbage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Brush {
+    Piece(BlockKind),
+    Garbage,
+}
+
+impl Brush {
+    pub const ALL: [Brush; 8] = [
+        Brush::Piece(BlockKind::I),
+        Brush::Piece(BlockKind::J),
+        Brush::Piece(BlockKind::L),
+        Brush::Piece(BlockKind::O),
+        Brush::Piece(BlockKind::S),
+        Brush::Piece(BlockKind::T),
+        Brush::Piece(BlockKind::Z),
+        Brush::Garbage,
+    ];
+
+    pub fn content(self) -> CellContent {
+        match self {
+            Brush::Piece(kind) => CellContent::Piece { kind, id: 0 },
+            Brush::Garbage => CellContent::Garbage,
+        }
+    }
+}
+
+/// Where typing goes in the editor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditorField {
+    Board,
+    Name,
+    Queue,
+}
+
+/// Why the edited setup can't be played yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetupError {
+    /// A row is already full, so it would never clear. Rows count from 1 at the top, as
+    /// in the saved file.
+    CompleteRow(usize),
+    NoName,
+    Queue(String),
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupError::CompleteRow(row) => write!(f, "Row {} is complete", row),
+            SetupError::NoName => write!(f, "The setup needs a name"),
+            SetupError::Queue(e) => write!(f, "Bad piece sequence: {}", e),
+        }
+    }
+}
+
+/// A board being drawn by hand for practice mode: a cursor over the cells, the brush it
+/// paints with, and the name to save under and pieces to play with.
+pub struct BoardEditor {
+    pub board: Board,
+    /// Row and column, from the top left.
+    pub cursor: (usize, usize),
+    brush: usize,
+    pub field: EditorField,
+    pub name: String,
+    pub queue: String,
+    /// Set when the last attempt to play the setup failed.
+    pub error: Option<SetupError>,
+}
+
+impl Default for BoardEditor {
+    fn default() -> Self {
+        Self {
+            board: Board::new(),
+            cursor: (BOARD_HEIGHT - 1, 0),
+            brush: 0,
+            field: EditorField::Board,
+            name: "setup".to_string(),
+            queue: String::new(),
+            error: None,
+        }
+    }
+}
+
+impl BoardEditor {
+    pub fn brush(&self) -> Brush {
+        Brush::ALL[self.brush]
+    }
+
+    /// Moves to the next brush in the palette, wrapping around.
+    pub fn cycle_brush(&mut self) {
+        self.brush = (self.brush + 1) % Brush::ALL.len();
+    }
+
+    /// Moves the cursor, stopping at the edges of the board.
+    pub fn move_cursor(&mut self, dx: isize, dy: isize) {
+        let (row, col) = self.cursor;
+        let row = row.saturating_add_signed(dy).min(BOARD_HEIGHT - 1);
+        let col = col.saturating_add_signed(dx).min(BOARD_WIDTH - 1);
+        self.cursor = (row, col);
+    }
+
+    /// Paints the cell under the cursor, or empties it if it already has the brush's color.
+    pub fn toggle(&mut self) {
+        let (row, col) = self.cursor;
+        let paint = Cell::Filled(self.brush().content());
+        let cell = if self.board.get_cell(row, col) == Some(paint) { Cell::Empty } else { paint };
+        self.board.set_cell(row, col, cell);
+    }
+
+    /// Fills the cursor's row with the brush, leaving a hole under the cursor so the row
+    /// can still be cleared.
+    pub fn fill_row(&mut self) {
+        let (row, hole) = self.cursor;
+        let paint = Cell::Filled(self.brush().content());
+        for col in 0..BOARD_WIDTH {
+            self.board.set_cell(row, col, if col == hole { Cell::Empty } else { paint });
+        }
+    }
+
+    pub fn clear_row(&mut self) {
+        let row = self.cursor.0;
+        for col in 0..BOARD_WIDTH {
+            self.board.set_cell(row, col, Cell::Empty);
+        }
+    }
+
+    /// Moves typing on to the next field, back round to the board after the queue.
+    pub fn next_field(&mut self) {
+        self.field = match self.field {
+            EditorField::Board => EditorField::Name,
+            EditorField::Name => EditorField::Queue,
+            EditorField::Queue => EditorField::Board,
+        };
+    }
+
+    /// Types a character into the field being edited. Names keep to characters that are
+    /// safe in a file name, and queues to piece letters.
+    pub fn type_char(&mut self, ch: char) {
+        match self.field {
+            EditorField::Board => {}
+            EditorField::Name => {
+                let allowed = ch.is_ascii_alphanumeric() || ch == '-' || ch == '_';
+                if allowed && self.name.len() < MAX_SETUP_NAME {
+                    self.name.push(ch);
+                }
+            }
+            EditorField::Queue => {
+                let ch = ch.to_ascii_uppercase();
+                if BlockKind::from_letter(ch).is_some() {
+                    self.queue.push(ch);
+                }
+            }
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        match self.field {
+            EditorField::Board => {}
+            EditorField::Name => {
+                self.name.pop();
+            }
+            EditorField::Queue => {
+                self.queue.pop();
+            }
+        }
+    }
+
+    /// The setup to play, if the board and fields make a valid one.
+    pub fn setup(&self) -> Result<PracticeSetup, SetupError> {
+        if let Some(&row) = self.board.find_complete_lines().first() {
+            return Err(SetupError::CompleteRow(row + 1));
+        }
+        if self.name.is_empty() {
+            return Err(SetupError::NoName);
+        }
+        let queue = parse_queue(&self.queue).map_err(SetupError::Queue)?;
+        Ok(PracticeSetup {
+            board: self.board.clone(),
+            queue,
+        })
+    }
+
+    /// Where the setup is saved in `dir`.
+    pub fn path(&self, dir: impl AsRef<Path>) -> PathBuf {
+        dir.as_ref().join(&self.name).with_extension("txt")
+    }
+
+    /// Writes the board to `dir` in the layout `--practice` loads, creating the directory
+    /// if needed, and returns where.
+    pub fn save(&self, dir: impl AsRef<Path>) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(&dir)?;
+        let path = self.path(dir);
+        export_board_ascii(&self.board, &path)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edited_boards_round_trip_through_the_text_layout() {
+        let mut editor = BoardEditor::default();
+        editor.fill_row();
+        editor.move_cursor(3, -1);
+        editor.cycle_brush();
+        editor.toggle();
+        editor.move_cursor(20, -30);
+        for _ in 0..6 {
+            editor.cycle_brush();
+        }
+        assert_eq!(editor.brush(), Brush::Garbage);
+        editor.toggle();
+        assert_eq!(editor.cursor, (0, BOARD_WIDTH - 1));
+
+        let text = format!("{:#}", editor.board);
+        assert!(text.ends_with("...J......\n.IIIIIIIII\n"));
+        assert!(text.starts_with(".........G\n"));
+        let loaded = Board::from_ascii(&text).unwrap();
+        assert_eq!(format!("{:#}", loaded), text);
+
+        // Painting the same color again rubs the cell out
+        editor.toggle();
+        assert_eq!(editor.board.get_cell(0, BOARD_WIDTH - 1), Some(Cell::Empty));
+        editor.move_cursor(0, BOARD_HEIGHT as isize);
+        editor.clear_row();
+        assert_eq!(editor.board.row_mask(BOARD_HEIGHT - 1), 0);
+    }
+
+    #[test]
+    fn only_valid_setups_can_be_played() {
+        let mut editor = BoardEditor::default();
+        editor.fill_row();
+        editor.toggle();
+        assert!(matches!(editor.setup(), Err(SetupError::CompleteRow(20))));
+        editor.toggle();
+
+        editor.next_field();
+        for ch in "my setup!".chars() {
+            editor.type_char(ch);
+        }
+        assert_eq!(editor.name, "setupmysetup");
+        editor.name.clear();
+        assert_eq!(editor.setup().err(), Some(SetupError::NoName));
+        editor.type_char('x');
+
+        editor.next_field();
+        for ch in "tsx zi".chars() {
+            editor.type_char(ch);
+        }
+        editor.backspace();
+        let setup = editor.setup().unwrap();
+        assert_eq!(setup.queue, [BlockKind::T, BlockKind::S, BlockKind::Z]);
+        assert_eq!(editor.path(SETUPS_DIR), Path::new("setups/x.txt"));
+    }
+}
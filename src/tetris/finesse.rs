@@ -0,0 +1,293 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+
+use super::{Block, BlockKind, Board, BOARD_HEIGHT};
+
+// Most placement hints shown for a piece: every distinct landing of a T, J or L on an open
+// board
+pub const MAX_PLACEMENT_HINTS: usize = 34;
+
+/// Inputs spent on the current piece, and how many placements this game took more inputs
+/// than they needed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Finesse {
+    pub inputs: u32,
+    pub faults: u32,
+}
+
+/// Where a piece ends up on an open field: its shape, as cells relative to its top left
+/// corner, and the leftmost column it covers. Rotations that cover the same cells, such
+/// as the two flat orientations of an I, are the same landing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Landing {
+    shape: [(i32, i32); 4],
+    left: i32,
+}
+
+impl Landing {
+    pub fn of(block: &Block) -> Self {
+        let mut cells = block.blocks();
+        let left = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+        let top = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+        for cell in &mut cells {
+            *cell = (cell.0 - left, cell.1 - top);
+        }
+        cells.sort_unstable();
+        Self { shape: cells, left }
+    }
+}
+
+/// Fewest inputs that take a freshly spawned piece to each landing, counting a tap, a
+/// hold to the wall and a rotation as one input each. The hard drop isn't included.
+pub type FinesseTable = HashMap<Landing, u32>;
+
+/// The table for `kind`, worked out once by searching every input sequence on an empty
+/// board with the same rotation and wall kicks as the game.
+pub fn finesse_table(kind: BlockKind) -> &'static FinesseTable {
+    static TABLES: OnceLock<HashMap<BlockKind, FinesseTable>> = OnceLock::new();
+    let tables = TABLES.get_or_init(|| {
+        BlockKind::ALL
+            .iter()
+            .map(|&kind| (kind, search_inputs(kind)))
+            .collect()
+    });
+    &tables[&kind]
+}
+
+/// Fewest inputs needed to get `block` where it is, or None for a placement that can't be
+/// reached on an open field, such as a tuck.
+pub fn optimal_inputs(block: &Block) -> Option<u32> {
+    finesse_table(block.kind).get(&Landing::of(block)).copied()
+}
+
+fn search_inputs(kind: BlockKind) -> FinesseTable {
+    search(&Board::new(), Block::new(kind), usize::MAX)
+        .into_iter()
+        .map(|hint| (Landing::of(&hint.landed), hint.inputs))
+        .collect()
+}
+
+/// A spot the current piece can be dropped on, and the fewest inputs that get it there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlacementHint {
+    /// The piece where it lands.
+    pub landed: Block,
+    pub inputs: u32,
+}
+
+/// Every landing `block` can reach on `board` from where it is, up to `MAX_PLACEMENT_HINTS`
+/// of the cheapest.
+pub fn placement_hints(board: &Board, block: &Block) -> Vec<PlacementHint> {
+    search(board, *block, MAX_PLACEMENT_HINTS)
+}
+
+/// The hints for the last piece and board asked about, searched again only when either
+/// changes.
+#[derive(Debug, Default)]
+pub struct PlacementHintCache {
+    key: Option<(Block, u64)>,
+    hints: Vec<PlacementHint>,
+}
+
+impl PlacementHintCache {
+    pub fn hints(&mut self, board: &Board, block: &Block) -> &[PlacementHint] {
+        let key = Some((*block, occupancy_hash(board)));
+        if self.key != key {
+            self.hints = placement_hints(board, block);
+            self.key = key;
+        }
+        &self.hints
+    }
+}
+
+// Placements only depend on which cells are filled
+pub(super) fn occupancy_hash(board: &Board) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for row in 0..BOARD_HEIGHT {
+        board.row_mask(row).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+// Searches taps, holds to the wall and rotations from `start` at its height, stopping once
+// `limit` landings are found. Hints come out cheapest first.
+fn search(board: &Board, start: Block, limit: usize) -> Vec<PlacementHint> {
+    let mut hints = Vec::new();
+    let mut landings = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([(start, 0)]);
+    while let Some((block, inputs)) = queue.pop_front() {
+        if hints.len() >= limit {
+            break;
+        }
+        if !seen.insert((block.x, block.rotation)) {
+            continue;
+        }
+        // Breadth first, so the first visit to a landing is the cheapest
+        if landings.insert(Landing::of(&block)) {
+            let landed = Block {
+                y: board.drop_row(&block),
+                ..block
+            };
+            hints.push(PlacementHint { landed, inputs });
+        }
+
+        let mut moves = vec![rotated(board, block)];
+        for dx in [-1, 1] {
+            let tap = shifted(board, block, dx);
+            moves.push(tap);
+            // Holding the key slides the piece to the wall
+            let mut wall = tap;
+            while let Some(next) = wall.and_then(|wall| shifted(board, wall, dx)) {
+                wall = Some(next);
+            }
+            moves.push(wall);
+        }
+        queue.extend(moves.into_iter().flatten().map(|next| (next, inputs + 1)));
+    }
+    hints
+}
+
+fn shifted(board: &Board, block: Block, dx: i32) -> Option<Block> {
+    let moved = Block {
+        x: block.x + dx,
+        ..block
+    };
+    board.is_valid_position(&moved).then_some(moved)
+}
+
+// Rotation with the game's kicks: in place, then one column left, then one right
+fn rotated(board: &Board, block: Block) -> Option<Block> {
+    let mut turned = block;
+    turned.rotate();
+    [0, -1, 1]
+        .into_iter()
+        .map(|dx| Block {
+            x: turned.x + dx,
+            ..turned
+        })
+        .find(|candidate| board.is_valid_position(candidate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::{Action, Cell, CellContent, Game, GameEvent, GameMode, PracticeSetup};
+
+    fn at(kind: BlockKind, x: i32, rotation: u8) -> Block {
+        Block {
+            kind,
+            x,
+            y: 10,
+            rotation,
+        }
+    }
+
+    #[test]
+    fn o_piece_columns() {
+        // Spawned over columns 4 and 5: taps nearby, a hold to either wall, and a tap back
+        // off the wall for the columns next to it
+        let expected = [1, 2, 2, 1, 0, 1, 2, 2, 1];
+        for (left, &inputs) in expected.iter().enumerate() {
+            let block = at(BlockKind::O, left as i32, 0);
+            assert_eq!(optimal_inputs(&block), Some(inputs), "O at column {}", left);
+        }
+    }
+
+    #[test]
+    fn rotations_that_look_alike_share_a_placement() {
+        // Both flat I orientations cover columns 3 to 6 when dropped from spawn
+        let flat = Block::new(BlockKind::I);
+        let mut flipped = flat;
+        flipped.rotation = 2;
+        flipped.x += 1;
+        assert_eq!(Landing::of(&flat), Landing::of(&flipped));
+        assert_eq!(optimal_inputs(&flipped), Some(0));
+        // Upright against either wall is a rotation and a hold
+        assert_eq!(optimal_inputs(&at(BlockKind::I, 0, 1)), Some(2));
+        assert_eq!(optimal_inputs(&at(BlockKind::I, 9, 1)), Some(2));
+    }
+
+    #[test]
+    fn every_piece_reaches_every_column() {
+        for kind in BlockKind::ALL {
+            let table = finesse_table(kind);
+            let columns: HashSet<i32> = table.keys().map(|landing| landing.left).collect();
+            // Upright, an I is one column wide and every other piece two
+            let narrowest = if kind == BlockKind::I { 1 } else { 2 };
+            assert_eq!(columns.len(), 10 + 1 - narrowest, "{:?}", kind);
+            assert!(table.values().all(|&inputs| inputs <= 5), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn placement_hints_match_the_finesse_counts() {
+        for kind in BlockKind::ALL {
+            let hints = placement_hints(&Board::new(), &Block::new(kind));
+            assert_eq!(hints.len(), finesse_table(kind).len().min(MAX_PLACEMENT_HINTS));
+            for hint in &hints {
+                assert_eq!(optimal_inputs(&hint.landed), Some(hint.inputs), "{:?}", hint);
+                assert_eq!(hint.landed.blocks().iter().map(|&(_, y)| y).max(), Some(19));
+            }
+        }
+    }
+
+    #[test]
+    fn placement_hints_stop_at_walls_on_the_board() {
+        let mut board = Board::new();
+        for row in 0..BOARD_HEIGHT {
+            board.set_cell(row, 2, Cell::Filled(CellContent::Garbage));
+        }
+        let mut block = Block::new(BlockKind::O);
+        block.y = 5;
+        let mut cache = PlacementHintCache::default();
+        let hints = cache.hints(&board, &block).to_vec();
+        // Columns 3 to 9 fit six placements of an O, with column 3 a tap away
+        assert_eq!(hints.len(), 6);
+        let left = |hint: &PlacementHint| Landing::of(&hint.landed).left;
+        assert_eq!(hints.iter().map(left).min(), Some(3));
+        assert!(hints.iter().any(|hint| left(hint) == 3 && hint.inputs == 1));
+        assert_eq!(cache.hints(&board, &block), hints);
+    }
+
+    fn game_with(kind: BlockKind) -> Game {
+        let mut game = Game::with_seed(1);
+        game.start_practice(PracticeSetup {
+            queue: vec![kind],
+            ..PracticeSetup::default()
+        });
+        assert_eq!(game.mode, GameMode::Practice);
+        game
+    }
+
+    #[test]
+    fn holding_to_the_wall_then_dropping_is_two_inputs() {
+        let mut game = game_with(BlockKind::O);
+        game.press(Action::MoveLeft);
+        // Key repeats go through apply and don't count
+        while game.apply(Action::MoveLeft) {}
+        assert_eq!(game.finesse.inputs, 1);
+        game.hard_drop();
+        assert_eq!(game.finesse, Finesse { inputs: 0, faults: 0 });
+        assert!(!game
+            .drain_events()
+            .iter()
+            .any(|event| matches!(event, GameEvent::FinesseFault { .. })));
+    }
+
+    #[test]
+    fn tapping_to_the_wall_is_a_fault() {
+        let mut game = game_with(BlockKind::O);
+        for _ in 0..4 {
+            game.press(Action::MoveLeft);
+        }
+        game.hard_drop();
+        assert_eq!(game.finesse.faults, 1);
+        assert!(game.drain_events().contains(&GameEvent::FinesseFault {
+            inputs: 5,
+            optimal: 2
+        }));
+    }
+}
@@ -1,16 +1,247 @@
+use futures_util::FutureExt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 
-use super::{Block, BlockKind, Board};
-use crate::tetris::multiplayer::{GameMessage, MultiplayerClient};
+use super::{
+    optimal_inputs, Block, BlockKind, Board, CascadeStep, Cell, ClearResult, Finesse,
+    GarbageConfig, GarbageQueue, IncomingAttack, PracticeSetup, Randomizer, RandomizerDebug,
+    RandomizerKind, RowDrops, SaveState, Snapshot, Suggestion, SuggestionCache, Weights,
+    BOARD_HEIGHT, BOARD_WIDTH, GARBAGE_DELAY_PIECES, SAVE_STATE_SLOTS, UNDO_DEPTH,
+};
+use crate::tetris::multiplayer::{
+    interpolate_offset, reconcile_players, ConnectionStatus, ErrorCode, GameMessage,
+    MultiplayerClient, NetStats, PlayerInfo, PlayerStatus, RemoteBoard, RoomRules, Throttled,
+    MATCH_PAUSES, MATCH_PAUSE_LIMIT,
+};
+use crate::tetris::datagram::UDP_REFRESH_INTERVAL;
+use crate::tetris::results::{MatchResult, SessionTally};
+use crate::tetris::resume::RESUME_TOKEN_TTL;
+use crate::tetris::splits::Splits;
+use crate::tetris::stats::{ClearCounts, Heatmap, SessionStats};
+use crate::tetris::verify::{HeldKeys, InputLog, Step};
 
-pub const INITIAL_FALL_INTERVAL: Duration = Duration::from_millis(800);
-pub const SOFT_DROP_FACTOR: f32 = 0.05;
+pub const INITIAL_FALL_INTERVAL: Duration = Duration::from_secs(1);
+pub const LOCK_DELAY: Duration = Duration::from_millis(500);
+// Shifts and rotations that restart the lock delay of one piece in 20G
+pub const MAX_LOCK_RESETS: u32 = 15;
+
+// Soft drop moves this many times faster than gravity, within the limits below
+pub const DEFAULT_SOFT_DROP_FACTOR: f32 = 20.0;
+pub const SOFT_DROP_MIN_INTERVAL: Duration = Duration::from_micros(16_667); // one frame at 60 FPS
+pub const SOFT_DROP_MAX_INTERVAL: Duration = Duration::from_millis(50);
 pub const SHAKE_DURATION: Duration = Duration::from_millis(300);
 pub const SHAKE_INTENSITY_PER_LINE: f32 = 3.0;
 
-// Level speed factors (each level will be this much faster than the previous)
-pub const LEVEL_SPEED_FACTOR: f32 = 0.8; // 20% faster each level
+// Gravity follows the guideline curve up to this level and stays flat afterwards
+pub const MAX_GRAVITY_LEVEL: u32 = 20;
+pub const MAX_GRAVITY: f32 = 20.0 * 60.0; // 20G: twenty rows per frame at 60 FPS
+// Highest level a marathon can start at, as in guideline games
+pub const MAX_START_LEVEL: u32 = 15;
+
+/// Guideline gravity in rows per second: (0.8 - (level - 1) * 0.007) ^ (level - 1) seconds per row.
+pub fn gravity_rows_per_sec(level: u32) -> f32 {
+    let level = level.clamp(1, MAX_GRAVITY_LEVEL) as f32;
+    let seconds_per_row = (0.8 - (level - 1.0) * 0.007).powf(level - 1.0);
+    (1.0 / seconds_per_row).min(MAX_GRAVITY)
+}
+
+// Completed rows stay on screen this long before they're removed
+pub const LINE_CLEAR_DELAY: Duration = Duration::from_millis(150);
+// Then the rows above take this long to slide down into the gap
+pub const COLLAPSE_DURATION: Duration = Duration::from_millis(100);
+// Cascade mode pauses on each chain step so the falling groups can be seen
+pub const CASCADE_STEP_DELAY: Duration = Duration::from_millis(300);
+// Extra points per chain step in cascade mode, times the chain step and the level
+pub const CHAIN_BONUS: u32 = 200;
+
+// Events kept for a consumer that has stopped draining them are dropped past this many
+pub const MAX_PENDING_EVENTS: usize = 256;
+
+// Entry delay (ARE) between a lock and the next spawn, and the extra wait after a line clear
+pub const MAX_ENTRY_DELAY: Duration = Duration::from_millis(500);
+pub const LINE_CLEAR_ENTRY_DELAY: Duration = Duration::from_millis(200);
+
+// Resuming from a pause counts down this long first, so pausing can't be used to plan
+pub const RESUME_COUNTDOWN: Duration = Duration::from_secs(3);
+
+// Pieces the double hold variant keeps; the usual hold keeps one
+pub const MAX_HOLD_SLOTS: usize = 2;
+
+// Classic mode follows the NES: its frame rate, gravity table and line clear scores
+pub const NES_FPS: f32 = 60.0988;
+pub const CLASSIC_MAX_START_LEVEL: u32 = 19;
+pub const CLASSIC_LINE_SCORES: [u32; 5] = [0, 40, 100, 300, 1200];
+
+/// Frames the NES takes to move a piece down one row at `level`.
+pub fn nes_frames_per_row(level: u32) -> u32 {
+    match level {
+        0..=8 => 48 - level * 5,
+        9 => 6,
+        10..=12 => 5,
+        13..=15 => 4,
+        16..=18 => 3,
+        19..=28 => 2,
+        _ => 1,
+    }
+}
+
+/// Level reached after `lines` when starting at `start_level`, using the NES rule that the
+/// first level up waits longer when starting high.
+pub fn classic_level(start_level: u32, lines: u32) -> u32 {
+    let first_threshold =
+        (start_level * 10 + 10).min((start_level * 10).saturating_sub(50).max(100));
+    if lines < first_threshold {
+        start_level
+    } else {
+        start_level + 1 + (lines - first_threshold) / 10
+    }
+}
+
+/// How many lines each level takes outside classic mode.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Leveling {
+    /// 10 lines a level.
+    #[default]
+    Fixed,
+    /// The guideline's variable goal: 5 lines times the level being cleared.
+    Variable,
+}
+
+impl Leveling {
+    /// Level reached after `lines` when starting at `start_level`.
+    pub fn level(self, start_level: u32, lines: u32) -> u32 {
+        match self {
+            Leveling::Fixed => start_level + lines / 10,
+            Leveling::Variable => {
+                let mut level = start_level.max(1);
+                let mut remaining = lines;
+                while remaining >= 5 * level {
+                    remaining -= 5 * level;
+                    level += 1;
+                }
+                level
+            }
+        }
+    }
+}
+
+// Most often our score and full board are shared with other players; unchanged ones aren't
+pub const GAME_STATE_INTERVAL: Duration = Duration::from_millis(250);
+pub const BOARD_STATE_INTERVAL: Duration = Duration::from_millis(100);
+// Wait between attempts to get back to the server after the connection drops
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(3);
+
+// Garbage lines sent for clearing 0, 1, 2, 3 or 4 lines at once
+pub const ATTACK_TABLE: [u32; 5] = [0, 0, 1, 2, 4];
+// Extra lines for a clear by how many clears came right before it; longer combos get
+// the last
+pub const COMBO_ATTACK_TABLE: [u32; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+// Extra line for a tetris or T-spin clear following another
+pub const BACK_TO_BACK_ATTACK: u32 = 1;
+// Garbage a perfect clear sends at the least, unless a room's rules say otherwise
+pub const PERFECT_CLEAR_ATTACK: u32 = 10;
+pub const SPRINT_LINES: u32 = 40;
+
+// Each KO badge adds a quarter to a player's attacks, up to double with this many
+pub const MAX_KO_BADGE_BONUS: u32 = 4;
+
+pub fn attack_for_lines(lines_cleared: u32) -> u32 {
+    ATTACK_TABLE[(lines_cleared as usize).min(ATTACK_TABLE.len() - 1)]
+}
+
+/// Garbage a locked piece's clear is worth, before any KO badge bonus. `combo` counts the
+/// clears in a row before this one, and `b2b` is whether this is a tetris or T-spin clear
+/// straight after another. T-spins send 2 per line, and minis one less than they clear.
+pub fn attack_for(clear: &LockResult, combo: i32, b2b: bool) -> u32 {
+    let lines = clear.lines_cleared;
+    if lines == 0 {
+        return 0;
+    }
+    let base = match clear.t_spin {
+        TSpinKind::None => attack_for_lines(lines),
+        TSpinKind::Mini => lines - 1,
+        TSpinKind::Full => lines * 2,
+    };
+    let combo = (combo.max(0) as usize).min(COMBO_ATTACK_TABLE.len() - 1);
+    let difficult = lines >= 4 || clear.t_spin != TSpinKind::None;
+    let back_to_back = if b2b && difficult { BACK_TO_BACK_ATTACK } else { 0 };
+    base + COMBO_ATTACK_TABLE[combo] + back_to_back
+}
+
+/// The most a clear of `lines_cleared` lines can be worth, with the longest combo and a
+/// back-to-back. T-spins clear 3 lines at most.
+pub fn max_attack_for_lines(lines_cleared: u32) -> u32 {
+    let longest = COMBO_ATTACK_TABLE.len() as i32 - 1;
+    [TSpinKind::None, TSpinKind::Mini, TSpinKind::Full]
+        .into_iter()
+        .filter(|&t_spin| t_spin == TSpinKind::None || lines_cleared <= 3)
+        .map(|t_spin| {
+            let clear = LockResult {
+                lines_cleared,
+                t_spin,
+                ..LockResult::default()
+            };
+            attack_for(&clear, longest, true)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// `attack` boosted for a player holding `ko_badges`.
+pub fn with_badges(attack: u32, ko_badges: u32) -> u32 {
+    let bonus = ko_badges.min(MAX_KO_BADGE_BONUS);
+    attack * (MAX_KO_BADGE_BONUS + bonus) / MAX_KO_BADGE_BONUS
+}
+
+/// Garbage sent for clearing `lines_cleared` lines by a player holding `ko_badges`, going
+/// by lines alone.
+pub fn attack_with_badges(lines_cleared: u32, ko_badges: u32) -> u32 {
+    with_badges(attack_for_lines(lines_cleared), ko_badges)
+}
+
+/// Garbage a `LineCleared` of `count` lines brings from a player holding `ko_badges`: what
+/// it says was `sent` after cancelling, but never more than a clear of that many lines can
+/// be worth, or than `perfect_clear`, the room's perfect clear attack, for one of those.
+/// Clients from before cancelling leave `sent` out and send the lines' worth.
+pub fn sent_attack(
+    count: i32,
+    sent: Option<u32>,
+    ko_badges: u32,
+    perfect_clear: Option<u32>,
+) -> u32 {
+    let lines = count.max(0) as u32;
+    let most = max_attack_for_lines(lines).max(perfect_clear.unwrap_or(0));
+    let most = with_badges(most, ko_badges);
+    sent.map_or(attack_with_badges(lines, ko_badges), |sent| sent.min(most))
+}
+
+/// Tries to get back to the server with our resume token until it gets through, or the
+/// server will have given up our place.
+async fn reconnect(server_addr: String, resume_token: String) -> Option<MultiplayerClient> {
+    let deadline = Instant::now() + RESUME_TOKEN_TTL;
+    while Instant::now() < deadline {
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+        let token = Some(resume_token.clone());
+        if let Ok(client) = MultiplayerClient::connect_resuming(&server_addr, token).await {
+            return Some(client);
+        }
+    }
+    None
+}
+
+// Rate of `count` over `time`, or 0 before any time has passed
+fn per_second(count: u32, time: Duration) -> f32 {
+    let seconds = time.as_secs_f32();
+    if seconds > 0.0 {
+        count as f32 / seconds
+    } else {
+        0.0
+    }
+}
 
 pub struct ScreenShake {
     pub intensity: f32,
@@ -56,13 +287,198 @@ impl ScreenShake {
     }
 }
 
+/// Rows sliding down into place after a line clear. The board already has them where
+/// they end up, so this only changes how it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collapse {
+    drops: RowDrops,
+    elapsed: Duration,
+}
+
+impl Collapse {
+    /// How many rows above its place `row` is still drawn.
+    pub fn lift(&self, row: usize) -> f32 {
+        let left = 1.0 - self.elapsed.as_secs_f32() / COLLAPSE_DURATION.as_secs_f32();
+        self.drops.get(row).map_or(0.0, |&drop| drop as f32 * left.max(0.0))
+    }
+}
+
+/// What a player can do to the falling piece, from a key press or a bot.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    SonicDrop,
+    RotateCw,
+    HardDrop,
+    Hold,
+}
+
+/// What an attack was sent for, so whoever it's headed for can be warned accordingly.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum AttackKind {
+    #[default]
+    Normal,
+    /// A clear that left the board empty.
+    PerfectClear,
+}
+
+impl AttackKind {
+    pub fn is_normal(&self) -> bool {
+        *self == AttackKind::Normal
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum TSpinKind {
+    #[default]
+    None,
+    Mini,
+    Full,
+}
+
+/// Everything that happened when a piece locked, for driving sounds, scoring and attacks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockResult {
+    pub locked: bool,
+    pub lines_cleared: u32,
+    pub cleared_rows: Vec<usize>,
+    pub drop_distance: u32,
+    pub t_spin: TSpinKind,
+    /// Clears in a cascade, counting the first one; 2 or more is a chain.
+    pub chain: u32,
+    /// Garbage the clear was worth with combo, back-to-back and KO badges, before it
+    /// cancelled any attacks on their way in.
+    pub attack: u32,
+    /// The clear left the board empty.
+    pub perfect_clear: bool,
+    pub game_over: bool,
+}
+
+/// Things that happened during play, for consumers such as missions that need more than
+/// the score.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GameEvent {
+    PieceLocked {
+        kind: BlockKind,
+        lines_cleared: u32,
+        cleared_rows: Vec<usize>,
+        t_spin: TSpinKind,
+    },
+    /// The current piece was hard dropped; its `PieceLocked` follows.
+    HardDropped,
+    /// The current piece went into the hold slot.
+    Held,
+    /// A hard drop took more inputs than the placement needed.
+    FinesseFault { inputs: u32, optimal: u32 },
+    /// Garbage rose up from the bottom of the board.
+    GarbageReceived { lines: u32 },
+    /// Another player's attack is on its way, landing as one of our pieces locks once
+    /// `GameConfig::garbage_delay_pieces` more have.
+    AttackIncoming {
+        id: u64,
+        from: String,
+        lines: u32,
+        kind: AttackKind,
+    },
+    /// A clear of ours left the board empty.
+    PerfectClear,
+    /// Our own clear cancelled `lines` of a pending attack, leaving `remaining`.
+    AttackCancelled { id: u64, lines: u32, remaining: u32 },
+    ToppedOut,
+    /// A multiplayer match we played in ended; its placements are in `match_result`.
+    MatchFinished,
+}
+
+/// The rules a game is played by. Serialized by name, e.g. `"cheese_race"`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum GameMode {
+    #[default]
+    Marathon,
+    /// Drill a fixed setup: scripted piece queue, instant reset and undo.
+    Practice,
+    /// Clear a set number of garbage lines as fast as possible.
+    CheeseRace,
+    /// NES rules: no hold, ghost or wall kicks, NES scoring and gravity, no lock delay.
+    Classic,
+    /// Sticky gravity: after a clear, pieces fall as connected groups and can chain clears.
+    Cascade,
+    /// Work through objectives from the mission list, each on its own starting board.
+    Mission,
+    /// Clear `SPRINT_LINES` lines as fast as possible.
+    Sprint,
+    /// Guided steps that teach the controls, each on its own setup.
+    Tutorial,
+}
+
+impl GameMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMode::Marathon => "Marathon",
+            GameMode::Practice => "Practice",
+            GameMode::CheeseRace => "Cheese Race",
+            GameMode::Classic => "Classic",
+            GameMode::Cascade => "Cascade",
+            GameMode::Mission => "Mission",
+            GameMode::Sprint => "Sprint",
+            GameMode::Tutorial => "Tutorial",
+        }
+    }
+}
+
+/// What is happening within a game that is Playing.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Phase {
+    /// The current piece is falling and takes input.
+    #[default]
+    Active,
+    /// A line clear is being animated and the next piece waits.
+    Clearing,
+    /// Entry delay: the next piece spawns once `remaining` runs out.
+    Entry { remaining: Duration },
+}
+
+/// Actions received while the piece isn't controllable, applied as soon as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct InputBuffer {
+    rotate: bool,
+    // Latest horizontal direction: -1, 0 or 1
+    direction: i32,
+    hold: bool,
+    hard_drop: bool,
+}
+
+impl InputBuffer {
+    fn push(&mut self, action: Action) {
+        match action {
+            Action::MoveLeft => self.direction = -1,
+            Action::MoveRight => self.direction = 1,
+            Action::RotateCw => self.rotate = true,
+            Action::Hold => self.hold = true,
+            Action::HardDrop => self.hard_drop = true,
+            // Drops only make sense for a piece that is already falling
+            Action::SoftDrop | Action::SonicDrop => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum GameState {
     Playing,
     Paused,
     GameOver,
+    /// A mode with a goal, such as cheese race, was completed.
+    Finished,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Score {
     pub points: u32,
     pub lines: u32,
@@ -79,18 +495,48 @@ impl Default for Score {
     }
 }
 
+/// Where a game's points came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScoreSources {
+    pub clears: u32,
+    pub t_spins: u32,
+    /// Cascade's bonus for each clear after the first in a chain.
+    pub chains: u32,
+}
+
+impl ScoreSources {
+    /// Counts the points for a clear, as a T-spin if it was one.
+    pub fn add_clear(&mut self, points: u32, t_spin: TSpinKind) {
+        match t_spin {
+            TSpinKind::None => self.clears += points,
+            TSpinKind::Mini | TSpinKind::Full => self.t_spins += points,
+        }
+    }
+}
+
 pub struct GameTimer {
     pub fall_interval: Duration,
-    pub last_fall: Instant,
+    pub last_update: Instant,
     pub soft_drop: bool,
+    // Fraction of a row the piece has fallen since it last moved down
+    pub gravity_progress: f32,
+    pub lock_elapsed: Option<Duration>,
 }
 
 impl GameTimer {
-    pub fn get_fall_interval(&self, level: u32) -> Duration {
-        // Calculate speed based on level
-        let speed_factor = LEVEL_SPEED_FACTOR.powi(level as i32 - 1);
-        let interval = INITIAL_FALL_INTERVAL.as_secs_f32() * speed_factor;
-        Duration::from_secs_f32(interval)
+    pub fn get_fall_interval(&self, level: u32, mode: GameMode) -> Duration {
+        match mode {
+            GameMode::Classic => {
+                Duration::from_secs_f32(nes_frames_per_row(level) as f32 / NES_FPS)
+            }
+            _ => Duration::from_secs_f32(1.0 / gravity_rows_per_sec(level)),
+        }
+    }
+
+    pub fn get_soft_drop_interval(&self, soft_drop_factor: f32) -> Duration {
+        self.fall_interval
+            .div_f32(soft_drop_factor)
+            .clamp(SOFT_DROP_MIN_INTERVAL, SOFT_DROP_MAX_INTERVAL)
     }
 }
 
@@ -98,56 +544,509 @@ impl Default for GameTimer {
     fn default() -> Self {
         Self {
             fall_interval: INITIAL_FALL_INTERVAL,
-            last_fall: Instant::now(),
+            last_update: Instant::now(),
             soft_drop: false,
+            gravity_progress: 0.0,
+            lock_elapsed: None,
+        }
+    }
+}
+
+/// Game time a debug frame step advances by: one frame at 60 FPS, two simulation steps.
+pub const DEBUG_FRAME: Duration = Duration::from_nanos(2 * FIXED_DT.as_nanos() as u64);
+
+/// Debug control over how fast game time passes relative to real time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameSpeed {
+    pub scale: f32,
+    /// Debug pause: game time only moves on by frame steps.
+    pub frozen: bool,
+    pending_frames: u32,
+}
+
+impl Default for GameSpeed {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            frozen: false,
+            pending_frames: 0,
+        }
+    }
+}
+
+impl GameSpeed {
+    /// Whether game time runs at anything other than real time.
+    pub fn is_scaled(&self) -> bool {
+        self.frozen || self.scale != 1.0
+    }
+
+    /// Game time for `real` time passed. While frozen, that's the frame steps asked for
+    /// since the last call.
+    pub fn game_time(&mut self, real: Duration) -> Duration {
+        if self.frozen {
+            DEBUG_FRAME * std::mem::take(&mut self.pending_frames)
+        } else {
+            real.mul_f32(self.scale)
+        }
+    }
+}
+
+/// Game time each simulation step advances by: about 120 steps a second, whatever the
+/// frame rate.
+pub const FIXED_DT: Duration = Duration::from_micros(8_333);
+// Most steps one frame catches up on. Time beyond them, after a stall such as dragging the
+// window, is dropped rather than played all at once
+pub const MAX_STEPS_PER_FRAME: u32 = 30;
+
+/// Turns frames of any length into whole simulation steps of `FIXED_DT`, carrying what's
+/// left over into the next frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FixedStep {
+    pending: Duration,
+}
+
+impl FixedStep {
+    /// Adds a frame of `dt` game time, returning how many steps are due.
+    pub fn advance(&mut self, dt: Duration) -> u32 {
+        self.pending += dt;
+        let due = (self.pending.as_nanos() / FIXED_DT.as_nanos()) as u32;
+        let steps = due.min(MAX_STEPS_PER_FRAME);
+        self.pending = if due > steps {
+            Duration::ZERO
+        } else {
+            self.pending - FIXED_DT * steps
+        };
+        steps
+    }
+
+    /// How far into the next step the time left over is, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        self.pending.as_secs_f32() / FIXED_DT.as_secs_f32()
+    }
+}
+
+// Mixed into the seed so garbage holes don't share a stream with the piece sequence
+const GARBAGE_SEED_SALT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Cheese race: dig through `target_lines` of garbage, kept topped up to `rows` high.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheeseConfig {
+    pub rows: u32,
+    pub target_lines: u32,
+    pub garbage: GarbageConfig,
+}
+
+impl Default for CheeseConfig {
+    fn default() -> Self {
+        Self {
+            rows: 10,
+            target_lines: 18,
+            // Every row gets its own hole, as in classic cheese
+            garbage: GarbageConfig {
+                messiness: 1.0,
+                hole_repeat: false,
+            },
+        }
+    }
+}
+
+/// Tuning for a game: garbage, drop speed, the start level and the like. `Default` plays
+/// by the standard rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameConfig {
+    pub garbage: GarbageConfig,
+    pub soft_drop_factor: f32,
+    pub cheese: CheeseConfig,
+    // Level picked at the start of a game: 0 to CLASSIC_MAX_START_LEVEL in classic, 1 to
+    // MAX_START_LEVEL in marathon, where 0 also starts at 1. Other modes start at 1
+    pub start_level: u32,
+    /// Lines per level outside classic mode.
+    pub leveling: Leveling,
+    /// Initial hold and rotation: hold or rotate keys held when a piece spawns act on it
+    /// straight away.
+    pub initial_actions: bool,
+    /// Wait between a lock and the next spawn, up to MAX_ENTRY_DELAY. Zero spawns at once.
+    pub entry_delay: Duration,
+    /// 20G: pieces drop to the stack as they spawn and after every move, so play happens
+    /// within lock delay. Shifts and rotations restart it up to MAX_LOCK_RESETS times.
+    pub instant_gravity: bool,
+    /// Where pieces come from, from the next game on.
+    pub randomizer: RandomizerKind,
+    /// How many pieces the hold keeps, up to MAX_HOLD_SLOTS. 0 turns hold off.
+    pub hold_slots: usize,
+    /// How long a grounded piece waits before it locks.
+    pub lock_delay: Duration,
+    /// Garbage a perfect clear sends at the least.
+    pub perfect_clear_attack: u32,
+    /// Pieces an attack waits out after the one it arrived during. It lands as the next
+    /// piece after those locks, so 0 lands it under the piece it arrived during.
+    pub garbage_delay_pieces: u32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            garbage: GarbageConfig::default(),
+            soft_drop_factor: DEFAULT_SOFT_DROP_FACTOR,
+            cheese: CheeseConfig::default(),
+            start_level: 0,
+            leveling: Leveling::Fixed,
+            initial_actions: false,
+            entry_delay: Duration::ZERO,
+            instant_gravity: false,
+            randomizer: RandomizerKind::default(),
+            hold_slots: 1,
+            lock_delay: LOCK_DELAY,
+            perfect_clear_attack: PERFECT_CLEAR_ATTACK,
+            garbage_delay_pieces: GARBAGE_DELAY_PIECES,
+        }
+    }
+}
+
+/// Held pieces in the order they went in. Holding puts the current piece at the back and,
+/// once every slot is taken, brings the oldest back out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HoldQueue {
+    blocks: [Option<Block>; MAX_HOLD_SLOTS],
+}
+
+impl HoldQueue {
+    /// The held pieces, oldest first.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks[0].is_none()
+    }
+
+    /// The piece the next hold brings out with `slots` slots, None while one is free.
+    pub fn next_out(&self, slots: usize) -> Option<&Block> {
+        self.blocks[0].as_ref().filter(|_| self.len() >= slots)
+    }
+
+    /// Puts `block` at the back, returning the oldest piece if all `slots` were taken.
+    pub fn push(&mut self, block: Block, slots: usize) -> Option<Block> {
+        let mut out = None;
+        if self.len() >= slots.clamp(1, MAX_HOLD_SLOTS) {
+            out = self.blocks[0].take();
+            self.blocks.rotate_left(1);
         }
+        let len = self.len();
+        self.blocks[len] = Some(block);
+        out
     }
 }
 
+/// One player's game: the board, the pieces, the score and, when online, the connection.
+/// Drive it with `apply` and `tick`, and read what happened from `drain_events`.
 pub struct Game {
+    pub config: GameConfig,
+    pub mode: GameMode,
+    pub practice: PracticeSetup,
+    queue_index: usize,
+    undo_stack: VecDeque<Snapshot>,
+    // Practice save states, oldest first, and how many loads back the next load goes
+    save_states: VecDeque<SaveState>,
+    save_state_cursor: usize,
+    pub seed: u64,
+    randomizer: Box<dyn Randomizer>,
+    garbage_rng: StdRng,
     pub board: Board,
     pub current_block: Block,
     pub next_block: Block,
-    pub hold_block: Option<Block>,
+    pub hold: HoldQueue,
+    /// Whether the current piece came out of a hold, which only goes once per piece.
     pub has_held: bool,
+    // Lock delay restarts the current piece has used up in 20G
+    lock_resets: u32,
     pub state: GameState,
+    pub phase: Phase,
+    input_buffer: InputBuffer,
     pub score: Score,
     pub timer: GameTimer,
     pub screen_shake: ScreenShake,
-    pub lines_just_cleared: bool,
+    // Whether the last successful movement of the current piece was a rotation
+    pub last_move_rotation: bool,
+    // Hold and rotate keys currently held, for initial hold and rotation on spawn
+    pub hold_requested: bool,
+    pub rotation_requested: bool,
+    pub outgoing_garbage: u32,
+    pub pieces_placed: u32,
+    pub garbage_cleared: u32,
+    /// Garbage lines sent to opponents this game.
+    pub lines_sent: u32,
+    /// Garbage lines received from opponents this game.
+    pub lines_received: u32,
+    /// Clears this game that left the board empty.
+    pub perfect_clears: u32,
+    /// Opponents who topped out to our attack this match.
+    pub ko_badges: u32,
+    /// Pieces in a row that cleared lines, and tetrises or T-spin clears in a row.
+    pub combo: u32,
+    pub best_combo: u32,
+    pub back_to_back: u32,
+    pub clears: ClearCounts,
+    pub score_sources: ScoreSources,
+    pub heatmap: Heatmap,
+    /// Pieces dealt into play over every game this session, and the current I drought.
+    pub session_stats: SessionStats,
+    /// When a sprint reached each split.
+    pub splits: Splits,
+    pub finesse: Finesse,
+    /// Debug slow motion and frame stepping; kept at real time in multiplayer.
+    pub speed: GameSpeed,
+    clock: FixedStep,
+    // Pieces placed and the falling piece before the latest step, to draw it moving
+    // smoothly between steps
+    last_step_piece: (u32, Block),
+    events: VecDeque<GameEvent>,
+    // Boards of a line clear still being shown, oldest first: the completed rows, then
+    // each intermediate step of a cascade
+    clear_frames: VecDeque<Board>,
+    // The rows above the last line clear sliding down, once its frames are done
+    collapse: Option<Collapse>,
+    clear_elapsed: Duration,
+    // Time spent playing, excluding pauses
+    pub play_time: Duration,
     pub player_id: Option<String>,
-    pub other_players: HashMap<String, i32>,
+    /// Name shown to other players instead of our id.
+    pub player_name: Option<String>,
+    pub other_players: HashMap<String, PlayerInfo>,
+    /// Whether the server reports enough players for a match.
+    pub match_active: bool,
+    /// Our accent color from the server, which other players' colors steer clear of.
+    pub accent: Option<u32>,
+    pub other_boards: HashMap<String, RemoteBoard>,
+    /// Boards from other players turned away as malformed, for the debug readout.
+    pub rejected_boards: u32,
+    pub paused_players: HashSet<String>,
+    pub forfeited_players: HashSet<String>,
+    // Players who have topped out, and when each last sent us garbage
+    pub out_players: HashSet<String>,
+    pub attacked_at: HashMap<String, Instant>,
+    game_state_sender: Throttled<GameMessage>,
+    board_state_sender: Throttled<GameMessage>,
+    // Multiplayer pauses taken this match, and when the current one started
+    pauses_used: u32,
+    paused_at: Option<Instant>,
+    // Time left before a pause that's been ended actually resumes
+    resume_countdown: Option<Duration>,
+    // Whether the current pause is one the game took itself, the player having gone idle
+    idle_paused: bool,
+    /// Whether the player has been idle long enough to show as away to other players.
+    pub afk: bool,
+    // Assist mode shows where the evaluator would put each piece. Having it on at any
+    // point in a game keeps that game off the records.
+    assist: bool,
+    assisted: bool,
+    suggestions: SuggestionCache,
+    // A NewGame to announce once the server has told us our id
+    new_game_pending: bool,
+    // Attacks from other players waiting to land, which hold still through a pause
+    garbage_queue: GarbageQueue,
+    // Hole column of the last attack received, so the next one can pick another
+    last_garbage_hole: Option<usize>,
     pub multiplayer: Option<MultiplayerClient>,
+    // Where we connected, and the token that gets our place back if the connection drops
+    server_addr: Option<String>,
+    resume_token: Option<String>,
+    // Keeps trying to reconnect while the server still holds our place
+    reconnecting: Option<tokio::task::JoinHandle<Option<MultiplayerClient>>>,
+    /// Set when the server went away mid-game and play carried on offline.
+    pub connection_lost: bool,
+    /// Room the server last placed us in, and whether it needs a password.
+    pub room: Option<String>,
+    pub room_locked: bool,
+    /// The rules of our room, which we play by from the next game on.
+    pub room_rules: RoomRules,
+    // Our own config while `config` holds a match's, built from it and the room's rules
+    local_config: Option<GameConfig>,
+    /// The last room request the server turned down.
+    pub room_error: Option<ErrorCode>,
+    /// Latest announcement from the server, and when it arrived.
+    pub server_message: Option<(String, Instant)>,
+    /// Placements from the last match we played in, until the next game starts.
+    pub match_result: Option<MatchResult>,
+    /// Our record over this stay in the room, kept across rematches.
+    pub session: SessionTally,
+    /// Our place in the matchmaking queue, and when we joined it.
+    pub queue_position: Option<usize>,
+    pub queued_at: Option<Instant>,
+    /// Player whose board is shown full size in place of our own.
+    pub spectating: Option<String>,
+    // Every input and frame of a sprint, for sending the finished run to the server
+    input_log: Option<InputLog>,
 }
 
 impl Default for Game {
     fn default() -> Self {
+        Self::with_seed(rand::random())
+    }
+}
+
+impl Game {
+    pub fn with_seed(seed: u64) -> Self {
+        let mut randomizer = RandomizerKind::default().randomizer(seed);
+        let current_block = Block::spawn(randomizer.next(), BOARD_WIDTH);
+        let next_block = Block::spawn(randomizer.next(), BOARD_WIDTH);
         Self {
+            config: GameConfig::default(),
+            mode: GameMode::default(),
+            practice: PracticeSetup::default(),
+            queue_index: 0,
+            undo_stack: VecDeque::new(),
+            save_states: VecDeque::new(),
+            save_state_cursor: 0,
+            seed,
+            randomizer,
+            garbage_rng: StdRng::seed_from_u64(seed ^ GARBAGE_SEED_SALT),
             board: Board::new(),
-            current_block: Block::new(BlockKind::random()),
-            next_block: Block::new(BlockKind::random()),
-            hold_block: None,
+            current_block,
+            next_block,
+            hold: HoldQueue::default(),
             has_held: false,
+            lock_resets: 0,
             state: GameState::Playing,
+            phase: Phase::Active,
+            input_buffer: InputBuffer::default(),
             score: Score::default(),
             timer: GameTimer::default(),
             screen_shake: ScreenShake::default(),
-            lines_just_cleared: false,
+            last_move_rotation: false,
+            hold_requested: false,
+            rotation_requested: false,
+            outgoing_garbage: 0,
+            pieces_placed: 0,
+            garbage_cleared: 0,
+            lines_sent: 0,
+            lines_received: 0,
+            perfect_clears: 0,
+            ko_badges: 0,
+            combo: 0,
+            best_combo: 0,
+            back_to_back: 0,
+            clears: ClearCounts::default(),
+            score_sources: ScoreSources::default(),
+            heatmap: Heatmap::default(),
+            session_stats: SessionStats::default(),
+            splits: Splits::default(),
+            finesse: Finesse::default(),
+            speed: GameSpeed::default(),
+            clock: FixedStep::default(),
+            last_step_piece: (0, current_block),
+            events: VecDeque::new(),
+            clear_frames: VecDeque::new(),
+            collapse: None,
+            clear_elapsed: Duration::ZERO,
+            play_time: Duration::ZERO,
             player_id: None,
+            player_name: None,
             other_players: HashMap::new(),
+            match_active: false,
+            accent: None,
+            other_boards: HashMap::new(),
+            rejected_boards: 0,
+            paused_players: HashSet::new(),
+            forfeited_players: HashSet::new(),
+            out_players: HashSet::new(),
+            attacked_at: HashMap::new(),
+            game_state_sender: Throttled::new(GAME_STATE_INTERVAL),
+            board_state_sender: Throttled::new(BOARD_STATE_INTERVAL),
+            pauses_used: 0,
+            paused_at: None,
+            resume_countdown: None,
+            idle_paused: false,
+            afk: false,
+            assist: false,
+            assisted: false,
+            suggestions: SuggestionCache::default(),
+            new_game_pending: false,
+            garbage_queue: GarbageQueue::default(),
+            last_garbage_hole: None,
             multiplayer: None,
+            server_addr: None,
+            resume_token: None,
+            reconnecting: None,
+            connection_lost: false,
+            room: None,
+            room_locked: false,
+            room_rules: RoomRules::default(),
+            local_config: None,
+            room_error: None,
+            server_message: None,
+            match_result: None,
+            session: SessionTally::default(),
+            queue_position: None,
+            queued_at: None,
+            spectating: None,
+            input_log: None,
+        }
+    }
+
+    fn next_piece(&mut self) -> Block {
+        let queue = &self.practice.queue;
+        let scripted = matches!(
+            self.mode,
+            GameMode::Practice | GameMode::Mission | GameMode::Tutorial
+        );
+        if scripted && !queue.is_empty() {
+            // The practice queue loops once it runs out
+            let kind = queue[self.queue_index % queue.len()];
+            self.queue_index += 1;
+            return Block::spawn(kind, BOARD_WIDTH);
+        }
+        Block::spawn(self.randomizer.next(), BOARD_WIDTH)
+    }
+
+    fn is_playing(&self) -> bool {
+        self.state == GameState::Playing && self.phase == Phase::Active
+    }
+
+    /// Whether the current piece takes input right now. Actions passed to `apply` while it
+    /// doesn't are buffered for the next piece.
+    pub fn is_controllable(&self) -> bool {
+        self.is_playing()
+    }
+
+    /// The board to draw, which trails the real one while a line clear plays out.
+    pub fn display_board(&self) -> &Board {
+        self.clear_frames.front().unwrap_or(&self.board)
+    }
+
+    /// The rows sliding down after a line clear, once the cleared rows are gone.
+    pub fn collapse(&self) -> Option<&Collapse> {
+        self.collapse.as_ref().filter(|_| self.phase != Phase::Clearing)
+    }
+
+    /// Completed rows of the board on display, while they wait to be cleared.
+    pub fn clearing_rows(&self) -> Vec<usize> {
+        if self.phase == Phase::Clearing {
+            self.display_board().find_complete_lines()
+        } else {
+            Vec::new()
         }
     }
-}
 
-impl Game {
     pub fn move_current_block(&mut self, dx: i32, dy: i32) -> bool {
+        if !self.is_playing() {
+            return false;
+        }
+
         let mut new_block = self.current_block;
         new_block.x += dx;
         new_block.y += dy;
 
         if self.board.is_valid_position(&new_block) {
             self.current_block = new_block;
+            self.last_move_rotation = false;
+            if dx != 0 {
+                self.settle(true);
+            }
             true
         } else {
             false
@@ -155,184 +1054,2989 @@ impl Game {
     }
 
     pub fn rotate_current_block(&mut self) -> bool {
+        let rotated = self.kick_rotate();
+        if rotated {
+            self.settle(true);
+        }
+        rotated
+    }
+
+    fn kick_rotate(&mut self) -> bool {
+        if !self.is_playing() {
+            return false;
+        }
+
         let mut new_block = self.current_block;
         new_block.rotate();
 
         if self.board.is_valid_position(&new_block) {
             self.current_block = new_block;
+            self.last_move_rotation = true;
             return true;
         }
 
+        // Classic rotation has no wall kicks
+        if self.mode == GameMode::Classic {
+            return false;
+        }
+
         new_block.x = self.current_block.x - 1;
         if self.board.is_valid_position(&new_block) {
             self.current_block = new_block;
+            self.last_move_rotation = true;
             return true;
         }
 
         new_block.x = self.current_block.x + 1;
         if self.board.is_valid_position(&new_block) {
             self.current_block = new_block;
+            self.last_move_rotation = true;
             return true;
         }
 
         false
     }
 
-    pub fn hard_drop(&mut self) -> bool {
-        while self.move_current_block(0, 1) {}
-        self.lock_current_block()
-    }
-
-    pub fn lock_current_block(&mut self) -> bool {
-        if !self.board.place_block(&self.current_block) {
-            self.state = GameState::GameOver;
+    pub fn apply(&mut self, action: Action) -> bool {
+        self.record(Step::Action(action));
+        if self.state == GameState::Playing && self.phase != Phase::Active {
+            // A hard drop during entry delay would land a piece the player hasn't seen
+            if !matches!((self.phase, action), (Phase::Entry { .. }, Action::HardDrop)) {
+                self.input_buffer.push(action);
+            }
             return false;
         }
 
-        let lines_cleared = self.board.clear_lines();
-        if lines_cleared > 0 {
-            self.screen_shake.start(lines_cleared);
-            self.lines_just_cleared = true;
+        match action {
+            Action::MoveLeft => self.move_current_block(-1, 0),
+            Action::MoveRight => self.move_current_block(1, 0),
+            Action::SoftDrop => self.move_current_block(0, 1),
+            Action::SonicDrop => self.sonic_drop(),
+            Action::RotateCw => self.rotate_current_block(),
+            Action::HardDrop => self.drop_and_lock().locked,
+            Action::Hold => self.hold_piece(),
         }
-        self.update_score(lines_cleared);
-        self.current_block = self.next_block;
-        self.next_block = Block::new(BlockKind::random());
-        self.has_held = false;
+    }
+
+    /// Writes a step to the input log of a recorded game, while it's being played.
+    fn record(&mut self, step: Step) {
+        if self.state != GameState::Playing {
+            return;
+        }
+        let held = HeldKeys::of(self);
+        if let Some(log) = &mut self.input_log {
+            log.record(held, step);
+        }
+    }
 
-        lines_cleared > 0
+    /// The inputs of the current game so far, if it's a sprint.
+    pub fn input_log(&self) -> Option<&InputLog> {
+        self.input_log.as_ref()
     }
 
-    pub fn update_score(&mut self, lines_cleared: u32) {
-        let points = match lines_cleared {
-            1 => 100,
-            2 => 300,
-            3 => 500,
-            4 => 800,
-            _ => 0,
-        } * self.score.level;
+    /// Applies an action from a fresh key press, counting it towards finesse. Key repeats
+    /// go through `apply`, so holding a key to the wall is one input.
+    pub fn press(&mut self, action: Action) -> bool {
+        let counted = matches!(action, Action::MoveLeft | Action::MoveRight | Action::RotateCw);
+        if counted && self.is_playing() {
+            self.finesse.inputs += 1;
+        }
+        self.apply(action)
+    }
 
-        self.score.points += points;
-        self.score.lines += lines_cleared;
-        self.score.level = (self.score.lines / 10) + 1;
+    /// Whether the current piece can be held now.
+    pub fn can_hold(&self) -> bool {
+        let hold_off = self.mode == GameMode::Classic || self.config.hold_slots == 0;
+        self.is_playing() && !self.has_held && !hold_off
     }
 
-    pub fn update(&mut self) {
-        if self.state != GameState::Playing {
-            return;
+    /// Swaps the current piece into hold. A piece that comes out of hold or the queue with
+    /// no room to enter the board blocks out: the hold still happens, and the game is over.
+    pub fn hold_piece(&mut self) -> bool {
+        if !self.can_hold() {
+            return false;
         }
 
-        // Update multiplayer state
-        if let Some(client) = &mut self.multiplayer {
-            // Send our game state
-            if let Some(player_id) = &self.player_id {
-                client.send(GameMessage::GameState {
-                    player_id: player_id.clone(),
-                    score: self.score.points as i32,
-                });
+        match self.hold.push(self.current_block, self.config.hold_slots) {
+            Some(held_block) => {
+                self.current_block = Block::spawn(held_block.kind, BOARD_WIDTH);
             }
-
-            // Receive other players' states
-            while let Some(msg) = client.try_receive() {
-                match msg {
-                    GameMessage::Join { player_id } => {
-                        if self.player_id.is_none() {
-                            self.player_id = Some(player_id.clone());
-                        }
-                        // Initialize score for new player
-                        if player_id != self.player_id.clone().unwrap_or_default() {
-                            self.other_players.insert(player_id, 0);
-                        }
-                    }
-                    GameMessage::GameState { player_id, score } => {
-                        if Some(&player_id) != self.player_id.as_ref() {
-                            self.other_players.insert(player_id, score);
-                        }
-                    }
-                    GameMessage::LineCleared { player_id, count } => {
-                        if Some(&player_id) != self.player_id.as_ref() {
-                            self.board.add_garbage_lines(count);
-                        }
-                    }
-                    GameMessage::PlayerLeft { player_id } => {
-                        self.other_players.remove(&player_id);
-                    }
-                    GameMessage::GameOver { player_id } => {
-                        if Some(&player_id) == self.player_id.as_ref() {
-                            self.state = GameState::GameOver;
-                        }
-                    }
-                }
+            None => {
+                self.current_block = self.next_block;
+                self.next_block = self.next_piece();
+                self.session_stats.record_spawn(self.current_block.kind);
             }
         }
+        self.has_held = true;
+        self.finesse.inputs = 0;
+        self.lock_resets = 0;
+        if !self.can_enter() {
+            self.push_event(GameEvent::Held);
+            self.top_out();
+            return true;
+        }
+        self.settle(false);
+        self.push_event(GameEvent::Held);
+        true
+    }
 
-        // Update fall interval based on current level
-        self.timer.fall_interval = self.timer.get_fall_interval(self.score.level);
-
-        let fall_interval = if self.timer.soft_drop {
-            self.timer.fall_interval.mul_f32(SOFT_DROP_FACTOR)
-        } else {
-            self.timer.fall_interval
-        };
-
-        if self.timer.last_fall.elapsed() >= fall_interval {
-            self.timer.last_fall = Instant::now();
+    /// Whether the current piece, fresh out of hold, has room to come down into the board.
+    /// Pieces spawn above the board, where nothing is in their way, so this checks where
+    /// they enter it: with their top row in the board's top row, or failing that one row
+    /// higher.
+    fn can_enter(&self) -> bool {
+        let top = self.current_block.blocks().iter().map(|&(_, y)| y).min().unwrap();
+        let mut entry = self.current_block;
+        entry.y -= top;
+        (0..2).any(|up| {
+            let mut block = entry;
+            block.y -= up;
+            self.board.is_valid_position(&block)
+        })
+    }
 
-            if !self.move_current_block(0, 1) {
-                self.lock_current_block();
-            }
+    /// In 20G, drops the current piece as far as it goes. A piece the player `moved`
+    /// also gets its lock delay back, while it has resets left.
+    fn settle(&mut self, moved: bool) {
+        if !self.config.instant_gravity {
+            return;
+        }
+        self.sonic_drop();
+        if moved && self.lock_resets < MAX_LOCK_RESETS {
+            self.lock_resets += 1;
+            self.timer.lock_elapsed = None;
         }
     }
 
-    pub fn toggle_pause(&mut self) {
-        match self.state {
-            GameState::Playing => self.state = GameState::Paused,
-            GameState::Paused => self.state = GameState::Playing,
-            _ => {}
+    pub fn hard_drop(&mut self) -> LockResult {
+        if !self.is_playing() {
+            return LockResult::default();
         }
+        self.record(Step::Action(Action::HardDrop));
+        self.drop_and_lock()
     }
 
-    pub fn start_game(&mut self) {
-        let multiplayer = self.multiplayer.take();
+    fn drop_and_lock(&mut self) -> LockResult {
+        if !self.is_playing() {
+            return LockResult::default();
+        }
+
+        let ghost = self.ghost_block();
+        let drop_distance = (ghost.y - self.current_block.y) as u32;
+        if drop_distance > 0 {
+            self.current_block = ghost;
+            self.last_move_rotation = false;
+        }
+        self.push_event(GameEvent::HardDropped);
+        self.judge_finesse();
+        LockResult {
+            drop_distance,
+            ..self.lock_current_block()
+        }
+    }
+
+    /// Counts a fault if the piece being hard dropped took more inputs than it needed,
+    /// including the drop itself. Placements an open field can't reach, such as tucks and
+    /// spins, aren't judged.
+    fn judge_finesse(&mut self) {
+        let inputs = self.finesse.inputs + 1;
+        if let Some(optimal) = optimal_inputs(&self.current_block).map(|moves| moves + 1) {
+            if inputs > optimal {
+                self.finesse.faults += 1;
+                self.push_event(GameEvent::FinesseFault { inputs, optimal });
+            }
+        }
+    }
+
+    /// Where the current piece would land if hard dropped now.
+    pub fn ghost_block(&self) -> Block {
+        Block {
+            y: self.board.drop_row(&self.current_block),
+            ..self.current_block
+        }
+    }
+
+    /// Drops the piece to the floor without locking it, leaving lock delay to run.
+    pub fn sonic_drop(&mut self) -> bool {
+        let mut moved = false;
+        while self.move_current_block(0, 1) {
+            moved = true;
+        }
+        moved
+    }
+
+    fn is_grounded(&self) -> bool {
+        let mut below = self.current_block;
+        below.y += 1;
+        !self.board.is_valid_position(&below)
+    }
+
+    /// Three-corner T-spin check: three of the four cells diagonal to the T's center must be
+    /// blocked, and it is a full T-spin when both corners on the pointing side are blocked.
+    fn detect_t_spin(&self) -> TSpinKind {
+        let block = &self.current_block;
+        if block.kind != BlockKind::T || !self.last_move_rotation {
+            return TSpinKind::None;
+        }
+
+        // Corners for the spawn orientation, front (pointing side) first
+        let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)].map(|(x, y)| {
+            let (x, y) = match block.rotation {
+                0 => (x, y),
+                1 => (-y, x),
+                2 => (-x, -y),
+                _ => (y, -x),
+            };
+            self.is_blocked(block.x + x, block.y + y)
+        });
+
+        let blocked = corners.iter().filter(|&&corner| corner).count();
+        if blocked < 3 {
+            TSpinKind::None
+        } else if corners[0] && corners[1] {
+            TSpinKind::Full
+        } else {
+            TSpinKind::Mini
+        }
+    }
+
+    fn is_blocked(&self, x: i32, y: i32) -> bool {
+        if x < 0 || x >= BOARD_WIDTH as i32 || y >= BOARD_HEIGHT as i32 {
+            return true;
+        }
+        y >= 0 && matches!(self.board.get_cell(y as usize, x as usize), Some(Cell::Filled(_)))
+    }
+
+    pub fn lock_current_block(&mut self) -> LockResult {
+        if !self.is_playing() {
+            return LockResult::default();
+        }
+
+        if self.mode == GameMode::Practice {
+            self.push_undo_snapshot();
+        }
+
+        let t_spin = self.detect_t_spin();
+        let placed_kind = self.current_block.kind;
+        if !self.board.place_block(&self.current_block) {
+            self.top_out();
+            return LockResult {
+                game_over: true,
+                ..LockResult::default()
+            };
+        }
+        self.heatmap.record(self.current_block.blocks());
+        // Completed rows are shown for a moment before they go
+        if !self.board.find_complete_lines().is_empty() {
+            self.clear_frames.push_back(self.board.clone());
+            self.clear_elapsed = Duration::ZERO;
+            self.phase = Phase::Clearing;
+        }
+
+        let (cleared, chain, mut attack) = if self.mode == GameMode::Cascade {
+            let steps = self.board.apply_cascade_gravity();
+            let chain = steps.len() as u32;
+            let (cleared, attack) = self.score_cascade(steps);
+            (cleared, chain, attack)
+        } else {
+            let cleared = self.board.clear_lines();
+            if cleared.count > 0 {
+                self.collapse = Some(Collapse {
+                    drops: cleared.drops,
+                    elapsed: Duration::ZERO,
+                });
+            }
+            let points = self.update_score(cleared.count);
+            self.score_sources.add_clear(points, t_spin);
+            let chain = u32::from(cleared.count > 0);
+            (cleared, chain, 0)
+        };
+        let lines_cleared = cleared.count;
+        let perfect_clear = lines_cleared > 0 && self.board.is_empty();
+        if lines_cleared > 0 {
+            self.screen_shake.start(lines_cleared);
+        }
+        if perfect_clear {
+            self.perfect_clears += 1;
+            self.push_event(GameEvent::PerfectClear);
+        }
+        self.garbage_cleared += cleared.garbage;
+        self.pieces_placed += 1;
+        self.clears.record(lines_cleared, t_spin);
+        if lines_cleared > 0 {
+            let difficult = lines_cleared >= 4 || t_spin != TSpinKind::None;
+            // A cascade already sent each step of its chain as it cleared, perfect clears
+            // included
+            if self.mode != GameMode::Cascade {
+                let clear = LockResult {
+                    lines_cleared,
+                    t_spin,
+                    ..LockResult::default()
+                };
+                let b2b = difficult && self.back_to_back > 0;
+                let mut base = attack_for(&clear, self.combo as i32, b2b);
+                let mut kind = AttackKind::Normal;
+                if perfect_clear {
+                    base = base.max(self.config.perfect_clear_attack);
+                    kind = AttackKind::PerfectClear;
+                }
+                attack = with_badges(base, self.ko_badges);
+                self.send_clear(lines_cleared, attack, kind);
+            }
+            self.combo += 1;
+            self.best_combo = self.best_combo.max(self.combo);
+            // Only an easier clear breaks the back-to-back; a lock without one doesn't
+            if difficult {
+                self.back_to_back += 1;
+            } else {
+                self.back_to_back = 0;
+            }
+        } else {
+            self.combo = 0;
+        }
+        self.timer.lock_elapsed = None;
+        self.timer.gravity_progress = 0.0;
+
+        // After a clear the next piece waits for the animation to finish, see tick_phase
+        if self.phase != Phase::Clearing {
+            let entry_delay = self.entry_delay(false);
+            if entry_delay.is_zero() {
+                self.spawn_next_piece();
+                self.apply_initial_actions();
+            } else {
+                self.phase = Phase::Entry {
+                    remaining: entry_delay,
+                };
+            }
+        }
+
+        self.push_event(GameEvent::PieceLocked {
+            kind: placed_kind,
+            lines_cleared,
+            cleared_rows: cleared.rows.clone(),
+            t_spin,
+        });
+
+        if self.mode == GameMode::Sprint && lines_cleared > 0 {
+            self.splits.record(self.score.lines, self.play_time);
+        }
+        if self.mode == GameMode::CheeseRace && self.cheese_remaining() == 0
+            || self.mode == GameMode::Sprint && self.score.lines >= SPRINT_LINES
+        {
+            self.state = GameState::Finished;
+        }
+        // Garbage only rises as a piece locks, after its clear has cancelled what it could
+        self.land_attacks();
+
+        LockResult {
+            locked: true,
+            lines_cleared,
+            cleared_rows: cleared.rows,
+            drop_distance: 0,
+            t_spin,
+            chain,
+            attack,
+            perfect_clear,
+            game_over: self.state == GameState::GameOver,
+        }
+    }
+
+    fn entry_delay(&self, after_clear: bool) -> Duration {
+        let delay = self.config.entry_delay.min(MAX_ENTRY_DELAY);
+        if after_clear && !delay.is_zero() {
+            delay + LINE_CLEAR_ENTRY_DELAY
+        } else {
+            delay
+        }
+    }
+
+    /// How long each frame of a line clear stays up. Cascades go slower, so the falling
+    /// groups can be followed.
+    fn clear_step_delay(&self) -> Duration {
+        if self.mode == GameMode::Cascade {
+            CASCADE_STEP_DELAY
+        } else {
+            LINE_CLEAR_DELAY
+        }
+    }
+
+    fn spawn_next_piece(&mut self) {
+        self.current_block = self.next_block;
+        self.next_block = self.next_piece();
+        self.session_stats.record_spawn(self.current_block.kind);
+        self.has_held = false;
+        self.last_move_rotation = false;
+        self.finesse.inputs = 0;
+        self.lock_resets = 0;
+        self.settle(false);
+    }
+
+    /// Applies buffered actions to the piece that just became controllable, in the order
+    /// hold, rotate, shift, then hard drop.
+    fn flush_input_buffer(&mut self) -> Option<LockResult> {
+        let buffer = std::mem::take(&mut self.input_buffer);
+        if buffer.hold {
+            self.hold_piece();
+        }
+        // A rotate key still held was already applied as an initial rotation
+        let rotated_on_spawn = self.config.initial_actions && self.rotation_requested;
+        if buffer.rotate && !rotated_on_spawn {
+            self.rotate_current_block();
+        }
+        if buffer.direction != 0 {
+            self.move_current_block(buffer.direction, 0);
+        }
+        buffer.hard_drop.then(|| self.drop_and_lock())
+    }
+
+    /// Applies a held hold or rotate key to a freshly spawned piece, before gravity acts on it.
+    fn apply_initial_actions(&mut self) {
+        if !self.config.initial_actions {
+            return;
+        }
+        if self.hold_requested {
+            self.hold_piece();
+        }
+        if self.rotation_requested {
+            self.rotate_current_block();
+        }
+    }
+
+    /// Scores every clear of a cascade, sends each as an attack and queues its boards for
+    /// display. Returns the combined clear, with the rows of the first step, and the
+    /// garbage it was worth.
+    fn score_cascade(&mut self, steps: Vec<CascadeStep>) -> (ClearResult, u32) {
+        let mut total = ClearResult::default();
+        let mut attack = 0;
+        let last = steps.len().saturating_sub(1);
+        // The board is already the one the chain leaves behind
+        let perfect_clear = !steps.is_empty() && self.board.is_empty();
+        for (index, step) in steps.into_iter().enumerate() {
+            let mut step_attack = attack_with_badges(step.cleared.count, self.ko_badges);
+            let mut kind = AttackKind::Normal;
+            // A perfect clear tops the chain's attack up to the big one with its last step
+            if index == last && perfect_clear {
+                let big = with_badges(self.config.perfect_clear_attack, self.ko_badges);
+                step_attack = step_attack.max(big.saturating_sub(attack));
+                kind = AttackKind::PerfectClear;
+            }
+            self.send_clear(step.cleared.count, step_attack, kind);
+            attack += step_attack;
+            let points = self.update_score(step.cleared.count);
+            self.score_sources.add_clear(points, TSpinKind::None);
+            let bonus = CHAIN_BONUS * index as u32 * self.score.level;
+            self.score.points += bonus;
+            self.score_sources.chains += bonus;
+
+            if index == 0 {
+                total.rows = step.cleared.rows;
+            }
+            total.count += step.cleared.count;
+            total.garbage += step.cleared.garbage;
+            // The final board is the real one, so only earlier steps need showing
+            if index < last {
+                self.clear_frames.push_back(step.board);
+            }
+        }
+        (total, attack)
+    }
+
+    /// Scores a clear of `lines_cleared` lines, returning the points it was worth.
+    pub fn update_score(&mut self, lines_cleared: u32) -> u32 {
+        if self.mode == GameMode::Classic {
+            let index = (lines_cleared as usize).min(CLASSIC_LINE_SCORES.len() - 1);
+            let points = CLASSIC_LINE_SCORES[index] * (self.score.level + 1);
+            self.score.points += points;
+            self.score.lines += lines_cleared;
+            self.score.level = classic_level(self.config.start_level, self.score.lines);
+            return points;
+        }
+
+        let points = match lines_cleared {
+            1 => 100,
+            2 => 300,
+            3 => 500,
+            4 => 800,
+            _ => 0,
+        } * self.score.level;
+
+        self.score.points += points;
+        self.score.lines += lines_cleared;
+        self.score.level = self.config.leveling.level(self.start_level(), self.score.lines);
+        points
+    }
+
+    /// Level the current game started at.
+    pub fn start_level(&self) -> u32 {
+        match self.mode {
+            GameMode::Classic => self.config.start_level,
+            GameMode::Marathon => self.config.start_level.clamp(1, MAX_START_LEVEL),
+            _ => 1,
+        }
+    }
+
+    // The level this game would be at after `lines`, under its leveling rule
+    fn level_after(&self, lines: u32) -> u32 {
+        match self.mode {
+            GameMode::Classic => classic_level(self.start_level(), lines),
+            _ => self.config.leveling.level(self.start_level(), lines),
+        }
+    }
+
+    /// Lines still to clear before the level goes up.
+    pub fn lines_to_next_level(&self) -> u32 {
+        // A level takes at most a few hundred lines, so counting up is cheap enough
+        (1..)
+            .find(|&more| self.level_after(self.score.lines + more) > self.score.level)
+            .unwrap_or(0)
+    }
+
+    /// How far through the current level's lines the game is, from 0 just after leveling
+    /// up towards 1.
+    pub fn level_progress(&self) -> f32 {
+        let lines = self.score.lines;
+        let done = (1..=lines)
+            .take_while(|&back| self.level_after(lines - back) == self.score.level)
+            .count() as u32;
+        let total = done + self.lines_to_next_level();
+        done as f32 / total.max(1) as f32
+    }
+
+    /// How fast pieces fall at the current level, in rows per second.
+    pub fn gravity(&self) -> f32 {
+        if self.config.instant_gravity {
+            return MAX_GRAVITY;
+        }
+        1.0 / self.timer.get_fall_interval(self.score.level, self.mode).as_secs_f32()
+    }
+
+    fn top_out(&mut self) {
+        self.state = GameState::GameOver;
+        self.push_event(GameEvent::ToppedOut);
+        if let Some(client) = &self.multiplayer {
+            if let Some(player_id) = &self.player_id {
+                client.send(GameMessage::GameOver {
+                    player_id: player_id.clone(),
+                });
+            }
+        }
+    }
+
+    /// Runs one frame: multiplayer messages, then gravity. Returns the result of a
+    /// gravity lock if the piece locked during this frame.
+    pub fn update(&mut self) -> Option<LockResult> {
+        let now = Instant::now();
+        let dt = now.duration_since(self.timer.last_update);
+        self.timer.last_update = now;
+        self.poll_reconnect();
+
+        if let Some(left) = self.resume_countdown {
+            match left.checked_sub(dt).filter(|left| !left.is_zero()) {
+                Some(left) => self.resume_countdown = Some(left),
+                None => self.resume(),
+            }
+        }
+
+        // Opponents keep playing through a multiplayer pause, so keep up with them
+        if self.state == GameState::Paused && self.multiplayer.is_some() {
+            let attacks = self.poll_multiplayer();
+            self.queue_attacks(attacks);
+            if self.pause_time_left() == Some(Duration::ZERO) {
+                self.resume();
+            }
+            return None;
+        }
+
+        // A finished game still hears about the match, and sends its final state
+        if self.state != GameState::Playing {
+            if self.multiplayer.is_some() {
+                self.poll_multiplayer();
+            }
+            return None;
+        }
+
+        let attacks = self.poll_multiplayer();
+        self.queue_attacks(attacks);
+        let dt = self.speed.game_time(dt);
+        self.advance(dt)
+    }
+
+    // Plays `dt` of game time as fixed steps, so a game goes the same however fast it's
+    // drawn. Returns the result of a gravity lock during them
+    fn advance(&mut self, dt: Duration) -> Option<LockResult> {
+        let mut lock = None;
+        for _ in 0..self.clock.advance(dt) {
+            if self.mode == GameMode::CheeseRace {
+                self.refill_cheese();
+                if self.state != GameState::Playing {
+                    break;
+                }
+            }
+            self.last_step_piece = (self.pieces_placed, self.current_block);
+            if let Some(result) = self.tick(FIXED_DT) {
+                lock = Some(result);
+            }
+            if self.state != GameState::Playing {
+                break;
+            }
+        }
+        lock
+    }
+
+    /// Where to draw the falling piece, in cells from where it is: partway back along the
+    /// latest step's fall, as far as the time since has got into the next step. A piece the
+    /// player has moved since is drawn where it is.
+    pub fn falling_offset(&self) -> (f32, f32) {
+        let (placed, from) = self.last_step_piece;
+        if placed != self.pieces_placed || from.x != self.current_block.x {
+            return (0.0, 0.0);
+        }
+        interpolate_offset(&from, &self.current_block, self.clock.progress())
+    }
+
+    /// Sets how fast game time runs. Refused in multiplayer, where it would be cheating.
+    pub fn set_speed(&mut self, scale: f32) -> bool {
+        if self.multiplayer.is_some() {
+            return false;
+        }
+        self.speed.scale = scale;
+        true
+    }
+
+    /// Stops or restarts game time for frame stepping. Refused in multiplayer.
+    pub fn toggle_frozen(&mut self) -> bool {
+        if self.multiplayer.is_some() {
+            return false;
+        }
+        self.speed.frozen = !self.speed.frozen;
+        self.speed.pending_frames = 0;
+        true
+    }
+
+    /// Advances a frozen game by exactly one frame on the next update.
+    pub fn step_frame(&mut self) {
+        if self.speed.frozen {
+            self.speed.pending_frames += 1;
+        }
+    }
+
+    /// Queues attacks from other players as (sender, lines), with a warning for each.
+    fn queue_attacks(&mut self, attacks: Vec<(String, u32, AttackKind)>) {
+        for (from, lines, kind) in attacks {
+            let id = self.garbage_queue.push(from.clone(), lines);
+            self.push_event(GameEvent::AttackIncoming {
+                id,
+                from,
+                lines,
+                kind,
+            });
+        }
+    }
+
+    /// Queues garbage from a game on this machine, to land by the same rule as attacks
+    /// from other players.
+    pub fn queue_garbage(&mut self, from: String, lines: u32) {
+        if lines > 0 {
+            self.queue_attacks(vec![(from, lines, AttackKind::Normal)]);
+        }
+    }
+
+    /// Lands the queued attacks that have waited out their pieces, as one locks. Each
+    /// arrives as its own block of garbage with its own hole.
+    fn land_attacks(&mut self) {
+        for attack in self.garbage_queue.lock(self.config.garbage_delay_pieces) {
+            self.lines_received += attack.lines;
+            self.receive_garbage(attack.lines);
+            if self.state != GameState::Playing {
+                break;
+            }
+        }
+    }
+
+    /// Shares our state and handles messages from the server. Returns the attacks other
+    /// players sent, in the order they arrived.
+    fn poll_multiplayer(&mut self) -> Vec<(String, u32, AttackKind)> {
+        let mut attacks = Vec::new();
+        let mut departed = Vec::new();
+        let mut match_seed = None;
+        let mut match_finished = false;
+        // No falling piece to show while the next one waits to spawn
+        let falling = self.is_controllable().then_some(self.current_block);
+        if let Some(client) = &mut self.multiplayer {
+            // Send our game state
+            if let Some(player_id) = &self.player_id {
+                let game_state = GameMessage::GameState {
+                    player_id: player_id.clone(),
+                    score: self.score.points as i32,
+                    lines: self.score.lines,
+                    name: self.player_name.clone(),
+                    afk: self.afk && self.state == GameState::Playing,
+                };
+                if let Some(msg) = self.game_state_sender.poll(game_state) {
+                    client.send(msg);
+                }
+
+                // An unchanged board is sent again now and then, so it doesn't look frozen to
+                // other players, and over UDP in case it was lost
+                self.board_state_sender.set_repeat(Some(UDP_REFRESH_INTERVAL));
+                let board_state = GameMessage::BoardState {
+                    player_id: player_id.clone(),
+                    cells: self.board.get_cells_for_network(),
+                    current: falling,
+                    next: Some(self.next_block.kind),
+                };
+                if let Some(msg) = self.board_state_sender.poll(board_state) {
+                    client.send(msg);
+                }
+            }
+
+            // Receive other players' states
+            while let Some(msg) = client.try_receive() {
+                match msg {
+                    GameMessage::Join {
+                        player_id,
+                        resume_token,
+                    } => {
+                        // Only our own Join carries a token; older servers don't send one
+                        if resume_token.is_some() {
+                            self.player_id = Some(player_id.clone());
+                            self.resume_token = resume_token;
+                        } else if self.player_id.is_none() {
+                            self.player_id = Some(player_id.clone());
+                        }
+                        // Initialize score for new player, and make sure they get our state
+                        if player_id != self.player_id.clone().unwrap_or_default() {
+                            self.other_players.insert(player_id, PlayerInfo::new());
+                            self.game_state_sender.reset();
+                            self.board_state_sender.reset();
+                        }
+                    }
+                    GameMessage::GameState {
+                        player_id,
+                        score,
+                        lines,
+                        name,
+                        afk,
+                    } => {
+                        if Some(&player_id) != self.player_id.as_ref() {
+                            let info = self.other_players.entry(player_id).or_default();
+                            info.score = score;
+                            info.lines = lines;
+                            info.name = name;
+                            info.afk = afk;
+                            info.last_seen = Instant::now();
+                        }
+                    }
+                    GameMessage::LineCleared {
+                        player_id,
+                        count,
+                        sent,
+                        kind,
+                    } => {
+                        if Some(&player_id) != self.player_id.as_ref() {
+                            let badges =
+                                self.other_players.get(&player_id).map_or(0, |p| p.ko_badges);
+                            let perfect_clear = (kind == AttackKind::PerfectClear)
+                                .then_some(self.room_rules.perfect_clear_attack);
+                            if perfect_clear.is_some() {
+                                // Flashes on their board for anyone watching it
+                                let boards = &mut self.other_boards;
+                                let remote = boards.entry(player_id.clone()).or_default();
+                                remote.perfect_clear_at = Some(Instant::now());
+                            }
+                            let attack = sent_attack(count, sent, badges, perfect_clear);
+                            if attack > 0 {
+                                self.attacked_at.insert(player_id.clone(), Instant::now());
+                                attacks.push((player_id, attack, kind));
+                            }
+                        }
+                    }
+                    GameMessage::BoardState {
+                        player_id,
+                        cells,
+                        current,
+                        next,
+                    } => {
+                        if Some(&player_id) != self.player_id.as_ref() {
+                            // Scores are only sent on change, so boards also show they're here
+                            if let Some(info) = self.other_players.get_mut(&player_id) {
+                                info.last_seen = Instant::now();
+                            }
+                            let remote = self.other_boards.entry(player_id).or_default();
+                            if remote.board.update_from_network(cells).is_ok() {
+                                remote.set_piece_at(current, Instant::now());
+                                remote.next = next;
+                            } else {
+                                self.rejected_boards += 1;
+                            }
+                        }
+                    }
+                    GameMessage::Paused { player_id } => {
+                        if Some(&player_id) != self.player_id.as_ref() {
+                            self.paused_players.insert(player_id);
+                        }
+                    }
+                    GameMessage::Resumed { player_id } => {
+                        // Only the server uses our id, to end a match pause that ran over
+                        // the limit; other players' messages can't name us
+                        if Some(&player_id) == self.player_id.as_ref()
+                            && self.state == GameState::Paused
+                            && self.paused_at.is_some()
+                        {
+                            self.state = GameState::Playing;
+                            self.paused_at = None;
+                            self.resume_countdown = None;
+                        }
+                        self.paused_players.remove(&player_id);
+                    }
+                    GameMessage::Forfeit { player_id } => {
+                        self.paused_players.remove(&player_id);
+                        self.forfeited_players.insert(player_id);
+                    }
+                    GameMessage::PlayerLeft { player_id } => {
+                        self.other_players.remove(&player_id);
+                        departed.push(player_id);
+                    }
+                    GameMessage::LobbySnapshot {
+                        players,
+                        match_active,
+                    } => {
+                        self.match_active = match_active;
+                        let own_id = self.player_id.as_deref();
+                        let removed = reconcile_players(&mut self.other_players, &players, own_id);
+                        departed.extend(removed);
+                        let own = players.iter().find(|state| own_id == Some(&*state.player_id));
+                        if let Some(own) = own {
+                            self.accent = own.accent;
+                        }
+                        for state in &players {
+                            if state.paused && Some(&state.player_id) != self.player_id.as_ref() {
+                                self.paused_players.insert(state.player_id.clone());
+                            } else {
+                                self.paused_players.remove(&state.player_id);
+                            }
+                        }
+                    }
+                    GameMessage::GameOver { player_id } => {
+                        if Some(&player_id) == self.player_id.as_ref() {
+                            self.state = GameState::GameOver;
+                        } else {
+                            self.out_players.insert(player_id);
+                        }
+                    }
+                    GameMessage::KnockOut { by, .. } => {
+                        if Some(&by) == self.player_id.as_ref() {
+                            self.ko_badges += 1;
+                        } else if let Some(info) = self.other_players.get_mut(&by) {
+                            info.ko_badges += 1;
+                        }
+                    }
+                    GameMessage::RoomJoined { room, locked } => {
+                        self.room = Some(room);
+                        self.room_locked = locked;
+                        self.session = SessionTally::default();
+                        self.room_error = None;
+                        // The new room hasn't seen our board yet
+                        self.game_state_sender.reset();
+                        self.board_state_sender.reset();
+                    }
+                    GameMessage::RoomRules { rules } => self.room_rules = rules,
+                    GameMessage::Error { code } => self.room_error = Some(code),
+                    GameMessage::ServerMessage { text } => {
+                        self.server_message = Some((text, Instant::now()));
+                    }
+                    GameMessage::QueueStatus { position } => {
+                        if self.queued_at.is_some() {
+                            self.queue_position = Some(position);
+                        }
+                    }
+                    GameMessage::MatchStart { seed } => match_seed = Some(seed),
+                    GameMessage::MatchResult { result } => {
+                        let own_id = self.player_id.as_deref();
+                        if result.placements.iter().any(|p| Some(p.player_id.as_str()) == own_id) {
+                            // Still standing when the match ended: that's the win
+                            if matches!(self.state, GameState::Playing | GameState::Paused) {
+                                self.state = GameState::GameOver;
+                                self.paused_at = None;
+                            }
+                            self.session.record(&result, own_id.unwrap_or_default());
+                            self.match_result = Some(result);
+                            match_finished = true;
+                        }
+                    }
+                    // Compression and the UDP side channel are negotiated by the connection
+                    // itself, and room and match requests only ever go to the server
+                    GameMessage::Hello { .. }
+                    | GameMessage::UdpRegister { .. }
+                    | GameMessage::UdpReady
+                    | GameMessage::CreateRoom { .. }
+                    | GameMessage::JoinRoom { .. }
+                    | GameMessage::LeaveRoom { .. }
+                    | GameMessage::NewGame { .. }
+                    | GameMessage::QueueForMatch
+                    | GameMessage::LeaveQueue
+                    | GameMessage::SubmitRecord { .. }
+                    | GameMessage::RecordAccepted
+                    | GameMessage::RecordRejected { .. }
+                    | GameMessage::DailyTop { .. }
+                    | GameMessage::DailyLeaderboard { .. }
+                    | GameMessage::ListRooms
+                    | GameMessage::RoomList { .. }
+                    | GameMessage::ListReplays
+                    | GameMessage::ReplayList { .. }
+                    | GameMessage::FetchReplay { .. }
+                    | GameMessage::ReplayChunk { .. } => {}
+                }
+            }
+        }
+        for player_id in departed {
+            self.forget_player(&player_id);
+        }
+        self.flush_new_game();
+        if match_finished {
+            self.push_event(GameEvent::MatchFinished);
+        }
+        // Both players of a queued match start on the same pieces at the same time
+        if let Some(seed) = match_seed {
+            self.queue_position = None;
+            self.queued_at = None;
+            self.start_game_with_seed(seed);
+            attacks.clear();
+        }
+
+        if self.multiplayer.as_ref().is_some_and(|client| !client.is_connected()) {
+            self.go_offline();
+            return Vec::new();
+        }
+        attacks
+    }
+
+    /// Drops a dead connection and carries on as a single player game, trying to get back
+    /// to the server in the background. Garbage that hadn't landed yet is discarded.
+    fn go_offline(&mut self) {
+        self.multiplayer = None;
+        self.connection_lost = true;
+        if let (Some(server_addr), Some(token)) = (self.server_addr.clone(), &self.resume_token) {
+            self.reconnecting = Some(tokio::spawn(reconnect(server_addr, token.clone())));
+        }
+        self.room = None;
+        self.room_rules = RoomRules::default();
+        self.restore_local_config();
+        self.session = SessionTally::default();
+        self.queue_position = None;
+        self.queued_at = None;
+        self.match_active = false;
+        self.garbage_queue.clear();
+        self.paused_at = None;
+        let players: Vec<String> = self.other_players.drain().map(|(id, _)| id).collect();
+        for player_id in players {
+            self.forget_player(&player_id);
+        }
+    }
+
+    /// Advances gravity and lock delay by `dt` of game time.
+    pub fn tick(&mut self, dt: Duration) -> Option<LockResult> {
+        self.record(Step::Frame(dt));
+        if self.phase != Phase::Clearing {
+            if let Some(collapse) = &mut self.collapse {
+                collapse.elapsed += dt;
+                if collapse.elapsed >= COLLAPSE_DURATION {
+                    self.collapse = None;
+                }
+            }
+        }
+        if self.state == GameState::Playing && self.phase != Phase::Active {
+            self.play_time += dt;
+            return self.tick_phase(dt);
+        }
+        if !self.is_playing() {
+            return None;
+        }
+        self.play_time += dt;
+
+        // Update fall interval based on current level
+        self.timer.fall_interval = self.timer.get_fall_interval(self.score.level, self.mode);
+
+        let fall_interval = if self.timer.soft_drop {
+            self.timer
+                .get_soft_drop_interval(self.config.soft_drop_factor)
+                .min(self.timer.fall_interval)
+        } else {
+            self.timer.fall_interval
+        };
+
+        // Classic has no lock delay: a grounded piece locks on the next gravity step
+        if self.mode == GameMode::Classic && self.is_grounded() {
+            self.timer.gravity_progress += dt.as_secs_f32() / fall_interval.as_secs_f32();
+            if self.timer.gravity_progress >= 1.0 {
+                return Some(self.lock_current_block());
+            }
+            return None;
+        }
+
+        // Rising garbage or an undo can leave a 20G piece above the stack
+        self.settle(false);
+
+        // A grounded piece, soft dropped or not, waits out the lock delay before locking
+        if self.is_grounded() {
+            self.timer.gravity_progress = 0.0;
+            let lock_elapsed = self.timer.lock_elapsed.get_or_insert(Duration::ZERO);
+            *lock_elapsed += dt;
+            if *lock_elapsed >= self.config.lock_delay {
+                return Some(self.lock_current_block());
+            }
+        } else {
+            self.timer.lock_elapsed = None;
+            // High gravity can move the piece several rows in a single tick
+            self.timer.gravity_progress += dt.as_secs_f32() / fall_interval.as_secs_f32();
+            while self.timer.gravity_progress >= 1.0 {
+                if !self.move_current_block(0, 1) {
+                    self.timer.gravity_progress = 0.0;
+                    break;
+                }
+                self.timer.gravity_progress -= 1.0;
+            }
+        }
+        None
+    }
+
+    /// Advances a cascade animation or entry delay, handing control to the next piece
+    /// once it ends.
+    fn tick_phase(&mut self, dt: Duration) -> Option<LockResult> {
+        match self.phase {
+            Phase::Active => return None,
+            Phase::Clearing => {
+                self.clear_elapsed += dt;
+                if self.clear_elapsed >= self.clear_step_delay() {
+                    self.clear_frames.pop_front();
+                    self.clear_elapsed = Duration::ZERO;
+                }
+                if !self.clear_frames.is_empty() {
+                    return None;
+                }
+                let entry_delay = self.entry_delay(true);
+                if !entry_delay.is_zero() {
+                    self.phase = Phase::Entry {
+                        remaining: entry_delay,
+                    };
+                    return None;
+                }
+                self.spawn_next_piece();
+            }
+            Phase::Entry { remaining } => {
+                if dt < remaining {
+                    self.phase = Phase::Entry {
+                        remaining: remaining - dt,
+                    };
+                    return None;
+                }
+                self.spawn_next_piece();
+            }
+        }
+        // Initial actions apply once the piece can be controlled
+        self.phase = Phase::Active;
+        self.apply_initial_actions();
+        self.flush_input_buffer()
+    }
+
+    pub fn toggle_pause(&mut self) {
+        match self.state {
+            GameState::Playing if self.can_pause() => {
+                self.state = GameState::Paused;
+                if self.multiplayer.is_some() && self.match_active {
+                    self.pauses_used += 1;
+                    self.paused_at = Some(Instant::now());
+                    self.send_own(|player_id| GameMessage::Paused { player_id });
+                }
+            }
+            // Once started, the countdown runs out before the game can pause again
+            GameState::Paused if self.resume_countdown.is_none() => {
+                self.resume_countdown = Some(RESUME_COUNTDOWN);
+            }
+            // A finished game can only be left through start_game
+            GameState::Playing | GameState::Paused | GameState::GameOver | GameState::Finished => {
+            }
+        }
+    }
+
+    /// Pauses a single-player game the player has walked away from, so it doesn't top out
+    /// on its own. Online games play on, and the player shows as away instead.
+    pub fn idle_pause(&mut self) {
+        if self.multiplayer.is_none() && self.can_pause() {
+            self.toggle_pause();
+            self.idle_paused = true;
+        }
+    }
+
+    pub fn assist(&self) -> bool {
+        self.assist
+    }
+
+    /// Turns assist mode on or off. Turning it on takes the game under way off the records.
+    pub fn set_assist(&mut self, on: bool) {
+        self.assist = on;
+        self.assisted |= on;
+    }
+
+    /// Whether assist mode was on at some point in this game.
+    pub fn is_assisted(&self) -> bool {
+        self.assisted
+    }
+
+    /// Assist mode's suggestion for the current piece, while it's on and the piece is in
+    /// play. It's only searched again once the board or the pieces change.
+    pub fn suggestion(&mut self) -> Option<Suggestion> {
+        if !self.assist || !self.is_playing() {
+            return None;
+        }
+        let mut cache = std::mem::take(&mut self.suggestions);
+        let suggestion = cache.suggestion(self, &Weights::default());
+        self.suggestions = cache;
+        suggestion
+    }
+
+    /// Whether the game is paused because the player went idle.
+    pub fn is_idle_paused(&self) -> bool {
+        self.idle_paused && self.state == GameState::Paused
+    }
+
+    fn resume(&mut self) {
+        self.resume_countdown = None;
+        self.idle_paused = false;
+        if self.state != GameState::Paused {
+            return;
+        }
+        self.state = GameState::Playing;
+        if self.paused_at.take().is_some() {
+            self.send_own(|player_id| GameMessage::Resumed { player_id });
+        }
+    }
+
+    /// Whole seconds left, rounded up, while a pause counts down to resuming.
+    pub fn resume_countdown(&self) -> Option<u64> {
+        self.resume_countdown.map(|left| left.as_millis().div_ceil(1000) as u64)
+    }
+
+    /// Whether a game like this one, outside versus, could set a record: the best score
+    /// or sprint time, or the daily challenge. Multiplayer, practice, missions and the
+    /// tutorial can't.
+    pub fn plays_for_records(&self) -> bool {
+        self.multiplayer.is_none()
+            && !self.assisted
+            && !matches!(self.mode, GameMode::Practice | GameMode::Mission | GameMode::Tutorial)
+    }
+
+    /// Whether pausing is allowed now. A multiplayer match only allows MATCH_PAUSES.
+    pub fn can_pause(&self) -> bool {
+        self.state == GameState::Playing && self.pauses_left() != Some(0)
+    }
+
+    /// Pauses left this match, or None outside a match, where pauses are unlimited.
+    pub fn pauses_left(&self) -> Option<u32> {
+        (self.multiplayer.is_some() && self.match_active)
+            .then(|| MATCH_PAUSES.saturating_sub(self.pauses_used))
+    }
+
+    /// Time until a multiplayer pause ends on its own.
+    pub fn pause_time_left(&self) -> Option<Duration> {
+        self.paused_at
+            .map(|paused_at| MATCH_PAUSE_LIMIT.saturating_sub(paused_at.elapsed()))
+    }
+
+    /// Pieces placed per second of play.
+    pub fn pps(&self) -> f32 {
+        per_second(self.pieces_placed, self.play_time)
+    }
+
+    /// Garbage lines sent per minute of play.
+    pub fn apm(&self) -> f32 {
+        per_second(self.lines_sent, self.play_time) * 60.0
+    }
+
+    /// Garbage from other players that hasn't landed yet.
+    pub fn pending_garbage(&self) -> u32 {
+        self.garbage_queue.total()
+    }
+
+    /// The attacks behind `pending_garbage`, oldest first.
+    pub fn incoming_attacks(&self) -> impl Iterator<Item = &IncomingAttack> {
+        self.garbage_queue.attacks()
+    }
+
+    /// Drops everything we track about a player who has left.
+    fn forget_player(&mut self, player_id: &str) {
+        self.other_boards.remove(player_id);
+        self.paused_players.remove(player_id);
+        self.forfeited_players.remove(player_id);
+        self.out_players.remove(player_id);
+        self.attacked_at.remove(player_id);
+        if self.spectating.as_deref() == Some(player_id) {
+            self.spectating = None;
+        }
+    }
+
+    /// Moves the spectator view on to the next player who has shared a board, or back to
+    /// our own game after the last of them.
+    pub fn spectate_next(&mut self) {
+        let mut ids: Vec<&String> = self.other_boards.keys().collect();
+        ids.sort();
+        let next = match &self.spectating {
+            Some(current) => ids
+                .iter()
+                .position(|id| *id == current)
+                .and_then(|index| ids.get(index + 1)),
+            None => ids.first(),
+        };
+        self.spectating = next.map(|id| (*id).clone());
+    }
+
+    pub fn stop_spectating(&mut self) {
+        self.spectating = None;
+    }
+
+    /// The player we're spectating and their board, if they're still around.
+    pub fn spectated_board(&self) -> Option<(&str, &RemoteBoard)> {
+        let player_id = self.spectating.as_deref()?;
+        self.other_boards.get(player_id).map(|remote| (player_id, remote))
+    }
+
+    /// Where another player's game stands, going by what they've told us.
+    pub fn player_status(&self, player_id: &str) -> PlayerStatus {
+        if self.forfeited_players.contains(player_id) {
+            PlayerStatus::Forfeit
+        } else if self.out_players.contains(player_id) {
+            PlayerStatus::Out
+        } else if self.paused_players.contains(player_id) {
+            PlayerStatus::Paused
+        } else if self.other_players.get(player_id).is_some_and(|info| info.afk) {
+            PlayerStatus::Afk
+        } else if self.other_players.get(player_id).is_some_and(PlayerInfo::is_stale) {
+            PlayerStatus::Stale
+        } else {
+            PlayerStatus::Playing
+        }
+    }
+
+    /// Where our own game stands.
+    pub fn own_status(&self) -> PlayerStatus {
+        match self.state {
+            GameState::Paused => PlayerStatus::Paused,
+            GameState::GameOver => PlayerStatus::Out,
+            GameState::Playing if self.afk => PlayerStatus::Afk,
+            GameState::Playing | GameState::Finished => PlayerStatus::Playing,
+        }
+    }
+
+    /// Gives up the current multiplayer game, ending any pause. Does nothing offline.
+    pub fn forfeit(&mut self) {
+        if self.paused_at.take().is_some() {
+            self.send_own(|player_id| GameMessage::Resumed { player_id });
+        }
+        self.send_own(|player_id| GameMessage::Forfeit { player_id });
+    }
+
+    fn send_own(&self, message: impl FnOnce(String) -> GameMessage) {
+        if let (Some(client), Some(player_id)) = (&self.multiplayer, &self.player_id) {
+            client.send(message(player_id.clone()));
+        }
+    }
+
+    /// The randomizer dealing this game's pieces, which can differ from the one in
+    /// `config` until the next game starts.
+    pub fn randomizer_kind(&self) -> RandomizerKind {
+        self.randomizer.kind()
+    }
+
+    /// What the randomizer is holding on to, for the debug overlay.
+    pub fn randomizer_debug(&self) -> RandomizerDebug {
+        self.randomizer.debug()
+    }
+
+    pub fn start_game(&mut self) {
+        self.start_game_with_seed(rand::random());
+    }
+
+    pub fn start_game_with_seed(&mut self, seed: u64) {
+        let multiplayer = self.multiplayer.take();
         let player_id = self.player_id.clone();
         let other_players = std::mem::take(&mut self.other_players);
+        let other_boards = std::mem::take(&mut self.other_boards);
+
+        // Everyone in a room starts each match under its rules, on top of our own config
+        if multiplayer.is_some() {
+            let local = self.local_config.take().unwrap_or_else(|| self.config.clone());
+            self.config = local.clone();
+            self.room_rules.apply(&mut self.config);
+            self.local_config = Some(local);
+        }
+        self.seed = seed;
+        self.randomizer = self.config.randomizer.randomizer(seed);
+        self.garbage_rng = StdRng::seed_from_u64(seed ^ GARBAGE_SEED_SALT);
+        self.last_garbage_hole = None;
+        self.garbage_queue.clear();
+        self.board = match self.mode {
+            GameMode::Practice | GameMode::Mission | GameMode::Tutorial => {
+                self.practice.board.clone()
+            }
+            _ => Board::new(),
+        };
+        self.queue_index = 0;
+        self.undo_stack.clear();
+        self.save_states.clear();
+        self.current_block = self.next_piece();
+        self.next_block = self.next_piece();
+        self.session_stats.new_game();
+        self.session_stats.record_spawn(self.current_block.kind);
+        self.hold = HoldQueue::default();
+        self.has_held = false;
+        self.state = GameState::Playing;
+        self.resume_countdown = None;
+        self.idle_paused = false;
+        self.assisted = self.assist;
+        self.score = Score {
+            level: self.start_level(),
+            ..Score::default()
+        };
+        self.timer = GameTimer::default();
+        self.screen_shake = ScreenShake::default();
+        self.last_move_rotation = false;
+        self.outgoing_garbage = 0;
+        self.pieces_placed = 0;
+        self.garbage_cleared = 0;
+        self.lines_sent = 0;
+        self.lines_received = 0;
+        self.perfect_clears = 0;
+        self.ko_badges = 0;
+        self.combo = 0;
+        self.back_to_back = 0;
+        self.clears = ClearCounts::default();
+        self.score_sources = ScoreSources::default();
+        self.best_combo = 0;
+        self.heatmap = Heatmap::default();
+        self.splits = Splits::default();
+        self.finesse = Finesse::default();
+        self.events.clear();
+        self.clear_frames.clear();
+        self.collapse = None;
+        self.clear_elapsed = Duration::ZERO;
+        self.phase = Phase::Active;
+        self.input_buffer = InputBuffer::default();
+        self.play_time = Duration::ZERO;
+        self.clock = FixedStep::default();
+        self.last_step_piece = (0, self.current_block);
+        self.input_log = (self.mode == GameMode::Sprint).then(|| InputLog::new(seed, &self.config));
+        if self.mode == GameMode::CheeseRace {
+            self.refill_cheese();
+        }
+        self.lock_resets = 0;
+        self.settle(false);
+
+        // Restore multiplayer state
+        self.multiplayer = multiplayer;
+        self.player_id = player_id;
+        self.other_players = other_players;
+        // Badges only count for the match they were won in
+        for info in self.other_players.values_mut() {
+            info.ko_badges = 0;
+        }
+        self.other_boards = other_boards;
+        self.match_result = None;
+        self.pauses_used = 0;
+        self.paused_at = None;
+        self.new_game_pending = self.multiplayer.is_some();
+        self.flush_new_game();
+    }
+
+    /// Announces the current game as ready for a match, if we know our id yet.
+    fn flush_new_game(&mut self) {
+        if self.new_game_pending && self.player_id.is_some() {
+            self.send_own(|player_id| GameMessage::NewGame { player_id });
+            self.new_game_pending = false;
+        }
+    }
+
+    /// Switches to practice mode on the given setup and starts a fresh game on it.
+    pub fn start_practice(&mut self, setup: PracticeSetup) {
+        self.mode = GameMode::Practice;
+        self.practice = setup;
+        self.start_game();
+    }
+
+    /// Switches to cheese race mode with the given number of garbage lines to clear.
+    pub fn start_cheese_race(&mut self, target_lines: u32) {
+        self.mode = GameMode::CheeseRace;
+        self.config.cheese.target_lines = target_lines;
+        self.start_game();
+    }
+
+    /// Switches to sprint mode and starts a game on the given seed.
+    pub fn start_sprint(&mut self, seed: u64) {
+        self.mode = GameMode::Sprint;
+        self.start_game_with_seed(seed);
+    }
+
+    /// Switches to mission mode and starts the mission's setup. Missions reuse the practice
+    /// setup for their starting board and piece queue.
+    pub fn start_mission(&mut self, setup: PracticeSetup) {
+        self.mode = GameMode::Mission;
+        self.practice = setup;
+        self.start_game();
+    }
+
+    fn push_event(&mut self, event: GameEvent) {
+        if self.events.len() == MAX_PENDING_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Takes the events produced since the last call, oldest first.
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Switches to tutorial mode on the setup of a tutorial step.
+    pub fn start_tutorial(&mut self, setup: PracticeSetup) {
+        self.mode = GameMode::Tutorial;
+        self.practice = setup;
+        self.start_game();
+    }
+
+    /// Switches to marathon mode, starting at the given level.
+    pub fn start_marathon(&mut self, start_level: u32) {
+        self.mode = GameMode::Marathon;
+        self.config.start_level = start_level.clamp(1, MAX_START_LEVEL);
+        self.start_game();
+    }
+
+    /// Switches to classic mode, starting at the given NES level.
+    pub fn start_classic(&mut self, start_level: u32) {
+        self.mode = GameMode::Classic;
+        self.config.start_level = start_level.min(CLASSIC_MAX_START_LEVEL);
+        self.start_game();
+    }
+
+    /// Puts the practice setup back as it was loaded, keeping score and stats.
+    pub fn reset_practice(&mut self) {
+        if self.mode != GameMode::Practice {
+            return;
+        }
+
+        self.board = self.practice.board.clone();
+        self.queue_index = 0;
+        self.undo_stack.clear();
+        self.current_block = self.next_piece();
+        self.next_block = self.next_piece();
+        self.hold = HoldQueue::default();
+        self.has_held = false;
+        self.state = GameState::Playing;
+        self.timer = GameTimer::default();
+        self.last_move_rotation = false;
+    }
+
+    fn push_undo_snapshot(&mut self) {
+        if self.undo_stack.len() == UNDO_DEPTH {
+            self.undo_stack.pop_front();
+        }
+        // Undoing respawns the piece rather than leaving it where it locked
+        let current_block = Block::spawn(self.current_block.kind, BOARD_WIDTH);
+        self.undo_stack.push_back(Snapshot {
+            board: self.board.clone(),
+            current_block,
+            next_block: self.next_block,
+            hold: self.hold,
+            has_held: self.has_held,
+            queue_index: self.queue_index,
+        });
+    }
+
+    pub fn undo_count(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    pub fn save_state_count(&self) -> usize {
+        self.save_states.len()
+    }
+
+    /// Saves the whole practice game, keeping the last `SAVE_STATE_SLOTS`. Returns how many
+    /// saves there are now, or None outside practice mode.
+    pub fn save_state(&mut self) -> Option<usize> {
+        if self.mode != GameMode::Practice {
+            return None;
+        }
+        if self.save_states.len() == SAVE_STATE_SLOTS {
+            self.save_states.pop_front();
+        }
+        self.save_states.push_back(SaveState {
+            snapshot: Snapshot {
+                board: self.board.clone(),
+                current_block: self.current_block,
+                next_block: self.next_block,
+                hold: self.hold,
+                has_held: self.has_held,
+                queue_index: self.queue_index,
+            },
+            score: self.score.clone(),
+            clears: self.clears,
+            combo: self.combo,
+            back_to_back: self.back_to_back,
+            pieces_placed: self.pieces_placed,
+            randomizer: self.randomizer.clone(),
+            garbage_rng: self.garbage_rng.clone(),
+            last_garbage_hole: self.last_garbage_hole,
+        });
+        self.save_state_cursor = 0;
+        Some(self.save_states.len())
+    }
+
+    /// Restores a practice save: the newest first, then each older one in turn on repeated
+    /// loads, wrapping back round to the newest. Returns how far back the restored save
+    /// is, 1 being the newest, or None if there was nothing to load.
+    pub fn load_state(&mut self) -> Option<usize> {
+        if self.mode != GameMode::Practice || self.save_states.is_empty() {
+            return None;
+        }
+        let age = self.save_state_cursor % self.save_states.len();
+        self.save_state_cursor = age + 1;
+        let save = self.save_states[self.save_states.len() - 1 - age].clone();
+
+        let snapshot = save.snapshot;
+        self.board = snapshot.board;
+        self.current_block = snapshot.current_block;
+        self.next_block = snapshot.next_block;
+        self.hold = snapshot.hold;
+        self.has_held = snapshot.has_held;
+        self.queue_index = snapshot.queue_index;
+        self.score = save.score;
+        self.clears = save.clears;
+        self.combo = save.combo;
+        self.back_to_back = save.back_to_back;
+        self.pieces_placed = save.pieces_placed;
+        self.randomizer = save.randomizer;
+        self.garbage_rng = save.garbage_rng;
+        self.last_garbage_hole = save.last_garbage_hole;
+
+        // Whatever was in progress belongs to the game being left behind
+        self.undo_stack.clear();
+        self.clear_frames.clear();
+        self.collapse = None;
+        self.events.clear();
+        self.state = GameState::Playing;
+        self.phase = Phase::Active;
+        self.input_buffer = InputBuffer::default();
+        self.timer = GameTimer::default();
+        self.last_move_rotation = false;
+        self.finesse.inputs = 0;
+        Some(age + 1)
+    }
+
+    /// Takes back the last placement in practice mode, including one that topped out.
+    pub fn undo(&mut self) -> bool {
+        if self.mode != GameMode::Practice {
+            return false;
+        }
+        let Some(snapshot) = self.undo_stack.pop_back() else {
+            return false;
+        };
+
+        self.board = snapshot.board;
+        self.current_block = snapshot.current_block;
+        self.next_block = snapshot.next_block;
+        self.hold = snapshot.hold;
+        self.has_held = snapshot.has_held;
+        self.queue_index = snapshot.queue_index;
+        self.state = GameState::Playing;
+        self.timer = GameTimer::default();
+        self.last_move_rotation = false;
+        self.phase = Phase::Active;
+        self.input_buffer = InputBuffer::default();
+        true
+    }
+
+    /// Picks up the connection again once a reconnection attempt gets through. The server
+    /// tells us our id, room and the other players over it as for a new one.
+    fn poll_reconnect(&mut self) {
+        let Some(reconnecting) = self.reconnecting.take_if(|handle| handle.is_finished()) else {
+            return;
+        };
+        if let Some(Ok(Some(client))) = reconnecting.now_or_never() {
+            self.multiplayer = Some(client);
+            self.connection_lost = false;
+            self.game_state_sender.reset();
+            self.board_state_sender.reset();
+        }
+    }
+
+    /// Whether we're trying to get back to the server after losing the connection.
+    pub fn is_reconnecting(&self) -> bool {
+        self.reconnecting.is_some()
+    }
+
+    /// How the connection stands, or None if the game was never online.
+    pub fn connection_status(&self) -> Option<ConnectionStatus> {
+        if self.multiplayer.is_some() {
+            Some(ConnectionStatus::Connected)
+        } else if self.is_reconnecting() {
+            Some(ConnectionStatus::Reconnecting)
+        } else {
+            self.connection_lost.then_some(ConnectionStatus::Offline)
+        }
+    }
+
+    pub fn net_stats(&mut self) -> Option<NetStats> {
+        self.multiplayer.as_mut().map(MultiplayerClient::net_stats)
+    }
+
+    /// Goes back to the lobby, giving up any game in progress.
+    pub fn leave_room(&mut self) {
+        if matches!(self.state, GameState::Playing | GameState::Paused) {
+            self.forfeit();
+        }
+        self.send_own(|player_id| GameMessage::LeaveRoom { player_id });
+        self.restore_local_config();
+    }
 
-        self.board = Board::new();
-        self.current_block = Block::new(BlockKind::random());
-        self.next_block = Block::new(BlockKind::random());
-        self.hold_block = None;
-        self.has_held = false;
-        self.state = GameState::Playing;
-        self.score = Score::default();
-        self.timer = GameTimer::default();
-        self.screen_shake = ScreenShake::default();
-        self.lines_just_cleared = false;
+    // Goes back to our own config after playing by a room's rules
+    fn restore_local_config(&mut self) {
+        if let Some(config) = self.local_config.take() {
+            self.config = config;
+        }
+    }
 
-        // Restore multiplayer state
-        self.multiplayer = multiplayer;
-        self.player_id = player_id;
-        self.other_players = other_players;
+    /// Tells the other players we're leaving, giving up any game in progress so the match
+    /// still places us, and closes the connection once that has gone out.
+    pub async fn leave_multiplayer(&mut self) {
+        if let Some(reconnecting) = self.reconnecting.take() {
+            reconnecting.abort();
+        }
+        self.leave_room();
+        self.send_own(|player_id| GameMessage::PlayerLeft { player_id });
+        if let Some(client) = self.multiplayer.take() {
+            client.close().await;
+        }
     }
 
     pub async fn connect_multiplayer(&mut self, server_addr: &str) -> Result<(), Box<dyn std::error::Error>> {
         let client = MultiplayerClient::connect(server_addr).await?;
         self.multiplayer = Some(client);
+        self.server_addr = Some(server_addr.to_string());
+        // Everyone plays in real time
+        self.speed = GameSpeed::default();
         Ok(())
     }
 
-    pub fn clear_lines(&mut self) -> u32 {
-        let lines = self.board.clear_lines();
+    /// Asks the server for a new room, locked if `password` is given.
+    pub fn create_room(&mut self, name: &str, password: Option<String>) {
+        if let Some(client) = &self.multiplayer {
+            self.room_error = None;
+            client.send(GameMessage::CreateRoom {
+                name: name.to_string(),
+                password,
+            });
+        }
+    }
+
+    /// Asks to change our room's rules, which the server only allows its owner.
+    pub fn set_room_rules(&mut self, rules: RoomRules) {
+        if let Some(client) = &self.multiplayer {
+            self.room_error = None;
+            client.send(GameMessage::RoomRules { rules });
+        }
+    }
+
+    pub fn join_room(&mut self, room: &str, password: Option<String>) {
+        if let Some(client) = &self.multiplayer {
+            self.room_error = None;
+            client.send(GameMessage::JoinRoom {
+                room: room.to_string(),
+                password,
+            });
+        }
+    }
+
+    /// Waits for the server to pair us with another player for a 1v1.
+    pub fn queue_for_match(&mut self) {
+        if let Some(client) = &self.multiplayer {
+            client.send(GameMessage::QueueForMatch);
+            self.queued_at = Some(Instant::now());
+        }
+    }
+
+    pub fn leave_queue(&mut self) {
+        if let Some(client) = &self.multiplayer {
+            client.send(GameMessage::LeaveQueue);
+        }
+        self.queue_position = None;
+        self.queued_at = None;
+    }
+
+    pub fn receive_garbage(&mut self, lines: u32) {
+        let config = self.config.garbage;
+        self.insert_garbage(lines, &config);
+    }
+
+    fn insert_garbage(&mut self, lines: u32, config: &GarbageConfig) {
+        if lines == 0 || matches!(self.state, GameState::GameOver | GameState::Finished) {
+            return;
+        }
+
+        // A fresh column for every attack, so consecutive attacks don't line up
+        let hole = match self.last_garbage_hole {
+            Some(last) => (last + self.garbage_rng.gen_range(1..BOARD_WIDTH)) % BOARD_WIDTH,
+            None => self.garbage_rng.gen_range(0..BOARD_WIDTH),
+        };
+        self.last_garbage_hole = Some(hole);
+        let topped_out =
+            self.board
+                .add_garbage_lines(lines as i32, hole, config, &mut self.garbage_rng);
+        self.push_event(GameEvent::GarbageReceived { lines });
+
+        // Between pieces the current block is the one that just locked, already part of
+        // the board, so only the stack itself can overflow
+        if self.phase != Phase::Active {
+            if topped_out {
+                self.top_out();
+            }
+            return;
+        }
+
+        // Push the falling piece up out of the risen garbage
+        let mut raised = 0;
+        while !self.board.is_valid_position(&self.current_block) && raised < lines {
+            self.current_block.y -= 1;
+            raised += 1;
+        }
+
+        if topped_out || !self.board.is_valid_position(&self.current_block) {
+            self.top_out();
+        }
+    }
+
+    /// Garbage lines still to clear before the cheese race is won.
+    pub fn cheese_remaining(&self) -> u32 {
+        self.config.cheese.target_lines.saturating_sub(self.garbage_cleared)
+    }
+
+    /// Tops the cheese back up to its configured height, without adding more
+    /// garbage than is left to clear.
+    fn refill_cheese(&mut self) {
+        let on_board = self.board.garbage_rows() as u32;
+        let missing = self.config.cheese.rows.saturating_sub(on_board);
+        let left_to_add = self.cheese_remaining().saturating_sub(on_board);
+        let config = self.config.cheese.garbage;
+        self.insert_garbage(missing.min(left_to_add), &config);
+    }
+
+    /// Takes the garbage produced by recent clears, for delivery to a local opponent.
+    pub fn take_outgoing_garbage(&mut self) -> u32 {
+        std::mem::take(&mut self.outgoing_garbage)
+    }
+
+    /// Sends the `attack` a clear of `lines` lines was worth, which cancels pending garbage
+    /// before the rest goes out as ours, and tells other players about the clear.
+    fn send_clear(&mut self, lines: u32, attack: u32, kind: AttackKind) {
         if lines > 0 {
-            self.lines_just_cleared = true;
+            let (sent, cancelled) = self.garbage_queue.cancel(attack);
+            for cut in cancelled {
+                self.push_event(GameEvent::AttackCancelled {
+                    id: cut.id,
+                    lines: cut.cancelled,
+                    remaining: cut.remaining,
+                });
+            }
+            self.outgoing_garbage += sent;
+            self.lines_sent += sent;
             // Send line clear message in multiplayer
             if let Some(client) = &self.multiplayer {
                 if let Some(player_id) = &self.player_id {
                     client.send(GameMessage::LineCleared {
                         player_id: player_id.clone(),
                         count: i32::try_from(lines).unwrap_or(0),
+                        sent: Some(sent),
+                        kind,
                     });
                 }
             }
         }
-        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::multiplayer::{MultiplayerServer, ServerPaths};
+
+    /// A game in progress on the given board layout, see `Board::from_ascii`.
+    fn game_on(layout: &str) -> Game {
+        let mut game = Game::with_seed(1);
+        game.start_game_with_seed(1);
+        game.board = Board::from_ascii(layout).unwrap();
+        game
+    }
+
+    #[test]
+    fn t_spin_double_is_detected_on_lock() {
+        let mut game = game_on(
+            "
+            ...GG.....
+            GG...GGGGG
+            GGG.GGGGGG",
+        );
+        // Pointing down into the slot, having just rotated in
+        game.current_block = Block {
+            kind: BlockKind::T,
+            x: 3,
+            y: 18,
+            rotation: 2,
+        };
+        game.last_move_rotation = true;
+
+        let result = game.lock_current_block();
+        assert_eq!(result.t_spin, TSpinKind::Full);
+        assert_eq!(result.lines_cleared, 2);
+        assert_eq!(result.cleared_rows, [18, 19]);
+        // Its points count as a T-spin's rather than a plain double's
+        assert_eq!(game.score_sources.t_spins, game.score.points);
+        assert_eq!(game.score_sources.clears, 0);
+        assert_eq!(game.best_combo, 1);
+    }
+
+    #[test]
+    fn t_spin_needs_a_rotation_and_three_corners() {
+        let layout = "
+            ...GG.....
+            GG...GGGGG
+            GGG.GGGGGG";
+        let slot = Block {
+            kind: BlockKind::T,
+            x: 3,
+            y: 18,
+            rotation: 2,
+        };
+        let mut game = game_on(layout);
+        game.current_block = slot;
+        game.last_move_rotation = false;
+        assert_eq!(game.lock_current_block().t_spin, TSpinKind::None);
+
+        // Only the two front corners blocked
+        let mut game = game_on(
+            "
+            ..........
+            GG...GGGGG
+            GGG.GGGGGG",
+        );
+        game.current_block = slot;
+        game.last_move_rotation = true;
+        assert_eq!(game.lock_current_block().t_spin, TSpinKind::None);
+    }
+
+    #[test]
+    fn line_clear_waits_in_clearing_then_spawns_the_next_piece() {
+        let mut game = game_on("IIIII.IIII");
+        game.current_block = Block {
+            kind: BlockKind::I,
+            x: 5,
+            y: 17,
+            rotation: 1,
+        };
+        let next = game.next_block.kind;
+
+        let result = game.lock_current_block();
+        assert_eq!(result.lines_cleared, 1);
+        assert_eq!(game.phase, Phase::Clearing);
+        assert_eq!(game.clearing_rows(), [19]);
+        assert!(!game.is_controllable());
+
+        assert!(game.collapse().is_none());
+
+        game.tick(LINE_CLEAR_DELAY);
+        assert_eq!(game.phase, Phase::Active);
+        assert!(game.clearing_rows().is_empty());
+        assert_eq!(game.current_block.kind, next);
+
+        // Then the rest of the I slides down over the cleared row
+        assert_eq!(game.collapse().unwrap().lift(19), 1.0);
+        game.tick(COLLAPSE_DURATION / 2);
+        let collapse = game.collapse().unwrap();
+        assert_eq!((collapse.lift(0), collapse.lift(19)), (0.0, 0.5));
+        game.tick(COLLAPSE_DURATION / 2);
+        assert!(game.collapse().is_none());
+    }
+
+    #[test]
+    fn rotate_pressed_mid_clear_applies_to_the_next_piece() {
+        let mut game = game_on("IIIII.IIII");
+        game.current_block = Block {
+            kind: BlockKind::I,
+            x: 5,
+            y: 17,
+            rotation: 1,
+        };
+        // An O looks the same either way round, so make sure the next piece isn't one
+        game.next_block = Block::new(BlockKind::T);
+        game.lock_current_block();
+
+        assert!(!game.apply(Action::RotateCw));
+        game.tick(LINE_CLEAR_DELAY);
+        assert_eq!(game.current_block.kind, BlockKind::T);
+        assert_eq!(game.current_block.rotation, 1);
+    }
+
+    #[test]
+    fn garbage_holes_follow_the_game_seed() {
+        let garbage = |seed: u64| {
+            let mut game = Game::with_seed(seed);
+            game.start_game_with_seed(seed);
+            game.receive_garbage(3);
+            game.receive_garbage(2);
+            game.board.to_string()
+        };
+        assert_eq!(garbage(7), garbage(7));
+        assert_ne!(garbage(7), garbage(8));
+    }
+
+    #[test]
+    fn an_attack_keeps_one_hole_column() {
+        for seed in 0..20 {
+            let mut game = Game::with_seed(seed);
+            game.start_game_with_seed(seed);
+            game.receive_garbage(4);
+            let holes = game.board.garbage_holes(4);
+            assert!(holes.iter().all(|&hole| hole == holes[0]), "seed {}: {:?}", seed, holes);
+        }
+    }
+
+    #[test]
+    fn consecutive_attacks_move_the_hole() {
+        for seed in 0..20 {
+            let mut game = Game::with_seed(seed);
+            game.start_game_with_seed(seed);
+            game.receive_garbage(2);
+            game.receive_garbage(2);
+            // The later attack pushed in under the first
+            let holes = game.board.garbage_holes(4);
+            assert_eq!(holes[0], holes[1]);
+            assert_eq!(holes[2], holes[3]);
+            assert_ne!(holes[0], holes[2], "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn rising_garbage_tops_out_a_nearly_full_board() {
+        let mut game = Game::default();
+        game.start_game_with_seed(1);
+        game.receive_garbage(4);
+        assert_eq!(game.state, GameState::Playing, "an empty board has room");
+
+        let mut game = game_on(&"GGGG.GGGGG\n".repeat(BOARD_HEIGHT - 2));
+        game.receive_garbage(4);
+        assert_eq!(game.state, GameState::GameOver);
+    }
+
+    #[test]
+    fn rising_garbage_pushes_the_falling_piece_up() {
+        let mut game = game_on("");
+        game.current_block = Block {
+            kind: BlockKind::O,
+            x: 4,
+            y: 19,
+            rotation: 0,
+        };
+        game.receive_garbage(2);
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.current_block.y, 17);
+        assert!(game.board.is_valid_position(&game.current_block));
+    }
+
+    #[test]
+    fn garbage_during_entry_delay_does_not_top_out() {
+        let mut game = Game::default();
+        game.config.entry_delay = Duration::from_millis(200);
+        game.start_game_with_seed(1);
+        game.hard_drop();
+        assert!(matches!(game.phase, Phase::Entry { .. }));
+
+        game.receive_garbage(2);
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.board.garbage_rows(), 2);
+    }
+
+    #[test]
+    fn classic_uses_nes_scoring_and_gravity() {
+        assert_eq!(nes_frames_per_row(0), 48);
+        assert_eq!(nes_frames_per_row(8), 8);
+        assert_eq!(nes_frames_per_row(9), 6);
+        assert_eq!(nes_frames_per_row(19), 2);
+        assert_eq!(nes_frames_per_row(29), 1);
+
+        // Starting high waits longer for the first level up
+        assert_eq!(classic_level(0, 9), 0);
+        assert_eq!(classic_level(0, 10), 1);
+        assert_eq!(classic_level(19, 139), 19);
+        assert_eq!(classic_level(19, 140), 20);
+
+        let mut game = Game::default();
+        game.start_classic(25);
+        assert_eq!(game.config.start_level, CLASSIC_MAX_START_LEVEL);
+        assert_eq!(game.score.level, CLASSIC_MAX_START_LEVEL);
+        game.update_score(4);
+        assert_eq!(game.score.points, 1200 * 20);
+        game.update_score(1);
+        assert_eq!(game.score.points, 1200 * 20 + 40 * 20);
+
+        let interval = game.timer.get_fall_interval(19, GameMode::Classic);
+        assert_eq!(interval, Duration::from_secs_f32(2.0 / NES_FPS));
+    }
+
+    #[test]
+    fn fixed_goal_levels_every_ten_lines() {
+        assert_eq!(Leveling::Fixed.level(1, 0), 1);
+        assert_eq!(Leveling::Fixed.level(1, 9), 1);
+        assert_eq!(Leveling::Fixed.level(1, 10), 2);
+        assert_eq!(Leveling::Fixed.level(1, 45), 5);
+        assert_eq!(Leveling::Fixed.level(8, 9), 8);
+        assert_eq!(Leveling::Fixed.level(8, 10), 9);
+        assert_eq!(Leveling::Fixed.level(15, 100), 25);
+    }
+
+    #[test]
+    fn variable_goal_takes_five_lines_per_level() {
+        // 5 lines for level 1, 10 for level 2, 15 for level 3...
+        assert_eq!(Leveling::Variable.level(1, 4), 1);
+        assert_eq!(Leveling::Variable.level(1, 5), 2);
+        assert_eq!(Leveling::Variable.level(1, 14), 2);
+        assert_eq!(Leveling::Variable.level(1, 15), 3);
+        assert_eq!(Leveling::Variable.level(1, 30), 4);
+        // Starting at 5 counts from level 5's goal
+        assert_eq!(Leveling::Variable.level(5, 24), 5);
+        assert_eq!(Leveling::Variable.level(5, 25), 6);
+        assert_eq!(Leveling::Variable.level(5, 55), 7);
+        assert_eq!(Leveling::Variable.level(10, 49), 10);
+        assert_eq!(Leveling::Variable.level(10, 50), 11);
+    }
+
+    #[test]
+    fn marathon_starts_at_the_chosen_level() {
+        let mut game = Game::default();
+        game.start_marathon(5);
+        assert_eq!(game.score.level, 5);
+        let interval = game.timer.get_fall_interval(5, GameMode::Marathon);
+        game.tick(Duration::ZERO);
+        assert_eq!(game.timer.fall_interval, interval);
+        assert_eq!(game.lines_to_next_level(), 10);
+        game.update_score(4);
+        assert_eq!(game.score.level, 5);
+        assert_eq!(game.lines_to_next_level(), 6);
+        game.update_score(4);
+        game.update_score(2);
+        assert_eq!(game.score.level, 6);
+        assert_eq!(game.level_progress(), 0.0);
+        assert!(game.gravity() > gravity_rows_per_sec(5));
+        game.update_score(3);
+        assert_eq!(game.level_progress(), 0.3);
+        // A tetris across the boundary carries its spare lines into the next level
+        game.update_score(4);
+        game.update_score(4);
+        assert_eq!((game.score.level, game.lines_to_next_level()), (7, 9));
+        assert_eq!(game.level_progress(), 0.1);
+
+        game.config.leveling = Leveling::Variable;
+        game.start_game();
+        assert_eq!(game.score.level, 5);
+        assert_eq!(game.lines_to_next_level(), 25);
+        game.update_score(4);
+        game.update_score(4);
+        game.update_score(4);
+        game.update_score(4);
+        game.update_score(4);
+        game.update_score(4);
+        game.update_score(4);
+        // 28 lines: level 5's 25, then 3 of level 6's 30
+        assert_eq!((game.score.level, game.lines_to_next_level()), (6, 27));
+        assert_eq!(game.level_progress(), 0.1);
+
+        game.start_marathon(99);
+        assert_eq!(game.score.level, MAX_START_LEVEL);
+        // Other modes always start at 1
+        game.start_sprint(1);
+        assert_eq!(game.score.level, 1);
+        assert_eq!(game.lines_to_next_level(), 5);
+    }
+
+    #[test]
+    fn gravity_follows_the_curve_and_plateaus_at_20g() {
+        assert_eq!(gravity_rows_per_sec(1), 1.0);
+        assert!((gravity_rows_per_sec(10) - 15.588).abs() < 0.01);
+        assert_eq!(gravity_rows_per_sec(20), MAX_GRAVITY);
+        assert_eq!(gravity_rows_per_sec(50), MAX_GRAVITY);
+
+        let timer = GameTimer::default();
+        assert_eq!(timer.get_fall_interval(1, GameMode::Marathon), INITIAL_FALL_INTERVAL);
+        assert_eq!(
+            timer.get_fall_interval(50, GameMode::Marathon),
+            timer.get_fall_interval(20, GameMode::Marathon)
+        );
+
+        // At 20G a single frame moves the piece most of the way down the board
+        let mut game = game_on("");
+        game.score.level = 20;
+        let start = game.current_block.y;
+        game.tick(Duration::from_micros(16_667));
+        assert!(game.current_block.y - start >= 19);
+    }
+
+    /// Rows a soft dropped piece falls in `time` at `level`, ticking at 200 frames a second.
+    fn soft_drop_rows(level: u32, time: Duration) -> i32 {
+        let mut game = game_on("");
+        game.score.level = level;
+        game.timer.soft_drop = true;
+        let start = game.current_block.y;
+        let frame = Duration::from_millis(5);
+        for _ in 0..time.as_millis() / frame.as_millis() {
+            game.tick(frame);
+        }
+        game.current_block.y - start
+    }
+
+    #[test]
+    fn soft_drop_has_its_own_clamped_rate() {
+        let interval = |level| {
+            let timer = GameTimer {
+                fall_interval: GameTimer::default().get_fall_interval(level, GameMode::Marathon),
+                ..GameTimer::default()
+            };
+            timer.get_soft_drop_interval(DEFAULT_SOFT_DROP_FACTOR)
+        };
+        assert_eq!(interval(1), SOFT_DROP_MAX_INTERVAL);
+        assert_eq!(interval(10), SOFT_DROP_MIN_INTERVAL);
+        assert_eq!(interval(20), SOFT_DROP_MIN_INTERVAL);
+
+        // 20 rows a second at level 1, and one a frame once gravity is fast
+        assert!((3..=4).contains(&soft_drop_rows(1, Duration::from_millis(200))));
+        assert!((11..=12).contains(&soft_drop_rows(10, Duration::from_millis(200))));
+    }
+
+    #[test]
+    fn soft_drop_to_the_floor_waits_for_lock_delay() {
+        let mut game = game_on("");
+        game.score.level = 10;
+        game.timer.soft_drop = true;
+        while !game.is_grounded() {
+            game.tick(Duration::from_millis(5));
+        }
+        game.tick(LOCK_DELAY / 2);
+        assert_eq!(game.pieces_placed, 0);
+        game.tick(LOCK_DELAY / 2);
+        assert_eq!(game.pieces_placed, 1);
+    }
+
+    #[test]
+    fn sonic_drop_reaches_the_floor_without_locking() {
+        let mut game = game_on("");
+        assert!(game.apply(Action::SonicDrop));
+        assert!(game.is_grounded());
+        assert_eq!(game.pieces_placed, 0);
+    }
+
+    #[test]
+    fn lock_result_for_a_plain_lock_a_single_and_a_top_out() {
+        let mut game = game_on("");
+        game.current_block = Block::new(BlockKind::O);
+        assert_eq!(
+            game.hard_drop(),
+            LockResult {
+                locked: true,
+                drop_distance: 21,
+                ..LockResult::default()
+            }
+        );
+
+        let mut game = game_on("OOOO..OOOO");
+        game.current_block = Block::new(BlockKind::O);
+        let result = game.hard_drop();
+        assert!(result.locked);
+        assert_eq!(result.lines_cleared, 1);
+        assert_eq!(result.cleared_rows, [19]);
+        assert!(!result.game_over);
+
+        // Locking while still above the board tops out
+        let mut game = game_on("");
+        game.current_block = Block::new(BlockKind::O);
+        let result = game.lock_current_block();
+        assert!(!result.locked);
+        assert!(result.game_over);
+        assert_eq!(game.state, GameState::GameOver);
+    }
+
+    #[test]
+    fn holding_a_piece_with_no_room_to_enter_blocks_out() {
+        let stack = "#########.\n".repeat(19);
+        let buried = format!("...####...\n{stack}");
+
+        // A first hold brings out the next piece
+        let mut game = game_on(&buried);
+        game.next_block = Block::new(BlockKind::T);
+        assert!(game.hold_piece());
+        assert_eq!(game.current_block.kind, BlockKind::T);
+        assert_eq!(game.state, GameState::GameOver);
+        assert!(game.drain_events().contains(&GameEvent::ToppedOut));
+
+        // A swap brings back the held piece
+        let mut game = game_on("");
+        game.current_block = Block::new(BlockKind::O);
+        assert!(game.hold_piece());
+        game.has_held = false;
+        game.board = Board::from_ascii(&buried).unwrap();
+        assert!(game.hold_piece());
+        assert_eq!(game.current_block.kind, BlockKind::O);
+        assert_eq!(game.state, GameState::GameOver);
+
+        // One row lower the stack leaves room for the piece a row higher than usual
+        let mut game = game_on(&format!("..........\n...####...\n{}", &stack[11..]));
+        game.next_block = Block::new(BlockKind::T);
+        assert!(game.hold_piece());
+        assert_eq!(game.state, GameState::Playing);
+        assert!(!game.drain_events().contains(&GameEvent::ToppedOut));
+    }
+
+    #[test]
+    fn mutators_do_nothing_unless_playing() {
+        for state in [GameState::Paused, GameState::GameOver] {
+            let mut game = game_on("IIII......");
+            game.state = state;
+            let board = format!("{:#}", game.board);
+            let piece = game.current_block;
+            let hold = game.hold;
+            let points = game.score.points;
+
+            assert!(!game.move_current_block(1, 0));
+            assert!(!game.rotate_current_block());
+            assert!(!game.hold_piece());
+            assert!(!game.sonic_drop());
+            for action in [
+                Action::MoveLeft,
+                Action::MoveRight,
+                Action::SoftDrop,
+                Action::SonicDrop,
+                Action::RotateCw,
+                Action::HardDrop,
+                Action::Hold,
+            ] {
+                assert!(!game.apply(action), "{:?} in {:?}", action, state);
+            }
+            assert!(!game.hard_drop().locked);
+            assert!(!game.lock_current_block().locked);
+            assert_eq!(game.tick(LOCK_DELAY * 4), None);
+
+            assert_eq!(format!("{:#}", game.board), board);
+            assert_eq!(game.current_block, piece);
+            assert_eq!(game.hold, hold);
+            assert_eq!(game.score.points, points);
+            assert_eq!(game.state, state);
+        }
+
+        // Pausing can't bring a finished game back either
+        let mut game = game_on("");
+        game.state = GameState::GameOver;
+        game.toggle_pause();
+        assert_eq!(game.state, GameState::GameOver);
+    }
+
+    #[test]
+    fn resuming_counts_down_first() {
+        let mut game = game_on("");
+        game.toggle_pause();
+        game.toggle_pause();
+        assert_eq!(game.state, GameState::Paused);
+        assert_eq!(game.resume_countdown(), Some(3));
+        // Pressing pause again doesn't stop the countdown to look at the board for longer
+        game.toggle_pause();
+        assert_eq!(game.resume_countdown(), Some(3));
+
+        game.timer.last_update = Instant::now() - RESUME_COUNTDOWN / 2;
+        game.update();
+        assert_eq!(game.resume_countdown(), Some(2));
+        game.timer.last_update = Instant::now() - RESUME_COUNTDOWN / 2;
+        game.update();
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.resume_countdown(), None);
+    }
+
+    #[test]
+    fn idle_pauses_resume_through_the_countdown() {
+        let mut game = game_on("");
+        game.idle_pause();
+        assert!(game.is_idle_paused());
+        // Nothing happens to a game that's already paused
+        game.idle_pause();
+        game.toggle_pause();
+        assert_eq!(game.resume_countdown(), Some(3));
+        assert!(game.is_idle_paused());
+        game.timer.last_update = Instant::now() - RESUME_COUNTDOWN;
+        game.update();
+        assert_eq!(game.state, GameState::Playing);
+        assert!(!game.is_idle_paused());
+
+        // A pause the player asks for isn't an idle one
+        game.toggle_pause();
+        assert!(!game.is_idle_paused());
+    }
+
+    #[test]
+    fn assist_suggests_holding_for_the_well_and_keeps_the_game_off_the_records() {
+        let mut game = game_on(
+            "
+            GGGGGGGGG.
+            GGGGGGGGG.
+            GGGGGGGGG.
+            GGGGGGGGG.",
+        );
+        game.current_block = Block::new(BlockKind::O);
+        game.next_block = Block::new(BlockKind::I);
+        assert!(game.plays_for_records());
+        assert_eq!(game.suggestion(), None);
+
+        game.set_assist(true);
+        let suggestion = game.suggestion().unwrap();
+        assert_eq!(suggestion.landed.kind, BlockKind::O);
+        // The I clears all four rows, which beats anywhere the O goes
+        assert!(suggestion.hold_better);
+        game.hold_piece();
+        let suggestion = game.suggestion().unwrap();
+        assert_eq!(suggestion.landed.kind, BlockKind::I);
+        assert!(!suggestion.hold_better);
+
+        // Turning it back off doesn't put the game back on the records, a new game does
+        game.set_assist(false);
+        assert!(!game.plays_for_records());
+        game.start_game_with_seed(2);
+        assert!(game.plays_for_records());
+    }
+
+    #[test]
+    fn held_keys_apply_to_the_next_piece_as_it_spawns() {
+        let spawn_after_lock = |initial_actions: bool, hold: bool, rotate: bool| {
+            let mut game = game_on("");
+            game.config.initial_actions = initial_actions;
+            game.current_block = Block::new(BlockKind::O);
+            game.next_block = Block::new(BlockKind::T);
+            game.hold_requested = hold;
+            game.rotation_requested = rotate;
+            game.hard_drop();
+            game
+        };
+
+        let game = spawn_after_lock(true, true, false);
+        assert_eq!(game.hold.blocks().map(|block| block.kind).collect::<Vec<_>>(), [BlockKind::T]);
+        assert!(game.has_held);
+
+        let game = spawn_after_lock(true, false, true);
+        assert_eq!(game.current_block.kind, BlockKind::T);
+        assert_eq!(game.current_block.rotation, 1);
+
+        // Off by default: held keys wait for a fresh press
+        let game = spawn_after_lock(false, true, true);
+        assert!(game.hold.is_empty());
+        assert_eq!(game.current_block.kind, BlockKind::T);
+        assert_eq!(game.current_block.rotation, 0);
+    }
+
+    #[tokio::test]
+    async fn lost_connection_carries_on_offline() {
+        let mut game = game_on("");
+        game.multiplayer = Some(MultiplayerClient::disconnected());
+        game.player_id = Some("me".to_string());
+        game.match_active = true;
+        game.other_players.insert("them".to_string(), PlayerInfo::new());
+        game.garbage_queue.push("them".to_string(), 4);
+
+        game.update();
+        assert!(game.connection_lost);
+        assert!(game.multiplayer.is_none());
+        assert!(game.other_players.is_empty());
+        assert!(!game.match_active);
+        assert_eq!(game.pending_garbage(), 0);
+        assert_eq!(game.state, GameState::Playing);
+
+        // The game itself goes on, and queued garbage never lands
+        game.update();
+        assert_eq!(game.board.garbage_rows(), 0);
+        assert!(game.apply(Action::MoveLeft));
+    }
+
+    #[tokio::test]
+    async fn room_rules_stay_behind_when_we_go_offline() {
+        let mut game = game_on("");
+        game.config.hold_slots = MAX_HOLD_SLOTS;
+        let own = game.config.clone();
+        game.multiplayer = Some(MultiplayerClient::disconnected());
+        game.room_rules = RoomRules {
+            lock_delay_ms: 1500,
+            hold: false,
+            perfect_clear_attack: 4,
+            garbage_delay_pieces: 3,
+            ..RoomRules::default()
+        };
+
+        game.start_game_with_seed(1);
+        assert_eq!(game.config.hold_slots, 0);
+        assert_eq!(game.config.lock_delay, Duration::from_millis(1500));
+        // A rematch builds on our own config again, not the last match's
+        game.start_game_with_seed(2);
+        assert_eq!(game.local_config.as_ref(), Some(&own));
+        // Back in the lobby we play by our own config until the next match starts
+        game.leave_room();
+        assert_eq!(game.config, own);
+
+        game.start_game_with_seed(3);
+        assert_eq!(game.config.hold_slots, 0);
+        game.update();
+        assert!(game.multiplayer.is_none());
+        game.start_game_with_seed(4);
+        assert_eq!(game.config, own);
+    }
+
+    #[tokio::test]
+    async fn ended_game_keeps_polling_and_announces_new_game_once_known() {
+        let server = MultiplayerServer::with_paths(ServerPaths::scratch("server-18141"));
+        tokio::spawn(async move { server.start("127.0.0.1:18141").await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut game = Game::default();
+        game.connect_multiplayer("ws://127.0.0.1:18141").await.unwrap();
+        game.start_game();
+        assert!(game.player_id.is_none());
+        assert!(game.new_game_pending);
+
+        game.state = GameState::GameOver;
+        for _ in 0..100 {
+            game.update();
+            if game.player_id.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(game.player_id.is_some());
+        assert!(!game.new_game_pending);
+    }
+
+    #[tokio::test]
+    async fn match_pauses_are_limited_and_reset_each_game() {
+        let server = MultiplayerServer::with_paths(ServerPaths::scratch("server-18130"));
+        tokio::spawn(async move { server.start("127.0.0.1:18130").await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut game = Game::default();
+        game.connect_multiplayer("ws://127.0.0.1:18130").await.unwrap();
+        game.start_game();
+        // Outside a match pausing is unlimited
+        assert_eq!(game.pauses_left(), None);
+
+        game.match_active = true;
+        for _ in 0..MATCH_PAUSES {
+            game.toggle_pause();
+            assert_eq!(game.state, GameState::Paused);
+            game.toggle_pause();
+            game.resume();
+        }
+        assert_eq!(game.pauses_left(), Some(0));
+        game.toggle_pause();
+        assert_eq!(game.state, GameState::Playing);
+
+        game.start_game();
+        assert_eq!(game.pauses_left(), Some(MATCH_PAUSES));
+    }
+
+    /// The kinds of the next `count` pieces to spawn, played out by hard drops.
+    fn upcoming_kinds(game: &mut Game, count: usize) -> Vec<BlockKind> {
+        (0..count)
+            .map(|_| {
+                let kind = game.current_block.kind;
+                game.hard_drop();
+                game.board = Board::new();
+                kind
+            })
+            .collect()
+    }
+
+    /// Practice on an empty board with random pieces from `seed`.
+    fn practice_game(seed: u64) -> Game {
+        let mut game = Game::with_seed(seed);
+        game.mode = GameMode::Practice;
+        game.start_game_with_seed(seed);
+        game
+    }
+
+    #[test]
+    fn save_states_restore_the_whole_practice_game() {
+        let mut game = practice_game(7);
+        game.hard_drop();
+        game.hold_piece();
+        assert_eq!(game.save_state(), Some(1));
+        let board = game.board.get_cells_for_network();
+        let (current, next, hold) = (game.current_block, game.next_block, game.hold);
+        let score = game.score.clone();
+
+        let mut original = practice_game(7);
+        original.hard_drop();
+        original.hold_piece();
+        let expected = upcoming_kinds(&mut original, 12);
+
+        // Play on, take garbage and finish the game off before going back
+        for _ in 0..5 {
+            game.hard_drop();
+        }
+        game.receive_garbage(4);
+        game.hold_piece();
+        game.state = GameState::GameOver;
+
+        assert_eq!(game.load_state(), Some(1));
+        assert_eq!(game.state, GameState::Playing);
+        assert_eq!(game.board.get_cells_for_network(), board);
+        assert_eq!((game.current_block, game.next_block, game.hold), (current, next, hold));
+        assert!(game.has_held);
+        assert_eq!(game.score, score);
+        assert_eq!(game.undo_count(), 0);
+        // The random piece stream carries on from where it was saved
+        game.board = Board::new();
+        assert_eq!(upcoming_kinds(&mut game, 12), expected);
+    }
+
+    #[test]
+    fn repeated_loads_cycle_through_recent_saves() {
+        let mut game = practice_game(3);
+        for _ in 0..SAVE_STATE_SLOTS + 2 {
+            game.save_state();
+            game.hard_drop();
+        }
+        let pieces = |game: &Game| game.board.get_cells_for_network().concat().iter().flatten().count();
+        // Each save has one more piece on the board than the one before
+        let loaded: Vec<(usize, usize)> = (0..SAVE_STATE_SLOTS + 1)
+            .map(|_| {
+                let age = game.load_state().unwrap();
+                (age, pieces(&game) / 4)
+            })
+            .collect();
+        assert_eq!(loaded, [(1, 6), (2, 5), (3, 4), (4, 3), (5, 2), (1, 6)]);
+
+        // A new save starts the cycle over from the newest
+        game.save_state();
+        assert_eq!(game.load_state(), Some(1));
+    }
+
+    #[test]
+    fn save_states_are_practice_only() {
+        let mut game = Game::with_seed(1);
+        game.start_game();
+        assert_eq!(game.save_state(), None);
+        assert_eq!(game.load_state(), None);
+    }
+
+    #[test]
+    fn frozen_games_only_move_by_frame_steps() {
+        let mut game = Game::with_seed(1);
+        game.start_game_with_seed(1);
+        assert!(game.toggle_frozen());
+        game.update();
+        game.update();
+        assert_eq!(game.play_time, Duration::ZERO);
+
+        game.step_frame();
+        game.step_frame();
+        game.update();
+        assert_eq!(game.play_time, DEBUG_FRAME * 2);
+        game.update();
+        assert_eq!(game.play_time, DEBUG_FRAME * 2);
+    }
+
+    #[test]
+    fn slow_motion_scales_game_time() {
+        let mut speed = GameSpeed::default();
+        assert!(!speed.is_scaled());
+        speed.scale = 0.25;
+        assert!(speed.is_scaled());
+        assert_eq!(speed.game_time(Duration::from_secs(2)), Duration::from_millis(500));
+        // Frame steps only count while frozen
+        speed.pending_frames = 3;
+        assert_eq!(speed.game_time(Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn attacks_follow_the_combo_and_back_to_back_table() {
+        use TSpinKind::{Full, Mini, None};
+        // Lines, T-spin, clears before it in the combo, back-to-back, then the attack
+        let table = [
+            (0, None, 0, false, 0),
+            (0, Full, 5, true, 0),
+            (1, None, 0, false, 0),
+            (2, None, 0, false, 1),
+            (3, None, 0, false, 2),
+            (4, None, 0, false, 4),
+            (4, None, 0, true, 5),
+            (1, Mini, 0, false, 0),
+            (2, Mini, 0, false, 1),
+            (2, Mini, 0, true, 2),
+            (1, Full, 0, false, 2),
+            (2, Full, 0, false, 4),
+            (3, Full, 0, false, 6),
+            (3, Full, 0, true, 7),
+            // Back-to-back only counts for tetrises and T-spins
+            (3, None, 0, true, 2),
+            (1, None, 1, false, 0),
+            (1, None, 2, false, 1),
+            (1, None, 3, false, 1),
+            (1, None, 4, false, 2),
+            (1, None, 5, false, 2),
+            (1, None, 6, false, 3),
+            (1, None, 7, false, 3),
+            (1, None, 8, false, 4),
+            (1, None, 9, false, 4),
+            (1, None, 10, false, 4),
+            (1, None, 11, false, 5),
+            (1, None, 40, false, 5),
+            (1, None, -1, false, 0),
+            (2, None, 4, false, 3),
+            (4, None, 6, true, 8),
+            (2, Full, 11, true, 10),
+        ];
+        for (lines_cleared, t_spin, combo, b2b, expected) in table {
+            let clear = LockResult {
+                lines_cleared,
+                t_spin,
+                ..LockResult::default()
+            };
+            let attack = attack_for(&clear, combo, b2b);
+            assert_eq!(attack, expected, "{:?} at combo {} b2b {}", clear, combo, b2b);
+        }
+        assert_eq!(max_attack_for_lines(2), 10);
+        assert_eq!(max_attack_for_lines(4), 10);
+        assert_eq!(max_attack_for_lines(0), 0);
+    }
+
+    #[test]
+    fn combos_and_back_to_backs_add_to_what_goes_out() {
+        // Room for two tetrises down the left column, with a row left under them so the
+        // second isn't a perfect clear
+        let mut game = game_on(
+            "
+            .GGGGGGGGG
+            .GGGGGGGGG
+            .GGGGGGGGG
+            .GGGGGGGGG
+            .GGGGGGGGG
+            .GGGGGGGGG
+            .GGGGGGGGG
+            .GGGGGGGGG
+            GGGGGGGGG.",
+        );
+        let mut attacks = Vec::new();
+        for _ in 0..2 {
+            game.current_block = Block::new(BlockKind::I);
+            game.current_block.rotate();
+            let (x, _) = game.current_block.blocks()[0];
+            game.current_block.x -= x;
+            attacks.push(game.hard_drop().attack);
+            game.tick(LINE_CLEAR_DELAY);
+        }
+        // The second is back-to-back, though a combo only adds from its third clear
+        assert_eq!(attacks, [4, 5]);
+        assert_eq!(game.lines_sent, 9);
+        assert_eq!(game.outgoing_garbage, 9);
+    }
+
+    #[test]
+    fn perfect_clears_send_the_big_attack_and_say_so() {
+        let mut game = game_on(
+            "
+            OOOOOOOO..
+            OOOOOOOO..",
+        );
+        game.current_block = Block::new(BlockKind::O);
+        game.current_block.x = 8;
+        let result = game.hard_drop();
+        assert!(result.perfect_clear);
+        // A double alone would be worth 1
+        assert_eq!(result.attack, PERFECT_CLEAR_ATTACK);
+        assert_eq!(game.lines_sent, PERFECT_CLEAR_ATTACK);
+        assert_eq!(game.perfect_clears, 1);
+        assert!(game.drain_events().contains(&GameEvent::PerfectClear));
+    }
+
+    // A chain of singles, worth nothing on their own, that empties the board: the I
+    // clears the garbage row and the Z drops into the gap it leaves under the L
+    const CASCADE_PERFECT_CLEAR: &str = "
+        ........ZZ
+        GGG....GGG
+        LLLLLLLL..";
+
+    #[test]
+    fn cascade_perfect_clears_send_the_big_attack() {
+        let mut game = game_on(CASCADE_PERFECT_CLEAR);
+        game.mode = GameMode::Cascade;
+        game.current_block = Block::new(BlockKind::I);
+        let result = game.hard_drop();
+        assert_eq!(result.chain, 2);
+        assert!(result.perfect_clear);
+        assert_eq!(result.attack, PERFECT_CLEAR_ATTACK);
+        assert_eq!(game.lines_sent, PERFECT_CLEAR_ATTACK);
+        assert!(game.drain_events().contains(&GameEvent::PerfectClear));
+    }
+
+    #[tokio::test]
+    async fn cascade_perfect_clears_warn_the_other_players() {
+        let server = MultiplayerServer::with_paths(ServerPaths::scratch("server-18157"));
+        tokio::spawn(async move { server.start("127.0.0.1:18157").await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut games = [Game::default(), Game::default()];
+        for game in &mut games {
+            game.connect_multiplayer("ws://127.0.0.1:18157").await.unwrap();
+            game.start_game_with_seed(1);
+        }
+        for _ in 0..200 {
+            games.iter_mut().for_each(|game| drop(game.update()));
+            if games.iter().all(|game| game.other_players.len() == 1) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let [sender, receiver] = &mut games;
+        sender.mode = GameMode::Cascade;
+        sender.board = Board::from_ascii(CASCADE_PERFECT_CLEAR).unwrap();
+        sender.current_block = Block::new(BlockKind::I);
+        // The server only believes a clear of lines it has seen on the board
+        for _ in 0..30 {
+            sender.update();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(sender.hard_drop().perfect_clear);
+
+        let mut warned = None;
+        for _ in 0..200 {
+            receiver.update();
+            warned = receiver.drain_events().into_iter().find_map(|event| match event {
+                GameEvent::AttackIncoming { lines, kind, .. } => Some((lines, kind)),
+                _ => None,
+            });
+            if warned.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(warned, Some((PERFECT_CLEAR_ATTACK, AttackKind::PerfectClear)));
+    }
+
+    #[test]
+    fn ko_badges_boost_attacks_up_to_double() {
+        assert_eq!(attack_with_badges(4, 0), 4);
+        assert_eq!(attack_with_badges(4, 1), 5);
+        assert_eq!(attack_with_badges(4, MAX_KO_BADGE_BONUS), 8);
+        assert_eq!(attack_with_badges(4, 10), 8);
+        assert_eq!(attack_with_badges(1, 3), 0);
+    }
+
+    // Four rows with a hole in the left column, so nothing clears
+    const STACK: &str = "
+        .GGGGGGGGG
+        .GGGGGGGGG
+        .GGGGGGGGG
+        .GGGGGGGGG";
+
+    #[test]
+    fn pieces_spawn_resting_on_the_stack_in_20g() {
+        let mut game = game_on(STACK);
+        game.config.instant_gravity = true;
+        assert!(game.hard_drop().locked);
+        assert_eq!(game.phase, Phase::Active);
+        assert!(game.is_grounded());
+        assert!(game.current_block.y > Block::new(game.current_block.kind).y);
+
+        assert!(game.apply(Action::Hold));
+        assert!(game.is_grounded());
+        assert_eq!(game.pieces_placed, 1);
+    }
+
+    #[test]
+    fn pieces_slide_and_kick_on_the_stack_in_20g() {
+        let mut game = game_on(STACK);
+        game.config.instant_gravity = true;
+        game.current_block = Block {
+            kind: BlockKind::T,
+            x: 9,
+            y: 0,
+            rotation: 3,
+        };
+        game.tick(LOCK_DELAY / 2);
+        assert!(game.is_grounded());
+
+        // At the wall the turn kicks left, and each move gives the lock delay back
+        assert!(game.apply(Action::RotateCw));
+        assert_eq!((game.current_block.x, game.current_block.rotation), (8, 0));
+        assert!(game.is_grounded());
+        game.tick(LOCK_DELAY / 2);
+        assert!(game.apply(Action::MoveLeft));
+        assert!(game.is_grounded());
+        game.tick(LOCK_DELAY / 2);
+        assert_eq!(game.pieces_placed, 0);
+        game.tick(LOCK_DELAY / 2);
+        assert_eq!(game.pieces_placed, 1);
+    }
+
+    #[test]
+    fn double_hold_rotates_pieces_through_both_slots() {
+        let mut game = game_on("");
+        game.config.hold_slots = MAX_HOLD_SLOTS;
+        let held = |game: &Game| game.hold.blocks().map(|block| block.kind).collect::<Vec<_>>();
+        let place_and_hold = |game: &mut Game, kind: BlockKind, next: BlockKind| {
+            game.current_block = Block::new(kind);
+            game.next_block = Block::new(next);
+            game.has_held = false;
+            assert!(game.hold_piece());
+            // Once per piece, whichever slot it came from
+            assert!(!game.hold_piece());
+        };
+
+        // A free slot takes the piece and brings out the next one
+        place_and_hold(&mut game, BlockKind::T, BlockKind::I);
+        assert_eq!(game.current_block.kind, BlockKind::I);
+        place_and_hold(&mut game, BlockKind::I, BlockKind::O);
+        assert_eq!(game.current_block.kind, BlockKind::O);
+        assert_eq!(held(&game), [BlockKind::T, BlockKind::I]);
+        assert_eq!(game.hold.next_out(MAX_HOLD_SLOTS).map(|block| block.kind), Some(BlockKind::T));
+
+        // Full, the oldest comes back out, reset to spawn
+        game.move_current_block(1, 2);
+        place_and_hold(&mut game, BlockKind::L, BlockKind::S);
+        assert_eq!(game.current_block, Block::new(BlockKind::T));
+        assert_eq!(game.next_block.kind, BlockKind::S);
+        assert_eq!(held(&game), [BlockKind::I, BlockKind::L]);
+
+        game.start_game_with_seed(1);
+        assert!(game.hold.is_empty() && !game.has_held);
+    }
+
+    #[test]
+    fn incoming_attacks_warn_then_land_as_pieces_lock_unless_cancelled() {
+        let mut game = game_on("");
+        let normal = AttackKind::Normal;
+        game.queue_attacks(vec![("alice".to_string(), 2, normal), ("bob".to_string(), 3, normal)]);
+        assert_eq!(game.pending_garbage(), 5);
+        let incoming = |id, from: &str, lines| GameEvent::AttackIncoming {
+            id,
+            from: from.to_string(),
+            lines,
+            kind: normal,
+        };
+        assert_eq!(game.drain_events(), [incoming(1, "alice", 2), incoming(2, "bob", 3)]);
+
+        // A triple is worth 2, which only cancels Alice's
+        game.send_clear(3, attack_for_lines(3), normal);
+        assert_eq!(game.outgoing_garbage, 0);
+        assert_eq!(game.lines_sent, 0);
+        assert_eq!(
+            game.drain_events(),
+            [GameEvent::AttackCancelled { id: 1, lines: 2, remaining: 0 }]
+        );
+
+        // Bob's lines wait out the piece after the one they arrived during, then land as
+        // that one locks
+        let pieces_left = |game: &Game| -> Vec<u32> {
+            let delay = game.config.garbage_delay_pieces;
+            game.incoming_attacks().map(|attack| attack.pieces_left(delay)).collect()
+        };
+        assert_eq!(pieces_left(&game), [2]);
+        game.current_block = Block::new(BlockKind::O);
+        game.hard_drop();
+        assert_eq!(game.board.garbage_rows(), 0);
+        assert_eq!(pieces_left(&game), [1]);
+        game.current_block = Block::new(BlockKind::O);
+        game.hard_drop();
+        assert_eq!(game.board.garbage_rows(), 3);
+        assert_eq!(game.lines_received, 3);
+        assert_eq!(game.pending_garbage(), 0);
+
+        // However long a piece takes, nothing rises until it locks
+        game.config.garbage_delay_pieces = 0;
+        game.queue_garbage("carol".to_string(), 1);
+        game.current_block = Block::new(BlockKind::O);
+        game.advance(Duration::from_millis(500));
+        assert_eq!(game.board.garbage_rows(), 3);
+        assert_eq!(pieces_left(&game), [1]);
+        game.hard_drop();
+        assert_eq!(game.board.garbage_rows(), 4);
+
+        // A clear with nothing to cancel goes out whole
+        game.send_clear(4, attack_for_lines(4), normal);
+        assert_eq!((game.outgoing_garbage, game.lines_sent), (4, 4));
+    }
+
+    #[test]
+    fn fixed_steps_carry_the_rest_of_each_frame() {
+        let mut clock = FixedStep::default();
+        let frames = [3, 17, 1, 40, 9, 8, 25].map(Duration::from_millis);
+        let steps: u32 = frames.iter().map(|&dt| clock.advance(dt)).sum();
+        let total: Duration = frames.iter().sum();
+        assert_eq!(FIXED_DT * steps + clock.pending, total);
+        assert!((0.0..1.0).contains(&clock.progress()));
+
+        // A long stall catches up a little at a time, and the rest is dropped
+        assert_eq!(clock.advance(Duration::from_secs(5)), MAX_STEPS_PER_FRAME);
+        assert_eq!(clock.advance(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn uneven_frames_play_the_same_as_fixed_steps() {
+        let mut uneven = Game::with_seed(9);
+        uneven.start_game_with_seed(9);
+        let mut fixed = Game::with_seed(9);
+        fixed.start_game_with_seed(9);
+
+        // Thirty seconds of frames from 1 to 40ms, through a lock or two at level 1 gravity
+        let frames = [5, 31, 2, 12, 40, 1, 9].map(Duration::from_millis);
+        let mut elapsed = Duration::ZERO;
+        for dt in frames.iter().cycle().take(2100) {
+            uneven.advance(*dt);
+            elapsed += *dt;
+        }
+        while fixed.play_time < uneven.play_time {
+            fixed.tick(FIXED_DT);
+        }
+        assert!(uneven.pieces_placed > 0);
+        assert_eq!(fixed.play_time, uneven.play_time);
+        assert!(elapsed - uneven.play_time < FIXED_DT);
+        assert_eq!(fixed.pieces_placed, uneven.pieces_placed);
+        assert_eq!(fixed.current_block, uneven.current_block);
+        assert_eq!(fixed.board.get_cells_for_network(), uneven.board.get_cells_for_network());
+    }
+
+    #[test]
+    fn falling_pieces_are_drawn_between_steps() {
+        let mut game = Game::with_seed(3);
+        game.start_game_with_seed(3);
+        // Run up to the step the piece falls a row in, and a third of the next one
+        while game.current_block.y == game.last_step_piece.1.y {
+            game.advance(FIXED_DT);
+        }
+        game.advance(FIXED_DT / 3);
+        let (dx, dy) = game.falling_offset();
+        assert_eq!(dx, 0.0);
+        assert!((dy + 2.0 / 3.0).abs() < 0.01, "{}", dy);
+
+        // A move by the player shows at once
+        game.apply(Action::MoveLeft);
+        assert_eq!(game.falling_offset(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn reported_attacks_are_capped_at_what_the_clear_is_worth() {
+        assert_eq!(sent_attack(4, None, 0, None), 4);
+        assert_eq!(sent_attack(4, Some(1), 0, None), 1);
+        // A tetris can be worth up to 10 with the longest combo and a back-to-back
+        assert_eq!(sent_attack(4, Some(9), 1, None), 9);
+        assert_eq!(sent_attack(4, Some(20), 1, None), 12);
+        assert_eq!(sent_attack(1, Some(20), 0, None), 8);
+        assert_eq!(sent_attack(-2, Some(3), 0, None), 0);
+        // A perfect clear is allowed the room's perfect clear attack
+        assert_eq!(sent_attack(1, Some(20), 0, Some(PERFECT_CLEAR_ATTACK)), 10);
     }
 }
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+
+/// Pieces an attack waits out after the one it arrived during before it lands, time to see
+/// it coming and clear lines to cancel it.
+pub const GARBAGE_DELAY_PIECES: u32 = 1;
+
+/// An attack on its way in, remembering who sent it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingAttack {
+    /// Tells attacks apart, counting up from 1 within a queue.
+    pub id: u64,
+    pub from: String,
+    /// Lines still to land, after any cancelled by our own clears.
+    pub lines: u32,
+    // Pieces locked since it arrived
+    placements: u32,
+}
+
+impl IncomingAttack {
+    /// How many more of our pieces lock before this attack lands with `delay`, counting
+    /// the one it lands under: 1 means the falling piece.
+    pub fn pieces_left(&self, delay: u32) -> u32 {
+        (delay + 1).saturating_sub(self.placements).max(1)
+    }
+}
+
+/// Lines cancelled out of one attack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CancelledLines {
+    pub id: u64,
+    pub cancelled: u32,
+    /// What's left of the attack; 0 when it was cancelled outright.
+    pub remaining: u32,
+}
+
+/// Attacks from other players waiting to land, oldest first. Our own clears cancel them
+/// from the front, and only what they don't cancel goes out as our attack.
+#[derive(Debug, Clone, Default)]
+pub struct GarbageQueue {
+    attacks: VecDeque<IncomingAttack>,
+    next_id: u64,
+}
+
+impl GarbageQueue {
+    /// Queues an attack of `lines` from `from`, returning its id.
+    pub fn push(&mut self, from: String, lines: u32) -> u64 {
+        self.next_id += 1;
+        self.attacks.push_back(IncomingAttack {
+            id: self.next_id,
+            from,
+            lines,
+            placements: 0,
+        });
+        self.next_id
+    }
+
+    pub fn attacks(&self) -> impl Iterator<Item = &IncomingAttack> {
+        self.attacks.iter()
+    }
+
+    /// Lines waiting to land, across every attack.
+    pub fn total(&self) -> u32 {
+        self.attacks.iter().map(|attack| attack.lines).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.attacks.clear();
+    }
+
+    /// Spends an attack of `lines` cancelling pending garbage, oldest first. Returns the
+    /// lines left over to send, and what was cancelled from each attack it reached.
+    pub fn cancel(&mut self, mut lines: u32) -> (u32, Vec<CancelledLines>) {
+        let mut cancelled = Vec::new();
+        while lines > 0 {
+            let Some(attack) = self.attacks.front_mut() else {
+                break;
+            };
+            let taken = lines.min(attack.lines);
+            attack.lines -= taken;
+            lines -= taken;
+            cancelled.push(CancelledLines {
+                id: attack.id,
+                cancelled: taken,
+                remaining: attack.lines,
+            });
+            if attack.lines == 0 {
+                self.attacks.pop_front();
+            }
+        }
+        (lines, cancelled)
+    }
+
+    /// Counts a piece locking, once its clear has cancelled what it could. Returns the
+    /// attacks that have now waited out `delay` more pieces and land, oldest first.
+    pub fn lock(&mut self, delay: u32) -> Vec<IncomingAttack> {
+        for attack in &mut self.attacks {
+            attack.placements += 1;
+        }
+        let due = self.attacks.iter().take_while(|attack| attack.placements > delay).count();
+        self.attacks.drain(..due).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clears_cancel_the_oldest_attacks_first() {
+        let mut queue = GarbageQueue::default();
+        let alice = queue.push("alice".to_string(), 4);
+        let bob = queue.push("bob".to_string(), 2);
+        assert_eq!(queue.total(), 6);
+
+        // A double only dents Alice's attack
+        let (sent, cancelled) = queue.cancel(2);
+        assert_eq!(sent, 0);
+        assert_eq!(cancelled, [CancelledLines { id: alice, cancelled: 2, remaining: 2 }]);
+
+        // A tetris finishes it off, then Bob's, with one line to spare
+        let (sent, cancelled) = queue.cancel(5);
+        assert_eq!(sent, 1);
+        assert_eq!(
+            cancelled,
+            [
+                CancelledLines { id: alice, cancelled: 2, remaining: 0 },
+                CancelledLines { id: bob, cancelled: 2, remaining: 0 },
+            ]
+        );
+        assert_eq!(queue.total(), 0);
+        assert_eq!(queue.cancel(3), (3, Vec::new()));
+    }
+
+    #[test]
+    fn attacks_land_once_they_have_waited_out_their_pieces() {
+        let mut queue = GarbageQueue::default();
+        let delay = GARBAGE_DELAY_PIECES;
+        queue.push("alice".to_string(), 3);
+        let left: Vec<u32> = queue.attacks().map(|attack| attack.pieces_left(delay)).collect();
+        assert_eq!(left, [2]);
+        assert!(queue.lock(delay).is_empty());
+        queue.push("bob".to_string(), 1);
+        let left: Vec<u32> = queue.attacks().map(|attack| attack.pieces_left(delay)).collect();
+        assert_eq!(left, [1, 2]);
+
+        let landed = queue.lock(delay);
+        let from: Vec<&str> = landed.iter().map(|attack| attack.from.as_str()).collect();
+        assert_eq!(from, ["alice"]);
+        assert_eq!(queue.total(), 1);
+        assert_eq!(queue.lock(delay).len(), 1);
+        assert_eq!(queue.attacks().count(), 0);
+
+        // Without a delay an attack lands under the piece it arrived during
+        queue.push("carol".to_string(), 2);
+        assert_eq!(queue.attacks().next().unwrap().pieces_left(0), 1);
+        assert_eq!(queue.lock(0).len(), 1);
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::{Duration, Instant};
+
+use super::{GameEvent, TSpinKind};
+
+/// A rumble of the gamepad's motors: `pulses` buzzes of `duration`, each followed by a gap
+/// as long. `left` is the heavy, low motor and `right` the light one, from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rumble {
+    pub left: f32,
+    pub right: f32,
+    pub duration: Duration,
+    pub pulses: u32,
+}
+
+impl Rumble {
+    fn strength(&self) -> f32 {
+        self.left.max(self.right)
+    }
+}
+
+// A light tap from the small motor for every hard drop
+pub const HARD_DROP_RUMBLE: Rumble = Rumble {
+    left: 0.0,
+    right: 0.3,
+    duration: Duration::from_millis(60),
+    pulses: 1,
+};
+// Tetrises and T-spin clears buzz both motors, harder for each line
+const BIG_CLEAR_BASE_STRENGTH: f32 = 0.2;
+const BIG_CLEAR_STRENGTH_PER_LINE: f32 = 0.2;
+const BIG_CLEAR_DURATION: Duration = Duration::from_millis(200);
+// Incoming garbage thuds twice on the heavy motor
+pub const GARBAGE_RUMBLE: Rumble = Rumble {
+    left: 0.7,
+    right: 0.0,
+    duration: Duration::from_millis(90),
+    pulses: 2,
+};
+
+/// The rumble `event` calls for, if any.
+pub fn rumble_for(event: &GameEvent) -> Option<Rumble> {
+    match event {
+        GameEvent::HardDropped => Some(HARD_DROP_RUMBLE),
+        GameEvent::PieceLocked {
+            lines_cleared,
+            t_spin,
+            ..
+        } if *lines_cleared >= 4 || *lines_cleared > 0 && *t_spin != TSpinKind::None => {
+            let strength =
+                BIG_CLEAR_BASE_STRENGTH + BIG_CLEAR_STRENGTH_PER_LINE * *lines_cleared as f32;
+            Some(Rumble {
+                left: strength.min(1.0),
+                right: strength.min(1.0),
+                duration: BIG_CLEAR_DURATION,
+                pulses: 1,
+            })
+        }
+        GameEvent::GarbageReceived { .. } => Some(GARBAGE_RUMBLE),
+        _ => None,
+    }
+}
+
+/// Turns game events into motor speeds over time, one rumble at a time. A stronger rumble
+/// cuts in over a weaker one still playing, while a weaker one is dropped.
+#[derive(Debug)]
+pub struct Haptics {
+    /// Off in the settings keeps the motors still.
+    pub enabled: bool,
+    playing: Option<(Rumble, Instant)>,
+}
+
+impl Haptics {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            playing: None,
+        }
+    }
+
+    /// Starts the rumbles `events` call for, as of `now`.
+    pub fn on_events(&mut self, events: &[GameEvent], now: Instant) {
+        for rumble in events.iter().filter_map(rumble_for) {
+            let busy = self.playing.filter(|(playing, started)| {
+                Self::level(playing, now.saturating_duration_since(*started)).is_some()
+                    && playing.strength() > rumble.strength()
+            });
+            if busy.is_none() {
+                self.playing = Some((rumble, now));
+            }
+        }
+    }
+
+    /// How fast the left and right motors should spin at `now`.
+    pub fn motors(&self, now: Instant) -> (f32, f32) {
+        match self.playing {
+            Some((rumble, started)) if self.enabled => {
+                let elapsed = now.saturating_duration_since(started);
+                Self::level(&rumble, elapsed).unwrap_or((0.0, 0.0))
+            }
+            _ => (0.0, 0.0),
+        }
+    }
+
+    // Motor speeds `elapsed` into `rumble`, or None once it's over
+    fn level(rumble: &Rumble, elapsed: Duration) -> Option<(f32, f32)> {
+        let step = elapsed.as_nanos() / rumble.duration.as_nanos().max(1);
+        if step >= rumble.pulses as u128 * 2 - 1 {
+            None
+        } else if step.is_multiple_of(2) {
+            Some((rumble.left, rumble.right))
+        } else {
+            Some((0.0, 0.0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::BlockKind;
+
+    fn locked(lines_cleared: u32, t_spin: TSpinKind) -> GameEvent {
+        GameEvent::PieceLocked {
+            kind: BlockKind::T,
+            lines_cleared,
+            cleared_rows: Vec::new(),
+            t_spin,
+        }
+    }
+
+    #[test]
+    fn big_clears_rumble_harder_for_more_lines() {
+        assert_eq!(rumble_for(&GameEvent::HardDropped), Some(HARD_DROP_RUMBLE));
+        assert_eq!(rumble_for(&GameEvent::GarbageReceived { lines: 3 }), Some(GARBAGE_RUMBLE));
+        assert_eq!(rumble_for(&GameEvent::Held), None);
+        assert_eq!(rumble_for(&locked(3, TSpinKind::None)), None);
+        assert_eq!(rumble_for(&locked(0, TSpinKind::Full)), None);
+
+        let strength = |event| rumble_for(&event).unwrap().left;
+        assert!(strength(locked(1, TSpinKind::Mini)) < strength(locked(2, TSpinKind::Full)));
+        assert!(strength(locked(3, TSpinKind::Full)) < strength(locked(4, TSpinKind::None)));
+        assert_eq!(strength(locked(4, TSpinKind::None)), 1.0);
+    }
+
+    #[test]
+    fn garbage_pulses_and_stronger_rumbles_cut_in() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut haptics = Haptics::new(true);
+
+        haptics.on_events(&[GameEvent::GarbageReceived { lines: 1 }], start);
+        assert_eq!(haptics.motors(at(0)), (0.7, 0.0));
+        assert_eq!(haptics.motors(at(100)), (0.0, 0.0));
+        assert_eq!(haptics.motors(at(200)), (0.7, 0.0));
+        assert_eq!(haptics.motors(at(300)), (0.0, 0.0));
+
+        // A hard drop is too light to interrupt the garbage, but a tetris isn't
+        haptics.on_events(&[GameEvent::HardDropped], at(10));
+        assert_eq!(haptics.motors(at(10)), (0.7, 0.0));
+        haptics.on_events(&[GameEvent::HardDropped, locked(4, TSpinKind::None)], at(20));
+        assert_eq!(haptics.motors(at(20)), (1.0, 1.0));
+
+        haptics.enabled = false;
+        assert_eq!(haptics.motors(at(20)), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+pub const LOCALES_DIR: &str = "locales";
+
+// Built in, so every key has a fallback even without the locales directory
+const ENGLISH: &str = include_str!("../../locales/en.toml");
+
+/// The UI text of one language, by key.
+#[derive(Debug, Clone, Default)]
+pub struct Strings {
+    table: HashMap<String, String>,
+}
+
+impl Strings {
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        Ok(Self {
+            table: toml::from_str(text)?,
+        })
+    }
+
+    /// Loads `<lang>.toml` from `dir`.
+    pub fn load(dir: impl AsRef<Path>, lang: &str) -> Result<Self, Box<dyn Error>> {
+        // The code becomes part of a path, so keep it to a plain name
+        let valid = !lang.is_empty()
+            && lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!("invalid language code {:?}", lang).into());
+        }
+        let text = fs::read_to_string(dir.as_ref().join(format!("{}.toml", lang)))?;
+        Ok(Self::parse(&text)?)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.table.get(key).map(String::as_str)
+    }
+
+    /// English keys this language has no text for, in no particular order.
+    pub fn missing_keys(&self) -> Vec<&'static str> {
+        english()
+            .table
+            .keys()
+            .filter(|key| !self.table.contains_key(*key))
+            .map(|key| key.as_str())
+            .collect()
+    }
+}
+
+fn english() -> &'static Strings {
+    static STRINGS: OnceLock<Strings> = OnceLock::new();
+    STRINGS.get_or_init(|| Strings::parse(ENGLISH).expect("the English locale parses"))
+}
+
+static LOCALE: OnceLock<Strings> = OnceLock::new();
+
+/// Picks the language for the rest of the run. Returns false if one was already picked.
+pub fn set_locale(strings: Strings) -> bool {
+    LOCALE.set(strings).is_ok()
+}
+
+/// The text for `key` in the chosen language, falling back to English key by key, and to
+/// the key itself if even English doesn't have it.
+pub fn tr(key: &'static str) -> &'static str {
+    LOCALE
+        .get()
+        .and_then(|strings| strings.get(key))
+        .or_else(|| english().get(key))
+        .unwrap_or(key)
+}
+
+/// `tr` with each `{name}` placeholder filled in from `args`.
+pub fn tr_args(key: &'static str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    fill(tr(key), args)
+}
+
+fn fill(template: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let mut text = template.to_string();
+    for (name, value) in args {
+        text = text.replace(&format!("{{{}}}", name), &value.to_string());
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::TUTORIAL_STEPS;
+
+    // Every file that looks up UI text
+    const SOURCES: &[&str] = &[
+        include_str!("../main.rs"),
+        include_str!("renderer.rs"),
+        include_str!("menu.rs"),
+    ];
+
+    /// Keys passed as literals to `tr` and `tr_args` in `source`.
+    fn referenced_keys(source: &str) -> Vec<&str> {
+        // Not the end of a longer name, such as push_str
+        let is_call = |at: usize| {
+            !source[..at]
+                .ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        };
+        ["tr(\"", "tr_args(\""]
+            .iter()
+            .flat_map(|call| {
+                source
+                    .match_indices(call)
+                    .filter(move |&(at, _)| is_call(at))
+                    .map(move |(at, _)| at + call.len())
+            })
+            .filter_map(|start| {
+                let end = source[start..].find('"')?;
+                Some(&source[start..start + end])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_key_in_the_code_is_in_english() {
+        let mut count = 0;
+        for source in SOURCES {
+            for key in referenced_keys(source) {
+                assert!(english().get(key).is_some(), "missing English text for {:?}", key);
+                count += 1;
+            }
+        }
+        assert!(count > 50, "only found {} keys", count);
+        for step in TUTORIAL_STEPS {
+            assert!(english().get(step.text).is_some(), "missing {:?}", step.text);
+        }
+    }
+
+    #[test]
+    fn shipped_locales_cover_every_key() {
+        let spanish = Strings::load(Path::new(env!("CARGO_MANIFEST_DIR")).join(LOCALES_DIR), "es")
+            .unwrap();
+        assert_eq!(spanish.missing_keys(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn missing_keys_fall_back_to_english() {
+        let partial = Strings::parse("paused = \"PAUSA\"").unwrap();
+        assert_eq!(partial.get("paused"), Some("PAUSA"));
+        assert_eq!(partial.get("game_over"), None);
+        assert!(partial.missing_keys().contains(&"game_over"));
+        assert_eq!(tr("game_over"), "GAME OVER");
+        assert_eq!(tr("no_such_key"), "no_such_key");
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        assert_eq!(
+            fill("+ {count} more players", &[("count", &3)]),
+            "+ 3 more players"
+        );
+        assert_eq!(
+            fill("{a} and {b}, {a}", &[("a", &"x"), ("b", &1.5)]),
+            "x and 1.5, x"
+        );
+        assert_eq!(tr_args("more_players", &[("count", &2)]), "+ 2 more players");
+    }
+
+    #[test]
+    fn language_codes_stay_in_the_directory() {
+        assert!(Strings::load(LOCALES_DIR, "../Cargo").is_err());
+        assert!(Strings::load(LOCALES_DIR, "").is_err());
+    }
+}
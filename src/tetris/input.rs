@@ -1,10 +1,17 @@
+#[cfg(feature = "gui")]
+use raylib::prelude::{GamepadButton, KeyboardKey};
 use std::time::{Duration, Instant};
 
+#[cfg(feature = "gui")]
+use super::Action;
+
 // Key repeat timing constants
 pub const KEY_REPEAT_DELAY: Duration = Duration::from_millis(150);
 pub const KEY_REPEAT_RATE: Duration = Duration::from_millis(30);
 pub const ROTATION_REPEAT_DELAY: Duration = Duration::from_millis(200);
 pub const ROTATION_REPEAT_RATE: Duration = Duration::from_millis(150);
+// How long the restart key is held to restart mid-game
+pub const RESTART_HOLD: Duration = Duration::from_millis(500);
 
 pub struct KeyState {
     last_press: Instant,
@@ -31,8 +38,17 @@ impl KeyState {
         }
     }
 
+    /// Whether the key was down at the last update, regardless of repeat timing.
+    pub fn is_held(&self) -> bool {
+        self.is_pressed
+    }
+
     pub fn update(&mut self, is_down: bool) -> bool {
-        let now = Instant::now();
+        self.update_at(is_down, Instant::now())
+    }
+
+    /// Returns whether the key acts this frame: on the press, then at the repeat rate.
+    pub fn update_at(&mut self, is_down: bool, now: Instant) -> bool {
         let (repeat_delay, repeat_rate) = if self.is_rotation {
             (ROTATION_REPEAT_DELAY, ROTATION_REPEAT_RATE)
         } else {
@@ -67,4 +83,408 @@ impl KeyState {
         self.is_pressed = is_down;
         should_trigger
     }
-} 
+}
+
+/// A physical input that can be bound to an action.
+#[cfg(feature = "gui")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Key(KeyboardKey),
+    Gamepad(GamepadButton),
+}
+
+/// Turns the inputs bound to each action into the actions to apply each frame, with each
+/// repeating its own way: movement auto-shifts, rotation repeats slowly and the drops and
+/// hold act once per press. Soft drop never shows up as an action; it acts for as long as
+/// it's held, so check `is_held` instead.
+#[cfg(feature = "gui")]
+pub struct InputMap {
+    bindings: Vec<(Input, Action)>,
+    horizontal: HorizontalInput,
+    rotation: KeyState,
+    // Actions with a bound input down at the last update
+    held: Vec<Action>,
+    // Actions from the last update that came from a held input rather than a new press
+    repeats: Vec<Action>,
+}
+
+#[cfg(feature = "gui")]
+impl InputMap {
+    pub fn new(bindings: Vec<(Input, Action)>) -> Self {
+        Self {
+            bindings,
+            horizontal: HorizontalInput::new(),
+            rotation: KeyState::new(true),
+            held: Vec::new(),
+            repeats: Vec::new(),
+        }
+    }
+
+    /// Swaps the bindings, keeping the repeat state of anything held through the change.
+    pub fn set_bindings(&mut self, bindings: Vec<(Input, Action)>) {
+        self.bindings = bindings;
+    }
+
+    /// Sets the delayed auto shift and auto repeat rate for movement.
+    pub fn set_timing(&mut self, delay: Duration, rate: Duration) {
+        self.horizontal.set_timing(delay, rate);
+    }
+
+    /// Whether any input bound to `action` was down at the last update.
+    pub fn is_held(&self, action: Action) -> bool {
+        self.held.contains(&action)
+    }
+
+    /// Whether any bound input at all was down at the last update.
+    pub fn any_held(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// Whether `action` came from the last update as a key repeat rather than a press.
+    /// Finesse only counts presses.
+    pub fn is_repeat(&self, action: Action) -> bool {
+        self.repeats.contains(&action)
+    }
+
+    pub fn update(&mut self, is_down: impl Fn(Input) -> bool) -> Vec<Action> {
+        self.update_at(is_down, Instant::now())
+    }
+
+    /// Reads every bound input through `is_down` and returns the actions to apply, in the
+    /// order they should be applied.
+    pub fn update_at(&mut self, is_down: impl Fn(Input) -> bool, now: Instant) -> Vec<Action> {
+        let was_held = std::mem::take(&mut self.held);
+        for &(input, action) in &self.bindings {
+            if !self.held.contains(&action) && is_down(input) {
+                self.held.push(action);
+            }
+        }
+        self.repeats.clear();
+        let mut actions = Vec::new();
+
+        let direction = self.horizontal.direction();
+        let shift = self.horizontal.update_at(
+            self.is_held(Action::MoveLeft),
+            self.is_held(Action::MoveRight),
+            now,
+        );
+        if shift != 0 {
+            let action = if shift < 0 { Action::MoveLeft } else { Action::MoveRight };
+            // Only a change of direction is a new press
+            if self.horizontal.direction() == direction {
+                self.repeats.push(action);
+            }
+            actions.push(action);
+        }
+
+        let rotation_held = self.rotation.is_held();
+        if self.rotation.update_at(self.is_held(Action::RotateCw), now) {
+            if rotation_held {
+                self.repeats.push(Action::RotateCw);
+            }
+            actions.push(Action::RotateCw);
+        }
+
+        for action in [Action::SonicDrop, Action::HardDrop, Action::Hold] {
+            if self.is_held(action) && !was_held.contains(&action) {
+                actions.push(action);
+            }
+        }
+        actions
+    }
+}
+
+/// Left and right keys resolved into one direction, with the most recently pressed key
+/// taking priority while both are held.
+pub struct HorizontalInput {
+    left_since: Option<Instant>,
+    right_since: Option<Instant>,
+    direction: i32,
+    // When a direction was first held; carried over when switching directions mid-hold
+    charge_start: Instant,
+    last_shift: Instant,
+    // Delayed auto shift and auto repeat rate
+    delay: Duration,
+    rate: Duration,
+}
+
+impl Default for HorizontalInput {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            left_since: None,
+            right_since: None,
+            direction: 0,
+            charge_start: now,
+            last_shift: now,
+            delay: KEY_REPEAT_DELAY,
+            rate: KEY_REPEAT_RATE,
+        }
+    }
+}
+
+impl HorizontalInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long a direction is held before it repeats, and how often it repeats then.
+    pub fn set_timing(&mut self, delay: Duration, rate: Duration) {
+        self.delay = delay;
+        self.rate = rate;
+    }
+
+    /// The resolved direction: -1 for left, 1 for right, 0 when neither is held.
+    pub fn direction(&self) -> i32 {
+        self.direction
+    }
+
+    /// Whether the held direction has passed the repeat delay.
+    pub fn is_charged(&self, now: Instant) -> bool {
+        self.direction != 0 && now.duration_since(self.charge_start) >= self.delay
+    }
+
+    pub fn update(&mut self, left_down: bool, right_down: bool) -> i32 {
+        self.update_at(left_down, right_down, Instant::now())
+    }
+
+    /// Returns the direction to shift this frame, or 0.
+    pub fn update_at(&mut self, left_down: bool, right_down: bool, now: Instant) -> i32 {
+        track_press(&mut self.left_since, left_down, now);
+        track_press(&mut self.right_since, right_down, now);
+
+        let direction = match (self.left_since, self.right_since) {
+            (Some(left), Some(right)) if right > left => 1,
+            (Some(_), _) => -1,
+            (None, Some(_)) => 1,
+            (None, None) => 0,
+        };
+
+        let shift = if direction == 0 {
+            0
+        } else if direction != self.direction {
+            // A fresh press charges from scratch; a switch keeps the charge built so far
+            if self.direction == 0 {
+                self.charge_start = now;
+            }
+            self.last_shift = now;
+            direction
+        } else if self.is_charged(now) && now.duration_since(self.last_shift) >= self.rate {
+            self.last_shift = now;
+            direction
+        } else {
+            0
+        };
+
+        self.direction = direction;
+        shift
+    }
+}
+
+/// A key that acts once it has been held for a while rather than on the press, so a tap
+/// does nothing. It acts once per hold.
+pub struct HoldGesture {
+    hold: Duration,
+    since: Option<Instant>,
+    fired: bool,
+}
+
+impl HoldGesture {
+    pub fn new(hold: Duration) -> Self {
+        Self {
+            hold,
+            since: None,
+            fired: false,
+        }
+    }
+
+    /// Returns whether the key has just been held long enough.
+    pub fn update_at(&mut self, is_down: bool, now: Instant) -> bool {
+        track_press(&mut self.since, is_down, now);
+        if !is_down {
+            self.fired = false;
+            return false;
+        }
+        let held = self.since.is_some_and(|since| now.duration_since(since) >= self.hold);
+        let fire = held && !self.fired;
+        self.fired |= fire;
+        fire
+    }
+
+    /// How far through the hold the key is, from 0 to 1, while it's held and yet to act.
+    pub fn progress(&self, now: Instant) -> Option<f32> {
+        let since = self.since.filter(|_| !self.fired)?;
+        let held = now.duration_since(since).as_secs_f32() / self.hold.as_secs_f32();
+        Some(held.min(1.0))
+    }
+}
+
+/// How long the player has gone without touching a key or button, for pausing a game
+/// they've walked away from.
+#[derive(Default)]
+pub struct IdleTimer {
+    since: Option<Instant>,
+}
+
+impl IdleTimer {
+    /// Notes whether this frame went by `idle`. Anything else starts the clock again, so
+    /// callers count frames outside a running game as not idle.
+    pub fn update_at(&mut self, idle: bool, now: Instant) {
+        track_press(&mut self.since, idle, now);
+    }
+
+    /// Whether the player has been idle for at least `limit`.
+    pub fn is_idle(&self, limit: Duration, now: Instant) -> bool {
+        self.since.is_some_and(|since| now.duration_since(since) >= limit)
+    }
+}
+
+fn track_press(since: &mut Option<Instant>, is_down: bool, now: Instant) {
+    if !is_down {
+        *since = None;
+    } else if since.is_none() {
+        *since = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_state_reports_held_apart_from_repeats() {
+        let mut key = KeyState::new(true);
+        assert!(key.update(true));
+        assert!(key.is_held());
+        // Still held, but too soon to repeat
+        assert!(!key.update(true));
+        assert!(key.is_held());
+        assert!(!key.update(false));
+        assert!(!key.is_held());
+    }
+
+    #[test]
+    fn last_pressed_direction_wins_while_both_are_held() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut input = HorizontalInput::new();
+
+        assert_eq!(input.update_at(true, false, at(0)), -1);
+        assert_eq!(input.update_at(true, false, at(50)), 0);
+        // Tapping right while left is held takes over at once
+        assert_eq!(input.update_at(true, true, at(100)), 1);
+        assert_eq!(input.direction(), 1);
+        assert_eq!(input.update_at(true, true, at(110)), 0);
+        // Letting go hands control back to left
+        assert_eq!(input.update_at(true, false, at(120)), -1);
+        assert_eq!(input.direction(), -1);
+    }
+
+    #[test]
+    fn charge_carries_over_a_switch_but_not_a_fresh_press() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let repeat_at = KEY_REPEAT_DELAY.as_millis() as u64;
+        let mut input = HorizontalInput::new();
+
+        input.update_at(true, false, at(0));
+        input.update_at(true, true, at(100));
+        assert!(!input.is_charged(at(repeat_at - 1)));
+        // Charged from the first press, so right repeats without a new delay
+        assert!(input.is_charged(at(repeat_at)));
+        assert_eq!(input.update_at(true, true, at(repeat_at)), 1);
+        assert_eq!(input.update_at(true, true, at(repeat_at + 10)), 0);
+        assert_eq!(input.update_at(true, true, at(repeat_at + 40)), 1);
+
+        assert_eq!(input.update_at(false, false, at(300)), 0);
+        assert_eq!(input.direction(), 0);
+        assert_eq!(input.update_at(false, true, at(310)), 1);
+        assert!(!input.is_charged(at(310 + repeat_at - 1)));
+    }
+
+    #[test]
+    fn pressing_both_in_one_frame_goes_left() {
+        let mut input = HorizontalInput::new();
+        assert_eq!(input.update_at(true, true, Instant::now()), -1);
+    }
+
+    #[test]
+    #[cfg(feature = "gui")]
+    fn input_map_repeats_each_action_its_own_way() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut input = InputMap::new(vec![
+            (Input::Key(KeyboardKey::KEY_LEFT), Action::MoveLeft),
+            (Input::Key(KeyboardKey::KEY_UP), Action::RotateCw),
+            (Input::Key(KeyboardKey::KEY_DOWN), Action::SoftDrop),
+            (Input::Key(KeyboardKey::KEY_SPACE), Action::HardDrop),
+            (Input::Key(KeyboardKey::KEY_C), Action::Hold),
+            (Input::Key(KeyboardKey::KEY_LEFT_SHIFT), Action::Hold),
+        ]);
+        let all = |input| input != Input::Key(KeyboardKey::KEY_LEFT_SHIFT);
+
+        let actions = input.update_at(all, at(0));
+        assert_eq!(
+            actions,
+            [Action::MoveLeft, Action::RotateCw, Action::HardDrop, Action::Hold]
+        );
+        assert!(!input.is_repeat(Action::MoveLeft));
+        assert!(input.is_held(Action::SoftDrop));
+
+        // Held through the repeat delays: movement and rotation repeat, the rest don't
+        assert!(input.update_at(all, at(10)).is_empty());
+        let delay = ROTATION_REPEAT_DELAY.max(KEY_REPEAT_DELAY).as_millis() as u64;
+        let actions = input.update_at(all, at(delay + 200));
+        assert_eq!(actions, [Action::MoveLeft, Action::RotateCw]);
+        assert!(input.is_repeat(Action::MoveLeft));
+        assert!(input.is_repeat(Action::RotateCw));
+
+        // A second key for a held action isn't a new press
+        let actions = input.update_at(|_| true, at(delay + 210));
+        assert!(!actions.contains(&Action::Hold));
+        assert!(input.update_at(|_| false, at(delay + 220)).is_empty());
+        assert!(!input.is_held(Action::SoftDrop));
+    }
+
+    #[test]
+    fn hold_gesture_acts_once_per_long_hold() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut hold = HoldGesture::new(Duration::from_millis(500));
+
+        // A tap does nothing
+        assert!(!hold.update_at(true, at(0)));
+        assert_eq!(hold.progress(at(250)), Some(0.5));
+        assert!(!hold.update_at(false, at(300)));
+        assert_eq!(hold.progress(at(300)), None);
+
+        assert!(!hold.update_at(true, at(400)));
+        assert!(!hold.update_at(true, at(899)));
+        assert!(hold.update_at(true, at(900)));
+        assert_eq!(hold.progress(at(950)), None);
+        // Holding on doesn't act again until the key is let go
+        assert!(!hold.update_at(true, at(2000)));
+        hold.update_at(false, at(2010));
+        assert!(!hold.update_at(true, at(2020)));
+        assert!(hold.update_at(true, at(2520)));
+    }
+
+    #[test]
+    fn idle_time_restarts_with_any_input() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let limit = Duration::from_secs(30);
+        let mut idle = IdleTimer::default();
+        assert!(!idle.is_idle(limit, at(0)));
+        idle.update_at(true, at(0));
+        idle.update_at(true, at(29_000));
+        assert!(!idle.is_idle(limit, at(29_000)));
+        assert!(idle.is_idle(limit, at(30_000)));
+
+        // A key press, or a frame spent paused, starts the wait over
+        idle.update_at(false, at(30_500));
+        idle.update_at(true, at(31_000));
+        assert!(!idle.is_idle(limit, at(60_000)));
+        assert!(idle.is_idle(limit, at(61_000)));
+    }
+}
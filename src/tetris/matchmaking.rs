@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Players are paired within this many rating points of each other, and the window
+// widens by the growth rate for every second the longer-waiting one has been queued
+pub const RATING_WINDOW: i32 = 200;
+pub const RATING_WINDOW_GROWTH: i32 = 10;
+
+struct Waiting {
+    player_id: String,
+    rating: i32,
+    since: Instant,
+}
+
+/// How far apart two ratings may be once a player has waited this long.
+pub fn rating_window(waited: Duration) -> i32 {
+    let seconds = i32::try_from(waited.as_secs()).unwrap_or(i32::MAX);
+    RATING_WINDOW.saturating_add(seconds.saturating_mul(RATING_WINDOW_GROWTH))
+}
+
+/// Players waiting for a 1v1 match, longest-waiting first.
+#[derive(Default)]
+pub struct MatchQueue {
+    waiting: VecDeque<Waiting>,
+}
+
+impl MatchQueue {
+    /// Adds a player to the back of the queue unless they are already waiting, and
+    /// returns their 1-based position.
+    pub fn join(&mut self, player_id: &str, rating: i32, now: Instant) -> usize {
+        if let Some(position) = self.position(player_id) {
+            return position;
+        }
+        self.waiting.push_back(Waiting {
+            player_id: player_id.to_string(),
+            rating,
+            since: now,
+        });
+        self.waiting.len()
+    }
+
+    /// Takes a player out of the queue. Returns false if they weren't in it.
+    pub fn leave(&mut self, player_id: &str) -> bool {
+        let before = self.waiting.len();
+        self.waiting.retain(|waiting| waiting.player_id != player_id);
+        self.waiting.len() != before
+    }
+
+    pub fn position(&self, player_id: &str) -> Option<usize> {
+        self.waiting
+            .iter()
+            .position(|waiting| waiting.player_id == player_id)
+            .map(|index| index + 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    /// Everyone still waiting, with their 1-based position.
+    pub fn positions(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.waiting
+            .iter()
+            .enumerate()
+            .map(|(index, waiting)| (waiting.player_id.as_str(), index + 1))
+    }
+
+    /// Takes two players off the queue: the longest-waiting player who has someone close
+    /// enough in rating, and the longest-waiting such opponent. Anyone else stays queued.
+    pub fn pop_pair(&mut self, now: Instant) -> Option<(String, String)> {
+        let window =
+            |waiting: &Waiting| rating_window(now.saturating_duration_since(waiting.since));
+        let (first, second) = (0..self.waiting.len()).find_map(|i| {
+            let a = &self.waiting[i];
+            (i + 1..self.waiting.len())
+                .find(|&j| {
+                    let b = &self.waiting[j];
+                    (a.rating - b.rating).abs() <= window(a).max(window(b))
+                })
+                .map(|j| (i, j))
+        })?;
+        let second = self.waiting.remove(second)?;
+        let first = self.waiting.remove(first)?;
+        Some((first.player_id, second.player_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_widens_while_waiting() {
+        assert_eq!(rating_window(Duration::ZERO), RATING_WINDOW);
+        assert_eq!(rating_window(Duration::from_secs(30)), RATING_WINDOW + 300);
+        assert_eq!(rating_window(Duration::from_secs(u64::MAX)), i32::MAX);
+    }
+
+    #[test]
+    fn join_and_leave_keep_positions() {
+        let now = Instant::now();
+        let mut queue = MatchQueue::default();
+        assert_eq!(queue.join("a", 1000, now), 1);
+        assert_eq!(queue.join("b", 1000, now), 2);
+        assert_eq!(queue.join("a", 1000, now), 1, "joining twice keeps the old spot");
+        assert!(queue.leave("a"));
+        assert!(!queue.leave("a"));
+        assert_eq!(queue.position("b"), Some(1));
+    }
+
+    #[test]
+    fn pairs_close_ratings_first() {
+        let now = Instant::now();
+        let mut queue = MatchQueue::default();
+        queue.join("low", 800, now);
+        queue.join("high", 1500, now);
+        queue.join("near_low", 950, now);
+        assert_eq!(
+            queue.pop_pair(now),
+            Some(("low".to_string(), "near_low".to_string()))
+        );
+        assert_eq!(queue.pop_pair(now), None);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn long_waits_pair_distant_ratings() {
+        let start = Instant::now();
+        let mut queue = MatchQueue::default();
+        queue.join("low", 1000, start);
+        queue.join("high", 1500, start);
+        assert_eq!(queue.pop_pair(start + Duration::from_secs(29)), None);
+        assert_eq!(
+            queue.pop_pair(start + Duration::from_secs(30)),
+            Some(("low".to_string(), "high".to_string()))
+        );
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::tetris::game::{GameMode, CLASSIC_MAX_START_LEVEL, MAX_START_LEVEL};
+use crate::tetris::i18n::tr;
+
+/// Entries on the main menu, top to bottom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuItem {
+    SinglePlayer,
+    Daily,
+    Missions,
+    BoardEditor,
+    Tutorial,
+    VersusCpu,
+    VersusLocal,
+    Online,
+    Settings,
+    Quit,
+}
+
+impl MenuItem {
+    pub const ALL: [MenuItem; 10] = [
+        MenuItem::SinglePlayer,
+        MenuItem::Daily,
+        MenuItem::Missions,
+        MenuItem::BoardEditor,
+        MenuItem::Tutorial,
+        MenuItem::VersusCpu,
+        MenuItem::VersusLocal,
+        MenuItem::Online,
+        MenuItem::Settings,
+        MenuItem::Quit,
+    ];
+
+    /// The item's label in the chosen language.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MenuItem::SinglePlayer => tr("menu_single_player"),
+            MenuItem::Daily => tr("menu_daily"),
+            MenuItem::Missions => tr("menu_missions"),
+            MenuItem::BoardEditor => tr("menu_board_editor"),
+            MenuItem::Tutorial => tr("menu_tutorial"),
+            MenuItem::VersusCpu => tr("menu_versus_cpu"),
+            MenuItem::VersusLocal => tr("menu_versus_local"),
+            MenuItem::Online => tr("menu_online"),
+            MenuItem::Settings => tr("menu_settings"),
+            MenuItem::Quit => tr("menu_quit"),
+        }
+    }
+}
+
+/// A number a mode lets you pick before starting, such as the start level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModeOption {
+    label: &'static str,
+    pub min: u32,
+    pub max: u32,
+    pub default: u32,
+}
+
+impl ModeOption {
+    /// The option's label in the chosen language, with a `{value}` placeholder.
+    pub fn label(&self) -> &'static str {
+        tr(self.label)
+    }
+
+    fn clamp(&self, value: u32) -> u32 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+/// One row of the mode select: what it starts, how it's described and what can be tuned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModeDescriptor {
+    pub mode: GameMode,
+    name: &'static str,
+    description: &'static str,
+    pub option: Option<ModeOption>,
+}
+
+impl ModeDescriptor {
+    pub fn name(&self) -> &'static str {
+        tr(self.name)
+    }
+
+    pub fn description(&self) -> &'static str {
+        tr(self.description)
+    }
+}
+
+/// The single-player modes, in the order the mode select lists them.
+pub const MODES: [ModeDescriptor; 6] = [
+    ModeDescriptor {
+        mode: GameMode::Marathon,
+        name: "menu_marathon",
+        description: "mode_marathon_description",
+        option: Some(ModeOption {
+            label: "mode_option_level",
+            min: 1,
+            max: MAX_START_LEVEL,
+            default: 1,
+        }),
+    },
+    ModeDescriptor {
+        mode: GameMode::Sprint,
+        name: "menu_sprint",
+        description: "mode_sprint_description",
+        option: None,
+    },
+    ModeDescriptor {
+        mode: GameMode::CheeseRace,
+        name: "menu_cheese_race",
+        description: "mode_cheese_race_description",
+        option: Some(ModeOption {
+            label: "mode_option_lines",
+            min: 1,
+            max: MAX_CHEESE_LINES,
+            default: 18,
+        }),
+    },
+    ModeDescriptor {
+        mode: GameMode::Classic,
+        name: "menu_classic",
+        description: "mode_classic_description",
+        option: Some(ModeOption {
+            label: "mode_option_level",
+            min: 0,
+            max: CLASSIC_MAX_START_LEVEL,
+            default: 0,
+        }),
+    },
+    ModeDescriptor {
+        mode: GameMode::Cascade,
+        name: "menu_cascade",
+        description: "mode_cascade_description",
+        option: None,
+    },
+    ModeDescriptor {
+        mode: GameMode::Practice,
+        name: "menu_practice",
+        description: "mode_practice_description",
+        option: None,
+    },
+];
+
+// The most garbage lines a cheese race can be set to dig through from the mode select
+const MAX_CHEESE_LINES: u32 = 100;
+
+/// The main menu: a cursor over the items, and the mode select behind "Single Player" with the
+/// option picked for each mode.
+pub struct Menu {
+    selected: usize,
+    mode: usize,
+    options: [u32; MODES.len()],
+    choosing_mode: bool,
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Self {
+            selected: 0,
+            mode: 0,
+            options: MODES.map(|mode| mode.option.map_or(0, |option| option.default)),
+            choosing_mode: false,
+        }
+    }
+}
+
+impl Menu {
+    pub fn selected(&self) -> MenuItem {
+        MenuItem::ALL[self.selected]
+    }
+
+    /// Whether the mode select is open rather than the main menu.
+    pub fn is_choosing_mode(&self) -> bool {
+        self.choosing_mode
+    }
+
+    pub fn open_mode_select(&mut self) {
+        self.choosing_mode = true;
+    }
+
+    pub fn close_mode_select(&mut self) {
+        self.choosing_mode = false;
+    }
+
+    /// The mode under the cursor in the mode select.
+    pub fn selected_mode(&self) -> &'static ModeDescriptor {
+        &MODES[self.mode]
+    }
+
+    /// The option picked for `mode`, or `None` if it has none.
+    pub fn option(&self, mode: GameMode) -> Option<u32> {
+        let index = MODES.iter().position(|descriptor| descriptor.mode == mode)?;
+        MODES[index].option.map(|_| self.options[index])
+    }
+
+    /// Moves the cursor by `step` rows of whichever screen is open, wrapping around at
+    /// either end.
+    pub fn move_cursor(&mut self, step: isize) {
+        let (cursor, len) = if self.choosing_mode {
+            (&mut self.mode, MODES.len())
+        } else {
+            (&mut self.selected, MenuItem::ALL.len())
+        };
+        *cursor = (*cursor as isize + step).rem_euclid(len as isize) as usize;
+    }
+
+    /// Indices of the items to show when only `fit` fit on screen.
+    pub fn visible_items(&self, fit: usize) -> std::ops::Range<usize> {
+        visible_items(MenuItem::ALL.len(), self.selected, fit)
+    }
+
+    /// Indices of the modes to show when only `fit` fit on screen.
+    pub fn visible_modes(&self, fit: usize) -> std::ops::Range<usize> {
+        visible_items(MODES.len(), self.mode, fit)
+    }
+
+    /// Left and right change the selected mode's option, within its range.
+    pub fn adjust(&mut self, step: i32) {
+        if !self.choosing_mode {
+            return;
+        }
+        if let Some(option) = MODES[self.mode].option {
+            let value = &mut self.options[self.mode];
+            *value = option.clamp(value.saturating_add_signed(step));
+        }
+    }
+}
+
+/// Indices of the items to show when only `fit` of `len` fit on screen, scrolled to keep
+/// `selected` in view.
+pub fn visible_items(len: usize, selected: usize, fit: usize) -> std::ops::Range<usize> {
+    let fit = fit.clamp(1, len);
+    let first = (selected + 1).saturating_sub(fit);
+    first..first + fit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_wraps_around() {
+        let mut menu = Menu::default();
+        menu.move_cursor(-1);
+        assert_eq!(menu.selected(), MenuItem::Quit);
+        menu.move_cursor(1);
+        assert_eq!(menu.selected(), MenuItem::SinglePlayer);
+
+        menu.open_mode_select();
+        menu.move_cursor(-1);
+        assert_eq!(menu.selected_mode().mode, GameMode::Practice);
+        assert_eq!(menu.selected(), MenuItem::SinglePlayer, "the main menu stays put");
+        menu.close_mode_select();
+        assert_eq!(menu.selected_mode().mode, GameMode::Practice, "reopening keeps the mode");
+    }
+
+    #[test]
+    fn scrolling_keeps_the_selection_in_view() {
+        let mut menu = Menu::default();
+        assert_eq!(menu.visible_items(20), 0..MenuItem::ALL.len());
+        assert_eq!(menu.visible_items(4), 0..4);
+        menu.move_cursor(5);
+        assert_eq!(menu.visible_items(4), 2..6);
+        menu.move_cursor(-1);
+        assert!(menu.visible_items(4).contains(&4));
+    }
+
+    #[test]
+    fn mode_options_stay_in_range() {
+        let mut menu = Menu::default();
+        menu.adjust(1);
+        assert_eq!(menu.option(GameMode::Marathon), Some(1), "only the mode select adjusts");
+
+        menu.open_mode_select();
+        menu.adjust(1);
+        assert_eq!(menu.option(GameMode::Marathon), Some(2));
+        assert_eq!(menu.option(GameMode::Classic), Some(0), "Classic has its own start level");
+        menu.adjust(-5);
+        assert_eq!(menu.option(GameMode::Marathon), Some(1));
+
+        menu.move_cursor(1);
+        menu.adjust(1);
+        assert_eq!(menu.option(GameMode::Sprint), None);
+
+        menu.move_cursor(2);
+        assert_eq!(menu.selected_mode().mode, GameMode::Classic);
+        menu.adjust(-1);
+        assert_eq!(menu.option(GameMode::Classic), Some(0));
+        for _ in 0..30 {
+            menu.adjust(1);
+        }
+        assert_eq!(menu.option(GameMode::Classic), Some(CLASSIC_MAX_START_LEVEL));
+        assert_eq!(menu.option(GameMode::CheeseRace), Some(18));
+    }
+
+    #[test]
+    fn every_mode_is_listed_once_with_a_default_in_range() {
+        for (i, descriptor) in MODES.iter().enumerate() {
+            assert!(MODES[..i].iter().all(|other| other.mode != descriptor.mode));
+            if let Some(option) = descriptor.option {
+                assert_eq!(option.clamp(option.default), option.default);
+            }
+        }
+    }
+}
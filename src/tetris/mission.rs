@@ -0,0 +1,490 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use super::{parse_queue, BlockKind, Board, GameEvent, PracticeSetup, TSpinKind, BOARD_HEIGHT};
+
+pub const MISSIONS_PATH: &str = "assets/missions.toml";
+
+/// What a mission asks the player to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Objective {
+    /// Clear exactly `lines` lines at once, `times` times, optionally with a given piece.
+    LineClear {
+        lines: u32,
+        #[serde(default = "one")]
+        times: u32,
+        #[serde(default)]
+        piece: Option<BlockKind>,
+    },
+    /// T-spin clearing at least `lines` lines, `times` times.
+    TSpin {
+        lines: u32,
+        #[serde(default = "one")]
+        times: u32,
+    },
+    /// Clear `lines` lines in total.
+    TotalLines { lines: u32 },
+    /// Clear the marked row, counted from the floor of the starting board (1 is the bottom).
+    ClearRow { row: usize },
+}
+
+fn one() -> u32 {
+    1
+}
+
+impl Objective {
+    /// The progress count at which the objective is met.
+    pub fn target(&self) -> u32 {
+        match *self {
+            Objective::LineClear { times, .. } | Objective::TSpin { times, .. } => times,
+            Objective::TotalLines { lines } => lines,
+            Objective::ClearRow { .. } => 1,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match *self {
+            Objective::LineClear { lines, times, .. } => {
+                if !(1..=4).contains(&lines) {
+                    return Err(format!("line_clear needs 1 to 4 lines, got {}", lines));
+                }
+                if times == 0 {
+                    return Err("line_clear needs times of at least 1".to_string());
+                }
+            }
+            Objective::TSpin { lines, times } => {
+                if lines > 3 {
+                    return Err(format!("t_spin clears at most 3 lines, got {}", lines));
+                }
+                if times == 0 {
+                    return Err("t_spin needs times of at least 1".to_string());
+                }
+            }
+            Objective::TotalLines { lines } => {
+                if lines == 0 {
+                    return Err("total_lines needs at least 1 line".to_string());
+                }
+            }
+            Objective::ClearRow { row } => {
+                if !(1..=BOARD_HEIGHT).contains(&row) {
+                    return Err(format!(
+                        "clear_row needs a row from 1 to {}, got {}",
+                        BOARD_HEIGHT, row
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Objective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Objective::LineClear { lines, times, piece } => {
+                write!(f, "Clear {} line{} at once", lines, if lines == 1 { "" } else { "s" })?;
+                if let Some(kind) = piece {
+                    write!(f, " with {}", kind.letter())?;
+                }
+                if times > 1 {
+                    write!(f, " {} times", times)?;
+                }
+                Ok(())
+            }
+            Objective::TSpin { lines, times } => {
+                write!(f, "T-spin")?;
+                if lines > 0 {
+                    write!(f, " clearing {}+ lines", lines)?;
+                }
+                if times > 1 {
+                    write!(f, " {} times", times)?;
+                }
+                Ok(())
+            }
+            Objective::TotalLines { lines } => write!(f, "Clear {} lines", lines),
+            Objective::ClearRow { .. } => write!(f, "Clear the marked row"),
+        }
+    }
+}
+
+// A mission as written in missions.toml
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MissionDef {
+    title: String,
+    objective: Objective,
+    #[serde(default)]
+    piece_limit: Option<u32>,
+    #[serde(default)]
+    board: Option<String>,
+    #[serde(default)]
+    queue: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MissionFile {
+    mission: Vec<MissionDef>,
+}
+
+#[derive(Clone)]
+pub struct Mission {
+    pub title: String,
+    pub objective: Objective,
+    pub piece_limit: Option<u32>,
+    /// Starting board and piece queue.
+    pub setup: PracticeSetup,
+}
+
+impl TryFrom<MissionDef> for Mission {
+    type Error = String;
+
+    fn try_from(def: MissionDef) -> Result<Self, Self::Error> {
+        if def.title.trim().is_empty() {
+            return Err("missing title".to_string());
+        }
+        def.objective.validate()?;
+        if def.piece_limit == Some(0) {
+            return Err("piece_limit must be at least 1".to_string());
+        }
+
+        let mut setup = PracticeSetup::default();
+        if let Some(board) = &def.board {
+            setup.board = Board::from_ascii(board).map_err(|e| format!("board: {}", e))?;
+        }
+        if let Some(queue) = &def.queue {
+            setup.queue = parse_queue(queue).map_err(|e| format!("queue: {}", e))?;
+        }
+
+        Ok(Self {
+            title: def.title,
+            objective: def.objective,
+            piece_limit: def.piece_limit,
+            setup,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum MissionError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Empty,
+    /// A mission failed validation; `mission` is its 1-based position in the file.
+    Invalid { mission: usize, message: String },
+}
+
+impl fmt::Display for MissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissionError::Io(e) => write!(f, "could not read missions: {}", e),
+            MissionError::Parse(e) => write!(f, "could not parse missions: {}", e),
+            MissionError::Empty => write!(f, "no missions defined"),
+            MissionError::Invalid { mission, message } => {
+                write!(f, "mission {}: {}", mission, message)
+            }
+        }
+    }
+}
+
+impl Error for MissionError {}
+
+pub fn parse_missions(text: &str) -> Result<Vec<Mission>, MissionError> {
+    let file: MissionFile = toml::from_str(text).map_err(MissionError::Parse)?;
+    if file.mission.is_empty() {
+        return Err(MissionError::Empty);
+    }
+    file.mission
+        .into_iter()
+        .enumerate()
+        .map(|(index, def)| {
+            Mission::try_from(def).map_err(|message| MissionError::Invalid {
+                mission: index + 1,
+                message,
+            })
+        })
+        .collect()
+}
+
+pub fn load_missions(path: impl AsRef<Path>) -> Result<Vec<Mission>, MissionError> {
+    let text = fs::read_to_string(path).map_err(MissionError::Io)?;
+    parse_missions(&text)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissionStatus {
+    Active,
+    Complete,
+    Failed,
+    Skipped,
+}
+
+/// Tracks one attempt at a mission from the game's events.
+#[derive(Debug, Clone)]
+pub struct MissionProgress {
+    pub objective: Objective,
+    pub piece_limit: Option<u32>,
+    pub count: u32,
+    pub pieces: u32,
+    pub status: MissionStatus,
+    // Board row of the marked row, followed as lines below it are cleared
+    marked_row: Option<usize>,
+}
+
+impl MissionProgress {
+    pub fn new(mission: &Mission) -> Self {
+        let marked_row = match mission.objective {
+            Objective::ClearRow { row } => Some(BOARD_HEIGHT - row),
+            _ => None,
+        };
+        Self {
+            objective: mission.objective,
+            piece_limit: mission.piece_limit,
+            count: 0,
+            pieces: 0,
+            status: MissionStatus::Active,
+            marked_row,
+        }
+    }
+
+    pub fn marked_row(&self) -> Option<usize> {
+        self.marked_row
+    }
+
+    pub fn on_event(&mut self, event: &GameEvent) -> MissionStatus {
+        if self.status != MissionStatus::Active {
+            return self.status;
+        }
+
+        match event {
+            GameEvent::PieceLocked {
+                kind,
+                lines_cleared,
+                cleared_rows,
+                t_spin,
+            } => {
+                self.pieces += 1;
+                match self.objective {
+                    Objective::LineClear { lines, piece, .. } => {
+                        if *lines_cleared == lines && (piece.is_none() || piece == Some(*kind)) {
+                            self.count += 1;
+                        }
+                    }
+                    Objective::TSpin { lines, .. } => {
+                        if *t_spin != TSpinKind::None && *lines_cleared >= lines {
+                            self.count += 1;
+                        }
+                    }
+                    Objective::TotalLines { .. } => self.count += lines_cleared,
+                    Objective::ClearRow { .. } => {
+                        if let Some(row) = self.marked_row {
+                            if cleared_rows.contains(&row) {
+                                self.count = 1;
+                            } else {
+                                // Rows cleared below the marked row pull it down
+                                let below = cleared_rows.iter().filter(|&&y| y > row).count();
+                                self.marked_row = Some(row + below);
+                            }
+                        }
+                    }
+                }
+
+                if self.count >= self.objective.target() {
+                    self.status = MissionStatus::Complete;
+                } else if self.piece_limit.is_some_and(|limit| self.pieces >= limit) {
+                    self.status = MissionStatus::Failed;
+                }
+            }
+            GameEvent::ToppedOut => self.status = MissionStatus::Failed,
+            GameEvent::HardDropped
+            | GameEvent::Held
+            | GameEvent::FinesseFault { .. }
+            | GameEvent::GarbageReceived { .. }
+            | GameEvent::AttackIncoming { .. }
+            | GameEvent::AttackCancelled { .. }
+            | GameEvent::PerfectClear
+            | GameEvent::MatchFinished => {}
+        }
+        self.status
+    }
+}
+
+/// A run through a list of missions, recording how each one ended.
+pub struct MissionRun {
+    pub missions: Vec<Mission>,
+    pub current: usize,
+    pub progress: MissionProgress,
+    pub results: Vec<MissionStatus>,
+}
+
+impl MissionRun {
+    pub fn new(missions: Vec<Mission>) -> Self {
+        let progress = MissionProgress::new(&missions[0]);
+        Self {
+            missions,
+            current: 0,
+            progress,
+            results: Vec::new(),
+        }
+    }
+
+    pub fn mission(&self) -> &Mission {
+        &self.missions[self.current.min(self.missions.len() - 1)]
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current >= self.missions.len()
+    }
+
+    pub fn retry(&mut self) {
+        self.progress = MissionProgress::new(self.mission());
+    }
+
+    /// Records how the current mission ended and moves on to the next one.
+    pub fn advance(&mut self, status: MissionStatus) {
+        self.results.push(status);
+        self.current += 1;
+        if !self.is_finished() {
+            self.retry();
+        }
+    }
+
+    pub fn completed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|&&status| status == MissionStatus::Complete)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mission(objective: Objective, piece_limit: Option<u32>) -> Mission {
+        Mission {
+            title: "Test".to_string(),
+            objective,
+            piece_limit,
+            setup: PracticeSetup::default(),
+        }
+    }
+
+    fn locked(kind: BlockKind, cleared_rows: &[usize], t_spin: TSpinKind) -> GameEvent {
+        GameEvent::PieceLocked {
+            kind,
+            lines_cleared: cleared_rows.len() as u32,
+            cleared_rows: cleared_rows.to_vec(),
+            t_spin,
+        }
+    }
+
+    #[test]
+    fn shipped_missions_load() {
+        let missions = load_missions(MISSIONS_PATH).unwrap();
+        assert!(missions.len() >= 10);
+    }
+
+    #[test]
+    fn invalid_missions_say_which_one_and_why() {
+        let text = r#"
+            [[mission]]
+            title = "Fine"
+            objective = { type = "total_lines", lines = 4 }
+
+            [[mission]]
+            title = "Too many"
+            objective = { type = "line_clear", lines = 5 }
+        "#;
+        match parse_missions(text) {
+            Err(MissionError::Invalid { mission, message }) => {
+                assert_eq!(mission, 2);
+                assert_eq!(message, "line_clear needs 1 to 4 lines, got 5");
+            }
+            other => panic!("expected a validation error, got {:?}", other.err()),
+        }
+
+        let typo = r#"
+            [[mission]]
+            title = "Typo"
+            objective = { type = "total_lines", lines = 4 }
+            peice_limit = 3
+        "#;
+        assert!(matches!(parse_missions(typo), Err(MissionError::Parse(_))));
+        assert!(matches!(parse_missions("mission = []"), Err(MissionError::Empty)));
+    }
+
+    #[test]
+    fn line_clear_with_a_piece_counts_only_that_piece() {
+        let objective = Objective::LineClear {
+            lines: 2,
+            times: 1,
+            piece: Some(BlockKind::T),
+        };
+        let mut progress = MissionProgress::new(&mission(objective, Some(3)));
+        let status = progress.on_event(&locked(BlockKind::O, &[18, 19], TSpinKind::None));
+        assert_eq!(status, MissionStatus::Active);
+        let status = progress.on_event(&locked(BlockKind::T, &[19], TSpinKind::None));
+        assert_eq!(status, MissionStatus::Active);
+        let status = progress.on_event(&locked(BlockKind::T, &[18, 19], TSpinKind::None));
+        assert_eq!(status, MissionStatus::Complete);
+    }
+
+    #[test]
+    fn piece_limit_and_top_out_fail_the_mission() {
+        let objective = Objective::TSpin { lines: 1, times: 1 };
+        let mut progress = MissionProgress::new(&mission(objective, Some(2)));
+        // A spin without a clear doesn't reach the line count
+        progress.on_event(&locked(BlockKind::T, &[], TSpinKind::Full));
+        let status = progress.on_event(&locked(BlockKind::I, &[19], TSpinKind::None));
+        assert_eq!(status, MissionStatus::Failed);
+        assert_eq!(progress.count, 0);
+
+        let mut progress = MissionProgress::new(&mission(Objective::TotalLines { lines: 4 }, None));
+        progress.on_event(&locked(BlockKind::I, &[17, 18, 19], TSpinKind::None));
+        assert_eq!(progress.on_event(&GameEvent::ToppedOut), MissionStatus::Failed);
+        // Nothing after the end changes the outcome
+        let status = progress.on_event(&locked(BlockKind::I, &[19], TSpinKind::None));
+        assert_eq!(status, MissionStatus::Failed);
+    }
+
+    #[test]
+    fn marked_row_follows_clears_below_it() {
+        let mut progress = MissionProgress::new(&mission(Objective::ClearRow { row: 3 }, None));
+        assert_eq!(progress.marked_row(), Some(17));
+
+        // Rows above don't move it, rows below pull it down
+        progress.on_event(&locked(BlockKind::I, &[12], TSpinKind::None));
+        assert_eq!(progress.marked_row(), Some(17));
+        let status = progress.on_event(&locked(BlockKind::I, &[18, 19], TSpinKind::None));
+        assert_eq!(status, MissionStatus::Active);
+        assert_eq!(progress.marked_row(), Some(19));
+
+        let status = progress.on_event(&locked(BlockKind::L, &[19], TSpinKind::None));
+        assert_eq!(status, MissionStatus::Complete);
+    }
+
+    #[test]
+    fn run_records_each_result_and_retries_from_scratch() {
+        let missions = vec![
+            mission(Objective::TotalLines { lines: 1 }, None),
+            mission(Objective::TotalLines { lines: 2 }, None),
+        ];
+        let mut run = MissionRun::new(missions);
+        run.progress.on_event(&locked(BlockKind::I, &[19], TSpinKind::None));
+        run.advance(MissionStatus::Complete);
+
+        run.progress.on_event(&locked(BlockKind::I, &[19], TSpinKind::None));
+        assert_eq!(run.progress.count, 1);
+        run.retry();
+        assert_eq!(run.progress.count, 0);
+        run.advance(MissionStatus::Skipped);
+
+        assert!(run.is_finished());
+        assert_eq!(run.results, [MissionStatus::Complete, MissionStatus::Skipped]);
+        assert_eq!(run.completed(), 1);
+    }
+}
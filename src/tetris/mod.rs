@@ -1,12 +1,55 @@
+pub mod admin;
+pub mod ai;
+pub mod anticheat;
 pub mod block;
 pub mod board;
+pub mod browser;
+pub mod capture;
+#[cfg(feature = "gui")]
+pub mod config;
+pub mod daily;
+pub mod datagram;
+pub mod date;
+pub mod editor;
+pub mod finesse;
 pub mod game;
+pub mod garbage;
+pub mod haptics;
+pub mod i18n;
 pub mod input;
+pub mod matchmaking;
+pub mod menu;
+pub mod mission;
 pub mod multiplayer;
+pub mod overlay;
+pub mod practice;
+pub mod prelude;
+pub mod presence;
+pub mod randomizer;
+pub mod rating;
+#[cfg(feature = "gui")]
 pub mod renderer;
+pub mod replay;
+pub mod resume;
+pub mod results;
+#[cfg(feature = "gui")]
+pub mod settings;
+pub mod splits;
+pub mod stats;
+pub mod text_input;
+pub mod tutorial;
+pub mod verify;
 
+pub use ai::*;
 pub use block::*;
 pub use board::*;
+pub use finesse::*;
 pub use game::*;
+pub use garbage::*;
 pub use input::*;
+pub use mission::*;
+pub use practice::*;
+pub use randomizer::*;
+#[cfg(feature = "gui")]
 pub use renderer::*;
+pub use tutorial::*;
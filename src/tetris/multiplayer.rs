@@ -1,90 +1,1246 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response,
+};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
 use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
 use futures_util::{SinkExt, StreamExt};
+use tracing::{debug, info, warn, Instrument, Span};
 
-#[derive(Serialize, Deserialize, Clone)]
+use super::anticheat::{filled_cells, Plausibility, MAX_VIOLATIONS};
+use super::daily::{daily_seed, DailyBoard, DailyEntry, DAILY_TOP};
+use super::datagram::{
+    decode_datagram, encode_datagram, first_sequence, Datagram, SequenceFilter,
+    MAX_DATAGRAM_BYTES, UDP_PROBES, UDP_PROBE_INTERVAL,
+};
+use super::matchmaking::MatchQueue;
+use super::rating::{Ratings, INITIAL_RATING, RATINGS_PATH};
+use super::replay::{
+    self, prune_replays, read_replay, replay_chunks, ReplayInfo, ReplayPlayer,
+    ReplayRecorder, REPLAYS_DIR, REPLAY_CHUNK_BYTES, REPLAY_RETENTION,
+};
+use super::resume::ResumeTokens;
+use super::results::{append_results_log, MatchEvent, MatchLog, MatchResult, RESULTS_LOG_PATH};
+use super::verify::{verify_record, ClaimedRecord, RecordRejection, VERIFY_BUDGET};
+use super::{
+    sent_attack, AttackKind, Block, BlockKind, Board, GameConfig, GameMode, GarbageConfig,
+    GARBAGE_DELAY_PIECES, LOCK_DELAY, PERFECT_CLEAR_ATTACK,
+};
+
+// Each player gets a few short pauses per match; the server resumes them when time is up
+pub const MATCH_PAUSES: u32 = 2;
+pub const MATCH_PAUSE_LIMIT: Duration = Duration::from_secs(30);
+// A player we haven't heard from for this long is shown as disconnected
+pub const PLAYER_STALE_AFTER: Duration = Duration::from_secs(3);
+// How often the server sends everyone the full player list
+pub const LOBBY_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(3);
+// Frames longer than this are deflated for peers that said they can inflate them
+pub const COMPRESSION_THRESHOLD: usize = 256;
+// First byte of a binary frame holding a deflated JSON message
+const FRAME_DEFLATE: u8 = 1;
+// Largest message a deflated frame may inflate to; anything bigger is dropped
+pub const MAX_FRAME_BYTES: usize = 256 * 1024;
+// Everyone starts out in this room; it has no password and is never removed
+pub const DEFAULT_ROOM: &str = "lobby";
+// Pause before answering a wrong room password, to slow down guessing
+const WRONG_PASSWORD_DELAY: Duration = Duration::from_secs(1);
+// Connections to this path answer one-off requests without joining the lobby
+pub const QUERY_PATH: &str = "/query";
+// How long a query waits for its answer
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+// Shown for daily results sent without a name
+const ANONYMOUS_NAME: &str = "anonymous";
+// How long a new connection waits for the client's Hello before treating it as new
+const HELLO_TIMEOUT: Duration = Duration::from_secs(2);
+// Players are told apart by one of this many accent colors, indexes into the theme's
+// piece colors
+pub const ACCENT_COUNT: u32 = 7;
+// Longest lock delay a room can set, in milliseconds
+pub const MAX_LOCK_DELAY_MS: u64 = 5000;
+pub const MAX_PERFECT_CLEAR_ATTACK: u32 = 20;
+// Most pieces a room can make garbage wait
+pub const MAX_GARBAGE_DELAY_PIECES: u32 = 5;
+// Most players a room other than the lobby takes
+pub const MAX_ROOM_PLAYERS: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct PlayerState {
     pub player_id: String,
     pub score: i32,
+    #[serde(default)]
+    pub lines: u32,
     pub name: Option<String>,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub pauses_used: u32,
+    #[serde(default)]
+    pub room: String,
+    /// Rating of the player's name, once they have given one.
+    #[serde(default)]
+    pub rating: Option<i32>,
+    /// Accent color the server picked for the player at join, kept for their session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accent: Option<u32>,
+    /// Reported something impossible this session, so their results aren't rated.
+    #[serde(default)]
+    pub unverified: bool,
+    // Counts every pause, unlike pauses_used, so a timer can tell its own pause apart
+    #[serde(skip)]
+    pause_serial: u64,
+    #[serde(skip)]
+    checks: Plausibility,
+}
+
+/// The first accent color nobody in `taken` has, or once they're all in use, the next one
+/// round.
+pub fn pick_accent(taken: &[u32]) -> u32 {
+    (0..ACCENT_COUNT)
+        .find(|accent| !taken.contains(accent))
+        .unwrap_or(taken.len() as u32 % ACCENT_COUNT)
+}
+
+/// A room as it appears in the room list. Private matchmaking rooms aren't listed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: usize,
+    /// Most players the room takes. The lobby has no limit.
+    #[serde(default)]
+    pub limit: Option<usize>,
+    pub locked: bool,
+    /// Whether a match is being played in it.
+    #[serde(default)]
+    pub in_match: bool,
+    #[serde(default)]
+    pub rules: RoomRules,
+}
+
+/// The most players `room` takes, with None for the lobby, which takes everyone.
+pub fn room_limit(room: &str) -> Option<usize> {
+    (room != DEFAULT_ROOM).then_some(MAX_ROOM_PLAYERS)
+}
+
+/// The rules everyone in a room plays under, which only whoever opened the room can
+/// change. Players take them up when their next game starts.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct RoomRules {
+    pub garbage: GarbageConfig,
+    pub lock_delay_ms: u64,
+    pub hold: bool,
+    /// Garbage a perfect clear sends at the least.
+    pub perfect_clear_attack: u32,
+    /// Pieces an attack waits out before it lands, see `GameConfig::garbage_delay_pieces`.
+    pub garbage_delay_pieces: u32,
+}
+
+impl Default for RoomRules {
+    fn default() -> Self {
+        Self {
+            garbage: GarbageConfig::default(),
+            lock_delay_ms: LOCK_DELAY.as_millis() as u64,
+            hold: true,
+            perfect_clear_attack: PERFECT_CLEAR_ATTACK,
+            garbage_delay_pieces: GARBAGE_DELAY_PIECES,
+        }
+    }
+}
+
+impl RoomRules {
+    /// The rules a game plays by with `config`.
+    pub fn from_config(config: &GameConfig) -> Self {
+        Self {
+            garbage: config.garbage,
+            lock_delay_ms: config.lock_delay.as_millis() as u64,
+            hold: config.hold_slots > 0,
+            perfect_clear_attack: config.perfect_clear_attack,
+            garbage_delay_pieces: config.garbage_delay_pieces,
+        }
+    }
+
+    /// Sets `config` up to play by these rules. Online games have a single hold slot.
+    pub fn apply(&self, config: &mut GameConfig) {
+        config.garbage = self.garbage;
+        config.lock_delay = Duration::from_millis(self.lock_delay_ms);
+        config.hold_slots = usize::from(self.hold);
+        config.perfect_clear_attack = self.perfect_clear_attack;
+        config.garbage_delay_pieces = self.garbage_delay_pieces;
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (0.0..=1.0).contains(&self.garbage.messiness)
+            && (1..=MAX_LOCK_DELAY_MS).contains(&self.lock_delay_ms)
+            && self.perfect_clear_attack <= MAX_PERFECT_CLEAR_ATTACK
+            && self.garbage_delay_pieces <= MAX_GARBAGE_DELAY_PIECES
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Why the server turned down a request.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    WrongPassword,
+    NoSuchRoom,
+    RoomExists,
+    InvalidRoomName,
+    /// Only whoever opened a room can change its rules.
+    NotRoomOwner,
+    InvalidRules,
+    /// The room already has `MAX_ROOM_PLAYERS` in it.
+    RoomFull,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::WrongPassword => write!(f, "Wrong room password"),
+            ErrorCode::NoSuchRoom => write!(f, "No such room"),
+            ErrorCode::RoomExists => write!(f, "A room with that name already exists"),
+            ErrorCode::InvalidRoomName => write!(f, "Invalid room name"),
+            ErrorCode::NotRoomOwner => write!(f, "Only the room's owner can change its rules"),
+            ErrorCode::InvalidRules => write!(f, "Invalid room rules"),
+            ErrorCode::RoomFull => write!(f, "That room is full"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub enum GameMessage {
-    Join { player_id: String },
-    GameState { player_id: String, score: i32 },
-    LineCleared { player_id: String, count: i32 },
+    /// First message each way on a new connection, saying what the sender can decode. A
+    /// client coming back after a dropped connection sends the token it was given at join.
+    Hello {
+        compression: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
+    /// Someone joined. Only the player's own Join carries their resume token.
+    Join {
+        player_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
+    GameState {
+        player_id: String,
+        score: i32,
+        #[serde(default)]
+        lines: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        /// The player hasn't touched anything for a while, though their game plays on.
+        #[serde(default)]
+        afk: bool,
+    },
+    LineCleared {
+        player_id: String,
+        count: i32,
+        /// Garbage that went out after cancelling incoming attacks; older clients leave
+        /// it out.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sent: Option<u32>,
+        #[serde(default, skip_serializing_if = "AttackKind::is_normal")]
+        kind: AttackKind,
+    },
+    BoardState {
+        player_id: String,
+        cells: Vec<Vec<Option<i32>>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        current: Option<Block>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next: Option<BlockKind>,
+    },
+    /// Asks the server to take our board updates over UDP as well, from datagrams that
+    /// carry `key`.
+    UdpRegister { key: u64 },
+    /// The server has heard our datagrams, so board updates can go over UDP from now on.
+    UdpReady,
     GameOver { player_id: String },
+    /// `player_id` topped out with `by`'s attack the last one they received, earning `by` a
+    /// KO badge. Only sent by the server.
+    KnockOut { player_id: String, by: String },
+    Paused { player_id: String },
+    Resumed { player_id: String },
+    /// The player abandoned their game mid-match.
+    Forfeit { player_id: String },
+    /// Takes the player out of their room and back to the lobby, staying connected.
+    LeaveRoom { player_id: String },
     PlayerLeft { player_id: String },
+    /// The server's full list of connected players.
+    LobbySnapshot {
+        players: Vec<PlayerState>,
+        match_active: bool,
+    },
+    /// Opens a new room and moves the sender into it.
+    CreateRoom {
+        name: String,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    JoinRoom {
+        room: String,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// The server moved us into `room`.
+    RoomJoined { room: String, locked: bool },
+    /// Asks to change the room's rules; from the server, the rules the room now plays
+    /// under, sent to everyone in it when they change and to each player who joins.
+    RoomRules { rules: RoomRules },
+    Error { code: ErrorCode },
+    /// An announcement from whoever runs the server.
+    ServerMessage { text: String },
+    /// The sender started a fresh game and is ready for the room's next match.
+    NewGame { player_id: String },
+    /// Final placements, sent to the room when a match ends.
+    MatchResult { result: MatchResult },
+    /// Asks the server to pair us with another waiting player.
+    QueueForMatch,
+    LeaveQueue,
+    /// Our 1-based place in the matchmaking queue.
+    QueueStatus { position: usize },
+    /// Sent to both players of a queued match once they are in their room.
+    MatchStart { seed: u64 },
+    /// Asks for the open rooms, answered with RoomList.
+    ListRooms,
+    RoomList { rooms: Vec<RoomInfo> },
+    /// A finished daily challenge for the day's leaderboard, with the input log the server
+    /// plays back to check it. Answered with RecordAccepted or RecordRejected, and only on
+    /// query connections.
+    SubmitRecord {
+        mode: GameMode,
+        date: String,
+        name: Option<String>,
+        claimed: ClaimedRecord,
+        replay_bytes: Vec<u8>,
+    },
+    RecordAccepted,
+    RecordRejected { reason: RecordRejection },
+    /// Asks for a day's leaderboard.
+    DailyTop { date: String },
+    /// The day's best daily challenge times, fastest first.
+    DailyLeaderboard {
+        date: String,
+        entries: Vec<DailyEntry>,
+    },
+    /// Asks for the recorded matches, answered with ReplayList.
+    ListReplays,
+    /// The recorded matches, newest first.
+    ReplayList { replays: Vec<ReplayInfo> },
+    /// Asks for a recorded match, answered with its file in ReplayChunks. There's a single
+    /// empty one if there's no such replay.
+    FetchReplay { id: String },
+    ReplayChunk { id: String, data: String, last: bool },
+}
+
+impl GameMessage {
+    /// The player a message is about, for messages that name one.
+    pub fn player_id(&self) -> Option<&str> {
+        match self {
+            GameMessage::Join { player_id, .. }
+            | GameMessage::GameState { player_id, .. }
+            | GameMessage::LineCleared { player_id, .. }
+            | GameMessage::BoardState { player_id, .. }
+            | GameMessage::GameOver { player_id }
+            | GameMessage::KnockOut { player_id, .. }
+            | GameMessage::Paused { player_id }
+            | GameMessage::Resumed { player_id }
+            | GameMessage::Forfeit { player_id }
+            | GameMessage::LeaveRoom { player_id }
+            | GameMessage::PlayerLeft { player_id }
+            | GameMessage::NewGame { player_id } => Some(player_id),
+            _ => None,
+        }
+    }
+}
+
+/// Deflates a large text frame into a binary one. Anything else passes through.
+pub fn compress_frame(msg: Message) -> Message {
+    match msg {
+        Message::Text(json) if json.len() > COMPRESSION_THRESHOLD => {
+            let mut encoder = DeflateEncoder::new(vec![FRAME_DEFLATE], Compression::default());
+            match encoder.write_all(json.as_bytes()).and_then(|_| encoder.finish()) {
+                Ok(bytes) => Message::Binary(bytes),
+                Err(_) => Message::Text(json),
+            }
+        }
+        msg => msg,
+    }
+}
+
+/// WebSocket limits for both ends: no message, or frame of one, past `MAX_FRAME_BYTES`, so a
+/// hostile peer can't make us buffer a huge board before it's even parsed.
+fn websocket_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_FRAME_BYTES),
+        max_frame_size: Some(MAX_FRAME_BYTES),
+        ..Default::default()
+    }
+}
+
+/// Reads a message from a plain text frame or a deflated binary one.
+pub fn decode_frame(msg: &Message) -> Option<GameMessage> {
+    match msg {
+        Message::Text(json) => serde_json::from_str(json).ok(),
+        Message::Binary(bytes) => match bytes.split_first() {
+            Some((&FRAME_DEFLATE, body)) => {
+                let mut json = String::new();
+                DeflateDecoder::new(body)
+                    .take(MAX_FRAME_BYTES as u64 + 1)
+                    .read_to_string(&mut json)
+                    .ok()?;
+                if json.len() > MAX_FRAME_BYTES {
+                    return None;
+                }
+                serde_json::from_str(&json).ok()
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Passes a value on only when it has changed, and at most once per interval. A change
+/// that arrives too soon is picked up by a later poll, so the latest value always goes out.
+pub struct Throttled<T> {
+    interval: Duration,
+    // Sends an unchanged value again once it is this old
+    repeat: Option<Duration>,
+    sent: Option<T>,
+    sent_at: Option<Instant>,
+}
+
+impl<T: PartialEq + Clone> Throttled<T> {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            repeat: None,
+            sent: None,
+            sent_at: None,
+        }
+    }
+
+    /// Sends an unchanged value again once it has gone this long, for channels that may
+    /// lose it.
+    pub fn set_repeat(&mut self, repeat: Option<Duration>) {
+        self.repeat = repeat;
+    }
+
+    pub fn poll(&mut self, value: T) -> Option<T> {
+        self.poll_at(value, Instant::now())
+    }
+
+    /// Returns `value` if it should be sent now.
+    pub fn poll_at(&mut self, value: T, now: Instant) -> Option<T> {
+        let stale = self
+            .repeat
+            .zip(self.sent_at)
+            .is_some_and(|(repeat, sent_at)| now.duration_since(sent_at) >= repeat);
+        if self.sent.as_ref() == Some(&value) && !stale {
+            return None;
+        }
+        if self
+            .sent_at
+            .is_some_and(|sent_at| now.duration_since(sent_at) < self.interval)
+        {
+            return None;
+        }
+        self.sent = Some(value.clone());
+        self.sent_at = Some(now);
+        Some(value)
+    }
+
+    /// Forgets the last value so the next poll sends, e.g. for a player who just joined.
+    pub fn reset(&mut self) {
+        self.sent = None;
+    }
+}
+
+/// Messages waiting to go out to the server. Snapshots of our own game only matter until
+/// the next one, so a newer snapshot replaces a queued one of the same kind, and they wait
+/// behind everything else. Everything else goes in order, so on a slow link attacks and
+/// game overs are held up by at most the latest board.
+#[derive(Default)]
+pub struct SendQueue {
+    messages: VecDeque<GameMessage>,
+    snapshots: VecDeque<GameMessage>,
+    // Snapshots replaced before they were sent
+    dropped: u64,
+    closed: bool,
+}
+
+impl SendQueue {
+    fn is_snapshot(msg: &GameMessage) -> bool {
+        matches!(msg, GameMessage::GameState { .. } | GameMessage::BoardState { .. })
+    }
+
+    /// Queues `msg`, unless the queue has been closed.
+    pub fn push(&mut self, msg: GameMessage) {
+        if self.closed {
+            return;
+        }
+        if !Self::is_snapshot(&msg) {
+            self.messages.push_back(msg);
+            return;
+        }
+        let kind = std::mem::discriminant(&msg);
+        match self.snapshots.iter_mut().find(|queued| std::mem::discriminant(*queued) == kind) {
+            Some(queued) => {
+                *queued = msg;
+                self.dropped += 1;
+            }
+            None => self.snapshots.push_back(msg),
+        }
+    }
+
+    /// The next message to send.
+    pub fn pop(&mut self) -> Option<GameMessage> {
+        self.messages.pop_front().or_else(|| self.snapshots.pop_front())
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len() + self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How many snapshots were replaced by a newer one before they went out.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Stops taking messages. Those already queued can still be popped.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+// A send queue shared with the task writing to the socket, which waits on `wake`
+#[derive(Default)]
+struct Outbox {
+    queue: Mutex<SendQueue>,
+    wake: Notify,
+}
+
+impl Outbox {
+    fn push(&self, msg: GameMessage) {
+        self.queue.lock().unwrap().push(msg);
+        self.wake.notify_one();
+    }
+
+    fn close(&self) {
+        self.queue.lock().unwrap().close();
+        self.wake.notify_one();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.queue.lock().unwrap().is_closed()
+    }
+
+    fn dropped(&self) -> u64 {
+        self.queue.lock().unwrap().dropped()
+    }
+
+    /// Waits for the next message, or returns None once the queue is closed and empty.
+    async fn next(&self) -> Option<GameMessage> {
+        loop {
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if let Some(msg) = queue.pop() {
+                    return Some(msg);
+                }
+                if queue.is_closed() {
+                    return None;
+                }
+            }
+            self.wake.notified().await;
+        }
+    }
+}
+
+/// How the connection to the server stands, for the indicator in the corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connected,
+    Reconnecting,
+    Offline,
+}
+
+// Rates are worked out over this long
+pub const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Turns a running total into a per second rate, updated once every `RATE_WINDOW`.
+#[derive(Debug, Default)]
+pub struct RateMeter {
+    window_start: Option<(Instant, u64)>,
+    rate: f32,
+}
+
+impl RateMeter {
+    /// The rate as of `now`, given the total so far.
+    pub fn sample(&mut self, total: u64, now: Instant) -> f32 {
+        match self.window_start {
+            Some((start, counted)) => {
+                let elapsed = now.duration_since(start);
+                if elapsed >= RATE_WINDOW {
+                    self.rate = total.saturating_sub(counted) as f32 / elapsed.as_secs_f32();
+                    self.window_start = Some((now, total));
+                }
+            }
+            None => self.window_start = Some((now, total)),
+        }
+        self.rate
+    }
+}
+
+// Counted by the client's read and write tasks, and read back for `NetStats`
+#[derive(Default)]
+struct NetCounters {
+    sent: AtomicU64,
+    received: AtomicU64,
+    // Late datagrams, passed over for a newer one already received
+    dropped: AtomicU64,
+    invalid: AtomicU64,
+    // Milliseconds after the client opened, plus one so 0 means nothing yet
+    last_received: AtomicU64,
+}
+
+impl NetCounters {
+    fn receive(&self, opened: Instant) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        let at = opened.elapsed().as_millis() as u64 + 1;
+        self.last_received.store(at, Ordering::Relaxed);
+    }
+}
+
+/// Message counts on a client's connection, for the debug overlay.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetStats {
+    pub sent: u64,
+    pub received: u64,
+    pub sent_per_sec: f32,
+    pub received_per_sec: f32,
+    /// Board snapshots replaced before they were sent, and late datagrams.
+    pub dropped: u64,
+    /// Frames and datagrams that didn't decode.
+    pub invalid: u64,
+    /// How long ago anything last arrived, if anything has.
+    pub since_received: Option<Duration>,
+}
+
+/// What we know about another player from their game state messages.
+#[derive(Debug, Clone)]
+pub struct PlayerInfo {
+    pub name: Option<String>,
+    pub score: i32,
+    pub lines: u32,
+    pub rating: Option<i32>,
+    /// KOs this match, each one boosting their attacks.
+    pub ko_badges: u32,
+    /// Whether they said they've stopped touching their game.
+    pub afk: bool,
+    /// Accent color from the server, if it hands them out.
+    pub accent: Option<u32>,
+    pub last_seen: Instant,
+}
+
+impl PlayerInfo {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            score: 0,
+            lines: 0,
+            rating: None,
+            ko_badges: 0,
+            afk: false,
+            accent: None,
+            last_seen: Instant::now(),
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.last_seen.elapsed() >= PLAYER_STALE_AFTER
+    }
+}
+
+impl Default for PlayerInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How a player's game stands, as shown on the scoreboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerStatus {
+    Playing,
+    Paused,
+    /// Still in the game, but nobody's at the keys.
+    Afk,
+    Out,
+    Forfeit,
+    /// No word from them lately; they may have lost their connection.
+    Stale,
+}
+
+impl PlayerStatus {
+    /// Whether the player's game is still going, paused or not.
+    pub fn is_in_game(self) -> bool {
+        matches!(self, PlayerStatus::Playing | PlayerStatus::Paused | PlayerStatus::Afk)
+    }
+}
+
+/// Brings `players` in line with a lobby snapshot: players the snapshot lacks are removed,
+/// missing ones are added and names, scores and ratings are updated. Returns the ids removed.
+pub fn reconcile_players(
+    players: &mut HashMap<String, PlayerInfo>,
+    snapshot: &[PlayerState],
+    own_id: Option<&str>,
+) -> Vec<String> {
+    let removed: Vec<String> = players
+        .keys()
+        .filter(|id| !snapshot.iter().any(|state| state.player_id == **id))
+        .cloned()
+        .collect();
+    for id in &removed {
+        players.remove(id);
+    }
+
+    for state in snapshot {
+        if Some(state.player_id.as_str()) == own_id {
+            continue;
+        }
+        let info = players.entry(state.player_id.clone()).or_default();
+        info.name.clone_from(&state.name);
+        info.score = state.score;
+        info.lines = state.lines;
+        info.rating = state.rating;
+        info.accent = state.accent;
+    }
+    removed
+}
+
+// Board updates come a few times a second at most, so a piece's move between two of them
+// is spread over no more than this
+const PIECE_INTERPOLATION_MAX: Duration = Duration::from_millis(250);
+/// A board that hasn't been updated for this long is shown frozen and dimmed.
+pub const REMOTE_STALE_AFTER: Duration = Duration::from_secs(2);
+
+/// Latest board another player has shared, along with the piece they are placing.
+#[derive(Clone, Default)]
+pub struct RemoteBoard {
+    pub board: Board,
+    pub current: Option<Block>,
+    pub next: Option<BlockKind>,
+    /// When the board last arrived, to show how out of date it is.
+    pub updated_at: Option<Instant>,
+    /// When the player last cleared their board, for a flash on it.
+    pub perfect_clear_at: Option<Instant>,
+    // The piece as the update before had it, and the time between the two, for moving it
+    // smoothly from one to the other
+    previous: Option<Block>,
+    interval: Duration,
+}
+
+impl RemoteBoard {
+    /// Takes in the piece from an update that arrived at `now`.
+    pub fn set_piece_at(&mut self, current: Option<Block>, now: Instant) {
+        self.previous = self.current;
+        self.current = current;
+        if let Some(updated_at) = self.updated_at {
+            self.interval = now.duration_since(updated_at).min(PIECE_INTERPOLATION_MAX);
+        }
+        self.updated_at = Some(now);
+    }
+
+    /// The falling piece as drawn at `now`, on its way from the last update's position to
+    /// this one's.
+    pub fn piece_at(&self, now: Instant) -> Option<RemotePiece> {
+        let block = self.current?;
+        let offset = match (self.previous, self.updated_at) {
+            (Some(from), Some(updated_at)) if !self.interval.is_zero() => {
+                let elapsed = now.duration_since(updated_at).as_secs_f32();
+                interpolate_offset(&from, &block, elapsed / self.interval.as_secs_f32())
+            }
+            _ => (0.0, 0.0),
+        };
+        Some(RemotePiece { block, offset })
+    }
+
+    /// Whether the board has gone without an update long enough to look frozen.
+    pub fn is_stale_at(&self, now: Instant) -> bool {
+        self.updated_at
+            .is_some_and(|updated_at| now.duration_since(updated_at) >= REMOTE_STALE_AFTER)
+    }
+}
+
+/// A falling piece as drawn on a mini board: where the latest update has it, drawn
+/// `offset` cells away while it catches up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RemotePiece {
+    pub block: Block,
+    pub offset: (f32, f32),
+}
+
+impl RemotePiece {
+    /// A piece drawn exactly where it is.
+    pub fn still(block: Block) -> Self {
+        Self {
+            block,
+            offset: (0.0, 0.0),
+        }
+    }
+}
+
+/// How far from `to` a piece is drawn `progress` of the way (0 to 1) through its move from
+/// `from`, in cells. A piece that turned, changed or went up, as a new piece does after a
+/// lock, is drawn where it is.
+pub fn interpolate_offset(from: &Block, to: &Block, progress: f32) -> (f32, f32) {
+    if from.kind != to.kind || from.rotation != to.rotation || to.y < from.y {
+        return (0.0, 0.0);
+    }
+    let left = 1.0 - progress.clamp(0.0, 1.0);
+    ((from.x - to.x) as f32 * left, (from.y - to.y) as f32 * left)
+}
+
+// Only a hash of the password is kept
+struct Room {
+    password_hash: Option<String>,
+    // Who opened the room, the only one who can change its rules. The lobby and
+    // matchmaking rooms have nobody
+    owner: Option<String>,
+    rules: RoomRules,
+    // Made by matchmaking; nobody else can join, and players go back to the lobby afterwards
+    private: bool,
+    current_match: Option<MatchLog>,
+    // Players who started a fresh game since the last match began
+    ready: HashSet<String>,
+    // Shared by everyone's pieces in a queued match, whose matches are recorded
+    seed: Option<u64>,
+    recording: Option<ReplayRecorder>,
+}
+
+/// Hashes a room password with a fresh salt. This is slow on purpose, so it runs on a
+/// blocking thread.
+fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Argon2 with default parameters hashes any password")
+        .to_string()
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+impl Room {
+    fn new(password_hash: Option<String>) -> Self {
+        Self {
+            password_hash,
+            owner: None,
+            rules: RoomRules::default(),
+            private: false,
+            current_match: None,
+            ready: HashSet::new(),
+            seed: None,
+            recording: None,
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.password_hash.is_some()
+    }
+}
+
+/// Remembers the path a WebSocket connection asked for.
+struct PathCallback<'a>(&'a mut String);
+
+impl Callback for PathCallback<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        self.0.push_str(request.uri().path());
+        Ok(response)
+    }
 }
 
 type Clients = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Message>>>>;
 type PlayerStates = Arc<Mutex<HashMap<String, PlayerState>>>;
+type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+type Queue = Arc<Mutex<MatchQueue>>;
+type SharedRatings = Arc<Mutex<Ratings>>;
+type SharedDaily = Arc<Mutex<DailyBoard>>;
+type SharedResumeTokens = Arc<Mutex<ResumeTokens>>;
+type SharedUdpPeers = Arc<Mutex<UdpPeers>>;
+
+/// Players taking board updates over UDP, and where their datagrams come from.
+#[derive(Default)]
+struct UdpPeers {
+    // Keys players registered over their WebSocket, and whose each is
+    keys: HashMap<u64, String>,
+    endpoints: HashMap<String, SocketAddr>,
+    sequences: SequenceFilter,
+}
+
+impl UdpPeers {
+    fn register(&mut self, player_id: &str, key: u64) {
+        self.forget(player_id);
+        self.keys.insert(key, player_id.to_string());
+    }
+
+    fn forget(&mut self, player_id: &str) {
+        self.keys.retain(|_, owner| owner != player_id);
+        self.endpoints.remove(player_id);
+        self.sequences.forget(player_id);
+    }
+
+    /// The player who registered `key`, noting that their datagrams come from `from`, and
+    /// whether that's news.
+    fn locate(&mut self, key: u64, from: SocketAddr) -> Option<(String, bool)> {
+        let player_id = self.keys.get(&key)?.clone();
+        let moved = self.endpoints.insert(player_id.clone(), from) != Some(from);
+        Some((player_id, moved))
+    }
+}
+
+/// Running totals since the server started.
+#[derive(Default)]
+pub struct ServerStats {
+    pub joins: AtomicU64,
+    pub leaves: AtomicU64,
+    pub relayed: AtomicU64,
+    /// Messages and requests the server refused.
+    pub rejected: AtomicU64,
+}
+
+impl ServerStats {
+    // Bumps a counter and returns its new value, for logging
+    fn count(counter: &AtomicU64) -> u64 {
+        counter.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// One room as seen from the admin console.
+pub struct RoomSummary {
+    pub name: String,
+    pub locked: bool,
+    pub players: Vec<PlayerState>,
+}
 
 pub struct MultiplayerServer {
     clients: Clients,
     player_states: PlayerStates,
+    rooms: Rooms,
+    queue: Queue,
+    ratings: SharedRatings,
+    daily: SharedDaily,
+    resume_tokens: SharedResumeTokens,
+    udp_peers: SharedUdpPeers,
+    pub stats: Arc<ServerStats>,
+    paths: Arc<ServerPaths>,
+}
+
+// Handles on the server's state, for a connection's task
+#[derive(Clone)]
+struct SharedState {
+    clients: Clients,
+    player_states: PlayerStates,
+    rooms: Rooms,
+    queue: Queue,
+    ratings: SharedRatings,
+    daily: SharedDaily,
+    resume_tokens: SharedResumeTokens,
+    udp_peers: SharedUdpPeers,
+    stats: Arc<ServerStats>,
+    paths: Arc<ServerPaths>,
+}
+
+impl Default for MultiplayerServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where the server keeps what outlives it: the results log, recorded matches and ratings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerPaths {
+    pub results_log: PathBuf,
+    pub replays: PathBuf,
+    pub ratings: PathBuf,
+}
+
+impl Default for ServerPaths {
+    /// The working directory.
+    fn default() -> Self {
+        Self::in_dir("")
+    }
+}
+
+impl ServerPaths {
+    /// Everything under `dir`, by its usual name.
+    pub fn in_dir(dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        Self {
+            results_log: dir.join(RESULTS_LOG_PATH),
+            replays: dir.join(REPLAYS_DIR),
+            ratings: dir.join(RATINGS_PATH),
+        }
+    }
+
+    /// A fresh directory of its own under the temp dir, so tests leave nothing behind in
+    /// the working directory.
+    #[cfg(test)]
+    pub(crate) fn scratch(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("tetris-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self::in_dir(dir)
+    }
 }
 
 impl MultiplayerServer {
+    /// A server keeping its files in the working directory.
     pub fn new() -> Self {
+        Self::with_paths(ServerPaths::default())
+    }
+
+    pub fn with_paths(paths: ServerPaths) -> Self {
+        let lobby = HashMap::from([(DEFAULT_ROOM.to_string(), Room::new(None))]);
+        let ratings = Ratings::load(&paths.ratings).unwrap_or_else(|e| {
+            warn!("Could not read {}, starting without ratings: {}", paths.ratings.display(), e);
+            Ratings::default()
+        });
         Self {
             clients: Arc::new(Mutex::new(HashMap::new())),
             player_states: Arc::new(Mutex::new(HashMap::new())),
+            rooms: Arc::new(Mutex::new(lobby)),
+            queue: Arc::new(Mutex::new(MatchQueue::default())),
+            ratings: Arc::new(Mutex::new(ratings)),
+            daily: Arc::new(Mutex::new(DailyBoard::default())),
+            resume_tokens: Arc::new(Mutex::new(ResumeTokens::default())),
+            udp_peers: Arc::new(Mutex::new(UdpPeers::default())),
+            stats: Arc::new(ServerStats::default()),
+            paths: Arc::new(paths),
+        }
+    }
+
+    fn shared(&self) -> SharedState {
+        SharedState {
+            clients: self.clients.clone(),
+            player_states: self.player_states.clone(),
+            rooms: self.rooms.clone(),
+            queue: self.queue.clone(),
+            ratings: self.ratings.clone(),
+            daily: self.daily.clone(),
+            resume_tokens: self.resume_tokens.clone(),
+            udp_peers: self.udp_peers.clone(),
+            stats: self.stats.clone(),
+            paths: self.paths.clone(),
         }
     }
 
     pub async fn start(&self, addr: &str) {
         let listener = TcpListener::bind(addr).await.expect("Failed to bind");
-        println!("WebSocket server listening on: {}", addr);
+        info!(%addr, "WebSocket server listening");
+
+        // Board updates can also come in over UDP, on the same port number
+        match UdpSocket::bind(addr).await {
+            Ok(socket) => {
+                tokio::spawn(Self::serve_udp(socket, self.shared()));
+            }
+            Err(e) => warn!("No UDP side channel, boards only go over WebSockets: {}", e),
+        }
+
+        // Regular snapshots repair any client whose player list has drifted. The queue is
+        // checked at the same time, since its rating windows widen as players wait, and so
+        // are dropped players who never came back
+        let shared = self.shared();
+        tokio::spawn(async move {
+            let SharedState {
+                clients,
+                player_states,
+                rooms,
+                queue,
+                ratings,
+                resume_tokens,
+                stats,
+                paths,
+                ..
+            } = shared;
+            let mut interval = tokio::time::interval(LOBBY_SNAPSHOT_INTERVAL);
+            loop {
+                interval.tick().await;
+                let expired = resume_tokens.lock().unwrap().expire(Instant::now());
+                for player_id in expired {
+                    Self::remove_player(
+                        &player_id,
+                        &clients,
+                        &player_states,
+                        &rooms,
+                        &ratings,
+                        &stats,
+                        &paths,
+                    );
+                }
+                Self::broadcast_snapshot(&clients, &player_states);
+                Self::pair_queued(&clients, &player_states, &rooms, &queue, &ratings, &paths);
+            }
+        });
 
         while let Ok((stream, _)) = listener.accept().await {
             let peer = stream.peer_addr().expect("Connected streams should have a peer address");
-            println!("Peer address: {}", peer);
+            let span = tracing::info_span!(
+                "connection",
+                %peer,
+                player_id = tracing::field::Empty,
+                room = tracing::field::Empty,
+            );
 
-            let clients = self.clients.clone();
-            let player_states = self.player_states.clone();
-            tokio::spawn(async move {
-                if let Err(e) = Self::handle_connection(stream, clients, player_states).await {
-                    eprintln!("Connection error: {}", e);
+            let shared = self.shared();
+            tokio::spawn(
+                async move {
+                    if let Err(e) = Self::handle_connection(stream, shared).await {
+                        warn!("Connection error: {}", e);
+                    }
                 }
-            });
+                .instrument(span),
+            );
         }
     }
 
     async fn handle_connection(
         stream: TcpStream,
-        clients: Clients,
-        player_states: PlayerStates,
+        shared: SharedState,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+        let SharedState {
+            clients,
+            player_states,
+            rooms,
+            queue,
+            ratings,
+            daily,
+            resume_tokens,
+            udp_peers,
+            stats,
+            paths,
+        } = shared.clone();
+        let mut path = String::new();
+        let callback = PathCallback(&mut path);
+        let config = Some(websocket_config());
+        let ws_stream =
+            tokio_tungstenite::accept_hdr_async_with_config(stream, callback, config).await?;
+        if path == QUERY_PATH {
+            return Self::handle_query(ws_stream, &player_states, &rooms, &daily, &paths).await;
+        }
         let (mut ws_sender, mut ws_receiver) = ws_stream.split();
         let (tx, mut rx) = mpsc::unbounded_channel();
-        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+        let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel::<Message>();
 
-        // Generate player ID
-        let player_id = uuid::Uuid::new_v4().to_string();
-        
-        // Send Join message to the new player
-        let join_msg = GameMessage::Join {
+        // Large frames are only compressed once the client says it can read them
+        let hello = GameMessage::Hello {
+            compression: true,
+            resume_token: None,
+        };
+        ws_sender.send(Message::Text(serde_json::to_string(&hello)?)).await?;
+        let peer_compression = Arc::new(AtomicBool::new(false));
+
+        // The client's Hello says whether it is coming back after a dropped connection.
+        // Clients too old to send one are treated as new once the wait is up
+        let mut resume_token = None;
+        match tokio::time::timeout(HELLO_TIMEOUT, ws_receiver.next()).await {
+            Ok(Some(Ok(msg))) => match decode_frame(&msg) {
+                Some(GameMessage::Hello {
+                    compression,
+                    resume_token: token,
+                }) => {
+                    peer_compression.store(compression, Ordering::Relaxed);
+                    resume_token = token;
+                }
+                _ => debug!("First message wasn't a Hello"),
+            },
+            Ok(Some(Err(e))) => return Err(e.into()),
+            Ok(None) => return Ok(()),
+            Err(_) => debug!("No Hello from the client"),
+        }
+
+        // A valid token takes the player back to their place, with their name, room, score
+        // and match as they left them
+        let resumed = resume_token.and_then(|token| {
+            let player_id = resume_tokens.lock().unwrap().resume(&token, Instant::now())?;
+            player_states.lock().unwrap().contains_key(&player_id).then_some(player_id)
+        });
+        let player_id = resumed.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let token = resume_tokens.lock().unwrap().issue(&player_id);
+        Span::current().record("player_id", player_id.as_str());
+
+        // Send Join message to the new player, with the token only they get to see
+        let own_join = GameMessage::Join {
             player_id: player_id.clone(),
+            resume_token: Some(token),
         };
-        ws_sender.send(Message::Text(serde_json::to_string(&join_msg)?)).await?;
+        ws_sender.send(Message::Text(serde_json::to_string(&own_join)?)).await?;
 
         // Add new player to states and get current states
-        let current_states = {
+        let (room, current_states) = {
             let mut states = player_states.lock().unwrap();
-            states.insert(player_id.clone(), PlayerState {
+            let accents: Vec<u32> = states
+                .values()
+                .filter(|state| state.room == DEFAULT_ROOM)
+                .filter_map(|state| state.accent)
+                .collect();
+            let state = states.entry(player_id.clone()).or_insert_with(|| PlayerState {
                 player_id: player_id.clone(),
                 score: 0,
+                lines: 0,
                 name: None,
+                paused: false,
+                pauses_used: 0,
+                room: DEFAULT_ROOM.to_string(),
+                rating: None,
+                accent: Some(pick_accent(&accents)),
+                unverified: false,
+                pause_serial: 0,
+                checks: Plausibility::default(),
             });
-            states.values().cloned().collect::<Vec<_>>()
+            let room = state.room.clone();
+            let current_states = states
+                .values()
+                .filter(|state| state.room == room)
+                .cloned()
+                .collect::<Vec<_>>();
+            (room, current_states)
         };
+        Span::current().record("room", room.as_str());
+        if resumed.is_some() {
+            for msg in Self::room_welcome(&rooms, &room) {
+                ws_sender.send(Message::Text(serde_json::to_string(&msg)?)).await?;
+            }
+        }
 
         // Store the sender in clients map
         {
@@ -92,151 +1248,2417 @@ impl MultiplayerServer {
             clients_guard.insert(player_id.clone(), tx.clone());
         }
 
+        Self::broadcast_snapshot(&clients, &player_states);
+
         // Send current player states to new player
         for state in current_states {
             let msg = GameMessage::GameState {
                 player_id: state.player_id,
                 score: state.score,
+                lines: state.lines,
+                name: state.name,
+                afk: false,
             };
             ws_sender.send(Message::Text(serde_json::to_string(&msg)?)).await?;
         }
 
-        // Broadcast new player joined to all other clients
-        {
-            let broadcast_join = Message::Text(serde_json::to_string(&join_msg)?);
-            let clients_guard = clients.lock().unwrap();
-            for (id, client) in clients_guard.iter() {
-                if *id != player_id {
-                    let _ = client.send(broadcast_join.clone());
-                }
-            }
-        }
+        // The room already knows a resumed player, so only a new one is announced
+        if resumed.is_some() {
+            info!("Player resumed");
+        } else {
+            let join_msg = GameMessage::Join {
+                player_id: player_id.clone(),
+                resume_token: None,
+            };
+            Self::send_to_room(&clients, &player_states, &room, Some(&player_id), &join_msg);
+            let joins = ServerStats::count(&stats.joins);
+            info!(joins, "Player joined");
+        }
 
-        // Handle outgoing messages to WebSocket
-        let outgoing_handle = tokio::spawn(async move {
-            while let Some(msg) = outgoing_rx.recv().await {
-                if let Err(e) = ws_sender.send(msg).await {
-                    eprintln!("WebSocket send error: {}", e);
-                    break;
+        // Handle outgoing messages to WebSocket, returning true if the server closed it
+        let compress = peer_compression.clone();
+        let mut outgoing_handle = tokio::spawn(
+            async move {
+                while let Some(msg) = outgoing_rx.recv().await {
+                    // A close frame from the server means the player was kicked
+                    let closing = msg.is_close();
+                    let msg = if compress.load(Ordering::Relaxed) {
+                        compress_frame(msg)
+                    } else {
+                        msg
+                    };
+                    if let Err(e) = ws_sender.send(msg).await {
+                        warn!("WebSocket send error: {}", e);
+                        return false;
+                    }
+                    if closing {
+                        return true;
+                    }
                 }
+                false
             }
-        });
+            .in_current_span(),
+        );
 
         // Handle incoming messages from other clients
         let incoming_handle = tokio::spawn(async move {
             while let Some(msg) = rx.recv().await {
                 if let Err(e) = outgoing_tx.send(msg) {
-                    eprintln!("Channel send error: {}", e);
+                    warn!("Channel send error: {}", e);
                     break;
                 }
             }
         });
 
-        // Handle messages from the WebSocket
-        while let Some(result) = ws_receiver.next().await {
+        // Handle messages from the WebSocket, until the socket fails or the player is kicked.
+        // Only a player who quits or is kicked gives up their place; one whose connection
+        // just dropped can come back to it
+        let mut quit = false;
+        loop {
+            let result = tokio::select! {
+                result = ws_receiver.next() => result,
+                kicked = &mut outgoing_handle => {
+                    quit = kicked.unwrap_or(false);
+                    None
+                }
+            };
+            let Some(result) = result else {
+                break;
+            };
             match result {
                 Ok(msg) => {
-                    if let Ok(game_msg) = serde_json::from_str::<GameMessage>(&msg.to_string()) {
+                    if msg.is_close() {
+                        quit = true;
+                    }
+                    if let Some(game_msg) = decode_frame(&msg) {
+                        if let GameMessage::Hello { compression, .. } = game_msg {
+                            peer_compression.store(compression, Ordering::Relaxed);
+                            continue;
+                        }
+                        if let GameMessage::UdpRegister { key } = game_msg {
+                            udp_peers.lock().unwrap().register(&player_id, key);
+                            continue;
+                        }
+                        if matches!(game_msg, GameMessage::PlayerLeft { .. }) {
+                            quit = true;
+                        }
+                        // Players only speak for themselves
+                        if game_msg.player_id().is_some_and(|id| id != player_id) {
+                            let rejected = ServerStats::count(&stats.rejected);
+                            warn!(rejected, "Message for another player");
+                            continue;
+                        }
+                        // Impossible reports go no further, and too many of them end the
+                        // connection
+                        if let Some(violations) =
+                            Self::check_report(&player_id, &game_msg, &player_states, &rooms)
+                        {
+                            ServerStats::count(&stats.rejected);
+                            if violations >= MAX_VIOLATIONS {
+                                warn!(violations, "Disconnecting for repeated impossible reports");
+                                let frame = CloseFrame {
+                                    code: CloseCode::Policy,
+                                    reason: "Too many impossible reports".into(),
+                                };
+                                let _ = tx.send(Message::Close(Some(frame)));
+                            }
+                            continue;
+                        }
+
                         // Update player state
-                        if let GameMessage::GameState { player_id, score } = &game_msg {
+                        if let GameMessage::GameState {
+                            player_id,
+                            score,
+                            lines,
+                            name,
+                            ..
+                        } = &game_msg
+                        {
+                            let rating =
+                                name.as_deref().map(|name| ratings.lock().unwrap().get(name));
                             let mut states = player_states.lock().unwrap();
                             if let Some(state) = states.get_mut(player_id) {
                                 state.score = *score;
+                                state.lines = *lines;
+                                state.name.clone_from(name);
+                                state.rating = rating;
                             }
                             drop(states);
                         }
 
-                        // Broadcast the message to all other clients
-                        let broadcast_msg = Message::Text(serde_json::to_string(&game_msg)?);
-                        let clients_guard = clients.lock().unwrap();
-                        for (id, client) in clients_guard.iter() {
-                            if *id != player_id {
-                                let _ = client.send(broadcast_msg.clone());
+                        Self::track_match(
+                            &player_id,
+                            &game_msg,
+                            &clients,
+                            &player_states,
+                            &rooms,
+                            &ratings,
+                            &paths,
+                        );
+                        if matches!(game_msg, GameMessage::NewGame { .. }) {
+                            Self::reset_pauses(&player_id, &player_states);
+                            continue;
+                        }
+
+                        match game_msg {
+                            GameMessage::QueueForMatch => {
+                                Self::queue_for_match(
+                                    &player_id,
+                                    &clients,
+                                    &player_states,
+                                    &rooms,
+                                    &queue,
+                                    &ratings,
+                                    &paths,
+                                );
+                                continue;
                             }
+                            GameMessage::LeaveQueue => {
+                                Self::leave_queue(&player_id, &clients, &queue);
+                                continue;
+                            }
+                            _ => {}
                         }
-                        drop(clients_guard);
+
+                        // Room requests are answered by the server, not relayed
+                        let room_request = match &game_msg {
+                            GameMessage::CreateRoom { name, password } => Some(
+                                Self::create_room(&player_id, name, password.as_deref(), &shared)
+                                    .await,
+                            ),
+                            GameMessage::JoinRoom { room, password } => Some(
+                                Self::join_room(&player_id, room, password.as_deref(), &shared)
+                                    .await,
+                            ),
+                            GameMessage::RoomRules { rules } => Some(Self::set_room_rules(
+                                &player_id,
+                                *rules,
+                                &clients,
+                                &player_states,
+                                &rooms,
+                            )),
+                            GameMessage::LeaveRoom { .. } => {
+                                Self::move_player(
+                                    &player_id,
+                                    DEFAULT_ROOM,
+                                    &clients,
+                                    &player_states,
+                                    &rooms,
+                                    &ratings,
+                                    &paths,
+                                );
+                                Some(Ok(()))
+                            }
+                            _ => None,
+                        };
+                        if let Some(result) = room_request {
+                            if let Err(code) = result {
+                                let rejected = ServerStats::count(&stats.rejected);
+                                warn!(rejected, ?code, "Room request refused");
+                                if code == ErrorCode::WrongPassword {
+                                    tokio::time::sleep(WRONG_PASSWORD_DELAY).await;
+                                }
+                                let _ = tx.send(Message::Text(serde_json::to_string(
+                                    &GameMessage::Error { code },
+                                )?));
+                            }
+                            continue;
+                        }
+
+                        // Only a player's own game updates are relayed; anything else is the
+                        // server's to send. Pauses over the allowance are dropped too
+                        let relay = match &game_msg {
+                            GameMessage::Paused { .. } => {
+                                Self::start_pause(&player_id, &clients, &player_states)
+                            }
+                            GameMessage::Resumed { .. } => {
+                                Self::end_pause(&player_id, &player_states)
+                            }
+                            GameMessage::GameState { .. }
+                            | GameMessage::LineCleared { .. }
+                            | GameMessage::BoardState { .. }
+                            | GameMessage::GameOver { .. }
+                            | GameMessage::Forfeit { .. } => true,
+                            _ => false,
+                        };
+                        if !relay {
+                            let rejected = ServerStats::count(&stats.rejected);
+                            debug!(rejected, "Message not relayed");
+                            continue;
+                        }
+
+                        // Broadcast the message to everyone else in the room
+                        let room = Self::room_of(&player_states, &player_id);
+                        Self::send_to_room(
+                            &clients,
+                            &player_states,
+                            &room,
+                            Some(&player_id),
+                            &game_msg,
+                        );
+                        Self::record_replay(&rooms, &room, &game_msg);
+                        let relayed = ServerStats::count(&stats.relayed);
+                        debug!(relayed, "Relayed message");
+                    } else if msg.is_text() || msg.is_binary() {
+                        let rejected = ServerStats::count(&stats.rejected);
+                        warn!(rejected, "Unreadable message");
                     }
                 }
                 Err(e) => {
-                    eprintln!("WebSocket error: {}", e);
+                    warn!("WebSocket error: {}", e);
                     break;
                 }
             }
         }
 
-        // Clean up when client disconnects
-        {
-            let mut clients_guard = clients.lock().unwrap();
-            clients_guard.remove(&player_id);
+        // Clean up when client disconnects, unless the player has already resumed on a new
+        // connection, which now has their place
+        let replaced = !clients
+            .lock()
+            .unwrap()
+            .get(&player_id)
+            .is_some_and(|client| client.same_channel(&tx));
+        if replaced {
+            debug!("Connection replaced by a resumed one");
+        } else {
+            Self::leave_queue(&player_id, &clients, &queue);
+            clients.lock().unwrap().remove(&player_id);
+            udp_peers.lock().unwrap().forget(&player_id);
+            if quit {
+                resume_tokens.lock().unwrap().revoke(&player_id);
+                Self::remove_player(
+                    &player_id,
+                    &clients,
+                    &player_states,
+                    &rooms,
+                    &ratings,
+                    &stats,
+                    &paths,
+                );
+            } else {
+                resume_tokens.lock().unwrap().drop_connection(&player_id, Instant::now());
+                info!("Connection dropped, keeping the player's place");
+            }
+        }
+
+        // Clean up tasks
+        outgoing_handle.abort();
+        incoming_handle.abort();
+
+        Ok(())
+    }
+
+    /// Relays board updates that come in over UDP to the rest of the sender's room: over
+    /// UDP to those who take it, and over their WebSocket to the rest. Anything more
+    /// important than a board has to come over the WebSocket.
+    async fn serve_udp(socket: UdpSocket, shared: SharedState) {
+        let SharedState {
+            clients,
+            player_states,
+            rooms,
+            udp_peers,
+            stats,
+            ..
+        } = shared;
+        let mut buf = [0; MAX_DATAGRAM_BYTES];
+        loop {
+            let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+            let located = decode_datagram(&buf[..len]).and_then(|datagram| {
+                let found = udp_peers.lock().unwrap().locate(datagram.key, from);
+                found.map(|found| (datagram, found))
+            });
+            let Some((datagram, (player_id, moved))) = located else {
+                let rejected = ServerStats::count(&stats.rejected);
+                debug!(rejected, %from, "Unknown datagram");
+                continue;
+            };
+            if moved {
+                info!(%player_id, %from, "UDP side channel open");
+                if let Ok(json) = serde_json::to_string(&GameMessage::UdpReady) {
+                    if let Some(client) = clients.lock().unwrap().get(&player_id) {
+                        let _ = client.send(Message::Text(json));
+                    }
+                }
+            }
+
+            // Probes carry nothing. Players only speak for themselves, and only in boards
+            let Some(msg) = datagram.msg else {
+                continue;
+            };
+            if !matches!(msg, GameMessage::BoardState { .. })
+                || msg.player_id() != Some(player_id.as_str())
+            {
+                let rejected = ServerStats::count(&stats.rejected);
+                warn!(rejected, %player_id, "Datagram refused");
+                continue;
+            }
+            if !udp_peers.lock().unwrap().sequences.accept(&player_id, datagram.seq) {
+                continue;
+            }
+
+            if let GameMessage::BoardState { cells, .. } = &msg {
+                if let Some(state) = player_states.lock().unwrap().get_mut(&player_id) {
+                    state.checks.board(Instant::now(), filled_cells(cells));
+                }
+            }
+            let room = Self::room_of(&player_states, &player_id);
+            Self::record_replay(&rooms, &room, &msg);
+            let members = Self::room_members(&player_states, &room, Some(&player_id));
+            let endpoints: Vec<(String, Option<SocketAddr>)> = {
+                let peers = udp_peers.lock().unwrap();
+                members
+                    .into_iter()
+                    .map(|id| {
+                        let endpoint = peers.endpoints.get(&id).copied();
+                        (id, endpoint)
+                    })
+                    .collect()
+            };
+            let Ok(json) = serde_json::to_string(&msg) else {
+                continue;
+            };
+            let relayed = Datagram {
+                key: 0,
+                seq: datagram.seq,
+                msg: Some(msg),
+            };
+            let Some(bytes) = encode_datagram(&relayed) else {
+                continue;
+            };
+            for (id, endpoint) in endpoints {
+                match endpoint {
+                    Some(endpoint) => {
+                        let _ = socket.send_to(&bytes, endpoint).await;
+                    }
+                    None => {
+                        if let Some(client) = clients.lock().unwrap().get(&id) {
+                            let _ = client.send(Message::Text(json.clone()));
+                        }
+                    }
+                }
+            }
+            let relayed = ServerStats::count(&stats.relayed);
+            debug!(relayed, "Relayed datagram");
         }
+    }
+
+    /// Removes a player who has gone for good, taking them out of their room and match.
+    fn remove_player(
+        player_id: &str,
+        clients: &Clients,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        ratings: &SharedRatings,
+        stats: &ServerStats,
+        paths: &ServerPaths,
+    ) {
+        let room = Self::room_of(player_states, player_id);
         {
             let mut states = player_states.lock().unwrap();
-            states.remove(&player_id);
+            states.remove(player_id);
         }
+        Self::leave_match(player_id, &room, clients, player_states, rooms, ratings, paths);
+        Self::close_room_if_empty(player_states, rooms, &room);
+        Self::broadcast_snapshot(clients, player_states);
 
         // Broadcast player left message
         let left_msg = GameMessage::PlayerLeft {
-            player_id: player_id.clone(),
+            player_id: player_id.to_string(),
+        };
+        Self::send_to_room(clients, player_states, &room, None, &left_msg);
+        let leaves = ServerStats::count(&stats.leaves);
+        info!(%player_id, leaves, "Player left");
+    }
+
+    /// Sends every client the player list of their room. A match is on once two players are in.
+    fn broadcast_snapshot(clients: &Clients, player_states: &PlayerStates) {
+        let mut rooms: HashMap<String, Vec<PlayerState>> = HashMap::new();
+        for state in player_states.lock().unwrap().values() {
+            rooms.entry(state.room.clone()).or_default().push(state.clone());
+        }
+
+        let clients_guard = clients.lock().unwrap();
+        for players in rooms.into_values() {
+            let ids: Vec<String> = players.iter().map(|state| state.player_id.clone()).collect();
+            let snapshot = GameMessage::LobbySnapshot {
+                match_active: players.len() >= 2,
+                players,
+            };
+            let Ok(json) = serde_json::to_string(&snapshot) else {
+                continue;
+            };
+            for id in ids {
+                if let Some(client) = clients_guard.get(&id) {
+                    let _ = client.send(Message::Text(json.clone()));
+                }
+            }
+        }
+    }
+
+    fn room_of(player_states: &PlayerStates, player_id: &str) -> String {
+        player_states
+            .lock()
+            .unwrap()
+            .get(player_id)
+            .map_or_else(|| DEFAULT_ROOM.to_string(), |state| state.room.clone())
+    }
+
+    fn players_in(player_states: &PlayerStates, room: &str) -> usize {
+        let states = player_states.lock().unwrap();
+        states.values().filter(|state| state.room == room).count()
+    }
+
+    /// Sends `msg` to everyone in `room` apart from `except`.
+    fn send_to_room(
+        clients: &Clients,
+        player_states: &PlayerStates,
+        room: &str,
+        except: Option<&str>,
+        msg: &GameMessage,
+    ) {
+        let Ok(json) = serde_json::to_string(msg) else {
+            return;
+        };
+        let members = Self::room_members(player_states, room, except);
+        let clients_guard = clients.lock().unwrap();
+        for id in members {
+            if let Some(client) = clients_guard.get(&id) {
+                let _ = client.send(Message::Text(json.clone()));
+            }
+        }
+    }
+
+    /// Everyone in `room` apart from `except`.
+    fn room_members(player_states: &PlayerStates, room: &str, except: Option<&str>) -> Vec<String> {
+        player_states
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|state| state.room == room && Some(state.player_id.as_str()) != except)
+            .map(|state| state.player_id.clone())
+            .collect()
+    }
+
+    async fn create_room(
+        player_id: &str,
+        name: &str,
+        password: Option<&str>,
+        shared: &SharedState,
+    ) -> Result<(), ErrorCode> {
+        let SharedState {
+            clients,
+            player_states,
+            rooms,
+            ratings,
+            paths,
+            ..
+        } = shared;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ErrorCode::InvalidRoomName);
+        }
+        if rooms.lock().unwrap().contains_key(name) {
+            return Err(ErrorCode::RoomExists);
+        }
+        // An empty password is the same as none. Hashing happens outside the rooms lock
+        let password = password.filter(|password| !password.is_empty()).map(str::to_string);
+        let password_hash = match password {
+            Some(password) => Some(
+                tokio::task::spawn_blocking(move || hash_password(&password))
+                    .await
+                    .expect("Password hashing doesn't panic"),
+            ),
+            None => None,
         };
-        let broadcast_msg = Message::Text(serde_json::to_string(&left_msg)?);
         {
-            let clients_guard = clients.lock().unwrap();
-            for client in clients_guard.values() {
-                let _ = client.send(broadcast_msg.clone());
+            // Someone else may have taken the name while we were hashing
+            let mut rooms_guard = rooms.lock().unwrap();
+            if rooms_guard.contains_key(name) {
+                return Err(ErrorCode::RoomExists);
             }
+            let mut room = Room::new(password_hash);
+            room.owner = Some(player_id.to_string());
+            rooms_guard.insert(name.to_string(), room);
         }
+        Self::move_player(player_id, name, clients, player_states, rooms, ratings, paths);
+        Ok(())
+    }
 
-        // Clean up tasks
-        outgoing_handle.abort();
-        incoming_handle.abort();
+    async fn join_room(
+        player_id: &str,
+        room: &str,
+        password: Option<&str>,
+        shared: &SharedState,
+    ) -> Result<(), ErrorCode> {
+        let SharedState {
+            clients,
+            player_states,
+            rooms,
+            ratings,
+            paths,
+            ..
+        } = shared;
+        let password_hash = {
+            let rooms_guard = rooms.lock().unwrap();
+            let Some(found) = rooms_guard.get(room).filter(|found| !found.private) else {
+                return Err(ErrorCode::NoSuchRoom);
+            };
+            found.password_hash.clone()
+        };
+        let full = |players: usize| room_limit(room).is_some_and(|limit| players >= limit);
+        if full(Self::players_in(player_states, room)) {
+            return Err(ErrorCode::RoomFull);
+        }
+        if let Some(hash) = password_hash {
+            let password = password.map(str::to_string);
+            let accepted = tokio::task::spawn_blocking(move || {
+                password.is_some_and(|password| verify_password(&hash, &password))
+            })
+            .await
+            .unwrap_or(false);
+            if !accepted {
+                return Err(ErrorCode::WrongPassword);
+            }
+            // The room may have closed or filled up while the password was checked
+            if !rooms.lock().unwrap().contains_key(room) {
+                return Err(ErrorCode::NoSuchRoom);
+            }
+            if full(Self::players_in(player_states, room)) {
+                return Err(ErrorCode::RoomFull);
+            }
+        }
+        Self::move_player(player_id, room, clients, player_states, rooms, ratings, paths);
+        Ok(())
+    }
 
+    /// Changes the rules of the sender's room, if they opened it, and tells everyone in it.
+    fn set_room_rules(
+        player_id: &str,
+        rules: RoomRules,
+        clients: &Clients,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+    ) -> Result<(), ErrorCode> {
+        if !rules.is_valid() {
+            return Err(ErrorCode::InvalidRules);
+        }
+        let room = Self::room_of(player_states, player_id);
+        {
+            let mut rooms_guard = rooms.lock().unwrap();
+            let found = rooms_guard.get_mut(&room).ok_or(ErrorCode::NoSuchRoom)?;
+            if found.owner.as_deref() != Some(player_id) {
+                return Err(ErrorCode::NotRoomOwner);
+            }
+            found.rules = rules;
+        }
+        info!(?rules, "Room rules changed");
+        Self::send_to_room(clients, player_states, &room, None, &GameMessage::RoomRules { rules });
         Ok(())
     }
+
+    /// Answers one-off requests, such as the room list, on a connection that never joins
+    /// the lobby.
+    async fn handle_query(
+        ws_stream: WebSocketStream<TcpStream>,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        daily: &SharedDaily,
+        paths: &ServerPaths,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+        while let Some(msg) = ws_receiver.next().await {
+            let reply = match decode_frame(&msg?) {
+                Some(GameMessage::ListRooms) => GameMessage::RoomList {
+                    rooms: Self::room_list(player_states, rooms),
+                },
+                Some(GameMessage::SubmitRecord {
+                    mode,
+                    date,
+                    name,
+                    claimed,
+                    replay_bytes,
+                }) => {
+                    let name = name.unwrap_or_else(|| ANONYMOUS_NAME.to_string());
+                    // Played back off the async threads, within a budget of its own
+                    let seed = daily_seed(&date);
+                    let verdict = tokio::task::spawn_blocking(move || {
+                        verify_record(mode, seed, &claimed, &replay_bytes, VERIFY_BUDGET)
+                    })
+                    .await?;
+                    let time_ms = claimed.time_ms;
+                    match verdict {
+                        Ok(()) => {
+                            info!(%date, %name, time_ms, "Daily result verified");
+                            daily.lock().unwrap().submit(&date, &name, time_ms);
+                            GameMessage::RecordAccepted
+                        }
+                        Err(reason) => {
+                            warn!(%date, %name, time_ms, %reason, "Daily result refused");
+                            GameMessage::RecordRejected { reason }
+                        }
+                    }
+                }
+                Some(GameMessage::DailyTop { date }) => {
+                    let entries = daily.lock().unwrap().top(&date, DAILY_TOP);
+                    GameMessage::DailyLeaderboard { date, entries }
+                }
+                Some(GameMessage::ListReplays) => {
+                    let dir = paths.replays.clone();
+                    let listed = move || replay::list_replays(&dir);
+                    let replays = tokio::task::spawn_blocking(listed).await?.unwrap_or_else(|e| {
+                        warn!("Could not list the replays: {}", e);
+                        Vec::new()
+                    });
+                    GameMessage::ReplayList { replays }
+                }
+                Some(GameMessage::FetchReplay { id }) => {
+                    let (dir, wanted) = (paths.replays.clone(), id.clone());
+                    let text = tokio::task::spawn_blocking(move || read_replay(&dir, &wanted))
+                    .await?
+                    .unwrap_or_else(|e| {
+                        warn!(%id, "Could not read the replay: {}", e);
+                        None
+                    })
+                    .unwrap_or_default();
+                    // Sent in pieces, so no single message gets too big
+                    let chunks = replay_chunks(&text, REPLAY_CHUNK_BYTES);
+                    let count = chunks.len().max(1);
+                    for index in 0..count {
+                        let chunk = GameMessage::ReplayChunk {
+                            id: id.clone(),
+                            data: chunks.get(index).copied().unwrap_or_default().to_string(),
+                            last: index + 1 == count,
+                        };
+                        ws_sender.send(Message::Text(serde_json::to_string(&chunk)?)).await?;
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            ws_sender.send(Message::Text(serde_json::to_string(&reply)?)).await?;
+        }
+        Ok(())
+    }
+
+    /// The rooms anyone can join, by name, with how many players are in each.
+    fn room_list(player_states: &PlayerStates, rooms: &Rooms) -> Vec<RoomInfo> {
+        let mut players: HashMap<String, usize> = HashMap::new();
+        for state in player_states.lock().unwrap().values() {
+            *players.entry(state.room.clone()).or_default() += 1;
+        }
+        let mut list: Vec<RoomInfo> = rooms
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, room)| !room.private)
+            .map(|(name, room)| RoomInfo {
+                name: name.clone(),
+                players: players.get(name).copied().unwrap_or(0),
+                limit: room_limit(name),
+                locked: room.is_locked(),
+                in_match: room.current_match.is_some(),
+                rules: room.rules,
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// Takes a player out of their room and into `room`, telling both rooms about it.
+    fn move_player(
+        player_id: &str,
+        room: &str,
+        clients: &Clients,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        ratings: &SharedRatings,
+        paths: &ServerPaths,
+    ) {
+        let old_room = {
+            let mut states = player_states.lock().unwrap();
+            let Some(state) = states.get_mut(player_id) else {
+                return;
+            };
+            if state.room == room {
+                None
+            } else {
+                state.paused = false;
+                Some(std::mem::replace(&mut state.room, room.to_string()))
+            }
+        };
+
+        if let Some(old_room) = old_room {
+            Span::current().record("room", room);
+            info!(from = %old_room, "Changed room");
+            let left = GameMessage::PlayerLeft {
+                player_id: player_id.to_string(),
+            };
+            Self::send_to_room(clients, player_states, &old_room, None, &left);
+            Self::leave_match(player_id, &old_room, clients, player_states, rooms, ratings, paths);
+            Self::close_room_if_empty(player_states, rooms, &old_room);
+
+            let join = GameMessage::Join {
+                player_id: player_id.to_string(),
+                resume_token: None,
+            };
+            Self::send_to_room(clients, player_states, room, Some(player_id), &join);
+            // Queued matches start once both players have begun the seeded game
+            let private = rooms.lock().unwrap().get(room).is_some_and(|found| found.private);
+            if !private {
+                Self::ready_for_match(player_id, room, player_states, rooms, paths);
+            }
+        }
+
+        for msg in Self::room_welcome(rooms, room) {
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Some(client) = clients.lock().unwrap().get(player_id) {
+                    let _ = client.send(Message::Text(json));
+                }
+            }
+        }
+        Self::broadcast_snapshot(clients, player_states);
+    }
+
+    /// What a player placed in `room` is told about it: whether it's locked, and the rules
+    /// it plays by.
+    fn room_welcome(rooms: &Rooms, room: &str) -> [GameMessage; 2] {
+        let (locked, rules) = rooms
+            .lock()
+            .unwrap()
+            .get(room)
+            .map_or((false, RoomRules::default()), |found| (found.is_locked(), found.rules));
+        let joined = GameMessage::RoomJoined {
+            room: room.to_string(),
+            locked,
+        };
+        [joined, GameMessage::RoomRules { rules }]
+    }
+
+    fn close_room_if_empty(player_states: &PlayerStates, rooms: &Rooms, room: &str) {
+        let occupied = player_states
+            .lock()
+            .unwrap()
+            .values()
+            .any(|state| state.room == room);
+        if !occupied && room != DEFAULT_ROOM {
+            rooms.lock().unwrap().remove(room);
+        }
+    }
+
+    /// Marks a player as ready for their room's next match, which starts once two are ready.
+    fn ready_for_match(
+        player_id: &str,
+        room: &str,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        paths: &ServerPaths,
+    ) {
+        let mut rooms_guard = rooms.lock().unwrap();
+        let Some(found) = rooms_guard.get_mut(room) else {
+            return;
+        };
+        found.ready.insert(player_id.to_string());
+        Self::start_match_if_ready(found, player_states, paths);
+    }
+
+    fn start_match_if_ready(room: &mut Room, player_states: &PlayerStates, paths: &ServerPaths) {
+        if room.current_match.is_some() || room.ready.len() < 2 {
+            return;
+        }
+        let states = player_states.lock().unwrap();
+        let players: Vec<(String, Option<String>)> = room
+            .ready
+            .drain()
+            .filter_map(|id| states.get(&id).map(|state| (id, state.name.clone())))
+            .collect();
+        info!(players = players.len(), "Match started");
+        if room.seed.is_some() {
+            let recorded = players
+                .iter()
+                .map(|(player_id, name)| ReplayPlayer {
+                    player_id: player_id.clone(),
+                    name: name.clone(),
+                })
+                .collect();
+            let dir = &paths.replays;
+            room.recording = match ReplayRecorder::start(dir, room.seed, recorded, Instant::now()) {
+                Ok(recorder) => Some(recorder),
+                Err(e) => {
+                    warn!("Could not start recording the match: {}", e);
+                    None
+                }
+            };
+        }
+        room.current_match = Some(MatchLog::new(players));
+    }
+
+    /// Adds a message to the replay of `room`'s match, if it's being recorded.
+    fn record_replay(rooms: &Rooms, room: &str, msg: &GameMessage) {
+        let mut rooms_guard = rooms.lock().unwrap();
+        let Some(found) = rooms_guard.get_mut(room) else {
+            return;
+        };
+        let Some(recorder) = &mut found.recording else {
+            return;
+        };
+        if let Err(e) = recorder.record(msg, Instant::now()) {
+            warn!(replay = recorder.id(), "Stopped recording the match: {}", e);
+            found.recording = None;
+        }
+    }
+
+    /// Feeds a player's message into their room's match, if it means anything to it.
+    fn track_match(
+        player_id: &str,
+        msg: &GameMessage,
+        clients: &Clients,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        ratings: &SharedRatings,
+        paths: &ServerPaths,
+    ) {
+        let room = Self::room_of(player_states, player_id);
+        let player_id = player_id.to_string();
+        let event = match msg {
+            GameMessage::GameState { score, name, .. } => MatchEvent::Score {
+                player_id,
+                score: *score,
+                name: name.clone(),
+            },
+            GameMessage::LineCleared {
+                count, sent, kind, ..
+            } => MatchEvent::Attack {
+                lines: Self::attack_lines(&player_id, &room, *count, *sent, *kind, rooms),
+                player_id,
+                kind: *kind,
+            },
+            GameMessage::GameOver { .. } => MatchEvent::ToppedOut { player_id },
+            GameMessage::Forfeit { .. } => MatchEvent::Forfeited { player_id },
+            GameMessage::NewGame { .. } => {
+                // Starting over mid-match gives up the match
+                let event = MatchEvent::Forfeited {
+                    player_id: player_id.clone(),
+                };
+                Self::record_match_event(
+                    &room,
+                    event,
+                    clients,
+                    player_states,
+                    rooms,
+                    ratings,
+                    paths,
+                );
+                Self::ready_for_match(&player_id, &room, player_states, rooms, paths);
+                return;
+            }
+            _ => return,
+        };
+        Self::record_match_event(&room, event, clients, player_states, rooms, ratings, paths);
+    }
+
+    /// Garbage a player's clear sends in `room`, with their KO badges and the room's
+    /// perfect clear attack.
+    fn attack_lines(
+        player_id: &str,
+        room: &str,
+        count: i32,
+        sent: Option<u32>,
+        kind: AttackKind,
+        rooms: &Rooms,
+    ) -> u32 {
+        let locked = rooms.lock().unwrap();
+        let current_room = locked.get(room);
+        let badges = current_room
+            .and_then(|room| room.current_match.as_ref())
+            .map_or(0, |current| current.ko_badges(player_id));
+        let perfect_clear = current_room
+            .filter(|_| kind == AttackKind::PerfectClear)
+            .map(|room| room.rules.perfect_clear_attack);
+        sent_attack(count, sent, badges, perfect_clear)
+    }
+
+    /// Runs a player's reported score, clears and boards past the plausibility checks.
+    /// Anything impossible marks the player unverified and is logged with what gave it
+    /// away; returns how many times they've now been caught.
+    fn check_report(
+        player_id: &str,
+        msg: &GameMessage,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+    ) -> Option<u32> {
+        let now = Instant::now();
+        let attack = match msg {
+            GameMessage::LineCleared {
+                count, sent, kind, ..
+            } => {
+                let room = Self::room_of(player_states, player_id);
+                Self::attack_lines(player_id, &room, *count, *sent, *kind, rooms)
+            }
+            _ => 0,
+        };
+        let mut states = player_states.lock().unwrap();
+        let state = states.get_mut(player_id)?;
+        let violation = match msg {
+            GameMessage::GameState { score, lines, .. } => state.checks.score(now, *score, *lines),
+            GameMessage::LineCleared { count, .. } => {
+                let count = u32::try_from(*count).unwrap_or(0);
+                let clear = state.checks.clear(now, count);
+                clear.or_else(|| state.checks.attack(now, attack))
+            }
+            GameMessage::BoardState { cells, .. } => {
+                state.checks.board(now, filled_cells(cells));
+                None
+            }
+            _ => None,
+        }?;
+        state.unverified = true;
+        let violations = state.checks.violations();
+        warn!(%violation, violations, "Impossible report");
+        Some(violations)
+    }
+
+    /// Takes a player out of their room's match; they don't get a placement.
+    fn leave_match(
+        player_id: &str,
+        room: &str,
+        clients: &Clients,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        ratings: &SharedRatings,
+        paths: &ServerPaths,
+    ) {
+        if let Some(found) = rooms.lock().unwrap().get_mut(room) {
+            found.ready.remove(player_id);
+        }
+        let event = MatchEvent::Disconnected {
+            player_id: player_id.to_string(),
+        };
+        Self::record_match_event(room, event, clients, player_states, rooms, ratings, paths);
+    }
+
+    /// Records a match event, and once a single player is left standing sends the room
+    /// the results and appends them to the results log.
+    fn record_match_event(
+        room: &str,
+        event: MatchEvent,
+        clients: &Clients,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        ratings: &SharedRatings,
+        paths: &ServerPaths,
+    ) {
+        let (knock_out, finished) = {
+            let mut rooms_guard = rooms.lock().unwrap();
+            let Some(found) = rooms_guard.get_mut(room) else {
+                return;
+            };
+            let Some(current) = &mut found.current_match else {
+                return;
+            };
+            let knock_out = current.record(event);
+            let finished = if current.is_over() {
+                let result = current.finish();
+                found.current_match = None;
+                let recording = found.recording.take();
+                Self::start_match_if_ready(found, player_states, paths);
+                Some((result, found.private, recording))
+            } else {
+                None
+            };
+            (knock_out, finished)
+        };
+
+        // Sent ahead of any result, so the badge shows on the final scoreboard
+        if let Some(MatchEvent::KnockOut { player_id, by }) = knock_out {
+            let msg = GameMessage::KnockOut { player_id, by };
+            Self::send_to_room(clients, player_states, room, None, &msg);
+            Self::record_replay(rooms, room, &msg);
+        }
+        let Some((mut result, private, recording)) = finished else {
+            return;
+        };
+
+        info!(players = result.placements.len(), "Match finished");
+        {
+            let states = player_states.lock().unwrap();
+            for placement in &mut result.placements {
+                placement.unverified =
+                    states.get(&placement.player_id).is_some_and(|state| state.unverified);
+            }
+        }
+        if private {
+            Self::update_ratings(&mut result, player_states, ratings);
+        }
+        // File writes go to a blocking thread rather than holding up the runtime
+        let logged = result.clone();
+        let (results_log, replays) = (paths.results_log.clone(), paths.replays.clone());
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = append_results_log(&results_log, &logged) {
+                warn!("Could not write the results log: {}", e);
+            }
+            if let Some(recorder) = recording {
+                match recorder.finish(&logged) {
+                    Ok(id) => info!(replay = %id, "Match recorded"),
+                    Err(e) => warn!("Could not finish the replay: {}", e),
+                }
+                if let Err(e) = prune_replays(&replays, REPLAY_RETENTION) {
+                    warn!("Could not prune the replays: {}", e);
+                }
+            }
+        });
+        let msg = GameMessage::MatchResult { result };
+        Self::send_to_room(clients, player_states, room, None, &msg);
+
+        if private {
+            let players: Vec<String> = player_states
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|state| state.room == room)
+                .map(|state| state.player_id.clone())
+                .collect();
+            for player_id in players {
+                Self::move_player(
+                    &player_id,
+                    DEFAULT_ROOM,
+                    clients,
+                    player_states,
+                    rooms,
+                    ratings,
+                    paths,
+                );
+            }
+        }
+    }
+
+    /// Moves rating points from the loser of a ranked 1v1 to the winner, and notes
+    /// the change in the result. Players without a name aren't rated.
+    fn update_ratings(
+        result: &mut MatchResult,
+        player_states: &PlayerStates,
+        ratings: &SharedRatings,
+    ) {
+        let [winner, loser] = result.placements.as_mut_slice() else {
+            return;
+        };
+        if winner.unverified || loser.unverified {
+            info!("Unverified match not rated");
+            return;
+        }
+        let (Some(1), Some(winner_name), Some(loser_name)) =
+            (winner.place, winner.name.clone(), loser.name.clone())
+        else {
+            return;
+        };
+        if winner_name == loser_name {
+            return;
+        }
+
+        let (change, winner_rating, loser_rating) = {
+            let mut ratings = ratings.lock().unwrap();
+            let change = ratings.record_win(&winner_name, &loser_name);
+            let saved = ratings.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = saved.save() {
+                    warn!("Could not write the ratings: {}", e);
+                }
+            });
+            (change, ratings.get(&winner_name), ratings.get(&loser_name))
+        };
+        info!(winner = %winner_name, loser = %loser_name, change, "Ratings updated");
+        winner.rating_change = Some(change);
+        loser.rating_change = Some(-change);
+
+        for state in player_states.lock().unwrap().values_mut() {
+            match state.name.as_deref() {
+                Some(name) if name == winner_name => state.rating = Some(winner_rating),
+                Some(name) if name == loser_name => state.rating = Some(loser_rating),
+                _ => {}
+            }
+        }
+    }
+
+    fn queue_for_match(
+        player_id: &str,
+        clients: &Clients,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        queue: &Queue,
+        ratings: &SharedRatings,
+        paths: &ServerPaths,
+    ) {
+        let rating = player_states
+            .lock()
+            .unwrap()
+            .get(player_id)
+            .and_then(|state| state.rating)
+            .unwrap_or(INITIAL_RATING);
+        queue.lock().unwrap().join(player_id, rating, Instant::now());
+        Self::send_queue_positions(clients, queue);
+        Self::pair_queued(clients, player_states, rooms, queue, ratings, paths);
+    }
+
+    /// Starts a private match for every pair of queued players close enough in rating.
+    fn pair_queued(
+        clients: &Clients,
+        player_states: &PlayerStates,
+        rooms: &Rooms,
+        queue: &Queue,
+        ratings: &SharedRatings,
+        paths: &ServerPaths,
+    ) {
+        loop {
+            let pair = queue.lock().unwrap().pop_pair(Instant::now());
+            let Some((first, second)) = pair else {
+                return;
+            };
+            Self::send_queue_positions(clients, queue);
+
+            let id = uuid::Uuid::new_v4().simple().to_string();
+            let room = format!("match-{}", &id[..8]);
+            let seed = rand::random();
+            let mut match_room = Room::new(None);
+            match_room.private = true;
+            match_room.seed = Some(seed);
+            rooms.lock().unwrap().insert(room.clone(), match_room);
+            info!(%room, %first, %second, "Paired players");
+
+            for player_id in [&first, &second] {
+                Self::move_player(player_id, &room, clients, player_states, rooms, ratings, paths);
+            }
+            let start = GameMessage::MatchStart { seed };
+            Self::send_to_room(clients, player_states, &room, None, &start);
+        }
+    }
+
+    fn leave_queue(player_id: &str, clients: &Clients, queue: &Queue) {
+        if queue.lock().unwrap().leave(player_id) {
+            Self::send_queue_positions(clients, queue);
+        }
+    }
+
+    /// Tells everyone waiting where they are in the queue.
+    fn send_queue_positions(clients: &Clients, queue: &Queue) {
+        let queue = queue.lock().unwrap();
+        let clients_guard = clients.lock().unwrap();
+        for (player_id, position) in queue.positions() {
+            let msg = GameMessage::QueueStatus { position };
+            if let (Some(client), Ok(json)) =
+                (clients_guard.get(player_id), serde_json::to_string(&msg))
+            {
+                let _ = client.send(Message::Text(json));
+            }
+        }
+    }
+
+    /// Records a pause if the player has one left, and schedules the forced resume.
+    fn start_pause(player_id: &str, clients: &Clients, player_states: &PlayerStates) -> bool {
+        let pause_number = {
+            let mut states = player_states.lock().unwrap();
+            let Some(state) = states.get_mut(player_id) else {
+                return false;
+            };
+            if state.paused || state.pauses_used >= MATCH_PAUSES {
+                return false;
+            }
+            state.paused = true;
+            state.pauses_used += 1;
+            state.pause_serial += 1;
+            state.pause_serial
+        };
+
+        let player_id = player_id.to_string();
+        let clients = clients.clone();
+        let player_states = player_states.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(MATCH_PAUSE_LIMIT).await;
+            {
+                let mut states = player_states.lock().unwrap();
+                match states.get_mut(&player_id) {
+                    Some(state) if state.paused && state.pause_serial == pause_number => {
+                        state.paused = false;
+                    }
+                    _ => return,
+                }
+            }
+
+            // Everyone in the room hears about it, the paused player included
+            let room = Self::room_of(&player_states, &player_id);
+            let resumed = GameMessage::Resumed { player_id };
+            Self::send_to_room(&clients, &player_states, &room, None, &resumed);
+        });
+        true
+    }
+
+    /// Every occupied room with its players, lobby first and the rest by name.
+    pub fn room_summaries(&self) -> Vec<RoomSummary> {
+        let mut by_room: HashMap<String, Vec<PlayerState>> = HashMap::new();
+        for state in self.player_states.lock().unwrap().values() {
+            by_room.entry(state.room.clone()).or_default().push(state.clone());
+        }
+
+        let rooms = self.rooms.lock().unwrap();
+        let mut summaries: Vec<RoomSummary> = by_room
+            .into_iter()
+            .map(|(name, mut players)| {
+                players.sort_by(|a, b| a.player_id.cmp(&b.player_id));
+                RoomSummary {
+                    locked: rooms.get(&name).is_some_and(Room::is_locked),
+                    name,
+                    players,
+                }
+            })
+            .collect();
+        summaries.sort_by_key(|summary| (summary.name != DEFAULT_ROOM, summary.name.clone()));
+        summaries
+    }
+
+    /// Closes a player's socket. Their connection cleans up and tells the room they left.
+    pub fn kick(&self, player_id: &str) -> bool {
+        let clients = self.clients.lock().unwrap();
+        let Some(client) = clients.get(player_id) else {
+            return false;
+        };
+        let frame = CloseFrame {
+            code: CloseCode::Policy,
+            reason: "Kicked by the server".into(),
+        };
+        client.send(Message::Close(Some(frame))).is_ok()
+    }
+
+    /// Sends an announcement to every connected player.
+    pub fn say(&self, text: &str) {
+        let msg = GameMessage::ServerMessage {
+            text: text.to_string(),
+        };
+        let Ok(json) = serde_json::to_string(&msg) else {
+            return;
+        };
+        for client in self.clients.lock().unwrap().values() {
+            let _ = client.send(Message::Text(json.clone()));
+        }
+    }
+
+    /// A new game is a new match, with its own pause allowance.
+    fn reset_pauses(player_id: &str, player_states: &PlayerStates) {
+        if let Some(state) = player_states.lock().unwrap().get_mut(player_id) {
+            state.paused = false;
+            state.pauses_used = 0;
+        }
+    }
+
+    fn end_pause(player_id: &str, player_states: &PlayerStates) -> bool {
+        let mut states = player_states.lock().unwrap();
+        match states.get_mut(player_id) {
+            Some(state) if state.paused => {
+                state.paused = false;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Board updates over UDP, alongside a WebSocket connection. Boards only go this way once
+/// the server says it hears our datagrams, so a blocked UDP port leaves everything on the
+/// WebSocket.
+struct SideChannel {
+    socket: Arc<UdpSocket>,
+    key: u64,
+    seq: AtomicU64,
+    ready: Arc<AtomicBool>,
+    tasks: [tokio::task::JoinHandle<()>; 2],
+}
+
+impl SideChannel {
+    /// Opens a UDP socket to the server's port and starts probing it. Received boards are
+    /// passed on to `messages` while anything still reads them.
+    async fn open(
+        server_addr: &str,
+        messages: mpsc::WeakUnboundedSender<GameMessage>,
+        ready: Arc<AtomicBool>,
+        counters: (Arc<NetCounters>, Instant),
+    ) -> Option<Self> {
+        let host = server_addr.strip_prefix("ws://")?.split('/').next()?;
+        let server = tokio::net::lookup_host(host).await.ok()?.next()?;
+        let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = Arc::new(UdpSocket::bind(local).await.ok()?);
+        socket.connect(server).await.ok()?;
+        // 0 is the server's own key
+        let key = rand::random::<u64>().max(1);
+
+        let incoming = socket.clone();
+        let reader = tokio::spawn(async move {
+            let (counters, opened) = counters;
+            let mut sequences = SequenceFilter::default();
+            let mut buf = [0; MAX_DATAGRAM_BYTES];
+            loop {
+                let Ok(len) = incoming.recv(&mut buf).await else {
+                    continue;
+                };
+                let Some(datagram) = decode_datagram(&buf[..len]) else {
+                    counters.invalid.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                };
+                let Some(msg) = datagram.msg.filter(|msg| msg.player_id().is_some()) else {
+                    continue;
+                };
+                if !sequences.accept(msg.player_id().unwrap_or_default(), datagram.seq) {
+                    counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                let Some(messages) = messages.upgrade() else {
+                    return;
+                };
+                counters.receive(opened);
+                let _ = messages.send(msg);
+            }
+        });
+
+        let probe = Datagram {
+            key,
+            seq: 0,
+            msg: None,
+        };
+        let probe = encode_datagram(&probe)?;
+        let (outgoing, heard) = (socket.clone(), ready.clone());
+        let prober = tokio::spawn(async move {
+            for _ in 0..UDP_PROBES {
+                if heard.load(Ordering::Relaxed) {
+                    return;
+                }
+                let _ = outgoing.send(&probe).await;
+                tokio::time::sleep(UDP_PROBE_INTERVAL).await;
+            }
+        });
+
+        Some(Self {
+            socket,
+            key,
+            seq: AtomicU64::new(first_sequence()),
+            ready,
+            tasks: [reader, prober],
+        })
+    }
+
+    /// Sends `msg` as a datagram, or hands it back if it has to go over the WebSocket.
+    fn send(&self, msg: GameMessage) -> Option<GameMessage> {
+        if !self.ready.load(Ordering::Relaxed) {
+            return Some(msg);
+        }
+        let datagram = Datagram {
+            key: self.key,
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            msg: Some(msg),
+        };
+        match encode_datagram(&datagram) {
+            Some(bytes) if self.socket.try_send(&bytes).is_ok() => None,
+            _ => datagram.msg,
+        }
+    }
+}
+
+impl Drop for SideChannel {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
 }
 
 pub struct MultiplayerClient {
-    sender: mpsc::UnboundedSender<GameMessage>,
+    outbox: Arc<Outbox>,
     receiver: mpsc::UnboundedReceiver<GameMessage>,
+    reader: tokio::task::JoinHandle<()>,
+    writer: tokio::task::JoinHandle<()>,
+    side_channel: Option<SideChannel>,
+    // Cleared once the read side has ended
+    connected: bool,
+    counters: Arc<NetCounters>,
+    opened: Instant,
+    sent_rate: RateMeter,
+    received_rate: RateMeter,
 }
 
 impl MultiplayerClient {
     pub async fn connect(server_addr: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let (ws_stream, _) = tokio_tungstenite::connect_async(server_addr).await?;
+        Self::open(server_addr, None, true).await
+    }
+
+    /// Connects, presenting the resume token from an earlier connection that dropped so the
+    /// server can hand back the same player.
+    pub async fn connect_resuming(
+        server_addr: &str,
+        resume_token: Option<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::open(server_addr, resume_token, true).await
+    }
+
+    async fn open(
+        server_addr: &str,
+        resume_token: Option<String>,
+        side_channel: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = Some(websocket_config());
+        let (ws_stream, _) =
+            tokio_tungstenite::connect_async_with_config(server_addr, config, false).await?;
         let (mut write, mut read) = ws_stream.split();
         
-        let (tx, mut rx) = mpsc::unbounded_channel();
+        let outbox = Arc::new(Outbox::default());
         let (msg_tx, msg_rx) = mpsc::unbounded_channel();
+        let counters = Arc::new(NetCounters::default());
+        let opened = Instant::now();
+        // Older servers never send Hello, so they keep getting plain text frames
+        let server_compression = Arc::new(AtomicBool::new(false));
+        let udp_ready = Arc::new(AtomicBool::new(false));
+        // Only the WebSocket keeps the message channel open, so its end still shows
+        let udp_messages = msg_tx.downgrade();
 
         // Handle incoming messages
-        tokio::spawn(async move {
+        let compress = server_compression.clone();
+        let heard = udp_ready.clone();
+        let received = counters.clone();
+        let reader = tokio::spawn(async move {
             while let Some(msg) = read.next().await {
                 if let Ok(msg) = msg {
-                    if let Ok(game_msg) = serde_json::from_str(&msg.to_string()) {
+                    let game_msg = decode_frame(&msg);
+                    if game_msg.is_none() && (msg.is_text() || msg.is_binary()) {
+                        received.invalid.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if let Some(game_msg) = game_msg {
+                        received.receive(opened);
+                        if let GameMessage::Hello { compression, .. } = game_msg {
+                            compress.store(compression, Ordering::Relaxed);
+                        }
+                        if game_msg == GameMessage::UdpReady {
+                            heard.store(true, Ordering::Relaxed);
+                        }
                         let _ = msg_tx.send(game_msg);
                     }
                 }
             }
         });
 
-        // Handle outgoing messages
-        tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                let json = serde_json::to_string(&msg).unwrap();
-                let _ = write.send(Message::Text(json)).await;
+        // Handle outgoing messages, closing the socket once the client is dropped
+        let queued = outbox.clone();
+        let sent = counters.clone();
+        let writer = tokio::spawn(async move {
+            while let Some(msg) = queued.next().await {
+                let mut frame = Message::Text(serde_json::to_string(&msg).unwrap());
+                if server_compression.load(Ordering::Relaxed) {
+                    frame = compress_frame(frame);
+                }
+                // A failed write means the server is gone
+                if write.send(frame).await.is_err() {
+                    queued.close();
+                    return;
+                }
+                sent.sent.fetch_add(1, Ordering::Relaxed);
             }
+            let _ = write.close().await;
         });
 
+        outbox.push(GameMessage::Hello {
+            compression: true,
+            resume_token,
+        });
+        let side_channel = if side_channel {
+            let counters = (counters.clone(), opened);
+            SideChannel::open(server_addr, udp_messages, udp_ready, counters).await
+        } else {
+            None
+        };
+        if let Some(channel) = &side_channel {
+            outbox.push(GameMessage::UdpRegister { key: channel.key });
+        }
+
         Ok(Self {
-            sender: tx,
+            outbox,
             receiver: msg_rx,
+            reader,
+            writer,
+            side_channel,
+            connected: true,
+            counters,
+            opened,
+            sent_rate: RateMeter::default(),
+            received_rate: RateMeter::default(),
         })
     }
 
+    /// Sends whatever is still queued and closes the connection.
+    pub async fn close(mut self) {
+        self.outbox.close();
+        let _ = (&mut self.writer).await;
+        self.reader.abort();
+    }
+
+    /// Cuts the connection without closing it properly, as a network failure would.
+    #[cfg(test)]
+    pub(crate) fn drop_connection(self) {
+        self.reader.abort();
+        self.writer.abort();
+    }
+
+    /// Sends a message over the WebSocket, except boards, which go over UDP once it works:
+    /// a lost one is soon replaced by the next.
     pub fn send(&self, msg: GameMessage) {
-        let _ = self.sender.send(msg);
+        let msg = match &self.side_channel {
+            Some(channel) if matches!(msg, GameMessage::BoardState { .. }) => channel.send(msg),
+            _ => Some(msg),
+        };
+        match msg {
+            Some(msg) => self.outbox.push(msg),
+            None => {
+                self.counters.sent.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The connection's message counts so far, with rates over the last `RATE_WINDOW`.
+    pub fn net_stats(&mut self) -> NetStats {
+        let now = Instant::now();
+        let counters = &self.counters;
+        let sent = counters.sent.load(Ordering::Relaxed);
+        let received = counters.received.load(Ordering::Relaxed);
+        let last_received = counters.last_received.load(Ordering::Relaxed);
+        NetStats {
+            sent,
+            received,
+            sent_per_sec: self.sent_rate.sample(sent, now),
+            received_per_sec: self.received_rate.sample(received, now),
+            dropped: self.outbox.dropped() + counters.dropped.load(Ordering::Relaxed),
+            invalid: counters.invalid.load(Ordering::Relaxed),
+            since_received: (last_received > 0).then(|| {
+                let at = self.opened + Duration::from_millis(last_received - 1);
+                now.saturating_duration_since(at)
+            }),
+        }
+    }
+
+    /// Whether board updates are going over UDP.
+    pub fn has_side_channel(&self) -> bool {
+        self.side_channel
+            .as_ref()
+            .is_some_and(|channel| channel.ready.load(Ordering::Relaxed))
     }
 
     pub fn try_receive(&mut self) -> Option<GameMessage> {
-        self.receiver.try_recv().ok()
+        match self.receiver.try_recv() {
+            Ok(msg) => Some(msg),
+            Err(mpsc::error::TryRecvError::Empty) => None,
+            Err(mpsc::error::TryRecvError::Disconnected) => {
+                self.connected = false;
+                None
+            }
+        }
+    }
+
+    /// False once either direction of the connection has shut down.
+    pub fn is_connected(&self) -> bool {
+        self.connected && !self.outbox.is_closed()
+    }
+
+    /// A client whose connection has already gone, as if the server had died.
+    #[cfg(test)]
+    pub(crate) fn disconnected() -> Self {
+        let outbox = Arc::new(Outbox::default());
+        outbox.close();
+        let (_, receiver) = mpsc::unbounded_channel();
+        Self {
+            outbox,
+            receiver,
+            reader: tokio::spawn(async {}),
+            writer: tokio::spawn(async {}),
+            side_channel: None,
+            connected: true,
+            counters: Arc::default(),
+            opened: Instant::now(),
+            sent_rate: RateMeter::default(),
+            received_rate: RateMeter::default(),
+        }
     }
-} 
+}
+
+impl Drop for MultiplayerClient {
+    // Lets the writer send what's left and close the socket
+    fn drop(&mut self) {
+        self.outbox.close();
+    }
+}
+
+/// Sends `requests` over a query connection, which doesn't join the lobby, and returns the
+/// first reply that `answer` picks out.
+pub async fn query<T>(
+    server_addr: &str,
+    requests: Vec<GameMessage>,
+    mut answer: impl FnMut(GameMessage) -> Option<T>,
+) -> Result<T, String> {
+    let mut client = MultiplayerClient::open(&format!("{}{}", server_addr, QUERY_PATH), None, false)
+        .await
+        .map_err(|e| e.to_string())?;
+    for request in requests {
+        client.send(request);
+    }
+
+    let deadline = tokio::time::Instant::now() + QUERY_TIMEOUT;
+    let reply = loop {
+        if let Some(msg) = client.try_receive() {
+            match answer(msg) {
+                Some(reply) => break Ok(reply),
+                None => continue,
+            }
+        }
+        if !client.is_connected() || tokio::time::Instant::now() >= deadline {
+            break Err("no answer from the server".to_string());
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    client.close().await;
+    reply
+}
+
+/// Fetches the rooms open on the server.
+pub async fn list_rooms(server_addr: &str) -> Result<Vec<RoomInfo>, String> {
+    query(server_addr, vec![GameMessage::ListRooms], |msg| match msg {
+        GameMessage::RoomList { rooms } => Some(rooms),
+        _ => None,
+    })
+    .await
+}
+
+/// Fetches the list of matches the server has recorded, newest first.
+pub async fn list_replays(server_addr: &str) -> Result<Vec<ReplayInfo>, String> {
+    query(server_addr, vec![GameMessage::ListReplays], |msg| match msg {
+        GameMessage::ReplayList { replays } => Some(replays),
+        _ => None,
+    })
+    .await
+}
+
+/// Downloads a recorded match, or None if the server has no such replay.
+pub async fn fetch_replay(server_addr: &str, id: &str) -> Result<Option<String>, String> {
+    let request = GameMessage::FetchReplay { id: id.to_string() };
+    let mut text = String::new();
+    query(server_addr, vec![request], |msg| match msg {
+        GameMessage::ReplayChunk { id: chunk_id, data, last } if chunk_id == id => {
+            text.push_str(&data);
+            last.then(|| std::mem::take(&mut text))
+        }
+        _ => None,
+    })
+    .await
+    .map(|text| (!text.is_empty()).then_some(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::{BOARD_HEIGHT, BOARD_WIDTH};
+
+    async fn start_server(port: u16) -> String {
+        let paths = ServerPaths::scratch(&format!("server-{}", port));
+        let server = MultiplayerServer::with_paths(paths);
+        let addr = format!("127.0.0.1:{}", port);
+        let listen_addr = addr.clone();
+        tokio::spawn(async move { server.start(&listen_addr).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        format!("ws://{}", addr)
+    }
+
+    /// Everything the client receives within `wait`.
+    async fn receive_for(client: &mut MultiplayerClient, wait: Duration) -> Vec<GameMessage> {
+        tokio::time::sleep(wait).await;
+        std::iter::from_fn(|| client.try_receive()).collect()
+    }
+
+    /// Receives until a message matches, giving up after a few seconds: password hashing
+    /// is slow in debug builds.
+    async fn receive_until(
+        client: &mut MultiplayerClient,
+        wanted: impl Fn(&GameMessage) -> bool,
+    ) -> Vec<GameMessage> {
+        let mut received = Vec::new();
+        for _ in 0..500 {
+            while let Some(msg) = client.try_receive() {
+                let found = wanted(&msg);
+                received.push(msg);
+                if found {
+                    return received;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        received
+    }
+
+    fn own_id(messages: &[GameMessage]) -> String {
+        messages
+            .iter()
+            .find_map(|msg| match msg {
+                GameMessage::Join { player_id, .. } => Some(player_id.clone()),
+                _ => None,
+            })
+            .expect("no Join from the server")
+    }
+
+    fn half_full_board_state() -> GameMessage {
+        let cells = (0..BOARD_HEIGHT)
+            .map(|row| {
+                (0..BOARD_WIDTH)
+                    .map(|col| (row >= BOARD_HEIGHT / 2 && col != row % BOARD_WIDTH).then_some(2))
+                    .collect()
+            })
+            .collect();
+        GameMessage::BoardState {
+            player_id: "player".to_string(),
+            cells,
+            current: None,
+            next: Some(BlockKind::T),
+        }
+    }
+
+    #[test]
+    fn compressed_board_state_round_trips_smaller() {
+        let msg = half_full_board_state();
+        let json = serde_json::to_string(&msg).unwrap();
+        let frame = compress_frame(Message::Text(json.clone()));
+        match &frame {
+            Message::Binary(bytes) => assert!(bytes.len() * 3 < json.len()),
+            _ => panic!("board state wasn't compressed"),
+        }
+        assert!(decode_frame(&frame) == Some(msg));
+    }
+
+    #[test]
+    fn small_messages_stay_plain_text() {
+        let msg = GameMessage::Paused {
+            player_id: "player".to_string(),
+        };
+        let frame = compress_frame(Message::Text(serde_json::to_string(&msg).unwrap()));
+        assert!(frame.is_text());
+        assert!(decode_frame(&frame) == Some(msg));
+    }
+
+    #[test]
+    fn rejects_frames_that_inflate_past_the_limit() {
+        let padding = " ".repeat(MAX_FRAME_BYTES);
+        let json = format!("{}{}", padding, r#"{"Paused":{"player_id":"p"}}"#);
+        let frame = compress_frame(Message::Text(json));
+        match &frame {
+            Message::Binary(bytes) => assert!(bytes.len() < 1024),
+            _ => panic!("padding wasn't compressed"),
+        }
+        assert!(decode_frame(&frame).is_none());
+    }
+
+    #[tokio::test]
+    async fn locked_rooms_are_listed_and_check_passwords() {
+        let addr = start_server(18139).await;
+        let mut owner = MultiplayerClient::connect(&addr).await.unwrap();
+        let mut guest = MultiplayerClient::connect(&addr).await.unwrap();
+        owner.send(GameMessage::CreateRoom {
+            name: "den".to_string(),
+            password: Some("secret".to_string()),
+        });
+        receive_until(&mut owner, |msg| matches!(msg, GameMessage::RoomJoined { .. })).await;
+
+        let rooms = list_rooms(&addr).await.unwrap();
+        let den = RoomInfo {
+            name: "den".to_string(),
+            players: 1,
+            limit: Some(MAX_ROOM_PLAYERS),
+            locked: true,
+            in_match: false,
+            rules: RoomRules::default(),
+        };
+        assert!(rooms.contains(&den));
+        // The query connection itself doesn't count as a player
+        assert!(rooms.iter().any(|room| room.name == DEFAULT_ROOM && room.players == 1));
+
+        guest.send(GameMessage::JoinRoom {
+            room: "den".to_string(),
+            password: Some("guess".to_string()),
+        });
+        let received =
+            receive_until(&mut guest, |msg| matches!(msg, GameMessage::Error { .. })).await;
+        assert!(received.contains(&GameMessage::Error {
+            code: ErrorCode::WrongPassword,
+        }));
+
+        guest.send(GameMessage::JoinRoom {
+            room: "den".to_string(),
+            password: Some("secret".to_string()),
+        });
+        let received =
+            receive_until(&mut guest, |msg| matches!(msg, GameMessage::RoomJoined { .. })).await;
+        assert!(received.contains(&GameMessage::RoomJoined {
+            room: "den".to_string(),
+            locked: true,
+        }));
+    }
+
+    #[tokio::test]
+    async fn full_rooms_turn_players_away() {
+        let addr = start_server(18156).await;
+        let mut owner = MultiplayerClient::connect(&addr).await.unwrap();
+        owner.send(GameMessage::CreateRoom {
+            name: "den".to_string(),
+            password: None,
+        });
+        receive_until(&mut owner, |msg| matches!(msg, GameMessage::RoomJoined { .. })).await;
+        let mut guests = Vec::new();
+        for _ in 1..MAX_ROOM_PLAYERS {
+            let mut guest = MultiplayerClient::connect(&addr).await.unwrap();
+            guest.send(GameMessage::JoinRoom {
+                room: "den".to_string(),
+                password: None,
+            });
+            receive_until(&mut guest, |msg| matches!(msg, GameMessage::RoomJoined { .. })).await;
+            guests.push(guest);
+        }
+        let rooms = list_rooms(&addr).await.unwrap();
+        let den = rooms.iter().find(|room| room.name == "den").unwrap();
+        assert_eq!((den.players, den.limit), (MAX_ROOM_PLAYERS, Some(MAX_ROOM_PLAYERS)));
+        assert!(den.in_match, "a match starts once two players are in");
+
+        let mut late = MultiplayerClient::connect(&addr).await.unwrap();
+        late.send(GameMessage::JoinRoom {
+            room: "den".to_string(),
+            password: None,
+        });
+        let received =
+            receive_until(&mut late, |msg| matches!(msg, GameMessage::Error { .. })).await;
+        assert!(received.contains(&GameMessage::Error {
+            code: ErrorCode::RoomFull,
+        }));
+    }
+
+    #[tokio::test]
+    async fn relays_game_updates_but_not_server_messages() {
+        let addr = start_server(18142).await;
+        let mut sender = MultiplayerClient::connect(&addr).await.unwrap();
+        let mut receiver = MultiplayerClient::connect(&addr).await.unwrap();
+        let sender_id = own_id(&receive_for(&mut sender, Duration::from_millis(100)).await);
+        receive_for(&mut receiver, Duration::from_millis(100)).await;
+
+        sender.send(GameMessage::MatchStart { seed: 7 });
+        sender.send(GameMessage::ServerMessage {
+            text: "forged".to_string(),
+        });
+        sender.send(GameMessage::QueueStatus { position: 1 });
+        sender.send(GameMessage::GameState {
+            player_id: sender_id.clone(),
+            score: 1234,
+            lines: 5,
+            name: None,
+            afk: false,
+        });
+
+        let received = receive_for(&mut receiver, Duration::from_millis(200)).await;
+        assert!(received.iter().any(|msg| matches!(
+            msg,
+            GameMessage::GameState { player_id, score: 1234, .. } if *player_id == sender_id
+        )));
+        assert!(!received.iter().any(|msg| matches!(
+            msg,
+            GameMessage::MatchStart { .. }
+                | GameMessage::ServerMessage { .. }
+                | GameMessage::QueueStatus { .. }
+        )));
+    }
+
+    #[test]
+    fn board_state_carries_the_falling_piece_when_known() {
+        let msg = GameMessage::BoardState {
+            player_id: "player".to_string(),
+            cells: Vec::new(),
+            current: Some(Block::new(BlockKind::T)),
+            next: Some(BlockKind::S),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""current":["T",4,-2,0],"next":"S""#), "{}", json);
+        let decoded: GameMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+
+        // Senders from before the pieces were shared leave both fields out
+        let old = r#"{"BoardState":{"player_id":"player","cells":[]}}"#;
+        match serde_json::from_str(old).unwrap() {
+            GameMessage::BoardState { current, next, .. } => {
+                assert!(current.is_none() && next.is_none());
+            }
+            _ => panic!("expected a board state"),
+        }
+        let expected = GameMessage::BoardState {
+            player_id: "player".to_string(),
+            cells: Vec::new(),
+            current: None,
+            next: None,
+        };
+        assert_eq!(serde_json::to_string(&expected).unwrap(), old);
+    }
+
+    fn player_state(id: &str, score: i32, name: Option<&str>) -> PlayerState {
+        PlayerState {
+            player_id: id.to_string(),
+            score,
+            lines: 0,
+            name: name.map(str::to_string),
+            paused: false,
+            pauses_used: 0,
+            room: String::new(),
+            rating: None,
+            accent: None,
+            unverified: false,
+            pause_serial: 0,
+            checks: Plausibility::default(),
+        }
+    }
+
+    #[test]
+    fn reconcile_drops_stale_players_and_adds_missing_ones() {
+        let mut players = HashMap::new();
+        players.insert("gone".to_string(), PlayerInfo::new());
+        players.insert("kept".to_string(), PlayerInfo::new());
+        let snapshot = [
+            player_state("kept", 120, Some("Kim")),
+            player_state("new", 40, None),
+            player_state("me", 999, None),
+        ];
+
+        let removed = reconcile_players(&mut players, &snapshot, Some("me"));
+        assert_eq!(removed, ["gone"]);
+        let mut ids: Vec<&str> = players.keys().map(String::as_str).collect();
+        ids.sort();
+        assert_eq!(ids, ["kept", "new"], "we never appear among the other players");
+        assert_eq!(players["kept"].score, 120);
+        assert_eq!(players["kept"].name.as_deref(), Some("Kim"));
+        assert_eq!(players["new"].score, 40);
+
+        // An empty lobby leaves nobody behind
+        let removed = reconcile_players(&mut players, &[], Some("me"));
+        assert_eq!(removed.len(), 2);
+        assert!(players.is_empty());
+    }
+
+    #[test]
+    fn throttled_sends_changes_at_most_once_per_interval() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut throttled = Throttled::new(Duration::from_millis(250));
+
+        assert_eq!(throttled.poll_at(10, at(0)), Some(10));
+        assert_eq!(throttled.poll_at(10, at(300)), None, "unchanged values aren't resent");
+        assert_eq!(throttled.poll_at(20, at(310)), Some(20));
+        // Changes within the interval wait, and the latest one goes out once it's over
+        assert_eq!(throttled.poll_at(30, at(400)), None);
+        assert_eq!(throttled.poll_at(40, at(500)), None);
+        assert_eq!(throttled.poll_at(40, at(560)), Some(40));
+
+        throttled.reset();
+        assert_eq!(throttled.poll_at(40, at(900)), Some(40));
+
+        throttled.set_repeat(Some(Duration::from_secs(1)));
+        assert_eq!(throttled.poll_at(40, at(1800)), None);
+        assert_eq!(throttled.poll_at(40, at(1900)), Some(40));
+    }
+
+    #[test]
+    fn remote_pieces_slide_between_updates_and_freeze_when_they_stop() {
+        let piece = |kind, x, y| Block {
+            kind,
+            x,
+            y,
+            rotation: 0,
+        };
+        let from = piece(BlockKind::T, 3, 5);
+        assert_eq!(interpolate_offset(&from, &piece(BlockKind::T, 5, 6), 0.0), (-2.0, -1.0));
+        assert_eq!(interpolate_offset(&from, &piece(BlockKind::T, 5, 6), 0.5), (-1.0, -0.5));
+        assert_eq!(interpolate_offset(&from, &piece(BlockKind::T, 5, 6), 2.0), (0.0, 0.0));
+        // A new piece at the top, even of the same kind, doesn't fly back up
+        assert_eq!(interpolate_offset(&from, &piece(BlockKind::T, 3, 0), 0.0), (0.0, 0.0));
+        assert_eq!(interpolate_offset(&from, &piece(BlockKind::S, 4, 5), 0.0), (0.0, 0.0));
+
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut remote = RemoteBoard::default();
+        remote.set_piece_at(Some(from), at(0));
+        assert_eq!(remote.piece_at(at(50)), Some(RemotePiece::still(from)));
+        remote.set_piece_at(Some(piece(BlockKind::T, 4, 5)), at(200));
+        let halfway = remote.piece_at(at(300)).unwrap();
+        assert_eq!(halfway.offset, (-0.5, 0.0));
+        assert!(!remote.is_stale_at(at(2000)));
+        assert!(remote.is_stale_at(at(2200)));
+        assert_eq!(remote.piece_at(at(2200)).unwrap().offset, (0.0, 0.0));
+    }
+
+    #[test]
+    fn send_queue_keeps_only_the_latest_snapshots_behind_the_rest() {
+        let state = |score| GameMessage::GameState {
+            player_id: "me".to_string(),
+            score,
+            lines: 0,
+            name: None,
+            afk: false,
+        };
+        let over = GameMessage::GameOver { player_id: "me".to_string() };
+        let attack = GameMessage::LineCleared {
+            player_id: "me".to_string(),
+            count: 4,
+            sent: Some(4),
+            kind: AttackKind::Normal,
+        };
+        let mut queue = SendQueue::default();
+        queue.push(state(100));
+        queue.push(half_full_board_state());
+        queue.push(attack.clone());
+        queue.push(state(200));
+        queue.push(over.clone());
+        queue.push(state(300));
+        assert_eq!(queue.len(), 4);
+
+        assert!(queue.pop() == Some(attack));
+        assert!(queue.pop() == Some(over));
+        // The game state keeps its place ahead of the board, with the newest score
+        assert!(queue.pop() == Some(state(300)));
+        assert!(matches!(queue.pop(), Some(GameMessage::BoardState { .. })));
+        assert!(queue.pop().is_none());
+
+        queue.push(state(400));
+        queue.close();
+        queue.push(state(500));
+        assert!(queue.pop() == Some(state(400)));
+        assert!(queue.is_empty() && queue.is_closed());
+        assert_eq!(queue.dropped(), 2);
+    }
+
+    #[test]
+    fn rates_are_averaged_over_each_window() {
+        let start = Instant::now();
+        let mut meter = RateMeter::default();
+        assert_eq!(meter.sample(10, start), 0.0);
+        // Not a full window yet: still the last rate
+        assert_eq!(meter.sample(15, start + RATE_WINDOW / 2), 0.0);
+        assert_eq!(meter.sample(40, start + RATE_WINDOW * 2), 15.0);
+        assert_eq!(meter.sample(40, start + RATE_WINDOW * 3), 0.0);
+    }
+
+    #[tokio::test]
+    async fn drops_messages_that_speak_for_another_player() {
+        let addr = start_server(18143).await;
+        let mut forger = MultiplayerClient::connect(&addr).await.unwrap();
+        let mut victim = MultiplayerClient::connect(&addr).await.unwrap();
+        receive_for(&mut forger, Duration::from_millis(100)).await;
+        let victim_id = own_id(&receive_for(&mut victim, Duration::from_millis(100)).await);
+
+        forger.send(GameMessage::Resumed {
+            player_id: victim_id.clone(),
+        });
+        let received = receive_for(&mut victim, Duration::from_millis(200)).await;
+        assert!(!received.iter().any(|msg| matches!(msg, GameMessage::Resumed { .. })));
+    }
+
+    #[tokio::test]
+    async fn pause_allowance_resets_with_a_new_game() {
+        let addr = start_server(18129).await;
+        let mut player = MultiplayerClient::connect(&addr).await.unwrap();
+        let mut opponent = MultiplayerClient::connect(&addr).await.unwrap();
+        let player_id = own_id(&receive_for(&mut player, Duration::from_millis(100)).await);
+        receive_for(&mut opponent, Duration::from_millis(100)).await;
+
+        let pause_round = |player: &MultiplayerClient| {
+            for _ in 0..=MATCH_PAUSES {
+                player.send(GameMessage::Paused {
+                    player_id: player_id.clone(),
+                });
+                player.send(GameMessage::Resumed {
+                    player_id: player_id.clone(),
+                });
+            }
+        };
+        let count_pauses = |messages: Vec<GameMessage>| {
+            messages
+                .iter()
+                .filter(|msg| matches!(msg, GameMessage::Paused { .. }))
+                .count() as u32
+        };
+
+        pause_round(&player);
+        let received = receive_for(&mut opponent, Duration::from_millis(200)).await;
+        assert_eq!(count_pauses(received), MATCH_PAUSES);
+
+        player.send(GameMessage::NewGame {
+            player_id: player_id.clone(),
+        });
+        pause_round(&player);
+        let received = receive_for(&mut opponent, Duration::from_millis(200)).await;
+        assert_eq!(count_pauses(received), MATCH_PAUSES);
+    }
+
+    #[test]
+    fn room_rules_round_trip_into_the_same_game_config() {
+        let rules = RoomRules {
+            garbage: GarbageConfig {
+                messiness: 0.25,
+                hole_repeat: true,
+            },
+            lock_delay_ms: 750,
+            hold: false,
+            perfect_clear_attack: 6,
+            garbage_delay_pieces: 2,
+        };
+        let json = serde_json::to_string(&GameMessage::RoomRules { rules }).unwrap();
+        let Some(GameMessage::RoomRules { rules: received }) = serde_json::from_str(&json).ok()
+        else {
+            panic!("rules didn't survive the trip: {}", json);
+        };
+
+        let mut sent = GameConfig::default();
+        rules.apply(&mut sent);
+        let mut applied = GameConfig::default();
+        received.apply(&mut applied);
+        assert_eq!(RoomRules::from_config(&applied), RoomRules::from_config(&sent));
+        assert_eq!(RoomRules::from_config(&applied), rules);
+        assert_eq!(applied.lock_delay, Duration::from_millis(750));
+        assert_eq!(applied.hold_slots, 0);
+        assert_eq!(applied.perfect_clear_attack, 6);
+        assert_eq!(applied.garbage_delay_pieces, 2);
+
+        // Fields an older client leaves out keep their defaults
+        let partial: RoomRules = serde_json::from_str(r#"{"hold":false}"#).unwrap();
+        assert_eq!(partial.lock_delay_ms, LOCK_DELAY.as_millis() as u64);
+        assert_eq!(partial.garbage_delay_pieces, GARBAGE_DELAY_PIECES);
+        assert!(partial.is_valid());
+        assert!(!RoomRules { lock_delay_ms: 0, ..rules }.is_valid());
+    }
+
+    #[tokio::test]
+    async fn only_the_room_owner_changes_its_rules() {
+        let addr = start_server(18155).await;
+        let mut owner = MultiplayerClient::connect(&addr).await.unwrap();
+        let mut guest = MultiplayerClient::connect(&addr).await.unwrap();
+        owner.send(GameMessage::CreateRoom {
+            name: "den".to_string(),
+            password: None,
+        });
+        receive_until(&mut owner, |msg| matches!(msg, GameMessage::RoomJoined { .. })).await;
+        let rules = RoomRules {
+            lock_delay_ms: 300,
+            hold: false,
+            ..RoomRules::default()
+        };
+        owner.send(GameMessage::RoomRules { rules });
+        // The owner first hears the room's default rules, on joining it
+        let changed = GameMessage::RoomRules { rules };
+        let received = receive_until(&mut owner, |msg| *msg == changed).await;
+        assert!(received.contains(&GameMessage::RoomRules {
+            rules: RoomRules::default(),
+        }));
+
+        // A player who joins is told the rules straight away
+        guest.send(GameMessage::JoinRoom {
+            room: "den".to_string(),
+            password: None,
+        });
+        let received =
+            receive_until(&mut guest, |msg| matches!(msg, GameMessage::RoomRules { .. })).await;
+        assert!(received.contains(&GameMessage::RoomRules { rules }));
+
+        guest.send(GameMessage::RoomRules {
+            rules: RoomRules::default(),
+        });
+        let received =
+            receive_until(&mut guest, |msg| matches!(msg, GameMessage::Error { .. })).await;
+        assert!(received.contains(&GameMessage::Error {
+            code: ErrorCode::NotRoomOwner,
+        }));
+        let received = receive_for(&mut owner, Duration::from_millis(100)).await;
+        assert!(!received.iter().any(|msg| matches!(msg, GameMessage::RoomRules { .. })));
+    }
+
+    #[tokio::test]
+    async fn forfeiting_players_place_and_can_leave_the_room() {
+        let addr = start_server(18154).await;
+        let mut quitter = MultiplayerClient::connect(&addr).await.unwrap();
+        let mut winner = MultiplayerClient::connect(&addr).await.unwrap();
+        let quitter_id = own_id(&receive_for(&mut quitter, Duration::from_millis(100)).await);
+        receive_for(&mut winner, Duration::from_millis(100)).await;
+        quitter.send(GameMessage::CreateRoom {
+            name: "den".to_string(),
+            password: None,
+        });
+        receive_until(&mut quitter, |msg| matches!(msg, GameMessage::RoomJoined { .. })).await;
+        winner.send(GameMessage::JoinRoom {
+            room: "den".to_string(),
+            password: None,
+        });
+        receive_until(&mut winner, |msg| matches!(msg, GameMessage::RoomJoined { .. })).await;
+
+        // Both joining started the match; giving it up places the quitter straight away
+        quitter.send(GameMessage::Forfeit {
+            player_id: quitter_id.clone(),
+        });
+        quitter.send(GameMessage::LeaveRoom {
+            player_id: quitter_id.clone(),
+        });
+        let received =
+            receive_until(&mut winner, |msg| matches!(msg, GameMessage::PlayerLeft { .. })).await;
+        let result = received
+            .iter()
+            .find_map(|msg| match msg {
+                GameMessage::MatchResult { result } => Some(result),
+                _ => None,
+            })
+            .expect("no result for the match");
+        assert_eq!(result.placements[1].player_id, quitter_id);
+        assert_eq!(result.placements[1].place, Some(2));
+
+        // The quitter is back in the lobby, still connected
+        let received =
+            receive_until(&mut quitter, |msg| matches!(msg, GameMessage::RoomJoined { .. })).await;
+        assert!(received.contains(&GameMessage::RoomJoined {
+            room: DEFAULT_ROOM.to_string(),
+            locked: false,
+        }));
+        assert!(quitter.is_connected());
+    }
+
+    #[tokio::test]
+    async fn dropped_players_resume_their_place_until_they_quit() {
+        let addr = start_server(18150).await;
+        let mut opponent = MultiplayerClient::connect(&addr).await.unwrap();
+        receive_for(&mut opponent, Duration::from_millis(100)).await;
+        let mut player = MultiplayerClient::connect(&addr).await.unwrap();
+        let token_of = |messages: &[GameMessage]| {
+            messages.iter().find_map(|msg| match msg {
+                GameMessage::Join { resume_token, .. } => resume_token.clone(),
+                _ => None,
+            })
+        };
+        let received = receive_for(&mut player, Duration::from_millis(100)).await;
+        let player_id = own_id(&received);
+        let token = token_of(&received).expect("no resume token");
+        // Nobody else gets to see it
+        let received = receive_for(&mut opponent, Duration::from_millis(100)).await;
+        assert!(received.contains(&GameMessage::Join {
+            player_id: player_id.clone(),
+            resume_token: None,
+        }));
+        assert_ne!(token_of(&received), Some(token.clone()));
+        player.send(GameMessage::GameState {
+            player_id: player_id.clone(),
+            score: 1200,
+            lines: 8,
+            name: Some("alice".to_string()),
+            afk: false,
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        player.drop_connection();
+        let mut player = MultiplayerClient::connect_resuming(&addr, Some(token)).await.unwrap();
+        let received = receive_until(&mut player, |msg| {
+            matches!(msg, GameMessage::GameState { player_id: id, .. } if *id == player_id)
+        })
+        .await;
+        assert_eq!(own_id(&received), player_id);
+        assert!(received.contains(&GameMessage::RoomJoined {
+            room: DEFAULT_ROOM.to_string(),
+            locked: false,
+        }));
+        assert!(received.contains(&GameMessage::GameState {
+            player_id: player_id.clone(),
+            score: 1200,
+            lines: 8,
+            name: Some("alice".to_string()),
+            afk: false,
+        }));
+        let token = token_of(&received).expect("no new resume token");
+        let received = receive_for(&mut opponent, Duration::from_millis(100)).await;
+        assert!(!received.iter().any(|msg| matches!(
+            msg,
+            GameMessage::Join { .. } | GameMessage::PlayerLeft { .. }
+        )));
+
+        // Quitting gives the place up for good
+        player.close().await;
+        let received = receive_until(&mut opponent, |msg| {
+            matches!(msg, GameMessage::PlayerLeft { .. })
+        })
+        .await;
+        assert!(received.contains(&GameMessage::PlayerLeft {
+            player_id: player_id.clone(),
+        }));
+        let mut player = MultiplayerClient::connect_resuming(&addr, Some(token)).await.unwrap();
+        let received = receive_for(&mut player, Duration::from_millis(100)).await;
+        assert_ne!(own_id(&received), player_id);
+    }
+
+    #[tokio::test]
+    async fn boards_go_over_udp_once_the_server_hears_it() {
+        let addr = start_server(18151).await;
+        let mut sender = MultiplayerClient::connect(&addr).await.unwrap();
+        let mut watcher = MultiplayerClient::connect(&addr).await.unwrap();
+        let ready = |msg: &GameMessage| *msg == GameMessage::UdpReady;
+        let sender_id = own_id(&receive_until(&mut sender, ready).await);
+        receive_until(&mut watcher, ready).await;
+        assert!(sender.has_side_channel() && watcher.has_side_channel());
+
+        let GameMessage::BoardState { cells, .. } = half_full_board_state() else {
+            unreachable!();
+        };
+        let board = GameMessage::BoardState {
+            player_id: sender_id,
+            cells,
+            current: None,
+            next: Some(BlockKind::T),
+        };
+        sender.send(board.clone());
+        let received = receive_until(&mut watcher, |msg| *msg == board).await;
+        assert!(received.contains(&board));
+    }
+
+    #[tokio::test]
+    async fn boards_stay_on_the_websocket_when_udp_is_blocked() {
+        // Something else holds the UDP port, so the server can't open its side channel
+        let _blocker = UdpSocket::bind("127.0.0.1:18152").await.unwrap();
+        let addr = start_server(18152).await;
+        let mut sender = MultiplayerClient::connect(&addr).await.unwrap();
+        let mut watcher = MultiplayerClient::connect(&addr).await.unwrap();
+        let sender_id = own_id(&receive_for(&mut sender, Duration::from_millis(100)).await);
+        receive_for(&mut watcher, Duration::from_millis(100)).await;
+
+        let board = GameMessage::BoardState {
+            player_id: sender_id,
+            cells: Vec::new(),
+            current: None,
+            next: None,
+        };
+        sender.send(board.clone());
+        let received = receive_until(&mut watcher, |msg| *msg == board).await;
+        assert!(received.contains(&board));
+        assert!(!sender.has_side_channel());
+    }
+
+    #[test]
+    fn accents_fill_gaps_then_cycle() {
+        assert_eq!(pick_accent(&[]), 0);
+        assert_eq!(pick_accent(&[0, 1, 3]), 2);
+        // Someone left, freeing their color for the next player
+        assert_eq!(pick_accent(&[1, 2]), 0);
+
+        let mut taken: Vec<u32> = (0..ACCENT_COUNT).collect();
+        assert_eq!(pick_accent(&taken), 0);
+        taken.push(0);
+        assert_eq!(pick_accent(&taken), 1);
+    }
+
+    #[tokio::test]
+    async fn missing_replays_come_back_empty() {
+        let addr = start_server(18153).await;
+        assert_eq!(fetch_replay(&addr, "000000000000000-deadbeef").await, Ok(None));
+        // Nothing outside the replays directory can be fetched
+        assert_eq!(fetch_replay(&addr, "../ratings").await, Ok(None));
+    }
+}
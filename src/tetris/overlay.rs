@@ -0,0 +1,180 @@
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::warn;
+
+use super::{Game, GameState};
+
+// How often overlay clients get a fresh snapshot
+pub const STATS_INTERVAL: Duration = Duration::from_millis(250);
+// How long shutting down waits for overlay clients to be told
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Live stats pushed to overlay clients as JSON, one object per message:
+///
+/// - `score`, `lines`, `level`: as shown in game
+/// - `pps`: pieces placed per second of play
+/// - `apm`: garbage lines sent per minute of play
+/// - `combo`: pieces in a row that cleared lines
+/// - `b2b`: tetrises and T-spin clears in a row
+/// - `pending_garbage`: garbage lines waiting to land
+/// - `state`: `"playing"`, `"paused"`, `"game_over"` or `"finished"`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatsSnapshot {
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub pps: f32,
+    pub apm: f32,
+    pub combo: u32,
+    pub b2b: u32,
+    pub pending_garbage: u32,
+    pub state: GameState,
+}
+
+impl StatsSnapshot {
+    pub fn from_game(game: &Game) -> Self {
+        Self {
+            score: game.score.points,
+            lines: game.score.lines,
+            level: game.score.level,
+            pps: game.pps(),
+            apm: game.apm(),
+            combo: game.combo,
+            b2b: game.back_to_back,
+            pending_garbage: game.pending_garbage(),
+            state: game.state,
+        }
+    }
+}
+
+/// Local WebSocket server that streams the latest `StatsSnapshot` to any number of
+/// overlay clients. Runs on the tokio runtime, so publishing never blocks the game loop.
+pub struct StatsServer {
+    latest: watch::Sender<Option<StatsSnapshot>>,
+    closing: watch::Sender<bool>,
+    listener: JoinHandle<()>,
+}
+
+impl StatsServer {
+    pub async fn start(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+        let (latest, latest_rx) = watch::channel(None);
+        let (closing, closing_rx) = watch::channel(false);
+        let listener = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let client = Self::handle_client(stream, latest_rx.clone(), closing_rx.clone());
+                tokio::spawn(client);
+            }
+        });
+        Ok(Self {
+            latest,
+            closing,
+            listener,
+        })
+    }
+
+    /// Replaces the snapshot that clients get on their next tick.
+    pub fn publish(&self, snapshot: StatsSnapshot) {
+        self.latest.send_replace(Some(snapshot));
+    }
+
+    /// Stops taking clients and closes the connections of the ones still open, waiting up
+    /// to `SHUTDOWN_TIMEOUT` for them to go.
+    pub async fn shutdown(self) {
+        self.listener.abort();
+        self.closing.send_replace(true);
+        let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, self.closing.closed()).await;
+    }
+
+    async fn handle_client(
+        stream: TcpStream,
+        latest: watch::Receiver<Option<StatsSnapshot>>,
+        mut closing: watch::Receiver<bool>,
+    ) {
+        let ws_stream = match tokio_tungstenite::accept_async(stream).await {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                warn!("Stats client handshake failed: {}", e);
+                return;
+            }
+        };
+        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+        let mut interval = tokio::time::interval(STATS_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let json = match latest.borrow().as_ref() {
+                        Some(snapshot) => serde_json::to_string(snapshot),
+                        None => continue,
+                    };
+                    let Ok(json) = json else {
+                        continue;
+                    };
+                    if ws_sender.send(Message::Text(json)).await.is_err() {
+                        return;
+                    }
+                }
+                _ = closing.changed() => {
+                    let _ = ws_sender.send(Message::Close(None)).await;
+                    return;
+                }
+                msg = ws_receiver.next() => {
+                    // Overlays only listen; anything but a closed socket is ignored
+                    if !matches!(msg, Some(Ok(_))) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_json_shape() {
+        let snapshot = StatsSnapshot::from_game(&Game::default());
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["state"], "playing");
+        for field in ["score", "lines", "level", "pps", "apm", "combo", "b2b", "pending_garbage"] {
+            assert!(json.get(field).is_some(), "missing {}", field);
+        }
+        let decoded: StatsSnapshot = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded, snapshot);
+    }
+
+    #[tokio::test]
+    async fn streams_snapshots_then_closes_on_shutdown() {
+        let server = StatsServer::start(18145).await.unwrap();
+        let mut snapshot = StatsSnapshot::from_game(&Game::default());
+        snapshot.score = 1234;
+        server.publish(snapshot.clone());
+
+        let (mut client, _) = tokio_tungstenite::connect_async("ws://127.0.0.1:18145")
+            .await
+            .unwrap();
+        let Some(Ok(Message::Text(json))) = client.next().await else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!(serde_json::from_str::<StatsSnapshot>(&json).unwrap(), snapshot);
+
+        server.shutdown().await;
+        let closed = tokio::time::timeout(Duration::from_secs(1), async {
+            while let Some(Ok(msg)) = client.next().await {
+                if msg.is_close() {
+                    return true;
+                }
+            }
+            false
+        });
+        assert!(closed.await.unwrap());
+    }
+}
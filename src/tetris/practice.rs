@@ -0,0 +1,64 @@
+use rand::rngs::StdRng;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use super::stats::ClearCounts;
+use super::{Block, BlockKind, Board, HoldQueue, Randomizer, Score};
+
+// How many placements practice mode can take back, and how many save states it keeps
+pub const UNDO_DEPTH: usize = 50;
+pub const SAVE_STATE_SLOTS: usize = 5;
+
+/// A board layout and piece sequence to drill. An empty queue falls back to random pieces.
+#[derive(Clone, Default)]
+pub struct PracticeSetup {
+    pub board: Board,
+    pub queue: Vec<BlockKind>,
+}
+
+impl PracticeSetup {
+    /// Loads the starting board from a file in the `Board::from_ascii` format.
+    pub fn load_board(&mut self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        self.board = Board::from_ascii(&text)?;
+        Ok(())
+    }
+}
+
+/// Parses a piece sequence such as `"TSZI"`, ignoring whitespace.
+pub fn parse_queue(text: &str) -> Result<Vec<BlockKind>, String> {
+    text.chars()
+        .filter(|ch| !ch.is_whitespace())
+        .map(|ch| {
+            BlockKind::from_letter(ch.to_ascii_uppercase())
+                .ok_or_else(|| format!("unknown piece letter '{}'", ch))
+        })
+        .collect()
+}
+
+/// State saved before each lock so the placement can be undone.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub board: Board,
+    pub current_block: Block,
+    pub next_block: Block,
+    pub hold: HoldQueue,
+    pub has_held: bool,
+    pub queue_index: usize,
+}
+
+/// A whole practice game saved to come back to, random piece stream included. Nothing
+/// about other players is kept.
+#[derive(Clone)]
+pub struct SaveState {
+    pub snapshot: Snapshot,
+    pub score: Score,
+    pub clears: ClearCounts,
+    pub combo: u32,
+    pub back_to_back: u32,
+    pub pieces_placed: u32,
+    pub randomizer: Box<dyn Randomizer>,
+    pub garbage_rng: StdRng,
+    pub last_garbage_hole: Option<usize>,
+}
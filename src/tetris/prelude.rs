@@ -0,0 +1,34 @@
+//! The game logic on its own, for embedding it without a window: bots, tournament runners
+//! and the like. Everything here builds without the `gui` feature, and nothing here needs
+//! raylib or a tokio runtime unless the game goes online.
+//!
+//! A headless game driven by random actions, starting over whenever it tops out:
+//!
+//! ```
+//! use rand::Rng;
+//! use tetris::prelude::*;
+//!
+//! let actions = [Action::MoveLeft, Action::MoveRight, Action::RotateCw, Action::HardDrop];
+//! let mut rng = rand::thread_rng();
+//! let mut game = Game::with_seed(1);
+//! game.start_game_with_seed(1);
+//! let mut pieces = 0;
+//! while pieces < 100 {
+//!     game.apply(actions[rng.gen_range(0..actions.len())]);
+//!     game.tick(FIXED_DT);
+//!     let events = game.drain_events();
+//!     pieces += events.iter().filter(|e| matches!(e, GameEvent::PieceLocked { .. })).count();
+//!     if game.state == GameState::GameOver {
+//!         game.start_game();
+//!     }
+//! }
+//! ```
+
+pub use super::block::{Block, BlockKind};
+pub use super::board::{Board, BOARD_HEIGHT, BOARD_WIDTH};
+pub use super::game::{
+    Action, AttackKind, Game, GameConfig, GameEvent, GameMode, GameState, LockResult, Score,
+    ScoreSources, TSpinKind, FIXED_DT,
+};
+pub use super::randomizer::{Randomizer, RandomizerKind};
+pub use super::stats::{ClearCounts, GameRecord, Heatmap};
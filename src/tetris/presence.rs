@@ -0,0 +1,176 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{Game, GameState};
+
+// Discord rate limits presence updates, and a score a few seconds stale is fine
+pub const PRESENCE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// What Discord shows under "Playing Tetris".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Presence {
+    /// First line, e.g. "Marathon — Level 5" or "In a match (3 players)".
+    pub details: String,
+    /// Second line, e.g. "12,340 points, 45 lines".
+    pub state: String,
+    /// Unix time the game started, so Discord can count up the elapsed time.
+    pub started: Option<i64>,
+}
+
+impl Presence {
+    pub fn in_menu() -> Self {
+        Self {
+            details: "In the menu".to_string(),
+            state: String::new(),
+            started: None,
+        }
+    }
+}
+
+/// Builds the presence for `game` as it is at `now`.
+pub fn presence(game: &Game, now: SystemTime) -> Presence {
+    let details = if game.multiplayer.is_some() && game.match_active {
+        let players = game.other_players.len() + 1;
+        format!("In a match ({} players)", players)
+    } else if game.multiplayer.is_some() {
+        "Waiting for a match".to_string()
+    } else {
+        format!("{} — Level {}", game.mode.name(), game.score.level)
+    };
+    let mut state = format!(
+        "{} points, {} lines",
+        thousands(game.score.points),
+        game.score.lines
+    );
+    match game.state {
+        GameState::Paused => state.push_str(" (paused)"),
+        GameState::GameOver | GameState::Finished => state.push_str(" (game over)"),
+        GameState::Playing => {}
+    }
+    // Only a running game has a clock worth showing
+    let started = (game.state == GameState::Playing)
+        .then(|| now.checked_sub(game.play_time))
+        .flatten()
+        .and_then(|start| start.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs() as i64);
+    Presence {
+        details,
+        state,
+        started,
+    }
+}
+
+// 12340 as "12,340"
+fn thousands(n: u32) -> String {
+    let digits = n.to_string();
+    let groups: Vec<&str> = digits
+        .as_bytes()
+        .rchunks(3)
+        .rev()
+        .map(|group| std::str::from_utf8(group).expect("digits are ASCII"))
+        .collect();
+    groups.join(",")
+}
+
+/// Sends the presence to a running Discord client. All IPC happens on a background
+/// thread, and failures such as Discord not running are ignored.
+#[cfg(feature = "discord")]
+pub struct DiscordPresence {
+    updates: std::sync::mpsc::Sender<Option<Presence>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(feature = "discord")]
+impl DiscordPresence {
+    /// Starts showing presence under the Discord application `app_id`.
+    pub fn start(app_id: String) -> Self {
+        let (updates, received) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || run_ipc(&app_id, received));
+        Self {
+            updates,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn update(&self, presence: Presence) {
+        let _ = self.updates.send(Some(presence));
+    }
+
+    /// Clears the presence and closes the connection.
+    pub fn shutdown(mut self) {
+        let _ = self.updates.send(None);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(feature = "discord")]
+fn run_ipc(app_id: &str, received: std::sync::mpsc::Receiver<Option<Presence>>) {
+    use discord_rich_presence::activity::{Activity, Timestamps};
+    use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
+
+    let mut client: Option<DiscordIpcClient> = None;
+    while let Ok(Some(presence)) = received.recv() {
+        // Connect on demand, so Discord can be started after the game
+        if client.is_none() {
+            let mut connecting = DiscordIpcClient::new(app_id);
+            if connecting.connect().is_ok() {
+                client = Some(connecting);
+            }
+        }
+        let Some(connected) = &mut client else {
+            continue;
+        };
+        let mut activity = Activity::new().details(&presence.details);
+        if !presence.state.is_empty() {
+            activity = activity.state(&presence.state);
+        }
+        if let Some(started) = presence.started {
+            activity = activity.timestamps(Timestamps::new().start(started));
+        }
+        if connected.set_activity(activity).is_err() {
+            // Discord went away; try again from scratch next time
+            client = None;
+        }
+    }
+    if let Some(mut connected) = client {
+        let _ = connected.clear_activity();
+        let _ = connected.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::GameMode;
+
+    #[test]
+    fn solo_presence() {
+        let mut game = Game::with_seed(1);
+        game.mode = GameMode::Sprint;
+        game.start_game_with_seed(1);
+        game.score.points = 12_340;
+        game.score.lines = 45;
+        game.score.level = 5;
+        game.play_time = Duration::from_secs(90);
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        let presence = presence(&game, now);
+        assert_eq!(presence.details, "Sprint — Level 5");
+        assert_eq!(presence.state, "12,340 points, 45 lines");
+        assert_eq!(presence.started, Some(1_000_000 - 90));
+
+        game.state = GameState::Paused;
+        let paused = super::presence(&game, now);
+        assert_eq!(paused.state, "12,340 points, 45 lines (paused)");
+        assert_eq!(paused.started, None);
+    }
+
+    #[test]
+    fn numbers_are_grouped_by_thousands() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1000), "1,000");
+        assert_eq!(thousands(1_234_567), "1,234,567");
+    }
+}
@@ -0,0 +1,266 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use super::BlockKind;
+
+// How many recent pieces the TGM-style randomizer avoids, and how often it rerolls
+pub const HISTORY_LENGTH: usize = 4;
+pub const HISTORY_REROLLS: usize = 4;
+// TGM starts the history with snakes, so the first pieces aren't S or Z
+const INITIAL_HISTORY: [BlockKind; HISTORY_LENGTH] =
+    [BlockKind::Z, BlockKind::S, BlockKind::S, BlockKind::Z];
+
+/// Where the piece sequence comes from. Each one is seeded from the game seed, so the same
+/// seed and kind always deal the same pieces.
+pub trait Randomizer: Send {
+    fn next(&mut self) -> BlockKind;
+
+    fn kind(&self) -> RandomizerKind;
+
+    /// A copy that deals the same pieces from here on, for save states.
+    fn box_clone(&self) -> Box<dyn Randomizer>;
+
+    /// What it's holding on to, for the debug overlay.
+    fn debug(&self) -> RandomizerDebug {
+        RandomizerDebug::default()
+    }
+}
+
+/// A randomizer's state, for the debug overlay. Each only fills in what it keeps.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RandomizerDebug {
+    /// Pieces left in the current bag, next out first.
+    pub bag: Option<Vec<BlockKind>>,
+    /// The recent pieces new ones are rerolled against, oldest first.
+    pub history: Option<Vec<BlockKind>>,
+}
+
+impl Clone for Box<dyn Randomizer> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// The randomizers to pick from. Serialized by name, e.g. `"seven_bag"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RandomizerKind {
+    /// Every piece equally likely every time.
+    #[default]
+    Classic,
+    /// All seven pieces in a shuffled bag, then the next bag.
+    SevenBag,
+    /// Rerolls pieces dealt in the last four, as in TGM.
+    History4,
+}
+
+impl RandomizerKind {
+    pub const ALL: [RandomizerKind; 3] = [
+        RandomizerKind::Classic,
+        RandomizerKind::SevenBag,
+        RandomizerKind::History4,
+    ];
+
+    /// The name in the config file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RandomizerKind::Classic => "classic",
+            RandomizerKind::SevenBag => "seven_bag",
+            RandomizerKind::History4 => "history4",
+        }
+    }
+
+    /// The kind `steps` places along `ALL`, wrapping around.
+    pub fn cycled(&self, steps: i32) -> Self {
+        let len = Self::ALL.len() as i32;
+        let index = Self::ALL.iter().position(|kind| kind == self).unwrap_or(0) as i32;
+        Self::ALL[(index + steps).rem_euclid(len) as usize]
+    }
+
+    pub fn randomizer(&self, seed: u64) -> Box<dyn Randomizer> {
+        let rng = StdRng::seed_from_u64(seed);
+        match self {
+            RandomizerKind::Classic => Box::new(Classic { rng }),
+            RandomizerKind::SevenBag => Box::new(SevenBag {
+                rng,
+                bag: Vec::new(),
+            }),
+            RandomizerKind::History4 => Box::new(History4 {
+                rng,
+                history: VecDeque::from(INITIAL_HISTORY),
+            }),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Classic {
+    rng: StdRng,
+}
+
+impl Randomizer for Classic {
+    fn next(&mut self) -> BlockKind {
+        BlockKind::random_with(&mut self.rng)
+    }
+
+    fn kind(&self) -> RandomizerKind {
+        RandomizerKind::Classic
+    }
+
+    fn box_clone(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct SevenBag {
+    rng: StdRng,
+    // What's left of the current bag, dealt from the back
+    bag: Vec<BlockKind>,
+}
+
+impl Randomizer for SevenBag {
+    fn next(&mut self) -> BlockKind {
+        if self.bag.is_empty() {
+            self.bag = BlockKind::ALL.to_vec();
+            self.bag.shuffle(&mut self.rng);
+        }
+        self.bag.pop().unwrap()
+    }
+
+    fn kind(&self) -> RandomizerKind {
+        RandomizerKind::SevenBag
+    }
+
+    fn box_clone(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+
+    fn debug(&self) -> RandomizerDebug {
+        RandomizerDebug {
+            bag: Some(self.bag.iter().rev().copied().collect()),
+            history: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct History4 {
+    rng: StdRng,
+    // The last pieces dealt, oldest first
+    history: VecDeque<BlockKind>,
+}
+
+impl Randomizer for History4 {
+    fn next(&mut self) -> BlockKind {
+        let mut kind = BlockKind::random_with(&mut self.rng);
+        for _ in 0..HISTORY_REROLLS {
+            if !self.history.contains(&kind) {
+                break;
+            }
+            kind = BlockKind::random_with(&mut self.rng);
+        }
+        self.history.pop_front();
+        self.history.push_back(kind);
+        kind
+    }
+
+    fn kind(&self) -> RandomizerKind {
+        RandomizerKind::History4
+    }
+
+    fn box_clone(&self) -> Box<dyn Randomizer> {
+        Box::new(self.clone())
+    }
+
+    fn debug(&self) -> RandomizerDebug {
+        RandomizerDebug {
+            bag: None,
+            history: Some(self.history.iter().copied().collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRAWS: usize = 7 * 1000;
+
+    fn deal(kind: RandomizerKind, seed: u64) -> Vec<BlockKind> {
+        let mut randomizer = kind.randomizer(seed);
+        (0..DRAWS).map(|_| randomizer.next()).collect()
+    }
+
+    // Share of pieces that were already among the `HISTORY_LENGTH` before them
+    fn repeat_rate(pieces: &[BlockKind]) -> f64 {
+        let repeats = pieces
+            .windows(HISTORY_LENGTH + 1)
+            .filter(|window| window[..HISTORY_LENGTH].contains(&window[HISTORY_LENGTH]))
+            .count();
+        repeats as f64 / (pieces.len() - HISTORY_LENGTH) as f64
+    }
+
+    #[test]
+    fn classic_deals_what_it_always_has() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let expected: Vec<BlockKind> =
+            (0..DRAWS).map(|_| BlockKind::random_with(&mut rng)).collect();
+        assert_eq!(deal(RandomizerKind::Classic, 7), expected);
+        // Unconstrained, so recent pieces come up again often
+        assert!(repeat_rate(&expected) > 0.3);
+    }
+
+    #[test]
+    fn every_bag_holds_all_seven_pieces() {
+        for seed in 0..5 {
+            for bag in deal(RandomizerKind::SevenBag, seed).chunks(7) {
+                let mut bag = bag.to_vec();
+                bag.sort_by_key(|kind| kind.color());
+                assert_eq!(bag, BlockKind::ALL, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn history_mostly_avoids_recent_pieces() {
+        for seed in 0..5 {
+            let pieces = deal(RandomizerKind::History4, seed);
+            assert!(!matches!(pieces[0], BlockKind::S | BlockKind::Z));
+            // Only all five rolls landing in the history repeat a piece: at most (4/7)^5
+            let rate = repeat_rate(&pieces);
+            assert!(rate < 0.1, "seed {} repeats {}", seed, rate);
+        }
+    }
+
+    #[test]
+    fn clones_deal_the_same_pieces() {
+        for kind in RandomizerKind::ALL {
+            let mut randomizer = kind.randomizer(3);
+            randomizer.next();
+            let mut copy = randomizer.clone();
+            let ahead: Vec<BlockKind> = (0..20).map(|_| randomizer.next()).collect();
+            let copied: Vec<BlockKind> = (0..20).map(|_| copy.next()).collect();
+            assert_eq!(ahead, copied, "{:?}", kind);
+            assert_eq!(copy.kind(), kind);
+        }
+    }
+
+    #[test]
+    fn debug_shows_what_comes_next() {
+        let mut bag = RandomizerKind::SevenBag.randomizer(5);
+        bag.next();
+        let left = bag.debug().bag.unwrap();
+        let dealt: Vec<BlockKind> = (0..6).map(|_| bag.next()).collect();
+        assert_eq!(dealt, left);
+
+        let mut history = RandomizerKind::History4.randomizer(5);
+        let kind = history.next();
+        let recent = history.debug().history.unwrap();
+        assert_eq!((recent.len(), recent.last()), (HISTORY_LENGTH, Some(&kind)));
+        assert_eq!(RandomizerKind::Classic.randomizer(5).debug(), RandomizerDebug::default());
+    }
+}
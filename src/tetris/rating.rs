@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const RATINGS_PATH: &str = "ratings.json";
+pub const INITIAL_RATING: i32 = 1000;
+const ELO_K: f64 = 32.0;
+
+/// Expected score of a player rated `rating` against one rated `opponent`, from 0 to 1.
+pub fn expected_score(rating: i32, opponent: i32) -> f64 {
+    1.0 / (1.0 + 10f64.powf(f64::from(opponent - rating) / 400.0))
+}
+
+/// Points the winner takes from the loser. A 1v1 can't be drawn, so this is all Elo needs.
+pub fn elo_change(winner: i32, loser: i32) -> i32 {
+    (ELO_K * (1.0 - expected_score(winner, loser))).round() as i32
+}
+
+/// Ratings by player name, kept in a JSON file.
+#[derive(Clone, Default)]
+pub struct Ratings {
+    ratings: HashMap<String, i32>,
+    path: Option<PathBuf>,
+}
+
+impl Ratings {
+    /// Loads ratings from `path`, starting empty if the file doesn't exist yet. Updates are
+    /// written back to the same file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let ratings = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            ratings,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> i32 {
+        self.ratings.get(name).copied().unwrap_or(INITIAL_RATING)
+    }
+
+    /// Moves points from the loser to the winner. Returns the change.
+    pub fn record_win(&mut self, winner: &str, loser: &str) -> i32 {
+        let change = elo_change(self.get(winner), self.get(loser));
+        *self.ratings.entry(winner.to_string()).or_insert(INITIAL_RATING) += change;
+        *self.ratings.entry(loser.to_string()).or_insert(INITIAL_RATING) -= change;
+        change
+    }
+
+    /// Writes the ratings back to the file they were loaded from.
+    pub fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => fs::write(path, serde_json::to_string_pretty(&self.ratings)?),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo_changes() {
+        assert_eq!(expected_score(1000, 1000), 0.5);
+        assert!((expected_score(1400, 1000) - 10.0 / 11.0).abs() < 1e-9);
+        assert_eq!(elo_change(1000, 1000), 16);
+        // Beating a much stronger player pays far more than beating a weaker one
+        assert_eq!(elo_change(1000, 1400), 29);
+        assert_eq!(elo_change(1400, 1000), 3);
+    }
+
+    #[test]
+    fn wins_move_points_between_players() {
+        let mut ratings = Ratings::default();
+        assert_eq!(ratings.get("ann"), INITIAL_RATING);
+        assert_eq!(ratings.record_win("ann", "bob"), 16);
+        assert_eq!(ratings.get("ann"), 1016);
+        assert_eq!(ratings.get("bob"), 984);
+        assert_eq!(ratings.record_win("bob", "ann"), 17);
+        assert_eq!(ratings.get("ann") + ratings.get("bob"), 2 * INITIAL_RATING);
+    }
+
+    #[test]
+    fn ratings_survive_a_reload() {
+        let path = std::env::temp_dir().join(format!("tetris-ratings-{}.json", std::process::id()));
+        let mut ratings = Ratings::load(&path).unwrap();
+        ratings.record_win("ann", "bob");
+        ratings.save().unwrap();
+        let reloaded = Ratings::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.get("ann"), 1016);
+        assert_eq!(reloaded.get("bob"), 984);
+    }
+}
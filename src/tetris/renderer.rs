@@ -1,24 +1,90 @@
 use raylib::prelude::*;
-use super::{Block, BlockKind, Board, Cell, BOARD_HEIGHT, BOARD_WIDTH};
-use std::collections::HashMap;
+use super::{
+    browser::{BrowserRow, Prompt, RoomListing, ServerBrowser},
+    multiplayer::{
+        ConnectionStatus, ErrorCode, NetStats, PlayerStatus, RemoteBoard, RemotePiece, RoomInfo,
+        RoomRules,
+    },
+    daily::{DailyEntry, HighScores},
+    editor::{BoardEditor, Brush, EditorField},
+    config::{key_name, Config},
+    i18n::{tr, tr_args},
+    menu::{Menu, MenuItem, MODES},
+    replay::Playback,
+    results::{head_to_head, HeadToHead, MatchResult, SessionTally},
+    settings::{Rebind, SettingsItem, SettingsMenu},
+    splits::{BestSplits, Splits, SPLIT_LINES},
+    stats::{Heatmap, SessionStats, CHI_SQUARED_SIGNIFICANT, LONG_DROUGHT},
+    verify::RecordRejection,
+    Action, AttackKind, Block, BlockKind, Board, Cell, CellContent, Collapse, Game, GameMode,
+    GameSpeed, GameState, HoldQueue, MissionRun, MissionStatus, PlacementHint, RandomizerDebug,
+    RandomizerKind,
+    Suggestion, Tutorial, BOARD_HEIGHT, BOARD_WIDTH, MAX_KO_BADGE_BONUS, SPRINT_LINES,
+};
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 
-pub const WINDOW_WIDTH: i32 = 750;
+pub const WINDOW_WIDTH: i32 = 850;
 pub const WINDOW_HEIGHT: i32 = 800;
 pub const FPS: u32 = 60;
 
 // Constants for rendering
 pub const CELL_SIZE: i32 = 30;
-pub const BOARD_OFFSET_X: i32 = 250;
 pub const BOARD_OFFSET_Y: i32 = 50;
 pub const PREVIEW_CELL_SIZE: i32 = 25;
+pub const PREVIEW_BOX_WIDTH: i32 = 4;
+pub const PREVIEW_BOX_HEIGHT: i32 = 2;
+// Between the boxes of the double hold, side by side
+pub const HOLD_BOX_GAP: i32 = 10;
+// Lines of text in the stats block and their size. The level progress bar sits in the
+// gap under the level line
+const STATS_LINES: i32 = 6;
+const STATS_SIZE: i32 = 18;
+const LEVEL_BAR_WIDTH: i32 = 150;
+const LEVEL_BAR_HEIGHT: i32 = 3;
+// The connection indicator in the top right corner
+const CONNECTION_DOT_RADIUS: i32 = 6;
+// Space between attacks on the garbage meter
+const METER_GAP: i32 = 2;
 pub const BLOCK_ROUNDNESS: f32 = 0.3;
 pub const GHOST_ALPHA: u8 = 50;
 pub const CELL_PADDING: i32 = 3;
 
-// Scoreboard constants
-pub const SCOREBOARD_X: i32 = BOARD_OFFSET_X + (BOARD_WIDTH as i32 * CELL_SIZE) + 30;
-pub const SCOREBOARD_Y: i32 = BOARD_OFFSET_Y + 150;
-pub const SCOREBOARD_SPACING: i32 = 25;
+const BOARD_PIXEL_WIDTH: i32 = BOARD_WIDTH as i32 * CELL_SIZE;
+const BOARD_PIXEL_HEIGHT: i32 = BOARD_HEIGHT as i32 * CELL_SIZE;
+
+// Text and the panels beside the board grow with the UI scale; the board's cells don't
+pub const MIN_UI_SCALE: f32 = 1.0;
+pub const MAX_UI_SCALE: f32 = 2.0;
+pub const UI_SCALE_STEP: f32 = 0.25;
+// Panel widths either side of the board at a scale of 1
+const LEFT_PANEL_WIDTH: i32 = 250;
+const RIGHT_PANEL_WIDTH: i32 = 300;
+const PANEL_GAP: i32 = 30;
+// Next, hold and score beside player 2's board in local versus, wide enough for two holds
+const VERSUS_PANEL_WIDTH: i32 = 250;
+
+// Opponent mini-board constants
+pub const MINI_CELL_SIZE: i32 = 16;
+pub const REMOTE_PIECE_ALPHA: u8 = 120;
+pub const REMOTE_GHOST_ALPHA: u8 = 45;
+pub const MIN_MINI_CELL_SIZE: i32 = 4;
+pub const MINI_BOARD_GAP: i32 = 8;
+pub const MINI_BOARD_LABEL_HEIGHT: i32 = 14;
+
+pub const CHAIN_POPUP_DURATION: Duration = Duration::from_millis(1200);
+pub const PERFECT_CLEAR_FLASH_DURATION: Duration = Duration::from_millis(1500);
+pub const CANCELLED_ATTACK_DURATION: Duration = Duration::from_millis(800);
+pub const PAUSE_DENIED_DURATION: Duration = Duration::from_millis(1500);
+pub const SERVER_MESSAGE_DURATION: Duration = Duration::from_secs(5);
+pub const TOAST_DURATION: Duration = Duration::from_secs(3);
+pub const FINESSE_FLASH_DURATION: Duration = Duration::from_millis(400);
+pub const WINDOW_TITLE_INTERVAL: Duration = Duration::from_secs(1);
+// A spectated board this old gets a note saying when it last changed
+pub const SPECTATE_STALE_AFTER: Duration = Duration::from_secs(1);
 
 // Background color
 pub const BACKGROUND_COLOR: Color = Color::new(46, 52, 64, 255);
@@ -33,8 +99,404 @@ pub const COLORS: [Color; 7] = [
     Color::new(180, 142, 173, 255), // T
     Color::new(208, 135, 112, 255), // Z
 ];
+pub const GARBAGE_COLOR: Color = Color::new(106, 114, 130, 255);
+
+/// The colors everything is drawn in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub background: Color,
+    pub grid: Color,
+    pub garbage: Color,
+    /// Piece colors, indexed by `BlockKind::color`.
+    pub pieces: [Color; 7],
+}
+
+pub const DEFAULT_THEME: Theme = Theme {
+    background: BACKGROUND_COLOR,
+    grid: GRID_COLOR,
+    garbage: GARBAGE_COLOR,
+    pieces: COLORS,
+};
+
+impl Default for Theme {
+    fn default() -> Self {
+        DEFAULT_THEME
+    }
+}
+
+impl Theme {
+    pub fn piece(&self, kind: BlockKind) -> Color {
+        self.pieces[kind.color() as usize]
+    }
+}
+
+// Swapped by set_theme, so a new theme shows from the next frame on
+static THEME: RwLock<Theme> = RwLock::new(DEFAULT_THEME);
+
+/// The theme currently drawn with.
+pub fn theme() -> Theme {
+    *THEME.read().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn set_theme(theme: Theme) {
+    *THEME.write().unwrap_or_else(|e| e.into_inner()) = theme;
+}
+
+pub fn content_color(content: CellContent) -> Color {
+    match content {
+        CellContent::Piece { kind, .. } => theme().piece(kind),
+        CellContent::Garbage => theme().garbage,
+    }
+}
+
+/// Where the board and the panels around it go at one UI scale. The window widens to fit
+/// the scaled panels, while its height and the board stay the same.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub scale: f32,
+    pub window_width: i32,
+    /// Left edge of the board.
+    pub board_x: i32,
+    /// Left edge of the panel right of the board: next piece, scoreboard and stats.
+    pub panel_x: i32,
+    /// Top of the next piece preview, a line below its label.
+    pub next_y: i32,
+    /// The hold label, left of the board, with the preview a line below.
+    pub hold_y: i32,
+    /// Top of the mode's panel under the hold box.
+    pub hud_y: i32,
+    pub scoreboard_y: i32,
+    /// Top of the stats block, which ends level with the bottom of the board.
+    pub stats_y: i32,
+    /// Online opponents' boards, under the hold box and shrinking as more join.
+    pub opponent_panel: Rect,
+    /// Left edge of player 2's board in local versus, right of the usual window.
+    pub versus_board_x: i32,
+    /// The window widened to fit player 2's board and panel.
+    pub versus_window_width: i32,
+}
+
+impl Layout {
+    pub fn new(scale: f32) -> Self {
+        let scale = scale.clamp(MIN_UI_SCALE, MAX_UI_SCALE);
+        let mut layout = Self {
+            scale,
+            window_width: 0,
+            board_x: 0,
+            panel_x: 0,
+            next_y: 0,
+            hold_y: BOARD_OFFSET_Y + 100,
+            hud_y: 0,
+            scoreboard_y: 0,
+            stats_y: 0,
+            opponent_panel: Rect { x: 20, y: 0, width: 0, height: 0 },
+            versus_board_x: 0,
+            versus_window_width: 0,
+        };
+        let preview_height = PREVIEW_BOX_HEIGHT * PREVIEW_CELL_SIZE;
+        layout.board_x = layout.scaled(LEFT_PANEL_WIDTH);
+        layout.panel_x = layout.board_x + BOARD_PIXEL_WIDTH + layout.scaled(PANEL_GAP);
+        layout.window_width = layout.board_x + BOARD_PIXEL_WIDTH + layout.scaled(RIGHT_PANEL_WIDTH);
+        layout.next_y = BOARD_OFFSET_Y + layout.line_height(20);
+        layout.hud_y = layout.hold_y + layout.line_height(20) + preview_height + 45;
+        layout.scoreboard_y = layout.next_y + preview_height + 75;
+        layout.stats_y =
+            BOARD_OFFSET_Y + BOARD_PIXEL_HEIGHT + 25 - layout.line_height(STATS_SIZE) * STATS_LINES;
+        // The page label goes under the panel
+        let opponents_bottom = WINDOW_HEIGHT - 15 - layout.line_height(20);
+        layout.opponent_panel = Rect {
+            x: 20,
+            y: layout.hud_y,
+            width: layout.board_x - 40,
+            height: opponents_bottom - layout.hud_y,
+        };
+        layout.versus_board_x = layout.window_width + layout.scaled(PANEL_GAP);
+        layout.versus_window_width =
+            layout.versus_board_x + BOARD_PIXEL_WIDTH + layout.scaled(VERSUS_PANEL_WIDTH);
+        layout
+    }
+
+    /// A text size or panel distance at this scale.
+    pub fn scaled(&self, value: i32) -> i32 {
+        (value as f32 * self.scale).round() as i32
+    }
+
+    /// Distance from one line of text at `size` to the next. The default font is exactly
+    /// as tall as its size, so this is the scaled size plus a quarter for the gap.
+    pub fn line_height(&self, size: i32) -> i32 {
+        let height = self.scaled(size);
+        height + height / 4
+    }
+
+    /// Mini-board label height at this scale.
+    pub fn label_height(&self) -> i32 {
+        self.scaled(MINI_BOARD_LABEL_HEIGHT)
+    }
+}
+
+// Set from the config, so a new scale shows from the next frame on
+static UI_SCALE: RwLock<f32> = RwLock::new(MIN_UI_SCALE);
+
+pub fn ui_scale() -> f32 {
+    *UI_SCALE.read().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn set_ui_scale(scale: f32) {
+    *UI_SCALE.write().unwrap_or_else(|e| e.into_inner()) = scale.clamp(MIN_UI_SCALE, MAX_UI_SCALE);
+}
+
+// Whether the window is widened for player 2 in local versus
+static VERSUS_LAYOUT: AtomicBool = AtomicBool::new(false);
+
+/// Widens the window for player 2's board, or back to one board.
+pub fn set_versus_layout(versus: bool) {
+    VERSUS_LAYOUT.store(versus, Ordering::Relaxed);
+}
+
+/// The layout at the current UI scale, as wide as local versus needs while it's on.
+pub fn layout() -> Layout {
+    let mut layout = Layout::new(ui_scale());
+    if VERSUS_LAYOUT.load(Ordering::Relaxed) {
+        layout.window_width = layout.versus_window_width;
+    }
+    layout
+}
+
+/// A draw handle that can also measure text: the window's, or a texture's begun from it.
+/// Drawing that doesn't line text up only needs `RaylibDraw`.
+pub trait Canvas: RaylibDraw {
+    fn text_width(&self, text: &str, size: i32) -> i32;
+}
+
+impl Canvas for RaylibDrawHandle<'_> {
+    fn text_width(&self, text: &str, size: i32) -> i32 {
+        self.measure_text(text, size)
+    }
+}
+
+impl<T: Deref<Target = RaylibHandle>> Canvas for RaylibTextureMode<'_, T> {
+    fn text_width(&self, text: &str, size: i32) -> i32 {
+        self.measure_text(text, size)
+    }
+}
+
+/// Draws UI text with `size` scaled to the UI scale. Text goes through here and the
+/// helpers below, so the scale is applied in one place.
+pub fn draw_ui_text(
+    d: &mut impl RaylibDraw,
+    text: &str,
+    x: i32,
+    y: i32,
+    size: i32,
+    color: Color,
+) {
+    d.draw_text(text, x, y, layout().scaled(size), color);
+}
+
+/// Width of `text` drawn by `draw_ui_text` at `size`.
+pub fn ui_text_width(d: &impl Canvas, text: &str, size: i32) -> i32 {
+    d.text_width(text, layout().scaled(size))
+}
+
+fn draw_text_right(
+    d: &mut impl Canvas,
+    text: &str,
+    right: i32,
+    y: i32,
+    size: i32,
+    color: Color,
+) {
+    let width = ui_text_width(d, text, size);
+    draw_ui_text(d, text, right - width, y, size, color);
+}
+
+fn draw_text_centered(
+    d: &mut impl Canvas,
+    text: &str,
+    center_x: i32,
+    y: i32,
+    size: i32,
+    color: Color,
+) {
+    let width = ui_text_width(d, text, size);
+    draw_ui_text(d, text, center_x - width / 2, y, size, color);
+}
+
+/// Draws each line of `(text, size, color)` under the last from `y`, left aligned at `x`
+/// or centered on it. Returns the y just below the last line.
+fn draw_text_lines(
+    d: &mut impl Canvas,
+    x: i32,
+    y: i32,
+    centered: bool,
+    lines: &[(&str, i32, Color)],
+) -> i32 {
+    let layout = layout();
+    let mut y = y;
+    for &(text, size, color) in lines {
+        if centered {
+            draw_text_centered(d, text, x, y, size, color);
+        } else {
+            draw_ui_text(d, text, x, y, size, color);
+        }
+        y += layout.line_height(size);
+    }
+    y
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum BlockStyle {
+    #[default]
+    Rounded,
+    /// Flat square cells, for the retro look of classic mode.
+    Square,
+    /// Cells of the same piece joined up, so each piece reads as one shape.
+    Connected,
+}
+
+impl BlockStyle {
+    pub const ALL: [BlockStyle; 3] =
+        [BlockStyle::Rounded, BlockStyle::Square, BlockStyle::Connected];
+}
+
+/// Which neighbors of a cell belong to the same piece, for `BlockStyle::Connected`. A
+/// corner only counts when both sides next to it do too, so the gap there gets filled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Joins {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub up_left: bool,
+    pub up_right: bool,
+    pub down_left: bool,
+    pub down_right: bool,
+}
+
+impl Joins {
+    /// The joins of the cell at (`x`, `y`), where `same` tells whether a cell is part of
+    /// the same piece.
+    pub fn of(x: i32, y: i32, same: impl Fn(i32, i32) -> bool) -> Self {
+        let (left, right) = (same(x - 1, y), same(x + 1, y));
+        let (up, down) = (same(x, y - 1), same(x, y + 1));
+        Self {
+            left,
+            right,
+            up,
+            down,
+            up_left: up && left && same(x - 1, y - 1),
+            up_right: up && right && same(x + 1, y - 1),
+            down_left: down && left && same(x - 1, y + 1),
+            down_right: down && right && same(x + 1, y + 1),
+        }
+    }
+}
+
+pub fn draw_cell(
+    d: &mut impl RaylibDraw,
+    x: i32,
+    y: i32,
+    size: i32,
+    color: Color,
+    style: BlockStyle,
+) {
+    match style {
+        BlockStyle::Rounded => draw_rounded_block(d, x, y, size, color),
+        BlockStyle::Square => d.draw_rectangle(x + 1, y + 1, size - 2, size - 2, color),
+        BlockStyle::Connected => {
+            draw_connected_cell(d, x, y, size, color, Joins::default());
+        }
+    }
+}
+
+/// Draws a cell of a connected piece: a flat square that reaches across the padding to the
+/// cells it `joins`, edged in a lighter color wherever the piece ends. Returns how many
+/// shapes that took.
+pub fn draw_connected_cell(
+    d: &mut impl RaylibDraw,
+    x: i32,
+    y: i32,
+    size: i32,
+    color: Color,
+    joins: Joins,
+) -> u32 {
+    let pad = CELL_PADDING;
+    let inner = size - pad * 2;
+    let far = size - pad;
+    let mut shapes = vec![(pad, pad, inner, inner)];
+    let sides = [
+        (joins.left, (0, pad, pad, inner)),
+        (joins.right, (far, pad, pad, inner)),
+        (joins.up, (pad, 0, inner, pad)),
+        (joins.down, (pad, far, inner, pad)),
+        (joins.up_left, (0, 0, pad, pad)),
+        (joins.up_right, (far, 0, pad, pad)),
+        (joins.down_left, (0, far, pad, pad)),
+        (joins.down_right, (far, far, pad, pad)),
+    ];
+    shapes.extend(sides.into_iter().filter(|(joined, _)| *joined).map(|(_, shape)| shape));
+
+    // The edge runs on into the padding on sides that join, so it meets the next cell's
+    let from_x = if joins.left { 0 } else { pad };
+    let to_x = if joins.right { size } else { far };
+    let from_y = if joins.up { 0 } else { pad };
+    let to_y = if joins.down { size } else { far };
+    let mut edges = Vec::new();
+    if !joins.up {
+        edges.push((from_x, pad, to_x - from_x, 2));
+    }
+    if !joins.down {
+        edges.push((from_x, far - 2, to_x - from_x, 2));
+    }
+    if !joins.left {
+        edges.push((pad, from_y, 2, to_y - from_y));
+    }
+    if !joins.right {
+        edges.push((far - 2, from_y, 2, to_y - from_y));
+    }
+
+    let highlight = lighten(color);
+    for (dx, dy, width, height) in &shapes {
+        d.draw_rectangle(x + dx, y + dy, *width, *height, color);
+    }
+    for (dx, dy, width, height) in &edges {
+        d.draw_rectangle(x + dx, y + dy, *width, *height, highlight);
+    }
+    (shapes.len() + edges.len()) as u32
+}
+
+// The lighter shade cell edges are drawn in
+fn lighten(color: Color) -> Color {
+    Color::new(
+        (color.r as u16 + 40).min(255) as u8,
+        (color.g as u16 + 40).min(255) as u8,
+        (color.b as u16 + 40).min(255) as u8,
+        color.a,
+    )
+}
+
+// Draws cells of one piece, given as board columns and rows `size` apart from the offset
+fn draw_piece_cells(
+    d: &mut impl RaylibDraw,
+    cells: &[(i32, i32)],
+    offset_x: i32,
+    offset_y: i32,
+    size: i32,
+    color: Color,
+    style: BlockStyle,
+) {
+    for &(x, y) in cells {
+        let (screen_x, screen_y) = (offset_x + x * size, offset_y + y * size);
+        if style == BlockStyle::Connected {
+            let joins = Joins::of(x, y, |x, y| cells.contains(&(x, y)));
+            draw_connected_cell(d, screen_x, screen_y, size, color, joins);
+        } else {
+            draw_cell(d, screen_x, screen_y, size, color, style);
+        }
+    }
+}
 
-pub fn draw_rounded_block(d: &mut RaylibDrawHandle, x: i32, y: i32, size: i32, color: Color) {
+pub fn draw_rounded_block(d: &mut impl RaylibDraw, x: i32, y: i32, size: i32, color: Color) {
     d.draw_rectangle_rounded(
         Rectangle::new(
             (x + CELL_PADDING) as f32,
@@ -47,12 +509,7 @@ pub fn draw_rounded_block(d: &mut RaylibDrawHandle, x: i32, y: i32, size: i32, c
         color,
     );
 
-    let highlight_color = Color::new(
-        (color.r as u16 + 40).min(255) as u8,
-        (color.g as u16 + 40).min(255) as u8,
-        (color.b as u16 + 40).min(255) as u8,
-        color.a,
-    );
+    let highlight_color = lighten(color);
     d.draw_rectangle_rounded_lines(
         Rectangle::new(
             (x + CELL_PADDING) as f32,
@@ -67,31 +524,48 @@ pub fn draw_rounded_block(d: &mut RaylibDrawHandle, x: i32, y: i32, size: i32, c
     );
 }
 
-pub fn draw_block(d: &mut RaylibDrawHandle, block: &Block, offset_x: i32, offset_y: i32) {
-    let color = COLORS[block.kind.color() as usize];
-    for (x, y) in block.blocks() {
-        let screen_x = offset_x + x * CELL_SIZE;
-        let screen_y = offset_y + y * CELL_SIZE;
-        draw_rounded_block(d, screen_x, screen_y, CELL_SIZE, color);
-    }
+pub fn draw_block(
+    d: &mut impl RaylibDraw,
+    block: &Block,
+    offset_x: i32,
+    offset_y: i32,
+    style: BlockStyle,
+) {
+    let color = theme().piece(block.kind);
+    draw_piece_cells(d, &block.blocks(), offset_x, offset_y, CELL_SIZE, color, style);
 }
 
-pub fn draw_ghost_block(
-    d: &mut RaylibDrawHandle,
-    block: &Block,
-    board: &Board,
+/// Draws the falling piece between where the last two simulation steps left it.
+pub fn draw_falling_block(
+    d: &mut impl RaylibDraw,
+    game: &Game,
     offset_x: i32,
     offset_y: i32,
+    style: BlockStyle,
 ) {
-    let mut ghost = *block;
-    while board.is_valid_position(&ghost) {
-        ghost.y += 1;
-    }
-    ghost.y -= 1;
+    let (dx, dy) = game.falling_offset();
+    let x = offset_x + (dx * CELL_SIZE as f32).round() as i32;
+    let y = offset_y + (dy * CELL_SIZE as f32).round() as i32;
+    draw_block(d, &game.current_block, x, y, style);
+}
 
-    let color = COLORS[block.kind.color() as usize];
+/// Draws `ghost`, the landing spot from `Game::ghost_block`, faded. It's rounded unless
+/// pieces are drawn connected.
+pub fn draw_ghost_block(
+    d: &mut impl Canvas,
+    ghost: &Block,
+    offset_x: i32,
+    offset_y: i32,
+    style: BlockStyle,
+) {
+    let color = theme().piece(ghost.kind);
     let ghost_color = Color::new(color.r, color.g, color.b, GHOST_ALPHA);
 
+    if style == BlockStyle::Connected {
+        let cells = ghost.blocks();
+        draw_piece_cells(d, &cells, offset_x, offset_y, CELL_SIZE, ghost_color, style);
+        return;
+    }
     for (x, y) in ghost.blocks() {
         let screen_x = offset_x + x * CELL_SIZE;
         let screen_y = offset_y + y * CELL_SIZE;
@@ -99,146 +573,2469 @@ pub fn draw_ghost_block(
     }
 }
 
+/// Assist mode's suggestion: an outline of where the piece is best landed, kept apart from
+/// the ghost by drawing only the edges of each cell.
+pub fn draw_suggestion(d: &mut impl Canvas, suggestion: &Suggestion, offset_x: i32, offset_y: i32) {
+    for (x, y) in suggestion.landed.blocks() {
+        if y < 0 {
+            continue;
+        }
+        d.draw_rectangle_lines_ex(
+            Rectangle::new(
+                (offset_x + x * CELL_SIZE + 2) as f32,
+                (offset_y + y * CELL_SIZE + 2) as f32,
+                (CELL_SIZE - 4) as f32,
+                (CELL_SIZE - 4) as f32,
+            ),
+            2.0,
+            Color::WHITE,
+        );
+    }
+}
+
+/// Labels a game played in assist mode under the hold box, with a nudge to hold when the
+/// suggestion is better made with the other piece.
+pub fn draw_assist_label(d: &mut impl Canvas, hold_better: bool, x: i32, y: i32) {
+    draw_ui_text(d, tr("assist_on"), x, y, 15, Color::SKYBLUE);
+    if hold_better {
+        let y = y + layout().line_height(15);
+        draw_ui_text(d, tr("assist_hold"), x, y, 15, Color::GOLD);
+    }
+}
+
+/// Draws the hold boxes for a game with `slots` of them, left to right from the oldest.
+/// Once they're full, the box the next hold empties is outlined.
+pub fn draw_hold_queue(
+    d: &mut impl Canvas,
+    hold: &HoldQueue,
+    slots: usize,
+    offset_x: i32,
+    offset_y: i32,
+    style: BlockStyle,
+) {
+    let box_width = PREVIEW_BOX_WIDTH * PREVIEW_CELL_SIZE;
+    for (i, block) in hold.blocks().enumerate() {
+        let x = offset_x + i as i32 * (box_width + HOLD_BOX_GAP);
+        draw_preview_block(d, block.kind, x, offset_y, style);
+    }
+    if slots > 1 && hold.next_out(slots).is_some() {
+        d.draw_rectangle_lines_ex(
+            Rectangle::new(
+                (offset_x - 4) as f32,
+                (offset_y - 4) as f32,
+                (box_width + 8) as f32,
+                (PREVIEW_BOX_HEIGHT * PREVIEW_CELL_SIZE + 8) as f32,
+            ),
+            2.0,
+            Color::GOLD,
+        );
+    }
+}
+
 pub fn draw_preview_block(
-    d: &mut RaylibDrawHandle,
+    d: &mut impl Canvas,
     block_kind: BlockKind,
     offset_x: i32,
     offset_y: i32,
+    style: BlockStyle,
 ) {
-    let color = COLORS[block_kind.color() as usize];
-    let base_positions = match block_kind {
-        BlockKind::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
-        BlockKind::J => [(-1, -1), (-1, 0), (0, 0), (1, 0)],
-        BlockKind::L => [(1, -1), (-1, 0), (0, 0), (1, 0)],
-        BlockKind::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
-        BlockKind::S => [(-1, 0), (0, 0), (0, -1), (1, -1)],
-        BlockKind::T => [(0, -1), (-1, 0), (0, 0), (1, 0)],
-        BlockKind::Z => [(-1, -1), (0, -1), (0, 0), (1, 0)],
-    };
+    let color = theme().piece(block_kind);
 
-    for (x, y) in base_positions {
-        let screen_x = offset_x + (x + 1) * PREVIEW_CELL_SIZE;
-        let screen_y = offset_y + (y + 1) * PREVIEW_CELL_SIZE;
-        draw_rounded_block(d, screen_x, screen_y, PREVIEW_CELL_SIZE, color);
-    }
+    // Center the piece inside a 4x2 preview box
+    let center_x = (PREVIEW_BOX_WIDTH - block_kind.width()) * PREVIEW_CELL_SIZE / 2;
+    let center_y = (PREVIEW_BOX_HEIGHT - block_kind.height()) * PREVIEW_CELL_SIZE / 2;
+
+    let cells = block_kind.base_cells();
+    let x = offset_x + center_x - block_kind.min_x() * PREVIEW_CELL_SIZE;
+    let y = offset_y + center_y - block_kind.min_y() * PREVIEW_CELL_SIZE;
+    draw_piece_cells(d, &cells, x, y, PREVIEW_CELL_SIZE, color, style);
 }
 
-pub fn draw_board(d: &mut RaylibDrawHandle, board: &Board, offset_x: i32, offset_y: i32) {
-    for y in 0..BOARD_HEIGHT {
-        for x in 0..BOARD_WIDTH {
-            let screen_x = offset_x + (x as i32) * CELL_SIZE;
-            let screen_y = offset_y + (y as i32) * CELL_SIZE;
+/// The outline an empty cell is drawn with.
+fn draw_grid_cell(d: &mut impl RaylibDraw, x: i32, y: i32, color: Color) {
+    d.draw_rectangle_rounded_lines(
+        Rectangle::new(
+            (x + CELL_PADDING) as f32,
+            (y + CELL_PADDING) as f32,
+            (CELL_SIZE - CELL_PADDING * 2) as f32,
+            (CELL_SIZE - CELL_PADDING * 2) as f32,
+        ),
+        0.1,
+        4,
+        1.0,
+        color,
+    );
+}
 
-            match board.get_cell(y, x) {
-                Some(Cell::Filled(color)) => {
-                    draw_rounded_block(d, screen_x, screen_y, CELL_SIZE, COLORS[color as usize]);
-                }
-                _ => {
-                    d.draw_rectangle_rounded_lines(
-                        Rectangle::new(
-                            (screen_x + CELL_PADDING) as f32,
-                            (screen_y + CELL_PADDING) as f32,
-                            (CELL_SIZE - CELL_PADDING * 2) as f32,
-                            (CELL_SIZE - CELL_PADDING * 2) as f32,
-                        ),
-                        0.1,
-                        4,
-                        1.0,
-                        GRID_COLOR,
-                    );
+// One column of the sprite sheet per piece color, then one for garbage
+const SPRITE_COLUMNS: i32 = 8;
+
+/// Textures that keep the board down to a draw call per filled cell: the empty grid, drawn
+/// in one go rather than an outline per cell, and each cell color in each style to stamp
+/// on top of it. They're drawn again whenever the theme changes.
+pub struct BoardSprites {
+    grid: RenderTexture2D,
+    cells: RenderTexture2D,
+    theme: Theme,
+}
+
+impl BoardSprites {
+    pub fn new(rl: &mut RaylibHandle, thread: &RaylibThread) -> Result<Self, String> {
+        let (width, height) = (BOARD_PIXEL_WIDTH as u32, BOARD_PIXEL_HEIGHT as u32);
+        let grid = rl.load_render_texture(thread, width, height)?;
+        let rows = BlockStyle::ALL.len() as i32;
+        let (width, height) = (SPRITE_COLUMNS * CELL_SIZE, rows * CELL_SIZE);
+        let cells = rl.load_render_texture(thread, width as u32, height as u32)?;
+        let mut sprites = Self {
+            grid,
+            cells,
+            theme: theme(),
+        };
+        sprites.render(rl, thread);
+        Ok(sprites)
+    }
+
+    /// Draws the textures again if the theme changed since they were last drawn.
+    pub fn refresh(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+        if self.theme != theme() {
+            self.theme = theme();
+            self.render(rl, thread);
+        }
+    }
+
+    fn render(&mut self, mut rl: &mut RaylibHandle, thread: &RaylibThread) {
+        {
+            let mut d = rl.begin_texture_mode(thread, &mut self.grid);
+            d.clear_background(Color::BLANK);
+            for y in 0..BOARD_HEIGHT as i32 {
+                for x in 0..BOARD_WIDTH as i32 {
+                    draw_grid_cell(&mut d, x * CELL_SIZE, y * CELL_SIZE, self.theme.grid);
                 }
             }
         }
+        let mut colors = self.theme.pieces.to_vec();
+        colors.push(self.theme.garbage);
+        let mut d = rl.begin_texture_mode(thread, &mut self.cells);
+        // Filled cells cover the whole square, grid outline included, as if it weren't there
+        d.clear_background(self.theme.background);
+        for (row, style) in BlockStyle::ALL.into_iter().enumerate() {
+            for (column, color) in colors.iter().enumerate() {
+                let (x, y) = (column as i32 * CELL_SIZE, row as i32 * CELL_SIZE);
+                draw_cell(&mut d, x, y, CELL_SIZE, *color, style);
+            }
+        }
+    }
+
+    /// Where the sprite for `content` drawn in `style` is on the sheet, flipped upright.
+    fn cell_source(content: CellContent, style: BlockStyle) -> Rectangle {
+        let column = match content {
+            CellContent::Piece { kind, .. } => kind.color() as i32,
+            CellContent::Garbage => SPRITE_COLUMNS - 1,
+        };
+        // Rows go in `BlockStyle::ALL` order, from the bottom as render textures are stored
+        // upside down
+        let y = (BlockStyle::ALL.len() as i32 - 1 - style as i32) * CELL_SIZE;
+        Rectangle::new((column * CELL_SIZE) as f32, y as f32, CELL_SIZE as f32, -CELL_SIZE as f32)
     }
 }
 
-pub fn draw_scoreboard(
-    d: &mut RaylibDrawHandle,
-    player_score: u32,
-    player_lines: u32,
-    player_level: u32,
-    other_players: &HashMap<String, i32>,
-    current_player_id: Option<&str>,
+// Board draw calls since the debug readout last showed them, and how many the board took
+// drawn a shape at a time
+static BOARD_DRAW_CALLS: AtomicU32 = AtomicU32::new(0);
+static UNBATCHED_DRAW_CALLS: AtomicU32 = AtomicU32::new(0);
+
+/// Board draw calls made since the last call, and what they'd have been drawing each cell's
+/// shape directly.
+pub fn take_board_draw_calls() -> (u32, u32) {
+    (
+        BOARD_DRAW_CALLS.swap(0, Ordering::Relaxed),
+        UNBATCHED_DRAW_CALLS.swap(0, Ordering::Relaxed),
+    )
+}
+
+pub fn draw_board(
+    d: &mut impl Canvas,
+    board: &Board,
+    offset_x: i32,
+    offset_y: i32,
+    style: BlockStyle,
+    sprites: &BoardSprites,
 ) {
-    // Draw scoreboard title
-    d.draw_text(
-        "SCOREBOARD",
-        SCOREBOARD_X,
-        SCOREBOARD_Y,
-        25,
-        Color::WHITE,
-    );
+    draw_board_rows(d, board, offset_x, offset_y, style, sprites, |_| 0);
+}
 
-    // Sort all players by score (including current player)
-    let mut all_players: Vec<(&str, i32)> = other_players
-        .iter()
-        .map(|(id, &score)| (id.as_str(), score))
-        .collect();
-    
-    if let Some(player_id) = current_player_id {
-        all_players.push((player_id, player_score as i32));
+/// Draws a game's board as displayed, with the rows above a line clear sliding down into
+/// place if `collapse` is given.
+pub fn draw_game_board(
+    d: &mut impl Canvas,
+    game: &Game,
+    offset_x: i32,
+    offset_y: i32,
+    style: BlockStyle,
+    sprites: &BoardSprites,
+    collapse: Option<&Collapse>,
+) {
+    let lift = |row| collapse.map_or(0, |collapse| (collapse.lift(row) * CELL_SIZE as f32) as i32);
+    draw_board_rows(d, game.display_board(), offset_x, offset_y, style, sprites, lift);
+}
+
+// Draws the board with each row raised `lift(row)` pixels above its place
+fn draw_board_rows(
+    d: &mut impl Canvas,
+    board: &Board,
+    offset_x: i32,
+    offset_y: i32,
+    style: BlockStyle,
+    sprites: &BoardSprites,
+    lift: impl Fn(usize) -> i32,
+) {
+    let (width, height) = (BOARD_PIXEL_WIDTH as f32, BOARD_PIXEL_HEIGHT as f32);
+    let grid_source = Rectangle::new(0.0, 0.0, width, -height);
+    let offset = Vector2::new(offset_x as f32, offset_y as f32);
+    d.draw_texture_rec(&sprites.grid, grid_source, offset, Color::WHITE);
+
+    let theme = theme();
+    let mut filled = 0;
+    // Connected cells depend on their neighbors, so they're drawn shape by shape
+    let mut connected_shapes = 0;
+    for y in 0..BOARD_HEIGHT {
+        for x in 0..BOARD_WIDTH {
+            let Some(Cell::Filled(content)) = board.get_cell(y, x) else {
+                continue;
+            };
+            let screen_x = offset_x + x as i32 * CELL_SIZE;
+            let screen_y = offset_y + y as i32 * CELL_SIZE - lift(y);
+            filled += 1;
+            if style == BlockStyle::Connected {
+                let (color, joins) = match content {
+                    // Cells from the network or a layout all have id 0, so they stay apart
+                    CellContent::Piece { kind, id } if id != 0 => {
+                        let same = |x, y| piece_id(board, x, y) == Some(id);
+                        (theme.piece(kind), Joins::of(x as i32, y as i32, same))
+                    }
+                    CellContent::Piece { kind, .. } => (theme.piece(kind), Joins::default()),
+                    CellContent::Garbage => (theme.garbage, Joins::default()),
+                };
+                connected_shapes +=
+                    draw_connected_cell(d, screen_x, screen_y, CELL_SIZE, color, joins);
+            } else {
+                let position = Vector2::new(screen_x as f32, screen_y as f32);
+                let source = BoardSprites::cell_source(content, style);
+                d.draw_texture_rec(&sprites.cells, source, position, Color::WHITE);
+            }
+        }
     }
-    all_players.sort_by(|a, b| b.1.cmp(&a.1));
 
-    // Display top 10 players
-    for (i, &(player_id, score)) in all_players.iter().take(10).enumerate() {
-        let y_offset = SCOREBOARD_Y + SCOREBOARD_SPACING * (2 + i as i32);
-        let id_short = if player_id.len() > 6 {
-            &player_id[..6]
-        } else {
-            player_id
-        };
-        
-        // Highlight current player
-        let (text, color) = if Some(player_id) == current_player_id {
-            (format!("YOU: {}", score), Color::YELLOW)
-        } else {
-            (format!("{}... : {}", id_short, score), Color::WHITE)
+    // Rounded cells took a fill and a highlight each, and empty ones an outline
+    let shapes = match style {
+        BlockStyle::Rounded => filled * 2,
+        BlockStyle::Square => filled,
+        BlockStyle::Connected => connected_shapes,
+    };
+    let cells = (BOARD_WIDTH * BOARD_HEIGHT) as u32;
+    let batched = if style == BlockStyle::Connected { connected_shapes } else { filled };
+    BOARD_DRAW_CALLS.fetch_add(1 + batched, Ordering::Relaxed);
+    UNBATCHED_DRAW_CALLS.fetch_add(cells - filled + shapes, Ordering::Relaxed);
+}
+
+// The id of the placed piece at (`x`, `y`), if there's one there
+fn piece_id(board: &Board, x: i32, y: i32) -> Option<u32> {
+    let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+    match board.get_cell(y, x)? {
+        Cell::Filled(CellContent::Piece { id, .. }) => Some(id),
+        _ => None,
+    }
+}
+
+/// The board on its own as an image, drawn as it is in game, for exporting.
+pub fn board_image(
+    mut rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    board: &Board,
+    style: BlockStyle,
+    sprites: &BoardSprites,
+) -> Result<Image, String> {
+    let (width, height) = (BOARD_PIXEL_WIDTH as u32, BOARD_PIXEL_HEIGHT as u32);
+    let mut target = rl.load_render_texture(thread, width, height)?;
+    {
+        let mut d = rl.begin_texture_mode(thread, &mut target);
+        d.clear_background(theme().background);
+        draw_board(&mut d, board, 0, 0, style, sprites);
+    }
+    let mut image = target.load_image()?;
+    // Render textures come out upside down
+    image.flip_vertical();
+    Ok(image)
+}
+
+/// Draws the frame, laid out at its own size in `scene`, scaled to fit the window. The
+/// window can be resized to any shape, with bars on the sides the scene doesn't fill.
+pub fn draw_scene(d: &mut RaylibDrawHandle, scene: &RenderTexture2D) {
+    let (width, height) = (scene.texture.width as f32, scene.texture.height as f32);
+    let screen = (d.get_screen_width() as f32, d.get_screen_height() as f32);
+    let dest = scene_area(screen, (width, height));
+    // Flipped, as render textures are stored upside down
+    let source = Rectangle::new(0.0, 0.0, width, -height);
+    d.clear_background(Color::BLACK);
+    d.draw_texture_pro(scene, source, dest, Vector2::zero(), 0.0, Color::WHITE);
+}
+
+// Where the scene goes in the window: as big as fits, centered
+fn scene_area(screen: (f32, f32), scene: (f32, f32)) -> Rectangle {
+    let scale = (screen.0 / scene.0).min(screen.1 / scene.1);
+    Rectangle::new(
+        (screen.0 - scene.0 * scale) / 2.0,
+        (screen.1 - scene.1 * scale) / 2.0,
+        scene.0 * scale,
+        scene.1 * scale,
+    )
+}
+
+/// The point in the scene under `point` in a window of `screen` size, such as the mouse.
+pub fn scene_point(point: Vector2, screen: (f32, f32), scene: (f32, f32)) -> Vector2 {
+    let area = scene_area(screen, scene);
+    let scale = area.width / scene.0;
+    Vector2::new((point.x - area.x) / scale, (point.y - area.y) / scale)
+}
+
+/// A screen area in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Lays out up to `count` mini-boards in a grid inside `panel`, using the largest cell size
+/// that fits them all. Past the minimum cell size, only as many as fit on one page are
+/// returned. Each rect is a board's area; its label, `label_height` tall, goes just above it.
+pub fn mini_board_grid(count: usize, panel: Rect, label_height: i32) -> Vec<Rect> {
+    for cell_size in (MIN_MINI_CELL_SIZE..=MINI_CELL_SIZE).rev() {
+        let width = BOARD_WIDTH as i32 * cell_size;
+        let height = BOARD_HEIGHT as i32 * cell_size;
+        let slot_height = label_height + height;
+        let cols = ((panel.width + MINI_BOARD_GAP) / (width + MINI_BOARD_GAP)).max(0) as usize;
+        let rows =
+            ((panel.height + MINI_BOARD_GAP) / (slot_height + MINI_BOARD_GAP)).max(0) as usize;
+        if cols * rows < count && cell_size > MIN_MINI_CELL_SIZE {
+            continue;
+        }
+
+        return (0..count.min(cols * rows))
+            .map(|i| Rect {
+                x: panel.x + (i % cols) as i32 * (width + MINI_BOARD_GAP),
+                y: panel.y + label_height + (i / cols) as i32 * (slot_height + MINI_BOARD_GAP),
+                width,
+                height,
+            })
+            .collect();
+    }
+    Vec::new()
+}
+
+/// Pages needed to show `count` opponents in the opponent panel.
+pub fn opponent_page_count(count: usize) -> usize {
+    let layout = layout();
+    let per_page = mini_board_grid(count, layout.opponent_panel, layout.label_height()).len();
+    let per_page = per_page.max(1);
+    (0..count).step_by(per_page).count().max(1)
+}
+
+/// How opponent boards are ordered in the panel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpponentOrder {
+    #[default]
+    Score,
+    /// Whoever sent us garbage most recently comes first.
+    RecentAttack,
+}
+
+impl OpponentOrder {
+    pub fn toggled(self) -> Self {
+        match self {
+            OpponentOrder::Score => OpponentOrder::RecentAttack,
+            OpponentOrder::RecentAttack => OpponentOrder::Score,
+        }
+    }
+}
+
+/// Ids of the opponents whose boards we have, in panel order.
+pub fn sorted_opponents(game: &Game, order: OpponentOrder) -> Vec<&str> {
+    let score = |id: &str| game.other_players.get(id).map_or(0, |info| info.score);
+    let attacked_at = |id: &str| game.attacked_at.get(id);
+    let mut ids: Vec<&str> = game.other_boards.keys().map(String::as_str).collect();
+    // Ties fall back to the id so boards don't swap places from frame to frame
+    ids.sort_by(|a, b| {
+        let by_order = match order {
+            OpponentOrder::Score => score(b).cmp(&score(a)),
+            OpponentOrder::RecentAttack => attacked_at(b).cmp(&attacked_at(a)),
         };
+        by_order.then_with(|| score(b).cmp(&score(a))).then_with(|| a.cmp(b))
+    });
+    ids
+}
 
-        d.draw_text(
-            &text,
-            SCOREBOARD_X,
-            y_offset,
+/// Draws one page of opponent boards. Players who have topped out are dimmed.
+pub fn draw_opponent_boards(
+    d: &mut impl Canvas,
+    game: &Game,
+    order: OpponentOrder,
+    page: usize,
+) {
+    let layout = layout();
+    let panel = layout.opponent_panel;
+    let ids = sorted_opponents(game, order);
+    let slots = mini_board_grid(ids.len(), panel, layout.label_height());
+    let per_page = slots.len().max(1);
+    let pages = opponent_page_count(ids.len());
+    let page = page.min(pages - 1);
+    let now = Instant::now();
+
+    for (id, rect) in ids.iter().skip(page * per_page).zip(&slots) {
+        let remote = &game.other_boards[*id];
+        let cell_size = rect.width / BOARD_WIDTH as i32;
+        let info = game.other_players.get(*id);
+        let mut label = scoreboard_name(id, info.and_then(|info| info.name.as_deref()));
+        if let Some(rating) = info.and_then(|info| info.rating) {
+            label = format!("{} {}", label, rating);
+        }
+        let accent = player_accent(game, id);
+        draw_ui_text(d, &label, rect.x, rect.y - layout.label_height(), 10, accent);
+        let falling = remote.piece_at(now);
+        draw_mini_board(d, &remote.board, rect.x, rect.y, cell_size, falling, accent);
+
+        if let Some(at) = remote.perfect_clear_at {
+            draw_perfect_clear_flash(d, rect.x, rect.y, cell_size, now.duration_since(at));
+        }
+        if remote.is_stale_at(now) && !game.out_players.contains(*id) {
+            // Frozen where the last update left it until the next one
+            d.draw_rectangle(rect.x, rect.y, rect.width, rect.height, Color::new(0, 0, 0, 110));
+        }
+        if game.out_players.contains(*id) {
+            d.draw_rectangle(rect.x, rect.y, rect.width, rect.height, Color::new(0, 0, 0, 160));
+            let size = (cell_size * 2).max(10);
+            let width = d.text_width(tr("out"), size);
+            d.draw_text(
+                tr("out"),
+                rect.x + rect.width / 2 - width / 2,
+                rect.y + rect.height / 2 - size / 2,
+                size,
+                Color::RED,
+            );
+        }
+    }
+
+    if pages > 1 {
+        draw_ui_text(
+            d,
+            &tr_args("page", &[("page", &(page + 1)), ("pages", &pages)]),
+            panel.x,
+            panel.y + panel.height + 10,
             20,
-            color,
+            Color::WHITE,
         );
     }
+}
 
-    // Show total player count if there are more players
-    let total_players = all_players.len();
-    if total_players > 10 {
-        let total_y = SCOREBOARD_Y + SCOREBOARD_SPACING * 13;
+/// Draws another player's board full size in the main board area, with their piece, and
+/// who they are in the panel on the left.
+pub fn draw_spectated_board(
+    d: &mut impl Canvas,
+    game: &Game,
+    player_id: &str,
+    remote: &RemoteBoard,
+    style: BlockStyle,
+    sprites: &BoardSprites,
+) {
+    let board_x = layout().board_x;
+    draw_board(d, &remote.board, board_x, BOARD_OFFSET_Y, style, sprites);
+    if let Some(block) = &remote.current {
+        draw_block(d, block, board_x, BOARD_OFFSET_Y, style);
+    }
+    if let Some(at) = remote.perfect_clear_at {
+        draw_perfect_clear_flash(d, board_x, BOARD_OFFSET_Y, CELL_SIZE, at.elapsed());
+    }
+    if game.out_players.contains(player_id) {
+        let width = d.text_width(tr("out"), 40);
         d.draw_text(
-            &format!("+ {} more players", total_players - 10),
-            SCOREBOARD_X,
-            total_y,
-            20,
-            Color::WHITE,
+            tr("out"),
+            board_x + BOARD_PIXEL_WIDTH / 2 - width / 2,
+            BOARD_OFFSET_Y + BOARD_PIXEL_HEIGHT / 2 - 20,
+            40,
+            Color::RED,
         );
     }
 
-    // Draw player stats
-    let stats_y = SCOREBOARD_Y + SCOREBOARD_SPACING * 15;
-    d.draw_text(
-        "YOUR STATS",
-        SCOREBOARD_X,
-        stats_y,
-        20,
-        Color::YELLOW,
-    );
-    d.draw_text(
-        &format!("Lines: {}", player_lines),
-        SCOREBOARD_X,
-        stats_y + SCOREBOARD_SPACING,
-        20,
-        Color::WHITE,
-    );
-    d.draw_text(
-        &format!("Level: {}", player_level),
-        SCOREBOARD_X,
-        stats_y + SCOREBOARD_SPACING * 2,
-        20,
-        Color::WHITE,
+    let info = game.other_players.get(player_id);
+    let name = scoreboard_name(player_id, info.and_then(|info| info.name.as_deref()));
+    let score = tr_args("score_value", &[("score", &info.map_or(0, |info| info.score))]);
+    let mut lines = vec![
+        (tr("spectating"), 20, Color::SKYBLUE),
+        (name.as_str(), 20, Color::WHITE),
+        (score.as_str(), 20, Color::WHITE),
+    ];
+    let age = remote.updated_at.map(|updated_at| updated_at.elapsed());
+    let stale = age
+        .filter(|age| *age >= SPECTATE_STALE_AFTER)
+        .map(|age| tr_args("last_update", &[("seconds", &age.as_secs())]));
+    if let Some(stale) = &stale {
+        lines.push((stale.as_str(), 15, Color::GRAY));
+    }
+    lines.push((tr("spectate_keys"), 12, Color::GRAY));
+    draw_text_lines(d, 20, BOARD_OFFSET_Y, false, &lines);
+}
+
+/// Player 2's side in local versus: their board right of player 1's panel, with next, hold
+/// and their stats beside it.
+pub fn draw_versus_board(
+    d: &mut impl Canvas,
+    game: &Game,
+    style: BlockStyle,
+    sprites: &BoardSprites,
+    reduce_motion: bool,
+) {
+    let layout = layout();
+    let x = layout.versus_board_x;
+    let collapse = game.collapse().filter(|_| !reduce_motion);
+    draw_game_board(d, game, x, BOARD_OFFSET_Y, style, sprites, collapse);
+    draw_clearing_rows(d, &game.clearing_rows(), x, BOARD_OFFSET_Y);
+    if game.is_controllable() {
+        // The ghost marks where the rows end up, so it waits for them to get there
+        if collapse.is_none() {
+            draw_ghost_block(d, &game.ghost_block(), x, BOARD_OFFSET_Y, style);
+        }
+        draw_falling_block(d, game, x, BOARD_OFFSET_Y, style);
+    }
+
+    let panel_x = x + BOARD_PIXEL_WIDTH + layout.scaled(PANEL_GAP);
+    draw_ui_text(d, tr("next"), panel_x, BOARD_OFFSET_Y, 20, Color::WHITE);
+    draw_preview_block(d, game.next_block.kind, panel_x, layout.next_y, style);
+    draw_ui_text(d, tr("hold"), panel_x, layout.hold_y, 20, Color::WHITE);
+    let y = layout.hold_y + layout.line_height(20);
+    draw_hold_queue(d, &game.hold, game.config.hold_slots, panel_x, y, style);
+    let score = tr_args("score_value", &[("score", &game.score.points)]);
+    let lines = tr_args("lines_value", &[("lines", &game.score.lines)]);
+    let level = tr_args("level_value", &[("level", &game.score.level)]);
+    draw_text_lines(
+        d,
+        panel_x,
+        layout.hud_y,
+        false,
+        &[
+            (tr("player_two"), 20, Color::YELLOW),
+            (&score, 20, Color::WHITE),
+            (&lines, 20, Color::WHITE),
+            (&level, 20, Color::WHITE),
+        ],
+    );
+}
+
+pub fn draw_mini_board(
+    d: &mut impl Canvas,
+    board: &Board,
+    offset_x: i32,
+    offset_y: i32,
+    cell_size: i32,
+    falling: Option<RemotePiece>,
+    border: Color,
+) {
+    d.draw_rectangle_lines(
+        offset_x - 1,
+        offset_y - 1,
+        BOARD_WIDTH as i32 * cell_size + 2,
+        BOARD_HEIGHT as i32 * cell_size + 2,
+        border,
     );
+    for y in 0..BOARD_HEIGHT {
+        for x in 0..BOARD_WIDTH {
+            if let Some(Cell::Filled(content)) = board.get_cell(y, x) {
+                d.draw_rectangle(
+                    offset_x + x as i32 * cell_size + 1,
+                    offset_y + y as i32 * cell_size + 1,
+                    cell_size - 2,
+                    cell_size - 2,
+                    content_color(content),
+                );
+            }
+        }
+    }
+
+    // The falling piece is only a snapshot, so draw it faded to set it apart from the stack,
+    // with its ghost fainter still
+    let Some(piece) = falling else {
+        return;
+    };
+    let color = theme().piece(piece.block.kind);
+    let ghost = Block {
+        y: board.drop_row(&piece.block),
+        ..piece.block
+    };
+    let (dx, dy) = piece.offset;
+    let layers = [
+        (ghost, (0.0, 0.0), REMOTE_GHOST_ALPHA),
+        (piece.block, (dx, dy), REMOTE_PIECE_ALPHA),
+    ];
+    for (block, (dx, dy), alpha) in layers {
+        let faded = Color::new(color.r, color.g, color.b, alpha);
+        for (x, y) in block.blocks() {
+            let (x, y) = (x as f32 + dx, y as f32 + dy);
+            if y < 0.0 {
+                continue;
+            }
+            d.draw_rectangle(
+                offset_x + (x * cell_size as f32) as i32 + 1,
+                offset_y + (y * cell_size as f32) as i32 + 1,
+                cell_size - 2,
+                cell_size - 2,
+                faded,
+            );
+        }
+    }
+}
+
+// Scoreboard table: one row per player, columns relative to the panel at a UI scale of 1
+const SCOREBOARD_FONT_SIZE: i32 = 14;
+const SCOREBOARD_HEADER_SIZE: i32 = 12;
+const SCOREBOARD_NAME_CHARS: usize = 7;
+const STATUS_COLUMN_X: i32 = 26;
+const NAME_COLUMN_X: i32 = 34;
+const SCORE_COLUMN_RIGHT: i32 = 125;
+const LINES_COLUMN_RIGHT: i32 = 160;
+// KO badges, one dot each up to the most that boost attacks
+const BADGE_COLUMN_X: i32 = 170;
+const BADGE_SPACING: i32 = 7;
+// Session record against each opponent, e.g. "W 3-2 L"
+const RECORD_COLUMN_RIGHT: i32 = 265;
+// Lifetime records shown under a match's results
+const MAX_RIVAL_LINES: usize = 3;
+
+struct ScoreboardRow<'a> {
+    id: &'a str,
+    name: String,
+    score: i32,
+    lines: u32,
+    ko_badges: u32,
+    record: Option<HeadToHead>,
+    status: PlayerStatus,
+    is_local: bool,
+    accent: Color,
+}
+
+/// Name shown for a player on the scoreboard: their chosen name, or the start of their id,
+/// cut to fit the name column.
+pub fn scoreboard_name(player_id: &str, name: Option<&str>) -> String {
+    let name = name.map(str::trim).filter(|name| !name.is_empty());
+    name.unwrap_or(player_id).chars().take(SCOREBOARD_NAME_CHARS).collect()
+}
+
+/// Another player's accent color: their server-given index into the theme's piece colors,
+/// moved one along when it's the same as ours, which it can be once everyone's is taken.
+/// White from servers that don't hand them out.
+pub fn accent_color(accent: Option<u32>, own: Option<u32>) -> Color {
+    let Some(accent) = accent else {
+        return Color::WHITE;
+    };
+    let accent = if Some(accent) == own { accent + 1 } else { accent };
+    let pieces = theme().pieces;
+    pieces[accent as usize % pieces.len()]
+}
+
+/// The accent color `player_id` is shown in.
+pub fn player_accent(game: &Game, player_id: &str) -> Color {
+    accent_color(game.other_players.get(player_id).and_then(|info| info.accent), game.accent)
+}
+
+fn status_color(status: PlayerStatus) -> Color {
+    match status {
+        PlayerStatus::Playing => Color::GREEN,
+        PlayerStatus::Paused => Color::YELLOW,
+        PlayerStatus::Afk => Color::SKYBLUE,
+        PlayerStatus::Out => Color::RED,
+        PlayerStatus::Forfeit => Color::ORANGE,
+        PlayerStatus::Stale => Color::GRAY,
+    }
+}
+
+/// Every player's scoreboard row, ours included, best score first.
+fn scoreboard_rows(game: &Game) -> Vec<ScoreboardRow<'_>> {
+    let mut rows: Vec<ScoreboardRow> = game
+        .other_players
+        .iter()
+        .map(|(id, info)| ScoreboardRow {
+            id,
+            name: scoreboard_name(id, info.name.as_deref()),
+            score: info.score,
+            lines: info.lines,
+            ko_badges: info.ko_badges,
+            record: game.session.opponents.get(id).copied(),
+            status: game.player_status(id),
+            is_local: false,
+            accent: accent_color(info.accent, game.accent),
+        })
+        .collect();
+    if let Some(player_id) = game.player_id.as_deref() {
+        rows.push(ScoreboardRow {
+            id: player_id,
+            name: tr("you").to_string(),
+            score: game.score.points as i32,
+            lines: game.score.lines,
+            ko_badges: game.ko_badges,
+            record: None,
+            status: game.own_status(),
+            is_local: true,
+            accent: Color::YELLOW,
+        });
+    }
+    // Ties are broken by id so rows don't swap places from frame to frame
+    rows.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.id.cmp(b.id)));
+    rows
+}
+
+/// The rows that fit in `fit` lines with their ranks, leaving a line for the overflow
+/// count when they don't all fit.
+fn visible_scoreboard_rows<'a, 'b>(
+    rows: &'b [ScoreboardRow<'a>],
+    fit: usize,
+) -> Vec<(usize, &'b ScoreboardRow<'a>)> {
+    let shown = if rows.len() > fit { fit - 1 } else { rows.len() };
+    let mut visible: Vec<(usize, &ScoreboardRow)> = rows.iter().enumerate().take(shown).collect();
+    // We always see our own row, in place of the last one if we're further down
+    if let Some(local) = rows.iter().enumerate().skip(shown).find(|(_, row)| row.is_local) {
+        visible.pop();
+        visible.push(local);
+    }
+    visible
+}
+
+pub fn draw_scoreboard(d: &mut impl Canvas, game: &Game) {
+    let layout = layout();
+    let x = layout.panel_x;
+    draw_ui_text(d, tr("scoreboard"), x, layout.scoreboard_y, 25, Color::WHITE);
+
+    let header_y = layout.scoreboard_y + layout.line_height(25);
+    let header = SCOREBOARD_HEADER_SIZE;
+    let name_x = x + layout.scaled(NAME_COLUMN_X);
+    let score_right = x + layout.scaled(SCORE_COLUMN_RIGHT);
+    let lines_right = x + layout.scaled(LINES_COLUMN_RIGHT);
+    let record_right = x + layout.scaled(RECORD_COLUMN_RIGHT);
+    draw_ui_text(d, "#", x, header_y, header, Color::GRAY);
+    draw_ui_text(d, tr("column_name"), name_x, header_y, header, Color::GRAY);
+    draw_text_right(d, tr("column_score"), score_right, header_y, header, Color::GRAY);
+    draw_text_right(d, tr("column_lines"), lines_right, header_y, header, Color::GRAY);
+    if game.session.matches > 0 {
+        draw_text_right(d, tr("column_record"), record_right, header_y, header, Color::GRAY);
+    }
+
+    // Online, attack totals go just above the stats block
+    let mut attack_y = layout.stats_y;
+    if game.multiplayer.is_some() {
+        attack_y -= layout.line_height(20);
+        let attack = tr_args(
+            "sent_received",
+            &[("sent", &game.lines_sent), ("received", &game.lines_received)],
+        );
+        draw_ui_text(d, &attack, x, attack_y, 20, Color::WHITE);
+    }
+
+    let rows = scoreboard_rows(game);
+    // Fill the space above the attack totals; past that, keep a row for the overflow count
+    let row_height = layout.line_height(SCOREBOARD_FONT_SIZE);
+    let rows_y = header_y + layout.line_height(header);
+    let fit = ((attack_y - 10 - rows_y) / row_height).max(1) as usize;
+    let visible = visible_scoreboard_rows(&rows, fit);
+
+    for (i, (rank, row)) in visible.iter().enumerate() {
+        let y = rows_y + row_height * i as i32;
+        let color = if row.is_local {
+            Color::YELLOW
+        } else if row.status.is_in_game() {
+            Color::WHITE
+        } else {
+            Color::GRAY
+        };
+        let size = SCOREBOARD_FONT_SIZE;
+        draw_ui_text(d, &format!("{}", rank + 1), x, y, size, color);
+        d.draw_circle(
+            x + layout.scaled(STATUS_COLUMN_X),
+            y + layout.scaled(size) / 2,
+            4.0 * layout.scale,
+            status_color(row.status),
+        );
+        // Names are in each player's accent, dimmed like the rest of the row once they're out
+        let name_color = if row.status.is_in_game() { row.accent } else { row.accent.fade(0.5) };
+        draw_ui_text(d, &row.name, name_x, y, size, name_color);
+        if row.status == PlayerStatus::Afk {
+            let afk_x = name_x + ui_text_width(d, &row.name, size) + layout.scaled(6);
+            draw_ui_text(d, tr("afk"), afk_x, y, size, Color::SKYBLUE);
+        }
+        draw_text_right(d, &row.score.to_string(), score_right, y, size, color);
+        draw_text_right(d, &row.lines.to_string(), lines_right, y, size, color);
+        for badge in 0..row.ko_badges.min(MAX_KO_BADGE_BONUS) as i32 {
+            d.draw_circle(
+                x + layout.scaled(BADGE_COLUMN_X + badge * BADGE_SPACING),
+                y + layout.scaled(size) / 2,
+                3.0 * layout.scale,
+                Color::GOLD,
+            );
+        }
+        if let Some(record) = row.record {
+            draw_text_right(d, &record_text(record), record_right, y, size, color);
+        }
+    }
+
+    let hidden = rows.len() - visible.len();
+    if hidden > 0 {
+        draw_ui_text(
+            d,
+            &tr_args("more_players", &[("count", &hidden)]),
+            x,
+            rows_y + row_height * visible.len() as i32,
+            SCOREBOARD_FONT_SIZE,
+            Color::WHITE,
+        );
+    }
+
+    draw_player_stats(d, game);
+}
+
+fn record_text(record: HeadToHead) -> String {
+    tr_args("record", &[("wins", &record.wins), ("losses", &record.losses)])
+}
+
+/// Gravity for the HUD: rows per second while slow enough to follow, then in G, rows per
+/// frame at 60 FPS.
+pub fn format_gravity(rows_per_sec: f32) -> String {
+    if rows_per_sec < 60.0 {
+        tr_args("gravity_rows", &[("rows", &format!("{:.2}", rows_per_sec))])
+    } else {
+        tr_args("gravity_g", &[("g", &format!("{:.1}", rows_per_sec / 60.0))])
+    }
+}
+
+pub fn draw_player_stats(d: &mut impl Canvas, game: &Game) {
+    let layout = layout();
+    let lines = tr_args("lines_value", &[("lines", &game.score.lines)]);
+    let level = tr_args("level_value", &[("level", &game.score.level)]);
+    let next_level = tr_args("next_level_value", &[("lines", &game.lines_to_next_level())]);
+    let gravity = format_gravity(game.gravity());
+    let finesse = tr_args("finesse_value", &[("faults", &game.finesse.faults)]);
+    let y = draw_text_lines(
+        d,
+        layout.panel_x,
+        layout.stats_y,
+        false,
+        &[
+            (tr("your_stats"), STATS_SIZE, Color::YELLOW),
+            (&lines, STATS_SIZE, Color::WHITE),
+            (&level, STATS_SIZE, Color::WHITE),
+        ],
+    );
+    let width = layout.scaled(LEVEL_BAR_WIDTH);
+    let filled = (width as f32 * game.level_progress().clamp(0.0, 1.0)) as i32;
+    let bar_y = y - LEVEL_BAR_HEIGHT - 1;
+    let theme = theme();
+    d.draw_rectangle(layout.panel_x, bar_y, width, LEVEL_BAR_HEIGHT, theme.grid);
+    d.draw_rectangle(layout.panel_x, bar_y, filled, LEVEL_BAR_HEIGHT, theme.piece(BlockKind::I));
+    draw_text_lines(
+        d,
+        layout.panel_x,
+        y,
+        false,
+        &[
+            (&next_level, STATS_SIZE, Color::WHITE),
+            (&gravity, STATS_SIZE, Color::WHITE),
+            (&finesse, STATS_SIZE, Color::WHITE),
+        ],
+    );
+}
+
+/// Coach mode's warning for a wasteful placement: the board border flashes red, fading out.
+pub fn draw_finesse_flash(
+    d: &mut impl RaylibDraw,
+    elapsed: Duration,
+    offset_x: i32,
+    offset_y: i32,
+) {
+    let fade = 1.0 - (elapsed.as_secs_f32() / FINESSE_FLASH_DURATION.as_secs_f32()).min(1.0);
+    d.draw_rectangle_lines_ex(
+        Rectangle::new(
+            (offset_x - 4) as f32,
+            (offset_y - 4) as f32,
+            (BOARD_WIDTH as i32 * CELL_SIZE + 8) as f32,
+            (BOARD_HEIGHT as i32 * CELL_SIZE + 8) as f32,
+        ),
+        4.0,
+        Color::RED.fade(fade),
+    );
+}
+
+pub fn draw_practice_hud(d: &mut impl Canvas, undo_count: usize) {
+    let undo = tr_args("practice_undo", &[("count", &undo_count)]);
+    draw_text_lines(
+        d,
+        20,
+        layout().hud_y,
+        false,
+        &[
+            (tr("practice"), 20, Color::YELLOW),
+            (tr("practice_reset"), 20, Color::WHITE),
+            (&undo, 20, Color::WHITE),
+            (tr("practice_hints"), 20, Color::WHITE),
+        ],
+    );
+}
+
+/// Practice mode's placement hints: the fewest inputs that take the piece to each spot it
+/// can land, in a corner of its top left cell there. Each rotation gets its own corner, so
+/// rotations landing in the same column don't cover each other.
+pub fn draw_placement_hints(
+    d: &mut impl Canvas,
+    hints: &[PlacementHint],
+    offset_x: i32,
+    offset_y: i32,
+) {
+    let half = CELL_SIZE / 2;
+    for hint in hints {
+        let cells = hint.landed.blocks();
+        let Some(&(x, y)) = cells.iter().min_by_key(|&&(x, y)| (y, x)) else {
+            continue;
+        };
+        if y < 0 {
+            continue;
+        }
+        let rotation = hint.landed.rotation as i32;
+        let corner_x = offset_x + x * CELL_SIZE + half * (rotation % 2);
+        let corner_y = offset_y + y * CELL_SIZE + half * (rotation / 2);
+        let text = hint.inputs.to_string();
+        draw_text_centered(d, &text, corner_x + half / 2, corner_y + 2, 10, Color::WHITE);
+    }
+}
+
+// Palette swatches in the board editor, and how many to a row
+const SWATCH_SIZE: i32 = 25;
+const SWATCHES_PER_ROW: usize = 4;
+
+/// The board editor: the board being drawn with the cursor over it, the palette with the
+/// brush picked out, the name and piece fields, the keys, and why the setup can't be
+/// played yet.
+pub fn draw_board_editor(
+    d: &mut impl Canvas,
+    editor: &BoardEditor,
+    style: BlockStyle,
+    sprites: &BoardSprites,
+) {
+    let layout = layout();
+    let (board_x, board_y) = (layout.board_x, BOARD_OFFSET_Y);
+    let center = board_x + BOARD_PIXEL_WIDTH / 2;
+    draw_text_centered(d, tr("editor_title"), center, 15, 20, Color::WHITE);
+    draw_board(d, &editor.board, board_x, board_y, style, sprites);
+    if editor.field == EditorField::Board {
+        let (row, col) = editor.cursor;
+        let (x, y) = (board_x + col as i32 * CELL_SIZE, board_y + row as i32 * CELL_SIZE);
+        let cursor = Rectangle::new(x as f32, y as f32, CELL_SIZE as f32, CELL_SIZE as f32);
+        d.draw_rectangle_lines_ex(cursor, 3.0, Color::YELLOW);
+    }
+    if let Some(error) = &editor.error {
+        let y = board_y + BOARD_PIXEL_HEIGHT + 10;
+        draw_text_centered(d, &error.to_string(), center, y, 15, Color::RED);
+    }
+
+    draw_ui_text(d, tr("editor_palette"), layout.panel_x, board_y, 20, Color::WHITE);
+    for (index, brush) in Brush::ALL.into_iter().enumerate() {
+        let x = layout.panel_x + (index % SWATCHES_PER_ROW) as i32 * (SWATCH_SIZE + 5);
+        let y = layout.next_y + (index / SWATCHES_PER_ROW) as i32 * (SWATCH_SIZE + 5);
+        draw_cell(d, x, y, SWATCH_SIZE, content_color(brush.content()), style);
+        if brush == editor.brush() {
+            let outline = Rectangle::new(
+                (x - 2) as f32,
+                (y - 2) as f32,
+                (SWATCH_SIZE + 4) as f32,
+                (SWATCH_SIZE + 4) as f32,
+            );
+            d.draw_rectangle_lines_ex(outline, 2.0, Color::YELLOW);
+        }
+    }
+
+    let rows = Brush::ALL.len().div_ceil(SWATCHES_PER_ROW) as i32;
+    let mut y = layout.next_y + rows * (SWATCH_SIZE + 5) + 20;
+    let queue = if editor.queue.is_empty() && editor.field != EditorField::Queue {
+        tr("editor_random_pieces")
+    } else {
+        &editor.queue
+    };
+    let fields = [
+        (EditorField::Name, tr("editor_name"), editor.name.as_str()),
+        (EditorField::Queue, tr("editor_queue"), queue),
+    ];
+    for (field, label, value) in fields {
+        let focused = editor.field == field;
+        let color = if focused { Color::YELLOW } else { Color::WHITE };
+        draw_ui_text(d, label, layout.panel_x, y, 20, color);
+        y += layout.line_height(20);
+        let value = if focused { format!("{}_", value) } else { value.to_string() };
+        draw_ui_text(d, &value, layout.panel_x, y, 15, Color::LIGHTGRAY);
+        y += layout.line_height(15) + 10;
+    }
+
+    let keys = [
+        "editor_keys_move",
+        "editor_keys_paint",
+        "editor_keys_color",
+        "editor_keys_rows",
+        "editor_keys_fields",
+        "editor_keys_play",
+        "editor_keys_back",
+    ];
+    let lines: Vec<_> = keys.iter().map(|&key| (tr(key), 15, Color::GRAY)).collect();
+    draw_text_lines(d, 20, layout.hold_y, false, &lines);
+}
+
+/// Formats a run time as `m:ss.mmm`.
+pub fn format_time(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+/// Window title summing up the game, e.g. "Tetris — Cheese Race — 7/18 lines — 1:32".
+pub fn window_title(game: &Game) -> String {
+    let progress = match game.mode {
+        GameMode::CheeseRace => format!(
+            "{}/{} lines",
+            game.garbage_cleared.min(game.config.cheese.target_lines),
+            game.config.cheese.target_lines
+        ),
+        GameMode::Sprint => format!(
+            "{}/{} lines",
+            game.score.lines.min(SPRINT_LINES),
+            SPRINT_LINES
+        ),
+        _ => format!("{} pts", game.score.points),
+    };
+    let seconds = game.play_time.as_secs();
+    let mut title = format!(
+        "Tetris — {} — {} — {}:{:02}",
+        game.mode.name(),
+        progress,
+        seconds / 60,
+        seconds % 60
+    );
+    if game.multiplayer.is_some() {
+        title.push_str(" — Online");
+        if let Some(room) = &game.room {
+            let lock = if game.room_locked { ", locked" } else { "" };
+            title.push_str(&format!(" ({}{})", room, lock));
+        }
+    } else if game.connection_lost {
+        title.push_str(" — Offline");
+    }
+    match game.state {
+        GameState::Paused => title.push_str(" [PAUSED]"),
+        GameState::GameOver => title.push_str(" [GAME OVER]"),
+        GameState::Playing | GameState::Finished => {}
+    }
+    title
+}
+
+pub fn draw_cheese_hud(d: &mut impl Canvas, play_time: Duration, remaining: u32) {
+    let remaining = tr_args("garbage_left", &[("count", &remaining)]);
+    draw_clock_hud(d, tr("cheese_race"), play_time, &remaining);
+}
+
+/// Sprint progress: the clock and the lines still to clear, under `title`.
+/// A sprint's panel, with how the last split compared to the best next to the clock.
+pub fn draw_sprint_hud(
+    d: &mut impl Canvas,
+    title: &str,
+    play_time: Duration,
+    lines: u32,
+    delta: Option<i64>,
+) {
+    let remaining = tr_args("lines_left", &[("count", &SPRINT_LINES.saturating_sub(lines))]);
+    draw_clock_hud(d, title, play_time, &remaining);
+    if let Some(delta) = delta {
+        let layout = layout();
+        let x = 20 + ui_text_width(d, &format_time(play_time), 30) + 10;
+        let y = layout.hud_y + layout.line_height(20) + 5;
+        draw_ui_text(d, &format_split_delta(delta), x, y, 20, split_delta_color(delta));
+    }
+}
+
+/// A split's difference from the best, e.g. "-1.3s" when ahead.
+pub fn format_split_delta(delta_ms: i64) -> String {
+    let sign = if delta_ms < 0 { '-' } else { '+' };
+    let tenths = (delta_ms.unsigned_abs() + 50) / 100;
+    format!("{}{}.{}s", sign, tenths / 10, tenths % 10)
+}
+
+fn split_delta_color(delta_ms: i64) -> Color {
+    if delta_ms < 0 {
+        Color::GREEN
+    } else {
+        Color::RED
+    }
+}
+
+/// A finished sprint's splits beside the results, each against the best run before it.
+pub fn draw_split_table(
+    d: &mut impl Canvas,
+    splits: &Splits,
+    best: Option<&BestSplits>,
+    new_best: bool,
+) {
+    let layout = layout();
+    let left = 20;
+    let time_right = left + layout.scaled(170);
+    let delta_right = time_right + layout.scaled(70);
+    let mut y = 120;
+    draw_ui_text(d, tr("splits"), left, y, 20, Color::YELLOW);
+    y += layout.line_height(20);
+    for (index, &time) in splits.times().iter().enumerate() {
+        let lines = tr_args("split_lines", &[("count", &((index as u32 + 1) * SPLIT_LINES))]);
+        draw_ui_text(d, &lines, left, y, 15, Color::WHITE);
+        draw_text_right(d, &format_time(time), time_right, y, 15, Color::WHITE);
+        if let Some(delta) = best.and_then(|best| best.delta(index, time)) {
+            let text = format_split_delta(delta);
+            draw_text_right(d, &text, delta_right, y, 15, split_delta_color(delta));
+        }
+        y += layout.line_height(15);
+    }
+    if new_best {
+        draw_ui_text(d, tr("new_best"), left, y + 5, 20, Color::GOLD);
+    }
+}
+
+// A race's panel: the title, a big clock and what's left to do
+fn draw_clock_hud(d: &mut impl Canvas, title: &str, play_time: Duration, remaining: &str) {
+    draw_text_lines(
+        d,
+        20,
+        layout().hud_y,
+        false,
+        &[
+            (title, 20, Color::YELLOW),
+            (&format_time(play_time), 30, Color::WHITE),
+            (remaining, 20, Color::WHITE),
+        ],
+    );
+}
+
+/// The end of a daily challenge: our time, and the day's leaderboard once the server
+/// has sent it.
+pub fn draw_daily_results(
+    d: &mut impl Canvas,
+    date: &str,
+    time: Duration,
+    official: bool,
+    verdict: Option<Result<(), RecordRejection>>,
+    leaderboard: Option<&[DailyEntry]>,
+) {
+    let layout = layout();
+    let center = layout.window_width / 2;
+    let left = center - layout.scaled(150);
+    let right = center + layout.scaled(150);
+    let title = tr_args("daily_title", &[("date", &date)]);
+    draw_text_centered(d, &title, center, 120, 30, Color::WHITE);
+    let mut y = 120 + layout.line_height(30) + 20;
+    let time = tr_args("time_value", &[("time", &format_time(time))]);
+    let mut lines = vec![(time.as_str(), 20, Color::WHITE)];
+    if !official {
+        lines.push((tr("daily_unofficial"), 15, Color::GRAY));
+    }
+    let rejected;
+    match verdict {
+        Some(Ok(())) => lines.push((tr("daily_verified"), 15, Color::GREEN)),
+        Some(Err(reason)) => {
+            rejected = tr_args("daily_rejected", &[("reason", &reason)]);
+            lines.push((rejected.as_str(), 15, Color::RED));
+        }
+        None => {}
+    }
+    y = draw_text_lines(d, left, y, false, &lines) + 20;
+
+    let footer_y = draw_footer(d, tr("play_again"));
+    if let Some(entries) = leaderboard {
+        draw_ui_text(d, tr("todays_best"), left, y, 20, Color::YELLOW);
+        y += layout.line_height(20);
+        let fit = ((footer_y - y) / layout.line_height(20)).max(0) as usize;
+        for (index, entry) in entries.iter().take(fit).enumerate() {
+            let row_y = y + index as i32 * layout.line_height(20);
+            draw_ui_text(d, &format!("{}", index + 1), left, row_y, 20, Color::WHITE);
+            let name = scoreboard_name(&entry.name, Some(&entry.name));
+            draw_ui_text(d, &name, left + layout.scaled(40), row_y, 20, Color::WHITE);
+            let time = format_time(Duration::from_millis(entry.time_ms));
+            draw_text_right(d, &time, right, row_y, 20, Color::WHITE);
+        }
+    }
+}
+
+/// Draws the prompt along the bottom of a results screen, returning its top.
+fn draw_footer(d: &mut impl Canvas, text: &str) -> i32 {
+    let layout = layout();
+    let y = WINDOW_HEIGHT - 60 - layout.scaled(20);
+    draw_text_centered(d, text, layout.window_width / 2, y, 20, Color::WHITE);
+    y
+}
+
+pub fn draw_results(d: &mut impl Canvas, play_time: Duration, pieces: u32) {
+    let time = tr_args("time_value", &[("time", &format_time(play_time))]);
+    let pieces = tr_args("pieces_value", &[("count", &pieces)]);
+    draw_overlay_text(
+        d,
+        &[
+            (tr("finished"), 30, Color::WHITE),
+            (&time, 20, Color::WHITE),
+            (&pieces, 20, Color::WHITE),
+            (tr("restart_hint"), 20, Color::WHITE),
+        ],
+    );
+}
+
+/// The end of a game played for points: the score and where it came from, how the game
+/// went and, online, the lines traded with the other players. Rows that don't fit above
+/// the keys at large UI scales are left off.
+pub fn draw_game_results(d: &mut impl Canvas, game: &Game, title: &str, new_best: bool) {
+    let layout = layout();
+    let center = layout.window_width / 2;
+    let left = center - layout.scaled(170);
+    let right = center + layout.scaled(170);
+    let footer_y = draw_footer(d, tr("results_keys"));
+    draw_text_centered(d, title, center, 120, 30, Color::WHITE);
+
+    let mut y = 120 + layout.line_height(30) + 20;
+    draw_ui_text(d, tr("results_score"), left, y, 30, Color::WHITE);
+    draw_text_right(d, &game.score.points.to_string(), right, y, 30, Color::WHITE);
+    y += layout.line_height(30);
+    if new_best {
+        draw_text_centered(d, tr("new_best"), center, y, 20, Color::GOLD);
+        y += layout.line_height(20);
+    }
+    if game.is_assisted() {
+        draw_text_centered(d, tr("assisted_no_records"), center, y, 15, Color::SKYBLUE);
+        y += layout.line_height(15);
+    }
+    let sources = game.score_sources;
+    let mut breakdown = vec![
+        (tr("results_clears"), sources.clears),
+        (tr("results_t_spins"), sources.t_spins),
+    ];
+    if game.mode == GameMode::Cascade {
+        breakdown.push((tr("results_chains"), sources.chains));
+    }
+    for (name, points) in breakdown {
+        draw_ui_text(d, name, left + layout.scaled(20), y, 15, Color::GRAY);
+        draw_text_right(d, &points.to_string(), right, y, 15, Color::GRAY);
+        y += layout.line_height(15);
+    }
+    y += 10;
+
+    let mut rows = vec![
+        (tr("results_lines"), game.score.lines.to_string()),
+        (tr("results_level"), game.score.level.to_string()),
+        (tr("results_time"), format_time(game.play_time)),
+        (tr("results_pps"), format!("{:.2}", game.pps())),
+        (tr("results_apm"), format!("{:.1}", game.apm())),
+        (tr("results_best_combo"), game.best_combo.to_string()),
+    ];
+    if game.multiplayer.is_some() {
+        rows.push((tr("results_sent"), game.lines_sent.to_string()));
+        rows.push((tr("results_received"), game.lines_received.to_string()));
+    }
+    for (name, value) in rows {
+        if y + layout.line_height(20) > footer_y - 10 {
+            break;
+        }
+        draw_ui_text(d, name, left, y, 20, Color::WHITE);
+        draw_text_right(d, &value, right, y, 20, Color::WHITE);
+        y += layout.line_height(20);
+    }
+}
+
+/// Game over screen text: who won or what ended, and the keys that work from here.
+pub fn draw_game_over(d: &mut impl Canvas, title: &str, hint: &str) {
+    draw_overlay_text(d, &[(title, 30, Color::WHITE), (hint, 20, Color::WHITE)]);
+}
+
+// Lines centered across the window, from its middle down
+fn draw_overlay_text(d: &mut impl Canvas, lines: &[(&str, i32, Color)]) {
+    draw_text_lines(d, layout().window_width / 2, WINDOW_HEIGHT / 2, true, lines);
+}
+
+/// An attack on its way in, named for whoever sent it, shown until it lands or a little
+/// while after our clears cancel it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttackWarning {
+    pub id: u64,
+    pub name: String,
+    /// The sender's accent color.
+    pub color: Color,
+    pub lines: u32,
+    pub kind: AttackKind,
+    pub cancelled_at: Option<Instant>,
+}
+
+/// The pending garbage as a bar up the board's left edge, a cell per line and a segment
+/// per attack, oldest at the bottom. Red lands when the falling piece locks, orange with
+/// the piece after, and yellow later still.
+pub fn draw_garbage_meter(d: &mut impl Canvas, game: &Game, board_x: i32, board_y: i32) {
+    let delay = game.config.garbage_delay_pieces;
+    let mut bottom = board_y + BOARD_PIXEL_HEIGHT;
+    for attack in game.incoming_attacks() {
+        let height = (attack.lines as i32 * CELL_SIZE).min(bottom - board_y);
+        if height <= 0 {
+            break;
+        }
+        let color = match attack.pieces_left(delay) {
+            1 => Color::RED,
+            2 => Color::ORANGE,
+            _ => Color::YELLOW,
+        };
+        let gap = if height > METER_GAP { METER_GAP } else { 0 };
+        d.draw_rectangle(board_x - 10, bottom - height + gap, 6, height - gap, color);
+        bottom -= height;
+    }
+}
+
+/// Lists incoming attacks, as "ALICE +4", up from the bottom left of the board by the
+/// garbage meter, newest on top. Cancelled ones are crossed out and fade away.
+pub fn draw_attack_warnings(
+    d: &mut impl Canvas,
+    warnings: &[AttackWarning],
+    board_x: i32,
+    board_y: i32,
+) {
+    let layout = layout();
+    let line = layout.line_height(20);
+    let mut y = board_y + BOARD_PIXEL_HEIGHT;
+    for warning in warnings {
+        y -= line;
+        let name = warning.name.to_uppercase();
+        let key = match warning.kind {
+            AttackKind::Normal => "attack_warning",
+            AttackKind::PerfectClear => "attack_warning_perfect_clear",
+        };
+        let text = tr_args(key, &[("name", &name), ("lines", &warning.lines)]);
+        let width = ui_text_width(d, &text, 20);
+        let (x, size) = (board_x + 6, layout.scaled(20));
+        let fade = warning.cancelled_at.map_or(1.0, |at| {
+            1.0 - at.elapsed().as_secs_f32() / CANCELLED_ATTACK_DURATION.as_secs_f32()
+        });
+        let fade = fade.clamp(0.0, 1.0);
+        d.draw_rectangle(x - 4, y - 2, width + 8, size + 4, Color::BLACK.fade(0.6 * fade));
+        if warning.cancelled_at.is_some() {
+            draw_ui_text(d, &text, x, y, 20, Color::GRAY.fade(fade));
+            let middle = y + size / 2;
+            d.draw_line_ex(
+                Vector2::new((x - 2) as f32, middle as f32),
+                Vector2::new((x + width + 2) as f32, middle as f32),
+                2.0,
+                Color::WHITE.fade(fade),
+            );
+        } else {
+            draw_ui_text(d, &text, x, y, 20, warning.color);
+        }
+    }
+}
+
+/// The whole board lit up and fading over `PERFECT_CLEAR_FLASH_DURATION` after a perfect
+/// clear, `elapsed` ago, with a banner across it while it's bright. `cell_size` scales it
+/// down for mini boards.
+pub fn draw_perfect_clear_flash(
+    d: &mut impl Canvas,
+    board_x: i32,
+    board_y: i32,
+    cell_size: i32,
+    elapsed: Duration,
+) {
+    let left = 1.0 - elapsed.as_secs_f32() / PERFECT_CLEAR_FLASH_DURATION.as_secs_f32();
+    if left <= 0.0 {
+        return;
+    }
+    let (width, height) = (BOARD_WIDTH as i32 * cell_size, BOARD_HEIGHT as i32 * cell_size);
+    d.draw_rectangle(board_x, board_y, width, height, Color::GOLD.fade(0.6 * left));
+    let size = (cell_size * 4 / 3).max(10);
+    let y = board_y + height / 3;
+    let text = tr("perfect_clear");
+    draw_text_centered(d, text, board_x + width / 2, y, size, Color::WHITE.fade(left.sqrt()));
+}
+
+pub fn draw_chain_popup(d: &mut impl Canvas, chain: u32) {
+    let text = tr_args("chain", &[("count", &chain)]);
+    let center = layout().board_x + BOARD_PIXEL_WIDTH / 2;
+    let y = BOARD_OFFSET_Y + BOARD_PIXEL_HEIGHT / 3;
+    draw_text_centered(d, &text, center, y, 40, Color::YELLOW);
+}
+
+/// The garbage a clear was worth, under where a chain shows.
+pub fn draw_attack_popup(d: &mut impl Canvas, lines: u32) {
+    let text = tr_args("attack_sent", &[("count", &lines)]);
+    let center = layout().board_x + BOARD_PIXEL_WIDTH / 2;
+    let y = BOARD_OFFSET_Y + BOARD_PIXEL_HEIGHT / 3 + layout().line_height(40);
+    draw_text_centered(d, &text, center, y, 30, Color::ORANGE);
+}
+
+// How opaque the hottest cells of the placement heatmap are
+const HEATMAP_ALPHA: u8 = 180;
+// Height of the tallest bar in the column chart under the heatmap
+const COLUMN_CHART_HEIGHT: i32 = 50;
+
+/// The heatmap color of a cell covered `count` times when the busiest was covered `max`
+/// times: clear when never covered, then from blue for rarely up to red for the busiest.
+pub fn heat_color(count: u32, max: u32) -> Color {
+    if count == 0 || max == 0 {
+        return Color::new(0, 0, 0, 0);
+    }
+    let heat = count.min(max) as f32 / max as f32;
+    let red = (255.0 * heat) as u8;
+    Color::new(red, 0, 255 - red, HEATMAP_ALPHA)
+}
+
+/// Where a game's pieces locked, as heat over the board, with how much each column was
+/// used charted underneath.
+pub fn draw_heatmap(d: &mut impl Canvas, heatmap: &Heatmap, offset_x: i32, offset_y: i32) {
+    let max = heatmap.max();
+    for y in 0..BOARD_HEIGHT {
+        for x in 0..BOARD_WIDTH {
+            let color = heat_color(heatmap.count(x, y), max);
+            let cell_x = offset_x + x as i32 * CELL_SIZE;
+            d.draw_rectangle(cell_x, offset_y + y as i32 * CELL_SIZE, CELL_SIZE, CELL_SIZE, color);
+        }
+    }
+
+    let columns = heatmap.columns();
+    let busiest = columns.iter().copied().max().unwrap_or(0);
+    let bottom = offset_y + BOARD_PIXEL_HEIGHT + 10 + COLUMN_CHART_HEIGHT;
+    for (x, &count) in columns.iter().enumerate() {
+        let height = (COLUMN_CHART_HEIGHT as u32 * count).checked_div(busiest).unwrap_or(0) as i32;
+        let bar_x = offset_x + x as i32 * CELL_SIZE + 2;
+        let color = heat_color(count, busiest);
+        d.draw_rectangle(bar_x, bottom - height, CELL_SIZE - 4, height, color);
+    }
+    draw_ui_text(d, tr("heatmap_hide"), offset_x, bottom + 8, 12, Color::GRAY);
+}
+
+/// Tells a finished game how to see its heatmap, under the board.
+pub fn draw_heatmap_hint(d: &mut impl Canvas, offset_x: i32, offset_y: i32) {
+    let y = offset_y + BOARD_PIXEL_HEIGHT + 10;
+    draw_ui_text(d, tr("heatmap_show"), offset_x, y, 12, Color::GRAY);
+}
+
+/// Fills a bar under the board as the restart key is held, `progress` from 0 to 1.
+pub fn draw_restart_hold(d: &mut impl Canvas, progress: f32) {
+    let x = layout().board_x;
+    let y = BOARD_OFFSET_Y + BOARD_PIXEL_HEIGHT + 8;
+    let filled = (BOARD_PIXEL_WIDTH as f32 * progress) as i32;
+    d.draw_rectangle_lines(x, y, BOARD_PIXEL_WIDTH, 6, Color::GRAY);
+    d.draw_rectangle(x, y, filled, 6, Color::ORANGE);
+    draw_ui_text(d, tr("restarting"), x, y + 10, 12, Color::ORANGE);
+}
+
+/// Pause screen text: the title, the keys that work from here, how long a multiplayer
+/// pause has left and a restart prompt.
+pub fn draw_pause_overlay(
+    d: &mut impl Canvas,
+    actions: &str,
+    time_left: Option<&str>,
+    confirm_restart: bool,
+    idle: bool,
+) {
+    let title = if idle { tr("paused_idle") } else { tr("paused") };
+    let mut lines = vec![(title, 30, Color::WHITE), (actions, 20, Color::WHITE)];
+    if let Some(time_left) = time_left {
+        lines.push((time_left, 20, Color::WHITE));
+    }
+    if confirm_restart {
+        lines.push((tr("restart_confirm"), 20, Color::YELLOW));
+    }
+    draw_overlay_text(d, &lines);
+}
+
+/// Covers the board, the next piece and `hold_slots` hold boxes, so a pause can't be used
+/// to plan. The score and the rest of the panels stay in view.
+pub fn hide_playfield(d: &mut impl Canvas, board_x: i32, hold_slots: usize) {
+    let layout = layout();
+    let background = theme().background;
+    d.draw_rectangle(board_x, BOARD_OFFSET_Y, BOARD_PIXEL_WIDTH, BOARD_PIXEL_HEIGHT, background);
+    let (box_width, box_height) = (
+        PREVIEW_BOX_WIDTH * PREVIEW_CELL_SIZE,
+        PREVIEW_BOX_HEIGHT * PREVIEW_CELL_SIZE,
+    );
+    d.draw_rectangle(layout.panel_x, layout.next_y, box_width, box_height, background);
+    if hold_slots > 0 {
+        let width = hold_slots as i32 * (box_width + HOLD_BOX_GAP);
+        let y = layout.hold_y + layout.line_height(20);
+        d.draw_rectangle(20, y, width, box_height, background);
+    }
+}
+
+/// The seconds left before a pause ends, large over the board, which shows again so the
+/// player can find their place before the piece moves.
+pub fn draw_resume_countdown(d: &mut impl Canvas, seconds: u64) {
+    let center = layout().board_x + BOARD_PIXEL_WIDTH / 2;
+    let y = BOARD_OFFSET_Y + BOARD_PIXEL_HEIGHT / 2 - 40;
+    draw_text_centered(d, &seconds.to_string(), center, y, 80, Color::WHITE);
+    draw_text_centered(d, tr("resuming"), center, y + 100, 20, Color::LIGHTGRAY);
+}
+
+// Rows of a menu-style list: where they start, how tall each is, and how many fit above
+// the hint
+fn choice_rows() -> (i32, i32, usize) {
+    let layout = layout();
+    let items_y = 120 + layout.line_height(40) + 50;
+    let item_height = layout.line_height(25).max(40);
+    let fit = ((choice_hint_y() - 20 - items_y) / item_height).max(1) as usize;
+    (items_y, item_height, fit)
+}
+
+fn choice_hint_y() -> i32 {
+    WINDOW_HEIGHT - 60 - layout().scaled(20)
+}
+
+// A title over `rows` of (text, selected), with a hint line at the bottom
+fn draw_choices(
+    d: &mut impl Canvas,
+    title: &str,
+    rows: impl Iterator<Item = (String, bool)>,
+    hint: &str,
+    hint_color: Color,
+) {
+    let center = layout().window_width / 2;
+    draw_text_centered(d, title, center, 120, 40, Color::WHITE);
+    let (items_y, item_height, _) = choice_rows();
+    for (row, (name, selected)) in rows.enumerate() {
+        let text = if selected { format!("> {} <", name) } else { name };
+        let color = if selected { Color::YELLOW } else { Color::WHITE };
+        draw_text_centered(d, &text, center, items_y + row as i32 * item_height, 25, color);
+    }
+    draw_text_centered(d, hint, center, choice_hint_y(), 20, hint_color);
+}
+
+/// The server browser: each room with its player count, whether a match is on and whether
+/// it's locked, then creating a room and going back. The selected room's rules go above
+/// the hint, and a prompt for a room name or password covers the list while it's open.
+pub fn draw_server_browser(d: &mut impl Canvas, browser: &ServerBrowser) {
+    let layout = layout();
+    let center = layout.window_width / 2;
+    draw_text_centered(d, tr("browser_title"), center, 120, 40, Color::WHITE);
+    let (items_y, item_height, fit) = choice_rows();
+    let (status, status_color) = match &browser.listing {
+        RoomListing::Loading => (tr("browser_loading").to_string(), Color::GRAY),
+        RoomListing::Failed(error) => (tr_args("browser_failed", &[("error", error)]), Color::RED),
+        RoomListing::Loaded if browser.rooms().is_empty() => {
+            (tr("browser_empty").to_string(), Color::GRAY)
+        }
+        RoomListing::Loaded => (String::new(), Color::GRAY),
+    };
+    draw_text_centered(d, &status, center, items_y - layout.line_height(20), 20, status_color);
+
+    let rows = browser.rows();
+    let selected = browser.selected();
+    for (line, index) in browser.visible_rows(fit.saturating_sub(1)).enumerate() {
+        let row = rows[index];
+        let (name, full) = match row {
+            BrowserRow::Room(room) => browser_room_line(&browser.rooms()[room]),
+            BrowserRow::CreateRoom => (tr("browser_create").to_string(), false),
+            BrowserRow::Back => (tr("browser_back").to_string(), false),
+        };
+        let (text, color) = match (row == selected, full) {
+            (true, _) => (format!("> {} <", name), Color::YELLOW),
+            (false, true) => (name, Color::GRAY),
+            (false, false) => (name, Color::WHITE),
+        };
+        draw_text_centered(d, &text, center, items_y + line as i32 * item_height, 25, color);
+    }
+    if let BrowserRow::Room(room) = selected {
+        let rules = format_room_rules(&browser.rooms()[room].rules);
+        let y = choice_hint_y() - layout.line_height(20);
+        draw_text_centered(d, &rules, center, y, 20, Color::LIGHTGRAY);
+    }
+
+    let hint = match &browser.prompt {
+        Some((prompt, input)) => {
+            let (label, text) = match prompt {
+                Prompt::RoomName => (tr("browser_room_name").to_string(), input.text().to_string()),
+                Prompt::Password { room } => {
+                    let label = tr_args("browser_password", &[("room", room)]);
+                    (label, "*".repeat(input.text().chars().count()))
+                }
+            };
+            let (width, height) = (layout.scaled(400), layout.line_height(20) * 2 + 20);
+            let (x, y) = (center - width / 2, items_y + item_height);
+            d.draw_rectangle(x, y, width, height, Color::BLACK.fade(0.9));
+            d.draw_rectangle_lines(x, y, width, height, Color::WHITE);
+            draw_text_centered(d, &label, center, y + 10, 20, Color::WHITE);
+            let text = format!("{}_", text);
+            let text_y = y + 10 + layout.line_height(20);
+            draw_text_centered(d, &text, center, text_y, 20, Color::YELLOW);
+            tr("browser_prompt_hint")
+        }
+        None => tr("browser_hint"),
+    };
+    draw_text_centered(d, hint, center, choice_hint_y(), 20, Color::GRAY);
+}
+
+/// The browser row under `y` in the scene, as an index into `ServerBrowser::rows`.
+pub fn browser_row_at(browser: &ServerBrowser, y: f32) -> Option<usize> {
+    let (items_y, item_height, fit) = choice_rows();
+    let line = (y - items_y as f32) / item_height as f32;
+    let visible = browser.visible_rows(fit.saturating_sub(1));
+    let index = visible.start + line.floor() as usize;
+    (line >= 0.0 && visible.contains(&index)).then_some(index)
+}
+
+// A room as "den  3/8  in a match  locked", and whether it's full
+fn browser_room_line(room: &RoomInfo) -> (String, bool) {
+    let mut line = match room.limit {
+        Some(limit) => format!("{}  {}/{}", room.name, room.players, limit),
+        None => format!("{}  {}", room.name, room.players),
+    };
+    if room.in_match {
+        line += &format!("  {}", tr("browser_in_match"));
+    }
+    if room.locked {
+        line += &format!("  {}", tr("browser_locked"));
+    }
+    (line, room.limit.is_some_and(|limit| room.players >= limit))
+}
+
+pub fn draw_menu(d: &mut impl Canvas, menu: &Menu) {
+    // Items past the hint scroll, keeping the selection in view
+    let (_, _, fit) = choice_rows();
+    let rows = menu.visible_items(fit).map(|i| {
+        let item = MenuItem::ALL[i];
+        (item.name().to_string(), item == menu.selected())
+    });
+    draw_choices(d, tr("menu_title"), rows, tr("menu_hint"), Color::GRAY);
+}
+
+/// The single-player modes with their options, and the selected one's description and
+/// personal best under the list.
+pub fn draw_mode_select(d: &mut impl Canvas, menu: &Menu, high_scores: &HighScores) {
+    // The last two rows' worth of space hold the description and the record
+    let (_, item_height, fit) = choice_rows();
+    let selected = menu.selected_mode();
+    let rows = menu.visible_modes(fit.saturating_sub(2)).map(|i| {
+        let mode = &MODES[i];
+        let name = match (mode.option, menu.option(mode.mode)) {
+            (Some(option), Some(value)) => {
+                let option = tr_args(option.label(), &[("value", &value)]);
+                tr_args("menu_mode_option", &[("name", &mode.name()), ("option", &option)])
+            }
+            _ => mode.name().to_string(),
+        };
+        (name, mode.mode == selected.mode)
+    });
+    draw_choices(d, tr("mode_select_title"), rows, tr("mode_select_hint"), Color::GRAY);
+
+    let best = match selected.mode {
+        GameMode::Sprint => {
+            high_scores.sprint_best.as_ref().and_then(BestSplits::total).map(format_time)
+        }
+        mode => high_scores.best_scores.get(&mode).map(|points| points.to_string()),
+    };
+    let record = match best {
+        Some(best) => tr_args("mode_best", &[("best", &best)]),
+        None => tr("mode_no_best").to_string(),
+    };
+    let center = layout().window_width / 2;
+    let y = choice_hint_y() - 2 * item_height;
+    draw_text_centered(d, selected.description(), center, y, 20, Color::LIGHTGRAY);
+    draw_text_centered(d, &record, center, y + item_height, 20, Color::GOLD);
+}
+
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::MoveLeft => tr("action_move_left"),
+        Action::MoveRight => tr("action_move_right"),
+        Action::SoftDrop | Action::SonicDrop => tr("action_soft_drop"),
+        Action::RotateCw => tr("action_rotate"),
+        Action::HardDrop => tr("action_hard_drop"),
+        Action::Hold => tr("action_hold"),
+    }
+}
+
+fn randomizer_name(kind: RandomizerKind) -> &'static str {
+    match kind {
+        RandomizerKind::Classic => tr("randomizer_classic"),
+        RandomizerKind::SevenBag => tr("randomizer_seven_bag"),
+        RandomizerKind::History4 => tr("randomizer_history4"),
+    }
+}
+
+pub fn draw_settings(d: &mut impl Canvas, settings: &SettingsMenu, config: &Config) {
+    let (_, _, fit) = choice_rows();
+    let rows = settings.visible_items(fit).map(|i| {
+        let item = SettingsItem::ALL[i];
+        let name = match item {
+            SettingsItem::UiScale => {
+                tr_args("settings_ui_scale", &[("scale", &config.ui_scale)])
+            }
+            SettingsItem::Rumble if config.rumble => tr("settings_rumble_on").to_string(),
+            SettingsItem::Rumble => tr("settings_rumble_off").to_string(),
+            SettingsItem::Randomizer => {
+                let name = randomizer_name(config.randomizer);
+                tr_args("settings_randomizer", &[("name", &name)])
+            }
+            SettingsItem::ConnectedPieces if config.connected_pieces => {
+                tr("settings_connected_on").to_string()
+            }
+            SettingsItem::ConnectedPieces => tr("settings_connected_off").to_string(),
+            SettingsItem::TranslucentPause if config.translucent_pause => {
+                tr("settings_pause_translucent").to_string()
+            }
+            SettingsItem::TranslucentPause => tr("settings_pause_hidden").to_string(),
+            SettingsItem::ReduceMotion if config.reduce_motion => {
+                tr("settings_motion_reduced").to_string()
+            }
+            SettingsItem::ReduceMotion => tr("settings_motion_full").to_string(),
+            SettingsItem::DroughtCounter if config.drought_counter => {
+                tr("settings_drought_on").to_string()
+            }
+            SettingsItem::DroughtCounter => tr("settings_drought_off").to_string(),
+            SettingsItem::Bind(action) => {
+                let bound = match settings.rebind {
+                    Some(Rebind::Listening(listening)) if listening == action => {
+                        tr("settings_press_key").to_string()
+                    }
+                    _ => key_list(config.keys.keys(action)),
+                };
+                format!("{}: {}", action_name(action), bound)
+            }
+            SettingsItem::ResetKeys => tr("settings_reset_keys").to_string(),
+            SettingsItem::Back => tr("settings_back").to_string(),
+        };
+        (name, item == settings.selected())
+    });
+    let (hint, hint_color) = match settings.rebind {
+        None => (tr("settings_hint").to_string(), Color::GRAY),
+        Some(Rebind::Listening(_)) => (tr("settings_listening_hint").to_string(), Color::GRAY),
+        Some(Rebind::Conflict { key, other, .. }) => {
+            let key = key_name(key).unwrap_or("?");
+            let action = action_name(other);
+            let text = tr_args("settings_conflict", &[("key", &key), ("action", &action)]);
+            (text, Color::ORANGE)
+        }
+    };
+    draw_choices(d, tr("settings_title"), rows, &hint, hint_color);
+}
+
+// Key names as a comma separated list, e.g. "LeftShift, C"
+fn key_list(keys: &[KeyboardKey]) -> String {
+    let names: Vec<&str> = keys.iter().map(|&key| key_name(key).unwrap_or("?")).collect();
+    names.join(", ")
+}
+
+// Notices along the top of the window, one line each
+fn notice_y(line: i32) -> i32 {
+    15 + layout().line_height(20) * line
+}
+
+/// Says the connection went, and whether we're still trying to get it back.
+pub fn draw_connection_lost(d: &mut impl Canvas, reconnecting: bool) {
+    let center = layout().window_width / 2;
+    let text = tr(if reconnecting { "connection_reconnecting" } else { "connection_lost" });
+    draw_text_centered(d, text, center, notice_y(0), 20, Color::RED);
+}
+
+pub fn draw_server_message(d: &mut impl Canvas, text: &str) {
+    let text = tr_args("server_message", &[("text", &text)]);
+    draw_text_centered(d, &text, layout().window_width / 2, notice_y(1), 20, Color::YELLOW);
+}
+
+/// Quick match banner: where we are in the queue and how long we've waited.
+pub fn draw_queue_status(d: &mut impl Canvas, position: Option<usize>, waited: Duration) {
+    let seconds = waited.as_secs();
+    let place = position.map_or_else(|| tr("queue_joining").to_string(), |position| {
+        format!("#{}", position)
+    });
+    let time = format!("{}:{:02}", seconds / 60, seconds % 60);
+    let text = tr_args("queue_status", &[("place", &place), ("time", &time)]);
+    draw_text_centered(d, &text, layout().window_width / 2, notice_y(2), 20, Color::SKYBLUE);
+}
+
+/// A recorded match being played back: every player's board side by side, named and
+/// scored, with the winner once it's over.
+pub fn draw_replay(d: &mut impl Canvas, playback: &Playback, paused: bool) {
+    let layout = layout();
+    let center = layout.window_width / 2;
+    let length = format_time(playback.replay.duration());
+    let title = tr_args(
+        "replay_title",
+        &[("time", &format_time(playback.elapsed)), ("length", &length)],
+    );
+    draw_text_centered(d, &title, center, 20, 20, Color::WHITE);
+    let mut notice_y = 20 + layout.line_height(20);
+    if paused {
+        draw_text_centered(d, tr("replay_paused"), center, notice_y, 20, Color::YELLOW);
+        notice_y += layout.line_height(20);
+    }
+    let winner = playback.replay.result.as_ref().and_then(|result| {
+        result.placements.iter().find(|placement| placement.place == Some(1))
+    });
+    if let Some(winner) = winner.filter(|_| playback.is_finished()) {
+        let name = scoreboard_name(&winner.player_id, winner.name.as_deref());
+        let text = tr_args("replay_winner", &[("name", &name)]);
+        draw_text_centered(d, &text, center, notice_y, 20, Color::GOLD);
+    }
+
+    let footer_y = draw_footer(d, tr("replay_keys"));
+    let top = 20 + layout.line_height(20) * 3;
+    let panel = Rect {
+        x: 20,
+        y: top,
+        width: layout.window_width - 40,
+        height: footer_y - 20 - top,
+    };
+    let players = &playback.replay.players;
+    let slots = mini_board_grid(players.len(), panel, layout.label_height() * 2);
+    let empty = RemoteBoard::default();
+    for (index, (player, rect)) in players.iter().zip(&slots).enumerate() {
+        let remote = playback.boards.get(&player.player_id).unwrap_or(&empty);
+        let cell_size = rect.width / BOARD_WIDTH as i32;
+        let accent = accent_color(Some(index as u32), None);
+        let name = scoreboard_name(&player.player_id, player.name.as_deref());
+        let score = playback.scores.get(&player.player_id).copied().unwrap_or(0);
+        let label_y = rect.y - layout.label_height() * 2;
+        draw_ui_text(d, &name, rect.x, label_y, 10, accent);
+        draw_ui_text(d, &score.to_string(), rect.x, label_y + layout.label_height(), 10, accent);
+        let falling = remote.current.map(RemotePiece::still);
+        draw_mini_board(d, &remote.board, rect.x, rect.y, cell_size, falling, accent);
+        if playback.out.contains(&player.player_id) {
+            d.draw_rectangle(rect.x, rect.y, rect.width, rect.height, Color::new(0, 0, 0, 160));
+            let out_y = rect.y + rect.height / 2 - 5;
+            draw_text_centered(d, tr("out"), rect.x + rect.width / 2, out_y, 10, Color::RED);
+        }
+    }
+}
+
+/// A room's rules in a line, e.g. "Rules: 500 ms lock delay, 30% messy garbage, hold".
+pub fn format_room_rules(rules: &RoomRules) -> String {
+    let messiness = (rules.garbage.messiness * 100.0).round() as u32;
+    let hold = if rules.hold { tr("rules_hold") } else { tr("rules_no_hold") };
+    tr_args(
+        "room_rules",
+        &[
+            ("lock", &rules.lock_delay_ms),
+            ("messiness", &messiness),
+            ("hold", &hold),
+            ("pc", &rules.perfect_clear_attack),
+            ("delay", &rules.garbage_delay_pieces),
+        ],
+    )
+}
+
+/// Explains why the server refused to create or join a room.
+pub fn draw_room_error(d: &mut impl Canvas, code: ErrorCode) {
+    let text = code.to_string();
+    draw_text_centered(d, &text, layout().window_width / 2, notice_y(0), 20, Color::RED);
+}
+
+// Bottom line of the window, for toasts and the debug readout
+fn bottom_line_y() -> i32 {
+    WINDOW_HEIGHT - 15 - layout().scaled(20)
+}
+
+/// Short confirmation along the bottom of the window, such as where a file was saved.
+pub fn draw_toast(d: &mut impl Canvas, text: &str) {
+    draw_text_centered(d, text, layout().window_width / 2, bottom_line_y(), 20, Color::WHITE);
+}
+
+/// Debug readout of the game speed, bottom left.
+pub fn draw_game_speed(d: &mut impl Canvas, speed: &GameSpeed) {
+    let (text, color) = if speed.frozen {
+        ("FROZEN  .: step".to_string(), Color::ORANGE)
+    } else if speed.is_scaled() {
+        (format!("SPEED {}x", speed.scale), Color::ORANGE)
+    } else {
+        ("SPEED 1x".to_string(), Color::GRAY)
+    };
+    draw_ui_text(d, &text, 20, bottom_line_y(), 20, color);
+}
+
+/// Debug readout of the board's draw calls this frame, and what they'd be drawn unbatched.
+pub fn draw_board_draw_calls(d: &mut impl Canvas, calls: u32, unbatched: u32) {
+    let text = format!("BOARD DRAWS {} (UNBATCHED {})", calls, unbatched);
+    let y = bottom_line_y() - layout().line_height(20);
+    draw_ui_text(d, &text, 20, y, 20, Color::GRAY);
+}
+
+/// How many boards from other players were malformed, above the draw call count.
+pub fn draw_rejected_boards(d: &mut impl Canvas, rejected: u32) {
+    let text = format!("REJECTED BOARDS {}", rejected);
+    let y = bottom_line_y() - layout().line_height(20) * 2;
+    draw_ui_text(d, &text, 20, y, 20, Color::GRAY);
+}
+
+/// The connection's message counts, above the rejected boards.
+pub fn draw_net_stats(d: &mut impl Canvas, stats: &NetStats) {
+    let last = stats
+        .since_received
+        .map_or("-".to_string(), |since| format!("{:.1}s", since.as_secs_f32()));
+    let text = format!(
+        "NET OUT {:.0}/s IN {:.0}/s DROPPED {} INVALID {} LAST {}",
+        stats.sent_per_sec, stats.received_per_sec, stats.dropped, stats.invalid, last
+    );
+    let y = bottom_line_y() - layout().line_height(20) * 3;
+    draw_ui_text(d, &text, 20, y, 20, Color::GRAY);
+}
+
+/// Which randomizer is dealing and what it will deal from, with this session's spawn counts
+/// and how far they are from even, above the connection's message counts.
+pub fn draw_randomizer_debug(
+    d: &mut impl Canvas,
+    kind: RandomizerKind,
+    debug: &RandomizerDebug,
+    stats: &SessionStats,
+) {
+    let letters =
+        |kinds: &[BlockKind]| kinds.iter().map(|&kind| char::from(kind)).collect::<String>();
+    let mut text = format!("RNG {}", kind.name().to_uppercase());
+    if let Some(bag) = &debug.bag {
+        text += &format!(" BAG {}", letters(bag));
+    }
+    if let Some(history) = &debug.history {
+        text += &format!(" HISTORY {}", letters(history));
+    }
+    text += &format!(" DROUGHT {}", stats.drought());
+    let line = layout().line_height(20);
+    draw_ui_text(d, &text, 20, bottom_line_y() - line * 5, 20, Color::GRAY);
+
+    let counts: Vec<String> = stats
+        .spawns()
+        .map(|(kind, count)| format!("{}{}", char::from(kind), count))
+        .collect();
+    let mut text = format!("SPAWNS {}", counts.join(" "));
+    let mut color = Color::GRAY;
+    if let Some(chi_squared) = stats.chi_squared() {
+        text += &format!(" CHI2 {:.1}", chi_squared);
+        if chi_squared > CHI_SQUARED_SIGNIFICANT {
+            color = Color::ORANGE;
+        }
+    }
+    draw_ui_text(d, &text, 20, bottom_line_y() - line * 4, 20, color);
+}
+
+/// Pieces since the last I piece, right of the next piece label. Turns orange once the
+/// wait gets long.
+pub fn draw_drought_counter(d: &mut impl Canvas, drought: u32, x: i32, y: i32) {
+    let color = if drought >= LONG_DROUGHT { Color::ORANGE } else { Color::GRAY };
+    let text = tr_args("drought", &[("count", &drought)]);
+    let width = ui_text_width(d, tr("next"), 20);
+    draw_ui_text(d, &text, x + width + layout().scaled(10), y, 20, color);
+}
+
+/// A dot in the top right corner showing whether we're still connected to the server.
+pub fn draw_connection_status(d: &mut impl Canvas, status: ConnectionStatus) {
+    let color = match status {
+        ConnectionStatus::Connected => Color::GREEN,
+        ConnectionStatus::Reconnecting => Color::YELLOW,
+        ConnectionStatus::Offline => Color::RED,
+    };
+    let layout = layout();
+    let radius = layout.scaled(CONNECTION_DOT_RADIUS);
+    let center = layout.window_width - BOARD_OFFSET_Y / 2;
+    d.draw_circle(center, BOARD_OFFSET_Y / 2, radius as f32, color);
+}
+
+pub fn draw_pause_denied(d: &mut impl Canvas) {
+    let center = layout().board_x + BOARD_PIXEL_WIDTH / 2;
+    let y = BOARD_OFFSET_Y + BOARD_PIXEL_HEIGHT / 2;
+    draw_text_centered(d, tr("pause_denied"), center, y, 20, Color::RED);
+}
+
+pub fn draw_mission_hud(d: &mut impl Canvas, run: &MissionRun) {
+    let mission = run.mission();
+    let progress = &run.progress;
+    let counter = tr_args(
+        "mission_counter",
+        &[("current", &(run.current + 1)), ("total", &run.missions.len())],
+    );
+    let objective = mission.objective.to_string();
+    let count = tr_args(
+        "progress_value",
+        &[("count", &progress.count), ("target", &progress.objective.target())],
+    );
+    let pieces = match progress.piece_limit {
+        Some(limit) => tr_args("pieces_limit", &[("count", &progress.pieces), ("limit", &limit)]),
+        None => tr_args("pieces_value", &[("count", &progress.pieces)]),
+    };
+    draw_text_lines(
+        d,
+        20,
+        layout().hud_y,
+        false,
+        &[
+            (&counter, 20, Color::YELLOW),
+            (&mission.title, 20, Color::WHITE),
+            (&objective, 10, Color::WHITE),
+            (&count, 20, Color::WHITE),
+            (&pieces, 20, Color::WHITE),
+            (tr("mission_keys"), 20, Color::GRAY),
+        ],
+    );
+}
+
+/// Tutorial step counter beside the board, the step's instruction over the top of the
+/// board, and a checkmark that draws itself in once the step is done.
+pub fn draw_tutorial_hud(d: &mut impl Canvas, tutorial: &Tutorial, now: Instant) {
+    let Some(step) = tutorial.step() else {
+        return;
+    };
+    let layout = layout();
+    let counter = tr_args(
+        "tutorial_counter",
+        &[("current", &(tutorial.current + 1)), ("total", &tutorial.len())],
+    );
+    draw_text_lines(
+        d,
+        20,
+        layout.hud_y,
+        false,
+        &[(&counter, 20, Color::YELLOW), (tr("tutorial_keys"), 20, Color::GRAY)],
+    );
+
+    // Centered on the board, and wider than it if the scaled text needs the room
+    let board_center = layout.board_x + BOARD_PIXEL_WIDTH / 2;
+    let lines: Vec<(&str, i32, Color)> =
+        tr(step.text).lines().map(|line| (line, 20, Color::WHITE)).collect();
+    let text_width = lines.iter().map(|(line, ..)| ui_text_width(d, line, 20)).max();
+    let banner_width = text_width.unwrap_or(0).max(BOARD_PIXEL_WIDTH - 16) + 16;
+    let banner_height = lines.len() as i32 * layout.line_height(20) + 12;
+    d.draw_rectangle(
+        board_center - banner_width / 2,
+        BOARD_OFFSET_Y + 40,
+        banner_width,
+        banner_height,
+        Color::new(0, 0, 0, 170),
+    );
+    draw_text_lines(d, board_center, BOARD_OFFSET_Y + 48, true, &lines);
+
+    if let Some(progress) = tutorial.checkmark(now) {
+        // The short stroke takes the first third, the long one the rest
+        let center_x = board_center as f32;
+        let center_y = (BOARD_OFFSET_Y + BOARD_PIXEL_HEIGHT / 2) as f32;
+        let start = Vector2::new(center_x - 40.0, center_y);
+        let corner = Vector2::new(center_x - 10.0, center_y + 30.0);
+        let end = Vector2::new(center_x + 45.0, center_y - 35.0);
+        let first = (progress * 3.0).min(1.0);
+        let second = ((progress * 3.0 - 1.0) / 2.0).clamp(0.0, 1.0);
+        d.draw_line_ex(start, start.lerp(corner, first), 10.0, Color::GREEN);
+        if second > 0.0 {
+            d.draw_line_ex(corner, corner.lerp(end, second), 10.0, Color::GREEN);
+        }
+    }
+}
+
+pub fn draw_tutorial_complete(d: &mut impl Canvas) {
+    draw_game_over(d, tr("tutorial_complete"), tr("tutorial_complete_keys"));
+}
+
+/// Arrow beside the board pointing at the row a mission wants cleared.
+/// Flashes rows that are about to be cleared.
+pub fn draw_clearing_rows(d: &mut impl Canvas, rows: &[usize], offset_x: i32, offset_y: i32) {
+    for &row in rows {
+        d.draw_rectangle(
+            offset_x,
+            offset_y + row as i32 * CELL_SIZE,
+            BOARD_WIDTH as i32 * CELL_SIZE,
+            CELL_SIZE,
+            Color::new(255, 255, 255, 160),
+        );
+    }
+}
+
+pub fn draw_row_marker(d: &mut impl Canvas, row: usize, offset_x: i32, offset_y: i32) {
+    let y = offset_y + row as i32 * CELL_SIZE + CELL_SIZE / 2;
+    d.draw_triangle(
+        Vector2::new((offset_x - 16) as f32, (y - 8) as f32),
+        Vector2::new((offset_x - 16) as f32, (y + 8) as f32),
+        Vector2::new((offset_x - 4) as f32, y as f32),
+        Color::YELLOW,
+    );
+}
+
+pub fn draw_mission_results(d: &mut impl Canvas, run: &MissionRun) {
+    let layout = layout();
+    let center = layout.window_width / 2;
+    let total = tr_args(
+        "missions_total",
+        &[("done", &run.completed()), ("total", &run.missions.len())],
+    );
+    draw_text_centered(d, &total, center, 120, 30, Color::WHITE);
+    let list_y = 120 + layout.line_height(30) + 20;
+    let row_height = layout.line_height(20);
+    let fit = ((draw_footer(d, tr("play_again")) - list_y) / row_height).max(0) as usize;
+    let rows = run.missions.iter().zip(&run.results).take(fit);
+    for (index, (mission, status)) in rows.enumerate() {
+        let (label, color) = match status {
+            MissionStatus::Complete => (tr("mission_clear"), Color::GREEN),
+            MissionStatus::Failed => (tr("mission_failed_short"), Color::RED),
+            MissionStatus::Skipped | MissionStatus::Active => (tr("mission_skipped"), Color::GRAY),
+        };
+        let y = list_y + index as i32 * row_height;
+        draw_ui_text(d, &mission.title, center - layout.scaled(200), y, 20, Color::WHITE);
+        draw_ui_text(d, label, center + layout.scaled(120), y, 20, color);
+    }
+}
+
+/// Placements at the end of a multiplayer match, with our own row highlighted, then our
+/// record this session and lifetime records against named opponents.
+pub fn draw_match_results(
+    d: &mut impl Canvas,
+    result: &MatchResult,
+    own_id: Option<&str>,
+    session: &SessionTally,
+    rivals: &BTreeMap<String, HeadToHead>,
+    rules: &RoomRules,
+) {
+    let layout = layout();
+    let center = layout.window_width / 2;
+    let left = center - layout.scaled(220);
+    let right = center + layout.scaled(220);
+    let name_x = left + layout.scaled(40);
+    let rating_right = right - layout.scaled(185);
+    let score_right = right - layout.scaled(120);
+    let sent_right = right - layout.scaled(55);
+    draw_text_centered(d, tr("match_results"), center, 120, 30, Color::WHITE);
+
+    let header_y = 120 + layout.line_height(30) + 20;
+    draw_ui_text(d, "#", left, header_y, 12, Color::GRAY);
+    draw_ui_text(d, tr("column_name"), name_x, header_y, 12, Color::GRAY);
+    // Only ranked matches change ratings
+    if result.placements.iter().any(|placement| placement.rating_change.is_some()) {
+        draw_text_right(d, tr("column_rating"), rating_right, header_y, 12, Color::GRAY);
+    }
+    draw_text_right(d, tr("column_score"), score_right, header_y, 12, Color::GRAY);
+    draw_text_right(d, tr("column_sent"), sent_right, header_y, 12, Color::GRAY);
+    draw_text_right(d, tr("column_received"), right, header_y, 12, Color::GRAY);
+
+    let footer_y = draw_footer(d, tr("rematch"));
+    let time_y = footer_y - layout.line_height(20) - 20;
+    let match_time = tr_args("match_time", &[("time", &format_time(result.duration()))]);
+    draw_ui_text(d, &match_time, left, time_y, 20, Color::WHITE);
+    // The rematch is played by the same rules, unless the room's owner changes them
+    let rules_y = time_y - layout.line_height(15);
+    draw_ui_text(d, &format_room_rules(rules), left, rules_y, 15, Color::GRAY);
+
+    // Records stack up from the match time
+    let mut records = Vec::new();
+    if session.matches > 0 {
+        records.push(tr_args(
+            "session_record",
+            &[("wins", &session.wins), ("matches", &session.matches)],
+        ));
+    }
+    let opponents = own_id.map_or_else(Vec::new, |own_id| head_to_head(result, own_id));
+    let lifetime = opponents.iter().filter_map(|(placement, _)| {
+        let name = placement.name.as_ref()?;
+        let record = rivals.get(name)?;
+        let text = record_text(*record);
+        Some(tr_args("lifetime_record", &[("name", name), ("record", &text)]))
+    });
+    records.extend(lifetime.take(MAX_RIVAL_LINES));
+    let mut records_y = rules_y;
+    for text in &records {
+        records_y -= layout.line_height(20);
+        draw_ui_text(d, text, left, records_y, 20, Color::SKYBLUE);
+    }
+
+    let rows_y = header_y + layout.line_height(12);
+    let row_height = layout.line_height(20);
+    let fit = ((records_y - 10 - rows_y) / row_height).max(0) as usize;
+    for (index, placement) in result.placements.iter().take(fit).enumerate() {
+        let y = rows_y + index as i32 * row_height;
+        let is_local = Some(placement.player_id.as_str()) == own_id;
+        let color = if is_local { Color::YELLOW } else { Color::WHITE };
+        let (place, place_color) = match placement.place {
+            Some(place) => (place.to_string(), color),
+            None => (tr("did_not_finish").to_string(), Color::GRAY),
+        };
+        let name = if is_local {
+            tr("you").to_string()
+        } else {
+            scoreboard_name(&placement.player_id, placement.name.as_deref())
+        };
+        draw_ui_text(d, &place, left, y, 20, place_color);
+        draw_ui_text(d, &name, name_x, y, 20, color);
+        let mut tag_x = name_x + ui_text_width(d, &name, 20) + 6;
+        if placement.perfect_clears > 0 {
+            let count = tr_args("perfect_clear_count", &[("count", &placement.perfect_clears)]);
+            draw_ui_text(d, &count, tag_x, y + 4, 12, Color::GOLD);
+            tag_x += ui_text_width(d, &count, 12) + 6;
+        }
+        if placement.unverified {
+            draw_ui_text(d, tr("match_unverified"), tag_x, y + 4, 12, Color::GRAY);
+        }
+        if let Some(change) = placement.rating_change {
+            let change_color = if change >= 0 { Color::GREEN } else { Color::RED };
+            draw_text_right(d, &format!("{:+}", change), rating_right, y, 20, change_color);
+        }
+        draw_text_right(d, &placement.score.to_string(), score_right, y, 20, color);
+        draw_text_right(d, &placement.lines_sent.to_string(), sent_right, y, 20, color);
+        draw_text_right(d, &placement.lines_received.to_string(), right, y, 20, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::multiplayer::{PlayerInfo, RemoteBoard};
+    use crate::tetris::MAX_HOLD_SLOTS;
+    use std::time::Instant;
+
+    fn inside(rect: &Rect, panel: &Rect) -> bool {
+        rect.x >= panel.x
+            && rect.y - MINI_BOARD_LABEL_HEIGHT >= panel.y
+            && rect.x + rect.width <= panel.x + panel.width
+            && rect.y + rect.height <= panel.y + panel.height
+    }
+
+    fn overlaps(a: &Rect, b: &Rect) -> bool {
+        a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    #[test]
+    fn mini_boards_shrink_to_fit_then_paginate() {
+        let panel = Layout::new(MIN_UI_SCALE).opponent_panel;
+        let grid = |count| mini_board_grid(count, panel, MINI_BOARD_LABEL_HEIGHT);
+        let one = grid(1);
+        assert_eq!(one.len(), 1);
+        assert_eq!(one[0].width, BOARD_WIDTH as i32 * MINI_CELL_SIZE);
+
+        let mut last_width = one[0].width;
+        for count in [2, 4, 8, 16] {
+            let grid = grid(count);
+            assert_eq!(grid.len(), count);
+            assert!(grid[0].width <= last_width, "{} boards got bigger", count);
+            last_width = grid[0].width;
+            for (i, rect) in grid.iter().enumerate() {
+                assert!(inside(rect, &panel), "{} boards: {:?}", count, rect);
+                assert!(grid[..i].iter().all(|other| !overlaps(rect, other)));
+            }
+        }
+
+        // Past the smallest cell size the rest go on later pages
+        let page = grid(100);
+        assert!(page.len() < 100);
+        assert_eq!(page[0].width, BOARD_WIDTH as i32 * MIN_MINI_CELL_SIZE);
+        let pages = opponent_page_count(100);
+        assert!(pages * page.len() >= 100 && (pages - 1) * page.len() < 100);
+        assert_eq!(opponent_page_count(0), 1);
+        assert_eq!(opponent_page_count(3), 1);
+    }
+
+    #[test]
+    fn layout_fits_at_every_ui_scale() {
+        assert_eq!(Layout::new(MIN_UI_SCALE).window_width, WINDOW_WIDTH);
+        let mut scale = MIN_UI_SCALE;
+        while scale <= MAX_UI_SCALE {
+            let layout = Layout::new(scale);
+            let lines = |count: i32, size: i32| count * layout.line_height(size);
+            let board_right = layout.board_x + BOARD_WIDTH as i32 * CELL_SIZE;
+            let board_bottom = BOARD_OFFSET_Y + BOARD_HEIGHT as i32 * CELL_SIZE;
+            let preview_height = PREVIEW_BOX_HEIGHT * PREVIEW_CELL_SIZE;
+            let at = |value| format!("{} at {}x", value, scale);
+
+            // Right panel: next piece, the scoreboard with at least one row, then the stats
+            assert!(layout.panel_x > board_right, "{}", at("panel"));
+            assert!(layout.window_width > layout.panel_x, "{}", at("window"));
+            assert!(BOARD_OFFSET_Y + lines(1, 20) <= layout.next_y, "{}", at("next label"));
+            assert!(layout.next_y + preview_height < layout.scoreboard_y, "{}", at("next"));
+            let rows_y = layout.scoreboard_y + lines(1, 25) + lines(1, 12);
+            // The attack totals take a line above the stats
+            assert!(rows_y + lines(1, 14) + lines(1, 20) < layout.stats_y, "{}", at("scoreboard"));
+            let stats_height = lines(STATS_LINES, STATS_SIZE);
+            assert!(layout.stats_y + stats_height <= board_bottom + 25, "{}", at("stats"));
+            // The level bar fits between the level line's text and the next line
+            assert!(LEVEL_BAR_HEIGHT < layout.line_height(STATS_SIZE) - layout.scaled(STATS_SIZE));
+            assert!(board_bottom + 25 < WINDOW_HEIGHT - 15 - layout.scaled(20), "{}", at("toast"));
+
+            let slots = MAX_HOLD_SLOTS as i32;
+            let hold_width = slots * 4 * PREVIEW_CELL_SIZE + (slots - 1) * HOLD_BOX_GAP;
+
+            // Left panel: hold, then the mode panel or the opponents with their page label
+            let hold_bottom = layout.hold_y + lines(1, 20) + preview_height;
+            assert!(hold_bottom < layout.hud_y, "{}", at("hold"));
+            assert!(20 + hold_width <= layout.board_x, "{}", at("double hold"));
+            let panel = layout.opponent_panel;
+            assert!(panel.x + panel.width < layout.board_x, "{}", at("opponents"));
+            let page_bottom = panel.y + panel.height + 10 + layout.scaled(20);
+            assert!(page_bottom <= WINDOW_HEIGHT, "{}", at("page label"));
+            let boards = mini_board_grid(8, panel, layout.label_height());
+            assert!(!boards.is_empty(), "{}", at("opponent boards"));
+
+            // Player 2 in local versus: board, then next, hold and stats beside it
+            assert!(layout.versus_board_x > layout.window_width, "{}", at("versus board"));
+            let versus_right = layout.versus_board_x + BOARD_WIDTH as i32 * CELL_SIZE;
+            let hold_right = versus_right + layout.scaled(PANEL_GAP) + hold_width;
+            assert!(hold_right < layout.versus_window_width, "{}", at("versus panel"));
+            assert!(layout.hud_y + lines(4, 20) <= WINDOW_HEIGHT, "{}", at("versus stats"));
+            scale += UI_SCALE_STEP;
+        }
+    }
+
+    #[test]
+    fn scoreboard_names_fall_back_and_truncate() {
+        assert_eq!(scoreboard_name("3f2a9c1e-77", Some("  Ann ")), "Ann");
+        assert_eq!(scoreboard_name("3f2a9c1e-77", Some("   ")), "3f2a9c1");
+        assert_eq!(scoreboard_name("3f2a9c1e-77", None), "3f2a9c1");
+        assert_eq!(scoreboard_name("id", Some("Bartholomew")), "Barthol");
+    }
+
+    #[test]
+    fn scoreboard_ranks_ties_stably_and_keeps_our_row_in_view() {
+        let mut game = Game::default();
+        game.player_id = Some("me".to_string());
+        for (id, score) in [("d", 40), ("b", 50), ("c", 50), ("a", 30)] {
+            let info = game.other_players.entry(id.to_string()).or_insert_with(PlayerInfo::new);
+            info.score = score;
+        }
+        game.score.points = 10;
+
+        let rows = scoreboard_rows(&game);
+        let ids: Vec<&str> = rows.iter().map(|row| row.id).collect();
+        assert_eq!(ids, ["b", "c", "d", "a", "me"]);
+        assert_eq!(rows[4].name, "YOU");
+
+        let ranks = |fit| -> Vec<usize> {
+            visible_scoreboard_rows(&rows, fit).iter().map(|(rank, _)| rank + 1).collect()
+        };
+        assert_eq!(ranks(5), [1, 2, 3, 4, 5]);
+        // One line goes to the overflow count, and our row takes the last place
+        assert_eq!(ranks(4), [1, 2, 5]);
+    }
+
+    #[test]
+    fn opponents_sort_by_score_or_latest_attack() {
+        let mut game = Game::default();
+        for (id, score) in [("a", 10), ("b", 30), ("c", 10)] {
+            game.other_boards.insert(id.to_string(), RemoteBoard::default());
+            let info = game.other_players.entry(id.to_string()).or_insert_with(PlayerInfo::new);
+            info.score = score;
+        }
+        assert_eq!(sorted_opponents(&game, OpponentOrder::Score), ["b", "a", "c"]);
+
+        let now = Instant::now();
+        game.attacked_at.insert("c".to_string(), now);
+        game.attacked_at.insert("a".to_string(), now - Duration::from_secs(5));
+        assert_eq!(sorted_opponents(&game, OpponentOrder::RecentAttack), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn window_title_layout() {
+        let mut game = Game::default();
+        game.start_cheese_race(18);
+        game.garbage_cleared = 7;
+        game.play_time = Duration::from_secs(92);
+        assert_eq!(window_title(&game), "Tetris — Cheese Race — 7/18 lines — 1:32");
+
+        game.state = GameState::Paused;
+        assert_eq!(window_title(&game), "Tetris — Cheese Race — 7/18 lines — 1:32 [PAUSED]");
+
+        let mut game = Game::default();
+        game.start_game();
+        game.score.points = 1200;
+        game.play_time = Duration::from_secs(605);
+        game.state = GameState::GameOver;
+        assert_eq!(window_title(&game), "Tetris — Marathon — 1200 pts — 10:05 [GAME OVER]");
+    }
+
+    #[test]
+    fn connected_cells_join_their_own_piece_only() {
+        let mut board = Board::new();
+        let mut o = Block::new(BlockKind::O);
+        o.y = BOARD_HEIGHT as i32 - 1;
+        assert!(board.place_block(&o));
+        let mut i = Block::new(BlockKind::I);
+        i.y = BOARD_HEIGHT as i32 - 3;
+        assert!(board.place_block(&i));
+        let joins = |x: i32, y: i32| {
+            let id = piece_id(&board, x, y).unwrap();
+            Joins::of(x, y, |x, y| piece_id(&board, x, y) == Some(id))
+        };
+
+        // Each O cell meets the other three, corner gap included
+        let (x, y) = o.blocks().into_iter().min().unwrap();
+        let top_left = joins(x, y);
+        assert!(top_left.right && top_left.down && top_left.down_right);
+        assert!(!top_left.up && !top_left.left && !top_left.up_right);
+        // The I sits right on top of the O, but is a piece of its own
+        let [(x, y), ..] = i.blocks();
+        let end = joins(x, y);
+        assert!(end.right && !end.left && !end.down && !end.down_right);
+
+        // Corners need both sides, so an L's bend doesn't fill in
+        let cells = BlockKind::L.base_cells();
+        for &(x, y) in &cells {
+            let joins = Joins::of(x, y, |x, y| cells.contains(&(x, y)));
+            assert!(!(joins.up_left || joins.up_right || joins.down_left || joins.down_right));
+        }
+    }
+
+    #[test]
+    fn accents_come_from_the_theme_and_steer_clear_of_ours() {
+        let pieces = theme().pieces;
+        assert_eq!(accent_color(Some(2), Some(0)), pieces[2]);
+        assert_eq!(accent_color(Some(2), Some(2)), pieces[3]);
+        assert_eq!(accent_color(Some(6), Some(6)), pieces[0]);
+        assert_eq!(accent_color(None, Some(0)), Color::WHITE);
+    }
+
+    #[test]
+    fn heat_runs_from_blue_to_red() {
+        assert_eq!(heat_color(0, 5).a, 0);
+        // A game without a single piece has nothing to scale by
+        assert_eq!(heat_color(0, 0).a, 0);
+        assert_eq!(heat_color(3, 0).a, 0);
+        assert_eq!(heat_color(5, 5), Color::new(255, 0, 0, HEATMAP_ALPHA));
+        let cool = heat_color(1, 5);
+        assert!(cool.b > cool.r);
+        assert_eq!(cool.a, HEATMAP_ALPHA);
+        assert_eq!(heat_color(9, 5), heat_color(5, 5));
+    }
+
+    #[test]
+    fn split_deltas_round_to_tenths() {
+        assert_eq!(format_split_delta(-1300), "-1.3s");
+        assert_eq!(format_split_delta(760), "+0.8s");
+        assert_eq!(format_split_delta(0), "+0.0s");
+        assert_eq!(format_split_delta(-12_049), "-12.0s");
+    }
+
+    #[test]
+    fn gravity_switches_to_g_at_a_row_per_frame() {
+        assert_eq!(format_gravity(1.0 / 0.8), "Gravity: 1.25 rows/s");
+        assert_eq!(format_gravity(59.9), "Gravity: 59.90 rows/s");
+        assert_eq!(format_gravity(60.0), "Gravity: 1.0G");
+        assert_eq!(format_gravity(1200.0), "Gravity: 20.0G");
+    }
+
+    #[test]
+    fn window_points_map_back_into_the_scene() {
+        // Twice the size and wider still, so the scene is centered with bars either side
+        let (window, scene) = ((2000.0, 1600.0), (850.0, 800.0));
+        let corner = scene_point(Vector2::new(150.0, 0.0), window, scene);
+        assert_eq!((corner.x, corner.y), (0.0, 0.0));
+        let middle = scene_point(Vector2::new(1000.0, 800.0), window, scene);
+        assert_eq!((middle.x, middle.y), (425.0, 400.0));
+    }
+
+    #[test]
+    fn room_rules_read_as_one_line() {
+        let mut rules = RoomRules::default();
+        assert_eq!(
+            format_room_rules(&rules),
+            "Rules: 500 ms lock delay, 0% messy garbage, hold, 10 for a perfect clear, \
+             1-piece garbage delay"
+        );
+        rules.garbage.messiness = 0.333;
+        rules.hold = false;
+        rules.perfect_clear_attack = 6;
+        rules.garbage_delay_pieces = 0;
+        assert_eq!(
+            format_room_rules(&rules),
+            "Rules: 500 ms lock delay, 33% messy garbage, no hold, 6 for a perfect clear, \
+             0-piece garbage delay"
+        );
+    }
 }
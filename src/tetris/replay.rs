@@ -0,0 +1,418 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::multiplayer::{GameMessage, RemoteBoard};
+use super::results::MatchResult;
+
+pub const REPLAYS_DIR: &str = "replays";
+// How many recorded matches the server keeps before deleting the oldest
+pub const REPLAY_RETENTION: usize = 50;
+// Replays go to clients in pieces of at most this many bytes
+pub const REPLAY_CHUNK_BYTES: usize = 16 * 1024;
+const REPLAY_EXTENSION: &str = "jsonl";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReplayPlayer {
+    pub player_id: String,
+    pub name: Option<String>,
+}
+
+/// One line of a replay file.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum ReplayLine {
+    /// The first line: the pieces' shared seed, and who played.
+    Start {
+        seed: Option<u64>,
+        players: Vec<ReplayPlayer>,
+    },
+    /// A gameplay message relayed during the match, `at_ms` after it started.
+    Message { at_ms: u64, msg: GameMessage },
+    /// The last line, written once the match is over.
+    Result { result: MatchResult },
+}
+
+/// A recorded match, as listed by the server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReplayInfo {
+    pub id: String,
+    pub players: Vec<ReplayPlayer>,
+}
+
+/// Writes a match to its replay file as it happens, one line per message.
+pub struct ReplayRecorder {
+    id: String,
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl ReplayRecorder {
+    /// Opens a new replay file in `dir`. Ids start with the time, so replays sort oldest
+    /// first.
+    pub fn start(
+        dir: &Path,
+        seed: Option<u64>,
+        players: Vec<ReplayPlayer>,
+        now: Instant,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis());
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let id = format!("{:015}-{}", millis, &suffix[..8]);
+        let file = File::create(replay_path(dir, &id))?;
+        let mut recorder = Self {
+            id,
+            file: BufWriter::new(file),
+            started: now,
+        };
+        recorder.write(&ReplayLine::Start { seed, players })?;
+        Ok(recorder)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn record(&mut self, msg: &GameMessage, now: Instant) -> io::Result<()> {
+        let at_ms = now.duration_since(self.started).as_millis() as u64;
+        self.write(&ReplayLine::Message {
+            at_ms,
+            msg: msg.clone(),
+        })
+    }
+
+    /// Ends the replay with the match's result. A replay that never gets here, because
+    /// everyone left or the server stopped, has no result line.
+    pub fn finish(mut self, result: &MatchResult) -> io::Result<String> {
+        self.write(&ReplayLine::Result {
+            result: result.clone(),
+        })?;
+        self.file.flush()?;
+        Ok(self.id)
+    }
+
+    fn write(&mut self, line: &ReplayLine) -> io::Result<()> {
+        serde_json::to_writer(&mut self.file, line)?;
+        self.file.write_all(b"\n")
+    }
+}
+
+// Ids are only ever made by ReplayRecorder, so anything else can't name a file outside
+// the replays
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn replay_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.{}", id, REPLAY_EXTENSION))
+}
+
+/// Ids of the replays in `dir`, oldest first.
+fn replay_ids(dir: &Path) -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut ids = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(REPLAY_EXTENSION) {
+            continue;
+        }
+        if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+            ids.push(id.to_string());
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Deletes the oldest replays in `dir` beyond the newest `keep`.
+pub fn prune_replays(dir: &Path, keep: usize) -> io::Result<()> {
+    let ids = replay_ids(dir)?;
+    let excess = ids.len().saturating_sub(keep);
+    for id in &ids[..excess] {
+        fs::remove_file(replay_path(dir, id))?;
+    }
+    Ok(())
+}
+
+/// The replays kept in `dir`, newest first.
+pub fn list_replays(dir: &Path) -> io::Result<Vec<ReplayInfo>> {
+    let mut replays = Vec::new();
+    for id in replay_ids(dir)?.into_iter().rev() {
+        let mut first = String::new();
+        BufReader::new(File::open(replay_path(dir, &id))?).read_line(&mut first)?;
+        if let Ok(ReplayLine::Start { players, .. }) = serde_json::from_str(&first) {
+            replays.push(ReplayInfo { id, players });
+        }
+    }
+    Ok(replays)
+}
+
+/// The text of replay `id`, or None if there's no such replay.
+pub fn read_replay(dir: &Path, id: &str) -> io::Result<Option<String>> {
+    if !valid_id(id) {
+        return Ok(None);
+    }
+    match fs::read_to_string(replay_path(dir, id)) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Splits `text` into pieces of at most `size` bytes, without splitting a character.
+pub fn replay_chunks(text: &str, size: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// A replay read back from its file.
+pub struct Replay {
+    pub seed: Option<u64>,
+    pub players: Vec<ReplayPlayer>,
+    /// Every message, with how far into the match it was sent.
+    pub messages: Vec<(Duration, GameMessage)>,
+    pub result: Option<MatchResult>,
+}
+
+impl Replay {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        let parse_line = |(index, line): (usize, &str)| {
+            let parsed = serde_json::from_str::<ReplayLine>(line);
+            parsed.map_err(|e| format!("line {}: {}", index + 1, e))
+        };
+        let Some(ReplayLine::Start { seed, players }) = lines.next().map(parse_line).transpose()?
+        else {
+            return Err("the replay doesn't start with its players".to_string());
+        };
+        let mut replay = Self {
+            seed,
+            players,
+            messages: Vec::new(),
+            result: None,
+        };
+        for line in lines {
+            match parse_line(line)? {
+                ReplayLine::Message { at_ms, msg } => {
+                    replay.messages.push((Duration::from_millis(at_ms), msg));
+                }
+                ReplayLine::Result { result } => replay.result = Some(result),
+                ReplayLine::Start { .. } => return Err("the replay starts twice".to_string()),
+            }
+        }
+        Ok(replay)
+    }
+
+    /// How long the match ran, up to its last message.
+    pub fn duration(&self) -> Duration {
+        self.messages.last().map_or(Duration::ZERO, |(at, _)| *at)
+    }
+}
+
+/// Plays a replay back like a spectator: each player's board as they last sent it,
+/// along with their score and whether they're out.
+pub struct Playback {
+    pub replay: Replay,
+    // Index of the next message to apply
+    next: usize,
+    pub elapsed: Duration,
+    pub boards: HashMap<String, RemoteBoard>,
+    pub scores: HashMap<String, i32>,
+    pub out: HashSet<String>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            next: 0,
+            elapsed: Duration::ZERO,
+            boards: HashMap::new(),
+            scores: HashMap::new(),
+            out: HashSet::new(),
+        }
+    }
+
+    /// Moves the playback on by `dt`, applying every message sent in that time.
+    pub fn advance(&mut self, dt: Duration) {
+        self.elapsed += dt;
+        while let Some((at, msg)) = self.replay.messages.get(self.next) {
+            if *at > self.elapsed {
+                break;
+            }
+            match msg.clone() {
+                GameMessage::BoardState {
+                    player_id,
+                    cells,
+                    current,
+                    next,
+                } => {
+                    let remote = self.boards.entry(player_id).or_default();
+                    // A malformed board leaves the last good one showing
+                    let _ = remote.board.update_from_network(cells);
+                    remote.current = current;
+                    remote.next = next;
+                }
+                GameMessage::GameState {
+                    player_id, score, ..
+                } => {
+                    self.scores.insert(player_id, score);
+                }
+                GameMessage::GameOver { player_id } | GameMessage::Forfeit { player_id } => {
+                    self.out.insert(player_id);
+                }
+                _ => {}
+            }
+            self.next += 1;
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.replay.messages.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tetris-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn players() -> Vec<ReplayPlayer> {
+        ["alice", "bob"]
+            .iter()
+            .map(|name| ReplayPlayer {
+                player_id: format!("{}-id", name),
+                name: Some(name.to_string()),
+            })
+            .collect()
+    }
+
+    fn board_with_one_cell(player_id: &str) -> GameMessage {
+        let mut cells = RemoteBoard::default().board.get_cells_for_network();
+        let last = cells.len() - 1;
+        cells[last][0] = Some(3);
+        GameMessage::BoardState {
+            player_id: player_id.to_string(),
+            cells,
+            current: None,
+            next: None,
+        }
+    }
+
+    #[test]
+    fn recorded_matches_read_back_with_their_result() {
+        let dir = test_dir("replay-record");
+        let start = Instant::now();
+        let mut recorder = ReplayRecorder::start(&dir, Some(7), players(), start).unwrap();
+        let score = GameMessage::GameState {
+            player_id: "alice-id".to_string(),
+            score: 400,
+            lines: 3,
+            name: Some("alice".to_string()),
+            afk: false,
+        };
+        recorder.record(&score, start + Duration::from_millis(250)).unwrap();
+        recorder.record(&board_with_one_cell("bob-id"), start + Duration::from_secs(1)).unwrap();
+        let result = MatchResult {
+            placements: Vec::new(),
+            duration_ms: 1000,
+        };
+        let id = recorder.finish(&result).unwrap();
+
+        let text = read_replay(&dir, &id).unwrap().expect("replay not written");
+        let replay = Replay::parse(&text).unwrap();
+        assert_eq!(replay.seed, Some(7));
+        assert_eq!(replay.players, players());
+        assert_eq!(replay.messages.len(), 2);
+        assert!(replay.messages[0] == (Duration::from_millis(250), score));
+        assert_eq!(replay.duration(), Duration::from_secs(1));
+        assert_eq!(replay.result, Some(result));
+
+        let listed = list_replays(&dir).unwrap();
+        assert_eq!(listed, [ReplayInfo { id: id.clone(), players: players() }]);
+        assert_eq!(read_replay(&dir, "../secrets").unwrap(), None);
+        assert_eq!(read_replay(&dir, "0-nothing").unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_newest_replays_are_kept() {
+        let dir = test_dir("replay-prune");
+        let now = Instant::now();
+        let ids: Vec<String> = (0..4)
+            .map(|_| {
+                let recorder = ReplayRecorder::start(&dir, None, players(), now).unwrap();
+                // Ids are in milliseconds, so give each one its own
+                std::thread::sleep(Duration::from_millis(2));
+                recorder.id().to_string()
+            })
+            .collect();
+
+        prune_replays(&dir, 2).unwrap();
+        let listed = list_replays(&dir).unwrap();
+        let kept: Vec<String> = listed.into_iter().map(|info| info.id).collect();
+        assert_eq!(kept, [ids[3].clone(), ids[2].clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_split_between_characters() {
+        let text = "añb".repeat(5);
+        let chunks = replay_chunks(&text, 4);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 4 && !chunk.is_empty()));
+        assert_eq!(chunks.concat(), text);
+        assert!(replay_chunks("", 4).is_empty());
+    }
+
+    #[test]
+    fn playback_rebuilds_boards_as_time_passes() {
+        let out = GameMessage::GameOver {
+            player_id: "bob-id".to_string(),
+        };
+        let replay = Replay {
+            seed: None,
+            players: players(),
+            messages: vec![
+                (Duration::from_millis(100), board_with_one_cell("bob-id")),
+                (Duration::from_millis(900), out),
+            ],
+            result: None,
+        };
+        let mut playback = Playback::new(replay);
+        playback.advance(Duration::from_millis(50));
+        assert!(playback.boards.is_empty());
+
+        playback.advance(Duration::from_millis(100));
+        let cells = playback.boards["bob-id"].board.get_cells_for_network();
+        assert_eq!(cells.concat().iter().flatten().count(), 1);
+        assert!(!playback.is_finished());
+
+        playback.advance(Duration::from_secs(1));
+        assert!(playback.out.contains("bob-id"));
+        assert!(playback.is_finished());
+    }
+}
@@ -0,0 +1,507 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::AttackKind;
+
+pub const RESULTS_LOG_PATH: &str = "match_results.jsonl";
+// How many matches the results log keeps before dropping the oldest
+pub const RESULTS_LOG_LIMIT: usize = 500;
+
+/// Something that happened to a player during a match, as the server saw it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchEvent {
+    Joined {
+        player_id: String,
+        name: Option<String>,
+    },
+    Score {
+        player_id: String,
+        score: i32,
+        name: Option<String>,
+    },
+    /// Garbage lines sent to everyone else still in the match.
+    Attack {
+        player_id: String,
+        lines: u32,
+        kind: AttackKind,
+    },
+    ToppedOut { player_id: String },
+    /// `player_id` topped out with `by`'s attack the last one they received.
+    KnockOut { player_id: String, by: String },
+    Forfeited { player_id: String },
+    /// Dropped out of the match without finishing, by disconnecting or changing rooms.
+    Disconnected { player_id: String },
+}
+
+impl MatchEvent {
+    fn player_id(&self) -> &str {
+        match self {
+            MatchEvent::Joined { player_id, .. }
+            | MatchEvent::Score { player_id, .. }
+            | MatchEvent::Attack { player_id, .. }
+            | MatchEvent::ToppedOut { player_id }
+            | MatchEvent::KnockOut { player_id, .. }
+            | MatchEvent::Forfeited { player_id }
+            | MatchEvent::Disconnected { player_id } => player_id,
+        }
+    }
+
+    fn ends_match_for_player(&self) -> bool {
+        matches!(
+            self,
+            MatchEvent::ToppedOut { .. }
+                | MatchEvent::Forfeited { .. }
+                | MatchEvent::Disconnected { .. }
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub player_id: String,
+    pub name: Option<String>,
+    /// 1 for the winner, `None` for a player who did not finish.
+    pub place: Option<u32>,
+    pub score: i32,
+    pub lines_sent: u32,
+    pub lines_received: u32,
+    #[serde(default)]
+    pub kos: u32,
+    #[serde(default)]
+    pub perfect_clears: u32,
+    /// The server caught the player reporting something impossible, so the result wasn't
+    /// rated.
+    #[serde(default)]
+    pub unverified: bool,
+    /// Rating points won or lost, for ranked matches.
+    #[serde(default)]
+    pub rating_change: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MatchResult {
+    /// Best first, with players who did not finish at the end.
+    pub placements: Vec<Placement>,
+    pub duration_ms: u64,
+}
+
+impl MatchResult {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+}
+
+// Per-player totals while going through the events
+struct Tally {
+    name: Option<String>,
+    score: i32,
+    sent: u32,
+    received: u32,
+    kos: u32,
+    perfect_clears: u32,
+    // Position in the order players went out, and whether they finished
+    out_at: Option<usize>,
+    finished: bool,
+}
+
+/// Works out placements from a match's events. Players still standing at the end place
+/// first (by score if there are several), then everyone who went out, most recent first.
+pub fn compile_results(events: &[MatchEvent], duration: Duration) -> MatchResult {
+    let mut order: Vec<String> = Vec::new();
+    let mut tallies: HashMap<String, Tally> = HashMap::new();
+    let mut outs = 0;
+
+    for event in events {
+        if let MatchEvent::Joined { player_id, name } = event {
+            if !tallies.contains_key(player_id) {
+                order.push(player_id.clone());
+                tallies.insert(
+                    player_id.clone(),
+                    Tally {
+                        name: name.clone(),
+                        score: 0,
+                        sent: 0,
+                        received: 0,
+                        kos: 0,
+                        perfect_clears: 0,
+                        out_at: None,
+                        finished: true,
+                    },
+                );
+            }
+            continue;
+        }
+
+        // Credited after the player went out, and even if the attacker has since gone too
+        if let MatchEvent::KnockOut { by, .. } = event {
+            if let Some(tally) = tallies.get_mut(by) {
+                tally.kos += 1;
+            }
+            continue;
+        }
+
+        let player_id = event.player_id();
+        // Only players still in the match can score, attack or go out
+        if !matches!(tallies.get(player_id), Some(tally) if tally.out_at.is_none()) {
+            continue;
+        }
+        match event {
+            MatchEvent::Score { score, name, .. } => {
+                let tally = tallies.get_mut(player_id).unwrap();
+                tally.score = *score;
+                if name.is_some() {
+                    tally.name.clone_from(name);
+                }
+            }
+            MatchEvent::Attack { lines, kind, .. } => {
+                for (id, tally) in tallies.iter_mut() {
+                    if id == player_id {
+                        tally.sent += lines;
+                        tally.perfect_clears += u32::from(*kind == AttackKind::PerfectClear);
+                    } else if tally.out_at.is_none() {
+                        tally.received += lines;
+                    }
+                }
+            }
+            _ => {
+                let tally = tallies.get_mut(player_id).unwrap();
+                tally.out_at = Some(outs);
+                tally.finished = !matches!(event, MatchEvent::Disconnected { .. });
+                outs += 1;
+            }
+        }
+    }
+
+    let mut ranked: Vec<&String> = order.iter().collect();
+    ranked.sort_by(|a, b| {
+        let (a, b) = (&tallies[*a], &tallies[*b]);
+        // Standing players first, then the latest to go out; DNFs last
+        let key = |tally: &Tally| (tally.finished, tally.out_at.unwrap_or(usize::MAX));
+        key(b).cmp(&key(a)).then_with(|| b.score.cmp(&a.score))
+    });
+
+    let mut place = 0;
+    let placements = ranked
+        .into_iter()
+        .map(|player_id| {
+            let tally = &tallies[player_id];
+            Placement {
+                player_id: player_id.clone(),
+                name: tally.name.clone(),
+                place: tally.finished.then(|| {
+                    place += 1;
+                    place
+                }),
+                score: tally.score,
+                lines_sent: tally.sent,
+                lines_received: tally.received,
+                kos: tally.kos,
+                perfect_clears: tally.perfect_clears,
+                unverified: false,
+                rating_change: None,
+            }
+        })
+        .collect();
+
+    MatchResult {
+        placements,
+        duration_ms: duration.as_millis() as u64,
+    }
+}
+
+/// The event history of one match in a room.
+pub struct MatchLog {
+    started_at: Instant,
+    events: Vec<MatchEvent>,
+}
+
+impl MatchLog {
+    pub fn new(players: impl IntoIterator<Item = (String, Option<String>)>) -> Self {
+        let events = players
+            .into_iter()
+            .map(|(player_id, name)| MatchEvent::Joined { player_id, name })
+            .collect();
+        Self {
+            started_at: Instant::now(),
+            events,
+        }
+    }
+
+    pub fn is_participant(&self, player_id: &str) -> bool {
+        self.events.iter().any(
+            |event| matches!(event, MatchEvent::Joined { player_id: id, .. } if id == player_id),
+        )
+    }
+
+    /// Whether the player is in this match and hasn't gone out yet.
+    pub fn is_alive(&self, player_id: &str) -> bool {
+        self.is_participant(player_id)
+            && !self
+                .events
+                .iter()
+                .any(|event| event.player_id() == player_id && event.ends_match_for_player())
+    }
+
+    /// Records an event, ignoring players who aren't in the match. A player topping out to
+    /// someone's attack also records a KnockOut, which is returned.
+    pub fn record(&mut self, event: MatchEvent) -> Option<MatchEvent> {
+        if !self.is_participant(event.player_id()) {
+            return None;
+        }
+        let knocked_out = match &event {
+            MatchEvent::ToppedOut { player_id } if self.is_alive(player_id) => {
+                Some(player_id.clone())
+            }
+            _ => None,
+        };
+        self.events.push(event);
+        let player_id = knocked_out?;
+        let by = self.last_attacker(&player_id)?;
+        let knock_out = MatchEvent::KnockOut { player_id, by };
+        self.events.push(knock_out.clone());
+        Some(knock_out)
+    }
+
+    // Whoever sent the last attack `player_id` received. Attacks go to everyone but the
+    // sender, so that's the latest attack by someone else.
+    fn last_attacker(&self, player_id: &str) -> Option<String> {
+        self.events.iter().rev().find_map(|event| match event {
+            MatchEvent::Attack {
+                player_id: by,
+                lines,
+                ..
+            } if by != player_id && *lines > 0 => {
+                Some(by.clone())
+            }
+            _ => None,
+        })
+    }
+
+    /// KOs `player_id` has this match, each one boosting their attacks.
+    pub fn ko_badges(&self, player_id: &str) -> u32 {
+        let kos = self.events.iter().filter(
+            |event| matches!(event, MatchEvent::KnockOut { by, .. } if by == player_id),
+        );
+        kos.count() as u32
+    }
+
+    /// A match is over once at most one player is left standing.
+    pub fn is_over(&self) -> bool {
+        let alive = self
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                MatchEvent::Joined { player_id, .. } => Some(player_id),
+                _ => None,
+            })
+            .filter(|player_id| self.is_alive(player_id))
+            .count();
+        alive <= 1
+    }
+
+    pub fn finish(&self) -> MatchResult {
+        compile_results(&self.events, self.started_at.elapsed())
+    }
+}
+
+/// Wins and losses against one opponent.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeadToHead {
+    pub wins: u32,
+    pub losses: u32,
+}
+
+impl HeadToHead {
+    pub fn add(&mut self, won: bool) {
+        if won {
+            self.wins += 1;
+        } else {
+            self.losses += 1;
+        }
+    }
+}
+
+/// Each opponent in a match `own_id` played, with whether `own_id` placed ahead of them.
+/// Opponents are left out when neither finished, as there's no order between the two.
+pub fn head_to_head<'a>(result: &'a MatchResult, own_id: &str) -> Vec<(&'a Placement, bool)> {
+    let placements = &result.placements;
+    let Some(own_at) = placements.iter().position(|p| p.player_id == own_id) else {
+        return Vec::new();
+    };
+    let finished = placements[own_at].place.is_some();
+    placements
+        .iter()
+        .enumerate()
+        .filter(|&(at, placement)| at != own_at && (finished || placement.place.is_some()))
+        .map(|(at, placement)| (placement, own_at < at))
+        .collect()
+}
+
+/// Our running record over the rematches of one stay in a room.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionTally {
+    pub matches: u32,
+    pub wins: u32,
+    /// Against each opponent, by player id.
+    pub opponents: HashMap<String, HeadToHead>,
+}
+
+impl SessionTally {
+    /// Counts a finished match, if `own_id` played in it.
+    pub fn record(&mut self, result: &MatchResult, own_id: &str) {
+        let Some(own) = result.placements.iter().find(|p| p.player_id == own_id) else {
+            return;
+        };
+        self.matches += 1;
+        if own.place == Some(1) {
+            self.wins += 1;
+        }
+        for (opponent, won) in head_to_head(result, own_id) {
+            self.opponents.entry(opponent.player_id.clone()).or_default().add(won);
+        }
+    }
+}
+
+/// Adds a result to the log file, one JSON object per line, keeping the latest
+/// `RESULTS_LOG_LIMIT` matches.
+pub fn append_results_log(path: impl AsRef<Path>, result: &MatchResult) -> io::Result<()> {
+    let path = path.as_ref();
+    let existing = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let mut lines: Vec<String> = existing.lines().map(str::to_string).collect();
+    lines.push(serde_json::to_string(result)?);
+    let keep_from = lines.len().saturating_sub(RESULTS_LOG_LIMIT);
+
+    let mut text = lines[keep_from..].join("\n");
+    text.push('\n');
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attack(player_id: &str, lines: u32) -> MatchEvent {
+        MatchEvent::Attack {
+            player_id: player_id.to_string(),
+            lines,
+            kind: AttackKind::Normal,
+        }
+    }
+
+    fn topped_out(player_id: &str) -> MatchEvent {
+        MatchEvent::ToppedOut {
+            player_id: player_id.to_string(),
+        }
+    }
+
+    fn match_of(players: &[&str]) -> MatchLog {
+        MatchLog::new(players.iter().map(|id| (id.to_string(), None)))
+    }
+
+    #[test]
+    fn last_attack_received_gets_the_ko() {
+        let mut log = match_of(&["a", "b", "c"]);
+        log.record(attack("a", 2));
+        log.record(attack("b", 1));
+        // c's own attack doesn't count against them, so b's was the last they received
+        log.record(attack("c", 4));
+        let knock_out = log.record(topped_out("c"));
+        assert_eq!(
+            knock_out,
+            Some(MatchEvent::KnockOut {
+                player_id: "c".to_string(),
+                by: "b".to_string(),
+            })
+        );
+        assert_eq!(log.ko_badges("b"), 1);
+        assert_eq!(log.ko_badges("a"), 0);
+
+        // Going out again, or without ever being attacked, earns nobody anything
+        assert_eq!(log.record(topped_out("c")), None);
+        let mut quiet = match_of(&["a", "b"]);
+        assert_eq!(quiet.record(topped_out("a")), None);
+    }
+
+    #[test]
+    fn kos_show_in_the_placements() {
+        let mut log = match_of(&["a", "b", "c"]);
+        log.record(attack("a", 1));
+        log.record(topped_out("b"));
+        log.record(topped_out("a"));
+        let result = log.finish();
+        let kos = |id: &str| {
+            let placement = result.placements.iter().find(|p| p.player_id == id).unwrap();
+            placement.kos
+        };
+        // a went out after their KO, and still keeps it
+        assert_eq!(kos("a"), 1);
+        assert_eq!(kos("b"), 0);
+        assert_eq!(result.placements[0].player_id, "c");
+    }
+
+    #[test]
+    fn perfect_clears_are_counted_for_whoever_sent_them() {
+        let mut log = match_of(&["a", "b"]);
+        log.record(attack("a", 4));
+        log.record(MatchEvent::Attack {
+            player_id: "a".to_string(),
+            lines: 10,
+            kind: AttackKind::PerfectClear,
+        });
+        let result = log.finish();
+        let perfect_clears: Vec<_> = result.placements.iter().map(|p| p.perfect_clears).collect();
+        assert_eq!(perfect_clears, [1, 0]);
+        assert_eq!(result.placements[0].lines_sent, 14);
+    }
+
+    fn placement(player_id: &str, place: Option<u32>) -> Placement {
+        Placement {
+            player_id: player_id.to_string(),
+            name: None,
+            place,
+            score: 0,
+            lines_sent: 0,
+            lines_received: 0,
+            kos: 0,
+            perfect_clears: 0,
+            unverified: false,
+            rating_change: None,
+        }
+    }
+
+    #[test]
+    fn session_tally_counts_rematches() {
+        let result = |placements| MatchResult {
+            placements,
+            duration_ms: 0,
+        };
+        let mut tally = SessionTally::default();
+        tally.record(&result(vec![placement("me", Some(1)), placement("b", Some(2))]), "me");
+        tally.record(&result(vec![placement("b", Some(1)), placement("me", Some(2))]), "me");
+        tally.record(
+            &result(vec![
+                placement("me", Some(1)),
+                placement("b", Some(2)),
+                placement("c", None),
+            ]),
+            "me",
+        );
+        // Not finishing loses to everyone who did
+        tally.record(&result(vec![placement("b", Some(1)), placement("me", None)]), "me");
+        // Matches we weren't in don't count at all
+        tally.record(&result(vec![placement("b", Some(1)), placement("c", Some(2))]), "me");
+
+        assert_eq!(tally.matches, 4);
+        assert_eq!(tally.wins, 2);
+        assert_eq!(tally.opponents["b"], HeadToHead { wins: 2, losses: 2 });
+        assert_eq!(tally.opponents["c"], HeadToHead { wins: 1, losses: 0 });
+    }
+}
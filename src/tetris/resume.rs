@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// A player whose connection dropped keeps their place this long, waiting to reconnect
+pub const RESUME_TOKEN_TTL: Duration = Duration::from_secs(120);
+
+struct Session {
+    token: String,
+    // When the connection went, while the player is away
+    dropped_at: Option<Instant>,
+}
+
+/// The resume token handed to each player at join. A player whose connection drops can
+/// present it on a new connection within the time limit and carry on as the same player.
+#[derive(Default)]
+pub struct ResumeTokens {
+    sessions: HashMap<String, Session>,
+}
+
+impl ResumeTokens {
+    /// Hands `player_id` a fresh token, replacing any they had.
+    pub fn issue(&mut self, player_id: &str) -> String {
+        let token = uuid::Uuid::new_v4().to_string();
+        self.sessions.insert(player_id.to_string(), Session {
+            token: token.clone(),
+            dropped_at: None,
+        });
+        token
+    }
+
+    /// Notes that a player's connection went without them quitting, starting the clock on
+    /// their token.
+    pub fn drop_connection(&mut self, player_id: &str, now: Instant) {
+        if let Some(session) = self.sessions.get_mut(player_id) {
+            session.dropped_at = Some(now);
+        }
+    }
+
+    /// Takes back a player's token, once they quit or are gone for good.
+    pub fn revoke(&mut self, player_id: &str) {
+        self.sessions.remove(player_id);
+    }
+
+    /// The player a token belongs to, unless they have been away too long. A player the
+    /// server still thinks is connected can resume too, since their old connection may
+    /// have died without anyone noticing. The token is used up: the resumed player gets a
+    /// new one.
+    pub fn resume(&mut self, token: &str, now: Instant) -> Option<String> {
+        let player_id = self.sessions.iter().find_map(|(player_id, session)| {
+            let expired = session
+                .dropped_at
+                .is_some_and(|dropped_at| now.duration_since(dropped_at) >= RESUME_TOKEN_TTL);
+            (session.token == token && !expired).then(|| player_id.clone())
+        })?;
+        self.sessions.remove(&player_id);
+        Some(player_id)
+    }
+
+    /// Removes the players who have been away too long to resume, and returns them.
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let expired: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                session
+                    .dropped_at
+                    .is_some_and(|dropped_at| now.duration_since(dropped_at) >= RESUME_TOKEN_TTL)
+            })
+            .map(|(player_id, _)| player_id.clone())
+            .collect();
+        for player_id in &expired {
+            self.sessions.remove(player_id);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_resume_their_own_player_once() {
+        let now = Instant::now();
+        let mut tokens = ResumeTokens::default();
+        let alice = tokens.issue("alice");
+        let bob = tokens.issue("bob");
+
+        tokens.drop_connection("alice", now);
+        assert_eq!(tokens.resume("made up", now), None);
+        assert_eq!(tokens.resume(&alice, now), Some("alice".to_string()));
+        assert_eq!(tokens.resume(&alice, now), None);
+        let again = tokens.issue("alice");
+        assert_ne!(again, alice);
+
+        // Bob's old connection hasn't been noticed dropping yet
+        assert_eq!(tokens.resume(&bob, now), Some("bob".to_string()));
+    }
+
+    #[test]
+    fn tokens_expire_or_are_revoked() {
+        let dropped = Instant::now();
+        let mut tokens = ResumeTokens::default();
+        let alice = tokens.issue("alice");
+        let bob = tokens.issue("bob");
+        tokens.drop_connection("alice", dropped);
+        tokens.drop_connection("bob", dropped);
+
+        // Quitting gives the token up straight away
+        tokens.revoke("bob");
+        assert_eq!(tokens.resume(&bob, dropped), None);
+
+        let late = dropped + RESUME_TOKEN_TTL;
+        assert!(tokens.expire(late - Duration::from_secs(1)).is_empty());
+        assert_eq!(tokens.resume(&alice, late), None);
+        assert_eq!(tokens.expire(late), ["alice"]);
+        assert!(tokens.expire(late).is_empty());
+    }
+}
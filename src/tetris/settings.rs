@@ -0,0 +1,184 @@
+use std::ops::Range;
+
+use raylib::prelude::KeyboardKey;
+
+use crate::tetris::config::{key_name, KeyBindings};
+use crate::tetris::menu::visible_items;
+use crate::tetris::renderer::{MAX_UI_SCALE, MIN_UI_SCALE, UI_SCALE_STEP};
+use crate::tetris::Action;
+
+/// Entries on the settings screen, top to bottom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingsItem {
+    UiScale,
+    Rumble,
+    Randomizer,
+    ConnectedPieces,
+    TranslucentPause,
+    ReduceMotion,
+    DroughtCounter,
+    Bind(Action),
+    ResetKeys,
+    Back,
+}
+
+impl SettingsItem {
+    pub const ALL: [SettingsItem; 15] = [
+        SettingsItem::UiScale,
+        SettingsItem::Rumble,
+        SettingsItem::Randomizer,
+        SettingsItem::ConnectedPieces,
+        SettingsItem::TranslucentPause,
+        SettingsItem::ReduceMotion,
+        SettingsItem::DroughtCounter,
+        SettingsItem::Bind(KeyBindings::ACTIONS[0]),
+        SettingsItem::Bind(KeyBindings::ACTIONS[1]),
+        SettingsItem::Bind(KeyBindings::ACTIONS[2]),
+        SettingsItem::Bind(KeyBindings::ACTIONS[3]),
+        SettingsItem::Bind(KeyBindings::ACTIONS[4]),
+        SettingsItem::Bind(KeyBindings::ACTIONS[5]),
+        SettingsItem::ResetKeys,
+        SettingsItem::Back,
+    ];
+}
+
+/// How far along rebinding an action is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rebind {
+    /// Waiting for the next key press, which becomes the action's key.
+    Listening(Action),
+    /// The pressed key already belongs to `other`; Enter swaps the two actions' keys.
+    Conflict {
+        action: Action,
+        key: KeyboardKey,
+        other: Action,
+    },
+}
+
+/// The settings screen, opened from the main menu: a cursor over the items.
+#[derive(Default)]
+pub struct SettingsMenu {
+    selected: usize,
+    /// While set, key presses go to the rebinding instead of moving the cursor.
+    pub rebind: Option<Rebind>,
+}
+
+impl SettingsMenu {
+    pub fn selected(&self) -> SettingsItem {
+        SettingsItem::ALL[self.selected]
+    }
+
+    /// Moves the cursor by `step` items, wrapping around at either end.
+    pub fn move_cursor(&mut self, step: isize) {
+        let len = SettingsItem::ALL.len() as isize;
+        self.selected = (self.selected as isize + step).rem_euclid(len) as usize;
+    }
+
+    /// Indices of the items to show when only `fit` fit on screen.
+    pub fn visible_items(&self, fit: usize) -> Range<usize> {
+        visible_items(SettingsItem::ALL.len(), self.selected, fit)
+    }
+
+    /// Handles a key pressed while rebinding, returning the new bindings once a key is bound.
+    /// Escape cancels, and keys that can't be written to the config file are ignored.
+    pub fn key_pressed(&mut self, key: KeyboardKey, keys: &KeyBindings) -> Option<KeyBindings> {
+        let rebind = self.rebind?;
+        if key == KeyboardKey::KEY_ESCAPE {
+            self.rebind = None;
+            return None;
+        }
+        let mut rebound = keys.clone();
+        match rebind {
+            Rebind::Listening(action) => {
+                key_name(key)?;
+                match keys.action_for(key) {
+                    Some(other) if other != action => {
+                        self.rebind = Some(Rebind::Conflict { action, key, other });
+                        return None;
+                    }
+                    _ => rebound.bind(action, key),
+                }
+            }
+            Rebind::Conflict { action, key: taken, .. } if key == KeyboardKey::KEY_ENTER => {
+                rebound.swap(action, taken);
+            }
+            Rebind::Conflict { .. } => return None,
+        }
+        self.rebind = None;
+        Some(rebound)
+    }
+}
+
+/// `scale` moved `steps` notches of `UI_SCALE_STEP`, kept within the allowed range.
+pub fn step_ui_scale(scale: f32, steps: i32) -> f32 {
+    // Snapped to a notch, so a hand-edited 1.3 lands on 1.25 or 1.5
+    let notch = (scale / UI_SCALE_STEP).round() + steps as f32;
+    (notch * UI_SCALE_STEP).clamp(MIN_UI_SCALE, MAX_UI_SCALE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_wraps_around() {
+        let mut settings = SettingsMenu::default();
+        settings.move_cursor(-1);
+        assert_eq!(settings.selected(), SettingsItem::Back);
+        settings.move_cursor(1);
+        assert_eq!(settings.selected(), SettingsItem::UiScale);
+    }
+
+    #[test]
+    fn pressed_key_becomes_the_binding() {
+        let mut settings = SettingsMenu {
+            rebind: Some(Rebind::Listening(Action::Hold)),
+            ..Default::default()
+        };
+        let keys = KeyBindings::default();
+        // No config file name, so it can't be saved
+        assert_eq!(settings.key_pressed(KeyboardKey::KEY_NULL, &keys), None);
+        assert!(settings.rebind.is_some());
+
+        let rebound = settings.key_pressed(KeyboardKey::KEY_A, &keys).unwrap();
+        assert_eq!(rebound.hold, [KeyboardKey::KEY_A]);
+        assert_eq!(settings.rebind, None);
+        assert_eq!(settings.key_pressed(KeyboardKey::KEY_B, &rebound), None);
+    }
+
+    #[test]
+    fn conflicts_swap_or_cancel() {
+        let keys = KeyBindings::default();
+        let mut settings = SettingsMenu {
+            rebind: Some(Rebind::Listening(Action::RotateCw)),
+            ..Default::default()
+        };
+        assert_eq!(settings.key_pressed(KeyboardKey::KEY_SPACE, &keys), None);
+        assert_eq!(
+            settings.rebind,
+            Some(Rebind::Conflict {
+                action: Action::RotateCw,
+                key: KeyboardKey::KEY_SPACE,
+                other: Action::HardDrop,
+            })
+        );
+        assert_eq!(settings.key_pressed(KeyboardKey::KEY_A, &keys), None);
+        let swapped = settings.key_pressed(KeyboardKey::KEY_ENTER, &keys).unwrap();
+        assert_eq!(swapped.rotate, [KeyboardKey::KEY_SPACE]);
+        assert_eq!(swapped.hard_drop, [KeyboardKey::KEY_UP]);
+
+        settings.rebind = Some(Rebind::Listening(Action::RotateCw));
+        settings.key_pressed(KeyboardKey::KEY_SPACE, &keys);
+        assert_eq!(settings.key_pressed(KeyboardKey::KEY_ESCAPE, &keys), None);
+        assert_eq!(settings.rebind, None);
+    }
+
+    #[test]
+    fn ui_scale_moves_in_notches_within_range() {
+        assert_eq!(step_ui_scale(1.0, 1), 1.25);
+        assert_eq!(step_ui_scale(1.5, -1), 1.25);
+        assert_eq!(step_ui_scale(1.3, 1), 1.5);
+        assert_eq!(step_ui_scale(2.0, 1), MAX_UI_SCALE);
+        assert_eq!(step_ui_scale(1.0, -1), MIN_UI_SCALE);
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::SPRINT_LINES;
+
+// A sprint is timed at every this many lines
+pub const SPLIT_LINES: u32 = 10;
+
+/// The times a sprint reached each `SPLIT_LINES` lines, up to `SPRINT_LINES`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Splits {
+    times: Vec<Duration>,
+}
+
+impl Splits {
+    /// Takes the split for every threshold `lines` has reached since the last call.
+    /// Returns whether it reached any.
+    pub fn record(&mut self, lines: u32, time: Duration) -> bool {
+        let reached = (lines.min(SPRINT_LINES) / SPLIT_LINES) as usize;
+        let new = reached > self.times.len();
+        self.times.resize(reached.max(self.times.len()), time);
+        new
+    }
+
+    pub fn times(&self) -> &[Duration] {
+        &self.times
+    }
+
+    /// Whether the run got all the way to `SPRINT_LINES`.
+    pub fn is_complete(&self) -> bool {
+        self.times.len() == (SPRINT_LINES / SPLIT_LINES) as usize
+    }
+}
+
+/// The splits of the fastest complete sprint, as kept with the high scores.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BestSplits {
+    pub splits_ms: Vec<u64>,
+}
+
+impl BestSplits {
+    /// How far ahead (negative) or behind (positive) of the best a run reaching split
+    /// `index` at `time` is, in milliseconds.
+    pub fn delta(&self, index: usize, time: Duration) -> Option<i64> {
+        let best = *self.splits_ms.get(index)?;
+        Some(time.as_millis() as i64 - best as i64)
+    }
+
+    pub fn total(&self) -> Option<Duration> {
+        self.splits_ms.last().map(|&ms| Duration::from_millis(ms))
+    }
+}
+
+/// Makes `run` the best if it's complete and faster than `best`, or the first complete
+/// run. Returns whether it was.
+pub fn update_best(best: &mut Option<BestSplits>, run: &Splits) -> bool {
+    let Some(&time) = run.times().last().filter(|_| run.is_complete()) else {
+        return false;
+    };
+    if best.as_ref().and_then(BestSplits::total).is_some_and(|total| total <= time) {
+        return false;
+    }
+    *best = Some(BestSplits {
+        splits_ms: run.times().iter().map(|time| time.as_millis() as u64).collect(),
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn run(splits: &[u64]) -> Splits {
+        let mut run = Splits::default();
+        for (index, &time) in splits.iter().enumerate() {
+            run.record((index as u32 + 1) * SPLIT_LINES, secs(time));
+        }
+        run
+    }
+
+    #[test]
+    fn splits_are_taken_as_thresholds_are_crossed() {
+        let mut splits = Splits::default();
+        assert!(!splits.record(9, secs(5)));
+        assert!(splits.record(11, secs(8)));
+        assert!(!splits.record(12, secs(9)));
+        // A clear across two thresholds takes both at once
+        assert!(splits.record(31, secs(15)));
+        assert_eq!(splits.times(), [secs(8), secs(15), secs(15)]);
+        assert!(!splits.is_complete());
+        // Lines past the end of the sprint don't add splits
+        assert!(splits.record(43, secs(26)));
+        assert!(!splits.record(50, secs(27)));
+        assert!(splits.is_complete());
+    }
+
+    #[test]
+    fn only_faster_complete_runs_become_the_best() {
+        let mut best = None;
+        // Topped out before the end: nothing to compare against later
+        assert!(!update_best(&mut best, &run(&[10, 20])));
+        assert_eq!(best, None);
+
+        assert!(update_best(&mut best, &run(&[10, 20, 30, 40])));
+        let pb = best.clone().unwrap();
+        assert_eq!(pb.total(), Some(secs(40)));
+        assert_eq!(pb.delta(1, Duration::from_millis(18_700)), Some(-1300));
+        assert_eq!(pb.delta(2, Duration::from_millis(30_800)), Some(800));
+        assert_eq!(pb.delta(4, secs(50)), None);
+
+        assert!(!update_best(&mut best, &run(&[9, 19, 29, 41])));
+        assert!(!update_best(&mut best, &run(&[10, 20, 30, 40])));
+        assert_eq!(best, Some(pb));
+        assert!(update_best(&mut best, &run(&[12, 22, 32, 39])));
+        assert_eq!(best.unwrap().splits_ms, [12_000, 22_000, 32_000, 39_000]);
+    }
+}
@@ -0,0 +1,384 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::date::UtcDateTime;
+use super::{BlockKind, Game, GameMode, RandomizerKind, TSpinKind, BOARD_HEIGHT, BOARD_WIDTH};
+
+// File the per-game records go in, inside the `--export-stats` directory
+pub const GAME_LOG_FILE: &str = "games.ndjson";
+
+/// How many clears of each kind a game had. T-spin clears count only as T-spins.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClearCounts {
+    pub singles: u32,
+    pub doubles: u32,
+    pub triples: u32,
+    pub tetrises: u32,
+    pub t_spin_minis: u32,
+    pub t_spins: u32,
+}
+
+impl ClearCounts {
+    pub fn record(&mut self, lines: u32, t_spin: TSpinKind) {
+        let count = match (lines, t_spin) {
+            (0, _) => return,
+            (_, TSpinKind::Mini) => &mut self.t_spin_minis,
+            (_, TSpinKind::Full) => &mut self.t_spins,
+            (1, TSpinKind::None) => &mut self.singles,
+            (2, TSpinKind::None) => &mut self.doubles,
+            (3, TSpinKind::None) => &mut self.triples,
+            (_, TSpinKind::None) => &mut self.tetrises,
+        };
+        *count += 1;
+    }
+}
+
+/// How many locked pieces covered each cell of the board over a game, row by row from
+/// the top.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Heatmap {
+    rows: Vec<Vec<u32>>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            rows: vec![vec![0; BOARD_WIDTH]; BOARD_HEIGHT],
+        }
+    }
+}
+
+impl Heatmap {
+    /// Counts a locked piece's cells. Any above the board don't count.
+    pub fn record(&mut self, cells: impl IntoIterator<Item = (i32, i32)>) {
+        for (x, y) in cells {
+            let (Ok(x), Ok(y)) = (usize::try_from(x), usize::try_from(y)) else {
+                continue;
+            };
+            if let Some(count) = self.rows.get_mut(y).and_then(|row| row.get_mut(x)) {
+                *count += 1;
+            }
+        }
+    }
+
+    pub fn count(&self, x: usize, y: usize) -> u32 {
+        self.rows.get(y).and_then(|row| row.get(x)).copied().unwrap_or(0)
+    }
+
+    /// The count of the busiest cell.
+    pub fn max(&self) -> u32 {
+        self.rows.iter().flatten().copied().max().unwrap_or(0)
+    }
+
+    /// Cells covered in each column, left to right.
+    pub fn columns(&self) -> Vec<u32> {
+        (0..BOARD_WIDTH)
+            .map(|x| (0..BOARD_HEIGHT).map(|y| self.count(x, y)).sum())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max() == 0
+    }
+}
+
+// Chi-squared past which counts of the seven kinds (six degrees of freedom) come out as
+// uneven less than one time in twenty with a fair randomizer
+pub const CHI_SQUARED_SIGNIFICANT: f32 = 12.59;
+// Droughts this long can't happen with a seven bag, which deals an I at most 12 pieces apart
+pub const LONG_DROUGHT: u32 = 13;
+
+/// Pieces that entered play this session by kind, and how long it's been since an I piece
+/// this game: a drought.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    spawns: [u32; BlockKind::ALL.len()],
+    drought: u32,
+}
+
+impl SessionStats {
+    /// Counts a piece entering play. An I piece ends the drought.
+    pub fn record_spawn(&mut self, kind: BlockKind) {
+        let index = BlockKind::ALL.iter().position(|&known| known == kind).unwrap_or(0);
+        self.spawns[index] += 1;
+        self.drought = if kind == BlockKind::I { 0 } else { self.drought + 1 };
+    }
+
+    /// Starts the drought over for a new game. The counts carry on.
+    pub fn new_game(&mut self) {
+        self.drought = 0;
+    }
+
+    /// Pieces since the last I piece.
+    pub fn drought(&self) -> u32 {
+        self.drought
+    }
+
+    /// How many of each kind entered play, in `BlockKind::ALL` order.
+    pub fn spawns(&self) -> impl Iterator<Item = (BlockKind, u32)> + '_ {
+        BlockKind::ALL.into_iter().zip(self.spawns)
+    }
+
+    /// Pearson's chi-squared of the counts against every kind being as likely: about 6 on
+    /// average for pieces dealt at random, less from a bag, and past
+    /// `CHI_SQUARED_SIGNIFICANT` for a suspicious run. None before any pieces.
+    pub fn chi_squared(&self) -> Option<f32> {
+        let total: u32 = self.spawns.iter().sum();
+        let expected = total as f32 / self.spawns.len() as f32;
+        (total > 0).then(|| {
+            self.spawns
+                .iter()
+                .map(|&count| (count as f32 - expected).powi(2) / expected)
+                .sum()
+        })
+    }
+}
+
+/// One finished game, as written to the stats log. Field names are part of the log format,
+/// so existing logs keep loading; add new fields with `#[serde(default)]`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    /// When the game ended, as RFC 3339 UTC.
+    pub date: String,
+    pub mode: GameMode,
+    pub seed: u64,
+    pub duration_ms: u64,
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    #[serde(flatten)]
+    pub clears: ClearCounts,
+    pub pps: f32,
+    pub apm: f32,
+    /// Which randomizer dealt the pieces; logs from before there was a choice were classic.
+    #[serde(default)]
+    pub randomizer: RandomizerKind,
+    /// Where the game's pieces locked; left out of logs when no piece did.
+    #[serde(default, skip_serializing_if = "Heatmap::is_empty")]
+    pub heatmap: Heatmap,
+}
+
+impl GameRecord {
+    pub fn from_game(game: &Game, ended: UtcDateTime) -> Self {
+        Self {
+            date: ended.to_rfc3339(),
+            mode: game.mode,
+            seed: game.seed,
+            duration_ms: game.play_time.as_millis() as u64,
+            score: game.score.points,
+            lines: game.score.lines,
+            level: game.score.level,
+            clears: game.clears,
+            pps: game.pps(),
+            apm: game.apm(),
+            randomizer: game.randomizer_kind(),
+            heatmap: game.heatmap.clone(),
+        }
+    }
+}
+
+/// Appends a record to an NDJSON log, creating the file and its directory if needed.
+pub fn append_record(path: impl AsRef<Path>, record: &GameRecord) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())
+}
+
+pub const CSV_HEADER: &str = "date,mode,seed,duration_ms,score,lines,level,singles,doubles,\
+triples,tetrises,t_spin_minis,t_spins,pps,apm,randomizer";
+
+/// Flattens an NDJSON stats log into CSV with a header row. Blank lines are skipped, and
+/// the first line that isn't a record is an error.
+pub fn log_to_csv(log: &str) -> Result<String, serde_json::Error> {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for line in log.lines().filter(|line| !line.trim().is_empty()) {
+        let record: GameRecord = serde_json::from_str(line)?;
+        let clears = record.clears;
+        let fields = [
+            csv_field(&record.date),
+            csv_field(record.mode.name()),
+            record.seed.to_string(),
+            record.duration_ms.to_string(),
+            record.score.to_string(),
+            record.lines.to_string(),
+            record.level.to_string(),
+            clears.singles.to_string(),
+            clears.doubles.to_string(),
+            clears.triples.to_string(),
+            clears.tetrises.to_string(),
+            clears.t_spin_minis.to_string(),
+            clears.t_spins.to_string(),
+            format!("{:.3}", record.pps),
+            format!("{:.3}", record.apm),
+            record.randomizer.name().to_string(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+// Quotes a field if it holds anything CSV treats specially
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> GameRecord {
+        GameRecord {
+            date: "2024-06-01T12:30:05Z".to_string(),
+            mode: GameMode::CheeseRace,
+            seed: 42,
+            duration_ms: 61_500,
+            score: 1200,
+            lines: 10,
+            level: 2,
+            clears: ClearCounts {
+                singles: 1,
+                tetrises: 2,
+                t_spins: 1,
+                ..ClearCounts::default()
+            },
+            pps: 1.5,
+            apm: 12.25,
+            randomizer: RandomizerKind::SevenBag,
+            heatmap: Heatmap::default(),
+        }
+    }
+
+    #[test]
+    fn clear_counts_by_kind() {
+        let mut clears = ClearCounts::default();
+        clears.record(0, TSpinKind::Full);
+        clears.record(4, TSpinKind::None);
+        clears.record(2, TSpinKind::Full);
+        clears.record(1, TSpinKind::Mini);
+        assert_eq!(
+            clears,
+            ClearCounts {
+                tetrises: 1,
+                t_spins: 1,
+                t_spin_minis: 1,
+                ..ClearCounts::default()
+            }
+        );
+    }
+
+    #[test]
+    fn record_format_is_stable() {
+        // Existing logs depend on this exact shape; change it only by adding fields
+        let json = serde_json::to_string(&record()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"date":"2024-06-01T12:30:05Z","mode":"cheese_race","seed":42,"duration_ms":61500,"#
+                .to_string()
+                + r#""score":1200,"lines":10,"level":2,"singles":1,"doubles":0,"triples":0,"#
+                + r#""tetrises":2,"t_spin_minis":0,"t_spins":1,"pps":1.5,"apm":12.25,"#
+                + r#""randomizer":"seven_bag"}"#
+        );
+        assert_eq!(serde_json::from_str::<GameRecord>(&json).unwrap(), record());
+
+        let old = json.replace(r#","randomizer":"seven_bag""#, "");
+        let old: GameRecord = serde_json::from_str(&old).unwrap();
+        assert_eq!(old.randomizer, RandomizerKind::Classic);
+    }
+
+    #[test]
+    fn log_converts_to_csv() {
+        let line = serde_json::to_string(&record()).unwrap();
+        let csv = log_to_csv(&format!("{}\n\n{}\n", line, line)).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], CSV_HEADER);
+        assert_eq!(
+            rows[1],
+            "2024-06-01T12:30:05Z,Cheese Race,42,61500,1200,10,2,1,0,0,2,0,1,1.500,12.250,seven_bag"
+        );
+        assert_eq!(CSV_HEADER.split(',').count(), rows[1].split(',').count());
+        assert!(log_to_csv("not json").is_err());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn records_append_one_per_line() {
+        let dir = std::env::temp_dir().join(format!("tetris-stats-{}", std::process::id()));
+        let path = dir.join("nested").join(GAME_LOG_FILE);
+        append_record(&path, &record()).unwrap();
+        append_record(&path, &record()).unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(log.lines().count(), 2);
+        assert_eq!(log_to_csv(&log).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn heatmap_counts_locked_cells_on_the_board() {
+        let mut heatmap = Heatmap::default();
+        assert!(heatmap.is_empty());
+        heatmap.record([(0, 19), (1, 19), (1, 18), (2, -1)]);
+        heatmap.record([(1, 19), (9, 0), (10, 0), (0, 20)]);
+        assert_eq!(heatmap.count(1, 19), 2);
+        assert_eq!(heatmap.max(), 2);
+        assert_eq!(heatmap.columns(), [1, 3, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        // Saved with the game's record, and logs from before heatmaps still load
+        let mut played = record();
+        played.heatmap = heatmap.clone();
+        let json = serde_json::to_string(&played).unwrap();
+        assert_eq!(serde_json::from_str::<GameRecord>(&json).unwrap().heatmap, heatmap);
+        let old = serde_json::to_string(&record()).unwrap();
+        assert!(!old.contains("heatmap"));
+        assert!(serde_json::from_str::<GameRecord>(&old).unwrap().heatmap.is_empty());
+    }
+
+    #[test]
+    fn droughts_end_when_an_i_piece_spawns() {
+        let mut stats = SessionStats::default();
+        assert_eq!(stats.chi_squared(), None);
+        for kind in [BlockKind::T, BlockKind::S, BlockKind::Z] {
+            stats.record_spawn(kind);
+        }
+        assert_eq!(stats.drought(), 3);
+        stats.record_spawn(BlockKind::I);
+        assert_eq!(stats.drought(), 0);
+        stats.record_spawn(BlockKind::O);
+        assert_eq!(stats.drought(), 1);
+        stats.new_game();
+        assert_eq!(stats.drought(), 0);
+        assert_eq!(stats.spawns().map(|(_, count)| count).sum::<u32>(), 5);
+    }
+
+    #[test]
+    fn even_counts_have_no_deviation() {
+        let mut stats = SessionStats::default();
+        for kind in BlockKind::ALL {
+            stats.record_spawn(kind);
+        }
+        assert_eq!(stats.chi_squared(), Some(0.0));
+        for _ in 0..20 {
+            stats.record_spawn(BlockKind::S);
+        }
+        assert!(stats.chi_squared().unwrap() > CHI_SQUARED_SIGNIFICANT);
+    }
+}
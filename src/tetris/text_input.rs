@@ -0,0 +1,47 @@
+/// A line of text being typed, such as the name of a new room.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextInput {
+    text: String,
+    max_chars: usize,
+}
+
+impl TextInput {
+    /// An empty line that takes up to `max_chars` characters.
+    pub fn new(max_chars: usize) -> Self {
+        Self {
+            text: String::new(),
+            max_chars,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Types `ch` at the end, unless it's a control character or the line is full.
+    pub fn type_char(&mut self, ch: char) {
+        if !ch.is_control() && self.text.chars().count() < self.max_chars {
+            self.text.push(ch);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.text.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_stops_at_the_limit() {
+        let mut input = TextInput::new(3);
+        for ch in "añ\nbc".chars() {
+            input.type_char(ch);
+        }
+        assert_eq!(input.text(), "añb");
+        input.backspace();
+        assert_eq!(input.text(), "añ");
+    }
+}
@@ -0,0 +1,304 @@
+use std::time::{Duration, Instant};
+
+use super::{parse_queue, Action, Board, GameEvent, PracticeSetup, TSpinKind};
+
+// How long a finished step shows its checkmark before the next one loads
+pub const STEP_DONE_DELAY: Duration = Duration::from_millis(1000);
+
+/// What a tutorial step waits for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TutorialGoal {
+    HardDrop,
+    Hold,
+    /// Clear at least this many lines with one piece.
+    ClearLines(u32),
+    /// Any T-spin that clears a line.
+    TSpin,
+}
+
+impl TutorialGoal {
+    fn is_met_by(&self, event: &GameEvent) -> bool {
+        match (*self, event) {
+            (TutorialGoal::HardDrop, GameEvent::HardDropped) => true,
+            (TutorialGoal::Hold, GameEvent::Held) => true,
+            (TutorialGoal::ClearLines(lines), GameEvent::PieceLocked { lines_cleared, .. }) => {
+                *lines_cleared >= lines
+            }
+            (TutorialGoal::TSpin, GameEvent::PieceLocked { lines_cleared, t_spin, .. }) => {
+                *t_spin != TSpinKind::None && *lines_cleared > 0
+            }
+            _ => false,
+        }
+    }
+}
+
+/// One step of the tutorial: the instruction shown over the board, the setup it is played
+/// on and the actions it lets through while input is locked.
+pub struct TutorialStep {
+    /// Key of the instruction in the UI text.
+    pub text: &'static str,
+    pub goal: TutorialGoal,
+    /// Starting board in the `Board::from_ascii` format, or an empty board. Steps with a
+    /// board start over when a piece locks without meeting the goal, as the setup is gone.
+    pub board: Option<&'static str>,
+    /// Looping piece queue; empty for random pieces.
+    pub queue: &'static str,
+    pub actions: &'static [Action],
+}
+
+// Everything needed to place a piece
+const PLACING: &[Action] = &[
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::RotateCw,
+    Action::SoftDrop,
+    Action::HardDrop,
+];
+
+pub const TUTORIAL_STEPS: &[TutorialStep] = &[
+    TutorialStep {
+        text: "tutorial_hard_drop",
+        goal: TutorialGoal::HardDrop,
+        board: None,
+        queue: "O",
+        actions: &[Action::HardDrop],
+    },
+    TutorialStep {
+        text: "tutorial_move",
+        goal: TutorialGoal::ClearLines(1),
+        board: Some("GGGGGG...."),
+        queue: "I",
+        actions: PLACING,
+    },
+    TutorialStep {
+        text: "tutorial_hold",
+        goal: TutorialGoal::Hold,
+        board: None,
+        queue: "SZ",
+        actions: &[Action::Hold],
+    },
+    TutorialStep {
+        text: "tutorial_tetris",
+        goal: TutorialGoal::ClearLines(4),
+        board: Some(
+            "
+            GGGGGGGGG.
+            GGGGGGGGG.
+            GGGGGGGGG.
+            GGGGGGGGG.",
+        ),
+        queue: "I",
+        actions: PLACING,
+    },
+    TutorialStep {
+        text: "tutorial_t_spin",
+        goal: TutorialGoal::TSpin,
+        board: Some(
+            "
+            ..GG......
+            GGG...GGGG
+            GGGG.GGGGG",
+        ),
+        queue: "T",
+        actions: PLACING,
+    },
+];
+
+/// How a step stands after an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepProgress {
+    Active,
+    /// The setup was spoiled and the step has to start over.
+    Retry,
+    Done,
+}
+
+/// Runs through the tutorial steps, one game event at a time.
+pub struct Tutorial {
+    steps: &'static [TutorialStep],
+    pub current: usize,
+    /// Whether actions the current step doesn't teach are ignored.
+    pub lock_input: bool,
+    done_at: Option<Instant>,
+}
+
+impl Tutorial {
+    pub fn new(steps: &'static [TutorialStep], lock_input: bool) -> Self {
+        Self {
+            steps,
+            current: 0,
+            lock_input,
+            done_at: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn step(&self) -> Option<&'static TutorialStep> {
+        self.steps.get(self.current)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current >= self.steps.len()
+    }
+
+    /// Board and queue the current step is played on.
+    pub fn setup(&self) -> PracticeSetup {
+        let mut setup = PracticeSetup::default();
+        if let Some(step) = self.step() {
+            if let Some(board) = step.board {
+                setup.board = Board::from_ascii(board).expect("tutorial boards are valid");
+            }
+            setup.queue = parse_queue(step.queue).expect("tutorial queues are valid");
+        }
+        setup
+    }
+
+    /// Whether `action` goes through. Everything does between steps and with input unlocked.
+    pub fn allows(&self, action: Action) -> bool {
+        match self.step() {
+            Some(step) if self.lock_input && self.done_at.is_none() => {
+                step.actions.contains(&action)
+            }
+            _ => true,
+        }
+    }
+
+    pub fn on_event(&mut self, event: &GameEvent, now: Instant) -> StepProgress {
+        let Some(step) = self.step() else {
+            return StepProgress::Done;
+        };
+        if self.done_at.is_some() {
+            return StepProgress::Done;
+        }
+        if step.goal.is_met_by(event) {
+            self.done_at = Some(now);
+            return StepProgress::Done;
+        }
+        match event {
+            GameEvent::PieceLocked { .. } if step.board.is_some() => StepProgress::Retry,
+            GameEvent::ToppedOut => StepProgress::Retry,
+            _ => StepProgress::Active,
+        }
+    }
+
+    /// Moves on once a finished step has shown its checkmark. Returns whether it did, so
+    /// the next step's setup can be loaded.
+    pub fn update(&mut self, now: Instant) -> bool {
+        match self.done_at {
+            Some(done_at) if now.saturating_duration_since(done_at) >= STEP_DONE_DELAY => {
+                self.done_at = None;
+                self.current += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// How far along the checkmark animation of a finished step is, from 0 to 1.
+    pub fn checkmark(&self, now: Instant) -> Option<f32> {
+        let shown = now.saturating_duration_since(self.done_at?);
+        Some((shown.as_secs_f32() / STEP_DONE_DELAY.as_secs_f32()).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::{BlockKind, Game, GameState};
+
+    fn locked(lines_cleared: u32, t_spin: TSpinKind) -> GameEvent {
+        GameEvent::PieceLocked {
+            kind: BlockKind::T,
+            lines_cleared,
+            cleared_rows: Vec::new(),
+            t_spin,
+        }
+    }
+
+    #[test]
+    fn every_step_has_a_valid_setup() {
+        let mut tutorial = Tutorial::new(TUTORIAL_STEPS, true);
+        while !tutorial.is_finished() {
+            let step = tutorial.step().unwrap();
+            if let Some(board) = step.board {
+                Board::from_ascii(board).unwrap();
+            }
+            assert!(!parse_queue(step.queue).unwrap().is_empty());
+            assert!(step.actions.contains(&Action::HardDrop) || step.goal == TutorialGoal::Hold);
+            tutorial.current += 1;
+        }
+    }
+
+    #[test]
+    fn goals_match_their_events() {
+        assert!(TutorialGoal::HardDrop.is_met_by(&GameEvent::HardDropped));
+        assert!(TutorialGoal::Hold.is_met_by(&GameEvent::Held));
+        assert!(TutorialGoal::ClearLines(2).is_met_by(&locked(3, TSpinKind::None)));
+        assert!(!TutorialGoal::ClearLines(4).is_met_by(&locked(3, TSpinKind::None)));
+        assert!(TutorialGoal::TSpin.is_met_by(&locked(2, TSpinKind::Full)));
+        assert!(!TutorialGoal::TSpin.is_met_by(&locked(0, TSpinKind::Full)));
+        assert!(!TutorialGoal::TSpin.is_met_by(&locked(2, TSpinKind::None)));
+    }
+
+    #[test]
+    fn steps_advance_after_the_checkmark() {
+        let start = Instant::now();
+        let mut tutorial = Tutorial::new(TUTORIAL_STEPS, true);
+        assert_eq!(tutorial.on_event(&GameEvent::Held, start), StepProgress::Active);
+        assert_eq!(tutorial.on_event(&GameEvent::HardDropped, start), StepProgress::Done);
+        assert_eq!(tutorial.checkmark(start), Some(0.0));
+        assert!(tutorial.allows(Action::Hold), "nothing is locked during the checkmark");
+
+        assert!(!tutorial.update(start + STEP_DONE_DELAY / 2));
+        assert!(tutorial.update(start + STEP_DONE_DELAY));
+        assert_eq!(tutorial.current, 1);
+        assert_eq!(tutorial.checkmark(start + STEP_DONE_DELAY), None);
+    }
+
+    #[test]
+    fn spoiled_setups_start_over() {
+        let now = Instant::now();
+        let mut tutorial = Tutorial::new(TUTORIAL_STEPS, true);
+        // The first step is on an empty board, so a plain lock is fine
+        assert_eq!(tutorial.on_event(&locked(0, TSpinKind::None), now), StepProgress::Active);
+        assert_eq!(tutorial.on_event(&GameEvent::ToppedOut, now), StepProgress::Retry);
+        tutorial.current = 1;
+        assert_eq!(tutorial.on_event(&locked(0, TSpinKind::None), now), StepProgress::Retry);
+    }
+
+    #[test]
+    fn locked_input_only_lets_the_step_through() {
+        let mut tutorial = Tutorial::new(TUTORIAL_STEPS, true);
+        assert!(tutorial.allows(Action::HardDrop));
+        assert!(!tutorial.allows(Action::Hold));
+        tutorial.lock_input = false;
+        assert!(tutorial.allows(Action::Hold));
+    }
+
+    #[test]
+    fn t_spin_step_can_be_done() {
+        let mut tutorial = Tutorial::new(TUTORIAL_STEPS, true);
+        tutorial.current = TUTORIAL_STEPS.len() - 1;
+        let mut game = Game::with_seed(1);
+        game.start_tutorial(tutorial.setup());
+        // Stand the T up, drop it into the slot and turn it flat into the hole
+        game.apply(Action::RotateCw);
+        game.sonic_drop();
+        game.apply(Action::RotateCw);
+        game.apply(Action::HardDrop);
+        let now = Instant::now();
+        let done = game
+            .drain_events()
+            .iter()
+            .any(|event| tutorial.on_event(event, now) == StepProgress::Done);
+        assert!(done, "board after the spin:\n{:#}", game.board);
+        assert_eq!(game.state, GameState::Playing);
+    }
+}
@@ -0,0 +1,508 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use super::anticheat::plausible_sprint_time;
+use super::{
+    Action, Game, GameConfig, GameMode, GameState, Leveling, RandomizerKind, SPRINT_LINES,
+};
+
+// Bumped whenever the log's layout or the rules it records change
+const LOG_VERSION: u8 = 1;
+// Largest log inflated for checking: hours of play at a high frame rate
+pub const MAX_LOG_BYTES: usize = 4 * 1024 * 1024;
+// Most time the server spends playing back one submission. An honest sprint takes a small
+// fraction of it
+pub const VERIFY_BUDGET: Duration = Duration::from_secs(2);
+// How far the played back time may be from the claimed one, which was rounded to the
+// millisecond
+pub const TIME_TOLERANCE: Duration = Duration::from_millis(10);
+// Steps played between looks at the clock
+const BUDGET_CHECK_STEPS: usize = 256;
+
+// Step layout: an action is its code on its own, held keys are their bits over
+// STEP_HELD, and a frame is STEP_FRAME_MICROS or STEP_FRAME_NANOS followed by its length
+// as a LEB128 number
+const STEP_HELD: u8 = 0x10;
+const STEP_FRAME_MICROS: u8 = 0x20;
+const STEP_FRAME_NANOS: u8 = 0x21;
+
+const ACTIONS: [Action; 7] = [
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::SoftDrop,
+    Action::SonicDrop,
+    Action::RotateCw,
+    Action::HardDrop,
+    Action::Hold,
+];
+
+/// The keys held down that act over time rather than on a press: soft drop, and hold and
+/// rotate for initial actions on spawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeldKeys {
+    pub soft_drop: bool,
+    pub hold: bool,
+    pub rotate: bool,
+}
+
+impl HeldKeys {
+    pub fn of(game: &Game) -> Self {
+        Self {
+            soft_drop: game.timer.soft_drop,
+            hold: game.hold_requested,
+            rotate: game.rotation_requested,
+        }
+    }
+
+    pub fn apply(self, game: &mut Game) {
+        game.timer.soft_drop = self.soft_drop;
+        game.hold_requested = self.hold;
+        game.rotation_requested = self.rotate;
+    }
+
+    fn bits(self) -> u8 {
+        u8::from(self.soft_drop) | u8::from(self.hold) << 1 | u8::from(self.rotate) << 2
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            soft_drop: bits & 1 != 0,
+            hold: bits & 2 != 0,
+            rotate: bits & 4 != 0,
+        }
+    }
+}
+
+/// One thing that happened to a recorded game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Action(Action),
+    Held(HeldKeys),
+    /// A frame of this much game time.
+    Frame(Duration),
+}
+
+/// The rules a run was played by, where they change what its inputs do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RecordedRules {
+    pub randomizer: RandomizerKind,
+    pub hold_slots: usize,
+    pub initial_actions: bool,
+    pub instant_gravity: bool,
+    pub entry_delay: Duration,
+    pub lock_delay: Duration,
+    pub soft_drop_factor: f32,
+    pub leveling: Leveling,
+}
+
+impl RecordedRules {
+    pub fn of(config: &GameConfig) -> Self {
+        Self {
+            randomizer: config.randomizer,
+            hold_slots: config.hold_slots,
+            initial_actions: config.initial_actions,
+            instant_gravity: config.instant_gravity,
+            entry_delay: config.entry_delay,
+            lock_delay: config.lock_delay,
+            soft_drop_factor: config.soft_drop_factor,
+            leveling: config.leveling,
+        }
+    }
+
+    pub fn apply(&self, config: &mut GameConfig) {
+        config.randomizer = self.randomizer;
+        config.hold_slots = self.hold_slots;
+        config.initial_actions = self.initial_actions;
+        config.instant_gravity = self.instant_gravity;
+        config.entry_delay = self.entry_delay;
+        config.lock_delay = self.lock_delay;
+        config.soft_drop_factor = self.soft_drop_factor;
+        config.leveling = self.leveling;
+    }
+}
+
+/// Everything needed to play a game again exactly: its seed, its rules, and every input
+/// and frame in order. Sent with a record so the server can check it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputLog {
+    version: u8,
+    pub seed: u64,
+    pub rules: RecordedRules,
+    // The steps packed as described at STEP_HELD
+    steps: Vec<u8>,
+    // Keys held as of the last step, so only changes are written
+    #[serde(skip)]
+    held: HeldKeys,
+}
+
+impl InputLog {
+    pub fn new(seed: u64, config: &GameConfig) -> Self {
+        Self {
+            version: LOG_VERSION,
+            seed,
+            rules: RecordedRules::of(config),
+            steps: Vec::new(),
+            held: HeldKeys::default(),
+        }
+    }
+
+    /// Writes `step`, after the held keys if they changed since the last one.
+    pub fn record(&mut self, held: HeldKeys, step: Step) {
+        if held != self.held {
+            self.held = held;
+            self.steps.push(STEP_HELD | held.bits());
+        }
+        match step {
+            Step::Action(action) => {
+                let code = ACTIONS
+                    .iter()
+                    .position(|&known| known == action)
+                    .unwrap_or(0);
+                self.steps.push(code as u8);
+            }
+            Step::Held(keys) => {
+                self.held = keys;
+                self.steps.push(STEP_HELD | keys.bits());
+            }
+            Step::Frame(dt) if dt.subsec_nanos() % 1000 == 0 => {
+                self.steps.push(STEP_FRAME_MICROS);
+                write_number(&mut self.steps, dt.as_micros() as u64);
+            }
+            Step::Frame(dt) => {
+                self.steps.push(STEP_FRAME_NANOS);
+                write_number(&mut self.steps, dt.as_nanos() as u64);
+            }
+        }
+    }
+
+    /// The steps in order, with an error in place of any that don't parse.
+    pub fn steps(&self) -> impl Iterator<Item = Result<Step, RecordRejection>> + '_ {
+        let mut bytes = self.steps.iter().copied();
+        std::iter::from_fn(move || {
+            let byte = bytes.next()?;
+            let step = match byte {
+                STEP_FRAME_MICROS => read_number(&mut bytes)
+                    .map(Duration::from_micros)
+                    .map(Step::Frame),
+                STEP_FRAME_NANOS => read_number(&mut bytes)
+                    .map(Duration::from_nanos)
+                    .map(Step::Frame),
+                _ if byte & !7 == STEP_HELD => Some(Step::Held(HeldKeys::from_bits(byte))),
+                _ => ACTIONS.get(byte as usize).copied().map(Step::Action),
+            };
+            Some(step.ok_or(RecordRejection::Malformed))
+        })
+    }
+
+    /// Deflated, for sending.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        encoder
+            .write_all(&json)
+            .and_then(|_| encoder.finish())
+            .unwrap_or_default()
+    }
+
+    /// Reads a log sent by `to_bytes`, refusing one that inflates past `MAX_LOG_BYTES` or
+    /// was written by another version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RecordRejection> {
+        let mut json = Vec::new();
+        DeflateDecoder::new(bytes)
+            .take(MAX_LOG_BYTES as u64 + 1)
+            .read_to_end(&mut json)
+            .map_err(|_| RecordRejection::Malformed)?;
+        if json.len() > MAX_LOG_BYTES {
+            return Err(RecordRejection::Malformed);
+        }
+        let log: Self = serde_json::from_slice(&json).map_err(|_| RecordRejection::Malformed)?;
+        if log.version != LOG_VERSION {
+            return Err(RecordRejection::Malformed);
+        }
+        Ok(log)
+    }
+}
+
+fn write_number(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let low = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(low);
+            return;
+        }
+        bytes.push(low | 0x80);
+    }
+}
+
+fn read_number(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes.next()?;
+        value |= u64::from(byte & 0x7f).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// What a player says their run came to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClaimedRecord {
+    pub time_ms: u64,
+    pub lines: u32,
+    pub score: u32,
+}
+
+impl ClaimedRecord {
+    pub fn of(game: &Game) -> Self {
+        Self {
+            time_ms: game.play_time.as_millis() as u64,
+            lines: game.score.lines,
+            score: game.score.points,
+        }
+    }
+}
+
+/// Why the server turned a record down.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordRejection {
+    /// Records aren't kept for the mode.
+    UnsupportedMode,
+    /// The claim couldn't be true, without playing anything back.
+    Implausible,
+    /// The log couldn't be read.
+    Malformed,
+    /// The log is of a game on another seed.
+    WrongSeed,
+    /// Playing the log back took longer than the server allows.
+    OverBudget,
+    /// The log didn't finish the run.
+    Unfinished,
+    /// The log finished with a different time, line count or score.
+    Mismatch,
+}
+
+impl fmt::Display for RecordRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            RecordRejection::UnsupportedMode => "no records are kept for this mode",
+            RecordRejection::Implausible => "the claimed result is impossible",
+            RecordRejection::Malformed => "the replay could not be read",
+            RecordRejection::WrongSeed => "the replay is of another game",
+            RecordRejection::OverBudget => "the replay took too long to check",
+            RecordRejection::Unfinished => "the replay does not finish the run",
+            RecordRejection::Mismatch => "the replay does not match the claimed result",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Plays `log` back from the start of a game in `mode`, giving up once it has taken
+/// `budget`. Only sprints are recorded.
+pub fn play_back(
+    log: &InputLog,
+    mode: GameMode,
+    budget: Duration,
+) -> Result<Game, RecordRejection> {
+    if mode != GameMode::Sprint {
+        return Err(RecordRejection::UnsupportedMode);
+    }
+    let started = Instant::now();
+    let mut game = Game::with_seed(log.seed);
+    log.rules.apply(&mut game.config);
+    game.start_sprint(log.seed);
+    for (index, step) in log.steps().enumerate() {
+        if game.state != GameState::Playing {
+            break;
+        }
+        if index % BUDGET_CHECK_STEPS == 0 && started.elapsed() > budget {
+            return Err(RecordRejection::OverBudget);
+        }
+        match step? {
+            Step::Action(action) => {
+                game.apply(action);
+            }
+            Step::Held(keys) => keys.apply(&mut game),
+            Step::Frame(dt) => {
+                game.tick(dt);
+            }
+        }
+    }
+    Ok(game)
+}
+
+/// Checks a claimed record by playing its log back on `seed`: it has to finish the run
+/// with the claimed lines and score, and within `TIME_TOLERANCE` of the claimed time.
+pub fn verify_record(
+    mode: GameMode,
+    seed: u64,
+    claimed: &ClaimedRecord,
+    replay_bytes: &[u8],
+    budget: Duration,
+) -> Result<(), RecordRejection> {
+    if mode != GameMode::Sprint {
+        return Err(RecordRejection::UnsupportedMode);
+    }
+    let time = Duration::from_millis(claimed.time_ms);
+    if claimed.lines < SPRINT_LINES || !plausible_sprint_time(SPRINT_LINES, time) {
+        return Err(RecordRejection::Implausible);
+    }
+    let log = InputLog::from_bytes(replay_bytes)?;
+    if log.seed != seed {
+        return Err(RecordRejection::WrongSeed);
+    }
+    let game = play_back(&log, mode, budget)?;
+    if game.state != GameState::Finished {
+        return Err(RecordRejection::Unfinished);
+    }
+    let off_by = game.play_time.abs_diff(time);
+    if ClaimedRecord::of(&game).lines != claimed.lines
+        || game.score.points != claimed.score
+        || off_by > TIME_TOLERANCE
+    {
+        return Err(RecordRejection::Mismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris::{best_placement, Weights};
+
+    const SEED: u64 = 0x5eed;
+    const FRAME: Duration = Duration::from_micros(16_667);
+
+    // A sprint played by the bot a piece at a time, with a frame between its inputs and
+    // soft drop held on every other piece
+    fn recorded_sprint() -> Game {
+        let mut game = Game::with_seed(SEED);
+        game.start_sprint(SEED);
+        let weights = Weights::default();
+        for piece in 0..1000 {
+            // Line clears play out before the next piece can move
+            while game.state == GameState::Playing && !game.is_controllable() {
+                game.tick(FRAME);
+            }
+            if game.state != GameState::Playing {
+                break;
+            }
+            game.timer.soft_drop = piece % 2 == 1;
+            let Some(placement) = best_placement(&game, &weights) else {
+                break;
+            };
+            for action in placement.actions(&game.current_block) {
+                game.apply(action);
+                game.tick(FRAME);
+            }
+        }
+        assert_eq!(
+            game.state,
+            GameState::Finished,
+            "the bot should finish a sprint"
+        );
+        game
+    }
+
+    #[test]
+    fn a_recorded_sprint_verifies() {
+        let game = recorded_sprint();
+        let bytes = game.input_log().unwrap().to_bytes();
+        let claimed = ClaimedRecord::of(&game);
+        assert_eq!(
+            verify_record(GameMode::Sprint, SEED, &claimed, &bytes, VERIFY_BUDGET),
+            Ok(())
+        );
+
+        let replayed = play_back(
+            &InputLog::from_bytes(&bytes).unwrap(),
+            GameMode::Sprint,
+            VERIFY_BUDGET,
+        );
+        let replayed = replayed.unwrap();
+        assert_eq!(replayed.pieces_placed, game.pieces_placed);
+        assert_eq!(replayed.score, game.score);
+        assert_eq!(replayed.play_time, game.play_time);
+    }
+
+    #[test]
+    fn tampered_records_are_rejected() {
+        let game = recorded_sprint();
+        let bytes = game.input_log().unwrap().to_bytes();
+        let claimed = ClaimedRecord::of(&game);
+        let verify = |claimed: &ClaimedRecord, bytes: &[u8], seed| {
+            verify_record(GameMode::Sprint, seed, claimed, bytes, VERIFY_BUDGET)
+        };
+
+        let higher = ClaimedRecord {
+            score: claimed.score + 100,
+            ..claimed
+        };
+        assert_eq!(
+            verify(&higher, &bytes, SEED),
+            Err(RecordRejection::Mismatch)
+        );
+        let faster = ClaimedRecord {
+            time_ms: claimed.time_ms - 1000,
+            ..claimed
+        };
+        assert_eq!(
+            verify(&faster, &bytes, SEED),
+            Err(RecordRejection::Mismatch)
+        );
+        assert_eq!(
+            verify(&claimed, &bytes, SEED + 1),
+            Err(RecordRejection::WrongSeed)
+        );
+        assert_eq!(
+            verify(&claimed, &bytes[..bytes.len() / 2], SEED),
+            Err(RecordRejection::Malformed)
+        );
+        assert_eq!(
+            verify_record(GameMode::Marathon, SEED, &claimed, &bytes, VERIFY_BUDGET),
+            Err(RecordRejection::UnsupportedMode)
+        );
+
+        // A log cut short doesn't finish the run
+        let log = game.input_log().unwrap();
+        let mut short = InputLog::new(SEED, &game.config);
+        for step in log.steps().take(log.steps().count() / 2) {
+            short.record(short.held, step.unwrap());
+        }
+        assert_eq!(
+            verify(&claimed, &short.to_bytes(), SEED),
+            Err(RecordRejection::Unfinished)
+        );
+    }
+
+    #[test]
+    fn steps_come_back_as_written() {
+        let mut log = InputLog::new(SEED, &GameConfig::default());
+        let held = HeldKeys {
+            soft_drop: true,
+            hold: false,
+            rotate: true,
+        };
+        log.record(HeldKeys::default(), Step::Action(Action::Hold));
+        log.record(held, Step::Frame(FRAME));
+        log.record(held, Step::Frame(Duration::from_nanos(16_666_667)));
+        let steps: Vec<Step> = log.steps().map(Result::unwrap).collect();
+        assert_eq!(
+            steps,
+            [
+                Step::Action(Action::Hold),
+                Step::Held(held),
+                Step::Frame(FRAME),
+                Step::Frame(Duration::from_nanos(16_666_667)),
+            ]
+        );
+    }
+}
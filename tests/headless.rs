@@ -0,0 +1,13 @@
+// Runs the headless example, so the public API it shows keeps working
+
+#[path = "../examples/headless.rs"]
+#[allow(dead_code)]
+mod headless;
+
+#[test]
+fn the_headless_example_plays_its_pieces() {
+    let summary = headless::play(7, 100);
+    assert_eq!(summary.pieces, 100);
+    assert!(summary.games >= 1);
+    assert_eq!(headless::play(7, 100), summary, "the same seed plays the same game");
+}